@@ -0,0 +1,133 @@
+// Full replay versus the state-only fast path on a synthetic 100k-event log. One full
+// replay of it takes seconds, so each sample is a single iteration and there are ten
+// of them.
+//
+//     cargo bench --bench replay
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, SamplingMode};
+use cross_margin_engine::prelude::*;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::sync::Arc;
+use std::time::Duration;
+
+const EVENTS: usize = 100_000;
+const ACCOUNTS: u64 = 10;
+
+fn markets() -> Vec<Market> {
+    vec![
+        Market::new("BTC-PERP".parse().unwrap(), dec!(0.05), dec!(0.03)),
+        Market::new("ETH-PERP".parse().unwrap(), dec!(0.10), dec!(0.05)),
+        Market::new("SOL-PERP".parse().unwrap(), dec!(0.10), dec!(0.05)),
+    ]
+}
+
+/// A deterministic log: deposits for every account, then trades, mark moves and
+/// funding in a fixed rotation, with whatever liquidations the live engine derives.
+fn synthetic_log() -> Vec<Event> {
+    let mut engine = Engine::with_config(config());
+    for market in markets() {
+        engine.add_market(market).unwrap();
+    }
+    let market_ids = ["BTC-PERP", "ETH-PERP", "SOL-PERP"];
+    let base = [dec!(50000), dec!(3000), dec!(100)];
+    for (market_id, price) in market_ids.iter().zip(base) {
+        engine.process(EventType::MarkPriceUpdate {
+            market_id: (*market_id).parse().unwrap(),
+            price,
+        });
+    }
+    for account in 0..ACCOUNTS {
+        engine.process(EventType::Deposit {
+            account_id: format!("acct-{account}").parse().unwrap(),
+            amount: dec!(1000000),
+        });
+    }
+
+    let mut i: u64 = 0;
+    while engine.event_log.len() < EVENTS {
+        i += 1;
+        let m = (i % 3) as usize;
+        let market_id: MarketId = market_ids[m].parse().unwrap();
+        let mark = engine.state.markets[&market_id].mark_price;
+        let event = match i % 10 {
+            0..=5 => EventType::TradeFill {
+                account_id: format!("acct-{}", (i * 7919) % ACCOUNTS).parse().unwrap(),
+                market_id,
+                quantity: if i % 4 < 2 { dec!(0.5) } else { dec!(-0.3) },
+                price: mark,
+                liquidity: None,
+            },
+            6..=8 => {
+                let step = Decimal::from((i * 31) % 41) - dec!(20);
+                EventType::MarkPriceUpdate {
+                    market_id,
+                    price: (base[m] * (Decimal::ONE + step / dec!(1000))).round_dp(2),
+                }
+            }
+            _ => EventType::FundingRate {
+                market_id,
+                rate: dec!(0.00001),
+                interval_id: i,
+            },
+        };
+        engine.process(event);
+    }
+    engine
+        .event_log
+        .into_iter()
+        .map(Arc::unwrap_or_clone)
+        .collect()
+}
+
+/// The generator's config, which its `ConfigMarker` records.
+fn config() -> EngineConfig {
+    EngineConfig {
+        snapshot_policy: SnapshotPolicy::Never,
+        ..EngineConfig::default()
+    }
+}
+
+/// Full replay under the generator's config. It still captures a snapshot of every
+/// account after every event, but hands them to a `NullSink`: kept, those of a 100k
+/// log do not fit in memory.
+fn full_options() -> ReplayOptions {
+    ReplayOptions {
+        config: config(),
+        snapshot_sink: Some(Box::new(NullSink)),
+        ..ReplayOptions::default()
+    }
+}
+
+fn bench_replay(c: &mut Criterion) {
+    let log = synthetic_log();
+    // The fast path must land on exactly the state full replay does, and full replay
+    // must get through the whole log for the comparison to mean anything.
+    let full = Engine::replay_with(full_options(), &log, markets());
+    assert_eq!(full.status, ReplayStatus::Completed);
+    assert_eq!(full.events_applied, log.len() as u64);
+    assert_eq!(
+        Engine::replay_state_only(log.iter().cloned(), markets(), config()).unwrap(),
+        full.state
+    );
+
+    let mut group = c.benchmark_group("replay_100k");
+    group.sampling_mode(SamplingMode::Flat);
+    group.sample_size(10);
+    group.warm_up_time(Duration::from_secs(1));
+    group.measurement_time(Duration::from_secs(90));
+    group.bench_function("replay", |b| {
+        b.iter(|| Engine::replay_with(full_options(), &log, markets()))
+    });
+    group.bench_function("replay_state_only", |b| {
+        b.iter_batched(
+            || log.clone(),
+            |log| Engine::replay_state_only(log, markets(), config()).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_replay);
+criterion_main!(benches);
@@ -0,0 +1,136 @@
+// Reader throughput on a 1,000-account and a 100,000-account book, with the writer
+// idle and with it processing marks as fast as it can: loading the published
+// `StateView`, against cloning a full snapshot out of a mutex the writer refreshes
+// after every event.
+//
+//     cargo bench --bench views
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::snapshot;
+use rust_decimal_macros::dec;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+
+const BOOKS: [usize; 2] = [1_000, 100_000];
+
+struct Writer {
+    busy: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<u64>,
+}
+
+fn btc() -> MarketId {
+    "BTC-PERP".parse().unwrap()
+}
+
+/// A book of `accounts` accounts with 1,000,000 each, every other one long 1 BTC at
+/// 50,000. Replaying the events that build it is far quicker than processing them.
+fn book(accounts: usize) -> State {
+    let mut events = vec![EventType::MarkPriceUpdate {
+        market_id: btc(),
+        price: dec!(50000),
+    }];
+    for i in 0..accounts {
+        let account_id: AccountId = format!("acct-{i:06}").parse().unwrap();
+        events.push(EventType::Deposit {
+            account_id: account_id.clone(),
+            amount: dec!(1000000),
+        });
+        if i % 2 == 0 {
+            events.push(EventType::TradeFill {
+                account_id,
+                market_id: btc(),
+                quantity: dec!(1),
+                price: dec!(50000),
+                liquidity: None,
+            });
+        }
+    }
+    let events = (1..)
+        .zip(events)
+        .map(|(sequence, e)| Event::new(sequence, e));
+    let markets = vec![Market::new(btc(), dec!(0.05), dec!(0.03))];
+    Engine::replay_state_only(events, markets, EngineConfig::default()).unwrap()
+}
+
+/// Seed an engine with `book(accounts)` on a thread of its own (the engine stays
+/// there) and hand back its views and a mutex holding the snapshot after the latest
+/// event. Every mark changes the half of the book that holds BTC and leaves the other
+/// half alone.
+fn spawn_writer(accounts: usize) -> (Writer, StateViews, Arc<Mutex<Snapshot>>) {
+    let busy = Arc::new(AtomicBool::new(false));
+    let stop = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = mpsc::channel();
+    let thread = {
+        let (busy, stop) = (busy.clone(), stop.clone());
+        std::thread::spawn(move || {
+            let mut engine = Engine::builder()
+                .snapshot_policy(SnapshotPolicy::Never)
+                .build();
+            engine.state = book(accounts);
+            let views = engine.views();
+            let latest = Arc::new(Mutex::new(snapshot::capture(&engine.state, 0)));
+            sender.send((views, latest.clone())).unwrap();
+
+            let mut marks = 0;
+            while !stop.load(Ordering::Relaxed) {
+                if !busy.load(Ordering::Relaxed) {
+                    std::thread::yield_now();
+                    continue;
+                }
+                let price = if marks % 2 == 0 {
+                    dec!(50010)
+                } else {
+                    dec!(50000)
+                };
+                engine.process(EventType::MarkPriceUpdate {
+                    market_id: btc(),
+                    price,
+                });
+                let sequence = engine.event_log.last().unwrap().sequence;
+                *latest.lock().unwrap() = snapshot::capture(&engine.state, sequence);
+                marks += 1;
+            }
+            marks
+        })
+    };
+    let (views, latest) = receiver.recv().unwrap();
+    (Writer { busy, stop, thread }, views, latest)
+}
+
+fn bench_views(c: &mut Criterion) {
+    for accounts in BOOKS {
+        let (writer, views, latest) = spawn_writer(accounts);
+        let account_id = format!("acct-{:06}", accounts / 2);
+        let mut group = c.benchmark_group(format!("reader_{accounts}_accounts"));
+        // A snapshot of the large book takes tens of milliseconds to clone.
+        group.sample_size(10);
+        for (busy, label) in [(false, "idle"), (true, "busy")] {
+            writer.busy.store(busy, Ordering::Relaxed);
+            group.bench_function(format!("view_load_writer_{label}"), |b| {
+                b.iter(|| {
+                    let view = views.load();
+                    black_box(view.account(&account_id).unwrap().collateral)
+                })
+            });
+            group.bench_function(format!("snapshot_clone_writer_{label}"), |b| {
+                b.iter(|| {
+                    let snapshot = latest.lock().unwrap().clone();
+                    black_box(snapshot.accounts[account_id.as_str()].collateral)
+                })
+            });
+        }
+        group.finish();
+        writer.stop.store(true, Ordering::Relaxed);
+        let marks = writer.thread.join().unwrap();
+        assert!(
+            marks > 0,
+            "the writer processed marks while the readers ran"
+        );
+    }
+}
+
+criterion_group!(benches, bench_views);
+criterion_main!(benches);
@@ -0,0 +1,254 @@
+// Merging duplicate accounts. Scenario 46 nets alice_old into alice, bob_old into bob
+// and carol_old into carol. Replay, state files and snapshots reproduce the merges,
+// the books balance, and each side's statement reconciles with a `Merge` line at the
+// merge. Attribution treats what the source handed over as a transfer, and risk
+// deltas take the closed source down to zeros. A second engine merges a position
+// whose entry does not terminate, keeping equity exact, and checks what else moves
+// with an account: its conservative funding checkpoint, its fills in the trade
+// statistics, its group membership and its backstop registration.
+
+use cross_margin_engine::margin;
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::report::{self, LedgerKind};
+use cross_margin_engine::scenario;
+use cross_margin_engine::snapshot;
+use cross_margin_engine::state;
+use cross_margin_engine::types::ImportedPosition;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+fn id(text: &str) -> AccountId {
+    text.parse().unwrap()
+}
+
+fn merge(from: &str, to: &str) -> EventType {
+    EventType::AccountsMerged {
+        from: id(from),
+        to: id(to),
+    }
+}
+
+fn main() {
+    let scenario = scenario::load("scenarios/46_account_merge.toml").unwrap();
+    let markets: Vec<Market> = scenario.markets.iter().map(|m| m.to_market()).collect();
+    let engine = scenario::run(&scenario).unwrap().engine;
+
+    let replayed =
+        Engine::replay_verified(&engine.event_log, markets.clone(), scenario.config.clone())
+            .unwrap();
+    assert_eq!(replayed.state, engine.state);
+    assert_eq!(
+        State::from_json(&engine.state.to_json()).unwrap(),
+        engine.state
+    );
+    for saved in &engine.snapshots {
+        snapshot::restore(saved, markets.clone()).unwrap();
+    }
+    assert!(engine.solvency().is_balanced());
+    for (pool_id, report) in state::solvency_by_pool(&engine.state, engine.metrics()) {
+        assert!(report.is_balanced(), "{pool_id}: {report:?}");
+    }
+
+    // Both statements meet at the merge: alice's principal rises by alice_old's, and
+    // her trading balance by the 2,000 the netting realized.
+    let merged = engine
+        .event_log
+        .iter()
+        .find(|e| e.event_type == merge("alice_old", "alice"))
+        .unwrap();
+    let into = report::statement(&engine.event_log, "alice", markets.clone());
+    let line = into.iter().find(|l| l.sequence == merged.sequence).unwrap();
+    assert_eq!(
+        (line.kind, line.amount, line.principal_amount),
+        (LedgerKind::Merge, dec!(12000), dec!(10000))
+    );
+    assert!(report::reconcile(&into).reconciled);
+    let out_of = report::statement(&engine.event_log, "alice_old", markets.clone());
+    let last = out_of.last().unwrap();
+    assert_eq!(
+        (last.sequence, last.kind, last.amount),
+        (merged.sequence, LedgerKind::Merge, dec!(-10000))
+    );
+    assert_eq!(last.balance_after, Decimal::ZERO);
+    assert!(report::reconcile(&out_of).reconciled);
+
+    // Attribution across the merge, for both sides.
+    let end = engine.event_log.last().unwrap().sequence;
+    for account_id in ["alice", "alice_old", "bob", "bob_old", "carol"] {
+        let attribution = report::attribution(
+            &engine.event_log,
+            &engine.snapshots,
+            &id(account_id),
+            0,
+            end,
+        );
+        assert!(attribution.reconciled, "{account_id}: {attribution:?}");
+    }
+    let alice = report::attribution(
+        &engine.event_log,
+        &engine.snapshots,
+        &id("alice"),
+        merged.sequence - 1,
+        merged.sequence,
+    );
+    assert_eq!(
+        (alice.transfers, alice.equity_change),
+        (dec!(12000), dec!(12000))
+    );
+
+    // The closed source drops to zeros.
+    let at = |sequence: u64| {
+        engine
+            .snapshots
+            .iter()
+            .find(|s| s.after_sequence == sequence)
+            .unwrap()
+    };
+    let deltas = snapshot::risk_deltas(at(merged.sequence - 1), at(merged.sequence));
+    let closed = deltas
+        .iter()
+        .find(|d| d.account_id.as_str() == "alice_old")
+        .unwrap();
+    assert_eq!(
+        (closed.before.equity, closed.after),
+        (dec!(12000), RiskFigures::default())
+    );
+
+    // The same from the engine's own deltas, resubmitting the scenario's events.
+    let mut config = scenario.config.clone();
+    config.risk_deltas = RiskDeltaPolicy::ObserversAndQueue;
+    let mut live = Engine::with_config(config);
+    for market in &markets {
+        live.add_market(market.clone()).unwrap();
+    }
+    for event in engine
+        .event_log
+        .iter()
+        .filter(|e| e.caused_by.is_none() && !e.event_type.is_engine_generated())
+    {
+        live.process(event.event_type.clone());
+    }
+    let mut expected = Vec::new();
+    let mut previous = snapshot::capture(&State::new(), 0);
+    for snapshot in &live.snapshots {
+        expected.extend(snapshot::risk_deltas(&previous, snapshot));
+        previous = snapshot.clone();
+    }
+    assert_eq!(live.drain_risk_deltas(), expected);
+
+    // A second engine: gina holds 3 BTC at a cost of 100,000, an entry that does not
+    // terminate. gina_old was imported short 1, last settled at index 4 against
+    // gina's 10, and is in a group, a backstop and the trade statistics.
+    let config = EngineConfig {
+        trade_stats: Some(TradeStatistics {
+            window: StatsWindow::Fills(10),
+            fee_tiers: Vec::new(),
+        }),
+        ..EngineConfig::default()
+    };
+    let btc: MarketId = "BTC-PERP".parse().unwrap();
+    let mut engine = Engine::with_config(config);
+    engine
+        .add_market(Market::new(btc.clone(), dec!(0.05), dec!(0.03)))
+        .unwrap();
+    let events = [
+        EventType::MarkPriceUpdate {
+            market_id: btc.clone(),
+            price: dec!(33000),
+        },
+        EventType::FundingUpdate {
+            market_id: btc.clone(),
+            new_cumulative_index: dec!(10),
+        },
+        EventType::Deposit {
+            account_id: id("gina"),
+            amount: dec!(20000),
+        },
+        EventType::TradeFill {
+            account_id: id("gina"),
+            market_id: btc.clone(),
+            quantity: dec!(1),
+            price: dec!(30000),
+            liquidity: None,
+        },
+        EventType::TradeFill {
+            account_id: id("gina"),
+            market_id: btc.clone(),
+            quantity: dec!(2),
+            price: dec!(35000),
+            liquidity: None,
+        },
+        EventType::StateImport {
+            account_id: id("gina_old"),
+            pool_id: "default".into(),
+            collateral: dec!(5000),
+            positions: vec![ImportedPosition {
+                market_id: btc.clone(),
+                quantity: dec!(-1),
+                cost_basis: dec!(-34000),
+                last_funding: dec!(4),
+            }],
+        },
+        EventType::GroupCreated {
+            group_id: "desk".into(),
+            max_group_notional: None,
+            fee_override: None,
+        },
+        EventType::GroupMembershipSet {
+            account_id: id("gina_old"),
+            group_id: Some("desk".into()),
+        },
+        EventType::BackstopRegistered {
+            account_id: id("gina_old"),
+            market_id: btc.clone(),
+            max_notional: dec!(1000),
+        },
+        EventType::TradeFill {
+            account_id: id("gina_old"),
+            market_id: btc.clone(),
+            quantity: dec!(-0.25),
+            price: dec!(33000),
+            liquidity: None,
+        },
+    ];
+    for event_type in events {
+        assert!(
+            engine.process(event_type.clone()).is_accepted(),
+            "{event_type:?}"
+        );
+    }
+    let equity = |engine: &Engine| -> Decimal {
+        let state = &engine.state;
+        state
+            .accounts
+            .values()
+            .map(|a| margin::equity(a, state))
+            .sum()
+    };
+    let before = equity(&engine);
+    assert!(engine.process(merge("gina_old", "gina")).is_accepted());
+    assert_eq!(equity(&engine), before);
+    assert!(engine.solvency().is_balanced());
+
+    // The 1.25 short closes at gina's entry of 33,333.33…, so the 583.33… it realizes
+    // is floored to collateral precision and the remainder stays in the cost basis.
+    let gina = &engine.state.accounts["gina"];
+    let position = &gina.positions[&btc];
+    assert_eq!(position.quantity, dec!(1.75));
+    assert_eq!(gina.trading_balance, dec!(583.33333333));
+    assert_eq!(
+        position.cost_basis,
+        dec!(100000) - dec!(42250) + dec!(583.33333333)
+    );
+    // Net long, so the lower of the two checkpoints.
+    assert_eq!(gina.last_funding[&btc], dec!(4));
+    assert_eq!(engine.state.trade_stats[&id("gina")].totals.trades, 3);
+    assert!(engine.state.groups["desk"].members.is_empty());
+    assert!(engine.state.backstops.is_empty());
+    assert!(!engine.state.accounts.contains_key("gina_old"));
+
+    println!(
+        "merged alice_old into alice at seq {} (+12000, statements reconcile); gina nets to {} BTC with equity exact",
+        merged.sequence, position.quantity
+    );
+}
@@ -0,0 +1,153 @@
+// Principal and trading balance, kept apart for regulatory reporting. The lifecycle
+// of `scenarios/44_balance_segregation.toml` ends with alice's 6,000 withdrawal
+// drawing her 4,900 of gains before 1,100 of principal, and bob's drawing only
+// principal behind a trading loss. Each account's statement shows which balance
+// every line moved and reconciles both on their own. A liquidation afterwards only
+// touches carol's trading balance. Replay, state files and snapshots all carry the
+// two balances, and under `WithdrawalOrder::PrincipalFirst` the same kind of
+// withdrawal takes principal first.
+
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::report::{self, LedgerKind};
+use cross_margin_engine::scenario;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+fn market() -> Market {
+    Market::new("BTC-PERP".parse().unwrap(), dec!(0.05), dec!(0.03))
+}
+
+fn id(s: &str) -> AccountId {
+    s.parse().unwrap()
+}
+
+fn mark(price: Decimal) -> EventType {
+    EventType::MarkPriceUpdate {
+        market_id: "BTC-PERP".parse().unwrap(),
+        price,
+    }
+}
+
+fn trade(account_id: &str, quantity: Decimal, price: Decimal) -> EventType {
+    EventType::TradeFill {
+        account_id: id(account_id),
+        market_id: "BTC-PERP".parse().unwrap(),
+        quantity,
+        price,
+        liquidity: None,
+    }
+}
+
+/// Every line of `account_id`'s statement as (kind, principal part, trading part),
+/// after checking that it reconciles and closes at the account's live balances.
+fn ledger(engine: &Engine, account_id: &str) -> Vec<(LedgerKind, Decimal, Decimal)> {
+    let lines = report::statement(&engine.event_log, account_id, vec![market()]);
+    let reconciliation = report::reconcile(&lines);
+    assert!(
+        reconciliation.reconciled,
+        "{account_id}: {reconciliation:?}"
+    );
+    let account = &engine.state.accounts[account_id];
+    assert_eq!(reconciliation.principal, account.principal);
+    assert_eq!(reconciliation.trading_balance, account.trading_balance);
+    let last = lines.last().unwrap();
+    assert_eq!(last.balance_after, account.collateral());
+    lines
+        .iter()
+        .map(|line| (line.kind, line.principal_amount, line.trading_amount()))
+        .collect()
+}
+
+fn main() {
+    let run =
+        scenario::run(&scenario::load("scenarios/44_balance_segregation.toml").unwrap()).unwrap();
+    let mut engine = run.engine;
+
+    assert_eq!(
+        ledger(&engine, "alice"),
+        [
+            (LedgerKind::Deposit, dec!(10000), dec!(0)),
+            (LedgerKind::Funding, dec!(0), dec!(-100)),
+            (LedgerKind::RealizedPnl, dec!(0), dec!(5000)),
+            (LedgerKind::Withdrawal, dec!(-1100), dec!(-4900)),
+        ]
+    );
+    assert_eq!(
+        ledger(&engine, "bob"),
+        [
+            (LedgerKind::Deposit, dec!(10000), dec!(0)),
+            (LedgerKind::Funding, dec!(0), dec!(-100)),
+            (LedgerKind::RealizedPnl, dec!(0), dec!(-2000)),
+            (LedgerKind::Withdrawal, dec!(-5000), dec!(0)),
+        ]
+    );
+
+    // Carol's liquidation loss comes out of her trading balance; her 3,000 of
+    // principal is what she put in.
+    engine.process(EventType::Deposit {
+        account_id: id("carol"),
+        amount: dec!(3000),
+    });
+    assert!(engine
+        .process(trade("carol", dec!(1), dec!(48000)))
+        .is_accepted());
+    engine.process(mark(dec!(45500)));
+    let carol = &engine.state.accounts["carol"];
+    assert!(carol.positions.is_empty());
+    assert_eq!(carol.principal, dec!(3000));
+    assert!(carol.trading_balance < Decimal::ZERO);
+    let carol_ledger = ledger(&engine, "carol");
+    assert_eq!(carol_ledger.last().unwrap().0, LedgerKind::Liquidation);
+    assert_eq!(carol_ledger.last().unwrap().1, Decimal::ZERO);
+
+    // Replay rebuilds both balances, a state file keeps them, and a snapshot shows them.
+    let replayed =
+        Engine::replay_verified(&engine.event_log, vec![market()], EngineConfig::default())
+            .unwrap();
+    assert_eq!(replayed.state, engine.state);
+    assert_eq!(
+        State::from_json(&engine.state.to_json()).unwrap(),
+        engine.state
+    );
+    let snapshot = engine.snapshots.last().unwrap();
+    let alice = &snapshot.accounts["alice"];
+    assert_eq!(
+        (alice.principal, alice.trading_balance, alice.collateral),
+        (dec!(8900), dec!(0), dec!(8900))
+    );
+
+    // Principal first: dave's 6,000 comes out of his 10,000 deposit, and his 5,000
+    // gain stays as trading balance.
+    let mut principal_first = Engine::builder()
+        .withdrawal_order(WithdrawalOrder::PrincipalFirst)
+        .build();
+    principal_first.add_market(market()).unwrap();
+    principal_first.process(mark(dec!(50000)));
+    principal_first.process(EventType::Deposit {
+        account_id: id("dave"),
+        amount: dec!(10000),
+    });
+    principal_first.process(trade("dave", dec!(1), dec!(50000)));
+    principal_first.process(mark(dec!(55000)));
+    principal_first.process(trade("dave", dec!(-1), dec!(55000)));
+    let withdraw = EventType::Withdraw {
+        account_id: id("dave"),
+        amount: dec!(6000),
+    };
+    assert!(principal_first.process(withdraw).is_accepted());
+    let dave = &principal_first.state.accounts["dave"];
+    assert_eq!(
+        (dave.principal, dave.trading_balance),
+        (dec!(4000), dec!(5000))
+    );
+    assert_eq!(
+        ledger(&principal_first, "dave").last().unwrap(),
+        &(LedgerKind::Withdrawal, dec!(-6000), dec!(0))
+    );
+
+    println!(
+        "alice withdrew 4900 of gains and 1100 of principal; bob 5000 of principal; \
+         carol liquidated to {} with principal 3000; dave withdrew principal first",
+        engine.state.accounts["carol"].collateral()
+    );
+}
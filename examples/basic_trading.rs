@@ -0,0 +1,104 @@
+// Deposit, trade and withdraw through the public API, handling every outcome. Check
+// that an oversized trade and an over-margin withdrawal are rejected with their kind
+// of reason and leave the account as it was, and that closing the position realizes
+// its PnL into collateral that can then be withdrawn in full.
+
+use cross_margin_engine::margin;
+use cross_margin_engine::prelude::*;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+fn trade(quantity: Decimal, price: Decimal) -> EventType {
+    EventType::TradeFill {
+        account_id: "alice".parse().unwrap(),
+        market_id: "BTC-PERP".parse().unwrap(),
+        quantity,
+        price,
+        liquidity: None,
+    }
+}
+
+fn withdraw(amount: Decimal) -> EventType {
+    EventType::Withdraw {
+        account_id: "alice".parse().unwrap(),
+        amount,
+    }
+}
+
+/// Submit `event_type` and return the reason it was rejected, if it was.
+fn submit(engine: &mut Engine, event_type: EventType) -> Option<RejectReason> {
+    match engine.process(event_type) {
+        ProcessOutcome::Accepted { sequence } => {
+            println!("seq {sequence}: accepted");
+            None
+        }
+        ProcessOutcome::Rejected { sequence, reason } => {
+            println!("seq {sequence}: rejected ({}): {reason}", reason.kind());
+            Some(reason)
+        }
+        ProcessOutcome::Duplicate { .. } => unreachable!("no idempotency key"),
+        ProcessOutcome::Suppressed { .. } => unreachable!("no rejection throttle"),
+        ProcessOutcome::SinkFailed { .. } => unreachable!("no snapshot sink"),
+        ProcessOutcome::LogStoreFailed { .. } => unreachable!("no log store"),
+    }
+}
+
+fn main() {
+    let mut engine = Engine::new();
+    engine
+        .add_market(Market::new(
+            "BTC-PERP".parse().unwrap(),
+            dec!(0.05),
+            dec!(0.03),
+        ))
+        .expect("valid market parameters");
+    let mark = |price| EventType::MarkPriceUpdate {
+        market_id: "BTC-PERP".parse().unwrap(),
+        price,
+    };
+    assert_eq!(submit(&mut engine, mark(dec!(50000))), None);
+    let deposit = EventType::Deposit {
+        account_id: "alice".parse().unwrap(),
+        amount: dec!(10000),
+    };
+    assert_eq!(submit(&mut engine, deposit), None);
+
+    // 20 BTC needs 50,000 of IM against 10,000 of equity.
+    let before = engine.state.accounts["alice"].clone();
+    let reason = submit(&mut engine, trade(dec!(20), dec!(50000)));
+    assert!(matches!(reason, Some(RejectReason::Trade(_))));
+    assert_eq!(engine.state.accounts["alice"], before);
+
+    // 2 BTC needs 5,000.
+    assert_eq!(submit(&mut engine, trade(dec!(2), dec!(50000))), None);
+    let account = &engine.state.accounts["alice"];
+    assert_eq!(
+        margin::initial_margin_required(account, &engine.state),
+        dec!(5000)
+    );
+
+    // Withdrawing 6,000 would leave 4,000 of equity under that IM; 4,000 leaves 6,000.
+    let reason = submit(&mut engine, withdraw(dec!(6000)));
+    assert!(matches!(reason, Some(RejectReason::Withdrawal(_))));
+    assert_eq!(engine.state.accounts["alice"].collateral(), dec!(10000));
+    assert_eq!(submit(&mut engine, withdraw(dec!(4000))), None);
+
+    // The mark rises 1,000; selling at it realizes 2,000 and frees all margin.
+    assert_eq!(submit(&mut engine, mark(dec!(51000))), None);
+    let account = &engine.state.accounts["alice"];
+    assert_eq!(margin::equity(account, &engine.state), dec!(8000));
+    assert_eq!(submit(&mut engine, trade(dec!(-2), dec!(51000))), None);
+    let account = &engine.state.accounts["alice"];
+    assert!(account.positions.is_empty());
+    assert_eq!(account.collateral(), dec!(8000));
+    assert_eq!(submit(&mut engine, withdraw(dec!(8000))), None);
+    assert_eq!(engine.state.accounts["alice"].collateral(), Decimal::ZERO);
+
+    // Each rejection is logged as the attempt followed by its record.
+    let rejections = engine
+        .event_log
+        .iter()
+        .filter(|e| e.event_type.is_rejection());
+    assert_eq!(rejections.count(), 2);
+    println!("{} events logged", engine.event_log.len());
+}
@@ -0,0 +1,226 @@
+// Cascades that reach accounts the event never named. Scenario 50 bankrupts alice with
+// a BTC mark and socializes her deficit in proportion to equity, which pushes bob, who
+// holds only ETH, under MM: the cascade rescans him and liquidates him under the same
+// mark. Check the records, their cause and verified replay, and that with no rescans
+// allowed bob waits for the next event that names him. Then a chain two rescans deep:
+// bob's slipped close bankrupts him too, and his socialized deficit pushes dave under.
+
+use cross_margin_engine::margin;
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::scenario::{self, Scenario};
+use rust_decimal_macros::dec;
+
+fn id(text: &str) -> AccountId {
+    text.parse().unwrap()
+}
+
+/// The kind and account of each record caused by the event at `sequence`.
+fn caused(engine: &Engine, sequence: u64) -> Vec<(&'static str, String)> {
+    engine
+        .event_log
+        .iter()
+        .filter(|e| e.caused_by == Some(sequence))
+        .map(|e| (e.event_type.kind(), e.event_type.accounts()[0].to_string()))
+        .collect()
+}
+
+fn record(kind: &'static str, account_id: &str) -> (&'static str, String) {
+    (kind, account_id.to_string())
+}
+
+/// The sequence of the last event without a cause.
+fn last_external(engine: &Engine) -> u64 {
+    engine
+        .event_log
+        .iter()
+        .rev()
+        .find(|e| e.caused_by.is_none())
+        .unwrap()
+        .sequence
+}
+
+fn main() {
+    let scenario = scenario::load("scenarios/50_cascade_rescan.toml").unwrap();
+    let markets: Vec<Market> = scenario.markets.iter().map(|m| m.to_market()).collect();
+    let engine = scenario::run(&scenario).unwrap().engine;
+
+    let replayed =
+        Engine::replay_verified(&engine.event_log, markets.clone(), scenario.config.clone())
+            .unwrap();
+    assert_eq!(replayed.state, engine.state);
+    assert!(engine.solvency().is_balanced());
+    let mark = last_external(&engine);
+    assert_eq!(
+        caused(&engine, mark),
+        [
+            record("LiquidationFill", "alice"),
+            record("LossSocialized", "alice"),
+            record("LiquidationFill", "bob")
+        ]
+    );
+
+    // With no rescans the cascade stops at alice. bob is left at or under MM, with
+    // nothing to liquidate him until an ETH mark scans him.
+    let config = EngineConfig {
+        max_cascade_rescans: 0,
+        ..scenario.config.clone()
+    };
+    let steps = scenario
+        .steps
+        .iter()
+        .filter(|step| !step.starts_with("expect"))
+        .cloned()
+        .collect();
+    let unrescanned = Scenario {
+        steps,
+        config: config.clone(),
+        ..scenario.clone()
+    };
+    let mut lingering = scenario::run(&unrescanned).unwrap().engine;
+    let mark = last_external(&lingering);
+    assert_eq!(
+        caused(&lingering, mark),
+        [
+            record("LiquidationFill", "alice"),
+            record("LossSocialized", "alice")
+        ]
+    );
+    let bob = &lingering.state.accounts["bob"];
+    assert!(margin::is_liquidatable(bob, &lingering.state));
+    let eth: MarketId = "ETH-PERP".parse().unwrap();
+    assert!(lingering
+        .process(EventType::MarkPriceUpdate {
+            market_id: eth,
+            price: dec!(3000)
+        })
+        .is_accepted());
+    assert_eq!(
+        caused(&lingering, last_external(&lingering)),
+        [record("LiquidationFill", "bob")]
+    );
+    assert_eq!(
+        lingering.state.accounts["bob"].collateral(),
+        engine.state.accounts["bob"].collateral()
+    );
+    Engine::replay_verified(&lingering.event_log, markets, config).unwrap();
+
+    // A chain. ETH closes slip 4 bps per unit of notional, so bob's 30,000 close
+    // fills 12% through mark and bankrupts him as well. His deficit is socialized
+    // across carol and dave, which leaves dave's SOL long under MM in turn.
+    let (btc, sol): (MarketId, MarketId) =
+        ("BTC-PERP".parse().unwrap(), "SOL-PERP".parse().unwrap());
+    let eth: MarketId = "ETH-PERP".parse().unwrap();
+    let mut slipping = Market::new(eth.clone(), dec!(0.10), dec!(0.05));
+    slipping.slippage_bps_per_notional = dec!(0.04);
+    let markets = vec![
+        Market::new(btc.clone(), dec!(0.10), dec!(0.05)),
+        slipping,
+        Market::new(sol.clone(), dec!(0.10), dec!(0.05)),
+    ];
+    let events = [
+        EventType::MarkPriceUpdate {
+            market_id: btc.clone(),
+            price: dec!(50000),
+        },
+        EventType::MarkPriceUpdate {
+            market_id: eth.clone(),
+            price: dec!(3000),
+        },
+        EventType::MarkPriceUpdate {
+            market_id: sol.clone(),
+            price: dec!(100),
+        },
+        EventType::Deposit {
+            account_id: id("alice"),
+            amount: dec!(10000),
+        },
+        EventType::Deposit {
+            account_id: id("bob"),
+            amount: dec!(3000),
+        },
+        EventType::Deposit {
+            account_id: id("carol"),
+            amount: dec!(9000),
+        },
+        EventType::Deposit {
+            account_id: id("dave"),
+            amount: dec!(2000),
+        },
+        EventType::TradeFill {
+            account_id: id("alice"),
+            market_id: btc.clone(),
+            quantity: dec!(2),
+            price: dec!(50000),
+            liquidity: None,
+        },
+        EventType::TradeFill {
+            account_id: id("bob"),
+            market_id: eth,
+            quantity: dec!(-10),
+            price: dec!(3000),
+            liquidity: None,
+        },
+        EventType::TradeFill {
+            account_id: id("dave"),
+            market_id: sol,
+            quantity: dec!(20),
+            price: dec!(100),
+            liquidity: None,
+        },
+    ];
+    let run = |max_cascade_rescans: u32| -> Engine {
+        let config = EngineConfig {
+            residual_deficit: ResidualDeficit::SocializeEquity,
+            max_cascade_rescans,
+            ..EngineConfig::default()
+        };
+        let mut engine = Engine::with_config(config.clone());
+        for market in &markets {
+            engine.add_market(market.clone()).unwrap();
+        }
+        for event_type in &events {
+            assert!(
+                engine.process(event_type.clone()).is_accepted(),
+                "{event_type:?}"
+            );
+        }
+        engine.process(EventType::MarkPriceUpdate {
+            market_id: btc.clone(),
+            price: dec!(40000),
+        });
+        let replayed = Engine::replay_verified(&engine.event_log, markets.clone(), config).unwrap();
+        assert_eq!(replayed.state, engine.state);
+        assert!(engine.solvency().is_balanced());
+        engine
+    };
+
+    let chained = run(16);
+    let mark = last_external(&chained);
+    assert_eq!(
+        caused(&chained, mark),
+        [
+            record("LiquidationFill", "alice"),
+            record("LossSocialized", "alice"),
+            record("LiquidationFill", "bob"),
+            record("LossSocialized", "bob"),
+            record("LiquidationFill", "dave"),
+        ]
+    );
+    assert!(margin::liquidatable_accounts(&chained.state).is_empty());
+    // Only the rounding of each split stays owed.
+    assert!(chained
+        .state
+        .accounts
+        .values()
+        .all(|a| a.bankruptcy_deficit <= dec!(0.00000001)));
+
+    // One rescan reaches bob but not dave, who is left liquidatable.
+    let cut = run(1);
+    assert_eq!(caused(&cut, mark), caused(&chained, mark)[..4]);
+    assert_eq!(margin::liquidatable_accounts(&cut.state), [id("dave")]);
+
+    println!(
+        "scenario 50 liquidates bob in the rescan after seq {}; the chain liquidates dave two rescans deep",
+        last_external(&engine)
+    );
+}
@@ -0,0 +1,129 @@
+// Validate checkpoints before going live on them. Run every scenario's external
+// events again, keep the live state after each as a checkpoint, and check each one
+// against the log: replay up to it matches bit for bit and replay from it reproduces
+// the rest. Then knock one position in scenario 38 off by a tick and check that the
+// divergence names the account and the field, and that a checkpoint inside a cascade
+// or past the end of the log is refused.
+
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::scenario::{self, Scenario};
+use rust_decimal_macros::dec;
+
+/// The live engine for `scenario` and its state after each external event.
+fn checkpoints(scenario: &Scenario) -> (Engine, Vec<Checkpoint>) {
+    let recorded = scenario::run(scenario).unwrap().engine;
+    let mut engine = Engine::with_config(scenario.config.clone());
+    for market in &scenario.markets {
+        engine.add_market(market.to_market()).unwrap();
+    }
+    let mut checkpoints = vec![Checkpoint {
+        after_sequence: 0,
+        state: engine.state.clone(),
+    }];
+    for event in recorded.event_log.iter().filter(|e| e.caused_by.is_none()) {
+        if event.event_type.is_engine_generated() {
+            continue;
+        }
+        let submission = Submission {
+            idempotency_key: event.idempotency_key.clone(),
+            timestamp: event.timestamp,
+        };
+        engine.process_with(event.event_type.clone(), submission);
+        checkpoints.push(Checkpoint {
+            after_sequence: engine.event_log.last().unwrap().sequence,
+            state: engine.state.clone(),
+        });
+    }
+    (engine, checkpoints)
+}
+
+fn main() {
+    let mut paths: Vec<_> = std::fs::read_dir("scenarios")
+        .expect("run from the repository root")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    paths.sort();
+    let mut validated = 0;
+    for path in &paths {
+        let scenario = scenario::load(path).unwrap();
+        let markets: Vec<Market> = scenario.markets.iter().map(|m| m.to_market()).collect();
+        let (engine, checkpoints) = checkpoints(&scenario);
+        let log = &engine.event_log;
+        for checkpoint in &checkpoints {
+            let report = Engine::validate_checkpoint(checkpoint, log, markets.clone())
+                .unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+            assert!(
+                report.is_valid(),
+                "{}: {:?}",
+                path.display(),
+                report.divergence
+            );
+            assert_eq!(report.checkpoint_checksum, report.replayed_checksum);
+            let replayed = report.prefix_events + report.suffix_events;
+            assert_eq!(replayed, log.len() as u64, "{}", path.display());
+            validated += 1;
+        }
+    }
+    println!("{} scenarios, {validated} checkpoints valid", paths.len());
+
+    // bob buys 1.01 BTC in lots of 0.01; the checkpoint says 1.02.
+    let scenario = scenario::load("scenarios/38_quantity_step_liquidation.toml").unwrap();
+    let markets: Vec<Market> = scenario.markets.iter().map(|m| m.to_market()).collect();
+    let (engine, checkpoints) = checkpoints(&scenario);
+    let log = &engine.event_log;
+    let mut corrupted = checkpoints
+        .iter()
+        .find(|c| {
+            c.state
+                .accounts
+                .get("bob")
+                .is_some_and(|a| !a.positions.is_empty())
+        })
+        .unwrap()
+        .clone();
+    let position = corrupted
+        .state
+        .accounts
+        .get_mut("bob")
+        .unwrap()
+        .positions
+        .get_mut("BTC-PERP")
+        .unwrap();
+    assert_eq!(position.quantity, dec!(1.01));
+    position.quantity += dec!(0.01);
+    let report = Engine::validate_checkpoint(&corrupted, log, markets.clone()).unwrap();
+    let divergence = report.divergence.clone().expect("the extra tick is caught");
+    assert_eq!(divergence.sequence, corrupted.after_sequence);
+    assert_eq!(divergence.path, "accounts.bob.positions.BTC-PERP.quantity");
+    assert_eq!(divergence.checkpoint.as_deref(), Some("1.02"));
+    assert_eq!(divergence.replayed.as_deref(), Some("1.01"));
+    assert_ne!(report.checkpoint_checksum, report.replayed_checksum);
+    assert_eq!(report.suffix_events, 0);
+    println!("{divergence}");
+
+    // The mark at 48,000 liquidates alice and carol: a checkpoint after the mark but
+    // before its fills is refused, as is one past the end of the log.
+    let liquidating = EventType::MarkPriceUpdate {
+        market_id: "BTC-PERP".parse().unwrap(),
+        price: dec!(48000),
+    };
+    let mark = log.iter().find(|e| e.event_type == liquidating).unwrap();
+    assert_eq!(log[mark.sequence as usize].caused_by, Some(mark.sequence));
+    let mid_cascade = Checkpoint {
+        after_sequence: mark.sequence,
+        state: corrupted.state.clone(),
+    };
+    let err = Engine::validate_checkpoint(&mid_cascade, log, markets.clone()).unwrap_err();
+    assert!(
+        matches!(err, EngineError::CheckpointMidCascade { .. }),
+        "{err}"
+    );
+    let last = log.last().unwrap().sequence;
+    let past_end = Checkpoint {
+        after_sequence: last + 1,
+        state: engine.state.clone(),
+    };
+    let err = Engine::validate_checkpoint(&past_end, log, markets).unwrap_err();
+    assert!(matches!(err, EngineError::SequenceNotInLog { sequence } if sequence == last + 1));
+}
@@ -0,0 +1,238 @@
+// Hot config reload. An engine that defers liquidations in closed sessions leaves
+// alice's under-margined long open; a `ConfigUpdated` switching to liquidate-anyway
+// closes it at the update's own sequence, while bob, still healthy, is untouched.
+// Switched back, bob's later breach is deferred, and only the next switch liquidates
+// him. A tighter withdrawal buffer takes effect at once, a new snapshot policy from
+// the update on, and updates that change the mode, contradict themselves or name no
+// keeper are rejected with nothing changed. Replay from the original config reaches
+// the live state and config, as do regeneration, snapshot reconstruction and a
+// checkpoint taken between updates.
+
+use cross_margin_engine::margin;
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::regenerate::diff_logs;
+use cross_margin_engine::snapshot::SnapshotPolicy;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+fn markets() -> Vec<Market> {
+    vec![Market::new(
+        "BTC-PERP".parse().unwrap(),
+        dec!(0.05),
+        dec!(0.03),
+    )]
+}
+
+fn id(s: &str) -> AccountId {
+    s.parse().unwrap()
+}
+
+fn mark(price: Decimal) -> EventType {
+    EventType::MarkPriceUpdate {
+        market_id: "BTC-PERP".parse().unwrap(),
+        price,
+    }
+}
+
+/// Submit `config` as a `ConfigUpdated` and return the update's sequence.
+fn update(engine: &mut Engine, config: EngineConfig) -> u64 {
+    match engine.process(EventType::ConfigUpdated { config }) {
+        ProcessOutcome::Accepted { sequence } => sequence,
+        outcome => panic!("update refused: {outcome:?}"),
+    }
+}
+
+/// Whether a `LiquidationFill` for `account_id` was caused by the event at `sequence`.
+fn liquidated_by(engine: &Engine, account_id: &str, sequence: u64) -> bool {
+    engine.event_log.iter().any(|e| {
+        e.caused_by == Some(sequence)
+            && matches!(&e.event_type, EventType::LiquidationFill { account_id: a, .. } if a == account_id)
+    })
+}
+
+fn deferred(engine: &Engine, account_id: &str) -> bool {
+    engine.event_log.iter().any(
+        |e| matches!(&e.event_type, EventType::LiquidationDeferred { account_id: a, .. } if a == account_id),
+    )
+}
+
+fn main() {
+    let mut engine = Engine::builder()
+        .closed_session_liquidation(ClosedSessionLiquidation::DeferUntilOpen)
+        .build();
+    for market in markets() {
+        engine.add_market(market).unwrap();
+    }
+    let initial = engine.config().clone();
+    engine.process(mark(dec!(50000)));
+    for (account_id, collateral) in [
+        ("alice", dec!(6000)),
+        ("bob", dec!(8000)),
+        ("carol", dec!(10000)),
+    ] {
+        engine.process(EventType::Deposit {
+            account_id: id(account_id),
+            amount: collateral,
+        });
+        let fill = EventType::TradeFill {
+            account_id: id(account_id),
+            market_id: "BTC-PERP".parse().unwrap(),
+            quantity: dec!(1),
+            price: dec!(50000),
+            liquidity: None,
+        };
+        assert!(engine.process(fill).is_accepted());
+    }
+    engine.process(EventType::SessionClose {
+        market_id: "BTC-PERP".parse().unwrap(),
+    });
+
+    // At 45,000 alice is at 1,000 against 1,350 of MM, but the session is closed.
+    engine.process(mark(dec!(45000)));
+    assert!(deferred(&engine, "alice"));
+    assert!(!engine.state.accounts["alice"].positions.is_empty());
+
+    // Liquidate-anyway releases her at the update itself; bob, at 3,000, stays.
+    let liquidate_anyway = EngineConfig {
+        closed_session_liquidation: ClosedSessionLiquidation::LiquidateAnyway,
+        ..initial.clone()
+    };
+    let first = update(&mut engine, liquidate_anyway);
+    assert!(liquidated_by(&engine, "alice", first));
+    assert!(engine.state.accounts["alice"].positions.is_empty());
+    assert!(!engine.state.accounts["bob"].positions.is_empty());
+    assert_eq!(
+        engine.config().closed_session_liquidation,
+        ClosedSessionLiquidation::LiquidateAnyway
+    );
+
+    // Back to deferring: bob's breach at 43,000 waits.
+    update(&mut engine, initial.clone());
+    let ProcessOutcome::Accepted { sequence: breach } = engine.process(mark(dec!(43000))) else {
+        panic!("the mark is accepted");
+    };
+    assert!(!liquidated_by(&engine, "bob", breach));
+    assert!(deferred(&engine, "bob"));
+    assert!(!engine.state.accounts["bob"].positions.is_empty());
+
+    // Updates a running engine cannot take change nothing.
+    let state = engine.state.clone();
+    let rejected = [
+        (
+            EngineConfig {
+                mode: EngineMode::DryRun,
+                ..initial.clone()
+            },
+            "mode cannot be changed on a running engine",
+        ),
+        (
+            EngineConfig {
+                withdrawal_buffer: dec!(0.9),
+                ..initial.clone()
+            },
+            "withdrawal_buffer must be at least 1, got 0.9",
+        ),
+        (
+            EngineConfig {
+                liquidation_path: LiquidationPath::Keepers(Vec::new()),
+                ..initial.clone()
+            },
+            "liquidation_path: no keeper accounts",
+        ),
+    ];
+    for (config, reason) in rejected {
+        let outcome = engine.process(EventType::ConfigUpdated { config });
+        let ProcessOutcome::Rejected { reason: got, .. } = outcome else {
+            panic!("expected a rejection");
+        };
+        assert_eq!(got, RejectReason::InvalidEvent(reason.to_string()));
+    }
+    assert_eq!(engine.state, state);
+    assert_eq!(*engine.config(), initial);
+
+    // A 20% withdrawal buffer cuts what carol may take out from 850 to 420.
+    let carol = &engine.state.accounts["carol"];
+    assert_eq!(
+        margin::max_withdrawable(carol, &engine.state, dec!(1)),
+        dec!(850)
+    );
+    let buffered = EngineConfig {
+        withdrawal_buffer: dec!(1.2),
+        ..initial.clone()
+    };
+    update(&mut engine, buffered.clone());
+    let withdraw = |amount| EventType::Withdraw {
+        account_id: id("carol"),
+        amount,
+    };
+    assert!(matches!(
+        engine.process(withdraw(dec!(500))),
+        ProcessOutcome::Rejected { .. }
+    ));
+    assert!(engine.process(withdraw(dec!(420))).is_accepted());
+
+    // Only now is bob liquidated.
+    let third = update(
+        &mut engine,
+        EngineConfig {
+            closed_session_liquidation: ClosedSessionLiquidation::LiquidateAnyway,
+            ..buffered.clone()
+        },
+    );
+    assert!(liquidated_by(&engine, "bob", third));
+    assert!(engine.state.accounts["bob"].positions.is_empty());
+    let after_third = engine.event_log.last().unwrap().sequence;
+
+    // At 44,000 carol could take out 1,380 at IM itself, but only 940 over the buffer.
+    engine.process(mark(dec!(44000)));
+    assert!(matches!(
+        engine.process(withdraw(dec!(1000))),
+        ProcessOutcome::Rejected { .. }
+    ));
+
+    // Snapshots stop from the update that turns them off.
+    let retained = engine.snapshots.len();
+    let last = EngineConfig {
+        snapshot_policy: SnapshotPolicy::Never,
+        ..engine.config().clone()
+    };
+    update(&mut engine, last);
+    engine.process(mark(dec!(44500)));
+    assert_eq!(engine.snapshots.len(), retained);
+
+    // Replay starts from the marker's config and switches where the log did.
+    let replayed = Engine::replay_verified(&engine.event_log, markets(), initial.clone()).unwrap();
+    assert_eq!(replayed.state, engine.state);
+    assert_eq!(replayed.config, *engine.config());
+    let regenerated = Engine::regenerate(&engine.event_log, markets(), initial.clone()).unwrap();
+    assert_eq!(diff_logs(&engine.event_log, &regenerated), None);
+    let snapshot = engine
+        .snapshots
+        .iter()
+        .find(|s| s.after_sequence == third)
+        .unwrap();
+    assert_eq!(engine.reconstruct_snapshot(third).unwrap(), *snapshot);
+
+    // A checkpoint between updates goes live under the config then in effect: the
+    // withdrawal after it is refused again only under the buffer.
+    let options = ReplayOptions {
+        config: initial.clone(),
+        stop_at_sequence: Some(after_third),
+        ..ReplayOptions::default()
+    };
+    let prefix = Engine::replay_with(options, &engine.event_log, markets());
+    assert_eq!(prefix.config.withdrawal_buffer, dec!(1.2));
+    let checkpoint = Checkpoint {
+        after_sequence: after_third,
+        state: prefix.state,
+    };
+    let report = Engine::validate_checkpoint(&checkpoint, &engine.event_log, markets()).unwrap();
+    assert!(report.is_valid(), "{:?}", report.divergence);
+    assert!(report.suffix_events > 0);
+
+    println!(
+        "alice liquidated at update seq {first}, bob deferred at seq {breach} and liquidated at update seq {third}; \
+         replay ends under the same config ({})",
+        engine.config().hash()
+    );
+}
@@ -0,0 +1,91 @@
+// Liquidation closes under a market's min_liquidation_notional. Scenario 51 liquidates
+// an off-step 0.015 BTC worth less than the minimum in one fill, and rounds a
+// backstop's one-lot share up to the minimum. Check the fills, verified replay, and
+// that without the minimum the same position takes a lot close and then its dust.
+
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::scenario::{self, Scenario};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+/// The quantity of each liquidation record naming `account_id`, as liquidated account.
+fn closes(engine: &Engine, account_id: &str) -> Vec<(&'static str, Decimal)> {
+    engine
+        .event_log
+        .iter()
+        .filter_map(|e| match &e.event_type {
+            EventType::LiquidationFill {
+                account_id: account,
+                quantity,
+                ..
+            } if account.as_str() == account_id => Some(("LiquidationFill", *quantity)),
+            EventType::LiquidationTakeover {
+                liquidated_account,
+                quantity,
+                ..
+            } if liquidated_account.as_str() == account_id => {
+                Some(("LiquidationTakeover", *quantity))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn main() {
+    let scenario = scenario::load("scenarios/51_min_liquidation_notional.toml").unwrap();
+    let markets: Vec<Market> = scenario.markets.iter().map(|m| m.to_market()).collect();
+    let engine = scenario::run(&scenario).unwrap().engine;
+
+    let replayed =
+        Engine::replay_verified(&engine.event_log, markets, scenario.config.clone()).unwrap();
+    assert_eq!(replayed.state, engine.state);
+    assert!(engine.solvency().is_balanced());
+
+    // The tiny residual: one fill closes it whole.
+    assert_eq!(
+        closes(&engine, "alice"),
+        [("LiquidationFill", dec!(-0.015))]
+    );
+    // dave's cap covers one lot, worth 460; he takes the 0.03 the minimum needs.
+    assert_eq!(
+        closes(&engine, "erin"),
+        [
+            ("LiquidationTakeover", dec!(-0.03)),
+            ("LiquidationFill", dec!(-0.97))
+        ]
+    );
+    let dave = &engine.state.backstops[0];
+    assert!(dave.absorbed * dec!(46000) > dave.max_notional);
+
+    // Without the minimum, whole lots go first. They leave alice healthy at 48,000,
+    // and her dust waits for the next mark, where dave's backstop takes it. What is
+    // left of his cap is less than a lot, so erin closes whole at mark.
+    let mut unbounded = scenario.clone();
+    for market in &mut unbounded.markets {
+        market.min_liquidation_notional = None;
+    }
+    let steps = unbounded
+        .steps
+        .iter()
+        .filter(|step| !step.starts_with("expect"))
+        .cloned()
+        .collect();
+    let unbounded = Scenario { steps, ..unbounded };
+    let split = scenario::run(&unbounded).unwrap().engine;
+    assert_eq!(
+        closes(&split, "alice"),
+        [
+            ("LiquidationFill", dec!(-0.01)),
+            ("LiquidationTakeover", dec!(-0.005))
+        ]
+    );
+    assert_eq!(closes(&split, "erin"), [("LiquidationFill", dec!(-1))]);
+
+    println!(
+        "scenario 51 closes alice in {} fill and erin in {}; without the minimum, {} and {}",
+        closes(&engine, "alice").len(),
+        closes(&engine, "erin").len(),
+        closes(&split, "alice").len(),
+        closes(&split, "erin").len()
+    );
+}
@@ -0,0 +1,69 @@
+// Embed the engine in a service: configure it, feed it events, and act on the
+// outcome of each one.
+
+use cross_margin_engine::margin;
+use cross_margin_engine::prelude::*;
+use rust_decimal_macros::dec;
+
+fn main() {
+    let mut engine = Engine::builder()
+        .liquidation_strategy(LiquidationStrategy::BestMarginImprovementFirst)
+        .build();
+    engine
+        .add_market(Market::new(
+            "BTC-PERP".parse().unwrap(),
+            dec!(0.05),
+            dec!(0.03),
+        ))
+        .expect("valid market parameters");
+
+    let submissions = vec![
+        EventType::MarkPriceUpdate {
+            market_id: "BTC-PERP".parse().unwrap(),
+            price: dec!(50000),
+        },
+        EventType::Deposit {
+            account_id: "alice".parse().unwrap(),
+            amount: dec!(10000),
+        },
+        // 50,000 of IM against 10,000 of equity: rejected.
+        EventType::TradeFill {
+            account_id: "alice".parse().unwrap(),
+            market_id: "BTC-PERP".parse().unwrap(),
+            quantity: dec!(20),
+            price: dec!(50000),
+            liquidity: None,
+        },
+        EventType::TradeFill {
+            account_id: "alice".parse().unwrap(),
+            market_id: "BTC-PERP".parse().unwrap(),
+            quantity: dec!(2),
+            price: dec!(50000),
+            liquidity: None,
+        },
+    ];
+
+    for event_type in submissions {
+        match engine.process(event_type) {
+            ProcessOutcome::Accepted { sequence } => println!("seq {sequence}: accepted"),
+            ProcessOutcome::Rejected { sequence, reason } => {
+                println!("seq {sequence}: rejected: {reason}")
+            }
+            ProcessOutcome::Duplicate {
+                sequence,
+                original_sequence,
+            } => println!("seq {sequence}: duplicate of seq {original_sequence}"),
+            ProcessOutcome::Suppressed { reason } => println!("suppressed repeat: {reason}"),
+            ProcessOutcome::SinkFailed { .. } => unreachable!("no snapshot sink"),
+            ProcessOutcome::LogStoreFailed { .. } => unreachable!("no log store"),
+        }
+    }
+
+    let account = &engine.state.accounts["alice"];
+    println!(
+        "alice: equity {}, IM {}, {} events logged",
+        margin::equity(account, &engine.state),
+        margin::initial_margin_required(account, &engine.state),
+        engine.event_log.len()
+    );
+}
@@ -0,0 +1,129 @@
+// Classify fills over the grid of position sign and fill sign and size, against a
+// table of the expected class. Then put each fill through an engine: what it does to
+// the position must be what its class says, and once the market is outside its
+// trading session, only the fills classified as reducing may be accepted.
+
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::risk::{classify_fill, FillClass, FillClass::*};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+const ENTRY: Decimal = dec!(100);
+const PRICE: Decimal = dec!(110);
+
+fn main() {
+    let currents = [dec!(-10), dec!(0), dec!(10)];
+    let fills = [
+        dec!(-15),
+        dec!(-10),
+        dec!(-4),
+        dec!(0),
+        dec!(4),
+        dec!(10),
+        dec!(15),
+    ];
+    #[rustfmt::skip]
+    let table = [
+        // fill:  -15       -10       -4        0         4         10        15
+        /* -10 */ [Increase, Increase, Increase, Increase, Reduce,   Close,    Flip],
+        /*   0 */ [Open,     Open,     Open,     Open,     Open,     Open,     Open],
+        /*  10 */ [Flip,     Close,    Reduce,   Increase, Increase, Increase, Increase],
+    ];
+
+    for (current, row) in currents.into_iter().zip(table) {
+        for (fill, expected) in fills.into_iter().zip(row) {
+            let class = classify_fill(current, fill);
+            assert_eq!(class, expected, "position {current}, fill {fill}");
+            check_in_engine(current, fill, class, false);
+            check_in_engine(current, fill, class, true);
+        }
+    }
+    println!(
+        "{} fills classified and applied",
+        currents.len() * fills.len()
+    );
+}
+
+fn check_in_engine(current: Decimal, fill: Decimal, class: FillClass, session_closed: bool) {
+    let mut engine = Engine::new();
+    engine
+        .add_market(Market::new(
+            "BTC-PERP".parse().unwrap(),
+            dec!(0.10),
+            dec!(0.05),
+        ))
+        .unwrap();
+    let trade = |quantity, price| EventType::TradeFill {
+        account_id: "alice".parse().unwrap(),
+        market_id: "BTC-PERP".parse().unwrap(),
+        quantity,
+        price,
+        liquidity: None,
+    };
+    engine.process(EventType::MarkPriceUpdate {
+        market_id: "BTC-PERP".parse().unwrap(),
+        price: ENTRY,
+    });
+    engine.process(EventType::Deposit {
+        account_id: "alice".parse().unwrap(),
+        amount: dec!(10000),
+    });
+    if !current.is_zero() {
+        assert!(engine.process(trade(current, ENTRY)).is_accepted());
+    }
+    if session_closed {
+        engine.process(EventType::SessionClose {
+            market_id: "BTC-PERP".parse().unwrap(),
+        });
+    }
+
+    let collateral = engine.state.accounts["alice"].collateral();
+    let outcome = engine.process(trade(fill, PRICE));
+    let label =
+        format!("position {current}, fill {fill} ({class:?}), session closed {session_closed}");
+    if session_closed && !class.is_risk_reducing() {
+        let ProcessOutcome::Rejected { reason, .. } = &outcome else {
+            panic!("{label}: {outcome:?}")
+        };
+        assert!(
+            matches!(reason, RejectReason::MarketClosed(_)),
+            "{label}: {reason}"
+        );
+        return;
+    }
+    assert!(outcome.is_accepted(), "{label}: {outcome:?}");
+
+    let account = &engine.state.accounts["alice"];
+    let realized = account.collateral() - collateral;
+    let position = account.positions.get("BTC-PERP");
+    let (quantity, cost_basis) = position.map_or((Decimal::ZERO, Decimal::ZERO), |p| {
+        (p.quantity, p.cost_basis)
+    });
+    assert_eq!(quantity, current + fill, "{label}");
+    match class {
+        // Nothing realized; the fill's cost is added to the basis.
+        Open | Increase => {
+            assert_eq!(realized, Decimal::ZERO, "{label}");
+            assert_eq!(cost_basis, current * ENTRY + fill * PRICE, "{label}");
+            assert_eq!(position.is_some(), !quantity.is_zero(), "{label}");
+        }
+        // The closed part realizes its move, the rest keeps its entry.
+        Reduce => {
+            assert_eq!(realized, -fill * (PRICE - ENTRY), "{label}");
+            assert_eq!(cost_basis, quantity * ENTRY, "{label}");
+        }
+        Close => {
+            assert!(position.is_none(), "{label}");
+            assert_eq!(realized, current * (PRICE - ENTRY), "{label}");
+        }
+        // All of the old position realizes; the remainder opens at the fill price.
+        Flip => {
+            assert!(
+                quantity.is_sign_negative() != current.is_sign_negative(),
+                "{label}"
+            );
+            assert_eq!(realized, current * (PRICE - ENTRY), "{label}");
+            assert_eq!(cost_basis, quantity * PRICE, "{label}");
+        }
+    }
+}
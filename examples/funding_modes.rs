@@ -0,0 +1,128 @@
+// Continuous against discrete funding. Scenario 45 liquidates alice on accrued BTC
+// funding alone, before any settlement, and then settles without moving anyone's
+// equity. Replay, state files and snapshots carry the accrual. Run the same external
+// events again with BTC settling discretely, and the accrual changes nothing until
+// the settlement: alice's extra trade and withdrawal pass, and the settlement is
+// what liquidates her.
+
+use cross_margin_engine::margin;
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::scenario;
+use cross_margin_engine::snapshot;
+use cross_margin_engine::types::FundingMode;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+fn btc_settlement(event: &Event, index: Decimal) -> bool {
+    matches!(
+        &event.event_type,
+        EventType::FundingUpdate { market_id, new_cumulative_index }
+            if market_id.as_str() == "BTC-PERP" && *new_cumulative_index == index
+    )
+}
+
+fn main() {
+    let scenario = scenario::load("scenarios/45_continuous_funding.toml").unwrap();
+    let markets: Vec<Market> = scenario.markets.iter().map(|m| m.to_market()).collect();
+    let engine = scenario::run(&scenario).unwrap().engine;
+
+    let replayed =
+        Engine::replay_verified(&engine.event_log, markets.clone(), scenario.config.clone())
+            .unwrap();
+    assert_eq!(replayed.state, engine.state);
+    assert_eq!(
+        State::from_json(&engine.state.to_json()).unwrap(),
+        engine.state
+    );
+    for saved in &engine.snapshots {
+        snapshot::restore(saved, markets.clone()).unwrap();
+    }
+    let pending = |saved: &Snapshot| {
+        let alice = saved.accounts.get("alice")?;
+        alice.pending_funding.get("BTC-PERP").copied()
+    };
+    assert!(engine
+        .snapshots
+        .iter()
+        .any(|s| pending(s) == Some(dec!(-1500))));
+
+    // The liquidation fill is caused by the accrual, and no settlement came before it.
+    let liquidation = engine
+        .event_log
+        .iter()
+        .find(|e| matches!(e.event_type, EventType::LiquidationFill { .. }))
+        .unwrap();
+    let cause = &engine.event_log[liquidation.caused_by.unwrap() as usize - 1];
+    assert!(matches!(cause.event_type, EventType::FundingAccrual { .. }));
+    assert!(!engine.event_log[..liquidation.sequence as usize]
+        .iter()
+        .any(|e| e.event_type.kind() == "FundingUpdate"));
+
+    // Equity either side of the settlement at the accrued index.
+    let settlement = engine
+        .event_log
+        .iter()
+        .find(|e| btc_settlement(e, dec!(1500)))
+        .unwrap();
+    let at = |sequence: u64| {
+        engine
+            .snapshots
+            .iter()
+            .find(|s| s.after_sequence == sequence)
+            .unwrap()
+    };
+    let (before, after) = (at(settlement.sequence - 1), at(settlement.sequence));
+    for account_id in ["alice", "bob"] {
+        assert_eq!(
+            before.accounts[account_id].equity,
+            after.accounts[account_id].equity
+        );
+        assert_ne!(
+            before.accounts[account_id].collateral,
+            after.accounts[account_id].collateral
+        );
+    }
+
+    // The same external events with BTC settling discretely.
+    let mut discrete = Engine::with_config(scenario.config.clone());
+    for market in &markets {
+        let mut market = market.clone();
+        market.funding_mode = FundingMode::Discrete;
+        discrete.add_market(market).unwrap();
+    }
+    let mut liquidated_by = None;
+    for event in engine
+        .event_log
+        .iter()
+        .filter(|e| e.caused_by.is_none() && !e.event_type.is_engine_generated())
+    {
+        let held = !discrete
+            .state
+            .accounts
+            .get("alice")
+            .is_none_or(|a| a.positions.is_empty());
+        let outcome = discrete.process(event.event_type.clone());
+        match &event.event_type {
+            EventType::TradeFill { account_id, .. } | EventType::Withdraw { account_id, .. }
+                if account_id.as_str() == "alice" =>
+            {
+                assert!(outcome.is_accepted(), "{:?}", event.event_type);
+            }
+            _ => {}
+        }
+        if held && discrete.state.accounts["alice"].positions.is_empty() {
+            liquidated_by.get_or_insert(event.event_type.clone());
+        }
+        if matches!(event.event_type, EventType::FundingAccrual { .. }) {
+            let alice = &discrete.state.accounts["alice"];
+            assert_eq!(margin::equity(alice, &discrete.state), alice.collateral());
+        }
+    }
+    let liquidated_by = liquidated_by.unwrap();
+    assert!(btc_settlement(&Event::new(0, liquidated_by), dec!(1500)));
+
+    println!(
+        "continuous: alice liquidated by accrual at seq {}, settlement kept equity; discrete: liquidated by the settlement",
+        cause.sequence
+    );
+}
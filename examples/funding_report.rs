@@ -0,0 +1,97 @@
+// Build the funding report for two scenarios and check it against totals worked out
+// by hand: one period with more longs than shorts, and a multi-account run where
+// funding arrives as index updates and as a rate, moves both ways, and ends unbalanced.
+
+use cross_margin_engine::report::{self, FundingPeriodReport};
+use cross_margin_engine::scenario;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+/// (old index, new index, implied rate, paid by longs, received by shorts, balanced)
+type Row = (Decimal, Decimal, Option<Decimal>, Decimal, Decimal, bool);
+
+fn funding_rows(path: &str) -> Vec<FundingPeriodReport> {
+    let path = format!("{}/scenarios/{path}", env!("CARGO_MANIFEST_DIR"));
+    let scenario = scenario::load(&path).unwrap();
+    let run = scenario::run(&scenario).unwrap();
+    let markets = scenario.markets.iter().map(|m| m.to_market()).collect();
+    report::funding_history(&run.engine.event_log, markets)
+}
+
+fn check(path: &str, expected: &[Row]) {
+    let periods = funding_rows(path);
+    let rows: Vec<Row> = periods
+        .iter()
+        .map(|p| {
+            assert_eq!(p.residual, p.received_by_shorts - p.paid_by_longs);
+            (
+                p.old_index,
+                p.new_index,
+                p.implied_rate,
+                p.paid_by_longs,
+                p.received_by_shorts,
+                p.balanced,
+            )
+        })
+        .collect();
+    assert_eq!(rows, expected, "{path}");
+    let longs: Decimal = periods.iter().map(|p| p.paid_by_longs).sum();
+    let shorts: Decimal = periods.iter().map(|p| p.received_by_shorts).sum();
+    println!(
+        "{path}: {} periods, longs paid {}, shorts received {}",
+        periods.len(),
+        longs.normalize(),
+        shorts.normalize()
+    );
+}
+
+fn main() {
+    check(
+        "04_funding.toml",
+        &[(
+            dec!(0),
+            dec!(1.5),
+            Some(dec!(0.0005)),
+            dec!(30),
+            dec!(15),
+            false,
+        )],
+    );
+    check(
+        "24_funding_multi_account.toml",
+        &[
+            (
+                dec!(0),
+                dec!(1.5),
+                Some(dec!(0.0005)),
+                dec!(22.5),
+                dec!(22.5),
+                true,
+            ),
+            (
+                dec!(1.5),
+                dec!(1.82),
+                Some(dec!(0.0001)),
+                dec!(4.8),
+                dec!(4.8),
+                true,
+            ),
+            (
+                dec!(1.82),
+                dec!(1.0),
+                Some(dec!(-0.00025625)),
+                dec!(-12.3),
+                dec!(-12.3),
+                true,
+            ),
+            (
+                dec!(1.0),
+                dec!(2.0),
+                Some(dec!(0.0003125)),
+                dec!(15),
+                dec!(12),
+                false,
+            ),
+        ],
+    );
+}
@@ -0,0 +1,153 @@
+// Liquidation order under hedge-pair relief. Scenario 47 gives alice a BTC pair whose
+// legs are each 100,000 of notional but only 10,000 charged, next to 60,000 of ETH.
+// Replayed just before the ETH drop that liquidates her, the planner closes ETH and
+// leaves her healthy, where closing a BTC leg first would have raised her MM. Both
+// strategies agree. bob holds nothing but the pair: the plan closes the leg with the
+// larger charged notional, the next step's MM already has the relief gone, and the
+// engine's fills are the plan's.
+
+use cross_margin_engine::liquidation;
+use cross_margin_engine::margin;
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::scenario;
+use rust_decimal_macros::dec;
+
+fn market(id: &str) -> MarketId {
+    id.parse().unwrap()
+}
+
+/// The state just before the first external event matching `target`. A mark update
+/// then moves the mark, without the liquidation it would trigger.
+fn state_at(
+    engine: &Engine,
+    markets: &[Market],
+    config: &EngineConfig,
+    target: &EventType,
+) -> State {
+    let mut live = Engine::with_config(config.clone());
+    for market in markets {
+        live.add_market(market.clone()).unwrap();
+    }
+    for event in engine
+        .event_log
+        .iter()
+        .filter(|e| e.caused_by.is_none() && !e.event_type.is_engine_generated())
+    {
+        if event.event_type == *target {
+            break;
+        }
+        live.process(event.event_type.clone());
+    }
+    let mut state = live.state.clone();
+    if let EventType::MarkPriceUpdate { market_id, price } = target {
+        state.markets.get_mut(market_id).unwrap().mark_price = *price;
+    }
+    state
+}
+
+fn main() {
+    let scenario = scenario::load("scenarios/47_hedged_liquidation_order.toml").unwrap();
+    let markets: Vec<Market> = scenario.markets.iter().map(|m| m.to_market()).collect();
+    let engine = scenario::run(&scenario).unwrap().engine;
+    let replayed =
+        Engine::replay_verified(&engine.event_log, markets.clone(), scenario.config.clone())
+            .unwrap();
+    assert_eq!(replayed.state, engine.state);
+
+    let (perp, dated, eth) = (market("BTC-PERP"), market("BTC-0327"), market("ETH-PERP"));
+    let alice: AccountId = "alice".parse().unwrap();
+    let bob: AccountId = "bob".parse().unwrap();
+
+    // alice at ETH 2,750: 3,000 of equity against 3,550 of MM.
+    let drop = EventType::MarkPriceUpdate {
+        market_id: eth.clone(),
+        price: dec!(2750),
+    };
+    let state = state_at(&engine, &markets, &scenario.config, &drop);
+    let account = &state.accounts[&alice];
+    assert!(margin::is_liquidatable(account, &state));
+    let charged: Vec<_> = [&perp, &dated, &eth]
+        .into_iter()
+        .map(|market_id| margin::charged_notional(&account.positions, market_id, &state))
+        .collect();
+    assert_eq!(charged, [dec!(10000), dec!(10000), dec!(55000)]);
+
+    for strategy in [
+        LiquidationStrategy::LargestNotionalFirst,
+        LiquidationStrategy::BestMarginImprovementFirst,
+    ] {
+        let plan = liquidation::plan_with(&state, &alice, strategy).unwrap();
+        assert_eq!(plan.steps.len(), 1, "{strategy:?}");
+        let step = &plan.steps[0];
+        assert_eq!((&step.market_id, step.close_quantity), (&eth, dec!(-20)));
+        assert_eq!(
+            (step.projected_equity, step.projected_maintenance_margin),
+            (dec!(3000), dec!(800))
+        );
+    }
+
+    // The leg gross notional would have picked (a tie at 100,000, broken by market id)
+    // loses the relief on both. It is at its entry, so the close realizes nothing.
+    let mut wrong = account.clone();
+    wrong.positions.remove(&dated);
+    assert_eq!(
+        margin::maintenance_margin_required(&wrong, &state),
+        dec!(5750)
+    );
+    assert!(margin::is_liquidatable(&wrong, &state));
+
+    // bob at BTC-0327 51,000: the dated leg is charged 12,000 and the perpetual 10,000.
+    let rise = EventType::MarkPriceUpdate {
+        market_id: dated.clone(),
+        price: dec!(51000),
+    };
+    let state = state_at(&engine, &markets, &scenario.config, &rise);
+    let plan = liquidation::plan(&state, &bob).unwrap();
+    let closes: Vec<_> = plan
+        .steps
+        .iter()
+        .map(|s| {
+            (
+                &s.market_id,
+                s.close_quantity,
+                s.projected_maintenance_margin,
+            )
+        })
+        .collect();
+    assert_eq!(
+        closes,
+        [(&dated, dec!(2), dec!(3000)), (&perp, dec!(-2), dec!(0))]
+    );
+    // The closes leave him flat at exactly zero, not under it: no deficit to finalize.
+    assert!(!plan.exhausted);
+    assert_eq!(plan.steps[1].projected_collateral, dec!(0));
+
+    // The engine took the same steps, in the same order.
+    let fills: Vec<_> = engine
+        .event_log
+        .iter()
+        .filter_map(|e| match &e.event_type {
+            EventType::LiquidationFill {
+                account_id,
+                market_id,
+                quantity,
+                price,
+            } if *account_id == bob => Some((market_id, *quantity, *price)),
+            _ => None,
+        })
+        .collect();
+    let planned: Vec<_> = plan
+        .steps
+        .iter()
+        .map(|s| (&s.market_id, s.close_quantity, s.price))
+        .collect();
+    assert_eq!(fills, planned);
+
+    println!(
+        "alice: ETH closed ahead of the BTC pair (charged {} against {}); bob: {} then {}",
+        charged[2].normalize(),
+        charged[0].normalize(),
+        closes[0].0,
+        closes[1].0
+    );
+}
@@ -0,0 +1,132 @@
+// Walk BTC out and back from 100 to ten levels, so its returns are a known set of
+// ratios, and ETH twice, so it has fewer returns than the window. Pin the VaR of a
+// BTC-only account at the 95th and 99th percentiles and over a shorter window, and of
+// an account long BTC and short ETH, whose older scenarios move BTC alone.
+
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::risk::{self, VarReport};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+fn mark(market_id: &str, price: Decimal) -> EventType {
+    EventType::MarkPriceUpdate {
+        market_id: market_id.parse().unwrap(),
+        price,
+    }
+}
+
+fn main() {
+    let mut engine = Engine::new();
+    engine
+        .add_market(Market::new(
+            "BTC-PERP".parse().unwrap(),
+            dec!(0.05),
+            dec!(0.03),
+        ))
+        .unwrap();
+    engine
+        .add_market(Market::new(
+            "ETH-PERP".parse().unwrap(),
+            dec!(0.10),
+            dec!(0.05),
+        ))
+        .unwrap();
+    engine.process(mark("BTC-PERP", dec!(100)));
+    engine.process(mark("ETH-PERP", dec!(3000)));
+    for (account_id, market_id, quantity, price) in [
+        ("alice", "BTC-PERP", dec!(10), dec!(100)),
+        ("bob", "BTC-PERP", dec!(10), dec!(100)),
+        ("bob", "ETH-PERP", dec!(-1), dec!(3000)),
+    ] {
+        engine.process(EventType::Deposit {
+            account_id: account_id.parse().unwrap(),
+            amount: dec!(50000),
+        });
+        let fill = engine.process(EventType::TradeFill {
+            account_id: account_id.parse().unwrap(),
+            market_id: market_id.parse().unwrap(),
+            quantity,
+            price,
+            liquidity: None,
+        });
+        assert!(fill.is_accepted());
+    }
+    engine.process(EventType::Deposit {
+        account_id: "carol".parse().unwrap(),
+        amount: dec!(1000),
+    });
+
+    // BTC returns, oldest first: 0.8, 1.25, 0.5, 2, 0.4, 2.5, 0.625, 1.6, 0.2, 5. The
+    // rejected negative mark is no observation.
+    for level in [dec!(80), dec!(50), dec!(40), dec!(62.5), dec!(20)] {
+        engine.process(mark("BTC-PERP", level));
+        engine.process(mark("BTC-PERP", dec!(100)));
+    }
+    assert!(!engine.process(mark("BTC-PERP", dec!(-5))).is_accepted());
+    // ETH returns: 0.8, 1.25.
+    engine.process(mark("ETH-PERP", dec!(2400)));
+    engine.process(mark("ETH-PERP", dec!(3000)));
+
+    let var = |account_id: &str, window, percentile| -> VarReport {
+        let account_id = account_id.parse().unwrap();
+        risk::historical_var(
+            &engine.state,
+            &engine.event_log,
+            &account_id,
+            window,
+            percentile,
+        )
+    };
+
+    // Long 10 BTC at 100 loses 1000 × (1 − ratio). Sorted: −4000, −1500, −1000, −600,
+    // −250, 200, 375, 500, 600, 800. The 95th percentile sits at rank 8.55.
+    let alice = var("alice", 250, dec!(0.95));
+    assert_eq!(alice.equity, dec!(50000));
+    assert_eq!((alice.scenarios, alice.observations["BTC-PERP"]), (10, 10));
+    assert_eq!(alice.var, dec!(710));
+    assert_eq!(alice.worst_loss, dec!(800));
+    let tail = var("alice", 250, dec!(0.99));
+    assert_eq!(tail.var, dec!(782));
+    assert_eq!(var("alice", 250, dec!(2)).var, dec!(800));
+    println!(
+        "alice: 95% VaR {}, 99% VaR {}, worst {}",
+        alice.var.normalize(),
+        tail.var.normalize(),
+        alice.worst_loss.normalize()
+    );
+
+    // The last four moves: 5, 0.2, 1.6, 0.625, so losses −4000, −600, 375, 800.
+    let recent = var("alice", 4, dec!(0.95));
+    assert_eq!(recent.scenarios, 4);
+    assert_eq!(recent.var, dec!(736.25));
+
+    // Short 1 ETH at 3000 loses 3000 × (ratio − 1). ETH moves only in the two latest
+    // scenarios: 5 with 1.25 (−3250) and 0.2 with 0.8 (200). The other eight are BTC's.
+    let bob = var("bob", 250, dec!(0.95));
+    assert_eq!(bob.observations["ETH-PERP"], 2);
+    assert_eq!(bob.scenarios, 10);
+    assert_eq!(bob.var, dec!(555));
+    assert_eq!(bob.worst_loss, dec!(600));
+
+    // A flat account, and one that does not exist, have no scenarios.
+    for account_id in ["carol", "dave"] {
+        let flat = var(account_id, 250, dec!(0.99));
+        assert_eq!(
+            (flat.scenarios, flat.var, flat.worst_loss),
+            (0, dec!(0), dec!(0))
+        );
+    }
+
+    // The report is a function of the log: replay gives the same one.
+    let markets = engine.state.markets.values().cloned().collect();
+    let replayed = Engine::replay_verified(&engine.event_log, markets, engine.config().clone());
+    let replayed = replayed.unwrap();
+    let again = risk::historical_var(
+        &replayed.state,
+        &engine.event_log,
+        &"bob".parse().unwrap(),
+        250,
+        dec!(0.95),
+    );
+    assert_eq!(again, bob);
+}
@@ -0,0 +1,248 @@
+// Drive the engine through its JSON command interface (`Engine::handle`): replay
+// every scenario in scenarios/ as `Process` commands and check the log, state and
+// queries against a direct run, then throw malformed and mutated JSON at it and
+// check that every answer is a well-formed response and that registering a market
+// twice is refused.
+
+use cross_margin_engine::command::{Command, CommandErrorKind, Response};
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::rng::Lcg;
+use cross_margin_engine::scenario::{self, Step};
+use cross_margin_engine::snapshot;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+fn handle(engine: &mut Engine, command: &Command) -> Response {
+    let json = serde_json::to_string(command).unwrap();
+    serde_json::from_str(&engine.handle(&json)).expect("a well-formed response")
+}
+
+/// A command as the engine receives it over JSON: decimals normalized, so that
+/// margin figures and rejection messages print the same digits.
+fn round_trip(command: &Command) -> Command {
+    serde_json::from_str(&serde_json::to_string(command).unwrap()).unwrap()
+}
+
+/// Run one scenario's actions as commands and check them against the same events
+/// processed directly. Returns the commands sent.
+fn drive(path: &std::path::Path) -> Vec<String> {
+    let scenario = scenario::load(path).unwrap();
+    let run = scenario::run(&scenario).unwrap().engine;
+
+    let mut engine = Engine::with_config(scenario.config.clone());
+    let mut direct = Engine::with_config(scenario.config.clone());
+    let mut sent = Vec::new();
+    for market in &scenario.markets {
+        let command = Command::AddMarket {
+            market: market.to_market(),
+        };
+        assert!(matches!(
+            handle(&mut engine, &command),
+            Response::MarketAdded { .. }
+        ));
+        let Command::AddMarket { market } = round_trip(&command) else {
+            unreachable!()
+        };
+        direct.add_market(market).unwrap();
+    }
+    for step in scenario::compile(&scenario).unwrap() {
+        let Step::Action(event) = step else { continue };
+        let command = Command::Process {
+            event: *event,
+            idempotency_key: None,
+            timestamp: None,
+        };
+        let Command::Process { event, .. } = round_trip(&command) else {
+            unreachable!()
+        };
+        direct.process(event);
+        sent.push(serde_json::to_string(&command).unwrap());
+        match handle(&mut engine, &command) {
+            Response::Accepted { sequence } | Response::Rejected { sequence, .. } => {
+                assert!(sequence < engine.next_sequence());
+            }
+            other => panic!("{}: unexpected response {other:?}", path.display()),
+        }
+    }
+
+    assert_eq!(engine.event_log, direct.event_log, "{}", path.display());
+    assert_eq!(engine.state, direct.state, "{}", path.display());
+    assert_eq!(engine.state, run.state, "{}", path.display());
+
+    for (account_id, account) in &direct.state.accounts {
+        let query = Command::GetAccount {
+            account_id: account_id.clone(),
+        };
+        let Response::Account { account: got, .. } = handle(&mut engine, &query) else {
+            panic!("{account_id} not found");
+        };
+        assert_eq!(*got, snapshot::capture_account(account, &direct.state));
+
+        let query = Command::GetRisk {
+            account_id: account_id.clone(),
+        };
+        let Response::Risk { risk, .. } = handle(&mut engine, &query) else {
+            panic!("no risk for {account_id}");
+        };
+        assert_eq!(risk.equity, got.equity);
+        assert_eq!(
+            risk.maintenance_margin_required,
+            got.maintenance_margin_required
+        );
+        assert_eq!(risk.liquidatable, got.liquidatable);
+    }
+    for (market_id, market) in &direct.state.markets {
+        let query = Command::GetMarket {
+            market_id: market_id.clone(),
+        };
+        assert_eq!(
+            handle(&mut engine, &query),
+            Response::Market {
+                market: Box::new(market.clone())
+            }
+        );
+    }
+    sent
+}
+
+fn main() {
+    let mut paths: Vec<_> = std::fs::read_dir("scenarios")
+        .expect("run from the repository root")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    paths.sort();
+
+    let mut corpus = Vec::new();
+    for path in &paths {
+        corpus.extend(drive(path));
+    }
+    println!(
+        "{} scenarios driven through JSON ({} commands)",
+        paths.len(),
+        corpus.len()
+    );
+
+    // Queries for things that do not exist, and events only the engine may write.
+    let mut engine = Engine::new();
+    let invalid_market = serde_json::to_string(&Command::AddMarket {
+        market: Market::new("BTC-PERP".parse().unwrap(), Decimal::ONE, Decimal::TWO),
+    })
+    .unwrap();
+    engine
+        .add_market(Market::new(
+            "ETH-PERP".parse().unwrap(),
+            dec!(0.10),
+            dec!(0.05),
+        ))
+        .unwrap();
+    let duplicate_market = serde_json::to_string(&Command::AddMarket {
+        market: Market::new("ETH-PERP".parse().unwrap(), dec!(0.20), dec!(0.10)),
+    })
+    .unwrap();
+    for (json, kind) in [
+        (
+            r#"{"command":"GetAccount","account_id":"nobody"}"#,
+            CommandErrorKind::UnknownAccount,
+        ),
+        (
+            r#"{"command":"GetRisk","account_id":"nobody"}"#,
+            CommandErrorKind::UnknownAccount,
+        ),
+        (
+            r#"{"command":"GetMarket","market_id":"NONE"}"#,
+            CommandErrorKind::UnknownMarket,
+        ),
+        (
+            r#"{"command":"Process","event":{"type":"LiquidationFill","account_id":"a","market_id":"M","quantity":"1","price":"1"}}"#,
+            CommandErrorKind::EngineGenerated,
+        ),
+        (&invalid_market, CommandErrorKind::InvalidMarket),
+        (&duplicate_market, CommandErrorKind::DuplicateMarket),
+    ] {
+        let response: Response = serde_json::from_str(&engine.handle(json)).unwrap();
+        assert!(
+            matches!(&response, Response::Error { kind: k, .. } if *k == kind),
+            "{json}: {response:?}"
+        );
+    }
+    assert_eq!(
+        engine.state.markets["ETH-PERP"].initial_margin_fraction,
+        dec!(0.10)
+    );
+
+    // Malformed input: every answer must be a `Parse` error, and the engine must be
+    // left untouched.
+    let deep = "[".repeat(10_000);
+    let mut malformed: Vec<String> = [
+        "",
+        "{",
+        "null",
+        "[]",
+        "42",
+        "\"Process\"",
+        r#"{"command":"Nope"}"#,
+        r#"{"command":"Process"}"#,
+        r#"{"command":"Process","event":{"type":"Deposit","account_id":"a","amount":"ten"}}"#,
+        r#"{"command":"Process","event":{"type":"TradeFill","account_id":"a"}}"#,
+        r#"{"command":"GetAccount","account_id":7}"#,
+        r#"{"command":"Process","event":{"type":"Deposit","account_id":"a","amount":"1"}} trailing"#,
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    malformed.push(deep);
+    for json in &malformed {
+        let response: Response = serde_json::from_str(&engine.handle(json)).unwrap();
+        assert!(
+            matches!(
+                response,
+                Response::Error {
+                    kind: CommandErrorKind::Parse,
+                    ..
+                }
+            ),
+            "{json:.80}: {response:?}"
+        );
+    }
+    assert!(engine.event_log.is_empty());
+
+    // Mutated commands: whatever still parses is executed, everything else is a
+    // `Parse` error. No mutation may panic the engine.
+    let mut engine = Engine::new();
+    let mut rng = Lcg::new(0xC0FFEE);
+    let alphabet: Vec<char> = "{}[]\":,.-+0123456789eEaAzZ \\ntrufls".chars().collect();
+    let (mut parsed, mut rejected) = (0, 0);
+    for _ in 0..20_000 {
+        let mut chars: Vec<char> = rng.pick(&corpus).chars().collect();
+        for _ in 0..1 + rng.below(3) {
+            let at = rng.index(chars.len() + 1);
+            match rng.below(4) {
+                0 => chars.truncate(at),
+                1 if at < chars.len() => {
+                    chars.remove(at);
+                }
+                2 => chars.insert(at, *rng.pick(&alphabet)),
+                _ if at < chars.len() => chars[at] = *rng.pick(&alphabet),
+                _ => {}
+            }
+        }
+        let json: String = chars.into_iter().collect();
+        let response: Response = serde_json::from_str(&engine.handle(&json)).unwrap();
+        match response {
+            Response::Error {
+                kind: CommandErrorKind::Parse,
+                ..
+            } => {
+                assert!(serde_json::from_str::<Command>(&json).is_err());
+                rejected += 1;
+            }
+            Response::Error {
+                kind: CommandErrorKind::Internal,
+                message,
+            } => panic!("{json} panicked the engine: {message}"),
+            _ => parsed += 1,
+        }
+    }
+    println!("20000 mutated commands: {parsed} executed, {rejected} parse errors");
+}
@@ -0,0 +1,167 @@
+// Record a generated crash, then backtest it: full closes against partial closes,
+// at the engine's prices and with seeded jitter. Checks that the harness reproduces
+// the recorded history under the engine's own strategy, and that a seed always gives
+// the same report.
+
+use cross_margin_engine::backtest::{BacktestOutcome, BacktestStrategy, Backtester, FillModel};
+use cross_margin_engine::prelude::*;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::sync::Arc;
+
+fn markets() -> Vec<Market> {
+    let mut btc = Market::new("BTC-PERP".parse().unwrap(), dec!(0.05), dec!(0.03));
+    btc.slippage_bps_per_notional = dec!(0.0002);
+    let mut eth = Market::new("ETH-PERP".parse().unwrap(), dec!(0.10), dec!(0.05));
+    eth.slippage_bps_per_notional = dec!(0.0004);
+    vec![btc, eth]
+}
+
+/// Twelve traders of increasing leverage, long into a 20% slide with one bounce.
+fn crash() -> Vec<Event> {
+    let mut engine = Engine::new();
+    for market in markets() {
+        engine.add_market(market).unwrap();
+    }
+    let mut process = |event| {
+        engine.process(event);
+    };
+    process(EventType::InsuranceFundDeposit {
+        pool_id: "default".into(),
+        amount: dec!(20000),
+    });
+    process(EventType::MarkPriceUpdate {
+        market_id: "BTC-PERP".parse().unwrap(),
+        price: dec!(50000),
+    });
+    process(EventType::MarkPriceUpdate {
+        market_id: "ETH-PERP".parse().unwrap(),
+        price: dec!(3000),
+    });
+    for i in 0..12 {
+        let account_id: AccountId = format!("trader-{i:02}").parse().unwrap();
+        process(EventType::Deposit {
+            account_id: account_id.clone(),
+            amount: dec!(20000),
+        });
+        // From about 2x to 18x on BTC, plus a smaller ETH leg.
+        process(EventType::TradeFill {
+            account_id: account_id.clone(),
+            market_id: "BTC-PERP".parse().unwrap(),
+            quantity: Decimal::from(1 + i * 2) / dec!(2.5),
+            price: dec!(50000),
+            liquidity: None,
+        });
+        process(EventType::TradeFill {
+            account_id,
+            market_id: "ETH-PERP".parse().unwrap(),
+            quantity: Decimal::from(2 + i),
+            price: dec!(3000),
+            liquidity: None,
+        });
+    }
+
+    let path = [
+        (49000, 2950),
+        (48000, 2880),
+        (46500, 2800),
+        (47500, 2850),
+        (45000, 2700),
+        (43000, 2560),
+        (41000, 2450),
+        (40000, 2400),
+    ];
+    for (step, (btc, eth)) in path.into_iter().enumerate() {
+        process(EventType::MarkPriceBatch {
+            updates: [
+                ("BTC-PERP".parse().unwrap(), Decimal::from(btc)),
+                ("ETH-PERP".parse().unwrap(), Decimal::from(eth)),
+            ]
+            .into_iter()
+            .collect(),
+        });
+        // Traders who survived the bounce buy the dip; some are no longer able to.
+        if step == 3 {
+            for i in (0..12).step_by(3) {
+                process(EventType::TradeFill {
+                    account_id: format!("trader-{i:02}").parse().unwrap(),
+                    market_id: "BTC-PERP".parse().unwrap(),
+                    quantity: dec!(0.5),
+                    price: Decimal::from(btc),
+                    liquidity: None,
+                });
+            }
+        }
+    }
+    engine
+        .event_log
+        .into_iter()
+        .map(Arc::unwrap_or_clone)
+        .collect()
+}
+
+fn print(label: &str, s: &BacktestOutcome) {
+    println!(
+        "{label:<34} {:>3} accounts {:>3} fills {:>12} closed {:>10} deficit {:>10} insurance {:>2} rejections",
+        s.accounts_liquidated.len(),
+        s.liquidation_fills,
+        s.closed_notional.round_dp(2),
+        s.deficit_incurred.round_dp(2),
+        s.insurance_paid.round_dp(2),
+        s.rejections,
+    );
+}
+
+fn main() {
+    let log = crash();
+    let order = LiquidationStrategy::LargestNotionalFirst;
+    let full = BacktestStrategy::full(order);
+    let partial = BacktestStrategy::partial(order, dec!(0.25));
+    let run = |seed, strategy, fill_model| {
+        Backtester::new(seed, strategy, fill_model)
+            .run(&log, markets())
+            .unwrap()
+    };
+
+    // The engine's own strategy at the engine's prices is the recorded history.
+    let baseline = run(1, full, FillModel::Engine);
+    assert_eq!(baseline.simulated, baseline.recorded);
+    let recorded = &baseline.recorded;
+    assert!(recorded.liquidation_fills > 0 && recorded.deficit_incurred > Decimal::ZERO);
+    print("recorded", recorded);
+
+    let partial_engine = run(1, partial, FillModel::Engine);
+    print("partial 25%, engine prices", &partial_engine.simulated);
+    for seed in [7, 8] {
+        let full_jittered = run(seed, full, FillModel::Jittered);
+        let partial_jittered = run(seed, partial, FillModel::Jittered);
+        print(
+            &format!("full, jittered (seed {seed})"),
+            &full_jittered.simulated,
+        );
+        print(
+            &format!("partial 25%, jittered (seed {seed})"),
+            &partial_jittered.simulated,
+        );
+
+        // Same seed, same log, same strategy: the same report, down to the JSON.
+        let again = run(seed, partial, FillModel::Jittered);
+        assert_eq!(again, partial_jittered);
+        assert_eq!(
+            serde_json::to_string(&again).unwrap(),
+            serde_json::to_string(&partial_jittered).unwrap()
+        );
+        assert_eq!(full_jittered.recorded, baseline.recorded);
+    }
+    assert_ne!(
+        run(7, full, FillModel::Jittered).simulated,
+        run(8, full, FillModel::Jittered).simulated
+    );
+
+    // Partial closes take more fills but close less, and on this path the smaller
+    // closes also slip less, so they leave a smaller deficit.
+    let simulated = &partial_engine.simulated;
+    assert!(simulated.liquidation_fills > recorded.liquidation_fills);
+    assert!(simulated.closed_notional < recorded.closed_notional);
+    assert!(simulated.deficit_incurred < recorded.deficit_incurred);
+}
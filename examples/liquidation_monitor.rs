@@ -0,0 +1,111 @@
+// Poll who is liquidatable at hypothetical marks without touching the live engine,
+// then check the answer against what actually gets liquidated: first by a no-op
+// tick on an engine seeded with the hypothetical state, then by the live tick.
+
+use cross_margin_engine::margin;
+use cross_margin_engine::prelude::*;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+/// Accounts liquidated (by fill or takeover) among `events`.
+fn liquidated(events: &[Arc<Event>]) -> BTreeSet<AccountId> {
+    events
+        .iter()
+        .filter_map(|e| match &e.event_type {
+            EventType::LiquidationFill { account_id, .. } => Some(account_id.clone()),
+            EventType::LiquidationTakeover {
+                liquidated_account, ..
+            } => Some(liquidated_account.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn main() {
+    let mut engine = Engine::new();
+    engine
+        .add_market(Market::new(
+            "BTC-PERP".parse().unwrap(),
+            dec!(0.05),
+            dec!(0.03),
+        ))
+        .unwrap();
+    engine
+        .add_market(Market::new(
+            "ETH-PERP".parse().unwrap(),
+            dec!(0.10),
+            dec!(0.05),
+        ))
+        .unwrap();
+    engine.process(EventType::MarkPriceBatch {
+        updates: BTreeMap::from([
+            ("BTC-PERP".parse().unwrap(), dec!(50000)),
+            ("ETH-PERP".parse().unwrap(), dec!(3000)),
+        ]),
+    });
+
+    // Leverage rises with the index so each drop takes out a few more accounts.
+    for i in 1..=8u32 {
+        let account_id: AccountId = format!("acct-{i}").parse().unwrap();
+        engine.process(EventType::Deposit {
+            account_id: account_id.clone(),
+            amount: dec!(10000),
+        });
+        engine.process(EventType::TradeFill {
+            account_id: account_id.clone(),
+            market_id: "BTC-PERP".parse().unwrap(),
+            quantity: Decimal::from(i) / dec!(4),
+            price: dec!(50000),
+            liquidity: None,
+        });
+        engine.process(EventType::TradeFill {
+            account_id,
+            market_id: "ETH-PERP".parse().unwrap(),
+            quantity: Decimal::from(i),
+            price: dec!(3000),
+            liquidity: None,
+        });
+    }
+    assert!(margin::liquidatable_accounts(&engine.state).is_empty());
+
+    let mut total = 0;
+    for (btc, eth) in [(48000, 2900), (46000, 2800), (44000, 2700), (40000, 2400)] {
+        let updates = BTreeMap::from([
+            ("BTC-PERP".parse().unwrap(), Decimal::from(btc)),
+            ("ETH-PERP".parse().unwrap(), Decimal::from(eth)),
+        ]);
+
+        let mut hypothetical = engine.state.clone();
+        for (market_id, price) in &updates {
+            hypothetical.markets.get_mut(market_id).unwrap().mark_price = *price;
+        }
+        let predicted = margin::liquidatable_accounts(&hypothetical);
+        for candidate in margin::liquidation_candidates(&hypothetical) {
+            println!(
+                "BTC {btc} ETH {eth}: {} ratio {} short {}",
+                candidate.account_id,
+                candidate.margin_ratio.unwrap_or_default().round_dp(4),
+                candidate.shortfall.round_dp(2)
+            );
+        }
+        let predicted: BTreeSet<AccountId> = predicted.into_iter().collect();
+
+        // A no-op tick over every market scans every account holding a position.
+        let mut seeded =
+            Engine::from_state(hypothetical, engine.next_sequence(), EngineMode::DryRun);
+        seeded.process(EventType::MarkPriceBatch {
+            updates: updates.clone(),
+        });
+        assert_eq!(liquidated(&seeded.event_log), predicted);
+
+        let start = engine.event_log.len();
+        engine.process(EventType::MarkPriceBatch { updates });
+        assert_eq!(liquidated(&engine.event_log[start..]), predicted);
+        assert!(margin::liquidatable_accounts(&engine.state).is_empty());
+        total += predicted.len();
+    }
+    assert!(total > 0, "the marks never liquidated anyone");
+    println!("{total} predicted liquidations, all confirmed");
+}
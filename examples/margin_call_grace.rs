@@ -0,0 +1,177 @@
+// Margin calls under a grace period. Scenario 48 replays to the same state, with bob's
+// liquidation caused by the event that expired his call. A clock grace holds an
+// account through events until enough milliseconds pass, a snapshot keeps an open
+// call, and replay refuses a call that is missing or altered, or a log replayed
+// without its policy. Zero grace and a hard fraction over 1 fail validation.
+
+use cross_margin_engine::error::EngineConfigError;
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::scenario;
+use cross_margin_engine::snapshot;
+use cross_margin_engine::types::MarginCallOpen;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::sync::Arc;
+
+fn markets() -> Vec<Market> {
+    vec![Market::new(
+        "ETH-PERP".parse().unwrap(),
+        dec!(0.10),
+        dec!(0.05),
+    )]
+}
+
+fn mark(price: Decimal) -> EventType {
+    EventType::MarkPriceUpdate {
+        market_id: "ETH-PERP".parse().unwrap(),
+        price,
+    }
+}
+
+fn deposit(account: &str, amount: Decimal) -> EventType {
+    EventType::Deposit {
+        account_id: account.parse().unwrap(),
+        amount,
+    }
+}
+
+fn main() {
+    let scenario = scenario::load("scenarios/48_margin_call_grace.toml").unwrap();
+    let scenario_markets: Vec<Market> = scenario.markets.iter().map(|m| m.to_market()).collect();
+    let engine = scenario::run(&scenario).unwrap().engine;
+    let log = &engine.event_log;
+    let replayed =
+        Engine::replay_verified(log, scenario_markets.clone(), scenario.config.clone()).unwrap();
+    assert_eq!(replayed.state, engine.state);
+
+    // bob's call is caused by the mark at s; his first fill by the deposit at s + 4.
+    let bob: AccountId = "bob".parse().unwrap();
+    let call = log
+        .iter()
+        .find(|e| matches!(&e.event_type, EventType::MarginCall { account_id, .. } if *account_id == bob))
+        .unwrap();
+    let breach = call.caused_by.unwrap();
+    let fill = log
+        .iter()
+        .find(|e| matches!(&e.event_type, EventType::LiquidationFill { account_id, .. } if *account_id == bob))
+        .unwrap();
+    assert_eq!(fill.caused_by, Some(breach + 4));
+    assert!(matches!(
+        log[breach as usize + 3].event_type,
+        EventType::Deposit { .. }
+    ));
+
+    // A log that ends before the call its last mark called for, or holds it at other
+    // figures, fails verification. The log's config marker names the policy, so
+    // replaying it without one is refused up front.
+    let position = log
+        .iter()
+        .position(|e| e.sequence == call.sequence)
+        .unwrap();
+    let result = Engine::replay_verified(
+        &log[..position],
+        scenario_markets.clone(),
+        scenario.config.clone(),
+    );
+    let Err(EngineError::InvalidDerivedEvent { sequence, reason }) = result else {
+        panic!("{result:?}")
+    };
+    assert_eq!(sequence, breach);
+    assert!(reason.contains("no margin call for bob"), "{reason}");
+    let mut forged = log.clone();
+    if let EventType::MarginCall { equity, .. } =
+        &mut Arc::make_mut(&mut forged[position]).event_type
+    {
+        *equity += dec!(1);
+    }
+    let result =
+        Engine::replay_verified(&forged, scenario_markets.clone(), scenario.config.clone());
+    assert!(
+        matches!(result, Err(EngineError::InvalidDerivedEvent { .. })),
+        "{result:?}"
+    );
+    let result = Engine::replay_verified(log, scenario_markets, EngineConfig::default());
+    assert!(
+        matches!(&result, Err(EngineError::ConfigMismatch { fields, .. }) if *fields == ["margin_call"]),
+        "{result:?}"
+    );
+
+    // A one-minute grace on the log clock. Events inside it leave erin alone,
+    // however many; the first one a minute past the breach liquidates her.
+    let policy = MarginCallPolicy {
+        grace: GracePeriod::Millis(60_000),
+        hard_fraction: dec!(0.5),
+    };
+    let mut engine = Engine::builder().margin_call(policy.clone()).build();
+    for market in markets() {
+        engine.add_market(market).unwrap();
+    }
+    engine.process_at(0, mark(dec!(3000)));
+    engine.process_at(0, deposit("erin", dec!(300)));
+    let trade = engine.process_at(
+        0,
+        EventType::TradeFill {
+            account_id: "erin".parse().unwrap(),
+            market_id: "ETH-PERP".parse().unwrap(),
+            quantity: dec!(1),
+            price: dec!(3000),
+            liquidity: None,
+        },
+    );
+    assert!(trade.is_accepted());
+    engine.process_at(1_000, mark(dec!(2830)));
+    let erin: AccountId = "erin".parse().unwrap();
+    let breached_at = engine.event_log.last().unwrap().caused_by.unwrap();
+    assert_eq!(
+        engine.state.accounts[&erin].margin_call,
+        Some(MarginCallOpen {
+            breached_at,
+            clock: 1_000
+        })
+    );
+    for t in (2_000..60_000).step_by(5_000) {
+        engine.process_at(t, deposit("frank", dec!(1)));
+    }
+    assert_eq!(engine.state.accounts[&erin].positions.len(), 1);
+
+    // A snapshot taken inside the grace restores with the call still open.
+    let snap = snapshot::capture(&engine.state, engine.event_log.last().unwrap().sequence);
+    let restored = snapshot::restore(&snap, markets()).unwrap();
+    assert_eq!(
+        restored.accounts[&erin].margin_call,
+        engine.state.accounts[&erin].margin_call
+    );
+
+    let expiry = engine.process_at(61_000, deposit("frank", dec!(1)));
+    assert!(expiry.is_accepted());
+    assert!(engine.state.accounts[&erin].positions.is_empty());
+    assert_eq!(engine.state.accounts[&erin].margin_call, None);
+    let replayed =
+        Engine::replay_verified(&engine.event_log, markets(), engine.config().clone()).unwrap();
+    assert_eq!(replayed.state, engine.state);
+
+    // A grace of zero, or a hard fraction over 1, is refused.
+    let config = |grace, hard_fraction| EngineConfig {
+        margin_call: Some(MarginCallPolicy {
+            grace,
+            hard_fraction,
+        }),
+        ..EngineConfig::default()
+    };
+    assert!(matches!(
+        config(GracePeriod::Events(0), dec!(0.5)).validate(),
+        Err(EngineConfigError::Zero {
+            field: "margin_call.grace"
+        })
+    ));
+    assert!(matches!(
+        config(GracePeriod::Events(4), dec!(1.5)).validate(),
+        Err(EngineConfigError::HardFraction(_))
+    ));
+    assert!(config(GracePeriod::Millis(1), dec!(0)).validate().is_ok());
+
+    println!(
+        "bob liquidated 4 sequences after his breach at {breach}; erin held for {} events, liquidated at 61,000 ms",
+        (2_000..60_000).step_by(5_000).count()
+    );
+}
@@ -0,0 +1,164 @@
+// Margin floors on a portfolio of dust. Fifty markets trade at a mark of 1, and alice
+// buys 3 contracts in each: 0.15 of IM and 0.09 of MM apiece. Without floors she opens
+// all fifty on 30 of collateral. With a floor of 1 of IM and 0.6 of MM per position she
+// opens thirty, and every snapshot shows what the floors add. A drop to 0.85 leaves
+// the unfloored account healthy, and takes the floored one under its floors, so the
+// liquidation closes positions until the floors of the rest are covered. Both logs
+// replay to the live state.
+
+use cross_margin_engine::prelude::*;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::BTreeMap;
+
+const MARKETS: usize = 50;
+
+fn market_id(i: usize) -> MarketId {
+    format!("M{i:02}-PERP").parse().unwrap()
+}
+
+fn markets(floors: Option<(Decimal, Decimal)>) -> Vec<Market> {
+    (0..MARKETS)
+        .map(|i| {
+            let mut market = Market::new(market_id(i), dec!(0.05), dec!(0.03));
+            if let Some((initial, maintenance)) = floors {
+                market.min_initial_margin = Some(initial);
+                market.min_maintenance_margin = Some(maintenance);
+            }
+            market
+        })
+        .collect()
+}
+
+fn marks(price: Decimal) -> EventType {
+    EventType::MarkPriceBatch {
+        updates: (0..MARKETS)
+            .map(|i| (market_id(i), price))
+            .collect::<BTreeMap<_, _>>(),
+    }
+}
+
+/// An engine over `markets` where alice has tried to buy 3 in every market; returns it
+/// with the number of fills accepted.
+fn dust_portfolio(markets: Vec<Market>) -> (Engine, usize) {
+    let mut engine = Engine::new();
+    for market in markets {
+        engine.add_market(market).unwrap();
+    }
+    engine.process(marks(dec!(1)));
+    engine.process(EventType::Deposit {
+        account_id: "alice".parse().unwrap(),
+        amount: dec!(30),
+    });
+    let accepted = (0..MARKETS)
+        .filter(|i| {
+            engine
+                .process(EventType::TradeFill {
+                    account_id: "alice".parse().unwrap(),
+                    market_id: market_id(*i),
+                    quantity: dec!(3),
+                    price: dec!(1),
+                    liquidity: None,
+                })
+                .is_accepted()
+        })
+        .count();
+    (engine, accepted)
+}
+
+fn main() {
+    let floors = Some((dec!(1), dec!(0.6)));
+    let (mut plain, plain_fills) = dust_portfolio(markets(None));
+    let (mut floored, floored_fills) = dust_portfolio(markets(floors));
+
+    // Fifty positions need 7.5 of IM without floors. With them each needs 1, and the
+    // thirty-first would take IM past the 30 of equity.
+    assert_eq!((plain_fills, floored_fills), (50, 30));
+    let alice = &plain.snapshots.last().unwrap().accounts["alice"];
+    assert_eq!(
+        (
+            alice.initial_margin_required,
+            alice.maintenance_margin_required
+        ),
+        (dec!(7.5), dec!(4.5))
+    );
+    assert_eq!(
+        (alice.initial_margin_floor, alice.maintenance_margin_floor),
+        (dec!(0), dec!(0))
+    );
+    let alice = &floored.snapshots.last().unwrap().accounts["alice"];
+    assert_eq!(
+        (
+            alice.initial_margin_required,
+            alice.maintenance_margin_required
+        ),
+        (dec!(30), dec!(18))
+    );
+    assert_eq!(
+        (alice.initial_margin_floor, alice.maintenance_margin_floor),
+        (dec!(25.5), dec!(15.3))
+    );
+    let rejected = floored.event_log.last().unwrap();
+    assert!(
+        matches!(&rejected.event_type, EventType::TradeRejected { reason, .. } if reason == "Insufficient margin: equity 30 < IM required 31"),
+        "{rejected:?}"
+    );
+
+    // At 0.85 the fifty plain positions lose 22.5 and need 3.825 of MM against 7.5 of
+    // equity. The thirty floored ones lose 13.5, leaving 16.5 of equity under 18 of
+    // floors. Closes at mark do not move equity, and each releases 0.6: three go, in
+    // market order since their notionals tie, and 27 floors of 16.2 are covered.
+    plain.process(marks(dec!(0.85)));
+    floored.process(marks(dec!(0.85)));
+    let alice = &plain.snapshots.last().unwrap().accounts["alice"];
+    assert!(!alice.liquidatable && alice.positions.len() == 50);
+    assert_eq!(
+        (alice.equity, alice.maintenance_margin_required),
+        (dec!(7.5), dec!(3.825))
+    );
+
+    let closed: Vec<MarketId> = floored
+        .event_log
+        .iter()
+        .filter_map(|e| match &e.event_type {
+            EventType::LiquidationFill { market_id, .. } => Some(market_id.clone()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(closed, [market_id(0), market_id(1), market_id(2)]);
+    let alice = &floored.snapshots.last().unwrap().accounts["alice"];
+    assert!(!alice.liquidatable && alice.positions.len() == 27);
+    assert_eq!(
+        (alice.equity, alice.maintenance_margin_required),
+        (dec!(16.5), dec!(16.2))
+    );
+    assert_eq!(alice.maintenance_margin_floor, dec!(14.1345));
+    assert_eq!(alice.bankruptcy_deficit, Decimal::ZERO);
+
+    for (engine, floors) in [(&plain, None), (&floored, floors)] {
+        let replayed =
+            Engine::replay_verified(&engine.event_log, markets(floors), EngineConfig::default())
+                .unwrap();
+        assert_eq!(replayed.state, engine.state);
+        assert_eq!(engine.solvency().residual, Decimal::ZERO);
+    }
+
+    // A maintenance floor above the initial one would charge less to open than to keep.
+    let mut market = Market::new("BAD-PERP".parse().unwrap(), dec!(0.05), dec!(0.03));
+    market.min_maintenance_margin = Some(dec!(2));
+    market.min_initial_margin = Some(dec!(1));
+    let error = floored.add_market(market).unwrap_err();
+    assert!(
+        matches!(
+            error,
+            MarketError::Invalid(MarketConfigError::MarginFloors { .. })
+        ),
+        "{error}"
+    );
+
+    println!(
+        "{plain_fills} dust fills without floors, {floored_fills} with; \
+         the drop liquidated {} floored positions and none of the plain ones",
+        closed.len()
+    );
+}
@@ -0,0 +1,267 @@
+// Moving positions between accounts. Scenario 49 transfers alice's BTC to bob and
+// carol at agreed prices. Replay, state files and snapshots reproduce the transfers,
+// the books balance, each side's statement reconciles with a `PositionTransfer` line,
+// and every accepted transfer leaves the two accounts' combined equity where it was.
+// A second engine fuzzes transfers in a discrete-funding market around an entry that
+// does not terminate: accepted or rejected, nothing is created or lost.
+
+use cross_margin_engine::margin;
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::report::{self, LedgerKind};
+use cross_margin_engine::rng::Lcg;
+use cross_margin_engine::scenario;
+use cross_margin_engine::snapshot;
+use cross_margin_engine::state;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+fn id(text: &str) -> AccountId {
+    text.parse().unwrap()
+}
+
+/// Collateral, unrealized PnL and pending funding: everything a transfer may move
+/// between two accounts, whether or not equity counts the pending part yet.
+fn value(account: &Account, state: &State) -> Decimal {
+    account.collateral()
+        + margin::total_unrealized_pnl(account, state)
+        + account.pending_funding.values().sum::<Decimal>()
+}
+
+fn main() {
+    let scenario = scenario::load("scenarios/49_position_transfer.toml").unwrap();
+    let markets: Vec<Market> = scenario.markets.iter().map(|m| m.to_market()).collect();
+    let engine = scenario::run(&scenario).unwrap().engine;
+
+    let replayed =
+        Engine::replay_verified(&engine.event_log, markets.clone(), scenario.config.clone())
+            .unwrap();
+    assert_eq!(replayed.state, engine.state);
+    assert_eq!(
+        State::from_json(&engine.state.to_json()).unwrap(),
+        engine.state
+    );
+    for saved in &engine.snapshots {
+        snapshot::restore(saved, markets.clone()).unwrap();
+    }
+    assert!(engine.solvency().is_balanced());
+    for (pool_id, report) in state::solvency_by_pool(&engine.state, engine.metrics()) {
+        assert!(report.is_balanced(), "{pool_id}: {report:?}");
+    }
+
+    // Each accepted transfer keeps the pair's equity: what one side realizes at the
+    // transfer price, the other carries as unrealized PnL against the mark.
+    let at = |sequence: u64| {
+        engine
+            .snapshots
+            .iter()
+            .find(|s| s.after_sequence == sequence)
+            .unwrap()
+    };
+    let transfers: Vec<&Event> = engine
+        .event_log
+        .windows(2)
+        .filter(|pair| matches!(pair[0].event_type, EventType::PositionTransfer { .. }))
+        .filter(|pair| !pair[1].event_type.is_rejection())
+        .map(|pair| &*pair[0])
+        .collect();
+    assert_eq!(transfers.len(), 3);
+    for event in &transfers {
+        let EventType::PositionTransfer { from, to, .. } = &event.event_type else {
+            unreachable!()
+        };
+        let pair = |sequence: u64| -> Decimal {
+            let snapshot = at(sequence);
+            [from, to]
+                .iter()
+                .map(|a| snapshot.accounts[a.as_str()].equity)
+                .sum()
+        };
+        assert_eq!(
+            pair(event.sequence - 1),
+            pair(event.sequence),
+            "seq {}",
+            event.sequence
+        );
+    }
+
+    // alice's line at the first transfer: -300 of funding settled, +500 realized.
+    let first = transfers[0];
+    let alice = report::statement(&engine.event_log, "alice", markets.clone());
+    let line = alice.iter().find(|l| l.sequence == first.sequence).unwrap();
+    assert_eq!(
+        (
+            line.kind,
+            line.market_id.as_ref().map(|m| m.as_str()),
+            line.amount,
+            line.principal_amount
+        ),
+        (
+            LedgerKind::PositionTransfer,
+            Some("BTC-PERP"),
+            dec!(200),
+            Decimal::ZERO
+        )
+    );
+    // carol's at the flip: +100 of funding, -1,000 closing her short at 51,000.
+    let carol = report::statement(&engine.event_log, "carol", markets.clone());
+    let line = carol
+        .iter()
+        .find(|l| l.sequence == transfers[1].sequence)
+        .unwrap();
+    assert_eq!(
+        (line.kind, line.amount),
+        (LedgerKind::PositionTransfer, dec!(-900))
+    );
+    for account_id in ["alice", "bob", "carol", "dave"] {
+        let lines = report::statement(&engine.event_log, account_id, markets.clone());
+        assert!(report::reconcile(&lines).reconciled, "{account_id}");
+    }
+
+    // Over the whole log every accrual has settled, so attribution reconciles.
+    let end = engine.event_log.last().unwrap().sequence;
+    for account_id in ["alice", "bob", "carol", "dave", "erin"] {
+        let attribution = report::attribution(
+            &engine.event_log,
+            &engine.snapshots,
+            &id(account_id),
+            0,
+            end,
+        );
+        assert!(attribution.reconciled, "{account_id}: {attribution:?}");
+    }
+
+    // A second engine: gina holds 3 BTC at a cost of 100,000, with discrete funding
+    // accrued but unpaid, and trades pieces of it back and forth with hal and ivy at
+    // random prices around the mark.
+    let btc: MarketId = "BTC-PERP".parse().unwrap();
+    let market = Market::new(btc.clone(), dec!(0.10), dec!(0.05));
+    let mut engine = Engine::new();
+    engine.add_market(market.clone()).unwrap();
+    let setup = [
+        EventType::MarkPriceUpdate {
+            market_id: btc.clone(),
+            price: dec!(33000),
+        },
+        EventType::Deposit {
+            account_id: id("gina"),
+            amount: dec!(40000),
+        },
+        EventType::Deposit {
+            account_id: id("hal"),
+            amount: dec!(20000),
+        },
+        EventType::Deposit {
+            account_id: id("ivy"),
+            amount: dec!(5000),
+        },
+        EventType::TradeFill {
+            account_id: id("gina"),
+            market_id: btc.clone(),
+            quantity: dec!(1),
+            price: dec!(30000),
+            liquidity: None,
+        },
+        EventType::TradeFill {
+            account_id: id("gina"),
+            market_id: btc.clone(),
+            quantity: dec!(2),
+            price: dec!(35000),
+            liquidity: None,
+        },
+        EventType::TradeFill {
+            account_id: id("hal"),
+            market_id: btc.clone(),
+            quantity: dec!(-1),
+            price: dec!(33000),
+            liquidity: None,
+        },
+        EventType::FundingAccrual {
+            market_id: btc.clone(),
+            accrued_index: dec!(7),
+        },
+    ];
+    for event_type in setup {
+        assert!(
+            engine.process(event_type.clone()).is_accepted(),
+            "{event_type:?}"
+        );
+    }
+    assert_ne!(
+        engine.state.accounts["gina"].pending_funding[&btc],
+        Decimal::ZERO
+    );
+
+    let names = ["gina", "hal", "ivy"];
+    let mut rng = Lcg::new(49);
+    let (mut accepted, mut rejected) = (0, 0);
+    for round in 0..400 {
+        if round % 50 == 49 {
+            let price = dec!(31000) + Decimal::from(rng.below(4000));
+            assert!(engine
+                .process(EventType::MarkPriceUpdate {
+                    market_id: btc.clone(),
+                    price
+                })
+                .is_accepted());
+        }
+        let from = names[rng.below(3) as usize];
+        let to = names[rng.below(3) as usize];
+        let held = engine.state.accounts[from]
+            .positions
+            .get(&btc)
+            .map_or(Decimal::ZERO, |p| p.quantity);
+        let quantity = if held.is_zero() || rng.below(10) == 0 {
+            Decimal::new(rng.below(200) as i64 - 100, 2)
+        } else {
+            (held * Decimal::new(rng.below(100) as i64 + 1, 2)).round_dp(4)
+        };
+        let transfer_price = engine.state.markets[&btc].mark_price
+            + Decimal::new(rng.below(400_000) as i64 - 200_000, 2);
+
+        let before = engine.state.clone();
+        let total = |state: &State| -> Decimal {
+            names
+                .iter()
+                .map(|n| value(&state.accounts[*n], state))
+                .sum()
+        };
+        let outcome = engine.process(EventType::PositionTransfer {
+            from: id(from),
+            to: id(to),
+            market_id: btc.clone(),
+            quantity,
+            transfer_price,
+        });
+        if outcome.is_accepted() {
+            accepted += 1;
+            assert_eq!(
+                total(&engine.state),
+                total(&before),
+                "round {round}: {from} -> {to} {quantity} @ {transfer_price}"
+            );
+            assert_eq!(engine.state.accounts[from].pending_funding.get(&btc), None);
+            let net: Decimal = names
+                .iter()
+                .filter_map(|n| engine.state.accounts[*n].positions.get(&btc))
+                .map(|p| p.quantity)
+                .sum();
+            assert_eq!(net, dec!(2));
+        } else {
+            rejected += 1;
+            assert_eq!(engine.state.accounts, before.accounts, "round {round}");
+        }
+        assert!(engine.solvency().is_balanced(), "round {round}");
+    }
+    assert!(
+        accepted > 50 && rejected > 50,
+        "{accepted} accepted, {rejected} rejected"
+    );
+    let replayed =
+        Engine::replay_verified(&engine.event_log, vec![market], EngineConfig::default()).unwrap();
+    assert_eq!(replayed.state, engine.state);
+
+    println!(
+        "transferred {} times in scenario 49 with pair equity kept; fuzzed {accepted} accepted and {rejected} rejected transfers with nothing lost",
+        transfers.len()
+    );
+}
@@ -0,0 +1,50 @@
+// Preview a trade without touching the live engine: run it through a dry-run copy
+// and inspect the outcome and the resulting margin.
+
+use cross_margin_engine::margin;
+use cross_margin_engine::prelude::*;
+use rust_decimal_macros::dec;
+
+fn main() {
+    let mut live = Engine::new();
+    live.add_market(Market::new(
+        "ETH-PERP".parse().unwrap(),
+        dec!(0.10),
+        dec!(0.05),
+    ))
+    .unwrap();
+    live.process(EventType::MarkPriceUpdate {
+        market_id: "ETH-PERP".parse().unwrap(),
+        price: dec!(3000),
+    });
+    live.process(EventType::Deposit {
+        account_id: "bob".parse().unwrap(),
+        amount: dec!(5000),
+    });
+
+    for quantity in [dec!(10), dec!(20)] {
+        let mut preview =
+            Engine::from_state(live.state.clone(), live.next_sequence(), EngineMode::DryRun);
+        let outcome = preview.process(EventType::TradeFill {
+            account_id: "bob".parse().unwrap(),
+            market_id: "ETH-PERP".parse().unwrap(),
+            quantity,
+            price: dec!(3000),
+            liquidity: None,
+        });
+
+        let account = &preview.state.accounts["bob"];
+        let im = margin::initial_margin_required(account, &preview.state);
+        match outcome {
+            ProcessOutcome::Accepted { .. } => println!("buy {quantity}: ok, IM would be {im}"),
+            ProcessOutcome::Rejected { reason, .. } => println!("buy {quantity}: {reason}"),
+            ProcessOutcome::Duplicate { .. } => unreachable!("no idempotency key"),
+            ProcessOutcome::Suppressed { .. } => unreachable!("no rejection throttle"),
+            ProcessOutcome::SinkFailed { .. } => unreachable!("no snapshot sink"),
+            ProcessOutcome::LogStoreFailed { .. } => unreachable!("no log store"),
+        }
+    }
+
+    // The live engine is untouched.
+    assert!(live.state.accounts["bob"].positions.is_empty());
+}
@@ -0,0 +1,226 @@
+// Which BTC holders a move of the BTC mark brings closest to liquidation. Alice and
+// dave are long 1 BTC with 10,000 of room to fall, bob is short 2 with 1,000 to rise,
+// and carol is long 2 BTC against a short in ETH, with 5,000. `risk::sensitivity`
+// lists them bob, carol, alice, dave, breaking alice and dave's tie by id. Each
+// sensitivity is checked against a copy of the state whose mark is actually moved:
+// small moves change the headroom by exactly the sensitivity, and each break-even
+// price is exactly where the account becomes liquidatable. Frank's SOL position sits
+// on its maintenance floor, so only its PnL moves with the mark.
+
+use cross_margin_engine::margin;
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::risk::{self, AccountSensitivity};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+fn mark(market_id: &str, price: Decimal) -> EventType {
+    EventType::MarkPriceUpdate {
+        market_id: market_id.parse().unwrap(),
+        price,
+    }
+}
+
+fn open(
+    engine: &mut Engine,
+    account_id: &str,
+    deposit: Decimal,
+    fills: &[(&str, Decimal, Decimal)],
+) {
+    engine.process(EventType::Deposit {
+        account_id: account_id.parse().unwrap(),
+        amount: deposit,
+    });
+    for &(market_id, quantity, price) in fills {
+        let fill = engine.process(EventType::TradeFill {
+            account_id: account_id.parse().unwrap(),
+            market_id: market_id.parse().unwrap(),
+            quantity,
+            price,
+            liquidity: None,
+        });
+        assert!(fill.is_accepted(), "{account_id} {market_id}");
+    }
+}
+
+/// `state` with `market_id` marked at `price`, as a shock: no event is processed.
+fn shocked(state: &State, market_id: &MarketId, price: Decimal) -> State {
+    let mut shocked = state.clone();
+    shocked.markets.get_mut(market_id).unwrap().mark_price = price;
+    shocked
+}
+
+fn headroom(state: &State, account_id: &AccountId) -> Decimal {
+    let account = &state.accounts[account_id];
+    margin::equity(account, state) - margin::maintenance_margin_required(account, state)
+}
+
+/// Move the mark and compare with the linearized figures of `row`.
+fn cross_check(state: &State, market_id: &MarketId, row: &AccountSensitivity) {
+    let current = state.markets[market_id].mark_price;
+    for step in [dec!(-1), dec!(-0.01), dec!(0.01), dec!(1)] {
+        let moved = shocked(state, market_id, current + step);
+        assert_eq!(
+            headroom(&moved, &row.account_id),
+            row.headroom + row.sensitivity * step
+        );
+    }
+    let break_even = row.break_even_price.unwrap();
+    let account = &state.accounts[&row.account_id];
+    assert!(margin::is_liquidatable(
+        account,
+        &shocked(state, market_id, break_even)
+    ));
+    let cent = if row.sensitivity.is_sign_positive() {
+        dec!(0.01)
+    } else {
+        dec!(-0.01)
+    };
+    let one_cent_better = break_even + cent;
+    assert!(!margin::is_liquidatable(
+        account,
+        &shocked(state, market_id, one_cent_better)
+    ));
+    assert_eq!((break_even - current).abs(), row.adverse_move.unwrap());
+}
+
+fn main() {
+    let mut sol = Market::new("SOL-PERP".parse().unwrap(), dec!(0.05), dec!(0.03));
+    sol.min_initial_margin = Some(dec!(600));
+    sol.min_maintenance_margin = Some(dec!(500));
+    let mut engine = Engine::new();
+    engine
+        .add_market(Market::new(
+            "BTC-PERP".parse().unwrap(),
+            dec!(0.05),
+            dec!(0.03),
+        ))
+        .unwrap();
+    engine
+        .add_market(Market::new(
+            "ETH-PERP".parse().unwrap(),
+            dec!(0.10),
+            dec!(0.05),
+        ))
+        .unwrap();
+    engine.add_market(sol).unwrap();
+    engine.process(mark("BTC-PERP", dec!(50000)));
+    engine.process(mark("ETH-PERP", dec!(3000)));
+    engine.process(mark("SOL-PERP", dec!(100)));
+
+    // Headroom over sensitivity: 9,700 / 0.97, 2,060 / 2.06 and 9,700 / 1.94.
+    open(
+        &mut engine,
+        "alice",
+        dec!(11200),
+        &[("BTC-PERP", dec!(1), dec!(50000))],
+    );
+    open(
+        &mut engine,
+        "dave",
+        dec!(11200),
+        &[("BTC-PERP", dec!(1), dec!(50000))],
+    );
+    open(
+        &mut engine,
+        "bob",
+        dec!(5060),
+        &[("BTC-PERP", dec!(-2), dec!(50000))],
+    );
+    open(
+        &mut engine,
+        "carol",
+        dec!(14200),
+        &[
+            ("BTC-PERP", dec!(2), dec!(50000)),
+            ("ETH-PERP", dec!(-10), dec!(3000)),
+        ],
+    );
+    open(
+        &mut engine,
+        "erin",
+        dec!(5000),
+        &[("ETH-PERP", dec!(5), dec!(3000))],
+    );
+    open(
+        &mut engine,
+        "frank",
+        dec!(800),
+        &[("SOL-PERP", dec!(10), dec!(100))],
+    );
+
+    let before = engine.state.clone();
+    let btc: MarketId = "BTC-PERP".parse().unwrap();
+    let rows = risk::sensitivity(&engine.state, &btc);
+    assert_eq!(engine.state, before);
+
+    let summary: Vec<_> = rows
+        .iter()
+        .map(|r| {
+            (
+                r.account_id.as_str(),
+                r.sensitivity,
+                r.headroom,
+                r.adverse_move,
+                r.break_even_price,
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (
+                "bob",
+                dec!(-2.06),
+                dec!(2060),
+                Some(dec!(1000)),
+                Some(dec!(51000))
+            ),
+            (
+                "carol",
+                dec!(1.94),
+                dec!(9700),
+                Some(dec!(5000)),
+                Some(dec!(45000))
+            ),
+            (
+                "alice",
+                dec!(0.97),
+                dec!(9700),
+                Some(dec!(10000)),
+                Some(dec!(40000))
+            ),
+            (
+                "dave",
+                dec!(0.97),
+                dec!(9700),
+                Some(dec!(10000)),
+                Some(dec!(40000))
+            ),
+        ]
+    );
+    for row in &rows {
+        cross_check(&engine.state, &btc, row);
+    }
+
+    // On its floor, frank's maintenance margin does not move: the sensitivity is the
+    // quantity itself, and the floor still holds at the break-even price.
+    let sol_id: MarketId = "SOL-PERP".parse().unwrap();
+    let frank = risk::sensitivity(&engine.state, &sol_id);
+    assert_eq!(frank.len(), 1);
+    assert_eq!(
+        (frank[0].sensitivity, frank[0].headroom),
+        (dec!(10), dec!(300))
+    );
+    assert_eq!(frank[0].break_even_price, Some(dec!(70)));
+    cross_check(&engine.state, &sol_id, &frank[0]);
+
+    assert!(risk::sensitivity(&engine.state, &"DOGE-PERP".parse().unwrap()).is_empty());
+
+    println!(
+        "BTC holders by adverse move: {}; sensitivities match shocked marks",
+        rows.iter()
+            .map(|r| format!("{} {}", r.account_id, r.adverse_move.unwrap()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+}
@@ -0,0 +1,134 @@
+// A client loops on an order its account cannot margin: 1,000 identical submissions.
+// Under a rejection throttle, the first five rejections are logged and the rest are
+// suppressed, summarized by a `RejectionSuppressed` every 100 and once more when the
+// account's deposit ends the burst. Check that the log stays small, that state ends up
+// as it would without the throttle, and that the log replays to the same state.
+
+use cross_margin_engine::prelude::*;
+use rust_decimal_macros::dec;
+use std::sync::Arc;
+
+const RETRIES: u64 = 1_000;
+
+fn run(throttle: Option<RejectionThrottle>) -> (Engine, Vec<ProcessOutcome>) {
+    let mut engine = Engine::with_config(EngineConfig {
+        rejection_throttle: throttle,
+        ..EngineConfig::default()
+    });
+    engine
+        .add_market(Market::new(
+            "BTC-PERP".parse().unwrap(),
+            dec!(0.10),
+            dec!(0.05),
+        ))
+        .unwrap();
+    let deposit = |account_id: &str, amount| EventType::Deposit {
+        account_id: account_id.parse().unwrap(),
+        amount,
+    };
+    let trade = |account_id: &str| EventType::TradeFill {
+        account_id: account_id.parse().unwrap(),
+        market_id: "BTC-PERP".parse().unwrap(),
+        quantity: dec!(1),
+        price: dec!(50000),
+        liquidity: None,
+    };
+
+    engine.process(EventType::MarkPriceUpdate {
+        market_id: "BTC-PERP".parse().unwrap(),
+        price: dec!(50000),
+    });
+    engine.process(deposit("alice", dec!(1000)));
+    engine.process(deposit("bob", dec!(10000)));
+    let mut outcomes = Vec::new();
+    for i in 0..RETRIES {
+        outcomes.push(engine.process(trade("alice")));
+        // Bob trading in between does not end alice's burst.
+        if i == 500 {
+            assert!(engine.process(trade("bob")).is_accepted());
+        }
+    }
+    engine.process(deposit("alice", dec!(5000)));
+    assert!(engine.process(trade("alice")).is_accepted());
+    (engine, outcomes)
+}
+
+fn main() {
+    let throttle = RejectionThrottle {
+        window: 20,
+        max_rejections: 5,
+        summary_every: 100,
+    };
+    let (engine, outcomes) = run(Some(throttle));
+    let (unthrottled, _) = run(None);
+
+    let logged = outcomes
+        .iter()
+        .filter(|o| matches!(o, ProcessOutcome::Rejected { .. }))
+        .count();
+    let suppressed = outcomes
+        .iter()
+        .filter(|o| matches!(o, ProcessOutcome::Suppressed { .. }))
+        .count();
+    assert_eq!((logged, suppressed), (5, 995));
+    let ProcessOutcome::Suppressed { reason } = &outcomes[5] else {
+        panic!("{:?}", outcomes[5])
+    };
+    assert!(matches!(reason, RejectReason::Trade(_)), "{reason}");
+
+    // Nine full summaries and the 95 the deposit ended, which it follows directly.
+    let summaries: Vec<(usize, u64)> = (engine.event_log.iter().enumerate())
+        .filter_map(|(i, e)| match &e.event_type {
+            EventType::RejectionSuppressed {
+                account_id, count, ..
+            } if account_id == "alice" => Some((i, *count)),
+            _ => None,
+        })
+        .collect();
+    let counts: Vec<u64> = summaries.iter().map(|(_, count)| *count).collect();
+    assert_eq!(counts, [vec![100; 9], vec![95]].concat());
+    let (last, _) = summaries[summaries.len() - 1];
+    assert!(matches!(
+        engine.event_log[last + 1].event_type,
+        EventType::Deposit { .. }
+    ));
+    assert_eq!(engine.metrics().rejections_suppressed, 995);
+
+    println!(
+        "{RETRIES} retries: {} events logged with the throttle, {} without",
+        engine.event_log.len(),
+        unthrottled.event_log.len()
+    );
+    assert!(engine.event_log.len() < 40);
+    assert!(unthrottled.event_log.len() > 2 * RETRIES as usize);
+
+    // Suppressed rejections changed nothing.
+    assert_eq!(engine.state.accounts, unthrottled.state.accounts);
+    assert_eq!(engine.state.markets, unthrottled.state.markets);
+    assert!(engine.solvency().is_balanced());
+
+    // Replay reaches the same throttle decisions from the log alone.
+    let markets = vec![Market::new(
+        "BTC-PERP".parse().unwrap(),
+        dec!(0.10),
+        dec!(0.05),
+    )];
+    let replayed =
+        Engine::replay_verified(&engine.event_log, markets.clone(), engine.config().clone())
+            .unwrap();
+    assert_eq!(replayed.state, engine.state);
+    assert_eq!(replayed.metrics.rejections_suppressed, 995);
+
+    // A summary the history does not support fails verification.
+    let mut forged = engine.event_log.clone();
+    if let EventType::RejectionSuppressed { account_id, .. } =
+        &mut Arc::make_mut(&mut forged[last]).event_type
+    {
+        *account_id = "bob".parse().unwrap();
+    }
+    let result = Engine::replay_verified(&forged, markets, engine.config().clone());
+    assert!(
+        matches!(result, Err(EngineError::InvalidDerivedEvent { .. })),
+        "{result:?}"
+    );
+}
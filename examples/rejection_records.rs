@@ -0,0 +1,525 @@
+// Build one event of every `EventType` variant and check its rejection record: that
+// `events::rejection_for` gives a `*Rejected` record that is informational, names the
+// same accounts and gives back its reason, and that whenever a fresh engine rejects
+// the event, the record it logs is that one. `variant` has no wildcard, so a new
+// event type breaks the build of this example until it has a sample here.
+
+use cross_margin_engine::events;
+use cross_margin_engine::prelude::*;
+use rust_decimal_macros::dec;
+use std::collections::BTreeMap;
+
+/// The position of `event_type`'s variant in `samples`.
+fn variant(event_type: &EventType) -> usize {
+    match event_type {
+        EventType::ConfigMarker { .. } => 0,
+        EventType::ConfigUpdated { .. } => 1,
+        EventType::Deposit { .. } => 2,
+        EventType::Withdraw { .. } => 3,
+        EventType::TradeFill { .. } => 4,
+        EventType::MarkPriceUpdate { .. } => 5,
+        EventType::MarkPriceBatch { .. } => 6,
+        EventType::FundingUpdate { .. } => 7,
+        EventType::FundingRate { .. } => 8,
+        EventType::FundingAccrual { .. } => 9,
+        EventType::FundingPayment { .. } => 10,
+        EventType::MarkPriceBatchSkipped { .. } => 11,
+        EventType::UnknownMarketIgnored { .. } => 12,
+        EventType::SetAccountLimits { .. } => 13,
+        EventType::OrderPlaced { .. } => 14,
+        EventType::OrderCancelled { .. } => 15,
+        EventType::AccountMetadata { .. } => 16,
+        EventType::AssignPool { .. } => 17,
+        EventType::GroupCreated { .. } => 18,
+        EventType::GroupMembershipSet { .. } => 19,
+        EventType::SetPositionLeverage { .. } => 20,
+        EventType::BackstopRegistered { .. } => 21,
+        EventType::AccountsMerged { .. } => 22,
+        EventType::PositionTransfer { .. } => 23,
+        EventType::InsuranceFundDeposit { .. } => 24,
+        EventType::StateImport { .. } => 25,
+        EventType::StateImportBelowMaintenance { .. } => 26,
+        EventType::MarketAdded { .. } => 27,
+        EventType::MarketRemoved { .. } => 28,
+        EventType::SessionOpen { .. } => 29,
+        EventType::SessionClose { .. } => 30,
+        EventType::HedgePairAdded { .. } => 31,
+        EventType::Expiry { .. } => 32,
+        EventType::ExpirySettlement { .. } => 33,
+        EventType::InterestTick { .. } => 34,
+        EventType::InterestCharged { .. } => 35,
+        EventType::YieldDistribution { .. } => 36,
+        EventType::YieldPaid { .. } => 37,
+        EventType::YieldResidual { .. } => 38,
+        EventType::AccountReinstated { .. } => 39,
+        EventType::ForceClose { .. } => 40,
+        EventType::ForceCloseFill { .. } => 41,
+        EventType::LiquidationFill { .. } => 42,
+        EventType::OrdersAutoCancelled { .. } => 43,
+        EventType::LiquidationDeferred { .. } => 44,
+        EventType::InsuranceFundPayout { .. } => 45,
+        EventType::LossSocialized { .. } => 46,
+        EventType::RiskAlert { .. } => 47,
+        EventType::RiskAlertCleared { .. } => 48,
+        EventType::MarginCall { .. } => 49,
+        EventType::MarginCallCleared { .. } => 50,
+        EventType::SkewLimitBreached { .. } => 51,
+        EventType::SkewLimitCleared { .. } => 52,
+        EventType::LiquidationTakeover { .. } => 53,
+        EventType::TradeRejected { .. } => 54,
+        EventType::WithdrawalRejected { .. } => 55,
+        EventType::MarkPriceRejected { .. } => 56,
+        EventType::MarkPriceBatchRejected { .. } => 57,
+        EventType::LiquidationTakeoverRejected { .. } => 58,
+        EventType::FundingRateRejected { .. } => 59,
+        EventType::FundingUpdateRejected { .. } => 60,
+        EventType::DuplicateIgnored { .. } => 61,
+        EventType::RejectionSuppressed { .. } => 62,
+        EventType::BatchStarted { .. } => 63,
+        EventType::BatchEnded { .. } => 64,
+        EventType::AccountMetadataRejected { .. } => 65,
+        EventType::AccountReinstatementRejected { .. } => 66,
+        EventType::AssignPoolRejected { .. } => 67,
+        EventType::StateImportRejected { .. } => 68,
+        EventType::HedgePairRejected { .. } => 69,
+        EventType::ExpiryRejected { .. } => 70,
+        EventType::InterestTickRejected { .. } => 71,
+        EventType::YieldDistributionRejected { .. } => 72,
+        EventType::GroupCreatedRejected { .. } => 73,
+        EventType::GroupMembershipRejected { .. } => 74,
+        EventType::PositionLeverageRejected { .. } => 75,
+        EventType::EventRejected { .. } => 76,
+        EventType::FeeCharged { .. } => 77,
+    }
+}
+
+/// One event per variant, in `variant` order.
+fn samples() -> Vec<EventType> {
+    let account_id = || -> AccountId { "alice".parse().unwrap() };
+    let market_id = || -> MarketId { "BTC-PERP".parse().unwrap() };
+    let reason = || "sample".to_string();
+    let updates = || BTreeMap::from([(market_id(), dec!(50000))]);
+    let positions = || {
+        vec![ImportedPosition {
+            market_id: market_id(),
+            quantity: dec!(1),
+            cost_basis: dec!(50000),
+            last_funding: dec!(0),
+        }]
+    };
+    vec![
+        EventType::ConfigMarker {
+            config_hash: "0".into(),
+            config: EngineConfig::default(),
+        },
+        EventType::ConfigUpdated {
+            config: EngineConfig {
+                withdrawal_buffer: dec!(0.5),
+                ..EngineConfig::default()
+            },
+        },
+        EventType::Deposit {
+            account_id: account_id(),
+            amount: dec!(100),
+        },
+        EventType::Withdraw {
+            account_id: account_id(),
+            amount: dec!(100),
+        },
+        EventType::TradeFill {
+            account_id: account_id(),
+            market_id: market_id(),
+            quantity: dec!(1),
+            price: dec!(50000),
+            liquidity: None,
+        },
+        EventType::MarkPriceUpdate {
+            market_id: "NOPE-PERP".parse().unwrap(),
+            price: dec!(1),
+        },
+        EventType::MarkPriceBatch { updates: updates() },
+        EventType::FundingUpdate {
+            market_id: market_id(),
+            new_cumulative_index: dec!(1),
+        },
+        EventType::FundingRate {
+            market_id: "NOPE-PERP".parse().unwrap(),
+            rate: dec!(0.0001),
+            interval_id: 1,
+        },
+        EventType::FundingAccrual {
+            market_id: "NOPE-PERP".parse().unwrap(),
+            accrued_index: dec!(1),
+        },
+        EventType::FundingPayment {
+            account_id: account_id(),
+            market_id: market_id(),
+            amount: dec!(1),
+        },
+        EventType::MarkPriceBatchSkipped {
+            market_ids: vec![market_id()],
+        },
+        EventType::UnknownMarketIgnored {
+            market_id: market_id(),
+            original_sequence: 1,
+        },
+        EventType::SetAccountLimits {
+            account_id: account_id(),
+            max_leverage: Some(dec!(5)),
+            max_total_notional: None,
+        },
+        EventType::OrderPlaced {
+            account_id: account_id(),
+            order_id: "o-1".into(),
+            market_id: market_id(),
+            quantity: dec!(1),
+        },
+        EventType::OrderCancelled {
+            account_id: account_id(),
+            order_id: "o-1".into(),
+        },
+        EventType::AccountMetadata {
+            account_id: account_id(),
+            key: "desk".into(),
+            value: "x".repeat(10_000),
+        },
+        EventType::AssignPool {
+            account_id: account_id(),
+            pool_id: "pool-a".into(),
+        },
+        EventType::GroupCreated {
+            group_id: "mm".into(),
+            max_group_notional: Some(dec!(-1)),
+            fee_override: None,
+        },
+        EventType::GroupMembershipSet {
+            account_id: account_id(),
+            group_id: Some("mm".into()),
+        },
+        EventType::SetPositionLeverage {
+            account_id: account_id(),
+            market_id: market_id(),
+            leverage: dec!(20),
+        },
+        EventType::BackstopRegistered {
+            account_id: account_id(),
+            market_id: market_id(),
+            max_notional: dec!(100000),
+        },
+        EventType::AccountsMerged {
+            from: account_id(),
+            to: account_id(),
+        },
+        EventType::PositionTransfer {
+            from: account_id(),
+            to: account_id(),
+            market_id: market_id(),
+            quantity: dec!(1),
+            transfer_price: dec!(50000),
+        },
+        EventType::InsuranceFundDeposit {
+            pool_id: "default".into(),
+            amount: dec!(100),
+        },
+        EventType::StateImport {
+            account_id: account_id(),
+            pool_id: "default".into(),
+            collateral: dec!(0),
+            positions: positions(),
+        },
+        EventType::StateImportBelowMaintenance {
+            account_id: account_id(),
+            equity: dec!(0),
+            maintenance_margin: dec!(1),
+        },
+        EventType::MarketAdded {
+            market: Box::new(Market::new(market_id(), dec!(0.05), dec!(0.03))),
+        },
+        EventType::MarketRemoved {
+            market_id: "NOPE-PERP".parse().unwrap(),
+        },
+        EventType::SessionOpen {
+            market_id: market_id(),
+        },
+        EventType::SessionClose {
+            market_id: market_id(),
+        },
+        EventType::HedgePairAdded {
+            market_a: market_id(),
+            market_b: market_id(),
+            offset_fraction: dec!(0.5),
+        },
+        EventType::Expiry {
+            market_id: market_id(),
+            settlement_price: dec!(50000),
+        },
+        EventType::ExpirySettlement {
+            account_id: account_id(),
+            market_id: market_id(),
+            quantity: dec!(1),
+            price: dec!(50000),
+            realized_pnl: dec!(0),
+        },
+        EventType::InterestTick { interval_id: 1 },
+        EventType::InterestCharged {
+            account_id: account_id(),
+            amount: dec!(1),
+        },
+        EventType::YieldDistribution {
+            rate: dec!(0.001),
+            interval_id: 1,
+        },
+        EventType::YieldPaid {
+            account_id: account_id(),
+            interval_id: 1,
+            amount: dec!(1),
+        },
+        EventType::YieldResidual {
+            pool_id: "default".into(),
+            interval_id: 1,
+            amount: dec!(0.000000001),
+        },
+        EventType::AccountReinstated {
+            account_id: account_id(),
+        },
+        EventType::ForceClose {
+            account_id: account_id(),
+            reason: reason(),
+        },
+        EventType::ForceCloseFill {
+            account_id: account_id(),
+            market_id: market_id(),
+            quantity: dec!(-1),
+            price: dec!(50000),
+            reason: reason(),
+        },
+        EventType::LiquidationFill {
+            account_id: account_id(),
+            market_id: market_id(),
+            quantity: dec!(-1),
+            price: dec!(50000),
+        },
+        EventType::OrdersAutoCancelled {
+            account_id: account_id(),
+            order_ids: vec!["o-1".into()],
+            reason: reason(),
+        },
+        EventType::LiquidationDeferred {
+            account_id: account_id(),
+            market_ids: vec![market_id()],
+        },
+        EventType::InsuranceFundPayout {
+            pool_id: "default".into(),
+            account_id: account_id(),
+            amount: dec!(1),
+        },
+        EventType::LossSocialized {
+            pool_id: "default".into(),
+            account_id: account_id(),
+            amount: dec!(1),
+            charges: BTreeMap::from([("bob".parse().unwrap(), dec!(1))]),
+        },
+        EventType::RiskAlert {
+            account_id: account_id(),
+            level: 1,
+            margin_usage: Some(dec!(0.8)),
+        },
+        EventType::RiskAlertCleared {
+            account_id: account_id(),
+            level: 0,
+            margin_usage: Some(dec!(0.5)),
+        },
+        EventType::MarginCall {
+            account_id: account_id(),
+            equity: dec!(400),
+            maintenance_margin: dec!(500),
+        },
+        EventType::MarginCallCleared {
+            account_id: account_id(),
+            equity: dec!(600),
+            maintenance_margin: dec!(500),
+        },
+        EventType::SkewLimitBreached {
+            market_id: market_id(),
+            net_notional: dec!(1000000),
+        },
+        EventType::SkewLimitCleared {
+            market_id: market_id(),
+            net_notional: dec!(500000),
+        },
+        EventType::LiquidationTakeover {
+            liquidated_account: account_id(),
+            keeper_account: "keeper".parse().unwrap(),
+            market_id: market_id(),
+            quantity: dec!(1),
+            price: dec!(50000),
+        },
+        EventType::TradeRejected {
+            account_id: account_id(),
+            market_id: market_id(),
+            quantity: dec!(1),
+            price: dec!(50000),
+            reason: reason(),
+        },
+        EventType::WithdrawalRejected {
+            account_id: account_id(),
+            amount: dec!(1),
+            reason: reason(),
+        },
+        EventType::MarkPriceRejected {
+            market_id: market_id(),
+            price: dec!(0),
+            reason: reason(),
+        },
+        EventType::MarkPriceBatchRejected {
+            updates: updates(),
+            reason: reason(),
+        },
+        EventType::LiquidationTakeoverRejected {
+            liquidated_account: account_id(),
+            keeper_account: "keeper".parse().unwrap(),
+            market_id: market_id(),
+            quantity: dec!(1),
+            price: dec!(50000),
+            reason: reason(),
+        },
+        EventType::FundingRateRejected {
+            market_id: market_id(),
+            rate: dec!(0.0001),
+            interval_id: 1,
+            reason: reason(),
+        },
+        EventType::FundingUpdateRejected {
+            market_id: market_id(),
+            new_cumulative_index: dec!(1),
+            reason: reason(),
+        },
+        EventType::DuplicateIgnored {
+            key: "k".into(),
+            original_sequence: 1,
+        },
+        EventType::RejectionSuppressed {
+            account_id: account_id(),
+            count: 2,
+            first_sequence: 1,
+            last_sequence: 2,
+        },
+        EventType::BatchStarted { submissions: 2 },
+        EventType::BatchEnded { submissions: 2 },
+        EventType::AccountMetadataRejected {
+            account_id: account_id(),
+            key: "desk".into(),
+            value: "a".into(),
+            reason: reason(),
+        },
+        EventType::AccountReinstatementRejected {
+            account_id: account_id(),
+            reason: reason(),
+        },
+        EventType::AssignPoolRejected {
+            account_id: account_id(),
+            pool_id: "pool-a".into(),
+            reason: reason(),
+        },
+        EventType::StateImportRejected {
+            account_id: account_id(),
+            pool_id: "default".into(),
+            collateral: dec!(0),
+            positions: positions(),
+            reason: reason(),
+        },
+        EventType::HedgePairRejected {
+            market_a: market_id(),
+            market_b: market_id(),
+            offset_fraction: dec!(0.5),
+            reason: reason(),
+        },
+        EventType::ExpiryRejected {
+            market_id: market_id(),
+            settlement_price: dec!(50000),
+            reason: reason(),
+        },
+        EventType::InterestTickRejected {
+            interval_id: 1,
+            reason: reason(),
+        },
+        EventType::YieldDistributionRejected {
+            rate: dec!(0.001),
+            interval_id: 1,
+            reason: reason(),
+        },
+        EventType::GroupCreatedRejected {
+            group_id: "mm".into(),
+            max_group_notional: None,
+            fee_override: None,
+            reason: reason(),
+        },
+        EventType::GroupMembershipRejected {
+            account_id: account_id(),
+            group_id: None,
+            reason: reason(),
+        },
+        EventType::PositionLeverageRejected {
+            account_id: account_id(),
+            market_id: market_id(),
+            leverage: dec!(5),
+            reason: reason(),
+        },
+        EventType::EventRejected {
+            event: Box::new(EventType::SessionOpen {
+                market_id: market_id(),
+            }),
+            reason: reason(),
+        },
+        EventType::FeeCharged {
+            account_id: account_id(),
+            market_id: market_id(),
+            rate: dec!(0.0005),
+            amount: dec!(25),
+        },
+    ]
+}
+
+fn main() {
+    let samples = samples();
+    for (i, sample) in samples.iter().enumerate() {
+        assert_eq!(variant(sample), i, "sample {i} is out of order: {sample:?}");
+    }
+    // The last arm of `variant` has a sample, so every arm before it does too.
+    assert_eq!(samples.len(), variant(samples.last().unwrap()) + 1);
+
+    let mut rejected = 0;
+    for sample in &samples {
+        let record = events::rejection_for(sample, "sample".into());
+        assert!(
+            record.is_rejection() && record.is_informational(),
+            "{record:?}"
+        );
+        assert_eq!(record.accounts(), sample.accounts(), "{sample:?}");
+        let reason = RejectReason::from_event(&record).expect("a rejection record");
+        assert_eq!(reason.message(), "sample");
+
+        // Only the market, so most events are refused for an unknown account or
+        // market, and every engine-generated one is refused outright.
+        let mut engine = Engine::new();
+        engine
+            .add_market(Market::new(
+                "BTC-PERP".parse().unwrap(),
+                dec!(0.05),
+                dec!(0.03),
+            ))
+            .unwrap();
+        if let ProcessOutcome::Rejected { reason, .. } = engine.process(sample.clone()) {
+            let logged = &engine.event_log.last().unwrap().event_type;
+            assert_eq!(
+                *logged,
+                events::rejection_for(sample, reason.message().into())
+            );
+            rejected += 1;
+        }
+    }
+    println!(
+        "{} event types map to a rejection record, {rejected} rejected live",
+        samples.len()
+    );
+}
@@ -0,0 +1,225 @@
+// Write a live engine's log to a JSONL file, read it back and replay it with full
+// verification. Then edit the file two ways and show what each check catches. An
+// enlarged trade is rejected on replay, so the liquidation the log records for it
+// cannot have happened and verified replay refuses the log at that fill, as it does
+// hand-written fills for an unknown account and for one without the position;
+// lenient replay lists them as invariant violations and applies none of them. A
+// changed deposit is self-consistent, so only a comparison with the live snapshots
+// finds it, and the differing accounts are printed.
+
+use cross_margin_engine::jsonl::{self, WriteOptions};
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::snapshot;
+use rust_decimal_macros::dec;
+
+fn markets() -> Vec<Market> {
+    vec![Market::new(
+        "BTC-PERP".parse().unwrap(),
+        dec!(0.05),
+        dec!(0.03),
+    )]
+}
+
+/// Replace the first event matching `pick` in the file at `path`.
+fn edit(path: &std::path::Path, pick: impl Fn(&EventType) -> Option<EventType>) {
+    let mut log = jsonl::read_jsonl(path).unwrap();
+    let event = log
+        .iter_mut()
+        .find(|e| pick(&e.event_type).is_some())
+        .unwrap();
+    event.event_type = pick(&event.event_type).unwrap();
+    jsonl::write_jsonl(path, &log, WriteOptions::default()).unwrap();
+}
+
+/// A log under `config` of a BTC mark and a deposit for carol, with a
+/// `LiquidationFill` of 1 BTC for `account_id` appended as if the deposit had caused
+/// it.
+fn with_fill(config: &EngineConfig, account_id: &str) -> Vec<Event> {
+    let mut engine = Engine::with_config(config.clone());
+    for market in markets() {
+        engine.add_market(market).unwrap();
+    }
+    engine.process(EventType::MarkPriceUpdate {
+        market_id: "BTC-PERP".parse().unwrap(),
+        price: dec!(50000),
+    });
+    engine.process(EventType::Deposit {
+        account_id: "carol".parse().unwrap(),
+        amount: dec!(10000),
+    });
+    let mut log: Vec<Event> = engine.event_log.iter().map(|e| (**e).clone()).collect();
+    let deposit = log.last().unwrap().sequence;
+    log.push(Event::derived(
+        deposit + 1,
+        EventType::LiquidationFill {
+            account_id: account_id.parse().unwrap(),
+            market_id: "BTC-PERP".parse().unwrap(),
+            quantity: dec!(-1),
+            price: dec!(50000),
+        },
+        deposit,
+    ));
+    log
+}
+
+fn main() -> Result<(), EngineError> {
+    let config = EngineConfig {
+        liquidation_strategy: LiquidationStrategy::BestMarginImprovementFirst,
+        ..EngineConfig::default()
+    };
+    let mut engine = Engine::builder().config(config.clone()).build();
+    for market in markets() {
+        engine.add_market(market)?;
+    }
+    let mark = |price| EventType::MarkPriceUpdate {
+        market_id: "BTC-PERP".parse().unwrap(),
+        price,
+    };
+    engine.process(mark(dec!(50000)));
+    for (account_id, amount) in [("alice", dec!(30000)), ("bob", dec!(60000))] {
+        engine.process(EventType::Deposit {
+            account_id: account_id.parse().unwrap(),
+            amount,
+        });
+        engine.process(EventType::TradeFill {
+            account_id: account_id.parse().unwrap(),
+            market_id: "BTC-PERP".parse().unwrap(),
+            quantity: dec!(10),
+            price: dec!(50000),
+            liquidity: None,
+        });
+    }
+    // Liquidates alice only.
+    engine.process(mark(dec!(47000)));
+
+    let path = std::env::temp_dir().join("cross-margin-engine-replay-from-file.jsonl");
+    jsonl::write_jsonl(&path, &engine.event_log, WriteOptions::default())?;
+    let log = jsonl::read_jsonl(&path)?;
+    assert_eq!(log, engine.event_log);
+    let replayed = Engine::replay_verified(&log, markets(), config.clone())?;
+    assert_eq!(replayed.state, engine.state);
+    assert_eq!(
+        snapshot::first_divergence(&replayed.snapshots, &engine.snapshots),
+        None
+    );
+    println!("{} events verified", replayed.events_applied);
+
+    // Under another config the log's marker stops the replay, naming what differs.
+    let err = Engine::replay_verified(&log, markets(), EngineConfig::default()).unwrap_err();
+    let EngineError::ConfigMismatch { fields, .. } = &err else {
+        panic!("expected a config mismatch, got {err}")
+    };
+    assert_eq!(fields, &["liquidation_strategy"]);
+    println!("default config: {err}");
+
+    // Twice the size needs 50,000 of IM against alice's 30,000, so replay rejects the
+    // trade, and the liquidation the log records closes a position she never opened.
+    edit(&path, |event_type| match event_type {
+        EventType::TradeFill {
+            account_id,
+            market_id,
+            price,
+            ..
+        } if account_id == "alice" => Some(EventType::TradeFill {
+            account_id: account_id.clone(),
+            market_id: market_id.clone(),
+            quantity: dec!(20),
+            price: *price,
+            liquidity: None,
+        }),
+        _ => None,
+    });
+    let edited = jsonl::read_jsonl(&path)?;
+    let Err(err) = Engine::replay_verified(&edited, markets(), config.clone()) else {
+        panic!("an edited trade replays differently")
+    };
+    let fill = edited
+        .iter()
+        .find(|e| matches!(e.event_type, EventType::LiquidationFill { .. }))
+        .unwrap()
+        .sequence;
+    assert!(
+        matches!(err, EngineError::InvalidDerivedEvent { sequence, .. } if sequence == fill),
+        "{err}"
+    );
+    println!("edited trade: {err}");
+
+    // Hand-written logs whose liquidation fill the engine could not have generated:
+    // one for an account never seen, one for carol, who holds no BTC.
+    for (account_id, reason) in [
+        ("mallory", "unknown account mallory"),
+        ("carol", "carol in BTC-PERP, which it has no position in"),
+    ] {
+        let log = with_fill(&config, account_id);
+        let sequence = log.last().unwrap().sequence;
+        let err = Engine::replay_verified(&log, markets(), config.clone()).unwrap_err();
+        let EngineError::InvalidDerivedEvent {
+            sequence: found,
+            reason: found_reason,
+        } = &err
+        else {
+            panic!("expected an invalid derived event, got {err}")
+        };
+        assert_eq!(*found, sequence, "{err}");
+        assert!(found_reason.contains(reason), "{err}");
+        println!("hand-written fill: {err}");
+
+        // Lenient replay reports it and applies nothing of it.
+        let options = ReplayOptions {
+            config: config.clone(),
+            ..ReplayOptions::default()
+        };
+        let result = Engine::replay_with(options, &log, markets());
+        assert_eq!(result.status, ReplayStatus::Completed);
+        let violations: Vec<u64> = result
+            .invariant_violations
+            .iter()
+            .map(|(s, _)| *s)
+            .collect();
+        assert_eq!(violations, [sequence]);
+        assert_eq!(
+            result
+                .state
+                .accounts
+                .keys()
+                .map(|a| a.as_str())
+                .collect::<Vec<_>>(),
+            ["carol"]
+        );
+        assert!(result.state.accounts["carol"].positions.is_empty());
+        assert!(result.state.in_liquidation.is_empty());
+    }
+
+    // A smaller deposit for bob still replays cleanly on its own terms.
+    jsonl::write_jsonl(&path, &engine.event_log, WriteOptions::default())?;
+    edit(&path, |event_type| match event_type {
+        EventType::Deposit { account_id, .. } if account_id == "bob" => Some(EventType::Deposit {
+            account_id: account_id.clone(),
+            amount: dec!(59000),
+        }),
+        _ => None,
+    });
+    let edited = Engine::replay_verified(&jsonl::read_jsonl(&path)?, markets(), config)?;
+    let seq = snapshot::first_divergence(&edited.snapshots, &engine.snapshots)
+        .expect("the edited deposit changes bob's collateral");
+    let at = |snapshots: &[Snapshot]| snapshots.iter().find(|s| s.after_sequence == seq).cloned();
+    let (before, after) = (
+        at(&engine.snapshots).unwrap(),
+        at(&edited.snapshots).unwrap(),
+    );
+    let mut differing = Vec::new();
+    for (account_id, live) in &before.accounts {
+        let other = &after.accounts[account_id];
+        if live != other {
+            println!(
+                "seq {seq}: {account_id} collateral {} -> {}, equity {} -> {}",
+                live.collateral, other.collateral, live.equity, other.equity
+            );
+            differing.push(account_id.as_str());
+        }
+    }
+    assert_eq!(differing, ["bob"]);
+
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
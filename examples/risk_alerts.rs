@@ -0,0 +1,138 @@
+// Walk a mark that flickers around the 80% margin-usage threshold. With a two-point
+// hysteresis band the flicker raises one alert; with none, every tick alerts or
+// clears. Then swing it wider than the band and check the exact alternation, that an
+// observer sees each move with the level in its snapshot, and that replay reproduces
+// the levels and refuses an alert that is altered or missing.
+
+use cross_margin_engine::prelude::*;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// (level moved to, raised) per alert event, as an observer saw it.
+struct Alerts(Rc<RefCell<Vec<(usize, bool)>>>);
+
+impl EngineObserver for Alerts {
+    fn on_event(&mut self, event: &Event, snapshot: &Snapshot) {
+        let (level, raised) = match &event.event_type {
+            EventType::RiskAlert { level, .. } => (*level, true),
+            EventType::RiskAlertCleared { level, .. } => (*level, false),
+            _ => return,
+        };
+        assert_eq!(snapshot.accounts["alice"].alert_level, level);
+        self.0.borrow_mut().push((level, raised));
+    }
+}
+
+fn markets() -> Vec<Market> {
+    vec![Market::new(
+        "BTC-PERP".parse().unwrap(),
+        dec!(0.05),
+        dec!(0.03),
+    )]
+}
+
+fn ladder(hysteresis: Decimal) -> RiskAlertLadder {
+    RiskAlertLadder {
+        thresholds: vec![dec!(0.8), dec!(0.9), dec!(0.95)],
+        hysteresis,
+    }
+}
+
+fn run(ladder: RiskAlertLadder, marks: &[Decimal]) -> (Engine, Vec<(usize, bool)>) {
+    let mut engine = Engine::builder()
+        .risk_alerts(ladder)
+        .snapshot_policy(SnapshotPolicy::EveryEvent)
+        .build();
+    for market in markets() {
+        engine.add_market(market).unwrap();
+    }
+    let alerts = Rc::new(RefCell::new(Vec::new()));
+    engine.add_observer(Box::new(Alerts(alerts.clone())));
+
+    let mark = |price| EventType::MarkPriceUpdate {
+        market_id: "BTC-PERP".parse().unwrap(),
+        price,
+    };
+    engine.process(mark(dec!(50000)));
+    engine.process(EventType::Deposit {
+        account_id: "alice".parse().unwrap(),
+        amount: dec!(3000),
+    });
+    let trade = engine.process(EventType::TradeFill {
+        account_id: "alice".parse().unwrap(),
+        market_id: "BTC-PERP".parse().unwrap(),
+        quantity: dec!(1),
+        price: dec!(50000),
+        liquidity: None,
+    });
+    assert!(trade.is_accepted());
+    for price in marks {
+        assert!(engine.process(mark(*price)).is_accepted());
+    }
+    let alerts = alerts.borrow().clone();
+    (engine, alerts)
+}
+
+fn main() {
+    // Usage at 48,800 is 81.3%, at 48,850 79.2%, at 48,900 77.2%.
+    let flicker: Vec<Decimal> = (0..20).map(|i| [dec!(48800), dec!(48850)][i % 2]).collect();
+    let (_, banded) = run(ladder(dec!(0.02)), &flicker);
+    assert_eq!(banded, [(1, true)]);
+    let (_, unbanded) = run(ladder(Decimal::ZERO), &flicker);
+    assert_eq!(unbanded.len(), 20);
+    println!(
+        "20 ticks across 80%: {} alert with a 2-point band, {} moves without",
+        banded.len(),
+        unbanded.len()
+    );
+
+    // 77.2% is outside the band, so each swing clears and raises again.
+    let swing: Vec<Decimal> = (0..10).map(|i| [dec!(48800), dec!(48900)][i % 2]).collect();
+    let (engine, alerts) = run(ladder(dec!(0.02)), &[flicker, swing].concat());
+    let mut expected = vec![(1, true)];
+    for _ in 0..5 {
+        expected.extend([(0, false), (1, true)]);
+    }
+    expected.pop();
+    assert_eq!(alerts, expected);
+    assert_eq!(engine.state.accounts["alice"].alert_level, 0);
+
+    // Each alert is caused by the mark before it and carries the usage it was set at.
+    let log = &engine.event_log;
+    let first = log
+        .iter()
+        .position(|e| matches!(e.event_type, EventType::RiskAlert { .. }))
+        .unwrap();
+    assert_eq!(log[first].caused_by, Some(log[first - 1].sequence));
+    let EventType::RiskAlert { margin_usage, .. } = &log[first].event_type else {
+        unreachable!()
+    };
+    assert_eq!(margin_usage.map(|u| u.round_dp(4)), Some(dec!(0.8133)));
+
+    // Replay applies the logged alerts and finds none missing.
+    let config = engine.config().clone();
+    let replayed = Engine::replay_verified(log, markets(), config.clone()).unwrap();
+    assert_eq!(replayed.state, engine.state);
+    assert_eq!(replayed.snapshots, engine.snapshots);
+
+    // An alert at the wrong level, or a log that ends before the alert its last mark
+    // called for, fails verification.
+    let mut forged = log.clone();
+    if let EventType::RiskAlert { level, .. } = &mut Arc::make_mut(&mut forged[first]).event_type {
+        *level = 2;
+    }
+    let result = Engine::replay_verified(&forged, markets(), config.clone());
+    assert!(
+        matches!(result, Err(EngineError::InvalidDerivedEvent { .. })),
+        "{result:?}"
+    );
+    let result = Engine::replay_verified(&log[..first], markets(), config);
+    let Err(EngineError::InvalidDerivedEvent { sequence, reason }) = result else {
+        panic!("{result:?}")
+    };
+    assert_eq!(sequence, log[first - 1].sequence);
+    assert!(reason.contains("alert level 1"), "{reason}");
+}
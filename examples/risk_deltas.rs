@@ -0,0 +1,203 @@
+// Subscribe to risk deltas. A mark update moves the three accounts holding BTC, and
+// each of their deltas must be exactly the difference between the snapshots either
+// side of it; the account holding only ETH must not appear. Over the whole run,
+// including a liquidation, the deltas must be those of consecutive snapshots with
+// unchanged accounts left out, and turning deltas off must not change the log.
+
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::snapshot;
+use rust_decimal_macros::dec;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+
+#[derive(Default)]
+struct Deltas {
+    live: Vec<RiskDelta>,
+    dry_run: Vec<RiskDelta>,
+}
+
+struct Collector(Rc<RefCell<Deltas>>);
+
+impl EngineObserver for Collector {
+    fn on_risk_delta(&mut self, delta: &RiskDelta) {
+        self.0.borrow_mut().live.push(delta.clone());
+    }
+
+    fn on_dry_run_risk_delta(&mut self, delta: &RiskDelta) {
+        self.0.borrow_mut().dry_run.push(delta.clone());
+    }
+}
+
+fn run(mode: EngineMode, policy: RiskDeltaPolicy) -> (Engine, Rc<RefCell<Deltas>>) {
+    let mut engine = Engine::builder()
+        .mode(mode)
+        .risk_deltas(policy)
+        .snapshot_policy(SnapshotPolicy::EveryEvent)
+        .build();
+    engine
+        .add_market(Market::new(
+            "BTC-PERP".parse().unwrap(),
+            dec!(0.10),
+            dec!(0.05),
+        ))
+        .unwrap();
+    engine
+        .add_market(Market::new(
+            "ETH-PERP".parse().unwrap(),
+            dec!(0.10),
+            dec!(0.05),
+        ))
+        .unwrap();
+    let deltas = Rc::new(RefCell::new(Deltas::default()));
+    engine.add_observer(Box::new(Collector(deltas.clone())));
+
+    let mark = |market_id: &str, price| EventType::MarkPriceUpdate {
+        market_id: market_id.parse().unwrap(),
+        price,
+    };
+    let deposit = |account_id: &str, amount| EventType::Deposit {
+        account_id: account_id.parse().unwrap(),
+        amount,
+    };
+    let trade = |account_id: &str, market_id: &str, quantity, price| EventType::TradeFill {
+        account_id: account_id.parse().unwrap(),
+        market_id: market_id.parse().unwrap(),
+        quantity,
+        price,
+        liquidity: None,
+    };
+    for event in [
+        mark("BTC-PERP", dec!(50000)),
+        mark("ETH-PERP", dec!(3000)),
+        deposit("alice", dec!(20000)),
+        deposit("bob", dec!(20000)),
+        deposit("carol", dec!(6000)),
+        deposit("dave", dec!(10000)),
+        trade("alice", "BTC-PERP", dec!(2), dec!(50000)),
+        trade("bob", "BTC-PERP", dec!(-1.5), dec!(50000)),
+        trade("carol", "BTC-PERP", dec!(1), dec!(50000)),
+        trade("dave", "ETH-PERP", dec!(10), dec!(3000)),
+        // Rejected: state is unchanged, so no delta.
+        trade("dave", "ETH-PERP", dec!(100), dec!(3000)),
+        mark("BTC-PERP", dec!(48000)),
+        // Carol's equity falls to 2,000 against MM 2,300: liquidated.
+        mark("BTC-PERP", dec!(46000)),
+    ] {
+        engine.process(event);
+    }
+    (engine, deltas)
+}
+
+fn main() {
+    let (mut engine, deltas) = run(EngineMode::Live, RiskDeltaPolicy::ObserversAndQueue);
+    let deltas = std::mem::take(&mut deltas.borrow_mut().live);
+    assert_eq!(
+        engine.drain_risk_deltas(),
+        deltas,
+        "the queue holds what observers saw"
+    );
+    assert!(engine.drain_risk_deltas().is_empty());
+
+    // The first BTC move touches exactly the three BTC holders.
+    let tick = engine
+        .event_log
+        .iter()
+        .find(|e| {
+            matches!(&e.event_type, EventType::MarkPriceUpdate { price, .. } if *price == dec!(48000))
+        })
+        .unwrap()
+        .sequence;
+    let at_tick: Vec<&RiskDelta> = deltas.iter().filter(|d| d.sequence == tick).collect();
+    let accounts: Vec<&str> = at_tick.iter().map(|d| d.account_id.as_str()).collect();
+    assert_eq!(accounts, ["alice", "bob", "carol"]);
+
+    let snapshot_at = |sequence: u64| {
+        let index = engine
+            .snapshots
+            .partition_point(|s| s.after_sequence < sequence);
+        &engine.snapshots[index]
+    };
+    let (before, after) = (snapshot_at(tick - 1), snapshot_at(tick));
+    assert_eq!(before.after_sequence, tick - 1);
+    for delta in &at_tick {
+        let (old, new) = (
+            &before.accounts[&delta.account_id],
+            &after.accounts[&delta.account_id],
+        );
+        assert_eq!(delta.before.equity, old.equity);
+        assert_eq!(delta.after.equity, new.equity);
+        assert_eq!(
+            delta.before.initial_margin_required,
+            old.initial_margin_required
+        );
+        assert_eq!(
+            delta.after.initial_margin_required,
+            new.initial_margin_required
+        );
+        assert_eq!(
+            delta.before.maintenance_margin_required,
+            old.maintenance_margin_required
+        );
+        assert_eq!(
+            delta.after.maintenance_margin_required,
+            new.maintenance_margin_required
+        );
+        assert_eq!(
+            delta.after.margin_ratio,
+            Some(new.equity / new.maintenance_margin_required)
+        );
+        println!(
+            "seq {tick} {:<6} equity {} -> {}, margin ratio {} -> {}",
+            delta.account_id,
+            delta.before.equity,
+            delta.after.equity,
+            delta.before.margin_ratio.unwrap().round_dp(4),
+            delta.after.margin_ratio.unwrap().round_dp(4),
+        );
+    }
+
+    // Over the whole run: the differences of consecutive snapshots, no more, no less.
+    let mut expected = Vec::new();
+    let mut previous = snapshot::capture(&State::new(), 0);
+    for snapshot in &engine.snapshots {
+        expected.extend(snapshot::risk_deltas(&previous, snapshot));
+        previous = snapshot.clone();
+    }
+    assert_eq!(deltas, expected);
+    let liquidated = engine
+        .event_log
+        .iter()
+        .find(|e| matches!(e.event_type, EventType::LiquidationFill { .. }))
+        .unwrap();
+    assert!(deltas
+        .iter()
+        .any(|d| d.sequence == liquidated.sequence && d.account_id == "carol"));
+    println!(
+        "{} deltas over {} events",
+        deltas.len(),
+        engine.event_log.len()
+    );
+
+    // Deltas are a side channel: the log is the same without them.
+    let (mut off, off_deltas) = run(EngineMode::Live, RiskDeltaPolicy::Off);
+    let without = |log: &[Arc<Event>]| -> Vec<EventType> {
+        log.iter()
+            .map(|e| match &e.event_type {
+                EventType::ConfigMarker { .. } => EventType::ConfigMarker {
+                    config_hash: String::new(),
+                    config: EngineConfig::default(),
+                },
+                other => other.clone(),
+            })
+            .collect()
+    };
+    assert_eq!(without(&off.event_log), without(&engine.event_log));
+    assert!(off_deltas.borrow().live.is_empty() && off.drain_risk_deltas().is_empty());
+
+    // Dry-run deltas reach only the dry-run callback.
+    let (_, dry_run) = run(EngineMode::DryRun, RiskDeltaPolicy::Observers);
+    let dry_run = dry_run.borrow();
+    assert!(dry_run.live.is_empty());
+    assert_eq!(dry_run.dry_run, deltas);
+}
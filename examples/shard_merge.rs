@@ -0,0 +1,298 @@
+// Run two engines as shards of one book: each owns its own accounts and both receive
+// the same market feed. Merge their logs by timestamp, check that one engine replaying
+// the merged log ends with the union of the shard states, and that splitting the
+// merged log by account gives back the shard logs. Then check that conflicting logs
+// are refused, and that every scenario survives a split and a merge back.
+
+use cross_margin_engine::events::{merge, split_by_account, MergeKey};
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::scenario;
+use rust_decimal_macros::dec;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+const SHARD_ACCOUNTS: [&[&str]; 2] = [&["alice", "bob"], &["carol", "dave"]];
+
+fn markets() -> Vec<Market> {
+    vec![
+        Market::new("BTC-PERP".parse().unwrap(), dec!(0.10), dec!(0.05)),
+        Market::new("ETH-PERP".parse().unwrap(), dec!(0.10), dec!(0.05)),
+    ]
+}
+
+fn shard_of(account_id: &str) -> usize {
+    SHARD_ACCOUNTS
+        .iter()
+        .position(|accounts| accounts.contains(&account_id))
+        .expect("every account is assigned")
+}
+
+/// Market-wide events, at the timestamps both shards receive them.
+fn feed() -> Vec<(u64, EventType)> {
+    let mark = |market_id: &str, price| EventType::MarkPriceUpdate {
+        market_id: market_id.parse().unwrap(),
+        price,
+    };
+    vec![
+        (1_000, mark("BTC-PERP", dec!(50000))),
+        (1_000, mark("ETH-PERP", dec!(3000))),
+        (5_000, mark("BTC-PERP", dec!(-1))),
+        (
+            6_000,
+            EventType::FundingUpdate {
+                market_id: "ETH-PERP".parse().unwrap(),
+                new_cumulative_index: dec!(4),
+            },
+        ),
+        (8_000, mark("BTC-PERP", dec!(46000))),
+        (9_000, mark("ETH-PERP", dec!(2900))),
+    ]
+}
+
+/// Account events, at the timestamps their shard receives them.
+fn orders() -> Vec<(u64, EventType)> {
+    let deposit = |account_id: &str, amount| EventType::Deposit {
+        account_id: account_id.parse().unwrap(),
+        amount,
+    };
+    let trade = |account_id: &str, market_id: &str, quantity, price| EventType::TradeFill {
+        account_id: account_id.parse().unwrap(),
+        market_id: market_id.parse().unwrap(),
+        quantity,
+        price,
+        liquidity: None,
+    };
+    vec![
+        (2_000, deposit("alice", dec!(10000))),
+        (2_000, deposit("carol", dec!(8000))),
+        (2_500, deposit("dave", dec!(50000))),
+        (3_000, deposit("bob", dec!(20000))),
+        (3_000, trade("carol", "BTC-PERP", dec!(1.4), dec!(50000))),
+        (3_500, trade("alice", "ETH-PERP", dec!(20), dec!(3000))),
+        (4_000, trade("dave", "ETH-PERP", dec!(-10), dec!(3000))),
+        // Over initial margin: rejected.
+        (4_500, trade("bob", "BTC-PERP", dec!(5), dec!(50000))),
+        (7_000, trade("bob", "BTC-PERP", dec!(-1), dec!(50000))),
+        (
+            7_500,
+            EventType::Withdraw {
+                account_id: "dave".parse().unwrap(),
+                amount: dec!(1000),
+            },
+        ),
+    ]
+}
+
+fn run_shard(shard: usize) -> Engine {
+    let mut engine = Engine::new();
+    for market in markets() {
+        engine.add_market(market).unwrap();
+    }
+    // The feed's keys are the feed's own, the same in every shard.
+    let feed = feed()
+        .into_iter()
+        .enumerate()
+        .map(|(i, (timestamp, event))| (timestamp, format!("feed-{i}"), event));
+    let orders = orders()
+        .into_iter()
+        .enumerate()
+        .map(|(i, (timestamp, event))| (timestamp, format!("order-{i}"), event))
+        .filter(|(_, _, event)| shard_of(event.accounts()[0]) == shard);
+    let mut events: Vec<_> = feed.chain(orders).collect();
+    events.sort_by_key(|(timestamp, _, _)| *timestamp);
+    for (timestamp, key, event) in events {
+        let submission = Submission {
+            idempotency_key: Some(key),
+            timestamp: Some(timestamp),
+        };
+        engine.process_with(event, submission);
+    }
+    engine
+}
+
+/// Markets as a shard sees them, without the sequence of the last mark, which is
+/// numbered differently in every log.
+fn market_view(state: &State) -> BTreeMap<MarketId, Market> {
+    state
+        .markets
+        .iter()
+        .map(|(market_id, market)| {
+            let market = Market {
+                last_mark_sequence: None,
+                ..market.clone()
+            };
+            (market_id.clone(), market)
+        })
+        .collect()
+}
+
+fn main() {
+    let shards = [run_shard(0), run_shard(1)];
+    let logs = [
+        shards[0].event_log.as_slice(),
+        shards[1].event_log.as_slice(),
+    ];
+    let liquidated = |log: &[Arc<Event>]| {
+        log.iter()
+            .any(|e| matches!(e.event_type, EventType::LiquidationFill { .. }))
+    };
+    assert!(liquidated(logs[1]) && !liquidated(logs[0]));
+
+    let merged = merge(&logs, MergeKey::Timestamp).unwrap();
+    let replayed = Engine::replay_verified(&merged, markets(), EngineConfig::default()).unwrap();
+    println!(
+        "merged {} + {} events into {}, replayed {}",
+        logs[0].len(),
+        logs[1].len(),
+        merged.len(),
+        replayed.events_applied
+    );
+
+    // One engine replaying the merged log holds every shard's accounts as the shard
+    // left them, and markets as both shards saw them.
+    let mut union = shards[0].state.accounts.clone();
+    union.extend(shards[1].state.accounts.clone());
+    assert_eq!(replayed.state.accounts, union);
+    assert_eq!(market_view(&replayed.state), market_view(&shards[0].state));
+    assert_eq!(market_view(&replayed.state), market_view(&shards[1].state));
+
+    // The shared feed is logged once, with its rejected mark, the rest keeps its
+    // shard, and timestamps never go backwards.
+    fn count(log: &[impl AsRef<Event>], kind: fn(&EventType) -> bool) -> usize {
+        log.iter().filter(|e| kind(&e.as_ref().event_type)).count()
+    }
+    let is_mark = |e: &EventType| matches!(e, EventType::MarkPriceUpdate { .. });
+    let is_rejection = |e: &EventType| e.is_rejection();
+    assert_eq!(count(&merged, is_mark), count(logs[0], is_mark));
+    assert_eq!(count(&merged, is_rejection), 2);
+    for event in &merged {
+        for account_id in event.event_type.accounts() {
+            assert_eq!(event.origin_shard, Some(shard_of(account_id)));
+        }
+    }
+    let mut clock = 0;
+    for event in merged.iter().filter_map(|e| e.timestamp) {
+        assert!(event >= clock);
+        clock = event;
+    }
+
+    // Splitting the merged log by account gives back each shard's log.
+    assert_eq!(split_by_account(&merged, shard_of).unwrap(), logs);
+
+    // Conflicts: an account in both shards, and shards under different configs.
+    let err = merge(&[logs[0], logs[0]], MergeKey::Timestamp).unwrap_err();
+    assert!(matches!(&err, MergeError::SharedAccounts(accounts) if accounts.len() == 2));
+    println!("{err}");
+
+    let mut other = Engine::with_config(EngineConfig {
+        scan_order: ScanOrder::WorstMarginRatioFirst,
+        ..EngineConfig::default()
+    });
+    other.process(EventType::Deposit {
+        account_id: "erin".parse().unwrap(),
+        amount: dec!(1),
+    });
+    let err = merge(&[logs[0], &other.event_log], MergeKey::Timestamp).unwrap_err();
+    assert!(matches!(err, MergeError::ConfigMismatch { shard: 1, .. }));
+    println!("{err}");
+
+    // A log without timestamps can only be merged by sequence.
+    let mut untimed = Engine::new();
+    untimed.process(EventType::Deposit {
+        account_id: "erin".parse().unwrap(),
+        amount: dec!(1),
+    });
+    let err = merge(&[logs[0], &untimed.event_log], MergeKey::Timestamp).unwrap_err();
+    assert!(matches!(err, MergeError::MissingTimestamp { shard: 1, .. }));
+    println!("{err}");
+    let merged = merge(&[logs[0], &untimed.event_log], MergeKey::Sequence).unwrap();
+    Engine::replay_verified(&merged, markets(), EngineConfig::default()).unwrap();
+
+    // Every scenario, split into one, two and three shards and merged back.
+    let mut paths: Vec<_> = std::fs::read_dir("scenarios")
+        .expect("run from the repository root")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    paths.sort();
+    for path in &paths {
+        let scenario = scenario::load(path).unwrap();
+        let engine = scenario::run(&scenario).unwrap().engine;
+        let markets: Vec<Market> = scenario.markets.iter().map(|m| m.to_market()).collect();
+        // A group's cap sums its members, so they share a shard: each account goes
+        // with the first group it joined, or on its own.
+        let mut groups: BTreeMap<&str, &str> = BTreeMap::new();
+        for event in &engine.event_log {
+            if let EventType::GroupMembershipSet {
+                account_id,
+                group_id: Some(group_id),
+            } = &event.event_type
+            {
+                groups
+                    .entry(account_id.as_str())
+                    .or_insert(group_id.as_str());
+            }
+        }
+        // A socialized loss charges the whole pool, and a yield residual is the pool's
+        // rounding, so then a pool is one unit. Skew is the net of every account in a
+        // market, and open interest the gross, so a skew limit or an open interest cap,
+        // a market's or the venue's, makes the book one. So does a takeover or a
+        // transfer, which moves a position between two accounts, or a merge, which
+        // moves a whole account.
+        // A margin call expires on whichever event comes next, any account's.
+        let socialize = scenario.config.residual_deficit.socializes()
+            || engine
+                .event_log
+                .iter()
+                .any(|e| matches!(e.event_type, EventType::YieldResidual { .. }));
+        let one_book = scenario
+            .markets
+            .iter()
+            .any(|m| m.skew_limit_notional.is_some() || m.max_open_interest_notional.is_some())
+            || scenario.config.max_total_open_interest_notional.is_some()
+            || scenario.config.margin_call.is_some()
+            || engine.event_log.iter().any(|e| {
+                matches!(
+                    e.event_type,
+                    EventType::LiquidationTakeover { .. }
+                        | EventType::AccountsMerged { .. }
+                        | EventType::PositionTransfer { .. }
+                )
+            });
+        let unit = |account_id: &str| match engine.state.accounts.get(account_id) {
+            _ if one_book => String::new(),
+            Some(account) if socialize => account.pool_id.clone(),
+            _ => groups
+                .get(account_id)
+                .copied()
+                .unwrap_or(account_id)
+                .to_string(),
+        };
+        let units: BTreeSet<String> = engine.state.accounts.keys().map(|a| unit(a)).collect();
+        for shard_count in 1..=3 {
+            let assignment = |account_id: &str| {
+                let unit = unit(account_id);
+                units.iter().position(|u| *u == unit).unwrap_or(0) % shard_count
+            };
+            let shard_logs = split_by_account(&engine.event_log, assignment).unwrap();
+            for log in &shard_logs {
+                Engine::replay_verified(log, markets.clone(), scenario.config.clone()).unwrap();
+            }
+            let logs: Vec<&[Event]> = shard_logs.iter().map(Vec::as_slice).collect();
+            let merged = merge(&logs, MergeKey::Sequence).unwrap();
+            let replayed =
+                Engine::replay_verified(&merged, markets.clone(), scenario.config.clone()).unwrap();
+            assert_eq!(
+                replayed.state.accounts,
+                engine.state.accounts,
+                "{}",
+                path.display()
+            );
+            assert_eq!(split_by_account(&merged, assignment).unwrap(), shard_logs);
+        }
+    }
+    println!(
+        "{} scenarios split into 1, 2 and 3 shards and merged back",
+        paths.len()
+    );
+}
@@ -0,0 +1,192 @@
+// Bankrupt two accounts with one mark and share out what they owe. Run scenario 37:
+// the insurance fund covers the first account in scan order in full and the second
+// by half, and the rest is socialized across the pool. Check the exact records and
+// their cause, that verified replay agrees, and that a forged split is refused. Then
+// make bob the bigger loser and show, under each scan order, who the fund covers.
+
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::scenario::{self, Scenario};
+use cross_margin_engine::snapshot;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Scenario 37's actions, with `edit` applied to each, under `config`.
+fn rerun(scenario: &Scenario, config: EngineConfig, edit: impl Fn(&str) -> String) -> Engine {
+    let steps = scenario
+        .steps
+        .iter()
+        .filter(|step| !step.starts_with("expect"))
+        .map(|step| edit(step))
+        .collect();
+    let scenario = Scenario {
+        steps,
+        config,
+        ..scenario.clone()
+    };
+    scenario::run(&scenario).unwrap().engine
+}
+
+/// Insurance paid and loss socialized per account, in log order.
+fn coverage(engine: &Engine) -> Vec<(AccountId, &'static str, Decimal)> {
+    engine
+        .event_log
+        .iter()
+        .filter_map(|event| match &event.event_type {
+            EventType::InsuranceFundPayout {
+                account_id, amount, ..
+            } => Some((account_id.clone(), "insurance", *amount)),
+            EventType::LossSocialized {
+                account_id, amount, ..
+            } => Some((account_id.clone(), "socialized", *amount)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn main() {
+    let path = "scenarios/37_shared_bankruptcy.toml";
+    let scenario = scenario::load(path).expect("run from the repository root");
+    let markets: Vec<Market> = scenario.markets.iter().map(|m| m.to_market()).collect();
+    let engine = scenario::run(&scenario).unwrap().engine;
+
+    let log = &engine.event_log;
+    let mark = log
+        .iter()
+        .rfind(|e| matches!(e.event_type, EventType::MarkPriceUpdate { .. }))
+        .unwrap();
+    let after_mark: Vec<&Event> = log
+        .iter()
+        .filter(|e| e.sequence > mark.sequence)
+        .map(AsRef::as_ref)
+        .collect();
+    let fill = |account_id: &str| EventType::LiquidationFill {
+        account_id: account_id.parse().unwrap(),
+        market_id: "BTC-PERP".parse().unwrap(),
+        quantity: dec!(-10),
+        price: dec!(46000),
+    };
+    let payout = |account_id: &str, amount| EventType::InsuranceFundPayout {
+        pool_id: "default".into(),
+        account_id: account_id.parse().unwrap(),
+        amount,
+    };
+    let socialized = EventType::LossSocialized {
+        pool_id: "default".into(),
+        account_id: "bob".parse().unwrap(),
+        amount: dec!(5000),
+        charges: BTreeMap::from([
+            ("carol".parse().unwrap(), dec!(2500)),
+            ("dave".parse().unwrap(), dec!(2407.5)),
+            ("erin".parse().unwrap(), dec!(92.5)),
+        ]),
+    };
+    let expected = [
+        fill("alice"),
+        payout("alice", dec!(10000)),
+        fill("bob"),
+        payout("bob", dec!(5000)),
+        socialized.clone(),
+    ];
+    assert!(after_mark.iter().map(|e| &e.event_type).eq(expected.iter()));
+    assert!(after_mark
+        .iter()
+        .all(|e| e.caused_by == Some(mark.sequence)));
+    assert_eq!(engine.events_caused_by(mark.sequence).unwrap().count(), 5);
+    for (account_id, kind, amount) in coverage(&engine) {
+        println!("seq {} -> {account_id}: {amount} {kind}", mark.sequence);
+    }
+
+    // Replay applies the recorded split only if it is the one the state calls for.
+    let config = engine.config().clone();
+    let replayed = Engine::replay_verified(log, markets.clone(), config.clone()).unwrap();
+    assert_eq!(replayed.state, engine.state);
+    assert_eq!(
+        snapshot::first_divergence(&replayed.snapshots, &engine.snapshots),
+        None
+    );
+
+    let mut forged = log.clone();
+    let event = forged
+        .iter_mut()
+        .find(|e| e.event_type == socialized)
+        .unwrap();
+    let EventType::LossSocialized { charges, .. } = &mut Arc::make_mut(event).event_type else {
+        unreachable!()
+    };
+    *charges.get_mut("carol").unwrap() -= dec!(1);
+    *charges.get_mut("dave").unwrap() += dec!(1);
+    let err = Engine::replay_verified(&forged, markets, config.clone()).unwrap_err();
+    assert!(
+        matches!(err, EngineError::InvalidDerivedEvent { .. }),
+        "{err}"
+    );
+    println!("forged split: {err}");
+
+    // Without socialization bob keeps the 5,000 the fund could not cover.
+    let hold = EngineConfig {
+        residual_deficit: ResidualDeficit::Hold,
+        ..config.clone()
+    };
+    let held = rerun(&scenario, hold, str::to_string);
+    assert_eq!(held.state.accounts["bob"].bankruptcy_deficit, dec!(5000));
+    assert_eq!(held.state.accounts["carol"].collateral(), dec!(100000));
+    assert!(coverage(&held)
+        .iter()
+        .all(|(_, kind, _)| *kind == "insurance"));
+
+    // With 11 BTC bob goes 14,000 through zero, against alice's 10,000, and the fund
+    // covers whoever the scan reaches first. Account order puts alice first. Largest
+    // notional puts bob first, and so does worst margin ratio: his -14,000 over MM of
+    // 15,180 is below her -10,000 over 13,800. With 35,000 deposited bob owes 9,000,
+    // a ratio of -0.59, so worst margin ratio takes alice first again while largest
+    // notional still takes bob.
+    let line =
+        |account_id: &str, kind, amount| (account_id.parse::<AccountId>().unwrap(), kind, amount);
+    let alice_first = |bob: Decimal| {
+        [
+            line("alice", "insurance", dec!(10000)),
+            line("bob", "insurance", dec!(5000)),
+            line("bob", "socialized", bob - dec!(5000)),
+        ]
+    };
+    let bob_first = |bob: Decimal| {
+        [
+            line("bob", "insurance", bob),
+            line("alice", "insurance", dec!(15000) - bob),
+            line("alice", "socialized", bob - dec!(5000)),
+        ]
+    };
+    for (deposit, deficit, ratio_first) in [
+        ("30000", dec!(14000), bob_first(dec!(14000))),
+        ("35000", dec!(9000), alice_first(dec!(9000))),
+    ] {
+        let edit = |step: &str| {
+            step.replace("trade bob BTC-PERP +10", "trade bob BTC-PERP +11")
+                .replace("deposit bob 30000", &format!("deposit bob {deposit}"))
+        };
+        for (scan_order, expected) in [
+            (ScanOrder::AccountId, alice_first(deficit)),
+            (ScanOrder::LargestNotionalFirst, bob_first(deficit)),
+            (ScanOrder::WorstMarginRatioFirst, ratio_first.clone()),
+        ] {
+            let config = EngineConfig {
+                scan_order,
+                ..config.clone()
+            };
+            let engine = rerun(&scenario, config, edit);
+            assert_eq!(coverage(&engine), expected, "{scan_order:?}, bob {deposit}");
+            assert!(engine
+                .state
+                .accounts
+                .values()
+                .all(|a| a.bankruptcy_deficit.is_zero()));
+            assert!(engine.solvency().is_balanced());
+            println!(
+                "bob deposits {deposit}, {scan_order:?}: fund covers {} first",
+                expected[0].0
+            );
+        }
+    }
+}
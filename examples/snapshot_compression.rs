@@ -0,0 +1,102 @@
+// Delta-compress snapshot streams and check that decompression gives back exactly
+// what went in: the demo log's replay, every scenario's live snapshots, and streams
+// out of order or with the clock going back to unset. Both forms must also survive
+// a round trip through a temporary file, and the demo's compressed size is held to
+// under a third of the plain JSON. The demo's stream is the tracked golden copy in
+// scenarios/demo.snapshots.json, which is only read here.
+//
+// Run from the repository root.
+
+use cross_margin_engine::demo;
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::scenario;
+use cross_margin_engine::snapshot::{self, CompressedSnapshots};
+use rust_decimal_macros::dec;
+
+/// Compress `snapshots`, check every way back, and return (plain, compressed) bytes.
+fn round_trip(snapshots: &[Snapshot]) -> (usize, usize) {
+    let compressed = snapshot::compress(snapshots);
+    assert_eq!(snapshot::decompress(&compressed), snapshots);
+    let json = serde_json::to_string(&compressed).unwrap();
+    let parsed: CompressedSnapshots = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed, compressed);
+
+    let plain = serde_json::to_string(snapshots).unwrap();
+    let path = std::env::temp_dir().join("cross-margin-engine-snapshots.json");
+    for form in [&plain, &json] {
+        std::fs::write(&path, form).unwrap();
+        assert_eq!(snapshot::read_snapshots(&path).unwrap(), snapshots);
+    }
+    std::fs::remove_file(&path).unwrap();
+    (plain.len(), json.len())
+}
+
+fn main() {
+    let (_, snapshots) = Engine::replay(&demo::engine().event_log, demo::markets());
+    let golden = snapshot::read_snapshots("scenarios/demo.snapshots.json").unwrap();
+    assert_eq!(golden, snapshots);
+    let (plain, compressed) = round_trip(&snapshots);
+    println!(
+        "demo: {} snapshots, {plain} bytes plain, {compressed} compressed",
+        snapshots.len()
+    );
+    assert!(
+        compressed * 3 < plain,
+        "the demo's snapshots compress to under a third"
+    );
+
+    // Every scenario's live stream.
+    let mut paths: Vec<_> = std::fs::read_dir("scenarios")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    paths.sort();
+    let (mut plain, mut compressed) = (0, 0);
+    for path in &paths {
+        let run = scenario::run(&scenario::load(path).unwrap()).unwrap();
+        let (p, c) = round_trip(&run.engine.snapshots);
+        plain += p;
+        compressed += c;
+    }
+    println!(
+        "{} scenarios: {plain} bytes plain, {compressed} compressed",
+        paths.len()
+    );
+
+    // Any order is lossless, including a clock that goes from set back to unset.
+    let mut engine = Engine::new();
+    engine
+        .add_market(Market::new(
+            "BTC-PERP".parse().unwrap(),
+            dec!(0.05),
+            dec!(0.03),
+        ))
+        .unwrap();
+    let deposit = EventType::Deposit {
+        account_id: "alice".parse().unwrap(),
+        amount: dec!(1000),
+    };
+    engine.process(deposit.clone());
+    for timestamp in [1_700_000_000_000, 1_700_000_001_000] {
+        let submission = Submission {
+            timestamp: Some(timestamp),
+            ..Submission::default()
+        };
+        engine.process_with(deposit.clone(), submission);
+    }
+    let mut reversed = engine.snapshots.clone();
+    reversed.reverse();
+    assert_eq!(reversed.last().unwrap().clock, None);
+    round_trip(&reversed);
+    round_trip(
+        &snapshots
+            .iter()
+            .rev()
+            .step_by(3)
+            .cloned()
+            .collect::<Vec<_>>(),
+    );
+    round_trip(&snapshots[..1]);
+    round_trip(&[]);
+}
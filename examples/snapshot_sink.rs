@@ -0,0 +1,315 @@
+// Snapshots handed to a sink instead of kept in `Engine::snapshots`. The same events
+// run with no sink, into an `InMemorySink`, a `JsonlFileSink` and a `NullSink`: each
+// engine logs the same events to the same state, and the sinks hold exactly what
+// the vector would have. Then a sink that refuses every third snapshot: the refused
+// events still apply, the outcomes say which snapshots were lost, and log, state and
+// verified replay match the engine without a sink. Attaching a sink to an engine that
+// already retained snapshots returns the refusals of those, and the JSON interface
+// reports refusals with the event's response. Replay takes sinks the same way.
+
+use cross_margin_engine::command::{Command, Response};
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::snapshot;
+use rust_decimal_macros::dec;
+
+fn id(text: &str) -> AccountId {
+    text.parse().unwrap()
+}
+
+/// Keeps what it accepts in `kept`, but refuses every third snapshot offered.
+struct Refusing {
+    offered: u64,
+    kept: InMemorySink,
+}
+
+impl SnapshotSink for Refusing {
+    fn accept(&mut self, snapshot: Snapshot) -> Result<(), SinkError> {
+        self.offered += 1;
+        if self.offered.is_multiple_of(3) {
+            return Err(SinkError(format!("disk full at snapshot {}", self.offered)));
+        }
+        self.kept.accept(snapshot)
+    }
+}
+
+fn markets() -> Vec<Market> {
+    ["BTC-PERP", "ETH-PERP"]
+        .into_iter()
+        .map(|m| Market::new(m.parse().unwrap(), dec!(0.10), dec!(0.05)))
+        .collect()
+}
+
+/// Deposits, trades, a rejected withdrawal and a mark that liquidates alice.
+fn events() -> Vec<EventType> {
+    let (btc, eth): (MarketId, MarketId) =
+        ("BTC-PERP".parse().unwrap(), "ETH-PERP".parse().unwrap());
+    vec![
+        EventType::MarkPriceUpdate {
+            market_id: btc.clone(),
+            price: dec!(50000),
+        },
+        EventType::MarkPriceUpdate {
+            market_id: eth.clone(),
+            price: dec!(3000),
+        },
+        EventType::Deposit {
+            account_id: id("alice"),
+            amount: dec!(10000),
+        },
+        EventType::Deposit {
+            account_id: id("bob"),
+            amount: dec!(20000),
+        },
+        EventType::TradeFill {
+            account_id: id("alice"),
+            market_id: btc.clone(),
+            quantity: dec!(1.5),
+            price: dec!(50000),
+            liquidity: None,
+        },
+        EventType::TradeFill {
+            account_id: id("bob"),
+            market_id: eth.clone(),
+            quantity: dec!(-20),
+            price: dec!(3000),
+            liquidity: None,
+        },
+        EventType::Withdraw {
+            account_id: id("alice"),
+            amount: dec!(9000),
+        },
+        EventType::MarkPriceUpdate {
+            market_id: eth,
+            price: dec!(2900),
+        },
+        EventType::MarkPriceUpdate {
+            market_id: btc,
+            price: dec!(45000),
+        },
+        EventType::Deposit {
+            account_id: id("carol"),
+            amount: dec!(500),
+        },
+    ]
+}
+
+/// An engine over `markets()` with `sink`, if any, that has processed `events()`.
+fn run(sink: Option<Box<dyn SnapshotSink>>) -> (Engine, Vec<ProcessOutcome>) {
+    let mut engine = Engine::new();
+    for market in markets() {
+        engine.add_market(market).unwrap();
+    }
+    if let Some(sink) = sink {
+        assert!(
+            engine.set_snapshot_sink(sink).is_empty(),
+            "nothing retained yet"
+        );
+    }
+    let outcomes = events()
+        .into_iter()
+        .map(|event_type| engine.process(event_type))
+        .collect();
+    (engine, outcomes)
+}
+
+fn main() {
+    let (reference, expected) = run(None);
+    let liquidated = reference
+        .event_log
+        .iter()
+        .any(|e| matches!(e.event_type, EventType::LiquidationFill { .. }));
+    assert!(liquidated, "alice is liquidated");
+    assert!(expected
+        .iter()
+        .any(|o| matches!(o, ProcessOutcome::Rejected { .. })));
+    assert!(reference.snapshots.len() > 9);
+
+    let in_memory = InMemorySink::new();
+    let (engine, outcomes) = run(Some(Box::new(in_memory.clone())));
+    assert_eq!(outcomes, expected);
+    assert_eq!(
+        (&engine.event_log, &engine.state),
+        (&reference.event_log, &reference.state)
+    );
+    assert!(engine.snapshots.is_empty());
+    assert_eq!(in_memory.snapshots(), reference.snapshots);
+
+    // The file holds one snapshot per line and reads back to the same snapshots.
+    let path = std::env::temp_dir().join("cross-margin-engine-snapshot-sink.jsonl");
+    let (engine, outcomes) = run(Some(Box::new(JsonlFileSink::create(&path).unwrap())));
+    assert_eq!(outcomes, expected);
+    assert_eq!(engine.event_log, reference.event_log);
+    let lines = std::fs::read_to_string(&path).unwrap().lines().count();
+    assert_eq!(lines, reference.snapshots.len());
+    assert_eq!(
+        snapshot::read_snapshots(&path).unwrap(),
+        reference.snapshots
+    );
+
+    let (engine, outcomes) = run(Some(Box::new(NullSink)));
+    assert_eq!(outcomes, expected);
+    assert_eq!(
+        (&engine.event_log, &engine.state),
+        (&reference.event_log, &reference.state)
+    );
+    assert!(engine.snapshots.is_empty());
+
+    // Every third snapshot is refused. The outcome of the event that recorded it
+    // wraps what would have come back without a sink, and lists the refusals; a
+    // liquidating mark records several events, so may lose more than one.
+    let kept = InMemorySink::new();
+    let refusing = Refusing {
+        offered: 0,
+        kept: kept.clone(),
+    };
+    let (engine, outcomes) = run(Some(Box::new(refusing)));
+    let mut refused = Vec::new();
+    for (outcome, expected) in outcomes.iter().zip(&expected) {
+        assert_eq!(outcome.outcome(), expected);
+        assert_eq!(outcome.is_accepted(), expected.is_accepted());
+        if let ProcessOutcome::SinkFailed { errors, .. } = outcome {
+            refused.extend(errors.iter().map(|(sequence, _)| *sequence));
+        }
+    }
+    assert_eq!(refused.len(), reference.snapshots.len() / 3);
+    let refused_by_process = refused.clone();
+    let survivors: Vec<Snapshot> = reference
+        .snapshots
+        .iter()
+        .filter(|s| !refused.contains(&s.after_sequence))
+        .cloned()
+        .collect();
+    assert_eq!(kept.snapshots(), survivors);
+    assert_eq!(
+        (&engine.event_log, &engine.state),
+        (&reference.event_log, &reference.state)
+    );
+    let replayed =
+        Engine::replay_verified(&engine.event_log, markets(), EngineConfig::default()).unwrap();
+    assert_eq!(replayed.state, reference.state);
+
+    let failed = outcomes
+        .iter()
+        .find(|o| matches!(o, ProcessOutcome::SinkFailed { .. }))
+        .unwrap();
+    match failed.clone().into_result() {
+        Err(EngineError::SnapshotSink {
+            sequence, outcome, ..
+        }) => {
+            assert_eq!(sequence, refused[0]);
+            assert_eq!(&*outcome, failed.outcome());
+        }
+        other => panic!("expected a sink error, got {other:?}"),
+    }
+    assert_eq!(expected[0].clone().into_result().unwrap(), expected[0]);
+
+    // Snapshots retained before a sink is attached are handed over at once, and the
+    // refusals come back from the call rather than with a later outcome.
+    let (mut engine, _) = run(None);
+    let retained: Vec<u64> = engine.snapshots.iter().map(|s| s.after_sequence).collect();
+    let kept = InMemorySink::new();
+    let refused = engine.set_snapshot_sink(Box::new(Refusing {
+        offered: 0,
+        kept: kept.clone(),
+    }));
+    let refused: Vec<u64> = refused.iter().map(|(sequence, _)| *sequence).collect();
+    assert_eq!(
+        refused,
+        retained
+            .iter()
+            .copied()
+            .skip(2)
+            .step_by(3)
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(kept.snapshots().len() + refused.len(), retained.len());
+    assert!(engine.snapshots.is_empty());
+    let outcome = engine.process(EventType::Deposit {
+        account_id: id("dave"),
+        amount: dec!(1),
+    });
+    assert!(
+        matches!(outcome, ProcessOutcome::Accepted { .. }),
+        "{outcome:?}"
+    );
+
+    // The JSON interface reports refusals alongside the event's own response.
+    let mut engine = Engine::new();
+    for market in markets() {
+        engine.add_market(market).unwrap();
+    }
+    let refusing = Refusing {
+        offered: 0,
+        kept: InMemorySink::new(),
+    };
+    assert!(engine.set_snapshot_sink(Box::new(refusing)).is_empty());
+    let responses: Vec<Response> = events()
+        .into_iter()
+        .map(|event| {
+            let command = Command::Process {
+                event,
+                idempotency_key: None,
+                timestamp: None,
+            };
+            let json = engine.handle(&serde_json::to_string(&command).unwrap());
+            serde_json::from_str(&json).unwrap()
+        })
+        .collect();
+    let lost: Vec<u64> = responses
+        .iter()
+        .filter_map(|response| match response {
+            Response::SinkFailed { outcome, errors } => {
+                assert!(!matches!(**outcome, Response::Error { .. }), "{outcome:?}");
+                assert!(errors.iter().all(|e| e.error.starts_with("disk full")));
+                Some(errors.iter().map(|e| e.sequence))
+            }
+            _ => None,
+        })
+        .flatten()
+        .collect();
+    assert_eq!(lost, refused_by_process);
+
+    // Replay into sinks. `KeepLast` bounds the vector, not what a sink is handed.
+    let replay = |policy: SnapshotPolicy, sink: Option<Box<dyn SnapshotSink>>| {
+        let options = ReplayOptions {
+            snapshot_policy: policy,
+            snapshot_sink: sink,
+            ..ReplayOptions::default()
+        };
+        Engine::replay_with(options, &reference.event_log, markets())
+    };
+    let replay_sink = InMemorySink::new();
+    let result = replay(
+        SnapshotPolicy::KeepLast(2),
+        Some(Box::new(replay_sink.clone())),
+    );
+    assert_eq!(result.state, reference.state);
+    assert!(result.snapshots.is_empty() && result.sink_errors.is_empty());
+    assert_eq!(
+        replay_sink.snapshots(),
+        replay(SnapshotPolicy::EveryEvent, None).snapshots
+    );
+    assert_eq!(replay(SnapshotPolicy::KeepLast(2), None).snapshots.len(), 2);
+
+    let replay_kept = InMemorySink::new();
+    let refusing = Refusing {
+        offered: 0,
+        kept: replay_kept.clone(),
+    };
+    let result = replay(SnapshotPolicy::EveryEvent, Some(Box::new(refusing)));
+    assert_eq!(result.status, ReplayStatus::Completed);
+    assert_eq!(result.state, reference.state);
+    assert_eq!(result.sink_errors.len(), replay_sink.snapshots().len() / 3);
+    assert_eq!(
+        replay_kept.snapshots().len() + result.sink_errors.len(),
+        replay_sink.snapshots().len()
+    );
+
+    std::fs::remove_file(&path).unwrap();
+    println!(
+        "{} snapshots through each sink; {} refused with {} events still applied",
+        reference.snapshots.len(),
+        refused.len(),
+        engine.event_log.len()
+    );
+}
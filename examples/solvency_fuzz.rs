@@ -0,0 +1,134 @@
+// Drive an engine with a long pseudo-random event stream (deposits, withdrawals,
+// fractional trades, mark moves, funding, keeper takeovers and liquidations) and
+// check that the books balance after every event and again after replay.
+
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::rng::Lcg;
+use cross_margin_engine::state;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+fn markets() -> Vec<Market> {
+    let mut btc = Market::new("BTC-PERP".parse().unwrap(), dec!(0.05), dec!(0.03));
+    btc.liquidation_discount = dec!(0.01);
+    btc.slippage_bps_per_notional = dec!(0.00001);
+    let mut eth = Market::new("ETH-PERP".parse().unwrap(), dec!(0.10), dec!(0.05));
+    eth.liquidation_discount = dec!(0.02);
+    vec![btc, eth]
+}
+
+fn main() {
+    let accounts = ["alice", "bob", "carol", "dave", "erin"];
+    let market_ids = ["BTC-PERP", "ETH-PERP"];
+    let base_price = |market_id: &str| {
+        if market_id == "BTC-PERP" {
+            50_000
+        } else {
+            3_000
+        }
+    };
+
+    let config = EngineConfig {
+        liquidation_path: LiquidationPath::Keepers(vec!["keeper".parse().unwrap()]),
+        assert_solvency: true,
+        ..EngineConfig::default()
+    };
+    let mut engine = Engine::with_config(config.clone());
+    for market in markets() {
+        engine.add_market(market).unwrap();
+    }
+    engine.process(EventType::Deposit {
+        account_id: "keeper".parse().unwrap(),
+        amount: dec!(10000000),
+    });
+    for market_id in market_ids {
+        engine.process(EventType::MarkPriceUpdate {
+            market_id: market_id.parse().unwrap(),
+            price: Decimal::from(base_price(market_id)),
+        });
+    }
+
+    let mut rng = Lcg::new(42);
+    let mut interval_id = 0;
+    for _ in 0..5_000 {
+        let account_id: AccountId = rng.pick(&accounts).parse().unwrap();
+        let market_id: MarketId = rng.pick(&market_ids).parse().unwrap();
+        let mark = engine.state.markets[&market_id].mark_price;
+        let event = match rng.below(10) {
+            0 => EventType::Deposit {
+                account_id,
+                amount: Decimal::from(rng.below(50_000) + 1),
+            },
+            1 => EventType::Withdraw {
+                account_id,
+                amount: Decimal::from(rng.below(20_000) + 1),
+            },
+            2..=5 => {
+                // Thirds and sevenths make partial closes realize inexact fractions.
+                let size = Decimal::from(rng.below(30) + 1) / Decimal::from(3 + 4 * rng.below(2));
+                let quantity = if rng.below(2) == 0 { size } else { -size };
+                let offset = Decimal::from(rng.below(21)) - dec!(10);
+                EventType::TradeFill {
+                    account_id,
+                    market_id,
+                    quantity: quantity.round_dp(6),
+                    price: mark + offset,
+                    liquidity: None,
+                }
+            }
+            6..=8 => {
+                let step = Decimal::from(rng.below(401)) - dec!(200);
+                let price = mark * (Decimal::ONE + step / dec!(10000));
+                EventType::MarkPriceUpdate {
+                    market_id,
+                    price: price.round_dp(2).max(dec!(1)),
+                }
+            }
+            _ => {
+                interval_id += 1;
+                EventType::FundingRate {
+                    market_id,
+                    rate: (Decimal::from(rng.below(21)) - dec!(10)) / dec!(100000),
+                    interval_id,
+                }
+            }
+        };
+        engine.process(event);
+    }
+
+    let live = engine.solvency();
+    assert!(live.is_balanced(), "live books do not balance: {live:?}");
+
+    let replayed = Engine::replay_with(
+        ReplayOptions {
+            config,
+            ..ReplayOptions::default()
+        },
+        &engine.event_log,
+        markets(),
+    );
+    assert_eq!(replayed.state, engine.state);
+    assert_eq!(&replayed.metrics, engine.metrics());
+    let report = state::solvency(&replayed.state, &replayed.metrics);
+    assert!(
+        report.is_balanced(),
+        "replayed books do not balance: {report:?}"
+    );
+
+    let liquidations = engine
+        .event_log
+        .iter()
+        .filter(|e| {
+            matches!(
+                e.event_type,
+                EventType::LiquidationFill { .. } | EventType::LiquidationTakeover { .. }
+            )
+        })
+        .count();
+    println!(
+        "{} events, {} liquidation steps; {}",
+        engine.event_log.len(),
+        liquidations,
+        serde_json::to_string(&report).unwrap()
+    );
+}
@@ -0,0 +1,144 @@
+// Run a long-lived engine with a bounded in-memory log: every event goes to a spill
+// file, memory keeps the recent tail, and history queries and recovery read across
+// the boundary.
+
+use cross_margin_engine::prelude::*;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+fn markets() -> Vec<Market> {
+    vec![Market::new(
+        "BTC-PERP".parse().unwrap(),
+        dec!(0.05),
+        dec!(0.03),
+    )]
+}
+
+fn main() -> Result<(), EngineError> {
+    let path = std::env::temp_dir().join("cross-margin-engine-spill.jsonl");
+    let options = LogStoreOptions {
+        memory_capacity: 8,
+        flush: FlushPolicy::EveryEvent,
+    };
+
+    let mut engine = Engine::new();
+    for market in markets() {
+        engine.add_market(market).unwrap();
+    }
+    engine.set_log_store(LogStore::create(&path, options)?)?;
+
+    engine.process(EventType::Deposit {
+        account_id: "alice".parse().unwrap(),
+        amount: dec!(100000),
+    });
+    for tick in 0..50 {
+        engine.process(EventType::MarkPriceUpdate {
+            market_id: "BTC-PERP".parse().unwrap(),
+            price: dec!(50000) + Decimal::from(tick),
+        });
+    }
+    engine.process(EventType::TradeFill {
+        account_id: "alice".parse().unwrap(),
+        market_id: "BTC-PERP".parse().unwrap(),
+        quantity: dec!(1),
+        price: dec!(50049),
+        liquidity: None,
+    });
+
+    let history: Vec<Event> = engine.history()?.collect::<Result<_, _>>()?;
+    let alice: Vec<u64> = engine
+        .events_for_account("alice")?
+        .map(|event| event.map(|e| e.sequence))
+        .collect::<Result<_, _>>()?;
+    println!(
+        "{} events in memory, {} in history; alice appears at {:?}",
+        engine.event_log.len(),
+        history.len(),
+        alice
+    );
+
+    let replayed = Engine::replay_with_fallible(
+        ReplayOptions {
+            config: engine.config().clone(),
+            ..ReplayOptions::default()
+        },
+        engine.history()?,
+        markets(),
+    );
+    assert_eq!(replayed.state, engine.state);
+
+    let recovered = Engine::recover(&path, markets(), options)?;
+    assert_eq!(recovered.state, engine.state);
+    assert_eq!(recovered.next_sequence(), engine.next_sequence());
+    println!("recovered at seq {}", recovered.next_sequence());
+
+    if cfg!(target_os = "linux") {
+        full_disk()?;
+    }
+    Ok(())
+}
+
+/// A store on `/dev/full`, where every write fails as on a full disk: the failed
+/// append is reported rather than panicking, and the engine takes nothing after it.
+fn full_disk() -> Result<(), EngineError> {
+    let mut engine = Engine::new();
+    for market in markets() {
+        engine.add_market(market).unwrap();
+    }
+    engine.set_log_store(LogStore::create("/dev/full", LogStoreOptions::default())?)?;
+
+    let deposit = EventType::Deposit {
+        account_id: "alice".parse().unwrap(),
+        amount: dec!(100000),
+    };
+    // The config marker logged ahead of the first event is the first append.
+    let outcome = engine.process(deposit.clone());
+    let ProcessOutcome::LogStoreFailed { sequence, error } = &outcome else {
+        panic!("expected a log store failure, got {outcome:?}");
+    };
+    assert_eq!(*sequence, 1);
+    assert!(error.contains("/dev/full"), "{error}");
+    assert!(matches!(
+        outcome.clone().into_result(),
+        Err(EngineError::LogStoreFailed { sequence: 1, .. })
+    ));
+
+    // Every later submission is refused the same way, without being applied.
+    let state = engine.state.clone();
+    assert_eq!(engine.process(deposit), outcome);
+    assert_eq!(engine.state, state);
+    assert!(engine.event_log.is_empty());
+    let added = engine.add_market(Market::new(
+        "ETH-PERP".parse().unwrap(),
+        dec!(0.10),
+        dec!(0.05),
+    ));
+    assert!(matches!(
+        added,
+        Err(MarketError::LogStoreFailed { sequence: 1, .. })
+    ));
+    let response = engine.handle(
+        r#"{"command":"Process","event":{"type":"Deposit","account_id":"bob","amount":"10"}}"#,
+    );
+    assert!(response.contains("LogStore"), "{response}");
+    println!("full disk: refused from seq {sequence}: {error}");
+
+    // Buffered appends only fail once memory is trimmed and the buffer is flushed.
+    let mut engine = Engine::new();
+    let options = LogStoreOptions {
+        memory_capacity: 1,
+        flush: FlushPolicy::EveryN(1_000),
+    };
+    engine.set_log_store(LogStore::create("/dev/full", options)?)?;
+    let outcome = engine.process(EventType::Deposit {
+        account_id: "alice".parse().unwrap(),
+        amount: dec!(100000),
+    });
+    let ProcessOutcome::LogStoreFailed { sequence, error } = &outcome else {
+        panic!("expected a log store failure, got {outcome:?}");
+    };
+    assert_eq!(*sequence, 2);
+    assert!(error.starts_with("failed to flush"), "{error}");
+    println!("full disk, buffered: refused from seq {sequence}: {error}");
+    Ok(())
+}
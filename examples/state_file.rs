@@ -0,0 +1,171 @@
+// Save an engine's state to JSON and load it back: a state with a bankruptcy
+// deficit, funding entries, a closed market and a hedge pair, and the final state of
+// every scenario, must come back equal. Then check that bad files are refused.
+
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::scenario;
+use rust_decimal_macros::dec;
+
+fn eventful_state() -> State {
+    let mut engine = Engine::new();
+    engine
+        .add_market(Market::new(
+            "BTC-PERP".parse().unwrap(),
+            dec!(0.05),
+            dec!(0.03),
+        ))
+        .unwrap();
+    engine
+        .add_market(Market::new(
+            "ETH-PERP".parse().unwrap(),
+            dec!(0.10),
+            dec!(0.05),
+        ))
+        .unwrap();
+    engine
+        .add_market(Market::new(
+            "ETH-0627".parse().unwrap(),
+            dec!(0.10),
+            dec!(0.05),
+        ))
+        .unwrap();
+
+    let events = [
+        EventType::MarkPriceUpdate {
+            market_id: "BTC-PERP".parse().unwrap(),
+            price: dec!(50000),
+        },
+        EventType::MarkPriceUpdate {
+            market_id: "ETH-PERP".parse().unwrap(),
+            price: dec!(3000),
+        },
+        EventType::MarkPriceUpdate {
+            market_id: "ETH-0627".parse().unwrap(),
+            price: dec!(3010),
+        },
+        EventType::HedgePairAdded {
+            market_a: "ETH-PERP".parse().unwrap(),
+            market_b: "ETH-0627".parse().unwrap(),
+            offset_fraction: dec!(0.5),
+        },
+        EventType::Deposit {
+            account_id: "alice".parse().unwrap(),
+            amount: dec!(5000),
+        },
+        EventType::Deposit {
+            account_id: "bob".parse().unwrap(),
+            amount: dec!(100000),
+        },
+        EventType::AccountMetadata {
+            account_id: "bob".parse().unwrap(),
+            key: "desk".into(),
+            value: "rates".into(),
+        },
+        EventType::TradeFill {
+            account_id: "alice".parse().unwrap(),
+            market_id: "BTC-PERP".parse().unwrap(),
+            quantity: dec!(1.5),
+            price: dec!(50000),
+            liquidity: None,
+        },
+        EventType::TradeFill {
+            account_id: "bob".parse().unwrap(),
+            market_id: "ETH-PERP".parse().unwrap(),
+            quantity: dec!(-10),
+            price: dec!(3000),
+            liquidity: None,
+        },
+        EventType::TradeFill {
+            account_id: "bob".parse().unwrap(),
+            market_id: "ETH-0627".parse().unwrap(),
+            quantity: dec!(7.25),
+            price: dec!(3010),
+            liquidity: None,
+        },
+        EventType::FundingUpdate {
+            market_id: "ETH-PERP".parse().unwrap(),
+            new_cumulative_index: dec!(1.333),
+        },
+        // Alice goes through zero: a deficit, and with no insurance it stays owed.
+        EventType::MarkPriceUpdate {
+            market_id: "BTC-PERP".parse().unwrap(),
+            price: dec!(45000),
+        },
+        EventType::SessionClose {
+            market_id: "ETH-0627".parse().unwrap(),
+        },
+    ];
+    for (i, event) in events.into_iter().enumerate() {
+        let submission = Submission {
+            idempotency_key: Some(format!("order-{i}")),
+            timestamp: Some(1_700_000_000_000 + i as u64),
+        };
+        engine.process_with(event, submission);
+    }
+
+    let state = engine.state;
+    assert!(state.accounts["alice"].bankruptcy_deficit > rust_decimal::Decimal::ZERO);
+    assert!(!state.accounts["bob"].last_funding.is_empty());
+    assert!(state.markets["ETH-0627"].session_closed);
+    state
+}
+
+fn main() {
+    let state = eventful_state();
+    let json = state.to_json();
+    let loaded = State::from_json(&json).unwrap();
+    assert_eq!(loaded, state);
+    assert_eq!(loaded.to_json(), json, "a second save is byte-identical");
+    println!("eventful state: {} bytes, round-trips", json.len());
+
+    let mut paths: Vec<_> = std::fs::read_dir("scenarios")
+        .expect("run from the repository root")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    paths.sort();
+    for path in &paths {
+        let state = scenario::run(&scenario::load(path).unwrap())
+            .unwrap()
+            .engine
+            .state;
+        assert_eq!(
+            State::from_json(&state.to_json()).unwrap(),
+            state,
+            "{}",
+            path.display()
+        );
+    }
+    println!("{} scenario end states round-trip", paths.len());
+
+    let mut value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    value["schema_version"] = 1.into();
+    let err = State::from_json(&value.to_string()).unwrap_err();
+    assert!(
+        matches!(err, StateLoadError::SchemaVersion { found: 1, .. }),
+        "{err}"
+    );
+
+    value.as_object_mut().unwrap().remove("schema_version");
+    assert!(matches!(
+        State::from_json(&value.to_string()),
+        Err(StateLoadError::Parse(_))
+    ));
+
+    let mut value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    let markets = value["markets"].as_object_mut().unwrap();
+    markets.remove("ETH-PERP");
+    markets.remove("ETH-0627");
+    let err = State::from_json(&value.to_string()).unwrap_err();
+    let StateLoadError::UnknownMarkets(unknown) = &err else {
+        panic!("{err}")
+    };
+    assert_eq!(unknown.len(), 2, "both of bob's positions are listed");
+    println!("{err}");
+
+    let mut value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    value["markets"]["BTC-PERP"]["maintenance_margin_fraction"] = "0.5".into();
+    let err = State::from_json(&value.to_string()).unwrap_err();
+    assert!(matches!(err, StateLoadError::InvalidMarket(_)), "{err}");
+    println!("{err}");
+}
@@ -0,0 +1,208 @@
+// Tracing instrumentation, built with `--features trace`. A capturing subscriber
+// records the spans and events of the demo's liquidation sequence: alice's 10 BTC
+// long passes its trade check with its post-trade figures, and the mark at 41,000
+// opens a `check_and_liquidate` span for her, selects the position from its
+// candidate notionals, routes it to the engine close and applies the fill under it.
+// Bob's second ETH long is refused with its numbers, and the demo's funding update
+// reports his settlement.
+
+use cross_margin_engine::prelude::*;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+/// Each entered span and each event, one line each, indented by span depth.
+#[derive(Default)]
+struct Trace {
+    spans: Vec<String>,
+    stack: Vec<usize>,
+    lines: Vec<String>,
+}
+
+#[derive(Clone, Default)]
+struct Capture(Arc<Mutex<Trace>>);
+
+/// `name field=value ...`, with an event's message as its name.
+#[derive(Default)]
+struct Line(String);
+
+impl Visit for Line {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record_debug(field, &format_args!("{value}"));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let sep = if self.0.is_empty() { "" } else { " " };
+        if field.name() == "message" {
+            self.0 = format!("{value:?}{sep}{}", self.0);
+        } else {
+            self.0.push_str(&format!("{sep}{}={value:?}", field.name()));
+        }
+    }
+}
+
+impl Subscriber for Capture {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let mut line = Line(span.metadata().name().to_string());
+        span.record(&mut line);
+        let mut trace = self.0.lock().unwrap();
+        trace.spans.push(line.0);
+        Id::from_u64(trace.spans.len() as u64)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut line = Line::default();
+        event.record(&mut line);
+        let mut trace = self.0.lock().unwrap();
+        let indent = "  ".repeat(trace.stack.len());
+        trace.lines.push(format!("{indent}{}", line.0));
+    }
+
+    fn enter(&self, span: &Id) {
+        let mut trace = self.0.lock().unwrap();
+        let index = span.into_u64() as usize - 1;
+        let line = format!("{}{}", "  ".repeat(trace.stack.len()), trace.spans[index]);
+        trace.lines.push(line);
+        trace.stack.push(index);
+    }
+
+    fn exit(&self, _: &Id) {
+        self.0.lock().unwrap().stack.pop();
+    }
+}
+
+impl Capture {
+    /// Assert the lines captured since the last call, and clear them.
+    fn expect(&self, expected: &[&str]) {
+        let lines = std::mem::take(&mut self.0.lock().unwrap().lines);
+        assert_eq!(lines, expected);
+    }
+
+    /// Discard the lines captured since the last call.
+    fn clear(&self) {
+        self.0.lock().unwrap().lines.clear();
+    }
+}
+
+fn mark(market_id: &str, price: Decimal) -> EventType {
+    EventType::MarkPriceUpdate {
+        market_id: market_id.parse().unwrap(),
+        price,
+    }
+}
+
+fn trade(account_id: &str, market_id: &str, quantity: Decimal, price: Decimal) -> EventType {
+    EventType::TradeFill {
+        account_id: account_id.parse().unwrap(),
+        market_id: market_id.parse().unwrap(),
+        quantity,
+        price,
+        liquidity: None,
+    }
+}
+
+fn main() {
+    let capture = Capture::default();
+    let mut engine = Engine::new();
+    engine
+        .add_market(Market::new(
+            "BTC-PERP".parse().unwrap(),
+            dec!(0.05),
+            dec!(0.03),
+        ))
+        .unwrap();
+    engine
+        .add_market(Market::new(
+            "ETH-PERP".parse().unwrap(),
+            dec!(0.10),
+            dec!(0.05),
+        ))
+        .unwrap();
+
+    tracing::subscriber::with_default(capture.clone(), || {
+        // Sequence 1 is the config marker. Neither event can breach anyone's margin.
+        engine.process(EventType::Deposit {
+            account_id: "alice".parse().unwrap(),
+            amount: dec!(100000),
+        });
+        engine.process(mark("BTC-PERP", dec!(50000)));
+        capture.expect(&[
+            "process sequence=2 kind=Deposit",
+            "  apply_event sequence=2 kind=Deposit",
+            "process sequence=3 kind=MarkPriceUpdate",
+            "  apply_event sequence=3 kind=MarkPriceUpdate",
+        ]);
+
+        engine.process(trade("alice", "BTC-PERP", dec!(10), dec!(50000)));
+        capture.expect(&[
+            "process sequence=4 kind=TradeFill",
+            "  apply_event sequence=4 kind=TradeFill",
+            "    check_trade account_id=alice market_id=BTC-PERP quantity=10 price=50000",
+            "      post-trade portfolio equity=100000 initial_margin=25000.00 notional=500000",
+            "      trade check accepted=true",
+            "  check_and_liquidate account_id=alice sequence=4",
+        ]);
+
+        // At 42,000 alice has 20,000 against 12,600 of MM: scanned, nothing selected.
+        engine.process(mark("BTC-PERP", dec!(42000)));
+        capture.expect(&[
+            "process sequence=5 kind=MarkPriceUpdate",
+            "  apply_event sequence=5 kind=MarkPriceUpdate",
+            "  check_and_liquidate account_id=alice sequence=5",
+        ]);
+
+        // At 41,000 she has 10,000 against 12,300, and the fill is caused by the mark.
+        engine.process(mark("BTC-PERP", dec!(41000)));
+        capture.expect(&[
+            "process sequence=6 kind=MarkPriceUpdate",
+            "  apply_event sequence=6 kind=MarkPriceUpdate",
+            "  check_and_liquidate account_id=alice sequence=6",
+            "    liquidation selected market_id=BTC-PERP close_quantity=-10 \
+             strategy=LargestNotionalFirst candidates=BTC-PERP=410000",
+            "    liquidation route route=engine price=41000",
+            "    apply_event sequence=7 kind=LiquidationFill caused_by=6",
+        ]);
+
+        engine.process(EventType::Deposit {
+            account_id: "bob".parse().unwrap(),
+            amount: dec!(10000),
+        });
+        engine.process(mark("ETH-PERP", dec!(3000)));
+        engine.process(trade("bob", "ETH-PERP", dec!(20), dec!(3000)));
+        capture.clear();
+        engine.process(trade("bob", "ETH-PERP", dec!(20), dec!(3000)));
+        capture.expect(&[
+            "process sequence=11 kind=TradeFill",
+            "  apply_event sequence=11 kind=TradeFill",
+            "    check_trade account_id=bob market_id=ETH-PERP quantity=20 price=3000",
+            "      post-trade portfolio equity=10000 initial_margin=12000.00 notional=120000",
+            "      trade check accepted=false reason=Insufficient margin: equity 10000 < IM required 12000.00",
+        ]);
+
+        engine.process(EventType::FundingUpdate {
+            market_id: "ETH-PERP".parse().unwrap(),
+            new_cumulative_index: dec!(1.50),
+        });
+        capture.expect(&[
+            "process sequence=13 kind=FundingUpdate",
+            "  apply_event sequence=13 kind=FundingUpdate",
+            "    funding settlement market_id=ETH-PERP old_index=0 new_index=1.50 holders=1",
+            "    funding settled account_id=bob market_id=ETH-PERP amount=-30.00",
+            "  check_and_liquidate account_id=bob sequence=13",
+        ]);
+    });
+
+    println!("alice liquidated at seq 7 under check_and_liquidate of the mark at seq 6; trace structure as expected");
+}
@@ -0,0 +1,132 @@
+// Track an account's turnover over the last minute of the log clock and the fee tier
+// it selects. Three fills 20 seconds apart take alice up two tiers; marks with later
+// timestamps then age them out one by one, down to the base rate. Check the totals
+// after every event, that each snapshot carries them, and that replay from genesis
+// and a resume from the snapshot after the third fill rebuild the same window.
+
+use cross_margin_engine::prelude::*;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+const T0: u64 = 1_700_000_000_000;
+
+fn markets() -> Vec<Market> {
+    vec![Market::new(
+        "BTC-PERP".parse().unwrap(),
+        dec!(0.05),
+        dec!(0.03),
+    )]
+}
+
+fn config() -> EngineConfig {
+    let tier = |min_turnover, rate| FeeTier { min_turnover, rate };
+    EngineConfig {
+        trade_stats: Some(TradeStatistics {
+            window: StatsWindow::Millis(60_000),
+            fee_tiers: vec![
+                tier(dec!(0), dec!(0.0005)),
+                tier(dec!(100000), dec!(0.0003)),
+                tier(dec!(200000), dec!(0.0002)),
+            ],
+        }),
+        ..EngineConfig::default()
+    }
+}
+
+fn at(seconds: u64) -> Submission {
+    Submission {
+        timestamp: Some(T0 + seconds * 1_000),
+        ..Submission::default()
+    }
+}
+
+fn main() {
+    let mut engine = Engine::builder().config(config()).build();
+    for market in markets() {
+        engine.add_market(market).unwrap();
+    }
+    let mark = EventType::MarkPriceUpdate {
+        market_id: "BTC-PERP".parse().unwrap(),
+        price: dec!(50000),
+    };
+    let buy = |quantity| EventType::TradeFill {
+        account_id: "alice".parse().unwrap(),
+        market_id: "BTC-PERP".parse().unwrap(),
+        quantity,
+        price: dec!(50000),
+        liquidity: None,
+    };
+    let deposit = EventType::Deposit {
+        account_id: "alice".parse().unwrap(),
+        amount: dec!(1000000),
+    };
+    engine.process_with(mark.clone(), at(0));
+    engine.process_with(deposit, at(0));
+    assert_eq!(engine.account_stats("alice"), None);
+    assert_eq!(engine.fee_rate("alice"), Some(dec!(0.0005)));
+
+    // (seconds, event, turnover, trades, fee rate after it)
+    let timeline = [
+        (0, buy(dec!(1)), dec!(50000), 1, dec!(0.0005)),
+        (20, buy(dec!(1)), dec!(100000), 2, dec!(0.0003)),
+        (40, buy(dec!(2)), dec!(200000), 3, dec!(0.0002)),
+        // A minute after the first fill it leaves the window.
+        (60, mark.clone(), dec!(150000), 2, dec!(0.0003)),
+        (79, mark.clone(), dec!(150000), 2, dec!(0.0003)),
+        (80, mark.clone(), dec!(100000), 1, dec!(0.0003)),
+        (100, mark.clone(), dec!(0), 0, dec!(0.0005)),
+    ];
+    let mut third_fill = 0;
+    for (seconds, event_type, turnover, trades, rate) in timeline {
+        let ProcessOutcome::Accepted { sequence } = engine.process_with(event_type, at(seconds))
+        else {
+            panic!("every event in the timeline is valid")
+        };
+        let stats = engine.account_stats("alice").unwrap();
+        println!("t+{seconds:>3}s: turnover {turnover:>6}, fee rate {rate}");
+        assert_eq!((stats.turnover, stats.trades), (turnover, trades));
+        assert_eq!(engine.fee_rate("alice"), Some(rate));
+        // A fill's fee record follows it, so its snapshot need not be the last.
+        let snapshot = engine
+            .snapshots
+            .iter()
+            .find(|s| s.after_sequence == sequence)
+            .unwrap();
+        assert_eq!(&snapshot.accounts["alice"].stats, stats);
+        if seconds == 40 {
+            third_fill = sequence;
+        }
+    }
+    assert_eq!(
+        engine.account_stats("alice").unwrap(),
+        &AccountStats::default()
+    );
+    assert_eq!(
+        engine.state.accounts["alice"].positions["BTC-PERP"].quantity,
+        dec!(4)
+    );
+
+    // Replay rebuilds the window from the fills and the clock alone.
+    let replayed = Engine::replay_verified(&engine.event_log, markets(), config()).unwrap();
+    assert_eq!(replayed.state, engine.state);
+    assert_eq!(replayed.snapshots, engine.snapshots);
+
+    // The snapshot after the third fill holds the three fills the rest still evicts.
+    let snapshot = engine
+        .snapshots
+        .iter()
+        .find(|s| s.after_sequence == third_fill)
+        .unwrap();
+    assert_eq!(snapshot.trade_stats["alice"].fills.len(), 3);
+    let options = ReplayOptions {
+        config: config(),
+        ..ReplayOptions::default()
+    };
+    let resumed =
+        Engine::resume_from_snapshot_with(options, snapshot, markets(), &engine.event_log).unwrap();
+    assert_eq!(resumed.state, engine.state);
+    assert_eq!(
+        resumed.state.trade_stats["alice"].totals.turnover,
+        Decimal::ZERO
+    );
+}
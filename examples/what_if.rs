@@ -0,0 +1,140 @@
+// Ask what-if questions of a live engine without touching it. Each question runs on
+// a fork: a dry-run engine seeded with a copy of the live state. Stress the book with
+// joint BTC and ETH moves and check exactly who each one liquidates, preview two
+// trades at the edge of carol's initial margin, and stress the one that passes. The
+// live engine ends exactly as it was, and no fork's log can be written as a
+// real one.
+
+use cross_margin_engine::jsonl::{self, WriteOptions};
+use cross_margin_engine::margin;
+use cross_margin_engine::prelude::*;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A dry-run copy of `live` that continues its sequence.
+fn fork(live: &Engine) -> Engine {
+    Engine::from_state(live.state.clone(), live.next_sequence(), EngineMode::DryRun)
+}
+
+/// The accounts `engine` would liquidate if BTC and ETH moved by these fractions.
+fn stress(mut engine: Engine, btc: Decimal, eth: Decimal) -> BTreeSet<AccountId> {
+    let shocked = |market_id: &str, shock| {
+        let mark = engine.state.markets[market_id].mark_price;
+        (market_id.parse().unwrap(), mark * (Decimal::ONE + shock))
+    };
+    let updates = BTreeMap::from([shocked("BTC-PERP", btc), shocked("ETH-PERP", eth)]);
+    assert!(engine
+        .process(EventType::MarkPriceBatch { updates })
+        .is_accepted());
+    engine
+        .event_log
+        .iter()
+        .filter_map(|e| match &e.event_type {
+            EventType::LiquidationFill { account_id, .. } => Some(account_id.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn trade(account_id: &str, market_id: &str, quantity: Decimal, price: Decimal) -> EventType {
+    EventType::TradeFill {
+        account_id: account_id.parse().unwrap(),
+        market_id: market_id.parse().unwrap(),
+        quantity,
+        price,
+        liquidity: None,
+    }
+}
+
+fn main() {
+    let mut live = Engine::new();
+    live.add_market(Market::new(
+        "BTC-PERP".parse().unwrap(),
+        dec!(0.05),
+        dec!(0.03),
+    ))
+    .unwrap();
+    live.add_market(Market::new(
+        "ETH-PERP".parse().unwrap(),
+        dec!(0.10),
+        dec!(0.05),
+    ))
+    .unwrap();
+    let marks = BTreeMap::from([
+        ("BTC-PERP".parse().unwrap(), dec!(50000)),
+        ("ETH-PERP".parse().unwrap(), dec!(3000)),
+    ]);
+    live.process(EventType::MarkPriceBatch { updates: marks });
+    for (account_id, collateral, market_id, quantity, price) in [
+        ("alice", dec!(40000), "BTC-PERP", dec!(10), dec!(50000)),
+        ("bob", dec!(40000), "ETH-PERP", dec!(-100), dec!(3000)),
+        ("carol", dec!(20000), "BTC-PERP", dec!(2), dec!(50000)),
+    ] {
+        live.process(EventType::Deposit {
+            account_id: account_id.parse().unwrap(),
+            amount: collateral,
+        });
+        assert!(live
+            .process(trade(account_id, market_id, quantity, price))
+            .is_accepted());
+    }
+    let before = live.state.clone();
+    let logged = live.event_log.clone();
+
+    // Alice is liquidated under a BTC mark of 47,423 (−5.2%), bob over an ETH mark of
+    // 3,239 (+7.9%).
+    for (btc, eth, expected) in [
+        (dec!(-0.05), dec!(0.05), &[][..]),
+        (dec!(-0.10), dec!(0), &["alice"][..]),
+        (dec!(-0.10), dec!(0.10), &["alice", "bob"][..]),
+    ] {
+        let liquidated = stress(fork(&live), btc, eth);
+        println!("BTC {btc:+}, ETH {eth:+}: liquidates {liquidated:?}");
+        assert!(liquidated.iter().eq(expected.iter()));
+    }
+
+    // 6 more BTC bring carol's IM to exactly her 20,000 of equity; 7 would exceed it.
+    let mut previews = Vec::new();
+    for quantity in [dec!(6), dec!(7)] {
+        let mut preview = fork(&live);
+        let outcome = preview.process(trade("carol", "BTC-PERP", quantity, dec!(50000)));
+        let account = &preview.state.accounts["carol"];
+        let im = margin::initial_margin_required(account, &preview.state);
+        match &outcome {
+            ProcessOutcome::Accepted { .. } => println!("carol buys {quantity}: ok, IM {im}"),
+            ProcessOutcome::Rejected { reason, .. } => println!("carol buys {quantity}: {reason}"),
+            ProcessOutcome::Duplicate { .. } => unreachable!("no idempotency key"),
+            ProcessOutcome::Suppressed { .. } => unreachable!("no rejection throttle"),
+            ProcessOutcome::SinkFailed { .. } => unreachable!("no snapshot sink"),
+            ProcessOutcome::LogStoreFailed { .. } => unreachable!("no log store"),
+        }
+        previews.push((outcome, im, preview));
+    }
+    let (accepted, im, preview) = previews.remove(0);
+    assert!(accepted.is_accepted());
+    assert_eq!(im, dec!(20000));
+    let (rejected, im, _) = previews.remove(0);
+    assert!(matches!(
+        rejected,
+        ProcessOutcome::Rejected {
+            reason: RejectReason::Trade(_),
+            ..
+        }
+    ));
+    assert_eq!(im, dec!(5000), "a rejected trade leaves the fork as it was");
+
+    // At full IM carol would not survive a drop she survives today.
+    assert!(!stress(fork(&live), dec!(-0.10), dec!(0)).contains("carol"));
+    assert!(stress(preview, dec!(-0.10), dec!(0)).contains("carol"));
+
+    // None of it reached the live engine, and a fork's log is marked as simulated.
+    assert_eq!(live.state, before);
+    assert_eq!(live.event_log, logged);
+    let mut probe = fork(&live);
+    probe.process(trade("carol", "BTC-PERP", dec!(1), dec!(50000)));
+    assert!(probe.event_log.iter().all(|e| e.dry_run));
+    let path = std::env::temp_dir().join("cross-margin-engine-what-if.jsonl");
+    let written = jsonl::write_jsonl(&path, &probe.event_log, WriteOptions::default());
+    assert!(matches!(written, Err(EngineError::DryRunLog { .. })));
+}
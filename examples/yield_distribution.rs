@@ -0,0 +1,211 @@
+// Venue yield on collateral, with conservation checked. Two hundred accounts with
+// balances down to the last of 8 decimals, spread over three pools, a few of them
+// negative, receive five distributions at rates that rarely divide evenly. In every
+// pool and interval the payments and the rounding residual add up to the rate times
+// the pool's positive balances exactly, the residual is never negative and never a
+// whole unit of collateral precision per account, and the venue's interest revenue
+// is down by exactly what was paid. A repeated interval pays nothing. Under
+// `YieldBasis::AllBalances` a negative balance is charged instead. Both logs replay
+// and regenerate to the live state, the statement shows each payment as yield, and
+// the attribution report reconciles with the yield as its own component.
+
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::regenerate::diff_logs;
+use cross_margin_engine::report::{self, LedgerKind};
+use cross_margin_engine::rng::Lcg;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::BTreeMap;
+
+const ACCOUNTS: u64 = 200;
+const POOLS: [&str; 3] = ["default", "pool-a", "pool-b"];
+const RATES: [Decimal; 5] = [
+    dec!(0.00123456),
+    dec!(0.0000333),
+    dec!(0.000987654321),
+    dec!(0.01),
+    dec!(0.0000001),
+];
+
+fn market() -> Market {
+    Market::new("BTC-PERP".parse().unwrap(), dec!(0.05), dec!(0.03))
+}
+
+fn account_id(i: u64) -> AccountId {
+    format!("acct-{i:03}").parse().unwrap()
+}
+
+/// An engine holding `ACCOUNTS` balances of up to 100,000 at 8 decimals, every
+/// seventh of them negative through a `StateImport`.
+fn funded(yield_basis: YieldBasis) -> Engine {
+    let mut engine = Engine::builder().yield_basis(yield_basis).build();
+    engine.add_market(market()).unwrap();
+    let mut rng = Lcg::new(42);
+    for i in 0..ACCOUNTS {
+        let balance = Decimal::new(rng.below(10_000_000_000_000) as i64 + 1, 8);
+        let pool_id = rng.pick(&POOLS).to_string();
+        let event = EventType::StateImport {
+            account_id: account_id(i),
+            pool_id,
+            collateral: if i % 7 == 0 { -balance } else { balance },
+            positions: Vec::new(),
+        };
+        assert!(engine.process(event).is_accepted());
+    }
+    engine
+}
+
+/// Each pool's sum of `rate` times its accounts' balances in the yield basis.
+fn owed(engine: &Engine, rate: Decimal) -> BTreeMap<PoolId, Decimal> {
+    let all_balances = engine.config().yield_basis == YieldBasis::AllBalances;
+    let mut owed = BTreeMap::new();
+    for account in engine.state.accounts.values() {
+        if account.collateral() > Decimal::ZERO
+            || (all_balances && account.collateral() < Decimal::ZERO)
+        {
+            *owed.entry(account.pool_id.clone()).or_insert(Decimal::ZERO) +=
+                account.collateral() * rate;
+        }
+    }
+    owed
+}
+
+/// Distribute `rate` for `interval_id` and check it conserves: per pool, payments
+/// and residual add up to what was owed, and revenue falls by the payments.
+fn distribute(engine: &mut Engine, interval_id: u64, rate: Decimal) {
+    let owed = owed(engine, rate);
+    let revenue_before = engine.state.interest_revenue.clone();
+    let start = engine.event_log.len();
+    assert!(engine
+        .process(EventType::YieldDistribution { rate, interval_id })
+        .is_accepted());
+
+    let mut paid: BTreeMap<PoolId, Decimal> = BTreeMap::new();
+    let mut residuals: BTreeMap<PoolId, Decimal> = BTreeMap::new();
+    let mut payments = 0;
+    for event in &engine.event_log[start..] {
+        match &event.event_type {
+            EventType::YieldPaid {
+                account_id, amount, ..
+            } => {
+                let pool_id = engine.state.accounts[account_id].pool_id.clone();
+                *paid.entry(pool_id).or_insert(Decimal::ZERO) += amount;
+                payments += 1;
+            }
+            EventType::YieldResidual {
+                pool_id, amount, ..
+            } => {
+                assert!(*amount > Decimal::ZERO, "{pool_id}: residual {amount}");
+                residuals.insert(pool_id.clone(), *amount);
+            }
+            _ => {}
+        }
+    }
+    assert!(payments > 0);
+    for (pool_id, owed) in &owed {
+        let paid = paid.get(pool_id).copied().unwrap_or_default();
+        let residual = residuals.get(pool_id).copied().unwrap_or_default();
+        assert_eq!(
+            paid + residual,
+            *owed,
+            "{pool_id} at interval {interval_id}"
+        );
+        // Each account's rounding keeps back less than one unit of the 8th decimal.
+        let accounts = engine
+            .state
+            .accounts
+            .values()
+            .filter(|a| a.pool_id == *pool_id)
+            .count();
+        assert!(residual < Decimal::new(accounts as i64, 8));
+
+        let before = revenue_before.get(pool_id).copied().unwrap_or_default();
+        assert_eq!(before - engine.state.interest_revenue[pool_id], paid);
+    }
+}
+
+/// Replay and regeneration of `engine`'s log both reproduce its state.
+fn check_log(engine: &Engine) {
+    let markets = || vec![market()];
+    let replayed =
+        Engine::replay_verified(&engine.event_log, markets(), engine.config().clone()).unwrap();
+    assert_eq!(replayed.state, engine.state);
+    let regenerated =
+        Engine::regenerate(&engine.event_log, markets(), engine.config().clone()).unwrap();
+    assert_eq!(diff_logs(&engine.event_log, &regenerated), None);
+}
+
+fn main() {
+    let mut engine = funded(YieldBasis::PositiveBalances);
+    let negative_before: Vec<Decimal> = (0..ACCOUNTS)
+        .step_by(7)
+        .map(|i| engine.state.accounts[&account_id(i)].collateral())
+        .collect();
+    for (interval_id, rate) in (1..).zip(RATES) {
+        distribute(&mut engine, interval_id, rate);
+    }
+    // Negative balances earn nothing under the default basis.
+    for (i, before) in (0..ACCOUNTS).step_by(7).zip(&negative_before) {
+        assert_eq!(engine.state.accounts[&account_id(i)].collateral(), *before);
+    }
+
+    // An interval pays once.
+    let state = engine.state.clone();
+    let outcome = engine.process(EventType::YieldDistribution {
+        rate: dec!(0.5),
+        interval_id: 1,
+    });
+    assert!(matches!(outcome, ProcessOutcome::Rejected { .. }));
+    assert_eq!(engine.state, state);
+
+    let solvency = engine.solvency();
+    assert_eq!(solvency.residual, Decimal::ZERO, "{solvency:?}");
+    check_log(&engine);
+
+    // acct-001's statement has its import and one yield line per distribution, and
+    // its attribution over the whole log puts the payments under yield.
+    let lines = report::statement(&engine.event_log, "acct-001", vec![market()]);
+    let yields: Vec<_> = lines
+        .iter()
+        .filter(|l| l.kind == LedgerKind::Yield)
+        .collect();
+    assert_eq!((lines.len(), yields.len()), (1 + RATES.len(), RATES.len()));
+    assert_eq!(
+        lines.last().unwrap().balance_after,
+        engine.state.accounts["acct-001"].collateral()
+    );
+    let paid: Decimal = yields.iter().map(|l| l.amount).sum();
+    let last = engine.event_log.last().unwrap().sequence;
+    let attribution = report::attribution(
+        &engine.event_log,
+        &engine.snapshots,
+        &account_id(1),
+        0,
+        last,
+    );
+    assert!(attribution.reconciled, "{attribution:?}");
+    assert_eq!(attribution.collateral_yield, paid);
+
+    // Under AllBalances the same rates charge negative balances, rounded away from
+    // zero, so the venue still keeps the residual.
+    let mut charged = funded(YieldBasis::AllBalances);
+    for (interval_id, rate) in (1..).zip(RATES) {
+        distribute(&mut charged, interval_id, rate);
+    }
+    for (i, before) in (0..ACCOUNTS).step_by(7).zip(&negative_before) {
+        assert!(charged.state.accounts[&account_id(i)].collateral() < *before);
+    }
+    assert_eq!(charged.solvency().residual, Decimal::ZERO);
+    check_log(&charged);
+
+    let total =
+        |engine: &Engine| -> Decimal { engine.state.interest_revenue.values().sum::<Decimal>() };
+    println!(
+        "{} distributions over {ACCOUNTS} accounts in {} pools conserve exactly; the venue paid {} \
+         on positive balances and {} net across all balances",
+        RATES.len(),
+        POOLS.len(),
+        -total(&engine),
+        -total(&charged)
+    );
+}
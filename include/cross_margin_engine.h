@@ -0,0 +1,31 @@
+/* C interface to cross-margin-engine (cargo feature `cffi`).
+ *
+ * Commands and responses are JSON; see `Command` and `Response` in src/command.rs.
+ * Strings returned by cme_handle belong to the caller: free them with
+ * cme_string_free. Engines from cme_new are freed with cme_free. An engine must
+ * not be used from two threads at once. */
+
+#ifndef CROSS_MARGIN_ENGINE_H
+#define CROSS_MARGIN_ENGINE_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct CmeEngine CmeEngine;
+
+/* config_json: an EngineConfig as JSON, or NULL for the default. NULL if invalid. */
+CmeEngine *cme_new(const char *config_json);
+
+/* Run one command; never NULL. */
+char *cme_handle(CmeEngine *engine, const char *command_json);
+
+void cme_string_free(char *s);
+
+void cme_free(CmeEngine *engine);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,273 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+use crate::config::{EngineConfig, LiquidationStrategy};
+use crate::decimal_str;
+use crate::engine::{Engine, ReplayOptions, Submission};
+use crate::error::EngineError;
+use crate::events::{self, Event, EventType};
+use crate::liquidation;
+use crate::rng::Lcg;
+use crate::snapshot::{Snapshot, SnapshotPolicy};
+use crate::state::State;
+use crate::types::{AccountId, Market};
+
+/// How much of the chosen position each liquidation fill closes.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum CloseSize {
+    /// The whole position, as the engine does.
+    Full,
+    /// `fraction` of the position on the first fill in a market in a cascade. If the
+    /// account is still liquidatable, later fills in that market close the rest.
+    Partial {
+        #[serde(with = "decimal_str")]
+        fraction: Decimal,
+    },
+}
+
+/// Which position to close next, and how much of it.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BacktestStrategy {
+    pub order: LiquidationStrategy,
+    pub close: CloseSize,
+}
+
+impl BacktestStrategy {
+    pub fn full(order: LiquidationStrategy) -> Self {
+        Self {
+            order,
+            close: CloseSize::Full,
+        }
+    }
+
+    /// Panics unless `fraction` is in (0, 1].
+    pub fn partial(order: LiquidationStrategy, fraction: Decimal) -> Self {
+        assert!(
+            fraction > Decimal::ZERO && fraction <= Decimal::ONE,
+            "partial close fraction must be in (0, 1], got {fraction}"
+        );
+        Self {
+            order,
+            close: CloseSize::Partial { fraction },
+        }
+    }
+}
+
+/// The price a simulated liquidation fill executes at.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum FillModel {
+    /// `liquidation::liquidation_price`: mark, moved against the account by the
+    /// market's slippage for the size of the close.
+    Engine,
+    /// Mark plus or minus a seeded draw of up to that same slippage, so a fill can
+    /// land on either side of mark. With zero slippage this is the mark.
+    Jittered,
+}
+
+/// What the liquidations of one history cost.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BacktestOutcome {
+    /// Accounts with at least one liquidation fill or takeover.
+    pub accounts_liquidated: BTreeSet<AccountId>,
+    pub liquidation_fills: usize,
+    /// Sum of `|quantity × price|` over those fills.
+    #[serde(with = "decimal_str")]
+    pub closed_notional: Decimal,
+    /// Every increase in an account's bankruptcy deficit, summed: the losses the
+    /// liquidations failed to contain, before insurance or repayment.
+    #[serde(with = "decimal_str")]
+    pub deficit_incurred: Decimal,
+    #[serde(with = "decimal_str")]
+    pub insurance_paid: Decimal,
+    /// Deficits charged to other accounts of the pool under
+    /// `ResidualDeficit::Socialize` or `SocializeEquity`.
+    #[serde(with = "decimal_str")]
+    pub loss_socialized: Decimal,
+    /// Bankruptcy deficits still owed after the last event.
+    #[serde(with = "decimal_str")]
+    pub deficit_outstanding: Decimal,
+    /// `*Rejected` records in the history. A simulated history can reject what the
+    /// recorded one accepted, e.g. a trade from an account it left with less equity.
+    pub rejections: usize,
+}
+
+/// A simulated history against the recorded one.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BacktestReport {
+    pub seed: u64,
+    pub strategy: BacktestStrategy,
+    pub fill_model: FillModel,
+    pub recorded: BacktestOutcome,
+    pub simulated: BacktestOutcome,
+}
+
+/// Replays a recorded log with a different liquidation strategy and fill model.
+///
+/// Every external event of the log is submitted again to a fresh engine under the
+/// log's config. The engine scans for liquidations exactly as it did live, but each
+/// liquidation event comes from the backtester: the next position under
+/// `strategy.order`, closed by `strategy.close`, at the `fill_model` price.
+/// Closes are always engine fills; keepers take nothing over. Deferral, bankruptcy
+/// and insurance payouts run as they do live. The seed fixes every jitter draw, so the
+/// same seed, log, markets and strategy always give the same report.
+#[derive(Debug, Clone)]
+pub struct Backtester {
+    seed: u64,
+    strategy: BacktestStrategy,
+    fill_model: FillModel,
+}
+
+impl Backtester {
+    pub fn new(seed: u64, strategy: BacktestStrategy, fill_model: FillModel) -> Self {
+        Self {
+            seed,
+            strategy,
+            fill_model,
+        }
+    }
+
+    /// Backtest `log`, recorded with `markets` registered. The log's `ConfigMarker`
+    /// gives the config (the default without one). Engine-generated events of the log
+    /// are not submitted: the simulation generates its own.
+    pub fn run(
+        &self,
+        log: &[impl AsRef<Event>],
+        markets: Vec<Market>,
+    ) -> Result<BacktestReport, EngineError> {
+        let log = &events::borrowed(log);
+        let config = match log.first().map(|e| &e.event_type) {
+            Some(EventType::ConfigMarker { config, .. }) => config.clone(),
+            _ => EngineConfig::default(),
+        };
+
+        let options = ReplayOptions {
+            config: config.clone(),
+            snapshot_policy: SnapshotPolicy::EveryEvent,
+            ..ReplayOptions::default()
+        };
+        let replayed = Engine::replay_with(options, log.iter().copied(), markets.clone());
+        let recorded = tally(log, &replayed.snapshots, &replayed.state);
+
+        let mut engine = Engine::with_config(EngineConfig {
+            snapshot_policy: SnapshotPolicy::EveryEvent,
+            ..config
+        });
+        for market in markets {
+            engine.add_market(market)?;
+        }
+        let closed_sessions = engine.config().closed_session_liquidation;
+        let (strategy, fill_model) = (self.strategy, self.fill_model);
+        let mut rng = Lcg::new(self.seed);
+        engine.set_liquidator(Box::new(move |state, account_id| {
+            let step = liquidation::plan_with_sessions(
+                state,
+                account_id,
+                strategy.order,
+                closed_sessions,
+            )?
+            .steps
+            .into_iter()
+            .next()?;
+            let market = &state.markets[&step.market_id];
+            let quantity = match strategy.close {
+                CloseSize::Partial { fraction }
+                    if !closed_before(state, account_id, &step.market_id) =>
+                {
+                    step.close_quantity * fraction
+                }
+                _ => step.close_quantity,
+            };
+            let price = liquidation::liquidation_price(market, quantity);
+            let price = match fill_model {
+                FillModel::Engine => price,
+                FillModel::Jittered => market.mark_price + (price - market.mark_price) * rng.unit(),
+            };
+            Some(EventType::LiquidationFill {
+                account_id: account_id.clone(),
+                market_id: step.market_id,
+                quantity,
+                price,
+            })
+        }));
+
+        for event in log {
+            if event.caused_by.is_some() || event.event_type.is_engine_generated() {
+                continue;
+            }
+            let submission = Submission {
+                idempotency_key: event.idempotency_key.clone(),
+                timestamp: event.timestamp,
+            };
+            engine.process_with(event.event_type.clone(), submission);
+        }
+        let simulated = tally(&engine.event_log, &engine.snapshots, &engine.state);
+
+        Ok(BacktestReport {
+            seed: self.seed,
+            strategy: self.strategy,
+            fill_model: self.fill_model,
+            recorded,
+            simulated,
+        })
+    }
+}
+
+/// Whether the current cascade already closed part of the account's position in
+/// `market_id`.
+fn closed_before(state: &State, account_id: &AccountId, market_id: &str) -> bool {
+    state
+        .liquidated_markets
+        .get(account_id)
+        .is_some_and(|markets| markets.contains(market_id))
+}
+
+/// Outcome of a history from its events, its snapshot after every applied event,
+/// and its final state.
+fn tally(log: &[impl AsRef<Event>], snapshots: &[Snapshot], state: &State) -> BacktestOutcome {
+    let mut outcome = BacktestOutcome {
+        accounts_liquidated: BTreeSet::new(),
+        liquidation_fills: 0,
+        closed_notional: Decimal::ZERO,
+        deficit_incurred: Decimal::ZERO,
+        insurance_paid: Decimal::ZERO,
+        loss_socialized: Decimal::ZERO,
+        deficit_outstanding: state.accounts.values().map(|a| a.bankruptcy_deficit).sum(),
+        rejections: 0,
+    };
+    for event in log {
+        match &event.as_ref().event_type {
+            EventType::LiquidationFill {
+                account_id,
+                quantity,
+                price,
+                ..
+            }
+            | EventType::LiquidationTakeover {
+                liquidated_account: account_id,
+                quantity,
+                price,
+                ..
+            } => {
+                outcome.accounts_liquidated.insert(account_id.clone());
+                outcome.liquidation_fills += 1;
+                outcome.closed_notional += (quantity * price).abs();
+            }
+            EventType::InsuranceFundPayout { amount, .. } => outcome.insurance_paid += amount,
+            EventType::LossSocialized { amount, .. } => outcome.loss_socialized += amount,
+            other if other.is_rejection() => outcome.rejections += 1,
+            _ => {}
+        }
+    }
+    let mut previous: Option<&Snapshot> = None;
+    for snapshot in snapshots {
+        for (account_id, after) in &snapshot.accounts {
+            let before = previous
+                .and_then(|p| p.accounts.get(account_id))
+                .map_or(Decimal::ZERO, |a| a.bankruptcy_deficit);
+            outcome.deficit_incurred += (after.bankruptcy_deficit - before).max(Decimal::ZERO);
+        }
+        previous = Some(snapshot);
+    }
+    outcome
+}
@@ -0,0 +1,248 @@
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::config::{fnv1a, EngineConfig};
+use crate::engine::{Engine, ReplayOptions};
+use crate::error::EngineError;
+use crate::events::{self, Event, EventType};
+use crate::snapshot::SnapshotPolicy;
+use crate::state::State;
+use crate::types::Market;
+
+/// A state claimed to be an engine's after the event at `after_sequence` (0 before
+/// any), such as a state file saved from a live engine at that point.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub after_sequence: u64,
+    pub state: State,
+}
+
+/// What `Engine::validate_checkpoint` compared, how long it took, and where the
+/// checkpoint first parts from the log, if it does.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationReport {
+    pub checkpoint_sequence: u64,
+    /// The last sequence in the log (the checkpoint's own when nothing follows it).
+    pub last_sequence: u64,
+    /// Events replayed from genesis up to the checkpoint.
+    pub prefix_events: u64,
+    /// Events replayed from the checkpoint to the end of the log. Zero when the
+    /// checkpoint diverged, since nothing after it can be trusted.
+    pub suffix_events: u64,
+    /// State sections compared by checksum: every account, every market, and each
+    /// other field of `State`.
+    pub checksums_compared: usize,
+    /// FNV-1a of the checkpoint's and of the replayed state's `State::to_json`.
+    pub checkpoint_checksum: String,
+    pub replayed_checksum: String,
+    pub prefix_replay: Duration,
+    pub comparison: Duration,
+    pub suffix_replay: Duration,
+    pub divergence: Option<Divergence>,
+}
+
+impl ValidationReport {
+    /// Whether the checkpoint is the log's state at its sequence, bit for bit.
+    pub fn is_valid(&self) -> bool {
+        self.divergence.is_none()
+    }
+}
+
+/// The first field in which a checkpoint differs from the state replay reached at
+/// its sequence. `path` is the field's place in the state's JSON encoding, e.g.
+/// `accounts.alice.positions.BTC-PERP.quantity`. The values are its JSON there,
+/// `None` where the field is absent; a string is given without its quotes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub sequence: u64,
+    pub path: String,
+    pub checkpoint: Option<String>,
+    pub replayed: Option<String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = |v: &Option<String>| v.clone().unwrap_or_else(|| "absent".to_string());
+        write!(
+            f,
+            "after seq {}, {} is {} in the checkpoint but {} on replay",
+            self.sequence,
+            self.path,
+            value(&self.checkpoint),
+            value(&self.replayed)
+        )
+    }
+}
+
+impl Engine {
+    /// Check a checkpoint against the log it claims to come from before going live on
+    /// it. Replay `full_log` from genesis up to the checkpoint's sequence, verified,
+    /// and compare the state it reaches with the checkpoint: section by section by
+    /// checksum, then field by field within the first section that differs. If they
+    /// agree, replay the rest of the log on top of the checkpoint, verified again, so
+    /// the engine it seeds reproduces every derived event the log records.
+    ///
+    /// The log runs under the config in its `ConfigMarker`, or the default without one,
+    /// and the suffix under that config with the prefix's `ConfigUpdated`s installed.
+    /// A log that fails verification, a sequence outside the log and a checkpoint
+    /// inside an event's cascade are errors; a checkpoint that differs is not, and is
+    /// reported as `ValidationReport::divergence`.
+    pub fn validate_checkpoint(
+        checkpoint: &Checkpoint,
+        full_log: &[impl AsRef<Event>],
+        markets: Vec<Market>,
+    ) -> Result<ValidationReport, EngineError> {
+        let full_log = &events::borrowed(full_log);
+        let sequence = checkpoint.after_sequence;
+        let split = full_log.partition_point(|e| e.sequence <= sequence);
+        let (prefix, suffix) = full_log.split_at(split);
+        if sequence > 0 && prefix.last().is_none_or(|e| e.sequence != sequence) {
+            return Err(EngineError::SequenceNotInLog { sequence });
+        }
+        if let Some(next) = suffix.first().filter(|e| e.caused_by.is_some()) {
+            return Err(EngineError::CheckpointMidCascade {
+                sequence,
+                next: next.sequence,
+            });
+        }
+        let config = match full_log.first().map(|e| &e.event_type) {
+            Some(EventType::ConfigMarker { config, .. }) => config.clone(),
+            _ => EngineConfig::default(),
+        };
+        let options = |config: &EngineConfig| ReplayOptions {
+            config: config.clone(),
+            snapshot_policy: SnapshotPolicy::Never,
+            ..ReplayOptions::default()
+        };
+
+        let started = Instant::now();
+        let mut genesis = State::new();
+        for market in markets {
+            genesis.markets.insert(market.market_id.clone(), market);
+        }
+        let replayed = Engine::replay_verified_from(options(&config), genesis, prefix)?;
+        let prefix_replay = started.elapsed();
+
+        let started = Instant::now();
+        let (checksums_compared, divergence) =
+            compare(&checkpoint.state, &replayed.state, sequence);
+        let checkpoint_checksum = fnv1a(checkpoint.state.to_json().as_bytes());
+        let replayed_checksum = fnv1a(replayed.state.to_json().as_bytes());
+        let comparison = started.elapsed();
+
+        let started = Instant::now();
+        let suffix_events = match divergence {
+            Some(_) => 0,
+            None => {
+                // The suffix runs under the config the prefix left in effect.
+                let base = checkpoint.state.clone();
+                Engine::replay_verified_from(options(&replayed.config), base, suffix)?
+                    .events_applied
+            }
+        };
+        Ok(ValidationReport {
+            checkpoint_sequence: sequence,
+            last_sequence: full_log
+                .last()
+                .map_or(sequence, |e| e.sequence.max(sequence)),
+            prefix_events: replayed.events_applied,
+            suffix_events,
+            checksums_compared,
+            checkpoint_checksum,
+            replayed_checksum,
+            prefix_replay,
+            comparison,
+            suffix_replay: started.elapsed(),
+            divergence,
+        })
+    }
+}
+
+/// The state's JSON encoding cut into sections: one per account and per market, and
+/// one per other field.
+fn sections(state: &State) -> BTreeMap<String, Value> {
+    let Value::Object(fields) = serde_json::to_value(state).expect("a state always serializes")
+    else {
+        unreachable!("a state serializes as an object")
+    };
+    let mut sections = BTreeMap::new();
+    for (field, value) in fields {
+        match value {
+            Value::Object(entries) if field == "accounts" || field == "markets" => {
+                for (id, entry) in entries {
+                    sections.insert(format!("{field}.{id}"), entry);
+                }
+            }
+            value => {
+                sections.insert(field, value);
+            }
+        }
+    }
+    sections
+}
+
+/// Compare two states section by section, by checksum. Returns the number of
+/// sections compared and the first differing field of the first section that differs.
+fn compare(checkpoint: &State, replayed: &State, sequence: u64) -> (usize, Option<Divergence>) {
+    let (ours, theirs) = (sections(checkpoint), sections(replayed));
+    let checksum = |value: &Value| fnv1a(value.to_string().as_bytes());
+    let mut compared = 0;
+    for path in ours
+        .keys()
+        .chain(theirs.keys().filter(|p| !ours.contains_key(*p)))
+    {
+        compared += 1;
+        let (a, b) = (ours.get(path), theirs.get(path));
+        if a.map(checksum) != b.map(checksum) {
+            return (
+                compared,
+                Some(first_difference(sequence, path.clone(), a, b)),
+            );
+        }
+    }
+    (compared, None)
+}
+
+/// The first leaf, in key order, at which `a` and `b` differ, under `path`.
+fn first_difference(
+    sequence: u64,
+    path: String,
+    a: Option<&Value>,
+    b: Option<&Value>,
+) -> Divergence {
+    match (a, b) {
+        (Some(Value::Object(a)), Some(Value::Object(b))) => {
+            let mut keys: Vec<&String> = a.keys().chain(b.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                if a.get(key) != b.get(key) {
+                    let path = format!("{path}.{key}");
+                    return first_difference(sequence, path, a.get(key), b.get(key));
+                }
+            }
+        }
+        (Some(Value::Array(a)), Some(Value::Array(b))) => {
+            for i in 0..a.len().max(b.len()) {
+                if a.get(i) != b.get(i) {
+                    let path = format!("{path}.{i}");
+                    return first_difference(sequence, path, a.get(i), b.get(i));
+                }
+            }
+        }
+        _ => {}
+    }
+    // Decimals are JSON strings; show them bare.
+    let text = |value: &Value| match value {
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    };
+    Divergence {
+        sequence,
+        path,
+        checkpoint: a.map(text),
+        replayed: b.map(text),
+    }
+}
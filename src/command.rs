@@ -0,0 +1,300 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::decimal_str;
+use crate::engine::{Engine, ProcessOutcome, Submission};
+use crate::error::MarketError;
+use crate::events::EventType;
+use crate::margin;
+use crate::snapshot::{self, AccountSnapshot};
+use crate::types::{AccountId, Market, MarketId};
+
+/// One request to an engine driven through `Engine::handle`, tagged by `command`:
+/// `{"command": "Process", "event": {"type": "Deposit", ...}}`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "command")]
+pub enum Command {
+    /// Submit an external event, as `Engine::process_with` does.
+    Process {
+        event: EventType,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        idempotency_key: Option<String>,
+        /// Submission time in Unix milliseconds.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<u64>,
+    },
+    /// Register a market, as `Engine::add_market` does.
+    AddMarket {
+        market: Market,
+    },
+    GetAccount {
+        account_id: AccountId,
+    },
+    GetMarket {
+        market_id: MarketId,
+    },
+    GetRisk {
+        account_id: AccountId,
+    },
+}
+
+/// The answer to a `Command`, tagged by `response`. Every command gets exactly one,
+/// and failures are `Error` responses rather than panics.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "response")]
+pub enum Response {
+    Accepted {
+        sequence: u64,
+    },
+    /// `kind` is `RejectReason::kind`; `reason` the text of the `*Rejected` event.
+    Rejected {
+        sequence: u64,
+        kind: String,
+        reason: String,
+    },
+    Duplicate {
+        sequence: u64,
+        original_sequence: u64,
+    },
+    /// Rejected as the account's latest rejection was, and not logged (see
+    /// `EngineConfig::rejection_throttle`).
+    Suppressed {
+        kind: String,
+        reason: String,
+    },
+    MarketAdded {
+        market_id: MarketId,
+    },
+    Account {
+        account_id: AccountId,
+        account: Box<AccountSnapshot>,
+    },
+    Market {
+        market: Box<Market>,
+    },
+    Risk {
+        account_id: AccountId,
+        risk: RiskSummary,
+    },
+    Error {
+        kind: CommandErrorKind,
+        message: String,
+    },
+    /// The event went as `outcome` says, but the engine's snapshot sink refused the
+    /// snapshots in `errors`, which are lost (see `ProcessOutcome::SinkFailed`).
+    SinkFailed {
+        outcome: Box<Response>,
+        errors: Vec<SinkRefusal>,
+    },
+}
+
+/// A snapshot the engine's `SnapshotSink` refused: the sequence it was taken after,
+/// and the sink's error.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SinkRefusal {
+    pub sequence: u64,
+    pub error: String,
+}
+
+/// Why a command produced no result. Rejections by the risk checks are not errors:
+/// they are logged and answered with `Response::Rejected`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum CommandErrorKind {
+    /// Not valid JSON, or not a known command with the fields it needs.
+    Parse,
+    /// A `Process` command carrying an event only the engine may write.
+    EngineGenerated,
+    UnknownAccount,
+    UnknownMarket,
+    /// `AddMarket` parameters failed `Market::validate` or were out of range.
+    InvalidMarket,
+    /// `AddMarket` for a market id already registered.
+    DuplicateMarket,
+    /// The engine panicked. Its state may be partly updated, so discard it.
+    Internal,
+    /// The engine's `LogStore` failed to append or flush, so it takes no more events
+    /// (`ProcessOutcome::LogStoreFailed`). Recover it from the store's file.
+    LogStore,
+}
+
+/// An account's margin position against the current marks.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RiskSummary {
+    #[serde(with = "decimal_str")]
+    pub equity: Decimal,
+    #[serde(with = "decimal_str")]
+    pub initial_margin_required: Decimal,
+    #[serde(with = "decimal_str")]
+    pub maintenance_margin_required: Decimal,
+    /// `equity - maintenance_margin_required`.
+    #[serde(with = "decimal_str")]
+    pub margin_excess: Decimal,
+    /// `equity / maintenance_margin_required`; `None` with no margin required.
+    #[serde(with = "decimal_str::option")]
+    pub margin_ratio: Option<Decimal>,
+    /// `margin::max_withdrawable` under the engine's `withdrawal_buffer`.
+    #[serde(with = "decimal_str")]
+    pub max_withdrawable: Decimal,
+    pub liquidatable: bool,
+    pub in_liquidation: bool,
+    pub suspended: bool,
+}
+
+impl Response {
+    fn error(kind: CommandErrorKind, message: impl Into<String>) -> Self {
+        Response::Error {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+impl Engine {
+    /// Parse a JSON `Command`, execute it, and return the JSON `Response`. Malformed
+    /// input and engine panics come back as `Response::Error`, so this never panics
+    /// (in a build that unwinds on panic, the default).
+    pub fn handle(&mut self, command_json: &str) -> String {
+        let response = match serde_json::from_str::<Command>(command_json) {
+            Ok(command) => match panic::catch_unwind(AssertUnwindSafe(|| self.execute(command))) {
+                Ok(response) => response,
+                Err(payload) => {
+                    let message = payload
+                        .downcast_ref::<String>()
+                        .map(String::as_str)
+                        .or_else(|| payload.downcast_ref::<&str>().copied())
+                        .unwrap_or("engine panicked");
+                    Response::error(CommandErrorKind::Internal, message)
+                }
+            },
+            Err(e) => Response::error(CommandErrorKind::Parse, e.to_string()),
+        };
+        serde_json::to_string(&response).unwrap_or_else(|e| {
+            let error = Response::error(CommandErrorKind::Internal, e.to_string());
+            serde_json::to_string(&error).unwrap_or_default()
+        })
+    }
+
+    /// Execute a parsed `Command`. Unlike `handle`, a panic in the engine propagates.
+    pub fn execute(&mut self, command: Command) -> Response {
+        match command {
+            Command::Process {
+                event,
+                idempotency_key,
+                timestamp,
+            } => {
+                if event.is_engine_generated() {
+                    return Response::error(
+                        CommandErrorKind::EngineGenerated,
+                        "only the engine writes this event type",
+                    );
+                }
+                let submission = Submission {
+                    idempotency_key,
+                    timestamp,
+                };
+                outcome_response(self.process_with(event, submission))
+            }
+
+            Command::AddMarket { market } => {
+                let market_id = market.market_id.clone();
+                match self.add_market(market) {
+                    Ok(()) => Response::MarketAdded { market_id },
+                    Err(e @ MarketError::Duplicate { .. }) => {
+                        Response::error(CommandErrorKind::DuplicateMarket, e.to_string())
+                    }
+                    Err(e @ MarketError::LogStoreFailed { .. }) => {
+                        Response::error(CommandErrorKind::LogStore, e.to_string())
+                    }
+                    Err(e) => Response::error(CommandErrorKind::InvalidMarket, e.to_string()),
+                }
+            }
+
+            Command::GetAccount { account_id } => match self.state.accounts.get(&account_id) {
+                Some(account) => Response::Account {
+                    account: Box::new(snapshot::capture_account(account, &self.state)),
+                    account_id,
+                },
+                None => unknown_account(&account_id),
+            },
+
+            Command::GetMarket { market_id } => match self.state.markets.get(&market_id) {
+                Some(market) => Response::Market {
+                    market: Box::new(market.clone()),
+                },
+                None => Response::error(
+                    CommandErrorKind::UnknownMarket,
+                    format!("Unknown market_id: {market_id}"),
+                ),
+            },
+
+            Command::GetRisk { account_id } => {
+                let Some(account) = self.state.accounts.get(&account_id) else {
+                    return unknown_account(&account_id);
+                };
+                let equity = margin::equity(account, &self.state);
+                let mm = margin::maintenance_margin_required(account, &self.state);
+                let risk = RiskSummary {
+                    equity,
+                    initial_margin_required: margin::initial_margin_required(account, &self.state),
+                    maintenance_margin_required: mm,
+                    margin_excess: equity - mm,
+                    margin_ratio: equity.checked_div(mm),
+                    max_withdrawable: margin::max_withdrawable(
+                        account,
+                        &self.state,
+                        self.config().withdrawal_buffer,
+                    ),
+                    liquidatable: margin::is_liquidatable(account, &self.state),
+                    in_liquidation: self.state.in_liquidation.contains(&account_id),
+                    suspended: account.suspended,
+                };
+                Response::Risk { account_id, risk }
+            }
+        }
+    }
+}
+
+/// The response to a `Process` command whose event had `outcome`.
+fn outcome_response(outcome: ProcessOutcome) -> Response {
+    match outcome {
+        ProcessOutcome::Accepted { sequence } => Response::Accepted { sequence },
+        ProcessOutcome::Rejected { sequence, reason } => Response::Rejected {
+            sequence,
+            kind: reason.kind().to_string(),
+            reason: reason.message().to_string(),
+        },
+        ProcessOutcome::Duplicate {
+            sequence,
+            original_sequence,
+        } => Response::Duplicate {
+            sequence,
+            original_sequence,
+        },
+        ProcessOutcome::Suppressed { reason } => Response::Suppressed {
+            kind: reason.kind().to_string(),
+            reason: reason.message().to_string(),
+        },
+        ProcessOutcome::SinkFailed { outcome, errors } => Response::SinkFailed {
+            outcome: Box::new(outcome_response(*outcome)),
+            errors: errors
+                .into_iter()
+                .map(|(sequence, error)| SinkRefusal {
+                    sequence,
+                    error: error.to_string(),
+                })
+                .collect(),
+        },
+        ProcessOutcome::LogStoreFailed { sequence, error } => Response::error(
+            CommandErrorKind::LogStore,
+            format!("log store failed at seq {sequence}: {error}"),
+        ),
+    }
+}
+
+fn unknown_account(account_id: &str) -> Response {
+    Response::error(
+        CommandErrorKind::UnknownAccount,
+        format!("Unknown account_id: {account_id}"),
+    )
+}
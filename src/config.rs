@@ -0,0 +1,774 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use crate::decimal_str;
+use crate::error::EngineConfigError;
+use crate::risk::{RiskCheck, TradeCheck, TradeContext};
+use crate::snapshot::SnapshotPolicy;
+use crate::types::AccountId;
+
+/// Whether an engine's output is authoritative.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum EngineMode {
+    /// Normal operation: the event log is the source of truth.
+    #[default]
+    Live,
+    /// Simulation: identical decisions, but every event is flagged `dry_run` and
+    /// the JSONL writer refuses to persist the log without an explicit override.
+    DryRun,
+}
+
+/// How `Engine::process` executes liquidations it detects.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum LiquidationPath {
+    /// The engine closes positions at mark (`LiquidationFill`).
+    #[default]
+    EngineClose,
+    /// Each close is first offered to these keeper accounts, in order, as a
+    /// `LiquidationTakeover` at the market's discount. The engine close is the
+    /// fallback when no keeper passes its IM check.
+    Keepers(Vec<AccountId>),
+}
+
+/// Order in which `Engine::process` liquidates the accounts an event made eligible.
+/// Every order breaks ties by account_id, so all of them are deterministic.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum ScanOrder {
+    #[default]
+    AccountId,
+    /// Lowest `equity / maintenance_margin` first; accounts without maintenance
+    /// margin go last.
+    WorstMarginRatioFirst,
+    /// Largest gross notional first.
+    LargestNotionalFirst,
+}
+
+/// Which position a liquidation closes next.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum LiquidationStrategy {
+    /// Largest notional (|qty| * mark) first, less the part hedge-pair relief
+    /// waives (`margin::charged_notional`); ties by gross notional, then market_id.
+    #[default]
+    LargestNotionalFirst,
+    /// The position whose full close at mark leaves the highest equity minus
+    /// maintenance margin; ties by larger notional, then market_id. Each candidate
+    /// close is simulated with `risk::apply_trade_to`, the same code that executes it.
+    BestMarginImprovementFirst,
+}
+
+/// What a risk-increasing trade must leave covered.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum TradeMarginPolicy {
+    /// Post-trade equity must cover IM on the whole post-trade portfolio.
+    #[default]
+    FullPortfolio,
+    /// "IM on delta, MM on stock": post-trade equity must cover MM on the positions
+    /// held before the trade plus the increase in IM the trade causes. An account
+    /// between IM and MM can still add small risk; a flat account is checked exactly
+    /// as under `FullPortfolio`.
+    IncrementalIm,
+}
+
+/// What an account suspended after bankruptcy may not do until it is reinstated.
+/// Risk-reducing trades, deposits and withdrawals are always allowed.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum BankruptcySuspension {
+    /// Suspension is tracked but not enforced.
+    #[default]
+    Off,
+    /// No new risk in any market.
+    AllMarkets,
+    /// No new risk in the markets the account was liquidated in on its way to
+    /// bankruptcy (`Account::suspended_markets`).
+    BankruptedMarkets,
+}
+
+/// What happens to the part of a bankruptcy deficit the account's pool's insurance
+/// fund cannot cover.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum ResidualDeficit {
+    /// It stays on the account.
+    #[default]
+    Hold,
+    /// Once the fund is empty, charge it to the pool's other accounts
+    /// (`LossSocialized`), in proportion to what each could withdraw and never more.
+    Socialize,
+    /// As `Socialize`, but what each account can bear is all of its equity, up to its
+    /// collateral, with no margin held back. A charge can leave an account at or
+    /// under maintenance margin, to be liquidated in the same cascade.
+    SocializeEquity,
+}
+
+impl ResidualDeficit {
+    fn is_hold(&self) -> bool {
+        *self == ResidualDeficit::Hold
+    }
+
+    /// Whether the deficit is charged to the pool rather than held.
+    pub fn socializes(&self) -> bool {
+        *self != ResidualDeficit::Hold
+    }
+
+    /// The multiple of IM an account keeps when it is charged: the `buffer` of the
+    /// `margin::max_withdrawable` that caps its charge.
+    pub fn charge_buffer(&self) -> Decimal {
+        match self {
+            ResidualDeficit::Hold | ResidualDeficit::Socialize => Decimal::ONE,
+            ResidualDeficit::SocializeEquity => Decimal::ZERO,
+        }
+    }
+}
+
+/// What a market whose `skew_limit_notional` is breached does beyond logging
+/// `SkewLimitBreached`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum SkewResponse {
+    /// Nothing: the event is for risk to act on.
+    #[default]
+    Report,
+    /// Reject fills that add exposure on the heavy side until `SkewLimitCleared`.
+    /// Reducing fills still pass.
+    ReduceOnly,
+}
+
+impl SkewResponse {
+    fn is_report(&self) -> bool {
+        *self == SkewResponse::Report
+    }
+}
+
+/// Which collateral balances a `YieldDistribution` pays its rate on.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum YieldBasis {
+    /// Positive balances only; an account at or below zero gets nothing.
+    #[default]
+    PositiveBalances,
+    /// Every balance: a negative one is charged the rate, as a venue netting the
+    /// yield against borrowed cash would.
+    AllBalances,
+}
+
+impl YieldBasis {
+    fn is_positive_balances(&self) -> bool {
+        *self == YieldBasis::PositiveBalances
+    }
+}
+
+/// Which of an account's balances a withdrawal draws on first (see
+/// `Account::withdraw`).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum WithdrawalOrder {
+    /// Positive `trading_balance` first, then `principal`: gains leave before the
+    /// customer's own money.
+    #[default]
+    TradingBalanceFirst,
+    /// Positive `principal` first, then `trading_balance`.
+    PrincipalFirst,
+}
+
+impl WithdrawalOrder {
+    fn is_trading_balance_first(&self) -> bool {
+        *self == WithdrawalOrder::TradingBalanceFirst
+    }
+}
+
+/// What `Engine::process` does with a liquidatable account's positions in markets
+/// whose trading session is closed.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum ClosedSessionLiquidation {
+    /// Close them anyway, at the (indicative) mark.
+    #[default]
+    LiquidateAnyway,
+    /// Leave them open and queue the account (`LiquidationDeferred`); it is scanned
+    /// again when one of those markets' `SessionOpen` arrives. Positions in open
+    /// markets are still liquidated first.
+    DeferUntilOpen,
+}
+
+/// What the post-event scan does with an account whose equity covers the IM of its
+/// positions but not that plus the margin its resting orders reserve.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum ReservationBreach {
+    /// Keep the orders. The account places no new orders and withdraws nothing
+    /// until it is back above IM plus reservations.
+    #[default]
+    Hold,
+    /// Cancel orders, largest reservation first and then by order id, until what is
+    /// left is covered, in one `OrdersAutoCancelled` logged before any liquidation.
+    AutoCancel,
+}
+
+impl ReservationBreach {
+    fn is_hold(&self) -> bool {
+        *self == ReservationBreach::Hold
+    }
+}
+
+/// Whether a rejected attempt (`TradeFill`, `Withdraw`, ...) is snapshotted under
+/// its own sequence as well as its rejection's. It leaves state unchanged, so the two
+/// snapshots would be identical.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum RejectedAttemptSnapshots {
+    /// One snapshot, under the rejection event's sequence.
+    #[default]
+    Skip,
+    /// A snapshot under the attempt's sequence too, as for any other event.
+    Keep,
+}
+
+impl RejectedAttemptSnapshots {
+    fn is_skip(&self) -> bool {
+        *self == RejectedAttemptSnapshots::Skip
+    }
+}
+
+/// What happens to a `MarkPriceUpdate`, `FundingUpdate`, `FundingRate`,
+/// `FundingAccrual`, `SessionOpen` or `SessionClose` (or a `MarkPriceBatch` entry) for
+/// a market that is not registered.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum UnknownMarketPolicy {
+    /// Accept the event, change nothing, and log `UnknownMarketIgnored` after it
+    /// (`MarkPriceBatchSkipped` for a batch).
+    #[default]
+    Ignore,
+    /// Reject the whole event.
+    Reject,
+}
+
+/// What happens to a `StateImport` that would leave the account at or under
+/// maintenance margin.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum ImportMarginCheck {
+    /// Reject the import.
+    #[default]
+    Reject,
+    /// Accept it, log `StateImportBelowMaintenance` after it, and let the usual scan
+    /// liquidate the account.
+    Warn,
+}
+
+/// Whether the engine reports how each event moved account margin figures
+/// (`snapshot::RiskDelta`). Deltas are a side channel: they are never logged, and
+/// replay does not produce them.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum RiskDeltaPolicy {
+    #[default]
+    Off,
+    /// Hand each delta to `EngineObserver::on_risk_delta`.
+    Observers,
+    /// As `Observers`, and also queue them for `Engine::drain_risk_deltas`.
+    ObserversAndQueue,
+}
+
+/// Interest on collateral balances, accrued by each `InterestTick`. Rates are per
+/// tick, not annualized: the caller picks the interval by how often it sends ticks.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct InterestAccrual {
+    /// Charged on negative collateral: at 0.001, an account at -5,000 pays 5.
+    #[serde(with = "decimal_str")]
+    pub rate_per_interval: Decimal,
+    /// Credited on positive collateral. Zero (the default) credits nothing.
+    #[serde(default, with = "decimal_str")]
+    pub credit_rate_per_interval: Decimal,
+}
+
+/// Collapsing of rejections an account repeats, under `EngineConfig::rejection_throttle`.
+/// Once `max_rejections` of the account's last `window` submissions were rejected, a
+/// submission rejected exactly as its latest rejection was (the same record, reason
+/// included) is not logged. The burst is summarized by one `RejectionSuppressed`,
+/// logged before the account's next logged submission or after every `summary_every`
+/// suppressed ones.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RejectionThrottle {
+    /// Submissions naming the account, counted back from the current one.
+    pub window: u64,
+    pub max_rejections: usize,
+    pub summary_every: u64,
+}
+
+/// Margin-usage alerts (`RiskAlert`, `RiskAlertCleared`), under
+/// `EngineConfig::risk_alerts`. Usage is maintenance margin over equity, and an
+/// account's alert level is the number of thresholds it has reached. It rises as soon
+/// as usage reaches a threshold, but falls back below one only once usage is
+/// `hysteresis` under it, so a mark flickering across a threshold alerts once.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RiskAlertLadder {
+    /// Ascending usage fractions: `[0.8, 0.9, 0.95]` alerts at 80%, 90% and 95%.
+    #[serde(with = "decimal_str::vec")]
+    pub thresholds: Vec<Decimal>,
+    /// How far under a threshold usage must fall to clear it: 0.02 is two points.
+    #[serde(default, with = "decimal_str")]
+    pub hysteresis: Decimal,
+}
+
+impl RiskAlertLadder {
+    /// The level an account at `current` moves to at `usage`. `None` is unbounded
+    /// usage (maintenance margin against no equity), which reaches every threshold.
+    pub fn level(&self, usage: Option<Decimal>, current: usize) -> usize {
+        let reached = |band: Decimal| match usage {
+            Some(usage) => self
+                .thresholds
+                .iter()
+                .filter(|t| usage >= **t - band)
+                .count(),
+            None => self.thresholds.len(),
+        };
+        current
+            .min(reached(self.hysteresis))
+            .max(reached(Decimal::ZERO))
+    }
+}
+
+/// A grace period before liquidation (`MarginCall`, `MarginCallCleared`), under
+/// `EngineConfig::margin_call`. An account that falls to or under maintenance margin
+/// is sent a margin call instead of being liquidated, and has until the grace period
+/// runs out to deposit or reduce. It is liquidated at once if its equity falls below
+/// `hard_fraction` of its maintenance margin, grace or not.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MarginCallPolicy {
+    pub grace: GracePeriod,
+    /// 0.5 liquidates an account in grace as soon as its equity is under half its MM.
+    #[serde(with = "decimal_str")]
+    pub hard_fraction: Decimal,
+}
+
+/// How long a margin call lasts. Both are read off the log, so replay expires a
+/// call at the same event.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum GracePeriod {
+    /// The call expires at the first event at least this many sequences after the
+    /// event that breached.
+    Events(u64),
+    /// The call expires once the log clock is this many milliseconds past the clock
+    /// at the breach.
+    Millis(u64),
+}
+
+/// Rolling per-account trade statistics (`Engine::account_stats`) and the fee tiers
+/// they select, under `EngineConfig::trade_stats`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TradeStatistics {
+    pub window: StatsWindow,
+    /// Fee rate by turnover in the window. Ascending by `min_turnover` is clearest,
+    /// but not required: the highest tier reached applies.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fee_tiers: Vec<FeeTier>,
+}
+
+impl TradeStatistics {
+    /// The rate of the highest tier `turnover` reaches. `None` below every tier.
+    pub fn fee_rate(&self, turnover: Decimal) -> Option<Decimal> {
+        self.fee_tiers
+            .iter()
+            .filter(|tier| turnover >= tier.min_turnover)
+            .max_by_key(|tier| tier.min_turnover)
+            .map(|tier| tier.rate)
+    }
+}
+
+/// Which of an account's fills its statistics cover. Fills leave the window only as
+/// the log moves on, so the statistics replay exactly.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum StatsWindow {
+    /// The account's last `n` fills, liquidations included.
+    Fills(usize),
+    /// Fills within this many milliseconds of the log clock. A fill is stamped with
+    /// the clock it was applied at, and leaves once the clock is that far past it.
+    Millis(u64),
+}
+
+/// One step of a fee schedule: accounts whose turnover reaches `min_turnover` pay
+/// `rate`, a fraction of notional (negative for a rebate).
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FeeTier {
+    #[serde(with = "decimal_str")]
+    pub min_turnover: Decimal,
+    #[serde(with = "decimal_str")]
+    pub rate: Decimal,
+}
+
+/// Custom pre-trade stages (`risk::RiskCheck`), under `EngineConfig::risk_checks`, in
+/// the order they run after the built-in ones.
+///
+/// Only the stages' names are serialized, and configs compare by them, so a
+/// `ConfigMarker` records which stages a log ran under and replay refuses to go on
+/// without them. A config read back from JSON or TOML cannot run what it names: each
+/// of its stages rejects every fill until the config is rebuilt with the real stages.
+#[derive(Clone, Default)]
+pub struct RiskChecks(Vec<Arc<dyn RiskCheck>>);
+
+impl RiskChecks {
+    pub fn push(&mut self, check: Box<dyn RiskCheck>) {
+        self.0.push(Arc::from(check));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn RiskCheck> {
+        self.0.iter().map(|check| check.as_ref())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.iter().map(|check| check.name()).collect()
+    }
+}
+
+impl std::fmt::Debug for RiskChecks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+impl PartialEq for RiskChecks {
+    fn eq(&self, other: &Self) -> bool {
+        self.names() == other.names()
+    }
+}
+
+impl Eq for RiskChecks {}
+
+impl Serialize for RiskChecks {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.names().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RiskChecks {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let names = Vec::<String>::deserialize(deserializer)?;
+        let stage = |name| Arc::new(Unregistered(name)) as Arc<dyn RiskCheck>;
+        Ok(RiskChecks(names.into_iter().map(stage).collect()))
+    }
+}
+
+/// A stage known only by name, from a deserialized config.
+struct Unregistered(String);
+
+impl RiskCheck for Unregistered {
+    fn name(&self) -> &str {
+        &self.0
+    }
+
+    fn check(&self, _ctx: &TradeContext) -> TradeCheck {
+        TradeCheck::Rejected("stage is not registered with this engine".to_string())
+    }
+}
+
+/// Every engine-level knob, in one serializable place. Markets are configured
+/// separately (`Engine::add_market`); this covers how the engine itself behaves.
+///
+/// A live engine writes its config at the head of its log (`ConfigMarker`), and
+/// replay refuses a log whose marker disagrees with the config it is given.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct EngineConfig {
+    #[serde(default)]
+    pub mode: EngineMode,
+    #[serde(default)]
+    pub liquidation_path: LiquidationPath,
+    #[serde(default)]
+    pub scan_order: ScanOrder,
+    #[serde(default)]
+    pub liquidation_strategy: LiquidationStrategy,
+    #[serde(default)]
+    pub trade_margin_policy: TradeMarginPolicy,
+    #[serde(default)]
+    pub bankruptcy_suspension: BankruptcySuspension,
+    /// Left out of the encoding while it is `Hold`, so configs from before it keep
+    /// their hash.
+    #[serde(default, skip_serializing_if = "ResidualDeficit::is_hold")]
+    pub residual_deficit: ResidualDeficit,
+    /// How many times a cascade rescans the accounts its own records touched, such as
+    /// those a `LossSocialized` charged, for liquidations they now need. Left out of
+    /// the encoding while it is the default of 16, like `residual_deficit`.
+    #[serde(
+        default = "default_max_cascade_rescans",
+        skip_serializing_if = "is_default_max_cascade_rescans"
+    )]
+    pub max_cascade_rescans: u32,
+    /// Left out of the encoding while it is `Report`, like `residual_deficit`.
+    #[serde(default, skip_serializing_if = "SkewResponse::is_report")]
+    pub skew_response: SkewResponse,
+    /// Cap on the gross open interest notional of every market together: the sum of
+    /// `|quantity| × |mark|` over all positions. Non-reducing fills that would take it
+    /// past the cap are rejected, as a market's own `max_open_interest_notional`
+    /// rejects them. `None` (the default) is uncapped and left out of the encoding.
+    #[serde(
+        default,
+        with = "decimal_str::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_total_open_interest_notional: Option<Decimal>,
+    #[serde(default)]
+    pub closed_session_liquidation: ClosedSessionLiquidation,
+    /// Left out of the encoding while it is `Hold`, like `residual_deficit`.
+    #[serde(default, skip_serializing_if = "ReservationBreach::is_hold")]
+    pub reservation_breach: ReservationBreach,
+    #[serde(default)]
+    pub unknown_markets: UnknownMarketPolicy,
+    #[serde(default)]
+    pub import_margin_check: ImportMarginCheck,
+    /// A withdrawal must leave equity of at least `IM × withdrawal_buffer`. At 1 (the
+    /// default) that is IM itself; at 1.1 it keeps a 10% cushion above it.
+    #[serde(default = "default_withdrawal_buffer", with = "decimal_str")]
+    pub withdrawal_buffer: Decimal,
+    /// Left out of the encoding while it is `TradingBalanceFirst`, like
+    /// `residual_deficit`.
+    #[serde(
+        default,
+        skip_serializing_if = "WithdrawalOrder::is_trading_balance_first"
+    )]
+    pub withdrawal_order: WithdrawalOrder,
+    #[serde(default)]
+    pub risk_deltas: RiskDeltaPolicy,
+    /// Interest on collateral balances. `None` (the default) rejects every
+    /// `InterestTick`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interest: Option<InterestAccrual>,
+    /// Balances a `YieldDistribution` pays on. Left out of the encoding while it is
+    /// `PositiveBalances`, like `residual_deficit`.
+    #[serde(default, skip_serializing_if = "YieldBasis::is_positive_balances")]
+    pub yield_basis: YieldBasis,
+    /// Suppression of repeated rejections. `None` (the default) logs every rejection.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rejection_throttle: Option<RejectionThrottle>,
+    /// Margin-usage alert levels. `None` (the default) raises no alerts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub risk_alerts: Option<RiskAlertLadder>,
+    /// Rolling trade statistics and fee tiers. `None` (the default) tracks nothing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trade_stats: Option<TradeStatistics>,
+    /// Margin calls with a grace period before liquidation. `None` (the default)
+    /// liquidates as soon as an account is at or under maintenance margin.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub margin_call: Option<MarginCallPolicy>,
+    /// Custom pre-trade stages, run after the built-in ones. Empty by default.
+    #[serde(default, skip_serializing_if = "RiskChecks::is_empty")]
+    pub risk_checks: RiskChecks,
+    /// Which events the live engine retains a snapshot for.
+    #[serde(default)]
+    pub snapshot_policy: SnapshotPolicy,
+    /// Left out of the encoding while it is `Skip`, like `residual_deficit`. Replay
+    /// follows the one in `ReplayOptions::config`.
+    #[serde(default, skip_serializing_if = "RejectedAttemptSnapshots::is_skip")]
+    pub rejected_attempt_snapshots: RejectedAttemptSnapshots,
+    /// Number of most recent idempotency keys remembered for deduplication.
+    #[serde(default = "default_idempotency_window")]
+    pub idempotency_window: usize,
+    /// In debug builds, panic as soon as an applied event leaves a nonzero
+    /// `state::solvency` residual. Ignored in release builds.
+    #[serde(default)]
+    pub assert_solvency: bool,
+}
+
+fn default_idempotency_window() -> usize {
+    10_000
+}
+
+fn default_max_cascade_rescans() -> u32 {
+    16
+}
+
+fn is_default_max_cascade_rescans(rescans: &u32) -> bool {
+    *rescans == default_max_cascade_rescans()
+}
+
+fn default_withdrawal_buffer() -> Decimal {
+    Decimal::ONE
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            mode: EngineMode::default(),
+            liquidation_path: LiquidationPath::default(),
+            scan_order: ScanOrder::default(),
+            liquidation_strategy: LiquidationStrategy::default(),
+            trade_margin_policy: TradeMarginPolicy::default(),
+            bankruptcy_suspension: BankruptcySuspension::default(),
+            residual_deficit: ResidualDeficit::default(),
+            max_cascade_rescans: default_max_cascade_rescans(),
+            skew_response: SkewResponse::default(),
+            max_total_open_interest_notional: None,
+            closed_session_liquidation: ClosedSessionLiquidation::default(),
+            reservation_breach: ReservationBreach::default(),
+            unknown_markets: UnknownMarketPolicy::default(),
+            import_margin_check: ImportMarginCheck::default(),
+            withdrawal_buffer: default_withdrawal_buffer(),
+            withdrawal_order: WithdrawalOrder::default(),
+            risk_deltas: RiskDeltaPolicy::default(),
+            interest: None,
+            yield_basis: YieldBasis::default(),
+            rejection_throttle: None,
+            risk_alerts: None,
+            trade_stats: None,
+            margin_call: None,
+            risk_checks: RiskChecks::default(),
+            snapshot_policy: SnapshotPolicy::default(),
+            rejected_attempt_snapshots: RejectedAttemptSnapshots::default(),
+            idempotency_window: default_idempotency_window(),
+            assert_solvency: false,
+        }
+    }
+}
+
+impl EngineConfig {
+    /// Fields fixed for an engine's lifetime: a `ConfigUpdated` may not change them.
+    /// The mode decides whether the log is authoritative, custom stages are code the
+    /// log only names, and the idempotency window, risk-delta baseline, rejection
+    /// bursts and trade statistics are state sized or started by the config. Every
+    /// other field is read afresh by each event, so a new value takes effect at the
+    /// sequence of its update.
+    pub const CONSTRUCTION_ONLY: [&'static str; 6] = [
+        "idempotency_window",
+        "mode",
+        "rejection_throttle",
+        "risk_checks",
+        "risk_deltas",
+        "trade_stats",
+    ];
+
+    /// Check the config for settings that contradict themselves: a
+    /// `withdrawal_buffer` below 1, a keeper path without keepers or with one listed
+    /// twice, a negative total open interest cap or interest rate, alert thresholds
+    /// that are not positive and strictly ascending or a hysteresis below zero, a
+    /// rejection throttle or statistics window of zero, a fee tier with a negative
+    /// turnover, or a margin call with no grace or a hard fraction outside `[0, 1]`.
+    pub fn validate(&self) -> Result<(), EngineConfigError> {
+        if self.withdrawal_buffer < Decimal::ONE {
+            return Err(EngineConfigError::WithdrawalBuffer(self.withdrawal_buffer));
+        }
+        if let LiquidationPath::Keepers(keepers) = &self.liquidation_path {
+            if keepers.is_empty() {
+                return Err(EngineConfigError::Keepers("no keeper accounts".to_string()));
+            }
+            let mut seen = BTreeSet::new();
+            if let Some(keeper) = keepers.iter().find(|keeper| !seen.insert(*keeper)) {
+                return Err(EngineConfigError::Keepers(format!(
+                    "{keeper} is listed twice"
+                )));
+            }
+        }
+        let negative = |field, value: Decimal| {
+            if value < Decimal::ZERO {
+                Err(EngineConfigError::Negative { field, value })
+            } else {
+                Ok(())
+            }
+        };
+        if let Some(cap) = self.max_total_open_interest_notional {
+            negative("max_total_open_interest_notional", cap)?;
+        }
+        if let Some(interest) = &self.interest {
+            negative("interest.rate_per_interval", interest.rate_per_interval)?;
+            negative(
+                "interest.credit_rate_per_interval",
+                interest.credit_rate_per_interval,
+            )?;
+        }
+        if let Some(ladder) = &self.risk_alerts {
+            let ascending = ladder.thresholds.windows(2).all(|pair| pair[0] < pair[1]);
+            if ladder
+                .thresholds
+                .first()
+                .is_none_or(|first| *first <= Decimal::ZERO)
+                || !ascending
+            {
+                return Err(EngineConfigError::AlertThresholds);
+            }
+            negative("risk_alerts.hysteresis", ladder.hysteresis)?;
+        }
+        if let Some(throttle) = &self.rejection_throttle {
+            let counts = [
+                ("rejection_throttle.window", throttle.window),
+                (
+                    "rejection_throttle.max_rejections",
+                    throttle.max_rejections as u64,
+                ),
+                ("rejection_throttle.summary_every", throttle.summary_every),
+            ];
+            if let Some((field, _)) = counts.into_iter().find(|(_, count)| *count == 0) {
+                return Err(EngineConfigError::Zero { field });
+            }
+        }
+        if let Some(stats) = &self.trade_stats {
+            if matches!(stats.window, StatsWindow::Fills(0) | StatsWindow::Millis(0)) {
+                return Err(EngineConfigError::Zero {
+                    field: "trade_stats.window",
+                });
+            }
+            for tier in &stats.fee_tiers {
+                negative("trade_stats.fee_tiers.min_turnover", tier.min_turnover)?;
+            }
+        }
+        if let Some(policy) = &self.margin_call {
+            if matches!(
+                policy.grace,
+                GracePeriod::Events(0) | GracePeriod::Millis(0)
+            ) {
+                return Err(EngineConfigError::Zero {
+                    field: "margin_call.grace",
+                });
+            }
+            if policy.hard_fraction < Decimal::ZERO || policy.hard_fraction > Decimal::ONE {
+                return Err(EngineConfigError::HardFraction(policy.hard_fraction));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a running engine on `self` may switch to `next`: `next` must pass
+    /// `validate` and leave every `CONSTRUCTION_ONLY` field as it is.
+    pub fn check_reload(&self, next: &EngineConfig) -> Result<(), EngineConfigError> {
+        next.validate()?;
+        let fields: Vec<String> = self
+            .diff(next)
+            .into_iter()
+            .filter(|field| Self::CONSTRUCTION_ONLY.contains(&field.as_str()))
+            .collect();
+        if !fields.is_empty() {
+            return Err(EngineConfigError::ConstructionOnly { fields });
+        }
+        Ok(())
+    }
+
+    /// Stable hash of the config: FNV-1a (64-bit) over its canonical JSON encoding,
+    /// as 16 lowercase hex digits. Unlike `std`'s hasher, this is stable across
+    /// builds, so it can be persisted.
+    pub fn hash(&self) -> String {
+        let json = serde_json::to_string(self).expect("EngineConfig serializes");
+        fnv1a(json.as_bytes())
+    }
+
+    /// Names of the top-level fields that differ between `self` and `other`,
+    /// sorted by name. Empty when the configs are equal. An optional feature that is
+    /// off is left out of the encoding, so a field set on either side only counts.
+    pub fn diff(&self, other: &EngineConfig) -> Vec<String> {
+        let a = serde_json::to_value(self).expect("EngineConfig serializes");
+        let b = serde_json::to_value(other).expect("EngineConfig serializes");
+        match (a, b) {
+            (serde_json::Value::Object(a), serde_json::Value::Object(b)) => a
+                .keys()
+                .chain(b.keys())
+                .filter(|field| a.get(field.as_str()) != b.get(field.as_str()))
+                .cloned()
+                .collect::<BTreeSet<String>>()
+                .into_iter()
+                .collect(),
+            _ => unreachable!("EngineConfig serializes as an object"),
+        }
+    }
+}
+
+/// FNV-1a (64-bit) of `bytes`, as 16 lowercase hex digits.
+pub(crate) fn fnv1a(bytes: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    format!("{hash:016x}")
+}
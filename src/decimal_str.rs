@@ -0,0 +1,137 @@
+//! Canonical serde representation for `Decimal`: a JSON string of the normalized
+//! value (trailing zeros stripped), so arithmetically equal decimals always
+//! serialize to identical bytes regardless of the scale they were computed at.
+//!
+//! Use via `#[serde(with = "decimal_str")]`, `decimal_str::option`, `decimal_str::vec`,
+//! `decimal_str::option_vec`, `decimal_str::map`, or `decimal_str::option_map`.
+//! Deserialization accepts any string or number `Decimal` accepts.
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer, Serializer};
+
+/// Canonical form of a decimal: same value, minimal scale, no negative zero.
+pub fn normalize(value: Decimal) -> Decimal {
+    value.normalize()
+}
+
+pub fn serialize<S: Serializer>(value: &Decimal, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&normalize(*value))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Decimal, D::Error> {
+    <Decimal as Deserialize>::deserialize(deserializer)
+}
+
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<Decimal>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(v) => serializer.collect_str(&normalize(*v)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Decimal>, D::Error> {
+        Option::<Decimal>::deserialize(deserializer)
+    }
+}
+
+pub mod vec {
+    use super::*;
+    use serde::ser::SerializeSeq;
+
+    pub fn serialize<S: Serializer>(value: &[Decimal], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(value.len()))?;
+        for v in value {
+            seq.serialize_element(&normalize(*v).to_string())?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Decimal>, D::Error> {
+        Vec::<Decimal>::deserialize(deserializer)
+    }
+}
+
+pub mod option_vec {
+    use super::*;
+    use serde::ser::SerializeSeq;
+
+    pub fn serialize<S: Serializer>(
+        value: &[Option<Decimal>],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(value.len()))?;
+        for v in value {
+            seq.serialize_element(&v.map(|v| normalize(v).to_string()))?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Option<Decimal>>, D::Error> {
+        Vec::<Option<Decimal>>::deserialize(deserializer)
+    }
+}
+
+pub mod map {
+    use super::*;
+    use serde::ser::SerializeMap;
+    use std::collections::BTreeMap;
+
+    pub fn serialize<K, S>(value: &BTreeMap<K, Decimal>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        K: serde::Serialize,
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(value.len()))?;
+        for (k, v) in value {
+            map.serialize_entry(k, &normalize(*v).to_string())?;
+        }
+        map.end()
+    }
+
+    pub fn deserialize<'de, K, D>(deserializer: D) -> Result<BTreeMap<K, Decimal>, D::Error>
+    where
+        K: Deserialize<'de> + Ord,
+        D: Deserializer<'de>,
+    {
+        BTreeMap::<K, Decimal>::deserialize(deserializer)
+    }
+}
+
+pub mod option_map {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    pub fn serialize<K, S>(
+        value: &Option<BTreeMap<K, Decimal>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        K: serde::Serialize,
+        S: Serializer,
+    {
+        match value {
+            Some(map) => super::map::serialize(map, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, K, D>(deserializer: D) -> Result<Option<BTreeMap<K, Decimal>>, D::Error>
+    where
+        K: Deserialize<'de> + Ord,
+        D: Deserializer<'de>,
+    {
+        Option::<BTreeMap<K, Decimal>>::deserialize(deserializer)
+    }
+}
@@ -0,0 +1,292 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::engine::{Engine, EngineConfig, ProcessOutcome, Submission};
+use crate::error::EngineError;
+use crate::events::{Event, EventType};
+use crate::jsonl::{self, LogDefect};
+use crate::types::Market;
+
+/// How long a `DurableEngine` has spent in fsync.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SyncMetrics {
+    /// fsync calls, one per `process_with` and one per `process_batch`.
+    pub syncs: u64,
+    /// Submissions those calls made durable.
+    pub submissions: u64,
+    pub total_latency: Duration,
+    pub max_latency: Duration,
+}
+
+impl SyncMetrics {
+    /// Mean fsync latency, zero before the first.
+    pub fn mean_latency(&self) -> Duration {
+        match u32::try_from(self.syncs) {
+            Ok(0) => Duration::ZERO,
+            Ok(syncs) => self.total_latency / syncs,
+            Err(_) => self.total_latency.div_f64(self.syncs as f64),
+        }
+    }
+}
+
+/// What `DurableEngine::open` found in the journal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Recovery {
+    /// Submissions re-processed.
+    pub submissions: u64,
+    /// Bytes of a torn last line that were cut off the journal.
+    pub torn_bytes: usize,
+}
+
+/// An `Engine` behind a write-ahead journal: every submission is written to the
+/// journal and fsynced before the engine applies it, so nothing the engine has applied
+/// or acknowledged can be missing from disk after a crash.
+///
+/// The journal holds submissions, not the engine's log. Its lines are `Event`s in the
+/// JSONL format `jsonl` reads: a `ConfigMarker` header at seq 0, then each submission
+/// with its idempotency key and timestamp, numbered from 1. Because processing is
+/// deterministic, re-processing the journal under the same config and markets rebuilds
+/// the engine exactly, its log, state and throttled rejections included. The engine's
+/// log can still be spilled to a `LogStore`, attached after `create` or `open`.
+pub struct DurableEngine {
+    engine: Engine,
+    path: PathBuf,
+    journal: File,
+    submitted: u64,
+    /// Set by a failed write or fsync, after which the journal may hold submissions
+    /// the engine has not applied.
+    failed: bool,
+    sync: SyncMetrics,
+    recovery: Recovery,
+}
+
+impl DurableEngine {
+    /// Start a new journal at `path`, replacing any file there, for an engine with
+    /// `config` and `markets`.
+    pub fn create(
+        path: impl AsRef<Path>,
+        markets: Vec<Market>,
+        config: EngineConfig,
+    ) -> Result<Self, EngineError> {
+        let path = path.as_ref().to_path_buf();
+        let engine = build(markets, config)?;
+        let mut journal = File::create(&path)?;
+        let header = Event::generated(
+            0,
+            EventType::ConfigMarker {
+                config_hash: engine.config().hash(),
+                config: engine.config().clone(),
+            },
+        );
+        write_entries(&mut journal, [&header])?;
+        journal.sync_all()?;
+        // The new file's directory entry must be durable too. Directories cannot be
+        // opened as files on every platform, where there is nothing more to do.
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            if let Ok(dir) = File::open(dir) {
+                dir.sync_all()?;
+            }
+        }
+        Ok(Self {
+            engine,
+            path,
+            journal,
+            submitted: 0,
+            failed: false,
+            sync: SyncMetrics::default(),
+            recovery: Recovery::default(),
+        })
+    }
+
+    /// Recover from the journal at `path`, written under `config` and `markets`: cut
+    /// off a torn last line (a submission whose write never completed, so it was
+    /// never applied), re-process every submission, and go on appending. An empty
+    /// journal, or one whose header never completed, starts over as `create` does.
+    ///
+    /// Any other damage fails with `EngineError::CorruptLog`, and a header whose config
+    /// differs from `config` with `EngineError::ConfigMismatch`. Neither touches the
+    /// file.
+    pub fn open(
+        path: impl AsRef<Path>,
+        markets: Vec<Market>,
+        config: EngineConfig,
+    ) -> Result<Self, EngineError> {
+        let path = path.as_ref();
+        let content = fs::read(path)?;
+        let (entries, defects) = jsonl::parse_jsonl_recover(&content);
+        let torn_bytes = match defects[..] {
+            [] => 0,
+            [LogDefect::TruncatedLastLine { bytes, .. }] => bytes,
+            _ => return Err(EngineError::CorruptLog { defects }),
+        };
+        let Some((header, submissions)) = entries.split_first() else {
+            let mut durable = Self::create(path, markets, config)?;
+            durable.recovery.torn_bytes = torn_bytes;
+            return Ok(durable);
+        };
+        let EventType::ConfigMarker { config: logged, .. } = &header.event_type else {
+            return Err(EngineError::JournalHeader);
+        };
+        if header.sequence != 0 {
+            return Err(EngineError::JournalHeader);
+        }
+        let fields = config.diff(logged);
+        if !fields.is_empty() {
+            return Err(EngineError::ConfigMismatch {
+                sequence: 0,
+                fields,
+            });
+        }
+
+        let mut engine = build(markets, config)?;
+        for entry in submissions {
+            let submission = Submission {
+                idempotency_key: entry.idempotency_key.clone(),
+                timestamp: entry.timestamp,
+            };
+            engine.process_with(entry.event_type.clone(), submission);
+        }
+
+        let kept = (content.len() - torn_bytes) as u64;
+        OpenOptions::new().write(true).open(path)?.set_len(kept)?;
+        // A last line written in full but for its newline is a complete submission.
+        let mut journal = OpenOptions::new().append(true).open(path)?;
+        if torn_bytes == 0 && content.last() != Some(&b'\n') {
+            journal.write_all(b"\n")?;
+        }
+        journal.sync_all()?;
+        Ok(Self {
+            engine,
+            path: path.to_path_buf(),
+            journal,
+            submitted: submissions.len() as u64,
+            failed: false,
+            sync: SyncMetrics::default(),
+            recovery: Recovery {
+                submissions: submissions.len() as u64,
+                torn_bytes,
+            },
+        })
+    }
+
+    /// Journal an external event, fsync, then process it. Fails without processing
+    /// it if the journal cannot be written.
+    pub fn process(&mut self, event_type: EventType) -> Result<ProcessOutcome, EngineError> {
+        self.process_with(event_type, Submission::default())
+    }
+
+    /// `process` with envelope fields, which are journaled with the event.
+    pub fn process_with(
+        &mut self,
+        event_type: EventType,
+        submission: Submission,
+    ) -> Result<ProcessOutcome, EngineError> {
+        let mut outcomes = self.process_batch([(event_type, submission)])?;
+        Ok(outcomes.remove(0))
+    }
+
+    /// Group commit: journal every submission, fsync once, then process them in order.
+    /// Fails without processing any of them if the journal cannot be written. The
+    /// journal may then hold some of them after all, so every later call fails with
+    /// `EngineError::JournalFailed` until `open` recovers from what reached the disk.
+    pub fn process_batch(
+        &mut self,
+        submissions: impl IntoIterator<Item = (EventType, Submission)>,
+    ) -> Result<Vec<ProcessOutcome>, EngineError> {
+        let entries: Vec<Event> = submissions
+            .into_iter()
+            .enumerate()
+            .map(|(i, (event_type, submission))| {
+                let mut entry = Event::new(self.submitted + 1 + i as u64, event_type);
+                entry.idempotency_key = submission.idempotency_key;
+                entry.timestamp = submission.timestamp;
+                entry
+            })
+            .collect();
+        if self.failed {
+            return Err(EngineError::JournalFailed);
+        }
+        if entries.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.failed = true;
+        write_entries(&mut self.journal, &entries)?;
+        let started = Instant::now();
+        self.journal.sync_data()?;
+        let latency = started.elapsed();
+        self.failed = false;
+        self.sync.syncs += 1;
+        self.sync.submissions += entries.len() as u64;
+        self.sync.total_latency += latency;
+        self.sync.max_latency = self.sync.max_latency.max(latency);
+        self.submitted += entries.len() as u64;
+
+        Ok(entries
+            .into_iter()
+            .map(|entry| {
+                let submission = Submission {
+                    idempotency_key: entry.idempotency_key,
+                    timestamp: entry.timestamp,
+                };
+                self.engine.process_with(entry.event_type, submission)
+            })
+            .collect())
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// The engine, e.g. to attach observers or a `LogStore`. Events processed on it
+    /// directly bypass the journal and are lost on recovery.
+    pub fn engine_mut(&mut self) -> &mut Engine {
+        &mut self.engine
+    }
+
+    pub fn into_engine(self) -> Engine {
+        self.engine
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Submissions in the journal.
+    pub fn submitted(&self) -> u64 {
+        self.submitted
+    }
+
+    pub fn sync_metrics(&self) -> SyncMetrics {
+        self.sync
+    }
+
+    /// What `open` recovered; all zero after `create`.
+    pub fn recovery(&self) -> Recovery {
+        self.recovery
+    }
+}
+
+fn build(markets: Vec<Market>, config: EngineConfig) -> Result<Engine, EngineError> {
+    let mut engine = Engine::builder().config(config).build();
+    for market in markets {
+        engine.add_market(market)?;
+    }
+    Ok(engine)
+}
+
+/// Write `entries` as JSONL lines in one call, so a crashed process leaves a prefix
+/// of them, torn at most in the last line.
+fn write_entries<'a>(
+    journal: &mut File,
+    entries: impl IntoIterator<Item = &'a Event>,
+) -> Result<(), EngineError> {
+    let mut content = String::new();
+    for entry in entries {
+        content.push_str(&serde_json::to_string(entry).map_err(EngineError::Serialize)?);
+        content.push('\n');
+    }
+    journal.write_all(content.as_bytes())?;
+    Ok(())
+}
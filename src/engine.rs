@@ -1,276 +1,4279 @@
-use crate::events::{Event, EventType};
+pub use crate::config::{
+    BankruptcySuspension, ClosedSessionLiquidation, EngineConfig, EngineMode, FeeTier, GracePeriod,
+    ImportMarginCheck, InterestAccrual, LiquidationPath, LiquidationStrategy, MarginCallPolicy,
+    RejectedAttemptSnapshots, RejectionThrottle, ReservationBreach, ResidualDeficit,
+    RiskAlertLadder, RiskChecks, RiskDeltaPolicy, ScanOrder, SkewResponse, StatsWindow,
+    TradeMarginPolicy, TradeStatistics, UnknownMarketPolicy, WithdrawalOrder, YieldBasis,
+};
+use crate::error::{EngineError, MarketError, ResumeError, SinkError};
+use crate::events::{self, Event, EventType, Liquidity};
+use crate::jsonl;
 use crate::liquidation;
-use crate::risk::{self, apply_trade_to, TradeCheck};
-use crate::snapshot::{self, Snapshot};
-use crate::state::State;
-use crate::types::{AccountId, Market};
+use crate::log_store::{LogStore, LogStoreOptions};
+use crate::margin;
+use crate::risk::{self, apply_trade_to, RiskCheck, TradeCheck};
+use crate::sink::SnapshotSink;
+use crate::snapshot::{self, RiskDelta, RiskFigures, Snapshot, SnapshotPolicy};
+use crate::state::{
+    self, AccountStats, EngineMetrics, RejectionHistory, SolvencyReport, State, StatsFill,
+};
+use crate::trace;
+use crate::types::{
+    check_metadata_update, Account, AccountGroup, AccountId, Backstop, HedgePair, InstrumentKind,
+    MarginCallOpen, Market, MarketId, OrderId, PoolId, RestingOrder,
+};
+use crate::view::{StateView, StateViews, Touched};
 
-use rust_decimal::Decimal;
-use std::collections::BTreeSet;
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 /// Result of applying a single event.
 enum ApplyResult {
     Ok,
     Rejected(String),
+    /// An engine-generated event that the engine could never have produced from this
+    /// state (e.g. a liquidation fill for an unknown account). State is unchanged.
+    InvalidDerived(String),
+}
+
+/// What happened to an externally submitted event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProcessOutcome {
+    /// Applied at `sequence`. Derived events (funding payments, liquidations) follow
+    /// it in the log.
+    Accepted { sequence: u64 },
+    /// Logged at `sequence` without effect, followed by its `*Rejected` record.
+    Rejected { sequence: u64, reason: RejectReason },
+    /// The idempotency key was already seen; only a `DuplicateIgnored` event was
+    /// logged, at `sequence`.
+    Duplicate {
+        sequence: u64,
+        original_sequence: u64,
+    },
+    /// Rejected exactly as the account's latest rejection while its rejections were
+    /// throttled (`EngineConfig::rejection_throttle`): nothing was logged. A
+    /// `RejectionSuppressed` will count it.
+    Suppressed { reason: RejectReason },
+    /// `outcome` happened as it says, but the engine's `SnapshotSink` refused the
+    /// snapshots after these sequences. The events are applied and logged regardless;
+    /// only the refused snapshots are lost. `into_result` makes it an error.
+    SinkFailed {
+        outcome: Box<ProcessOutcome>,
+        errors: Vec<(u64, SinkError)>,
+    },
+    /// The `LogStore` failed to append or flush the event at `sequence`, so its file
+    /// may end before the engine's state. The engine takes no more events: this and
+    /// every later submission gets this outcome, and nothing is logged or told to
+    /// observers after the failure. Recover from the file with `Engine::recover`.
+    LogStoreFailed { sequence: u64, error: String },
+}
+
+impl ProcessOutcome {
+    pub fn is_accepted(&self) -> bool {
+        matches!(self.outcome(), ProcessOutcome::Accepted { .. })
+    }
+
+    /// The outcome of the event itself, whether or not the sink refused a snapshot.
+    pub fn outcome(&self) -> &ProcessOutcome {
+        match self {
+            ProcessOutcome::SinkFailed { outcome, .. } => outcome.outcome(),
+            outcome => outcome,
+        }
+    }
+
+    /// `SinkFailed` as `EngineError::SnapshotSink` for its first refusal, carrying
+    /// the outcome of the event, and `LogStoreFailed` as `EngineError::LogStoreFailed`;
+    /// any other outcome as it is.
+    pub fn into_result(self) -> Result<ProcessOutcome, EngineError> {
+        match self {
+            ProcessOutcome::LogStoreFailed { sequence, error } => {
+                Err(EngineError::LogStoreFailed { sequence, error })
+            }
+            ProcessOutcome::SinkFailed { outcome, errors } => {
+                let (sequence, source) = errors
+                    .into_iter()
+                    .next()
+                    .expect("a sink failure records its refusal");
+                Err(EngineError::SnapshotSink {
+                    sequence,
+                    source,
+                    outcome,
+                })
+            }
+            outcome => Ok(outcome),
+        }
+    }
+}
+
+/// The rejection reason of an engine-generated event submitted from outside: only
+/// the engine writes those, as records of the events that caused them.
+pub const ENGINE_GENERATED: &str = "Engine-generated events cannot be submitted";
+
+/// Why an event was rejected, by the kind of event rejected. The message is the
+/// same text recorded in the `*Rejected` event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RejectReason {
+    Trade(String),
+    Withdrawal(String),
+    MarkPrice(String),
+    Takeover(String),
+    FundingRate(String),
+    FundingUpdate(String),
+    AccountMetadata(String),
+    /// A withdrawal or risk-adding trade from an account that was already
+    /// liquidatable (see `risk::IN_LIQUIDATION`).
+    AccountInLiquidation(String),
+    /// A risk-adding trade from an account suspended after bankruptcy (see
+    /// `risk::SUSPENDED_AFTER_BANKRUPTCY`).
+    AccountSuspendedAfterBankruptcy(String),
+    /// A trade, withdrawal or keeper takeover by an account frozen by a `ForceClose`
+    /// (see `risk::FROZEN`).
+    AccountFrozen(String),
+    /// A risk-adding trade in a market outside its trading session (see
+    /// `risk::MARKET_CLOSED`).
+    MarketClosed(String),
+    /// A trade adding exposure on the heavy side of a market whose skew limit is
+    /// breached, under `SkewResponse::ReduceOnly` (see `risk::SKEW_LIMIT`).
+    SkewLimit(String),
+    /// A trade rejected by a custom stage of `EngineConfig::risk_checks` (see
+    /// `risk::RISK_CHECK`); the message names the stage.
+    RiskCheck(String),
+    /// An `AccountReinstated` for an account that is neither suspended nor frozen, or
+    /// still owes part of its deficit.
+    Reinstatement(String),
+    /// An `AssignPool` for an account that already exists.
+    AssignPool(String),
+    /// A `StateImport` for an existing account, an unknown market or an invalid
+    /// position, or one under maintenance margin under `ImportMarginCheck::Reject`.
+    StateImport(String),
+    /// A `HedgePairAdded` naming an unknown or already paired market, or with a
+    /// fraction outside `(0, 1]`.
+    HedgePair(String),
+    /// An `Expiry` for a perpetual, an expired market, or a future before its expiry.
+    Expiry(String),
+    /// An `InterestTick` without interest configured, or for an interval already
+    /// accrued.
+    InterestTick(String),
+    /// A `YieldDistribution` with a negative rate, or for an interval already paid.
+    YieldDistribution(String),
+    /// A `GroupCreated` for an existing group or with an invalid cap or fee rate, or a
+    /// `GroupMembershipSet` naming an account or group that does not exist.
+    Group(String),
+    /// A `SetPositionLeverage` naming an unknown account or market, outside the
+    /// market's leverage range, or lowering leverage further than equity covers.
+    Leverage(String),
+    /// An event without a rejection of its own carrying a value beyond
+    /// `risk::MAX_EVENT_VALUE`, a `ForceClose` without a reason or for an unknown or
+    /// already frozen account, a `ConfigUpdated` that fails
+    /// `EngineConfig::check_reload`, or an engine-generated event submitted from
+    /// outside (see `ENGINE_GENERATED`).
+    InvalidEvent(String),
+}
+
+impl RejectReason {
+    /// The reason carried by a `*Rejected` event; `None` for any other event.
+    pub fn from_event(event_type: &EventType) -> Option<Self> {
+        let reason = match event_type {
+            EventType::TradeRejected { reason, .. }
+            | EventType::WithdrawalRejected { reason, .. }
+                if reason.starts_with(risk::IN_LIQUIDATION) =>
+            {
+                RejectReason::AccountInLiquidation(reason.clone())
+            }
+            EventType::TradeRejected { reason, .. }
+            | EventType::WithdrawalRejected { reason, .. }
+            | EventType::LiquidationTakeoverRejected { reason, .. }
+                if reason.starts_with(risk::FROZEN) =>
+            {
+                RejectReason::AccountFrozen(reason.clone())
+            }
+            EventType::TradeRejected { reason, .. }
+                if reason.starts_with(risk::SUSPENDED_AFTER_BANKRUPTCY) =>
+            {
+                RejectReason::AccountSuspendedAfterBankruptcy(reason.clone())
+            }
+            EventType::TradeRejected { reason, .. } if reason.starts_with(risk::MARKET_CLOSED) => {
+                RejectReason::MarketClosed(reason.clone())
+            }
+            EventType::TradeRejected { reason, .. } if reason.starts_with(risk::SKEW_LIMIT) => {
+                RejectReason::SkewLimit(reason.clone())
+            }
+            EventType::TradeRejected { reason, .. } if reason.starts_with(risk::RISK_CHECK) => {
+                RejectReason::RiskCheck(reason.clone())
+            }
+            EventType::TradeRejected { reason, .. } => RejectReason::Trade(reason.clone()),
+            EventType::WithdrawalRejected { reason, .. } => {
+                RejectReason::Withdrawal(reason.clone())
+            }
+            EventType::MarkPriceRejected { reason, .. }
+            | EventType::MarkPriceBatchRejected { reason, .. } => {
+                RejectReason::MarkPrice(reason.clone())
+            }
+            EventType::LiquidationTakeoverRejected { reason, .. } => {
+                RejectReason::Takeover(reason.clone())
+            }
+            EventType::FundingRateRejected { reason, .. } => {
+                RejectReason::FundingRate(reason.clone())
+            }
+            EventType::FundingUpdateRejected { reason, .. } => {
+                RejectReason::FundingUpdate(reason.clone())
+            }
+            EventType::AccountMetadataRejected { reason, .. } => {
+                RejectReason::AccountMetadata(reason.clone())
+            }
+            EventType::AccountReinstatementRejected { reason, .. } => {
+                RejectReason::Reinstatement(reason.clone())
+            }
+            EventType::AssignPoolRejected { reason, .. } => {
+                RejectReason::AssignPool(reason.clone())
+            }
+            EventType::StateImportRejected { reason, .. } => {
+                RejectReason::StateImport(reason.clone())
+            }
+            EventType::HedgePairRejected { reason, .. } => RejectReason::HedgePair(reason.clone()),
+            EventType::ExpiryRejected { reason, .. } => RejectReason::Expiry(reason.clone()),
+            EventType::InterestTickRejected { reason, .. } => {
+                RejectReason::InterestTick(reason.clone())
+            }
+            EventType::YieldDistributionRejected { reason, .. } => {
+                RejectReason::YieldDistribution(reason.clone())
+            }
+            EventType::GroupCreatedRejected { reason, .. }
+            | EventType::GroupMembershipRejected { reason, .. } => {
+                RejectReason::Group(reason.clone())
+            }
+            EventType::PositionLeverageRejected { reason, .. } => {
+                RejectReason::Leverage(reason.clone())
+            }
+            EventType::EventRejected { reason, .. } => RejectReason::InvalidEvent(reason.clone()),
+            _ => return None,
+        };
+        Some(reason)
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            RejectReason::Trade(m)
+            | RejectReason::Withdrawal(m)
+            | RejectReason::MarkPrice(m)
+            | RejectReason::Takeover(m)
+            | RejectReason::FundingRate(m)
+            | RejectReason::FundingUpdate(m)
+            | RejectReason::AccountMetadata(m)
+            | RejectReason::AccountInLiquidation(m)
+            | RejectReason::AccountSuspendedAfterBankruptcy(m)
+            | RejectReason::AccountFrozen(m)
+            | RejectReason::MarketClosed(m)
+            | RejectReason::SkewLimit(m)
+            | RejectReason::RiskCheck(m)
+            | RejectReason::Reinstatement(m)
+            | RejectReason::AssignPool(m)
+            | RejectReason::StateImport(m)
+            | RejectReason::HedgePair(m)
+            | RejectReason::Expiry(m)
+            | RejectReason::InterestTick(m)
+            | RejectReason::YieldDistribution(m)
+            | RejectReason::Group(m)
+            | RejectReason::Leverage(m)
+            | RejectReason::InvalidEvent(m) => m,
+        }
+    }
+
+    /// The variant name (`"Trade"`, `"MarketClosed"`, ...), a stable tag for
+    /// callers that cannot match on the enum, such as the JSON command interface.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RejectReason::Trade(_) => "Trade",
+            RejectReason::Withdrawal(_) => "Withdrawal",
+            RejectReason::MarkPrice(_) => "MarkPrice",
+            RejectReason::Takeover(_) => "Takeover",
+            RejectReason::FundingRate(_) => "FundingRate",
+            RejectReason::FundingUpdate(_) => "FundingUpdate",
+            RejectReason::AccountMetadata(_) => "AccountMetadata",
+            RejectReason::AccountInLiquidation(_) => "AccountInLiquidation",
+            RejectReason::AccountSuspendedAfterBankruptcy(_) => "AccountSuspendedAfterBankruptcy",
+            RejectReason::AccountFrozen(_) => "AccountFrozen",
+            RejectReason::MarketClosed(_) => "MarketClosed",
+            RejectReason::SkewLimit(_) => "SkewLimit",
+            RejectReason::RiskCheck(_) => "RiskCheck",
+            RejectReason::Reinstatement(_) => "Reinstatement",
+            RejectReason::AssignPool(_) => "AssignPool",
+            RejectReason::StateImport(_) => "StateImport",
+            RejectReason::HedgePair(_) => "HedgePair",
+            RejectReason::Expiry(_) => "Expiry",
+            RejectReason::InterestTick(_) => "InterestTick",
+            RejectReason::YieldDistribution(_) => "YieldDistribution",
+            RejectReason::Group(_) => "Group",
+            RejectReason::Leverage(_) => "Leverage",
+            RejectReason::InvalidEvent(_) => "InvalidEvent",
+        }
+    }
+}
+
+impl std::fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+/// Envelope fields a submitter can attach to an external event.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Submission {
+    pub idempotency_key: Option<String>,
+    /// Submission time in Unix milliseconds.
+    pub timestamp: Option<u64>,
+}
+
+/// Fluent construction of an `Engine`; `Engine::builder().build()` equals `Engine::new()`.
+#[derive(Debug, Clone, Default)]
+pub struct EngineBuilder {
+    config: EngineConfig,
+}
+
+impl EngineBuilder {
+    pub fn config(mut self, config: EngineConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn mode(mut self, mode: EngineMode) -> Self {
+        self.config.mode = mode;
+        self
+    }
+
+    pub fn liquidation_path(mut self, path: LiquidationPath) -> Self {
+        self.config.liquidation_path = path;
+        self
+    }
+
+    pub fn scan_order(mut self, order: ScanOrder) -> Self {
+        self.config.scan_order = order;
+        self
+    }
+
+    pub fn liquidation_strategy(mut self, strategy: LiquidationStrategy) -> Self {
+        self.config.liquidation_strategy = strategy;
+        self
+    }
+
+    pub fn residual_deficit(mut self, policy: ResidualDeficit) -> Self {
+        self.config.residual_deficit = policy;
+        self
+    }
+
+    pub fn max_cascade_rescans(mut self, rescans: u32) -> Self {
+        self.config.max_cascade_rescans = rescans;
+        self
+    }
+
+    pub fn skew_response(mut self, response: SkewResponse) -> Self {
+        self.config.skew_response = response;
+        self
+    }
+
+    pub fn max_total_open_interest_notional(mut self, cap: Decimal) -> Self {
+        self.config.max_total_open_interest_notional = Some(cap);
+        self
+    }
+
+    pub fn trade_margin_policy(mut self, policy: TradeMarginPolicy) -> Self {
+        self.config.trade_margin_policy = policy;
+        self
+    }
+
+    pub fn snapshot_policy(mut self, policy: SnapshotPolicy) -> Self {
+        self.config.snapshot_policy = policy;
+        self
+    }
+
+    pub fn rejected_attempt_snapshots(mut self, policy: RejectedAttemptSnapshots) -> Self {
+        self.config.rejected_attempt_snapshots = policy;
+        self
+    }
+
+    pub fn idempotency_window(mut self, capacity: usize) -> Self {
+        self.config.idempotency_window = capacity;
+        self
+    }
+
+    pub fn closed_session_liquidation(mut self, policy: ClosedSessionLiquidation) -> Self {
+        self.config.closed_session_liquidation = policy;
+        self
+    }
+
+    pub fn reservation_breach(mut self, policy: ReservationBreach) -> Self {
+        self.config.reservation_breach = policy;
+        self
+    }
+
+    pub fn unknown_markets(mut self, policy: UnknownMarketPolicy) -> Self {
+        self.config.unknown_markets = policy;
+        self
+    }
+
+    pub fn import_margin_check(mut self, check: ImportMarginCheck) -> Self {
+        self.config.import_margin_check = check;
+        self
+    }
+
+    pub fn withdrawal_buffer(mut self, buffer: Decimal) -> Self {
+        self.config.withdrawal_buffer = buffer;
+        self
+    }
+
+    pub fn withdrawal_order(mut self, order: WithdrawalOrder) -> Self {
+        self.config.withdrawal_order = order;
+        self
+    }
+
+    pub fn risk_deltas(mut self, policy: RiskDeltaPolicy) -> Self {
+        self.config.risk_deltas = policy;
+        self
+    }
+
+    pub fn interest(mut self, accrual: InterestAccrual) -> Self {
+        self.config.interest = Some(accrual);
+        self
+    }
+
+    pub fn yield_basis(mut self, basis: YieldBasis) -> Self {
+        self.config.yield_basis = basis;
+        self
+    }
+
+    pub fn rejection_throttle(mut self, throttle: RejectionThrottle) -> Self {
+        self.config.rejection_throttle = Some(throttle);
+        self
+    }
+
+    pub fn risk_alerts(mut self, ladder: RiskAlertLadder) -> Self {
+        self.config.risk_alerts = Some(ladder);
+        self
+    }
+
+    pub fn trade_stats(mut self, stats: TradeStatistics) -> Self {
+        self.config.trade_stats = Some(stats);
+        self
+    }
+
+    pub fn margin_call(mut self, policy: MarginCallPolicy) -> Self {
+        self.config.margin_call = Some(policy);
+        self
+    }
+
+    /// Append a custom pre-trade stage, run after the built-in ones and any appended
+    /// before it.
+    pub fn risk_check(mut self, check: Box<dyn RiskCheck>) -> Self {
+        self.config.risk_checks.push(check);
+        self
+    }
+
+    pub fn assert_solvency(mut self, enabled: bool) -> Self {
+        self.config.assert_solvency = enabled;
+        self
+    }
+
+    pub fn build(self) -> Engine {
+        Engine::with_config(self.config)
+    }
+}
+
+/// Callbacks invoked by `Engine::process` after each event is logged and snapshotted.
+/// Dry-run engines call `on_dry_run_event` instead of `on_event`, so an observer
+/// wired to production sinks cannot mistake simulated output for real output.
+///
+/// With `EngineConfig::risk_deltas` on, each event's `on_event` is followed by one
+/// `on_risk_delta` per account whose margin figures it moved.
+pub trait EngineObserver {
+    fn on_event(&mut self, _event: &Event, _snapshot: &Snapshot) {}
+    fn on_dry_run_event(&mut self, _event: &Event, _snapshot: &Snapshot) {}
+    fn on_risk_delta(&mut self, _delta: &RiskDelta) {}
+    fn on_dry_run_risk_delta(&mut self, _delta: &RiskDelta) {}
 }
 
 pub struct Engine {
     pub state: State,
-    pub event_log: Vec<Event>,
+    /// The log, or with a `LogStore` attached only its most recent events; the full
+    /// history is `history()`. Events are shared, so copying the log copies none.
+    pub event_log: Vec<Arc<Event>>,
+    /// Retained snapshots. With a `LogStore`, only those for events still in memory.
+    /// Empty with a `SnapshotSink`, which gets them instead.
     pub snapshots: Vec<Snapshot>,
     next_sequence: u64,
+    /// Events recorded by this engine, including any no longer in memory.
+    events_recorded: u64,
+    /// State before the first logged event (empty plus registered markets, or the
+    /// state the engine was seeded with), and that event's sequence. Historical
+    /// snapshots are reconstructed by replaying the log from here.
+    base: State,
+    base_sequence: u64,
+    /// Cash totals behind `solvency`, counted since `base`.
+    metrics: EngineMetrics,
+    log_store: Option<LogStore>,
+    /// The sequence and error of the first append or flush `log_store` failed. The
+    /// file may end before the state from then on, so the engine takes no more events.
+    log_store_failure: Option<(u64, String)>,
+    config: EngineConfig,
+    /// The config in effect at `base`. `ConfigUpdated` events since may have replaced
+    /// `config`; replaying the log from `base` starts from this one.
+    base_config: EngineConfig,
+    observers: Vec<Box<dyn EngineObserver>>,
+    /// Informational events derived while applying the current event (e.g. per-account
+    /// funding payments). Drained into the log by `process`; discarded on replay,
+    /// where the log already contains them.
+    pending_derived: Vec<EventType>,
+    /// Chooses each liquidation event of the scan in place of
+    /// `liquidation::next_liquidation_with_sessions`. Only the backtester sets one.
+    liquidator: Option<Liquidator>,
+    /// Deltas not yet drained, under `RiskDeltaPolicy::ObserversAndQueue`.
+    risk_delta_queue: Vec<RiskDelta>,
+    /// Rejections suppressed under `EngineConfig::rejection_throttle` and not yet
+    /// summarized, per account.
+    suppressed: BTreeMap<AccountId, SuppressedBurst>,
+    /// Where each recorded event's `StateView` is published, once `views` was called
+    /// or the first event was processed with risk deltas on.
+    views: Option<StateViews>,
+    /// Accounts changed since the last view without the event naming them: those whose
+    /// trade statistics a clock advance expired. Kept only while views are on.
+    touched: BTreeSet<AccountId>,
+    /// Takes retained snapshots in place of `snapshots`, once `set_snapshot_sink` was
+    /// called.
+    snapshot_sink: Option<Box<dyn SnapshotSink>>,
+    /// Refusals by `snapshot_sink` during the current `process` call.
+    sink_errors: Vec<(u64, SinkError)>,
+    /// While `process_batch` runs, the accounts its events called to scan, which are
+    /// scanned once after the last of them.
+    batch: Option<BTreeSet<AccountId>>,
+}
+
+/// A run of suppressed rejections from one account, as its `RejectionSuppressed` will
+/// record it.
+struct SuppressedBurst {
+    count: u64,
+    first_sequence: u64,
+    last_sequence: u64,
+}
+
+/// The next liquidation event for an account, or `None` when its scan is done.
+pub(crate) type Liquidator = Box<dyn FnMut(&State, &AccountId) -> Option<EventType>>;
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Engine {
     pub fn new() -> Self {
+        Self::with_config(EngineConfig::default())
+    }
+
+    pub fn with_mode(mode: EngineMode) -> Self {
+        Self::with_config(EngineConfig {
+            mode,
+            ..EngineConfig::default()
+        })
+    }
+
+    pub fn with_config(config: EngineConfig) -> Self {
         Self {
             state: State::new(),
             event_log: Vec::new(),
             snapshots: Vec::new(),
             next_sequence: 1,
+            events_recorded: 0,
+            base: State::new(),
+            base_sequence: 1,
+            metrics: EngineMetrics::default(),
+            log_store: None,
+            log_store_failure: None,
+            base_config: config.clone(),
+            config,
+            observers: Vec::new(),
+            pending_derived: Vec::new(),
+            liquidator: None,
+            risk_delta_queue: Vec::new(),
+            suppressed: BTreeMap::new(),
+            views: None,
+            touched: BTreeSet::new(),
+            snapshot_sink: None,
+            sink_errors: Vec::new(),
+            batch: None,
+        }
+    }
+
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::default()
+    }
+
+    /// Seed an engine from existing state (e.g. a live checkpoint). The new engine's
+    /// log starts empty, so a `DryRun` engine seeded this way never touches the
+    /// authoritative log it was derived from.
+    pub fn from_state(state: State, next_sequence: u64, mode: EngineMode) -> Self {
+        Self {
+            base: state.clone(),
+            base_sequence: next_sequence,
+            metrics: EngineMetrics::opening(&state),
+            state,
+            next_sequence,
+            ..Self::with_mode(mode)
+        }
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    pub fn mode(&self) -> EngineMode {
+        self.config.mode
+    }
+
+    pub fn metrics(&self) -> &EngineMetrics {
+        &self.metrics
+    }
+
+    /// The books as of the last applied event; see `state::solvency`.
+    pub fn solvency(&self) -> SolvencyReport {
+        state::solvency(&self.state, &self.metrics)
+    }
+
+    /// The account's totals over `EngineConfig::trade_stats`'s window. `None` while
+    /// statistics are off or the account has never filled.
+    pub fn account_stats(&self, account_id: &str) -> Option<&AccountStats> {
+        self.state
+            .trade_stats
+            .get(account_id)
+            .map(|stats| &stats.totals)
+    }
+
+    /// The fee rate the account's next `TradeFill` is charged: its group's
+    /// `fee_override`, else the tier its turnover reaches. `None` when neither applies,
+    /// and the fill is free.
+    pub fn fee_rate(&self, account_id: &str) -> Option<Decimal> {
+        let account = self.state.accounts.get(account_id)?;
+        let group = account
+            .group_id
+            .as_ref()
+            .and_then(|id| self.state.groups.get(id));
+        if let Some(rate) = group.and_then(|group| group.fee_override) {
+            return Some(rate);
+        }
+        let turnover = self
+            .account_stats(account_id)
+            .map_or(Decimal::ZERO, |s| s.turnover);
+        self.config.trade_stats.as_ref()?.fee_rate(turnover)
+    }
+
+    /// Sequence number that will be assigned to the next logged event.
+    pub fn next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+
+    pub fn liquidation_path(&self) -> &LiquidationPath {
+        &self.config.liquidation_path
+    }
+
+    /// Change the liquidation path. Call before the first event: the config
+    /// recorded in the log's `ConfigMarker` is the one in effect at that point.
+    pub fn set_liquidation_path(&mut self, path: LiquidationPath) {
+        self.config.liquidation_path = path;
+    }
+
+    /// Spill the log to `store`: from now on every recorded event is appended to its
+    /// file, and memory keeps only the most recent `memory_capacity` events and their
+    /// snapshots. Events already in memory are written to the file first, so attach
+    /// before memory was trimmed by another store. Dry-run engines cannot spill.
+    pub fn set_log_store(&mut self, mut store: LogStore) -> Result<(), EngineError> {
+        if self.config.mode == EngineMode::DryRun {
+            return Err(EngineError::DryRunLogStore);
+        }
+        for event in &self.event_log {
+            store.append(event)?;
+        }
+        store.flush()?;
+        self.log_store = Some(store);
+        Ok(())
+    }
+
+    pub fn log_store(&self) -> Option<&LogStore> {
+        self.log_store.as_ref()
+    }
+
+    /// Flush the log store's buffered writes, if one is attached.
+    pub fn flush_log(&mut self) -> Result<(), EngineError> {
+        match &mut self.log_store {
+            Some(store) => store.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// The complete log in sequence order: events spilled to the log store's file,
+    /// then those still in memory. Feed it to `replay_with_fallible` to replay a
+    /// history larger than memory.
+    pub fn history(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<Event, EngineError>> + '_, EngineError> {
+        Ok(self
+            .history_borrowed()?
+            .map(|item| item.map(Cow::into_owned)))
+    }
+
+    /// `history()` without copying the events still in memory.
+    fn history_borrowed(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<Cow<'_, Event>, EngineError>> + '_, EngineError> {
+        let spilled = match &self.log_store {
+            Some(store) if store.spilled() > 0 => {
+                Some(jsonl::stream_jsonl(store.path())?.take(store.spilled() as usize))
+            }
+            _ => None,
+        };
+        Ok(spilled
+            .into_iter()
+            .flatten()
+            .map(|item| item.map(Cow::Owned))
+            .chain(
+                self.event_log
+                    .iter()
+                    .map(|event| Ok(Cow::Borrowed(&**event))),
+            ))
+    }
+
+    /// Every event in `history()` that names `account_id` (see
+    /// `EventType::involves_account`).
+    pub fn events_for_account<'a>(
+        &'a self,
+        account_id: &'a str,
+    ) -> Result<impl Iterator<Item = Result<Event, EngineError>> + 'a, EngineError> {
+        Ok(self.history()?.filter(move |item| match item {
+            Ok(event) => event.event_type.involves_account(account_id),
+            Err(_) => true,
+        }))
+    }
+
+    /// Replace the liquidation choice of the live scan (see `backtest`). Every event
+    /// it returns must be valid for the state it was given.
+    pub(crate) fn set_liquidator(&mut self, liquidator: Liquidator) {
+        self.liquidator = Some(liquidator);
+    }
+
+    pub fn add_observer(&mut self, observer: Box<dyn EngineObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// Hand every snapshot the policy retains from now on to `sink` rather than
+    /// keeping it in `snapshots`, which are handed over first. Returns the sink's
+    /// refusals of those, by sequence. A later refusal turns the outcome of the
+    /// `process` call that recorded the snapshot into `ProcessOutcome::SinkFailed`;
+    /// either way the engine carries on as if the sink had accepted.
+    pub fn set_snapshot_sink(&mut self, mut sink: Box<dyn SnapshotSink>) -> Vec<(u64, SinkError)> {
+        let mut refused = Vec::new();
+        for snapshot in std::mem::take(&mut self.snapshots) {
+            let sequence = snapshot.after_sequence;
+            if let Err(e) = sink.accept(snapshot) {
+                refused.push((sequence, e));
+            }
+        }
+        self.snapshot_sink = Some(sink);
+        refused
+    }
+
+    /// A handle on the engine's latest `StateView`, for readers on other threads. The
+    /// first call publishes a view of the current state; from then on every recorded
+    /// event publishes one, capturing again only the accounts it can have changed and
+    /// sharing the rest with the last. The view follows recorded events only: change
+    /// `state` directly before this call, not after, since a later view would miss
+    /// the change and debug builds check each view against `state`.
+    pub fn views(&mut self) -> StateViews {
+        if self.views.is_none() {
+            self.views = Some(StateViews::default());
+            self.publish_view(self.next_sequence.saturating_sub(1), None);
+        }
+        self.views.clone().unwrap()
+    }
+
+    /// Publish the view after `after_sequence`, built from the last one by capturing
+    /// again only what `event_type` may have changed; `None` captures every account.
+    /// With risk deltas on, returns those of the accounts the view changed.
+    fn publish_view(
+        &mut self,
+        after_sequence: u64,
+        event_type: Option<&EventType>,
+    ) -> Vec<RiskDelta> {
+        let Some(views) = &self.views else {
+            return Vec::new();
+        };
+        let touched = match event_type {
+            Some(event_type) if !moves_every_account(event_type) => {
+                let mut accounts = std::mem::take(&mut self.touched);
+                accounts.extend(event_type.accounts().into_iter().cloned());
+                let markets = match event_type {
+                    EventType::FundingUpdate { market_id, .. }
+                    | EventType::FundingRate { market_id, .. }
+                    | EventType::FundingAccrual { market_id, .. } => vec![market_id.clone()],
+                    _ => Vec::new(),
+                };
+                Touched::Some { accounts, markets }
+            }
+            _ => {
+                self.touched.clear();
+                Touched::All
+            }
+        };
+        let last = views.load();
+        let (view, changed) = last.updated(&self.state, after_sequence, touched);
+        debug_assert!(
+            view.differences(&self.state).is_empty(),
+            "view after seq {after_sequence} differs from the state: {:?}",
+            view.differences(&self.state)
+        );
+        let view = Arc::new(view);
+        views.publish(view.clone());
+        if self.config.risk_deltas == RiskDeltaPolicy::Off || event_type.is_none() {
+            return Vec::new();
+        }
+        // An account the event created moves from all zeros, one it closed to them.
+        let figures = |view: &StateView, account_id: &str| {
+            view.account(account_id)
+                .map(RiskFigures::of)
+                .unwrap_or_default()
+        };
+        changed
+            .into_iter()
+            .filter_map(|account_id| {
+                let before = figures(&last, &account_id);
+                let after = figures(&view, &account_id);
+                (before != after).then_some(RiskDelta {
+                    sequence: after_sequence,
+                    account_id,
+                    before,
+                    after,
+                })
+            })
+            .collect()
+    }
+
+    /// `MarketError::LogStoreFailed` once the log store failed: a market is not
+    /// registered or removed after that, as configuration or by an event.
+    fn check_log_store(&self) -> Result<(), MarketError> {
+        match &self.log_store_failure {
+            Some((sequence, error)) => Err(MarketError::LogStoreFailed {
+                sequence: *sequence,
+                error: error.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// After a market is registered or removed as configuration, outside any event.
+    fn republish_view(&mut self) {
+        self.publish_view(self.next_sequence.saturating_sub(1), None);
+    }
+
+    /// Risk deltas queued since the last call, oldest first. Always empty unless
+    /// `risk_deltas` is `ObserversAndQueue`.
+    pub fn drain_risk_deltas(&mut self) -> Vec<RiskDelta> {
+        std::mem::take(&mut self.risk_delta_queue)
+    }
+
+    /// Register a market. Before the engine's first event this is configuration, like
+    /// the markets replay is given. After it, the market is added by processing a
+    /// `MarketAdded`, so the log records it and replay adds it at the same point.
+    /// Parameters that fail `Market::validate`, values out of range and an id already
+    /// registered are refused, and nothing is registered or logged.
+    pub fn add_market(&mut self, market: Market) -> Result<(), MarketError> {
+        self.check_log_store()?;
+        risk::check_market_addition(&self.state, &market)?;
+        if self.events_recorded > 0 {
+            self.process(EventType::MarketAdded {
+                market: Box::new(market),
+            });
+            return self.check_log_store();
+        }
+        self.base
+            .markets
+            .insert(market.market_id.clone(), market.clone());
+        self.state.markets.insert(market.market_id.clone(), market);
+        self.republish_view();
+        Ok(())
+    }
+
+    /// Deregister a market, as configuration before the first event and by processing
+    /// a `MarketRemoved` after it, as `add_market` does. Refused for an unknown market,
+    /// or while any account holds a position in it.
+    pub fn remove_market(&mut self, market_id: &MarketId) -> Result<(), MarketError> {
+        self.check_log_store()?;
+        risk::check_market_removal(&self.state, market_id)?;
+        if self.events_recorded > 0 {
+            self.process(EventType::MarketRemoved {
+                market_id: market_id.clone(),
+            });
+            return self.check_log_store();
+        }
+        self.base.remove_market(market_id);
+        self.state.remove_market(market_id);
+        self.republish_view();
+        Ok(())
+    }
+
+    /// Process an external event in live mode.
+    /// Assigns a sequence number, applies it, snapshots, then scans for liquidations.
+    pub fn process(&mut self, event_type: EventType) -> ProcessOutcome {
+        self.process_with(event_type, Submission::default())
+    }
+
+    /// Every event in `history()` generated by the external event at `sequence` (see
+    /// `Event::caused_by`), in log order. Empty for a log written without the field.
+    pub fn events_caused_by(
+        &self,
+        sequence: u64,
+    ) -> Result<impl Iterator<Item = Result<Event, EngineError>> + '_, EngineError> {
+        Ok(self.history()?.filter(move |item| match item {
+            Ok(event) => event.caused_by == Some(sequence),
+            Err(_) => true,
+        }))
+    }
+
+    /// Process an external event carrying an idempotency key. If the key is still in
+    /// the dedup window, only a `DuplicateIgnored` event is logged.
+    pub fn process_idempotent(
+        &mut self,
+        key: impl Into<String>,
+        event_type: EventType,
+    ) -> ProcessOutcome {
+        self.process_with(
+            event_type,
+            Submission {
+                idempotency_key: Some(key.into()),
+                ..Submission::default()
+            },
+        )
+    }
+
+    /// Process an external event stamped with its submission time (Unix ms).
+    pub fn process_at(&mut self, timestamp: u64, event_type: EventType) -> ProcessOutcome {
+        self.process_with(
+            event_type,
+            Submission {
+                timestamp: Some(timestamp),
+                ..Submission::default()
+            },
+        )
+    }
+
+    /// Process an external event with the given envelope fields.
+    pub fn process_with(
+        &mut self,
+        event_type: EventType,
+        submission: Submission,
+    ) -> ProcessOutcome {
+        if let Some((sequence, error)) = &self.log_store_failure {
+            return ProcessOutcome::LogStoreFailed {
+                sequence: *sequence,
+                error: error.clone(),
+            };
+        }
+        let outcome = self.process_submission(event_type, submission);
+        if let Some((sequence, error)) = &self.log_store_failure {
+            self.sink_errors.clear();
+            return ProcessOutcome::LogStoreFailed {
+                sequence: *sequence,
+                error: error.clone(),
+            };
+        }
+        if self.sink_errors.is_empty() {
+            return outcome;
+        }
+        ProcessOutcome::SinkFailed {
+            outcome: Box::new(outcome),
+            errors: std::mem::take(&mut self.sink_errors),
+        }
+    }
+
+    /// Process external events as one batch. Each is applied and logged as by
+    /// `process_with`, but the liquidation scan, margin calls and alerts they call for
+    /// run once, after the last of them, caused by a `BatchEnded` marker. A
+    /// `BatchStarted` marker opens the batch, so replay knows the scan is not due
+    /// until then.
+    ///
+    /// An account that an event of the batch leaves at or under maintenance margin
+    /// stays there until the end: its later fills that add risk, and its withdrawals,
+    /// are rejected with `RejectReason::AccountInLiquidation`, while reducing fills
+    /// are accepted. The check reads the state the earlier events left, so replay
+    /// rejects the same fills.
+    ///
+    /// Returns the outcomes in submission order. Refusals by a `SnapshotSink` during
+    /// the end scan are reported on the last outcome. An empty batch logs nothing.
+    pub fn process_batch(
+        &mut self,
+        submissions: impl IntoIterator<Item = (EventType, Submission)>,
+    ) -> Vec<ProcessOutcome> {
+        let submissions: Vec<(EventType, Submission)> = submissions.into_iter().collect();
+        if let Some((sequence, error)) = &self.log_store_failure {
+            let failed = ProcessOutcome::LogStoreFailed {
+                sequence: *sequence,
+                error: error.clone(),
+            };
+            return vec![failed; submissions.len()];
+        }
+        if submissions.is_empty() {
+            return Vec::new();
+        }
+        let count = submissions.len() as u64;
+        self.open_log();
+        self.record_marker(EventType::BatchStarted { submissions: count });
+        self.batch = Some(BTreeSet::new());
+        let mut outcomes: Vec<ProcessOutcome> = submissions
+            .into_iter()
+            .map(|(event_type, submission)| self.process_with(event_type, submission))
+            .collect();
+        let accounts = self.batch.take().unwrap_or_default();
+        // Past a failed append the engine takes nothing more, its own records included.
+        if self.log_store_failure.is_some() {
+            return outcomes;
+        }
+
+        let sequence = self.record_marker(EventType::BatchEnded { submissions: count });
+        self.scan(accounts, sequence);
+        let last = outcomes.pop().expect("a non-empty batch");
+        outcomes.push(if let Some((sequence, error)) = &self.log_store_failure {
+            self.sink_errors.clear();
+            ProcessOutcome::LogStoreFailed {
+                sequence: *sequence,
+                error: error.clone(),
+            }
+        } else if self.sink_errors.is_empty() {
+            last
+        } else {
+            let mut errors = std::mem::take(&mut self.sink_errors);
+            match last {
+                ProcessOutcome::SinkFailed {
+                    outcome,
+                    errors: mut earlier,
+                } => {
+                    earlier.append(&mut errors);
+                    ProcessOutcome::SinkFailed {
+                        outcome,
+                        errors: earlier,
+                    }
+                }
+                outcome => ProcessOutcome::SinkFailed {
+                    outcome: Box::new(outcome),
+                    errors,
+                },
+            }
+        });
+        outcomes
+    }
+
+    /// Before the first event is logged: publish views if risk deltas are on, since
+    /// deltas come from the accounts each view changes, and write the `ConfigMarker`.
+    fn open_log(&mut self) {
+        if self.config.risk_deltas != RiskDeltaPolicy::Off {
+            self.views();
+        }
+
+        if self.events_recorded == 0 {
+            let marker = Event::generated(
+                self.next_sequence,
+                EventType::ConfigMarker {
+                    config_hash: self.config.hash(),
+                    config: self.config.clone(),
+                },
+            );
+            self.next_sequence += 1;
+            self.record(marker);
+        }
+    }
+
+    /// Apply and log a batch marker, which has no cause. Returns its sequence.
+    fn record_marker(&mut self, event_type: EventType) -> u64 {
+        let sequence = self.next_sequence;
+        let marker = Event::generated(sequence, event_type);
+        self.next_sequence += 1;
+        self.apply_event(&marker);
+        self.record(marker);
+        sequence
+    }
+
+    fn process_submission(
+        &mut self,
+        event_type: EventType,
+        submission: Submission,
+    ) -> ProcessOutcome {
+        let Submission {
+            idempotency_key,
+            timestamp,
+        } = submission;
+        self.open_log();
+
+        // The submission's sequence, or its `DuplicateIgnored`'s.
+        let _span = trace::span!(
+            "process",
+            sequence = self.next_sequence,
+            kind = event_type.kind()
+        );
+
+        if let Some(key) = &idempotency_key {
+            if let Some(original_sequence) = self.state.idempotency.get(key) {
+                let sequence = self.next_sequence;
+                let duplicate = Event::generated(
+                    sequence,
+                    EventType::DuplicateIgnored {
+                        key: key.clone(),
+                        original_sequence,
+                    },
+                );
+                self.next_sequence += 1;
+                // Not applied, but it still ends any cascade, as it does on replay.
+                self.state.in_liquidation.clear();
+                self.state.liquidated_markets.clear();
+                self.record(duplicate);
+                return ProcessOutcome::Duplicate {
+                    sequence,
+                    original_sequence,
+                };
+            }
+        }
+
+        // A submission that may repeat its account's latest rejection is applied first,
+        // and suppressed if it is rejected the same way again. Any other submission
+        // ends the bursts of the accounts it names before it is logged.
+        let repeat = self.repeated_rejection(&event_type, idempotency_key.is_none());
+        if repeat.is_none() {
+            self.summarize_suppressed(&event_type.accounts());
+        }
+        let saved = repeat.map(|history| {
+            let cascade = (
+                self.state.in_liquidation.clone(),
+                self.state.liquidated_markets.clone(),
+            );
+            // Its clock can age fills out of a `Millis` window.
+            let stats = self
+                .config
+                .trade_stats
+                .is_some()
+                .then(|| self.state.trade_stats.clone());
+            (history, self.state.clock, cascade, stats)
+        });
+
+        let mut sequence = self.next_sequence;
+        let mut event = Event::new(sequence, event_type);
+        event.idempotency_key = idempotency_key;
+        event.timestamp = timestamp;
+        self.next_sequence += 1;
+
+        // Indistinguishable from the engine's own records once logged, so refused here
+        // rather than by `apply_event`, and never applied.
+        let result = if event.event_type.is_uncaused_marker() {
+            self.apply_envelope(&event);
+            ApplyResult::Rejected(ENGINE_GENERATED.to_string())
+        } else {
+            self.apply_event(&event)
+        };
+        let reject_type = match &result {
+            ApplyResult::Ok => None,
+            ApplyResult::Rejected(reason) | ApplyResult::InvalidDerived(reason) => {
+                Some(events::rejection_for(&event.event_type, reason.clone()))
+            }
+        };
+
+        if let Some(((account_id, history), clock, (in_liquidation, liquidated_markets), stats)) =
+            saved
+        {
+            if reject_type.is_some() && reject_type == history.last {
+                // Nothing of it is kept: not its sequence, its clock, or its count.
+                self.next_sequence = sequence;
+                self.state.clock = clock;
+                if let Some(stats) = stats {
+                    self.state.trade_stats = stats;
+                }
+                self.state.in_liquidation = in_liquidation;
+                self.state.liquidated_markets = liquidated_markets;
+                self.state
+                    .rejection_history
+                    .insert(account_id.clone(), history);
+                let reason = reject_type.as_ref().and_then(RejectReason::from_event);
+                self.suppress(account_id, sequence - 1);
+                return ProcessOutcome::Suppressed {
+                    reason: reason.expect("a rejection event"),
+                };
+            }
+            // Not a repeat after all. Its burst still ends before it: the summary takes
+            // its sequence, checked against the history it was throttled by, and the
+            // event moves up one. Nothing a submission naming an account applies
+            // depends on its own sequence.
+            if self.suppressed.contains_key(&account_id) {
+                let after = self
+                    .state
+                    .rejection_history
+                    .insert(account_id.clone(), history);
+                self.next_sequence = sequence;
+                self.summarize_suppressed(&[&account_id]);
+                if let Some(after) = after {
+                    self.state.rejection_history.insert(account_id, after);
+                }
+                sequence = self.next_sequence;
+                event.sequence = sequence;
+                self.next_sequence += 1;
+            }
+        }
+
+        // Handle rejections. An external event the engine cannot make sense of, which
+        // `apply_event` reports as an invalid derived event, changed nothing either.
+        if let Some(reject_type) = reject_type {
+            // State is unchanged, so unless the config keeps one for the attempt too, a
+            // single snapshot under the rejection's sequence covers both events.
+            let reason = RejectReason::from_event(&reject_type).expect("a rejection event");
+            let keep = self.config.rejected_attempt_snapshots == RejectedAttemptSnapshots::Keep;
+            self.record_with(event, keep);
+            let reject_event = Event::derived(self.next_sequence, reject_type, sequence);
+            self.next_sequence += 1;
+            self.track_rejections(&reject_event);
+            self.record(reject_event);
+            return ProcessOutcome::Rejected { sequence, reason };
+        }
+
+        // Determine which accounts need liquidation scanning based on event type.
+        // Use a BTreeSet to canonicalize ordering and deduplicate deterministically.
+        let accounts_to_scan: BTreeSet<AccountId> = match &event.event_type {
+            EventType::TradeFill { account_id, .. } | EventType::StateImport { account_id, .. } => {
+                [account_id.clone()].into_iter().collect()
+            }
+            EventType::LiquidationTakeover {
+                liquidated_account,
+                keeper_account,
+                ..
+            } => [liquidated_account.clone(), keeper_account.clone()]
+                .into_iter()
+                .collect(),
+            EventType::PositionTransfer { from, to, .. } => {
+                [from.clone(), to.clone()].into_iter().collect()
+            }
+            EventType::MarkPriceUpdate { market_id, .. } => self
+                .state
+                .accounts_with_position_in(market_id)
+                .into_iter()
+                .collect(),
+            // One scan over every account the tick touched, after all marks moved.
+            EventType::MarkPriceBatch { updates } => updates
+                .keys()
+                .flat_map(|market_id| self.state.accounts_with_position_in(market_id))
+                .collect(),
+            // The settlement moved the holders to the final price; they are named by
+            // the settlement records. Likewise the accounts a tick charged or credited,
+            // and those a yield distribution paid.
+            EventType::Expiry { .. }
+            | EventType::InterestTick { .. }
+            | EventType::YieldDistribution { .. } => self
+                .pending_derived
+                .iter()
+                .flat_map(|derived| derived.accounts())
+                .cloned()
+                .collect(),
+            // A new `closed_session_liquidation` may release a deferred liquidation.
+            EventType::ConfigUpdated { .. } => {
+                self.state.deferred_liquidations.iter().cloned().collect()
+            }
+            // Includes the accounts whose deferred liquidation the open released.
+            EventType::FundingUpdate { market_id, .. }
+            | EventType::FundingRate { market_id, .. }
+            | EventType::FundingAccrual { market_id, .. }
+            | EventType::SessionOpen { market_id } => self
+                .state
+                .accounts_with_position_in(market_id)
+                .into_iter()
+                .collect(),
+            _ => BTreeSet::new(),
+        };
+
+        let force_close = match &event.event_type {
+            EventType::ForceClose { account_id, reason } => {
+                Some((account_id.clone(), reason.clone()))
+            }
+            _ => None,
+        };
+
+        // Snapshot BEFORE liquidation scanning — this is the state after just this event
+        self.record(event);
+
+        // Informational events derived during apply (state already reflects them)
+        for derived in std::mem::take(&mut self.pending_derived) {
+            let derived_event = Event::derived(self.next_sequence, derived, sequence);
+            self.next_sequence += 1;
+            self.record(derived_event);
+        }
+
+        // A force close flattens the account it just froze, one position at a time.
+        if let Some((account_id, reason)) = force_close {
+            let strategy = self.config.liquidation_strategy;
+            for step in liquidation::force_close_steps(&self.state, &account_id, strategy) {
+                self.apply_derived(
+                    EventType::ForceCloseFill {
+                        account_id: account_id.clone(),
+                        market_id: step.market_id,
+                        quantity: step.close_quantity,
+                        price: step.price,
+                        reason: reason.clone(),
+                    },
+                    sequence,
+                );
+            }
+        }
+
+        // Inside a batch the scan waits for its end, which covers every account the
+        // batch's events named.
+        match &mut self.batch {
+            Some(batch) => batch.extend(accounts_to_scan),
+            None => self.scan(accounts_to_scan, sequence),
+        }
+        ProcessOutcome::Accepted { sequence }
+    }
+
+    /// The scan after the event at `sequence`, which named `accounts_to_scan`, and
+    /// every account under a margin call, since a call can expire on any event.
+    fn scan(&mut self, mut accounts_to_scan: BTreeSet<AccountId>, sequence: u64) {
+        accounts_to_scan.extend(
+            self.state
+                .accounts
+                .values()
+                .filter(|account| account.margin_call.is_some())
+                .map(|account| account.account_id.clone()),
+        );
+
+        // Reservations go before liquidation: an account whose positions still cover
+        // IM is only released from the orders it can no longer back.
+        if self.config.reservation_breach == ReservationBreach::AutoCancel {
+            let breaches: Vec<(AccountId, Vec<OrderId>, String)> = self
+                .state
+                .accounts
+                .values()
+                .filter_map(|account| {
+                    let order_ids = risk::orders_to_auto_cancel(account, &self.state);
+                    (!order_ids.is_empty()).then(|| {
+                        let reason = format!(
+                            "Equity {} below IM {} plus reserved {}",
+                            margin::equity(account, &self.state).normalize(),
+                            margin::initial_margin_required(account, &self.state).normalize(),
+                            margin::reserved_margin(account, &self.state).normalize()
+                        );
+                        (account.account_id.clone(), order_ids, reason)
+                    })
+                })
+                .collect();
+            for (account_id, order_ids, reason) in breaches {
+                self.apply_derived(
+                    EventType::OrdersAutoCancelled {
+                        account_id,
+                        order_ids,
+                        reason,
+                    },
+                    sequence,
+                );
+            }
+        }
+
+        // Execute liquidations one event at a time, through the same apply path as
+        // replay, and snapshot after each. The records one account's liquidation
+        // generates can push others it names under MM, such as the accounts a
+        // `LossSocialized` charges; those are rescanned until none is left, up to
+        // `max_cascade_rescans` times.
+        let keepers = match &self.config.liquidation_path {
+            LiquidationPath::EngineClose => Vec::new(),
+            LiquidationPath::Keepers(keepers) => keepers.clone(),
+        };
+        let mut candidates = accounts_to_scan;
+        let mut rescans = 0;
+        loop {
+            let mut touched = BTreeSet::new();
+            for account_id in self.scan_order(candidates) {
+                touched.extend(self.check_and_liquidate(&account_id, sequence, &keepers));
+            }
+            candidates =
+                touched
+                    .into_iter()
+                    .filter(|account_id| {
+                        !self.state.deferred_liquidations.contains(account_id)
+                            && self.state.accounts.get(account_id).is_some_and(|account| {
+                                margin::is_liquidatable(account, &self.state)
+                            })
+                    })
+                    .collect();
+            if candidates.is_empty() || rescans == self.config.max_cascade_rescans {
+                break;
+            }
+            rescans += 1;
+            trace::event!(
+                rescan = rescans,
+                accounts = candidates.len(),
+                "cascade rescan"
+            );
+        }
+
+        // Alert levels and skew move on the state the whole cascade left.
+        for record in self.due_records() {
+            self.apply_derived(record, sequence);
+        }
+    }
+
+    /// Liquidate `account_id`, scanned after the event at `sequence`, as far as it
+    /// needs: its liquidation steps, then the settlement of any deficit. Returns the
+    /// other accounts the records it generated name, for the cascade to rescan.
+    fn check_and_liquidate(
+        &mut self,
+        account_id: &AccountId,
+        sequence: u64,
+        keepers: &[AccountId],
+    ) -> BTreeSet<AccountId> {
+        let _span = trace::span!("check_and_liquidate", account_id = %account_id, sequence);
+        let mut touched = BTreeSet::new();
+        if self.in_grace(account_id, sequence) {
+            trace::event!("margin call grace");
+            return touched;
+        }
+        let strategy = self.config.liquidation_strategy;
+        let closed_sessions = self.config.closed_session_liquidation;
+        let mut record = |engine: &mut Self, event_type: EventType| {
+            touched.extend(
+                event_type
+                    .accounts()
+                    .into_iter()
+                    .filter(|id| *id != account_id)
+                    .cloned(),
+            );
+            engine.apply_derived(event_type, sequence);
+        };
+        let mut liquidated = false;
+        loop {
+            let next = match &mut self.liquidator {
+                Some(liquidator) => liquidator(&self.state, account_id),
+                None => liquidation::next_liquidation_with_sessions(
+                    &self.state,
+                    account_id,
+                    keepers,
+                    strategy,
+                    closed_sessions,
+                ),
+            };
+            let Some(event_type) = next else { break };
+            record(self, event_type);
+            liquidated = true;
+        }
+
+        // Whatever is left waits for its session to open rather than going bankrupt.
+        let market_ids = liquidation::deferred_markets(&self.state, account_id, closed_sessions);
+        if market_ids.is_empty() {
+            // Only a liquidation settles a deficit. A flat account the event merely
+            // touched, such as one charged interest, owes its negative balance; no
+            // logged event would put it into bankruptcy on replay.
+            let liquidatable = self
+                .state
+                .accounts
+                .get(account_id)
+                .is_some_and(|account| margin::is_liquidatable(account, &self.state));
+            if liquidated || liquidatable {
+                liquidation::finish_liquidation(&mut self.state, account_id);
+            }
+            if let Some(payout) = liquidation::insurance_payout(&self.state, account_id) {
+                record(self, payout);
+            }
+            let policy = self.config.residual_deficit;
+            if policy.socializes() {
+                if let Some(charge) =
+                    liquidation::loss_socialization(&self.state, account_id, policy)
+                {
+                    record(self, charge);
+                }
+            }
+        } else if !self.state.deferred_liquidations.contains(account_id) {
+            record(
+                self,
+                EventType::LiquidationDeferred {
+                    account_id: account_id.clone(),
+                    market_ids,
+                },
+            );
+        }
+        touched
+    }
+
+    /// The `RiskAlert` and `RiskAlertCleared` events the ladder calls for in the current
+    /// state, in account order. Every account is checked: a mark, a hedge pair or a
+    /// funding settlement moves usage without naming the accounts it moves.
+    fn risk_alerts(&self) -> Vec<EventType> {
+        let Some(ladder) = &self.config.risk_alerts else {
+            return Vec::new();
+        };
+        self.state
+            .accounts
+            .values()
+            .filter(|account| !account.positions.is_empty() || account.alert_level > 0)
+            .filter_map(|account| {
+                let margin_usage = margin::margin_usage(account, &self.state);
+                let level = ladder.level(margin_usage, account.alert_level);
+                let account_id = account.account_id.clone();
+                match level.cmp(&account.alert_level) {
+                    std::cmp::Ordering::Greater => Some(EventType::RiskAlert {
+                        account_id,
+                        level,
+                        margin_usage,
+                    }),
+                    std::cmp::Ordering::Less => Some(EventType::RiskAlertCleared {
+                        account_id,
+                        level,
+                        margin_usage,
+                    }),
+                    std::cmp::Ordering::Equal => None,
+                }
+            })
+            .collect()
+    }
+
+    /// The `SkewLimitBreached` and `SkewLimitCleared` events the markets' skew limits
+    /// call for in the current state, in market order.
+    fn skew_records(&self) -> Vec<EventType> {
+        self.state
+            .markets
+            .values()
+            .filter_map(|market| {
+                let limit = market.skew_limit_notional?;
+                let market_id = market.market_id.clone();
+                let net_notional = self.state.net_notional(&market_id);
+                match (net_notional.abs() > limit, market.skew_breached) {
+                    (true, false) => Some(EventType::SkewLimitBreached {
+                        market_id,
+                        net_notional,
+                    }),
+                    (false, true) => Some(EventType::SkewLimitCleared {
+                        market_id,
+                        net_notional,
+                    }),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    /// The margin calls, then the risk alerts, then the skew records, the current
+    /// state calls for.
+    fn due_records(&self) -> Vec<EventType> {
+        self.margin_calls()
+            .into_iter()
+            .chain(self.risk_alerts())
+            .chain(self.skew_records())
+            .collect()
+    }
+
+    /// Whether the liquidation of `account_id`, at or under maintenance margin after
+    /// the event at `sequence`, waits under `EngineConfig::margin_call`. It waits
+    /// while its equity is at least `hard_fraction` of its MM and its call, if it has
+    /// one yet, has not expired. Without one, the call is logged after the cascade.
+    fn in_grace(&self, account_id: &AccountId, sequence: u64) -> bool {
+        let (Some(policy), Some(account)) = (
+            &self.config.margin_call,
+            self.state.accounts.get(account_id),
+        ) else {
+            return false;
+        };
+        if !margin::is_liquidatable(account, &self.state) {
+            return false;
+        }
+        let maintenance_margin = margin::maintenance_margin_required(account, &self.state);
+        if margin::equity(account, &self.state) < policy.hard_fraction * maintenance_margin {
+            return false;
+        }
+        let Some(call) = account.margin_call else {
+            return true;
+        };
+        match policy.grace {
+            GracePeriod::Events(events) => sequence.saturating_sub(call.breached_at) < events,
+            GracePeriod::Millis(millis) => {
+                self.state.clock.unwrap_or(0).saturating_sub(call.clock) < millis
+            }
+        }
+    }
+
+    /// The `MarginCall` and `MarginCallCleared` events the current state calls for, in
+    /// account order. Under `EngineConfig::margin_call`, an account at or under
+    /// maintenance margin without a call gets one. An account with a call that is
+    /// no longer there has it cleared, whatever the config.
+    fn margin_calls(&self) -> Vec<EventType> {
+        let policy = self.config.margin_call.is_some();
+        self.state
+            .accounts
+            .values()
+            .filter(|account| policy || account.margin_call.is_some())
+            .filter_map(|account| {
+                let liquidatable = margin::is_liquidatable(account, &self.state);
+                if liquidatable == account.margin_call.is_some() || (liquidatable && !policy) {
+                    return None;
+                }
+                let account_id = account.account_id.clone();
+                let equity = margin::equity(account, &self.state);
+                let maintenance_margin = margin::maintenance_margin_required(account, &self.state);
+                Some(if liquidatable {
+                    EventType::MarginCall {
+                        account_id,
+                        equity,
+                        maintenance_margin,
+                    }
+                } else {
+                    EventType::MarginCallCleared {
+                        account_id,
+                        equity,
+                        maintenance_margin,
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Log and apply one engine-generated liquidation step or risk alert through the
+    /// replay path, caused by the external event at `caused_by`.
+    fn apply_derived(&mut self, event_type: EventType, caused_by: u64) {
+        let event = Event::derived(self.next_sequence, event_type, caused_by);
+        self.next_sequence += 1;
+        if let ApplyResult::Rejected(reason) | ApplyResult::InvalidDerived(reason) =
+            self.apply_event(&event)
+        {
+            unreachable!("engine-generated event failed to apply: {reason}");
+        }
+        self.record(event);
+    }
+
+    /// Order scan candidates by the configured `ScanOrder`. Keys are computed once per
+    /// scan, on the state before any of its liquidations execute: for the first scan
+    /// that is the state the event left, for a rescan the state the scan before left.
+    fn scan_order(&self, candidates: BTreeSet<AccountId>) -> Vec<AccountId> {
+        let mut ordered: Vec<AccountId> = candidates.into_iter().collect();
+        let state = &self.state;
+        match self.config.scan_order {
+            ScanOrder::AccountId => {}
+            ScanOrder::WorstMarginRatioFirst => {
+                // `None` (no maintenance margin) sorts after every ratio. A ratio beyond
+                // `Decimal`'s range, over a vanishing margin, saturates.
+                ordered.sort_by_cached_key(|id| {
+                    let ratio = state.accounts.get(id).and_then(|account| {
+                        let mm = margin::maintenance_margin_required(account, state);
+                        let equity = margin::equity(account, state);
+                        (!mm.is_zero()).then(|| {
+                            equity
+                                .checked_div(mm)
+                                .unwrap_or(if equity.is_sign_negative() {
+                                    Decimal::MIN
+                                } else {
+                                    Decimal::MAX
+                                })
+                        })
+                    });
+                    (ratio.is_none(), ratio)
+                });
+            }
+            ScanOrder::LargestNotionalFirst => {
+                ordered.sort_by_cached_key(|id| {
+                    let notional = state
+                        .accounts
+                        .get(id)
+                        .map(|account| margin::total_notional(account, state))
+                        .unwrap_or(Decimal::ZERO);
+                    std::cmp::Reverse(notional)
+                });
+            }
+        }
+        ordered
+    }
+
+    /// Append an event to the log, publish its view, notify observers, and snapshot
+    /// the current state under its sequence if the policy keeps it.
+    fn record(&mut self, event: Event) {
+        self.record_with(event, true);
+    }
+
+    /// Like `record`, but `keep_snapshot = false` only hands the snapshot to observers
+    /// instead of retaining it (used for a rejected primary event, whose state is
+    /// identical to that of the rejection event that follows it, under
+    /// `RejectedAttemptSnapshots::Skip`).
+    fn record_with(&mut self, mut event: Event, keep_snapshot: bool) {
+        if self.log_store_failure.is_some() {
+            return;
+        }
+        self.assert_solvent(event.sequence);
+        event.dry_run = self.config.mode == EngineMode::DryRun;
+        let keep_snapshot = keep_snapshot
+            && self
+                .config
+                .snapshot_policy
+                .captures(self.events_recorded + 1, &event.event_type);
+
+        // Durable before anyone is told about it. Continuing after a failed append
+        // would acknowledge an event the log does not have.
+        if let Some(store) = &mut self.log_store {
+            if let Err(e) = store.append(&event) {
+                self.log_store_failure = Some((
+                    event.sequence,
+                    format!("failed to append to {}: {e}", store.path().display()),
+                ));
+                return;
+            }
+        }
+
+        let deltas = self.publish_view(event.sequence, Some(&event.event_type));
+        // Observers are handed every event's snapshot. Without them it is captured only
+        // when the policy keeps it.
+        let snapshot = (keep_snapshot || !self.observers.is_empty())
+            .then(|| snapshot::capture(&self.state, event.sequence));
+        if let Some(snapshot) = &snapshot {
+            for observer in &mut self.observers {
+                match self.config.mode {
+                    EngineMode::Live => observer.on_event(&event, snapshot),
+                    EngineMode::DryRun => observer.on_dry_run_event(&event, snapshot),
+                }
+                for delta in &deltas {
+                    match self.config.mode {
+                        EngineMode::Live => observer.on_risk_delta(delta),
+                        EngineMode::DryRun => observer.on_dry_run_risk_delta(delta),
+                    }
+                }
+            }
+        }
+        if self.config.risk_deltas == RiskDeltaPolicy::ObserversAndQueue {
+            self.risk_delta_queue.extend(deltas);
+        }
+
+        self.event_log.push(Arc::new(event));
+        self.events_recorded += 1;
+        if let Some(snapshot) = snapshot.filter(|_| keep_snapshot) {
+            match &mut self.snapshot_sink {
+                Some(sink) => {
+                    let sequence = snapshot.after_sequence;
+                    if let Err(e) = sink.accept(snapshot) {
+                        self.sink_errors.push((sequence, e));
+                    }
+                }
+                None => {
+                    self.snapshots.push(snapshot);
+                    self.config.snapshot_policy.retain(&mut self.snapshots);
+                }
+            }
+        }
+        self.trim_memory();
+    }
+
+    /// Apply an accepted `AccountsMerged`: install the merged account at `to`, move
+    /// `from`'s fills into `to`'s trade statistics, and close `from`.
+    fn merge_accounts(&mut self, from: &AccountId, to: &AccountId) {
+        let merged = risk::merged_account(&self.state, &self.state.accounts[from], to);
+        self.state.accounts.insert(to.clone(), merged);
+        if let (Some(stats), Some(fills)) = (
+            &self.config.trade_stats,
+            self.state.trade_stats.remove(from),
+        ) {
+            let into = self.state.trade_stats.entry(to.clone()).or_default();
+            into.absorb(fills, stats.window);
+        }
+        self.state.remove_account(from);
+    }
+
+    /// Apply an accepted `PositionTransfer`: install both accounts as it leaves them,
+    /// and record the pending funding each settled first as a settlement would.
+    fn transfer_position(
+        &mut self,
+        from: &AccountId,
+        to: &AccountId,
+        market_id: &MarketId,
+        quantity: Decimal,
+        price: Decimal,
+    ) {
+        for (account, amount) in
+            risk::transferred_accounts(&self.state, from, to, market_id, quantity, price)
+        {
+            if !amount.is_zero() {
+                self.metrics
+                    .record(&account.pool_id, |m| m.funding += amount);
+                self.pending_derived.push(EventType::FundingPayment {
+                    account_id: account.account_id.clone(),
+                    market_id: market_id.clone(),
+                    amount,
+                });
+            }
+            self.state
+                .accounts
+                .insert(account.account_id.clone(), account);
+        }
+    }
+
+    /// Under `EngineConfig::assert_solvency` in a debug build, panic unless the books
+    /// balance after `sequence`.
+    fn assert_solvent(&self, sequence: u64) {
+        if cfg!(debug_assertions) && self.config.assert_solvency {
+            let report = self.solvency();
+            assert!(
+                report.is_balanced(),
+                "books do not balance after seq {sequence}: {report:?}"
+            );
+        }
+    }
+
+    /// With a log store, drop events (and their snapshots) that are safely on disk
+    /// once memory holds more than the store allows.
+    fn trim_memory(&mut self) {
+        let Some(store) = &mut self.log_store else {
+            return;
+        };
+        let excess = match store.spill(self.event_log.len()) {
+            Ok(excess) => excess,
+            Err(e) => {
+                let sequence = self.event_log.last().map_or(0, |event| event.sequence);
+                self.log_store_failure = Some((
+                    sequence,
+                    format!("failed to flush {}: {e}", store.path().display()),
+                ));
+                return;
+            }
+        };
+        if excess == 0 {
+            return;
+        }
+        self.event_log.drain(..excess);
+        let first_kept = self.event_log[0].sequence;
+        let stale = self
+            .snapshots
+            .partition_point(|s| s.after_sequence < first_kept);
+        self.snapshots.drain(..stale);
+    }
+
+    /// Log the `RejectionSuppressed` summaries of the bursts still going on, as before
+    /// shutting down. Otherwise a burst is summarized only when its account's next
+    /// submission is logged or it reaches `RejectionThrottle::summary_every`.
+    pub fn summarize_suppressed_rejections(&mut self) {
+        let accounts: Vec<AccountId> = self.suppressed.keys().cloned().collect();
+        self.summarize_suppressed(&accounts.iter().collect::<Vec<_>>());
+    }
+
+    /// The account whose latest rejection `event_type` may repeat, with its rejection
+    /// history: a submission without an idempotency key, naming one throttled account,
+    /// whose rejection record for that rejection's reason would be the same record.
+    fn repeated_rejection(
+        &self,
+        event_type: &EventType,
+        keyless: bool,
+    ) -> Option<(AccountId, RejectionHistory)> {
+        let throttle = self.config.rejection_throttle.as_ref()?;
+        let [account_id] = event_type.accounts()[..] else {
+            return None;
+        };
+        let history = self.state.rejection_history.get(account_id)?;
+        let last = history.last.as_ref()?;
+        let reason = RejectReason::from_event(last)?.message().to_string();
+        let repeats = keyless
+            && history.throttles(history.submissions + 1, throttle)
+            && events::rejection_for(event_type, reason) == *last;
+        repeats.then(|| (account_id.clone(), history.clone()))
+    }
+
+    /// Count a suppressed submission from `account_id`, which arrived after seq
+    /// `logged`, and summarize the burst once it holds `summary_every` of them.
+    fn suppress(&mut self, account_id: AccountId, logged: u64) {
+        let Some(throttle) = &self.config.rejection_throttle else {
+            return;
+        };
+        let summary_every = throttle.summary_every;
+        let burst = self
+            .suppressed
+            .entry(account_id.clone())
+            .or_insert(SuppressedBurst {
+                count: 0,
+                first_sequence: logged,
+                last_sequence: logged,
+            });
+        burst.count += 1;
+        burst.last_sequence = logged;
+        if burst.count >= summary_every {
+            self.summarize_suppressed(&[&account_id]);
+        }
+    }
+
+    /// Log a `RejectionSuppressed` for each of `accounts` with a burst going on.
+    fn summarize_suppressed(&mut self, accounts: &[&AccountId]) {
+        for &account_id in accounts {
+            let Some(burst) = self.suppressed.remove(account_id) else {
+                continue;
+            };
+            let summary = Event::generated(
+                self.next_sequence,
+                EventType::RejectionSuppressed {
+                    account_id: account_id.clone(),
+                    count: burst.count,
+                    first_sequence: burst.first_sequence,
+                    last_sequence: burst.last_sequence,
+                },
+            );
+            self.next_sequence += 1;
+            let result = self.apply_event(&summary);
+            debug_assert!(
+                matches!(result, ApplyResult::Ok),
+                "seq {}",
+                summary.sequence
+            );
+            self.record(summary);
+        }
+    }
+
+    /// Under `EngineConfig::rejection_throttle`, count a logged submission, or the
+    /// rejection record of one, towards the accounts it names.
+    fn track_rejections(&mut self, event: &Event) {
+        let Some(throttle) = &self.config.rejection_throttle else {
+            return;
+        };
+        let rejection = event.event_type.is_rejection();
+        if !rejection && (event.caused_by.is_some() || event.event_type.is_engine_generated()) {
+            return;
+        }
+        for account_id in event.event_type.accounts() {
+            let history = self
+                .state
+                .rejection_history
+                .entry(account_id.clone())
+                .or_default();
+            if rejection {
+                history.reject(&event.event_type, throttle);
+            } else {
+                history.submissions += 1;
+            }
+        }
+    }
+
+    /// Count a fill in the account's statistics, under `EngineConfig::trade_stats`.
+    fn record_fill(
+        &mut self,
+        account_id: &AccountId,
+        notional: Decimal,
+        liquidation: bool,
+        liquidity: Option<Liquidity>,
+    ) {
+        let Some(stats) = &self.config.trade_stats else {
+            return;
+        };
+        let fill = StatsFill {
+            clock: self.state.clock.unwrap_or(0),
+            notional,
+            liquidation,
+            liquidity,
+        };
+        let account_stats = self
+            .state
+            .trade_stats
+            .entry(account_id.clone())
+            .or_default();
+        account_stats.record(fill, stats.window);
+    }
+
+    /// Charge `rate` on a fill of `notional`: debit the fee from the account's trading
+    /// balance, book it as its pool's fee revenue and record it as a `FeeCharged`. The
+    /// fee is rounded up to collateral precision, so a charge never comes out smaller
+    /// than the rate implies and a rebate never larger.
+    fn charge_fee(
+        &mut self,
+        account_id: &AccountId,
+        market_id: &MarketId,
+        rate: Decimal,
+        notional: Decimal,
+    ) {
+        let amount = (notional * rate).round_dp_with_strategy(
+            margin::COLLATERAL_DECIMALS,
+            RoundingStrategy::ToPositiveInfinity,
+        );
+        if amount.is_zero() {
+            return;
+        }
+        let account = self.state.accounts.get_mut(account_id).unwrap();
+        account.trading_balance -= amount;
+        self.metrics.record(&account.pool_id, |m| m.fees += amount);
+        self.pending_derived.push(EventType::FeeCharged {
+            account_id: account_id.clone(),
+            market_id: market_id.clone(),
+            rate,
+            amount,
+        });
+    }
+
+    /// What every logged event does to state, applied or not: it moves the clock, uses
+    /// its idempotency key, and ends a liquidation cascade unless it is part of one.
+    fn apply_envelope(&mut self, event: &Event) {
+        if let Some(timestamp) = event.timestamp {
+            self.state.advance_clock(timestamp);
+            if let (Some(stats), Some(clock)) = (&self.config.trade_stats, self.state.clock) {
+                for (account_id, account_stats) in self.state.trade_stats.iter_mut() {
+                    if account_stats.expire(clock, stats.window) && self.views.is_some() {
+                        self.touched.insert(account_id.clone());
+                    }
+                }
+            }
+        }
+
+        // The key counts as used even if the event is rejected: a retry of a rejected
+        // submission is still a retry.
+        if let Some(key) = &event.idempotency_key {
+            self.state
+                .idempotency
+                .remember(key, event.sequence, self.config.idempotency_window);
+        }
+
+        // The in-liquidation marker lasts for one cascade: the run of liquidation
+        // events right after the event that triggered them, and the alerts after those.
+        if !matches!(
+            event.event_type,
+            EventType::LiquidationFill { .. }
+                | EventType::LiquidationTakeover { .. }
+                | EventType::LiquidationDeferred { .. }
+                | EventType::InsuranceFundPayout { .. }
+                | EventType::LossSocialized { .. }
+                | EventType::RiskAlert { .. }
+                | EventType::RiskAlertCleared { .. }
+                | EventType::MarginCall { .. }
+                | EventType::MarginCallCleared { .. }
+                | EventType::SkewLimitBreached { .. }
+                | EventType::SkewLimitCleared { .. }
+        ) {
+            self.state.in_liquidation.clear();
+            self.state.liquidated_markets.clear();
+        }
+    }
+
+    /// Apply a single event to state. Pure state mutation — no liquidation scanning,
+    /// no event generation. Used identically in live and replay modes.
+    fn apply_event(&mut self, event: &Event) -> ApplyResult {
+        let _span = trace::span!(
+            "apply_event",
+            sequence = event.sequence,
+            kind = event.event_type.kind(),
+            caused_by = event.caused_by
+        );
+        self.apply_envelope(event);
+
+        // Only the engine writes these, each as a record of the event that caused it.
+        // The markers without a cause are refused by `process_with` when submitted.
+        if event.caused_by.is_none()
+            && event.event_type.is_engine_generated()
+            && !event.event_type.is_uncaused_marker()
+        {
+            return ApplyResult::Rejected(ENGINE_GENERATED.to_string());
+        }
+        self.track_rejections(event);
+        // A rejection, an ignored duplicate, or a record of what its trigger already
+        // applied: nothing to do beyond counting the rejection.
+        if event.event_type.is_informational() {
+            return ApplyResult::Ok;
+        }
+        if let TradeCheck::Rejected(reason) = risk::check_event_values(&event.event_type) {
+            return ApplyResult::Rejected(reason);
+        }
+
+        let result = match &event.event_type {
+            // Checked by replay before it is applied; carries no state.
+            EventType::ConfigMarker { .. } => ApplyResult::Ok,
+            // Bracket the events whose scan waits for the end of their batch. Replay
+            // checks that they pair up.
+            EventType::BatchStarted { .. } | EventType::BatchEnded { .. } => ApplyResult::Ok,
+
+            // The summary of a burst the account's history throttled, with nothing logged
+            // for it since.
+            EventType::RejectionSuppressed {
+                account_id,
+                count,
+                first_sequence,
+                last_sequence,
+            } => {
+                let throttled = self
+                    .config
+                    .rejection_throttle
+                    .as_ref()
+                    .is_some_and(|throttle| {
+                        self.state
+                            .rejection_history
+                            .get(account_id)
+                            .is_some_and(|history| {
+                                history.last.is_some()
+                                    && history.throttles(history.submissions + 1, throttle)
+                            })
+                    });
+                if !throttled {
+                    return ApplyResult::InvalidDerived(format!(
+                        "{account_id} has no throttled rejection to repeat"
+                    ));
+                }
+                if *count == 0 || first_sequence > last_sequence || *last_sequence >= event.sequence
+                {
+                    return ApplyResult::InvalidDerived(format!(
+                        "{count} suppressed between seq {first_sequence} and {last_sequence}"
+                    ));
+                }
+                self.metrics.rejections_suppressed += count;
+                ApplyResult::Ok
+            }
+
+            EventType::Deposit { account_id, amount } => {
+                let account = self.state.get_or_create_account(account_id);
+                account.principal += amount;
+                self.metrics
+                    .record(&account.pool_id, |m| m.deposits += amount);
+                // A deposit into a bankrupt account repays its deficit first.
+                if account.bankruptcy_deficit > Decimal::ZERO {
+                    account.bankruptcy_deficit =
+                        (account.bankruptcy_deficit - amount).max(Decimal::ZERO);
+                }
+                ApplyResult::Ok
+            }
+
+            EventType::Withdraw { account_id, amount } => {
+                match risk::check_withdrawal_with(&self.state, account_id, *amount, &self.config) {
+                    TradeCheck::Accepted => {
+                        let account = self.state.accounts.get_mut(account_id).unwrap();
+                        account.withdraw(*amount, self.config.withdrawal_order);
+                        self.metrics
+                            .record(&account.pool_id, |m| m.withdrawals += amount);
+                        ApplyResult::Ok
+                    }
+                    TradeCheck::Rejected(reason) => ApplyResult::Rejected(reason),
+                }
+            }
+
+            EventType::TradeFill {
+                account_id,
+                market_id,
+                quantity,
+                price,
+                liquidity,
+            } => match risk::check_trade_with(
+                &self.state,
+                account_id,
+                market_id,
+                *quantity,
+                *price,
+                &self.config,
+            ) {
+                TradeCheck::Accepted => {
+                    // Due at the turnover before this fill.
+                    let fee_rate = self.fee_rate(account_id);
+                    let account = self.state.accounts.get_mut(account_id).unwrap();
+                    apply_trade_to(
+                        &mut account.trading_balance,
+                        &mut account.positions,
+                        market_id,
+                        *quantity,
+                        *price,
+                    );
+                    let cash = *quantity * *price;
+                    self.metrics
+                        .record(&account.pool_id, |m| m.fill_cash_flow -= cash);
+                    if let Some(rate) = fee_rate {
+                        self.charge_fee(account_id, market_id, rate, cash.abs());
+                    }
+                    self.record_fill(account_id, cash.abs(), false, *liquidity);
+                    ApplyResult::Ok
+                }
+                TradeCheck::Rejected(reason) => ApplyResult::Rejected(reason),
+            },
+
+            EventType::MarkPriceUpdate { market_id, price } => {
+                let clock = self.state.clock;
+                let Some(market) = self.state.markets.get_mut(market_id) else {
+                    return self.unknown_market(market_id, event.sequence);
+                };
+                if let TradeCheck::Rejected(reason) = risk::check_price(market, *price) {
+                    return ApplyResult::Rejected(reason);
+                }
+                set_mark(market, *price, event.sequence, clock);
+                ApplyResult::Ok
+            }
+
+            EventType::MarkPriceBatch { updates } => {
+                // Validate every price before moving any mark, so the batch is all or nothing.
+                for (market_id, price) in updates {
+                    match self.state.markets.get(market_id) {
+                        Some(market) => {
+                            if let TradeCheck::Rejected(reason) = risk::check_price(market, *price)
+                            {
+                                return ApplyResult::Rejected(reason);
+                            }
+                        }
+                        None if self.config.unknown_markets == UnknownMarketPolicy::Reject => {
+                            return ApplyResult::Rejected(format!(
+                                "Unknown market_id: {market_id}"
+                            ));
+                        }
+                        None => {}
+                    }
+                }
+
+                let clock = self.state.clock;
+                let mut skipped = Vec::new();
+                for (market_id, price) in updates {
+                    match self.state.markets.get_mut(market_id) {
+                        Some(market) => set_mark(market, *price, event.sequence, clock),
+                        None => skipped.push(market_id.clone()),
+                    }
+                }
+                if !skipped.is_empty() {
+                    self.pending_derived.push(EventType::MarkPriceBatchSkipped {
+                        market_ids: skipped,
+                    });
+                }
+                ApplyResult::Ok
+            }
+
+            EventType::FundingUpdate {
+                market_id,
+                new_cumulative_index,
+            } => {
+                match self.state.markets.get(market_id) {
+                    None => return self.unknown_market(market_id, event.sequence),
+                    Some(market) if market.is_future() => return no_funding(market_id),
+                    Some(_) => {}
+                }
+                self.settle_funding(market_id, *new_cumulative_index);
+                ApplyResult::Ok
+            }
+
+            EventType::FundingRate {
+                market_id,
+                rate,
+                interval_id,
+            } => {
+                let Some(market) = self.state.markets.get(market_id) else {
+                    return self.unknown_market(market_id, event.sequence);
+                };
+                if market.is_future() {
+                    return no_funding(market_id);
+                }
+
+                if market.settled_funding_intervals.contains(interval_id) {
+                    return ApplyResult::Rejected(format!(
+                        "Funding interval {interval_id} already settled for {market_id}"
+                    ));
+                }
+
+                let new_index = market.cumulative_funding_index
+                    + margin::funding_index_increment(market, *rate);
+                if new_index.abs() > risk::MAX_EVENT_VALUE {
+                    return ApplyResult::Rejected(format!(
+                        "Funding index {new_index} for {market_id} would exceed {}",
+                        risk::MAX_EVENT_VALUE
+                    ));
+                }
+
+                self.settle_funding(market_id, new_index);
+                self.state
+                    .markets
+                    .get_mut(market_id)
+                    .unwrap()
+                    .settled_funding_intervals
+                    .insert(*interval_id);
+                ApplyResult::Ok
+            }
+
+            EventType::FundingAccrual {
+                market_id,
+                accrued_index,
+            } => {
+                match self.state.markets.get(market_id) {
+                    None => return self.unknown_market(market_id, event.sequence),
+                    Some(market) if market.is_future() => return no_funding(market_id),
+                    Some(_) => {}
+                }
+                self.accrue_funding(market_id, *accrued_index);
+                ApplyResult::Ok
+            }
+
+            EventType::SetAccountLimits {
+                account_id,
+                max_leverage,
+                max_total_notional,
+            } => {
+                let account = self.state.get_or_create_account(account_id);
+                account.limits.max_leverage = *max_leverage;
+                account.limits.max_total_notional = *max_total_notional;
+                ApplyResult::Ok
+            }
+
+            EventType::SessionOpen { market_id } => {
+                let Some(market) = self.state.markets.get_mut(market_id) else {
+                    return self.unknown_market(market_id, event.sequence);
+                };
+                market.session_closed = false;
+                // Released accounts are scanned right after this event; any that are
+                // still waiting on another closed market are deferred again.
+                let released = self.state.accounts_with_position_in(market_id);
+                for account_id in released {
+                    self.state.deferred_liquidations.remove(&account_id);
+                }
+                ApplyResult::Ok
+            }
+
+            EventType::Expiry {
+                market_id,
+                settlement_price,
+            } => {
+                let clock = self.state.clock;
+                let Some(market) = self.state.markets.get_mut(market_id) else {
+                    return ApplyResult::Rejected(format!("Unknown market_id: {market_id}"));
+                };
+                let InstrumentKind::Future { expiry_timestamp } = market.instrument else {
+                    return ApplyResult::Rejected(format!(
+                        "{market_id} is a perpetual and does not expire"
+                    ));
+                };
+                if market.expired {
+                    return ApplyResult::Rejected(format!(
+                        "Market {market_id} has already expired"
+                    ));
+                }
+                if let Some(now) = clock.filter(|now| *now < expiry_timestamp) {
+                    return ApplyResult::Rejected(format!(
+                        "{market_id} expires at {expiry_timestamp} ms, clock is {now} ms"
+                    ));
+                }
+                if let TradeCheck::Rejected(reason) = risk::check_price(market, *settlement_price) {
+                    return ApplyResult::Rejected(reason);
+                }
+                set_mark(market, *settlement_price, event.sequence, clock);
+                market.expired = true;
+                self.settle_expiry(market_id, *settlement_price);
+                ApplyResult::Ok
+            }
+
+            EventType::InterestTick { interval_id } => {
+                let Some(accrual) = self.config.interest.clone() else {
+                    return ApplyResult::Rejected("Interest accrual is not configured".to_string());
+                };
+                if !self.state.settled_interest_intervals.insert(*interval_id) {
+                    return ApplyResult::Rejected(format!(
+                        "Interest interval {interval_id} already accrued"
+                    ));
+                }
+                self.accrue_interest(&accrual);
+                ApplyResult::Ok
+            }
+
+            EventType::YieldDistribution { rate, interval_id } => {
+                if *rate < Decimal::ZERO {
+                    return ApplyResult::Rejected(format!("Yield rate {rate} is negative"));
+                }
+                if self.state.distributed_yield_intervals.contains(interval_id) {
+                    return ApplyResult::Rejected(format!(
+                        "Yield interval {interval_id} already distributed"
+                    ));
+                }
+                if let Err(reason) = self.distribute_yield(*rate, *interval_id) {
+                    return ApplyResult::Rejected(reason);
+                }
+                self.state.distributed_yield_intervals.insert(*interval_id);
+                ApplyResult::Ok
+            }
+
+            EventType::SessionClose { market_id } => {
+                let Some(market) = self.state.markets.get_mut(market_id) else {
+                    return self.unknown_market(market_id, event.sequence);
+                };
+                market.session_closed = true;
+                ApplyResult::Ok
+            }
+
+            EventType::LiquidationDeferred {
+                account_id,
+                market_ids,
+            } => {
+                let closed = market_ids.iter().all(|mid| {
+                    self.state
+                        .markets
+                        .get(mid)
+                        .is_some_and(|m| m.session_closed)
+                });
+                if !self.state.accounts.contains_key(account_id) || !closed {
+                    return ApplyResult::InvalidDerived(format!(
+                        "liquidation of {account_id} deferred on markets that are not all closed: {market_ids:?}"
+                    ));
+                }
+                self.state.deferred_liquidations.insert(account_id.clone());
+                ApplyResult::Ok
+            }
+
+            EventType::AssignPool {
+                account_id,
+                pool_id,
+            } => match risk::check_pool_assignment(&self.state, account_id, pool_id) {
+                TradeCheck::Accepted => {
+                    self.state.accounts.insert(
+                        account_id.clone(),
+                        Account::in_pool(account_id.clone(), pool_id.clone()),
+                    );
+                    ApplyResult::Ok
+                }
+                TradeCheck::Rejected(reason) => ApplyResult::Rejected(reason),
+            },
+
+            // Groups change no margin, so neither event needs a scan afterwards.
+            EventType::GroupCreated {
+                group_id,
+                max_group_notional,
+                fee_override,
+            } => match risk::check_group_creation(
+                &self.state,
+                group_id,
+                *max_group_notional,
+                *fee_override,
+            ) {
+                TradeCheck::Accepted => {
+                    let group = AccountGroup {
+                        max_group_notional: *max_group_notional,
+                        fee_override: *fee_override,
+                        ..AccountGroup::default()
+                    };
+                    self.state.groups.insert(group_id.clone(), group);
+                    ApplyResult::Ok
+                }
+                TradeCheck::Rejected(reason) => ApplyResult::Rejected(reason),
+            },
+
+            EventType::GroupMembershipSet {
+                account_id,
+                group_id,
+            } => match risk::check_group_membership(&self.state, account_id, group_id.as_deref()) {
+                TradeCheck::Accepted => {
+                    let account = self.state.accounts.get_mut(account_id).unwrap();
+                    let previous = std::mem::replace(&mut account.group_id, group_id.clone());
+                    let groups = &mut self.state.groups;
+                    if let Some(group) = previous.and_then(|g| groups.get_mut(&g)) {
+                        group.members.remove(account_id);
+                    }
+                    if let Some(group) = group_id.as_ref().and_then(|g| groups.get_mut(g)) {
+                        group.members.insert(account_id.clone());
+                    }
+                    ApplyResult::Ok
+                }
+                TradeCheck::Rejected(reason) => ApplyResult::Rejected(reason),
+            },
+
+            // Leverage moves IM only, and liquidation and alerts look at MM.
+            EventType::SetPositionLeverage {
+                account_id,
+                market_id,
+                leverage,
+            } => match risk::check_position_leverage(&self.state, account_id, market_id, *leverage)
+            {
+                TradeCheck::Accepted => {
+                    let account = self.state.accounts.get_mut(account_id).unwrap();
+                    account.leverage.insert(market_id.clone(), *leverage);
+                    ApplyResult::Ok
+                }
+                TradeCheck::Rejected(reason) => ApplyResult::Rejected(reason),
+            },
+
+            // Changes no margin, so no scan: the next liquidation in the market is the
+            // first to be offered to the backstop.
+            EventType::BackstopRegistered {
+                account_id,
+                market_id,
+                max_notional,
+            } => match risk::check_backstop_registration(
+                &self.state,
+                account_id,
+                market_id,
+                *max_notional,
+            ) {
+                TradeCheck::Accepted => {
+                    let backstops = &mut self.state.backstops;
+                    match backstops
+                        .iter_mut()
+                        .find(|b| b.account_id == *account_id && b.market_id == *market_id)
+                    {
+                        Some(backstop) => backstop.max_notional = *max_notional,
+                        None => backstops.push(Backstop {
+                            account_id: account_id.clone(),
+                            market_id: market_id.clone(),
+                            max_notional: *max_notional,
+                            absorbed: Decimal::ZERO,
+                        }),
+                    }
+                    ApplyResult::Ok
+                }
+                TradeCheck::Rejected(reason) => ApplyResult::Rejected(reason),
+            },
+
+            EventType::AccountsMerged { from, to } => {
+                match risk::check_account_merge(&self.state, from, to) {
+                    TradeCheck::Accepted => {
+                        self.merge_accounts(from, to);
+                        ApplyResult::Ok
+                    }
+                    TradeCheck::Rejected(reason) => ApplyResult::Rejected(reason),
+                }
+            }
+
+            EventType::PositionTransfer {
+                from,
+                to,
+                market_id,
+                quantity,
+                transfer_price,
+            } => match risk::check_position_transfer(
+                &self.state,
+                from,
+                to,
+                market_id,
+                *quantity,
+                *transfer_price,
+            ) {
+                TradeCheck::Accepted => {
+                    self.transfer_position(from, to, market_id, *quantity, *transfer_price);
+                    ApplyResult::Ok
+                }
+                TradeCheck::Rejected(reason) => ApplyResult::Rejected(reason),
+            },
+
+            EventType::StateImport {
+                account_id,
+                pool_id,
+                collateral,
+                positions,
+            } => {
+                if let TradeCheck::Rejected(reason) = risk::check_state_import(
+                    &self.state,
+                    account_id,
+                    pool_id,
+                    *collateral,
+                    positions,
+                    self.config.import_margin_check,
+                ) {
+                    return ApplyResult::Rejected(reason);
+                }
+                let account = risk::imported_account(account_id, pool_id, *collateral, positions);
+                let cost_basis: Decimal = account.positions.values().map(|p| p.cost_basis).sum();
+                self.metrics.record(pool_id, |m| {
+                    m.imported_collateral += collateral;
+                    m.imported_cost_basis += cost_basis;
+                });
+                if margin::is_liquidatable(&account, &self.state) {
+                    self.pending_derived
+                        .push(EventType::StateImportBelowMaintenance {
+                            account_id: account_id.clone(),
+                            equity: margin::equity(&account, &self.state),
+                            maintenance_margin: margin::maintenance_margin_required(
+                                &account,
+                                &self.state,
+                            ),
+                        });
+                }
+                self.state.accounts.insert(account_id.clone(), account);
+                ApplyResult::Ok
+            }
+
+            // The custom stages stay the engine's own: the logged config only names
+            // them, and `check_reload` has made sure the names are the same.
+            EventType::ConfigUpdated { config } => match self.config.check_reload(config) {
+                Ok(()) => {
+                    let risk_checks = std::mem::take(&mut self.config.risk_checks);
+                    self.config = EngineConfig {
+                        risk_checks,
+                        ..config.clone()
+                    };
+                    ApplyResult::Ok
+                }
+                Err(e) => ApplyResult::Rejected(e.to_string()),
+            },
+
+            // No account holds a new market, or one being removed: nothing to scan.
+            EventType::MarketAdded { market } => {
+                match risk::check_market_addition(&self.state, market) {
+                    Ok(()) => {
+                        let market = Market::clone(market);
+                        self.state.markets.insert(market.market_id.clone(), market);
+                        ApplyResult::Ok
+                    }
+                    Err(e) => ApplyResult::Rejected(e.to_string()),
+                }
+            }
+
+            EventType::MarketRemoved { market_id } => {
+                match risk::check_market_removal(&self.state, market_id) {
+                    Ok(()) => {
+                        self.state.remove_market(market_id);
+                        ApplyResult::Ok
+                    }
+                    Err(e) => ApplyResult::Rejected(e.to_string()),
+                }
+            }
+
+            // Relief only lowers margin, so no account needs a scan afterwards.
+            EventType::HedgePairAdded {
+                market_a,
+                market_b,
+                offset_fraction,
+            } => match risk::check_hedge_pair(&self.state, market_a, market_b, *offset_fraction) {
+                TradeCheck::Accepted => {
+                    self.state.hedge_pairs.push(HedgePair {
+                        market_a: market_a.clone(),
+                        market_b: market_b.clone(),
+                        offset_fraction: *offset_fraction,
+                    });
+                    ApplyResult::Ok
+                }
+                TradeCheck::Rejected(reason) => ApplyResult::Rejected(reason),
+            },
+
+            EventType::InsuranceFundDeposit { pool_id, amount } => {
+                *self
+                    .state
+                    .insurance_funds
+                    .entry(pool_id.clone())
+                    .or_insert(Decimal::ZERO) += amount;
+                self.metrics
+                    .record(pool_id, |m| m.insurance_deposits += amount);
+                ApplyResult::Ok
+            }
+
+            EventType::InsuranceFundPayout {
+                pool_id,
+                account_id,
+                amount,
+            } => {
+                if let Err(reason) =
+                    liquidation::check_insurance_payout(&self.state, pool_id, account_id, *amount)
+                {
+                    return ApplyResult::InvalidDerived(reason);
+                }
+                *self.state.insurance_funds.get_mut(pool_id).unwrap() -= amount;
+                let account = self.state.accounts.get_mut(account_id).unwrap();
+                account.trading_balance += amount;
+                account.bankruptcy_deficit -= amount;
+                ApplyResult::Ok
+            }
+
+            EventType::LossSocialized {
+                account_id,
+                amount,
+                charges,
+                ..
+            } => {
+                let policy = self.config.residual_deficit;
+                if !policy.socializes() {
+                    return ApplyResult::InvalidDerived(format!(
+                        "loss socialization for {account_id} under a config that holds residual deficits"
+                    ));
+                }
+                if let Err(reason) =
+                    liquidation::check_loss_socialization(&self.state, &event.event_type, policy)
+                {
+                    return ApplyResult::InvalidDerived(reason);
+                }
+                for (charged, charge) in charges {
+                    self.state
+                        .accounts
+                        .get_mut(charged)
+                        .unwrap()
+                        .trading_balance -= charge;
+                }
+                let account = self.state.accounts.get_mut(account_id).unwrap();
+                account.trading_balance += amount;
+                account.bankruptcy_deficit -= amount;
+                ApplyResult::Ok
+            }
+
+            EventType::AccountReinstated { account_id } => {
+                match risk::check_reinstatement(&self.state, account_id) {
+                    TradeCheck::Accepted => {
+                        let account = self.state.accounts.get_mut(account_id).unwrap();
+                        account.suspended = false;
+                        account.suspended_markets.clear();
+                        account.frozen = None;
+                        ApplyResult::Ok
+                    }
+                    TradeCheck::Rejected(reason) => ApplyResult::Rejected(reason),
+                }
+            }
+
+            // Freezes the account; its `ForceCloseFill`s follow.
+            EventType::ForceClose { account_id, reason } => {
+                if reason.is_empty() {
+                    return ApplyResult::Rejected("Force close needs a reason".to_string());
+                }
+                let Some(account) = self.state.accounts.get_mut(account_id) else {
+                    return ApplyResult::Rejected("Account does not exist".to_string());
+                };
+                if let Some(frozen) = &account.frozen {
+                    return ApplyResult::Rejected(format!(
+                        "Account {account_id} is already frozen ({frozen})"
+                    ));
+                }
+                account.frozen = Some(reason.clone());
+                ApplyResult::Ok
+            }
+
+            EventType::ForceCloseFill {
+                account_id,
+                market_id,
+                quantity,
+                price,
+                reason,
+            } => {
+                if let Err(reason) = check_force_close_fill(
+                    &self.state,
+                    account_id,
+                    market_id,
+                    *quantity,
+                    *price,
+                    reason,
+                ) {
+                    return ApplyResult::InvalidDerived(reason);
+                }
+                let account = self.state.accounts.get_mut(account_id).unwrap();
+                apply_trade_to(
+                    &mut account.trading_balance,
+                    &mut account.positions,
+                    market_id,
+                    *quantity,
+                    *price,
+                );
+                let cash = *quantity * *price;
+                self.metrics
+                    .record(&account.pool_id, |m| m.fill_cash_flow -= cash);
+                self.record_fill(account_id, cash.abs(), false, None);
+                ApplyResult::Ok
+            }
+
+            EventType::OrderPlaced {
+                account_id,
+                order_id,
+                market_id,
+                quantity,
+            } => match risk::check_order(&self.state, account_id, order_id, market_id, *quantity) {
+                TradeCheck::Accepted => {
+                    let account = self.state.accounts.get_mut(account_id).unwrap();
+                    account.orders.insert(
+                        order_id.clone(),
+                        RestingOrder {
+                            market_id: market_id.clone(),
+                            quantity: *quantity,
+                        },
+                    );
+                    ApplyResult::Ok
+                }
+                TradeCheck::Rejected(reason) => ApplyResult::Rejected(reason),
+            },
+
+            EventType::OrderCancelled {
+                account_id,
+                order_id,
+            } => {
+                let Some(account) = self.state.accounts.get_mut(account_id) else {
+                    return ApplyResult::Rejected("Account does not exist".to_string());
+                };
+                if account.orders.remove(order_id).is_none() {
+                    return ApplyResult::Rejected(format!(
+                        "Account {account_id} has no resting order {order_id}"
+                    ));
+                }
+                ApplyResult::Ok
+            }
+
+            // Must be exactly the orders the scan would cancel now.
+            EventType::OrdersAutoCancelled {
+                account_id,
+                order_ids,
+                ..
+            } => {
+                let Some(account) = self.state.accounts.get(account_id) else {
+                    return ApplyResult::InvalidDerived(format!(
+                        "Orders auto-cancelled for unknown account {account_id}"
+                    ));
+                };
+                let expected = risk::orders_to_auto_cancel(account, &self.state);
+                if expected.is_empty() || *order_ids != expected {
+                    return ApplyResult::InvalidDerived(format!(
+                        "Auto-cancelled orders {order_ids:?} of {account_id} are not the {expected:?} its reservations call for"
+                    ));
+                }
+                let account = self.state.accounts.get_mut(account_id).unwrap();
+                for order_id in order_ids {
+                    account.orders.remove(order_id);
+                }
+                ApplyResult::Ok
+            }
+
+            EventType::AccountMetadata {
+                account_id,
+                key,
+                value,
+            } => {
+                let empty = BTreeMap::new();
+                let current = self
+                    .state
+                    .accounts
+                    .get(account_id)
+                    .map(|a| &a.metadata)
+                    .unwrap_or(&empty);
+                if let Err(reason) = check_metadata_update(current, key, value) {
+                    return ApplyResult::Rejected(reason);
+                }
+
+                let account = self.state.get_or_create_account(account_id);
+                if value.is_empty() {
+                    account.metadata.remove(key);
+                } else {
+                    account.metadata.insert(key.clone(), value.clone());
+                }
+                ApplyResult::Ok
+            }
+
+            EventType::LiquidationFill {
+                account_id,
+                market_id,
+                quantity,
+                price,
+            } => {
+                if let Err(reason) =
+                    check_liquidation_fill(&self.state, account_id, market_id, *quantity)
+                {
+                    return ApplyResult::InvalidDerived(reason);
+                }
+                self.state.in_liquidation.insert(account_id.clone());
+                // Direct application — no risk check
+                liquidation::apply_fill(&mut self.state, account_id, market_id, *quantity, *price);
+                let cash = *quantity * *price;
+                let pool_id = &self.state.accounts[account_id].pool_id;
+                self.metrics.record(pool_id, |m| m.fill_cash_flow -= cash);
+                self.record_fill(account_id, cash.abs(), true, None);
+                ApplyResult::Ok
+            }
+
+            EventType::LiquidationTakeover {
+                liquidated_account,
+                keeper_account,
+                market_id,
+                quantity,
+                price,
+            } => match risk::check_takeover(
+                &self.state,
+                liquidated_account,
+                keeper_account,
+                market_id,
+                *quantity,
+                *price,
+            ) {
+                TradeCheck::Accepted => {
+                    self.state.in_liquidation.insert(liquidated_account.clone());
+                    liquidation::apply_takeover(
+                        &mut self.state,
+                        liquidated_account,
+                        keeper_account,
+                        market_id,
+                        *quantity,
+                        *price,
+                    );
+                    let notional = (*quantity * *price).abs();
+                    self.record_fill(liquidated_account, notional, true, None);
+                    ApplyResult::Ok
+                }
+                TradeCheck::Rejected(reason) => ApplyResult::Rejected(reason),
+            },
+
+            // The one move the ladder makes from the account's current level.
+            EventType::RiskAlert {
+                account_id,
+                level,
+                margin_usage,
+            }
+            | EventType::RiskAlertCleared {
+                account_id,
+                level,
+                margin_usage,
+            } => {
+                let raised = matches!(event.event_type, EventType::RiskAlert { .. });
+                let Some(ladder) = &self.config.risk_alerts else {
+                    return ApplyResult::InvalidDerived(
+                        "no risk alert ladder is configured".into(),
+                    );
+                };
+                let Some(account) = self.state.accounts.get(account_id) else {
+                    return ApplyResult::InvalidDerived(format!("{account_id} does not exist"));
+                };
+                let usage = margin::margin_usage(account, &self.state);
+                let expected = ladder.level(usage, account.alert_level);
+                let moved = if raised {
+                    expected > account.alert_level
+                } else {
+                    expected < account.alert_level
+                };
+                if !moved || expected != *level || usage != *margin_usage {
+                    return ApplyResult::InvalidDerived(format!(
+                        "{account_id} at margin usage {} moves from alert level {} to {expected}, not {level}",
+                        usage.map_or("unbounded".to_string(), |u| u.normalize().to_string()),
+                        account.alert_level
+                    ));
+                }
+                self.state.accounts.get_mut(account_id).unwrap().alert_level = *level;
+                ApplyResult::Ok
+            }
+
+            // A margin call opens on an account at or under MM without one, and clears
+            // once it is above again, each at exactly the account's figures.
+            EventType::MarginCall {
+                account_id,
+                equity,
+                maintenance_margin,
+            }
+            | EventType::MarginCallCleared {
+                account_id,
+                equity,
+                maintenance_margin,
+            } => {
+                let called = matches!(event.event_type, EventType::MarginCall { .. });
+                if called && self.config.margin_call.is_none() {
+                    return ApplyResult::InvalidDerived(
+                        "no margin call policy is configured".into(),
+                    );
+                }
+                let Some(account) = self.state.accounts.get(account_id) else {
+                    return ApplyResult::InvalidDerived(format!("{account_id} does not exist"));
+                };
+                let figures = (
+                    margin::equity(account, &self.state),
+                    margin::maintenance_margin_required(account, &self.state),
+                );
+                let due = margin::is_liquidatable(account, &self.state) == called
+                    && account.margin_call.is_some() != called;
+                if !due || figures != (*equity, *maintenance_margin) {
+                    return ApplyResult::InvalidDerived(format!(
+                        "{account_id} at equity {} and MM {} {}",
+                        figures.0,
+                        figures.1,
+                        if called {
+                            "is not due a margin call"
+                        } else {
+                            "has no margin call to clear"
+                        }
+                    ));
+                }
+                let open = MarginCallOpen {
+                    breached_at: event.caused_by.unwrap_or(event.sequence),
+                    clock: self.state.clock.unwrap_or(0),
+                };
+                self.state.accounts.get_mut(account_id).unwrap().margin_call =
+                    called.then_some(open);
+                ApplyResult::Ok
+            }
+
+            // A market crossing its skew limit, at exactly the net notional it has.
+            EventType::SkewLimitBreached {
+                market_id,
+                net_notional,
+            }
+            | EventType::SkewLimitCleared {
+                market_id,
+                net_notional,
+            } => {
+                let breached = matches!(event.event_type, EventType::SkewLimitBreached { .. });
+                let Some(market) = self.state.markets.get(market_id) else {
+                    return ApplyResult::InvalidDerived(format!("{market_id} is not registered"));
+                };
+                let Some(limit) = market.skew_limit_notional else {
+                    return ApplyResult::InvalidDerived(format!("{market_id} has no skew limit"));
+                };
+                let actual = self.state.net_notional(market_id);
+                let crossed =
+                    (actual.abs() > limit) == breached && market.skew_breached != breached;
+                if actual != *net_notional || !crossed {
+                    return ApplyResult::InvalidDerived(format!(
+                        "{market_id} at net notional {actual} against skew limit {limit} is not {}",
+                        if breached {
+                            "newly breached"
+                        } else {
+                            "newly back within it"
+                        }
+                    ));
+                }
+                self.state.markets.get_mut(market_id).unwrap().skew_breached = breached;
+                ApplyResult::Ok
+            }
+
+            // Only true of a market that is still unregistered.
+            EventType::UnknownMarketIgnored { market_id, .. } => {
+                if self.state.markets.contains_key(market_id) {
+                    return ApplyResult::InvalidDerived(format!(
+                        "{market_id} is registered, so no event for it was ignored"
+                    ));
+                }
+                ApplyResult::Ok
+            }
+
+            // Informational events returned before the match. Any other event without
+            // an arm above is a gap in this match, reported rather than ignored.
+            other => ApplyResult::InvalidDerived(format!("no apply rule for {other:?}")),
+        };
+        if matches!(result, ApplyResult::Ok) {
+            self.state.release_backstops();
+        }
+        result
+    }
+
+    /// A `MarkPriceUpdate` or `FundingUpdate` for an unregistered market: rejected, or
+    /// accepted with an `UnknownMarketIgnored` marker, per `EngineConfig::unknown_markets`.
+    fn unknown_market(&mut self, market_id: &MarketId, sequence: u64) -> ApplyResult {
+        match self.config.unknown_markets {
+            UnknownMarketPolicy::Reject => {
+                ApplyResult::Rejected(format!("Unknown market_id: {market_id}"))
+            }
+            UnknownMarketPolicy::Ignore => {
+                self.metrics.unknown_markets_ignored += 1;
+                self.pending_derived.push(EventType::UnknownMarketIgnored {
+                    market_id: market_id.clone(),
+                    original_sequence: sequence,
+                });
+                ApplyResult::Ok
+            }
+        }
+    }
+
+    /// Move a market's cumulative funding index to `new_cumulative_index` and settle the
+    /// difference eagerly into the collateral of every account holding a position,
+    /// together with the funding accrued in the market since its last settlement
+    /// (`Account::pending_funding`), position or not. Shared by `FundingUpdate` (raw
+    /// index) and `FundingRate` (derived index).
+    fn settle_funding(&mut self, market_id: &MarketId, new_cumulative_index: Decimal) {
+        let deltas = self.funding_deltas(market_id, new_cumulative_index);
+        trace::event!(
+            market_id = %market_id,
+            old_index = %self.state.markets.get(market_id).map_or(Decimal::ZERO, Market::funding_index_reached),
+            new_index = %new_cumulative_index,
+            holders = deltas.len(),
+            "funding settlement"
+        );
+        if let Some(market) = self.state.markets.get_mut(market_id) {
+            market.cumulative_funding_index = new_cumulative_index;
+            market.accrued_funding_index = None;
+        }
+
+        let mut amounts: BTreeMap<AccountId, Decimal> = deltas.into_iter().collect();
+        for (account_id, account) in self.state.accounts.iter_mut() {
+            if let Some(pending) = account.pending_funding.remove(market_id) {
+                *amounts.entry(account_id.clone()).or_insert(Decimal::ZERO) += pending;
+            }
+        }
+
+        for (account_id, amount) in amounts {
+            trace::event!(account_id = %account_id, market_id = %market_id, amount = %amount, "funding settled");
+            let account = self.state.accounts.get_mut(&account_id).unwrap();
+            account.trading_balance += amount;
+            self.metrics
+                .record(&account.pool_id, |m| m.funding += amount);
+            if account.positions.contains_key(market_id) {
+                account
+                    .last_funding
+                    .insert(market_id.clone(), new_cumulative_index);
+            }
+
+            if !amount.is_zero() {
+                if let Some(pos) = account.positions.get_mut(market_id) {
+                    pos.funding_paid -= amount;
+                }
+                *account
+                    .funding_paid
+                    .entry(market_id.clone())
+                    .or_insert(Decimal::ZERO) -= amount;
+
+                self.pending_derived.push(EventType::FundingPayment {
+                    account_id,
+                    market_id: market_id.clone(),
+                    amount,
+                });
+            }
+        }
+    }
+
+    /// Accrue a market's funding to `accrued_index` without settling it: each holder's
+    /// funding is added to its `pending_funding` and its index moves to
+    /// `accrued_index`, and no collateral changes until the next settlement.
+    fn accrue_funding(&mut self, market_id: &MarketId, accrued_index: Decimal) {
+        let deltas = self.funding_deltas(market_id, accrued_index);
+        trace::event!(
+            market_id = %market_id,
+            old_index = %self.state.markets.get(market_id).map_or(Decimal::ZERO, Market::funding_index_reached),
+            new_index = %accrued_index,
+            holders = deltas.len(),
+            "funding accrual"
+        );
+        if let Some(market) = self.state.markets.get_mut(market_id) {
+            market.accrued_funding_index = Some(accrued_index);
+        }
+
+        for (account_id, amount) in deltas {
+            let account = self.state.accounts.get_mut(&account_id).unwrap();
+            account
+                .last_funding
+                .insert(market_id.clone(), accrued_index);
+            let pending = account
+                .pending_funding
+                .entry(market_id.clone())
+                .or_insert(Decimal::ZERO);
+            *pending += amount;
+            if pending.is_zero() {
+                account.pending_funding.remove(market_id);
+            }
+        }
+    }
+
+    /// Every holder's funding as the market's index moves to `new_index`, in account
+    /// order, rounded by `margin::allocate_funding`. A holder is charged from the index
+    /// it last settled or accrued at, or from `Market::funding_index_reached` without
+    /// one. Changes nothing but stale index entries.
+    ///
+    /// Every holder's settlement is planned from the state as the funding event finds
+    /// it, after every earlier event of a batch, before anything changes.
+    fn funding_deltas(
+        &mut self,
+        market_id: &MarketId,
+        new_index: Decimal,
+    ) -> Vec<(AccountId, Decimal)> {
+        let old_index = self
+            .state
+            .markets
+            .get(market_id)
+            .map_or(Decimal::ZERO, Market::funding_index_reached);
+
+        let plan = plan_funding(&self.state, market_id, old_index);
+
+        // An entry left behind by a since-closed position would charge a reopened
+        // position for funding accrued while flat. Drop it; a holder without an entry
+        // is charged from the index funding has reached.
+        for account in self.state.accounts.values_mut() {
+            if !account.positions.contains_key(market_id) {
+                account.last_funding.remove(market_id);
+            }
+        }
+
+        // Compute every holder's raw delta first so rounding can be conserved across
+        // the whole settlement rather than per account.
+        let raw: Vec<(AccountId, Decimal)> = plan
+            .into_iter()
+            .map(|i| {
+                let delta = margin::funding_delta(i.quantity, i.last_index, new_index);
+                (i.account_id, delta)
+            })
+            .collect();
+        margin::allocate_funding(&raw)
+    }
+
+    /// Charge `rate_per_interval` on every negative collateral balance and credit
+    /// `credit_rate_per_interval` on every positive one, moving the amounts to and from
+    /// the pool's interest revenue, and record each as an `InterestCharged`. Amounts are
+    /// rounded down to collateral precision: a charge never comes out smaller than the
+    /// rate implies, a credit never larger. Interest on a bankrupt account adds to its
+    /// deficit.
+    fn accrue_interest(&mut self, accrual: &InterestAccrual) {
+        for (account_id, account) in self.state.accounts.iter_mut() {
+            let rate = if account.collateral().is_sign_negative() {
+                accrual.rate_per_interval
+            } else {
+                accrual.credit_rate_per_interval
+            };
+            let amount = (account.collateral() * rate).round_dp_with_strategy(
+                margin::COLLATERAL_DECIMALS,
+                RoundingStrategy::ToNegativeInfinity,
+            );
+            if amount.is_zero() {
+                continue;
+            }
+            account.trading_balance += amount;
+            if account.bankruptcy_deficit > Decimal::ZERO {
+                account.bankruptcy_deficit -= amount;
+            }
+            *self
+                .state
+                .interest_revenue
+                .entry(account.pool_id.clone())
+                .or_insert(Decimal::ZERO) -= amount;
+            self.pending_derived.push(EventType::InterestCharged {
+                account_id: account_id.clone(),
+                amount,
+            });
+        }
+    }
+
+    /// Pay `rate` on every balance in the yield basis out of the pool's interest
+    /// revenue, recording each payment as a `YieldPaid` and each pool's rounding
+    /// residual as a `YieldResidual`. Payments are rounded down to collateral
+    /// precision, so the residual is never negative and stays with the venue: the
+    /// payments and residuals of a pool add up to `rate` times its basis exactly.
+    /// Under `YieldBasis::AllBalances` a negative balance is charged, which adds to a
+    /// bankrupt account's deficit as interest does. Every payment is computed before
+    /// any is made, so a balance whose payment would overflow leaves state untouched.
+    fn distribute_yield(&mut self, rate: Decimal, interval_id: u64) -> Result<(), String> {
+        let all_balances = self.config.yield_basis == YieldBasis::AllBalances;
+        let overflow =
+            |account_id: &AccountId| format!("Yield at rate {rate} overflows for {account_id}");
+        let mut payments = Vec::new();
+        // Per pool: `rate` times the basis, and what was paid of it.
+        let mut owed: BTreeMap<PoolId, (Decimal, Decimal)> = BTreeMap::new();
+        for (account_id, account) in &self.state.accounts {
+            let collateral = account.collateral();
+            if collateral.is_zero() || (collateral < Decimal::ZERO && !all_balances) {
+                continue;
+            }
+            let exact = collateral
+                .checked_mul(rate)
+                .ok_or_else(|| overflow(account_id))?;
+            let amount = exact.round_dp_with_strategy(
+                margin::COLLATERAL_DECIMALS,
+                RoundingStrategy::ToNegativeInfinity,
+            );
+            account
+                .trading_balance
+                .checked_add(amount)
+                .and_then(|balance| balance.checked_add(account.principal))
+                .ok_or_else(|| overflow(account_id))?;
+            let (pool_exact, pool_paid) = owed.entry(account.pool_id.clone()).or_default();
+            *pool_exact = pool_exact
+                .checked_add(exact)
+                .ok_or_else(|| overflow(account_id))?;
+            *pool_paid += amount;
+            if !amount.is_zero() {
+                payments.push((account_id.clone(), amount));
+            }
+        }
+        for (account_id, amount) in payments {
+            let account = self.state.accounts.get_mut(&account_id).unwrap();
+            account.trading_balance += amount;
+            if account.bankruptcy_deficit > Decimal::ZERO {
+                account.bankruptcy_deficit -= amount;
+            }
+            *self
+                .state
+                .interest_revenue
+                .entry(account.pool_id.clone())
+                .or_insert(Decimal::ZERO) -= amount;
+            self.pending_derived.push(EventType::YieldPaid {
+                account_id,
+                interval_id,
+                amount,
+            });
+        }
+        for (pool_id, (exact, paid)) in owed {
+            if exact != paid {
+                self.pending_derived.push(EventType::YieldResidual {
+                    pool_id,
+                    interval_id,
+                    amount: exact - paid,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Close every position in an expired market at `price` as a fill, realizing its
+    /// PnL into collateral, and record each close as an `ExpirySettlement`.
+    fn settle_expiry(&mut self, market_id: &MarketId, price: Decimal) {
+        for account_id in self.state.accounts_with_position_in(market_id) {
+            let account = self.state.accounts.get_mut(&account_id).unwrap();
+            let quantity = -account.positions[market_id].quantity;
+            let collateral_before = account.trading_balance;
+            apply_trade_to(
+                &mut account.trading_balance,
+                &mut account.positions,
+                market_id,
+                quantity,
+                price,
+            );
+            account.last_funding.remove(market_id);
+            let cash = quantity * price;
+            self.metrics
+                .record(&account.pool_id, |m| m.fill_cash_flow -= cash);
+            let realized_pnl = account.trading_balance - collateral_before;
+            self.pending_derived.push(EventType::ExpirySettlement {
+                account_id,
+                market_id: market_id.clone(),
+                quantity,
+                price,
+                realized_pnl,
+            });
+        }
+    }
+
+    /// Replay a full event log from scratch. Returns the final state and snapshots.
+    ///
+    /// Note: During replay, it is EXPECTED that some `TradeFill`/`Withdraw` events may be
+    /// rejected again if the log includes the original attempted action plus an informational
+    /// `TradeRejected`/`WithdrawalRejected` entry. In that case, state remains unchanged and
+    /// the rejection is listed in `ReplayResult::rejections` instead of panicking.
+    ///
+    /// Runs under the default `EngineConfig`; a log written under another config stops
+    /// at its `ConfigMarker`. Use `replay_with` to choose the config and see the status.
+    pub fn replay(event_log: &[impl AsRef<Event>], markets: Vec<Market>) -> (State, Vec<Snapshot>) {
+        let events = event_log.iter().map(AsRef::as_ref);
+        let result = Self::replay_with(ReplayOptions::default(), events, markets);
+        (result.state, result.snapshots)
+    }
+
+    /// The final state `replay` would return, and nothing else. No snapshots are
+    /// captured, rejections and progress are not tracked, and events are consumed by
+    /// value instead of cloned, so this is the fast way to rebuild state from a large
+    /// log. It runs under `config`, as `replay_with` runs under `ReplayOptions::config`,
+    /// and fails with `EngineError::ConfigMismatch` at a `ConfigMarker` that disagrees
+    /// with the config in effect, rather than returning the state before it.
+    pub fn replay_state_only(
+        events: impl IntoIterator<Item = Event>,
+        markets: Vec<Market>,
+        config: EngineConfig,
+    ) -> Result<State, EngineError> {
+        let mut engine = Engine::with_config(config);
+        for market in markets {
+            engine
+                .state
+                .markets
+                .insert(market.market_id.clone(), market);
+        }
+        let mut events = events.into_iter().peekable();
+        while let Some(event) = events.next() {
+            if let EventType::ConfigMarker { config, .. } = &event.event_type {
+                let refused = events.peek().is_some_and(|next| rejects(next, &event));
+                let fields = config.diff(&engine.config);
+                if !fields.is_empty() && !refused {
+                    return Err(EngineError::ConfigMismatch {
+                        sequence: event.sequence,
+                        fields,
+                    });
+                }
+            }
+            engine.apply_event(&event);
+            engine.pending_derived.clear();
         }
+        Ok(engine.state)
     }
 
-    /// Register a market (configuration, not an event).
-    pub fn add_market(&mut self, market: Market) {
-        self.state.markets.insert(market.market_id.clone(), market);
+    /// Replay an event stream with progress reporting, early stop, and cancellation.
+    /// Events are consumed one at a time, so the source can be a streaming reader
+    /// (see `jsonl::stream_jsonl`) rather than a materialized log. Events may be owned
+    /// borrowed or shared: replaying `&engine.event_log` copies nothing.
+    pub fn replay_with(
+        options: ReplayOptions,
+        events: impl IntoIterator<Item = impl AsRef<Event>>,
+        markets: Vec<Market>,
+    ) -> ReplayResult {
+        Self::replay_with_fallible(
+            options,
+            events.into_iter().map(Ok::<_, std::convert::Infallible>),
+            markets,
+        )
     }
 
-    /// Process an external event in live mode.
-    /// Assigns a sequence number, applies it, snapshots, then scans for liquidations.
-    pub fn process(&mut self, event_type: EventType) {
-        let event = Event {
-            sequence: self.next_sequence,
-            event_type,
-        };
-        self.next_sequence += 1;
-        self.event_log.push(event.clone());
+    /// Like `replay_with`, but over a source that can fail mid-stream (e.g. a JSONL
+    /// parse error). A source error ends the replay with `ReplayStatus::Errored`,
+    /// returning the state reached after the last successfully applied event.
+    pub fn replay_with_fallible<E: std::fmt::Display>(
+        options: ReplayOptions,
+        events: impl IntoIterator<Item = Result<impl AsRef<Event>, E>>,
+        markets: Vec<Market>,
+    ) -> ReplayResult {
+        let mut base = State::new();
+        for market in markets {
+            base.markets.insert(market.market_id.clone(), market);
+        }
+        Self::replay_from(options, base, events)
+    }
 
-        let result = self.apply_event(&event);
+    /// Replay `events` on top of `base`, the state before the first of them.
+    fn replay_from<E: std::fmt::Display>(
+        mut options: ReplayOptions,
+        base: State,
+        events: impl IntoIterator<Item = Result<impl AsRef<Event>, E>>,
+    ) -> ReplayResult {
+        let mut engine = Engine::with_config(options.config.clone());
+        engine.metrics = EngineMetrics::opening(&base);
+        engine.state = base;
 
-        // Handle rejections
-        if let ApplyResult::Rejected(reason) = result {
-            // Snapshot the unchanged state for the primary event
-            self.snapshots
-                .push(snapshot::capture(&self.state, event.sequence));
+        let started = Instant::now();
+        let mut snapshots = Vec::new();
+        let mut events_applied: u64 = 0;
+        let mut last_sequence: Option<u64> = None;
+        let mut rejections = Vec::new();
+        let mut invariant_violations = Vec::new();
+        let mut unknown_markets_ignored = Vec::new();
+        let mut derived_records = Vec::new();
+        let mut sink_errors = Vec::new();
+        let mut alerts_due = false;
+        // The `BatchStarted` of the batch being replayed, whose scan waits for its end.
+        let mut open_batch: Option<u64> = None;
+        let mut status = ReplayStatus::Completed;
 
-            let reject_event = match &event.event_type {
-                EventType::TradeFill {
-                    account_id,
-                    market_id,
-                    quantity,
-                    price,
-                } => Event {
-                    sequence: self.next_sequence,
-                    event_type: EventType::TradeRejected {
-                        account_id: account_id.clone(),
-                        market_id: market_id.clone(),
-                        quantity: *quantity,
-                        price: *price,
-                        reason,
-                    },
-                },
-                EventType::Withdraw { account_id, amount } => Event {
-                    sequence: self.next_sequence,
-                    event_type: EventType::WithdrawalRejected {
-                        account_id: account_id.clone(),
-                        amount: *amount,
-                        reason,
-                    },
-                },
-                _ => unreachable!("Only trades and withdrawals can be rejected"),
-            };
+        let mut events = events.into_iter().peekable();
+        while let Some(item) = events.next() {
+            if let Some(cancel) = &options.cancel {
+                if cancel.load(Ordering::Relaxed) {
+                    status = ReplayStatus::Cancelled;
+                    break;
+                }
+            }
 
-            self.next_sequence += 1;
-            self.event_log.push(reject_event.clone());
-            self.snapshots
-                .push(snapshot::capture(&self.state, reject_event.sequence));
-            return;
-        }
+            let item = match item {
+                Ok(item) => item,
+                Err(e) => {
+                    status = ReplayStatus::Errored(e.to_string());
+                    break;
+                }
+            };
+            let event: &Event = item.as_ref();
 
-        // Snapshot BEFORE liquidation scanning — this is the state after just this event
-        self.snapshots
-            .push(snapshot::capture(&self.state, event.sequence));
+            if let Some(stop) = options.stop_at_sequence {
+                if event.sequence > stop {
+                    status = ReplayStatus::StoppedAt(stop);
+                    break;
+                }
+            }
 
-        // Determine which accounts need liquidation scanning based on event type.
-        // Use a BTreeSet to canonicalize ordering and deduplicate deterministically.
-        let accounts_to_scan: BTreeSet<AccountId> = match &event.event_type {
-            EventType::TradeFill { account_id, .. } => [account_id.clone()].into_iter().collect(),
-            EventType::MarkPriceUpdate { market_id, .. } => self
-                .state
-                .accounts_with_position_in(market_id)
-                .into_iter()
-                .collect(),
-            EventType::FundingUpdate { market_id, .. } => self
-                .state
-                .accounts_with_position_in(market_id)
-                .into_iter()
-                .collect(),
-            _ => BTreeSet::new(),
-        };
+            // A marker the log records as refused was submitted from outside. As in
+            // `process_with`, it is not applied.
+            let refused = event.event_type.is_uncaused_marker()
+                && matches!(events.peek(), Some(Ok(next)) if rejects(next.as_ref(), event));
 
-        // Execute liquidations and snapshot after each
-        for account_id in accounts_to_scan {
-            let liq_events = liquidation::check_and_liquidate(
-                &mut self.state,
-                &account_id,
-                &mut self.next_sequence,
-            );
-            for liq_event in liq_events {
-                self.event_log.push(liq_event.clone());
-                self.snapshots
-                    .push(snapshot::capture(&self.state, liq_event.sequence));
+            // The alerts and skew records an accepted event calls for follow its cascade,
+            // so by the next event without a cause none may be left due. In a batch the
+            // cascade follows its `BatchEnded`.
+            if event.caused_by.is_none() && open_batch.is_none() && std::mem::take(&mut alerts_due)
+            {
+                if let Some(record) = engine.due_records().first() {
+                    invariant_violations.push((event.sequence, missing_record(record)));
+                }
+            }
+            match (&event.event_type, open_batch) {
+                (EventType::BatchStarted { .. }, Some(started)) if !refused => {
+                    invariant_violations.push((
+                        event.sequence,
+                        format!("batch started inside the batch started at seq {started}"),
+                    ));
+                }
+                (EventType::BatchStarted { .. }, None) if !refused => {
+                    open_batch = Some(event.sequence);
+                }
+                (EventType::BatchEnded { .. }, None) if !refused => {
+                    invariant_violations
+                        .push((event.sequence, "batch ended without starting".to_string()));
+                }
+                (EventType::BatchEnded { .. }, Some(_)) if !refused => open_batch = None,
+                _ => {}
             }
-        }
-    }
 
-    /// Apply a single event to state. Pure state mutation — no liquidation scanning,
-    /// no event generation. Used identically in live and replay modes.
-    fn apply_event(&mut self, event: &Event) -> ApplyResult {
-        match &event.event_type {
-            EventType::Deposit { account_id, amount } => {
-                let account = self.state.get_or_create_account(account_id);
-                account.collateral += amount;
-                ApplyResult::Ok
+            if let EventType::ConfigMarker { config, .. } = &event.event_type {
+                let fields = config.diff(&options.config);
+                if !fields.is_empty() && !refused {
+                    status = ReplayStatus::ConfigMismatch(fields);
+                    break;
+                }
             }
 
-            EventType::Withdraw { account_id, amount } => {
-                match risk::check_withdrawal(&self.state, account_id, *amount) {
-                    TradeCheck::Accepted => {
-                        let account = self.state.accounts.get_mut(account_id).unwrap();
-                        account.collateral -= amount;
-                        ApplyResult::Ok
-                    }
-                    TradeCheck::Rejected(reason) => ApplyResult::Rejected(reason),
+            // A duplicate marker stands in for a submission whose key was in use.
+            if let EventType::DuplicateIgnored {
+                key,
+                original_sequence,
+            } = &event.event_type
+            {
+                if !refused && engine.state.idempotency.get(key) != Some(*original_sequence) {
+                    invariant_violations.push((
+                        event.sequence,
+                        format!("key {key} was not in use by seq {original_sequence}"),
+                    ));
                 }
             }
 
-            EventType::TradeFill {
-                account_id,
-                market_id,
-                quantity,
-                price,
-            } => match risk::check_trade(&self.state, account_id, market_id, *quantity, *price) {
-                TradeCheck::Accepted => {
-                    let account = self.state.accounts.get_mut(account_id).unwrap();
-                    apply_trade_to(
-                        &mut account.collateral,
-                        &mut account.positions,
+            let result = if refused {
+                engine.apply_envelope(event);
+                ApplyResult::Rejected(ENGINE_GENERATED.to_string())
+            } else {
+                engine.apply_event(event)
+            };
+            // Derived events are already in the log being replayed; the caller checks
+            // them against what replay derived.
+            for derived in engine.pending_derived.drain(..) {
+                match derived {
+                    EventType::UnknownMarketIgnored {
                         market_id,
-                        *quantity,
-                        *price,
-                    );
-                    ApplyResult::Ok
+                        original_sequence,
+                    } => unknown_markets_ignored.push((original_sequence, market_id)),
+                    record => derived_records.push((event.sequence, record)),
                 }
-                TradeCheck::Rejected(reason) => ApplyResult::Rejected(reason),
-            },
-
-            EventType::MarkPriceUpdate { market_id, price } => {
-                if let Some(market) = self.state.markets.get_mut(market_id) {
-                    market.mark_price = *price;
+            }
+            engine.assert_solvent(event.sequence);
+            // Live mode keeps no snapshot for a rejected primary event unless the config
+            // asks for one; mirror that. An invalid derived event changed nothing either.
+            let rejected = !matches!(result, ApplyResult::Ok);
+            let snapshotted = !rejected
+                || (event.caused_by.is_none()
+                    && options.config.rejected_attempt_snapshots == RejectedAttemptSnapshots::Keep);
+            alerts_due |=
+                !rejected && event.caused_by.is_none() && !event.event_type.is_engine_generated();
+            match result {
+                ApplyResult::Ok => {}
+                ApplyResult::InvalidDerived(reason) => {
+                    invariant_violations.push((event.sequence, reason));
+                }
+                ApplyResult::Rejected(reason) => {
+                    // Expected for attempted actions that failed margin checks in live mode.
+                    // State is unchanged (apply_event returned Rejected without mutating).
+                    rejections.push((event.sequence, reason));
                 }
-                ApplyResult::Ok
             }
 
-            EventType::FundingUpdate {
-                market_id,
-                new_cumulative_index,
-            } => {
-                let old_index = self
-                    .state
-                    .markets
-                    .get(market_id)
-                    .map(|m| m.cumulative_funding_index)
-                    .unwrap_or(Decimal::ZERO);
+            events_applied += 1;
+            last_sequence = Some(event.sequence);
 
-                if let Some(market) = self.state.markets.get_mut(market_id) {
-                    market.cumulative_funding_index = *new_cumulative_index;
+            if snapshotted
+                && options
+                    .snapshot_policy
+                    .captures(events_applied, &event.event_type)
+            {
+                let snapshot = snapshot::capture(&engine.state, event.sequence);
+                match &mut options.snapshot_sink {
+                    Some(sink) => {
+                        if let Err(e) = sink.accept(snapshot) {
+                            sink_errors.push((event.sequence, e));
+                        }
+                    }
+                    None => {
+                        snapshots.push(snapshot);
+                        options.snapshot_policy.retain(&mut snapshots);
+                    }
                 }
+            }
 
-                let affected = self.state.accounts_with_position_in(market_id);
-                for account_id in &affected {
-                    let account = self.state.accounts.get_mut(account_id).unwrap();
-
-                    let last_index = account
-                        .last_funding
-                        .get(market_id)
-                        .copied()
-                        .unwrap_or(old_index);
-
-                    // Defensive: avoid panics if the affected list ever goes stale.
-                    if let Some(pos) = account.positions.get(market_id) {
-                        let quantity = pos.quantity;
-                        let funding_delta = (last_index - *new_cumulative_index) * quantity;
-                        account.collateral += funding_delta;
-                        account
-                            .last_funding
-                            .insert(market_id.clone(), *new_cumulative_index);
-                    }
+            if let Some(progress) = options.progress.as_mut() {
+                if events_applied.is_multiple_of(options.progress_interval.max(1)) {
+                    progress(ReplayProgress {
+                        events_applied,
+                        current_sequence: event.sequence,
+                        rejections: rejections.len() as u64,
+                        elapsed: started.elapsed(),
+                    });
                 }
+            }
+        }
 
-                ApplyResult::Ok
+        if status == ReplayStatus::Completed && alerts_due {
+            if let (Some(record), Some(last)) = (engine.due_records().first(), last_sequence) {
+                invariant_violations.push((last, missing_record(record)));
             }
+        }
 
-            EventType::LiquidationFill {
-                account_id,
-                market_id,
-                quantity,
-                price,
-            } => {
-                // Direct application — no risk check
-                let account = self.state.get_or_create_account(account_id);
-                apply_trade_to(
-                    &mut account.collateral,
-                    &mut account.positions,
-                    market_id,
-                    *quantity,
-                    *price,
-                );
-                ApplyResult::Ok
+        // Advance next_sequence so future appends (if ever added) are consistent.
+        if let Some(last) = last_sequence {
+            engine.next_sequence = last.saturating_add(1);
+        }
+
+        // Final progress report so callers always see the end position.
+        if let (Some(progress), Some(current_sequence)) = (options.progress.as_mut(), last_sequence)
+        {
+            if !events_applied.is_multiple_of(options.progress_interval.max(1)) {
+                progress(ReplayProgress {
+                    events_applied,
+                    current_sequence,
+                    rejections: rejections.len() as u64,
+                    elapsed: started.elapsed(),
+                });
             }
+        }
 
-            // Rejection events are informational — no state mutation
-            EventType::TradeRejected { .. } | EventType::WithdrawalRejected { .. } => {
-                ApplyResult::Ok
+        ReplayResult {
+            status,
+            config: engine.config,
+            state: engine.state,
+            metrics: engine.metrics,
+            snapshots,
+            events_applied,
+            last_sequence,
+            rejections,
+            invariant_violations,
+            unknown_markets_ignored,
+            derived_records,
+            sink_errors,
+        }
+    }
+
+    /// Resume from a retained snapshot instead of replaying from genesis: restore the
+    /// state `snapshot` was captured from (`snapshot::restore`, which proves that state
+    /// captures back to the snapshot), then replay `log_tail` under the default config,
+    /// like `replay`. `log_tail` may be the whole log; events up to the snapshot are
+    /// skipped. Returns the final state and the tail's snapshots, equal to what
+    /// `replay` returns over the whole log from the snapshot on.
+    pub fn resume_from_snapshot(
+        snapshot: &Snapshot,
+        markets: Vec<Market>,
+        log_tail: &[impl AsRef<Event>],
+    ) -> Result<(State, Vec<Snapshot>), ResumeError> {
+        let result =
+            Self::resume_from_snapshot_with(ReplayOptions::default(), snapshot, markets, log_tail)?;
+        Ok((result.state, result.snapshots))
+    }
+
+    /// `resume_from_snapshot` with the options of `replay_with`. The result's metrics
+    /// and `events_applied` count from the snapshot, not from genesis. `options.config`
+    /// is the config in effect at the snapshot: after a `ConfigUpdated` before it, the
+    /// updated one (a `ReplayResult::config`), not the log's `ConfigMarker`.
+    pub fn resume_from_snapshot_with(
+        options: ReplayOptions,
+        snapshot: &Snapshot,
+        markets: Vec<Market>,
+        log_tail: &[impl AsRef<Event>],
+    ) -> Result<ReplayResult, ResumeError> {
+        let tail = log_tail
+            .iter()
+            .position(|e| e.as_ref().sequence > snapshot.after_sequence)
+            .map_or(&[][..], |start| &log_tail[start..]);
+        let expected = snapshot.after_sequence + 1;
+        if let Some(first) = tail
+            .first()
+            .map(AsRef::as_ref)
+            .filter(|e| e.sequence != expected)
+        {
+            return Err(ResumeError::TailMismatch {
+                expected,
+                found: first.sequence,
+            });
+        }
+        let base = snapshot::restore(snapshot, markets)?;
+        Ok(Self::replay_from(
+            options,
+            base,
+            tail.iter()
+                .map(AsRef::as_ref)
+                .map(Ok::<_, std::convert::Infallible>),
+        ))
+    }
+
+    /// The snapshot after `sequence`: the retained one if there is one, otherwise
+    /// `reconstruct_snapshot`.
+    pub fn snapshot_at(&self, sequence: u64) -> Result<Snapshot, EngineError> {
+        let retained = self
+            .snapshots
+            .binary_search_by_key(&sequence, |s| s.after_sequence)
+            .ok()
+            .map(|i| self.snapshots[i].clone());
+        match retained {
+            Some(snapshot) => Ok(snapshot),
+            None => self.reconstruct_snapshot(sequence),
+        }
+    }
+
+    /// Rebuild the snapshot after `sequence` by replaying `history()` from the nearest
+    /// retained snapshot at or before it, restored with `snapshot::restore`, or from
+    /// the state the engine started from when none is retained (or none restores).
+    /// For a rejected attempt the result equals the snapshot of its rejection event.
+    pub fn reconstruct_snapshot(&self, sequence: u64) -> Result<Snapshot, EngineError> {
+        if sequence < self.base_sequence || sequence >= self.next_sequence {
+            return Err(EngineError::SequenceNotInLog { sequence });
+        }
+
+        let (seed, seed_sequence, config) = match self.replay_seed(sequence)? {
+            Some((state, after_sequence, config)) => (state, Some(after_sequence), config),
+            None => (self.base.clone(), None, self.base_config.clone()),
+        };
+        let mut source_error = None;
+        let events = self
+            .history_borrowed()?
+            .filter(|item| match (item, seed_sequence) {
+                (Ok(event), Some(after)) => event.sequence > after,
+                _ => true,
+            })
+            .map(|item| {
+                item.map_err(|e| {
+                    let message = e.to_string();
+                    source_error = Some(e);
+                    message
+                })
+            });
+        let options = ReplayOptions {
+            stop_at_sequence: Some(sequence),
+            snapshot_policy: SnapshotPolicy::Never,
+            config,
+            ..ReplayOptions::default()
+        };
+        let result = Self::replay_from(options, seed, events);
+        if let Some(e) = source_error {
+            return Err(e);
+        }
+        if let ReplayStatus::ConfigMismatch(fields) = result.status {
+            return Err(EngineError::ConfigMismatch {
+                sequence: self.base_sequence,
+                fields,
+            });
+        }
+        Ok(snapshot::capture(&result.state, sequence))
+    }
+
+    /// The latest retained snapshot at or before `sequence` restored to state, with
+    /// its sequence and the config in effect after it: `base_config` with every
+    /// accepted `ConfigUpdated` up to the snapshot installed. `None` when no retained
+    /// snapshot is that early or the latest one does not restore.
+    fn replay_seed(
+        &self,
+        sequence: u64,
+    ) -> Result<Option<(State, u64, EngineConfig)>, EngineError> {
+        let Some(snapshot) = self
+            .snapshots
+            .iter()
+            .rev()
+            .find(|s| s.after_sequence <= sequence && s.after_sequence >= self.base_sequence)
+        else {
+            return Ok(None);
+        };
+        let markets = self.state.markets.values().cloned().collect();
+        let Ok(state) = snapshot::restore(snapshot, markets) else {
+            return Ok(None);
+        };
+
+        let mut config = self.base_config.clone();
+        let mut events = self
+            .history_borrowed()?
+            .take_while(|item| {
+                item.as_ref()
+                    .map_or(true, |e| e.sequence <= snapshot.after_sequence + 1)
+            })
+            .peekable();
+        while let Some(item) = events.next() {
+            let event = item?;
+            if event.sequence > snapshot.after_sequence {
+                break;
+            }
+            if let EventType::ConfigUpdated { config: next } = &event.event_type {
+                let refused = matches!(events.peek(), Some(Ok(e)) if rejects(e, &event));
+                if !refused && config.check_reload(next).is_ok() {
+                    let risk_checks = std::mem::take(&mut config.risk_checks);
+                    config = EngineConfig {
+                        risk_checks,
+                        ..next.clone()
+                    };
+                }
             }
         }
+        Ok(Some((state, snapshot.after_sequence, config)))
     }
 
-    /// Replay a full event log from scratch. Returns the final state and snapshots.
+    /// Rebuild a live engine after a crash from the spill file of its `LogStore`.
     ///
-    /// Note: During replay, it is EXPECTED that some `TradeFill`/`Withdraw` events may be
-    /// rejected again if the log includes the original attempted action plus an informational
-    /// `TradeRejected`/`WithdrawalRejected` entry. In that case, state remains unchanged and we
-    /// record a warning instead of panicking.
-    pub fn replay(event_log: &[Event], markets: Vec<Market>) -> (State, Vec<Snapshot>) {
-        let mut engine = Engine::new();
+    /// The file is replayed under the config in its `ConfigMarker` (the default if it
+    /// has none); the engine resumes at the next sequence, holding the last
+    /// `memory_capacity` events in memory, and keeps appending to the same file.
+    /// Snapshots start empty. An unreadable line — such as a line torn by the crash —
+    /// fails recovery with the parse error rather than silently dropping history.
+    pub fn recover(
+        path: impl AsRef<Path>,
+        markets: Vec<Market>,
+        options: LogStoreOptions,
+    ) -> Result<Engine, EngineError> {
+        let path = path.as_ref();
+        let config = match jsonl::stream_jsonl(path)?.next().transpose()? {
+            Some(Event {
+                event_type: EventType::ConfigMarker { config, .. },
+                ..
+            }) => config,
+            _ => EngineConfig::default(),
+        };
+
+        let capacity = options.memory_capacity.max(1);
+        let mut recent = VecDeque::with_capacity(capacity);
+        let mut source_error = None;
+        let events = jsonl::stream_jsonl(path)?.map(|item| {
+            let event = item.map_err(|e| {
+                let message = e.to_string();
+                source_error = Some(e);
+                message
+            })?;
+            if recent.len() == capacity {
+                recent.pop_front();
+            }
+            let event = Arc::new(event);
+            recent.push_back(Arc::clone(&event));
+            Ok::<_, String>(event)
+        });
+        let replay_options = ReplayOptions {
+            config: config.clone(),
+            snapshot_policy: SnapshotPolicy::Never,
+            ..ReplayOptions::default()
+        };
+        let mut base = State::new();
         for market in markets {
-            engine.add_market(market);
+            base.markets.insert(market.market_id.clone(), market);
+        }
+        let result = Self::replay_from(replay_options, base.clone(), events);
+        if let Some(e) = source_error {
+            return Err(e);
         }
 
-        // Optional: advance next_sequence so future appends (if ever added) are consistent.
-        if let Some(last) = event_log.last() {
-            engine.next_sequence = last.sequence.saturating_add(1);
+        let mut engine = Engine::with_config(config);
+        engine.base = base;
+        engine.state = result.state;
+        engine.metrics = result.metrics;
+        engine.next_sequence = result.last_sequence.map_or(1, |last| last + 1);
+        engine.events_recorded = result.events_applied;
+        engine.event_log = recent.into();
+        let spilled = result.events_applied - engine.event_log.len() as u64;
+        engine.log_store = Some(LogStore::append_to(path, options, spilled)?);
+        Ok(engine)
+    }
+
+    /// Strict replay of a complete log: any sign that the log does not describe what
+    /// this build would have done is an error rather than a status. Fails when the
+    /// sequences are not contiguous from the first event, when a `ConfigMarker`
+    /// disagrees with `config`, when an engine-generated event could not have been
+    /// generated (see `ReplayResult::invariant_violations`), when the log's
+    /// `UnknownMarketIgnored` markers are not exactly the events replay ignored,
+    /// when an event is rejected on replay without the log recording its rejection
+    /// immediately after it, or recorded as rejected but accepted on replay, when the
+    /// informational records an event caused (funding payments, expiry settlements,
+    /// interest) are not exactly the ones replay derives from it, in any order, or
+    /// when a `caused_by` does not name the external event the run of generated
+    /// events it belongs to follows.
+    pub fn replay_verified(
+        log: &[impl AsRef<Event>],
+        markets: Vec<Market>,
+        config: EngineConfig,
+    ) -> Result<ReplayResult, EngineError> {
+        let mut base = State::new();
+        for market in markets {
+            base.markets.insert(market.market_id.clone(), market);
         }
+        let options = ReplayOptions {
+            config,
+            ..ReplayOptions::default()
+        };
+        Self::replay_verified_from(options, base, log)
+    }
 
-        let mut snapshots = Vec::new();
+    /// `replay_verified` of `log` on top of `base`, the state before its first event,
+    /// which must not be caused by an earlier one.
+    pub(crate) fn replay_verified_from(
+        options: ReplayOptions,
+        base: State,
+        log: &[impl AsRef<Event>],
+    ) -> Result<ReplayResult, EngineError> {
+        let log: Vec<&Event> = log.iter().map(AsRef::as_ref).collect();
+        let log = log.as_slice();
+        if let Some(first) = log.first() {
+            for (expected, event) in (first.sequence..).zip(log) {
+                if event.sequence != expected {
+                    return Err(EngineError::SequenceGap {
+                        expected,
+                        found: event.sequence,
+                    });
+                }
+            }
+        }
 
-        for event in event_log {
-            match engine.apply_event(event) {
-                ApplyResult::Ok => {}
-                ApplyResult::Rejected(reason) => {
-                    // Expected for attempted actions that failed margin checks in live mode.
-                    // State is unchanged (apply_event returned Rejected without mutating).
-                    eprintln!(
-                        "[replay] event seq {} was rejected again (expected): {}",
-                        event.sequence, reason
-                    );
+        // Generated events follow their trigger directly, so each `caused_by` must name
+        // the latest event without one.
+        let mut trigger = None;
+        for event in log {
+            match event.caused_by {
+                None => trigger = Some(event.sequence),
+                Some(caused_by) if Some(caused_by) != trigger => {
+                    return Err(EngineError::CausalityMismatch {
+                        sequence: event.sequence,
+                        caused_by,
+                    });
+                }
+                Some(_) => {}
+            }
+        }
+
+        let events = log.iter().copied().map(Ok::<_, std::convert::Infallible>);
+        let result = Self::replay_from(options, base, events);
+
+        match &result.status {
+            ReplayStatus::Completed => {}
+            ReplayStatus::ConfigMismatch(fields) => {
+                let sequence = result.last_sequence.map_or(log[0].sequence, |s| s + 1);
+                return Err(EngineError::ConfigMismatch {
+                    sequence,
+                    fields: fields.clone(),
+                });
+            }
+            ReplayStatus::Cancelled => {
+                return Err(EngineError::Cancelled {
+                    last_sequence: result.last_sequence,
+                })
+            }
+            ReplayStatus::StoppedAt(_) | ReplayStatus::Errored(_) => {
+                unreachable!("verified replay never stops early and its source is infallible")
+            }
+        }
+
+        if let Some((sequence, reason)) = result.invariant_violations.first() {
+            return Err(EngineError::InvalidDerivedEvent {
+                sequence: *sequence,
+                reason: reason.clone(),
+            });
+        }
+
+        let logged: Vec<(u64, MarketId)> = log
+            .iter()
+            .enumerate()
+            .filter(|(i, e)| !log.get(i + 1).is_some_and(|next| rejects(next, e)))
+            .filter_map(|(_, e)| match &e.event_type {
+                EventType::UnknownMarketIgnored {
+                    market_id,
+                    original_sequence,
+                } => Some((*original_sequence, market_id.clone())),
+                _ => None,
+            })
+            .collect();
+        if logged != result.unknown_markets_ignored {
+            let derived = &result.unknown_markets_ignored;
+            let (sequence, market_id) = match (0..)
+                .map(|i| (logged.get(i), derived.get(i)))
+                .find(|(a, b)| a != b)
+            {
+                Some((Some(a), Some(b))) => a.min(b).clone(),
+                Some((Some(only), None) | (None, Some(only))) => only.clone(),
+                _ => unreachable!("the lists differ"),
+            };
+            return Err(EngineError::UnknownMarketMarkerMismatch {
+                sequence,
+                market_id,
+            });
+        }
+
+        // Each recorded rejection follows its attempt, which replay must reject too.
+        let rejected: BTreeSet<u64> = result
+            .rejections
+            .iter()
+            .map(|(sequence, _)| *sequence)
+            .collect();
+        for pair in log.windows(2) {
+            let (attempt, record) = (&pair[0], &pair[1]);
+            if rejects(record, attempt) && !rejected.contains(&attempt.sequence) {
+                return Err(EngineError::MissingRejection {
+                    sequence: attempt.sequence,
+                });
+            }
+        }
+
+        // The records each trigger derived, in any order within the trigger: merged
+        // shard logs list an event's records shard by shard. Records without
+        // `caused_by`, from logs written before the field, belong to the latest
+        // event that is not itself a record.
+        let mut logged: BTreeMap<u64, Vec<&Event>> = BTreeMap::new();
+        let mut trigger = None;
+        for (i, event) in log.iter().enumerate() {
+            let record = event.event_type.is_informational()
+                && !event.event_type.is_rejection()
+                && !matches!(event.event_type, EventType::DuplicateIgnored { .. });
+            if event.caused_by.is_none() && !record {
+                trigger = Some(event.sequence);
+            }
+            let refused = log.get(i + 1).is_some_and(|next| rejects(next, event));
+            if let Some(trigger) = event.caused_by.or(trigger).filter(|_| record && !refused) {
+                logged.entry(trigger).or_default().push(event);
+            }
+        }
+        let mut derived: BTreeMap<u64, Vec<&EventType>> = BTreeMap::new();
+        for (trigger, record) in &result.derived_records {
+            derived.entry(*trigger).or_default().push(record);
+        }
+        for trigger in logged
+            .keys()
+            .chain(derived.keys())
+            .copied()
+            .collect::<BTreeSet<u64>>()
+        {
+            let records = logged.get(&trigger).map_or(&[][..], |r| r.as_slice());
+            let mut expected = derived.get(&trigger).cloned().unwrap_or_default();
+            let unmatched = records.iter().find(|record| {
+                match expected.iter().position(|e| **e == record.event_type) {
+                    Some(index) => {
+                        expected.remove(index);
+                        false
+                    }
+                    None => true,
                 }
+            });
+            if let Some(record) = unmatched {
+                return Err(EngineError::DerivedRecordMismatch {
+                    sequence: record.sequence,
+                    trigger,
+                });
+            }
+            if !expected.is_empty() {
+                return Err(EngineError::DerivedRecordMismatch {
+                    sequence: records.last().map_or(trigger, |r| r.sequence),
+                    trigger,
+                });
             }
+        }
+
+        let first = log.first().map_or(0, |e| e.sequence);
+        for (sequence, reason) in &result.rejections {
+            let recorded = log
+                .get((sequence - first + 1) as usize)
+                .is_some_and(|next| next.event_type.is_rejection());
+            if !recorded {
+                return Err(EngineError::UnexpectedRejection {
+                    sequence: *sequence,
+                    reason: reason.clone(),
+                });
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+/// Progress report passed to `ReplayOptions::progress`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayProgress {
+    pub events_applied: u64,
+    pub current_sequence: u64,
+    /// Events rejected again so far, as listed in `ReplayResult::rejections`.
+    pub rejections: u64,
+    pub elapsed: Duration,
+}
+
+/// Options for `Engine::replay_with`.
+pub struct ReplayOptions {
+    /// Apply events up to and including this sequence, then stop.
+    pub stop_at_sequence: Option<u64>,
+    /// Called every `progress_interval` applied events, and once at the end.
+    pub progress: Option<Box<dyn FnMut(ReplayProgress)>>,
+    pub progress_interval: u64,
+    pub snapshot_policy: SnapshotPolicy,
+    /// Checked before each event; setting it stops the replay at an event boundary.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Engine config to replay under. Must match the log's `ConfigMarker`, if any.
+    pub config: EngineConfig,
+    /// Takes the captured snapshots in place of `ReplayResult::snapshots`. Refusals
+    /// are listed in `ReplayResult::sink_errors`; replay carries on regardless.
+    pub snapshot_sink: Option<Box<dyn SnapshotSink>>,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self {
+            stop_at_sequence: None,
+            progress: None,
+            progress_interval: 10_000,
+            snapshot_policy: SnapshotPolicy::default(),
+            cancel: None,
+            config: EngineConfig::default(),
+            snapshot_sink: None,
+        }
+    }
+}
 
-            snapshots.push(snapshot::capture(&engine.state, event.sequence));
+/// How a replay ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayStatus {
+    /// The event source was exhausted.
+    Completed,
+    /// Stopped because the next event was past `stop_at_sequence`.
+    StoppedAt(u64),
+    /// The cancel flag was set.
+    Cancelled,
+    /// The event source failed.
+    Errored(String),
+    /// The log's `ConfigMarker` differs from `ReplayOptions::config` in these fields.
+    /// Nothing after the marker was applied.
+    ConfigMismatch(Vec<String>),
+}
+
+/// Outcome of `Engine::replay_with`. `state` and `snapshots` always reflect every
+/// event applied before the replay ended, whatever the status.
+#[derive(Debug, Clone)]
+pub struct ReplayResult {
+    pub status: ReplayStatus,
+    /// The config in effect after the last replayed event: the one replay started
+    /// under, with every `ConfigUpdated` it applied installed.
+    pub config: EngineConfig,
+    pub state: State,
+    /// Cash totals over the replayed events, for `state::solvency`.
+    pub metrics: EngineMetrics,
+    pub snapshots: Vec<Snapshot>,
+    pub events_applied: u64,
+    pub last_sequence: Option<u64>,
+    /// Events rejected on replay, with the reason. Expected for every attempt the
+    /// log records as rejected.
+    pub rejections: Vec<(u64, String)>,
+    /// Engine-generated events the engine could not have produced, e.g. a
+    /// `LiquidationFill` for an unknown account or a position the account does not
+    /// hold. They are skipped, never applied. Empty for any log the engine wrote.
+    pub invariant_violations: Vec<(u64, String)>,
+    /// `(original_sequence, market_id)` of every event replay ignored for naming an
+    /// unregistered market, i.e. the `UnknownMarketIgnored` markers the log should hold.
+    pub unknown_markets_ignored: Vec<(u64, MarketId)>,
+    /// The informational records replay derived itself (funding payments, expiry
+    /// settlements, interest, skipped batch markets, imports below maintenance), each
+    /// with the sequence of the event it derived them from: the records the log
+    /// should hold.
+    pub derived_records: Vec<(u64, EventType)>,
+    /// The snapshots `ReplayOptions::snapshot_sink` refused, by sequence.
+    pub sink_errors: Vec<(u64, SinkError)>,
+}
+
+/// One holder's part of a funding settlement, planned before any account changes.
+struct FundingInstruction {
+    account_id: AccountId,
+    quantity: Decimal,
+    /// The index the holder last settled at; the pre-update index if it never did.
+    last_index: Decimal,
+}
+
+/// Every current holder of `market_id`, in account order, with what its settlement
+/// needs. An entry in `last_funding` only counts while the position is open.
+fn plan_funding(state: &State, market_id: &str, old_index: Decimal) -> Vec<FundingInstruction> {
+    state
+        .accounts
+        .iter()
+        .filter_map(|(account_id, account)| {
+            let pos = account.positions.get(market_id)?;
+            Some(FundingInstruction {
+                account_id: account_id.clone(),
+                quantity: pos.quantity,
+                last_index: account
+                    .last_funding
+                    .get(market_id)
+                    .copied()
+                    .unwrap_or(old_index),
+            })
+        })
+        .collect()
+}
+
+/// A `LiquidationFill` must close (part of) a position the account actually holds:
+/// same market, opposite sign, no larger than the position.
+fn check_liquidation_fill(
+    state: &State,
+    account_id: &AccountId,
+    market_id: &MarketId,
+    quantity: Decimal,
+) -> Result<(), String> {
+    let account = state
+        .accounts
+        .get(account_id)
+        .ok_or_else(|| format!("Liquidation fill for unknown account {account_id}"))?;
+    let position = account.positions.get(market_id).ok_or_else(|| {
+        format!("Liquidation fill for {account_id} in {market_id}, which it has no position in")
+    })?;
+    if !risk::classify_fill(position.quantity, quantity).is_risk_reducing() {
+        return Err(format!(
+            "Liquidation fill of {quantity} for {account_id} in {market_id} does not reduce its position of {}",
+            position.quantity
+        ));
+    }
+    if let Some(market) = state.markets.get(market_id) {
+        let on_step = risk::check_step(market, position.quantity, quantity);
+        if let TradeCheck::Rejected(reason) = on_step {
+            return Err(format!("Liquidation fill for {account_id}: {reason}"));
         }
+    }
+    Ok(())
+}
+
+/// A `ForceCloseFill` must close the whole of a position, at its market's mark, of an
+/// account frozen by the force close it names.
+fn check_force_close_fill(
+    state: &State,
+    account_id: &AccountId,
+    market_id: &MarketId,
+    quantity: Decimal,
+    price: Decimal,
+    reason: &str,
+) -> Result<(), String> {
+    let account = state
+        .accounts
+        .get(account_id)
+        .ok_or_else(|| format!("Force close fill for unknown account {account_id}"))?;
+    if account.frozen.as_deref() != Some(reason) {
+        return Err(format!(
+            "Force close fill for {account_id}, which is not frozen for {reason:?}"
+        ));
+    }
+    let position = account.positions.get(market_id).ok_or_else(|| {
+        format!("Force close fill for {account_id} in {market_id}, which it has no position in")
+    })?;
+    if quantity != -position.quantity {
+        return Err(format!(
+            "Force close fill of {quantity} for {account_id} in {market_id} does not close its position of {}",
+            position.quantity
+        ));
+    }
+    let mark = state.markets.get(market_id).map(|market| market.mark_price);
+    if mark != Some(price) {
+        return Err(format!(
+            "Force close fill for {account_id} in {market_id} at {price}, not at mark"
+        ));
+    }
+    Ok(())
+}
+
+/// Whether `next` records the rejection of `event`. A `ConfigMarker` or
+/// `UnknownMarketIgnored` followed by its rejection was submitted and refused, so it
+/// says nothing about the config or the markets ignored.
+fn rejects(next: &Event, event: &Event) -> bool {
+    next.caused_by == Some(event.sequence) && next.event_type.is_rejection()
+}
 
-        (engine.state, snapshots)
+/// Why replay flags a margin call, risk alert or skew record the log should hold but
+/// does not.
+fn missing_record(record: &EventType) -> String {
+    match record {
+        EventType::RiskAlert {
+            account_id, level, ..
+        }
+        | EventType::RiskAlertCleared {
+            account_id, level, ..
+        } => {
+            format!("no risk alert moved {account_id} to alert level {level}")
+        }
+        EventType::MarginCall { account_id, .. } => format!("no margin call for {account_id}"),
+        EventType::MarginCallCleared { account_id, .. } => {
+            format!("no MarginCallCleared for {account_id}")
+        }
+        EventType::SkewLimitBreached {
+            market_id,
+            net_notional,
+        } => {
+            format!("no SkewLimitBreached for {market_id} at net notional {net_notional}")
+        }
+        EventType::SkewLimitCleared {
+            market_id,
+            net_notional,
+        } => {
+            format!("no SkewLimitCleared for {market_id} at net notional {net_notional}")
+        }
+        other => unreachable!("not a margin call, risk alert or skew record: {other:?}"),
     }
 }
+
+/// Funding events are refused for dated futures.
+fn no_funding(market_id: &MarketId) -> ApplyResult {
+    ApplyResult::Rejected(format!("{market_id} is a future and pays no funding"))
+}
+
+/// Apply an accepted mark at `sequence`, stamped with the log clock.
+fn set_mark(market: &mut Market, price: Decimal, sequence: u64, clock: Option<u64>) {
+    market.mark_price = price;
+    market.last_mark_sequence = Some(sequence);
+    market.last_mark_timestamp = clock;
+    market.stale = false;
+}
+
+/// Whether `event_type` can change accounts it does not name, beyond the holders of
+/// the markets it moves: interest and yield reach every account, and a hedge pair
+/// every holder of both its legs.
+fn moves_every_account(event_type: &EventType) -> bool {
+    matches!(
+        event_type,
+        EventType::InterestTick { .. }
+            | EventType::YieldDistribution { .. }
+            | EventType::HedgePairAdded { .. }
+    )
+}
@@ -0,0 +1,404 @@
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::engine::ProcessOutcome;
+use crate::jsonl::LogDefect;
+use crate::types::{AccountId, MarketId};
+
+/// Every fallible operation outside the event path itself — reading and writing
+/// logs, verified replay — fails with this one type. Business rejections (margin,
+/// prices, limits) are not errors: they are logged events, surfaced to callers as
+/// `ProcessOutcome::Rejected`.
+#[derive(Debug, Error)]
+pub enum EngineError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("failed to serialize event: {0}")]
+    Serialize(#[source] serde_json::Error),
+
+    /// A JSONL line failed to parse. `line` is 1-based.
+    #[error("line {line}: {source}")]
+    Parse {
+        line: usize,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    /// Refused to write a dry-run log without `WriteOptions::allow_dry_run`.
+    #[error("refusing to write dry-run event seq {sequence} without allow_dry_run")]
+    DryRunLog { sequence: u64 },
+
+    /// A `LogStore` was attached to a dry-run engine.
+    #[error("a dry-run engine cannot spill its log to a log store")]
+    DryRunLogStore,
+
+    /// A sequence outside the engine's log was requested.
+    #[error("seq {sequence} is not in the log")]
+    SequenceNotInLog { sequence: u64 },
+
+    /// The log's sequences are not contiguous.
+    #[error("sequence gap: expected seq {expected}, found seq {found}")]
+    SequenceGap { expected: u64, found: u64 },
+
+    /// `jsonl::repair` found defects its policy does not drop. `defects` lists every
+    /// defect in the log, not only the refused ones.
+    #[error("log has {} defect(s), not all of which the repair policy drops", defects.len())]
+    CorruptLog { defects: Vec<LogDefect> },
+
+    /// The log's `ConfigMarker` at `sequence` differs from the replay config.
+    #[error("config marker at seq {sequence} differs in: {}", fields.join(", "))]
+    ConfigMismatch { sequence: u64, fields: Vec<String> },
+
+    /// An engine-generated event the engine could not have produced from the state
+    /// at that point.
+    #[error("seq {sequence} is an invalid engine-generated event: {reason}")]
+    InvalidDerivedEvent { sequence: u64, reason: String },
+
+    /// Replay rejected an event that the log does not record as rejected.
+    #[error("seq {sequence} was rejected on replay but the log records no rejection: {reason}")]
+    UnexpectedRejection { sequence: u64, reason: String },
+
+    /// The log's `UnknownMarketIgnored` markers differ from the events replay ignored,
+    /// first at the event at `sequence`.
+    #[error("unknown-market marker mismatch for {market_id} at seq {sequence}")]
+    UnknownMarketMarkerMismatch { sequence: u64, market_id: MarketId },
+
+    /// The log records an event as rejected that replay accepted.
+    #[error("seq {sequence} is recorded as rejected but was accepted on replay")]
+    MissingRejection { sequence: u64 },
+
+    /// The informational records the log holds for the event at `trigger` (funding
+    /// payments, expiry settlements, interest and the like) differ from the ones
+    /// replay derived from it, first at seq `sequence`.
+    #[error("records derived from seq {trigger} differ from replay, first at seq {sequence}")]
+    DerivedRecordMismatch { sequence: u64, trigger: u64 },
+
+    /// An engine-generated event names a trigger other than the external event it
+    /// follows.
+    #[error("seq {sequence} claims to be caused by seq {caused_by}, which did not trigger it")]
+    CausalityMismatch { sequence: u64, caused_by: u64 },
+
+    /// A checkpoint after `sequence`, which the event at `next` follows as part of its
+    /// cascade or as its rejection record. Checkpoints fall between whole events.
+    #[error("a checkpoint after seq {sequence} splits it from seq {next}, which it caused")]
+    CheckpointMidCascade { sequence: u64, next: u64 },
+
+    /// The replay was cancelled before the end of the log.
+    #[error("replay cancelled after seq {last_sequence:?}")]
+    Cancelled { last_sequence: Option<u64> },
+
+    /// A `DurableEngine` journal whose first line is not its `ConfigMarker` header.
+    #[error("journal does not start with a config marker at seq 0")]
+    JournalHeader,
+
+    /// A `DurableEngine` whose journal failed a write or fsync earlier. The journal may
+    /// hold submissions the engine never applied, so it takes no more.
+    #[error("the journal failed an earlier write; reopen it to recover")]
+    JournalFailed,
+
+    /// The engine's `LogStore` failed to append or flush the event at `sequence`
+    /// (`ProcessOutcome::into_result`). The engine takes no more events.
+    #[error("log store failed at seq {sequence}: {error}")]
+    LogStoreFailed { sequence: u64, error: String },
+
+    /// `Engine::add_market` or `Engine::remove_market` refused.
+    #[error(transparent)]
+    Market(#[from] MarketError),
+
+    /// The snapshot sink refused the snapshot after `sequence`, recorded for an event
+    /// that went as `outcome` says (`ProcessOutcome::into_result`).
+    #[error("snapshot sink refused the snapshot after seq {sequence}: {source}")]
+    SnapshotSink {
+        sequence: u64,
+        #[source]
+        source: SinkError,
+        outcome: Box<ProcessOutcome>,
+    },
+}
+
+/// Why `Engine::add_market` or `Engine::remove_market` refused. Nothing is registered,
+/// removed or logged.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum MarketError {
+    /// Parameters that fail `Market::validate`.
+    #[error("invalid market: {0}")]
+    Invalid(#[from] MarketConfigError),
+
+    /// A value beyond `risk::MAX_EVENT_VALUE`, which `MarketAdded` would be rejected
+    /// for.
+    #[error("{0}")]
+    OutOfRange(String),
+
+    /// A market is already registered under this id. Adding it again would reset its
+    /// mark and funding index under every holder.
+    #[error("market {market_id} is already registered")]
+    Duplicate { market_id: MarketId },
+
+    #[error("market {market_id} is not registered")]
+    Unknown { market_id: MarketId },
+
+    /// Accounts still hold positions in the market, so it has open interest.
+    #[error("market {market_id} has open positions held by: {}", accounts.join(", "))]
+    OpenPositions {
+        market_id: MarketId,
+        accounts: Vec<AccountId>,
+    },
+
+    /// Accounts are still owed or owe funding accrued in the market, which only its
+    /// next settlement pays.
+    #[error("market {market_id} has unsettled accrued funding for: {}", accounts.join(", "))]
+    PendingFunding {
+        market_id: MarketId,
+        accounts: Vec<AccountId>,
+    },
+
+    /// The engine's `LogStore` failed earlier, so it takes no more events
+    /// (`ProcessOutcome::LogStoreFailed`).
+    #[error("log store failed at seq {sequence}: {error}")]
+    LogStoreFailed { sequence: u64, error: String },
+}
+
+/// Why a string is not a valid `AccountId` or `MarketId`. `kind` is `"account"` or
+/// `"market"`.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum IdError {
+    #[error("{kind} id is empty")]
+    Empty { kind: &'static str },
+
+    #[error(
+        "{kind} id is {len} bytes long, over the limit of {}",
+        crate::types::MAX_ID_LEN
+    )]
+    TooLong { kind: &'static str, len: usize },
+
+    /// A character other than an ASCII letter or digit, `-`, `_`, `.` or `:`.
+    #[error(
+        "{kind} id {id:?} contains {found:?}; ids are ASCII letters, digits, '-', '_', '.' and ':'"
+    )]
+    InvalidChar {
+        kind: &'static str,
+        id: String,
+        found: char,
+    },
+}
+
+/// Why `Market::validate` refused a market's parameters.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum MarketConfigError {
+    /// The fractions break `0 < maintenance <= initial < 1`.
+    #[error(
+        "{market_id}: margin fractions must satisfy 0 < maintenance <= initial < 1, \
+         got initial {initial}, maintenance {maintenance}"
+    )]
+    MarginFractions {
+        market_id: MarketId,
+        initial: Decimal,
+        maintenance: Decimal,
+    },
+
+    /// A discount, slippage, threshold, cap or multiplier below zero.
+    #[error("{market_id}: {field} must not be negative, got {value}")]
+    Negative {
+        market_id: MarketId,
+        field: &'static str,
+        value: Decimal,
+    },
+
+    /// A `min_maintenance_margin` above `min_initial_margin` (or without one), which
+    /// would charge a small position less to open than to keep.
+    #[error(
+        "{market_id}: min_maintenance_margin ({maintenance}) must not exceed \
+         min_initial_margin ({})",
+        initial.map_or("none".to_string(), |initial| initial.to_string())
+    )]
+    MarginFloors {
+        market_id: MarketId,
+        initial: Option<Decimal>,
+        maintenance: Decimal,
+    },
+
+    /// A `max_leverage` below 1, or high enough that IM at it would fall under MM.
+    #[error(
+        "{market_id}: max_leverage must be at least 1 and at most 1 / maintenance \
+         ({maintenance}), got {max_leverage}"
+    )]
+    MaxLeverage {
+        market_id: MarketId,
+        max_leverage: Decimal,
+        maintenance: Decimal,
+    },
+
+    /// A `quantity_step` of zero or below.
+    #[error("{market_id}: quantity_step must be positive, got {step}")]
+    QuantityStep { market_id: MarketId, step: Decimal },
+
+    /// A `stale_im_multiplier` below 1, which would lower IM on a stale mark.
+    #[error("{market_id}: stale_im_multiplier must be at least 1, got {multiplier}")]
+    StaleImMultiplier {
+        market_id: MarketId,
+        multiplier: Decimal,
+    },
+}
+
+/// Why `EngineConfig::validate` refused a config, or `EngineConfig::check_reload` a
+/// change to it.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum EngineConfigError {
+    /// Fields a running engine cannot change (`EngineConfig::CONSTRUCTION_ONLY`).
+    #[error("{} cannot be changed on a running engine", .fields.join(", "))]
+    ConstructionOnly { fields: Vec<String> },
+
+    /// A `withdrawal_buffer` below 1, which would let a withdrawal leave equity under IM.
+    #[error("withdrawal_buffer must be at least 1, got {0}")]
+    WithdrawalBuffer(Decimal),
+
+    /// `LiquidationPath::Keepers` without a keeper, or naming one twice.
+    #[error("liquidation_path: {0}")]
+    Keepers(String),
+
+    /// A total open interest cap, interest rate, alert hysteresis or fee tier turnover
+    /// below zero.
+    #[error("{field} must not be negative, got {value}")]
+    Negative { field: &'static str, value: Decimal },
+
+    /// Alert thresholds that are not positive and strictly ascending, or none at all.
+    #[error("risk_alerts thresholds must be positive and strictly ascending")]
+    AlertThresholds,
+
+    /// A rejection throttle, statistics window or margin call grace of zero.
+    #[error("{field} must be at least 1")]
+    Zero { field: &'static str },
+
+    /// A margin call `hard_fraction` below 0 or above 1.
+    #[error("margin_call.hard_fraction must be between 0 and 1, got {0}")]
+    HardFraction(Decimal),
+}
+
+/// Why a `SnapshotSink` refused a snapshot. Only the message is kept, so that the
+/// error can travel in a `ProcessOutcome`, which clones and compares.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{0}")]
+pub struct SinkError(pub String);
+
+impl From<std::io::Error> for SinkError {
+    fn from(e: std::io::Error) -> Self {
+        SinkError(format!("I/O error: {e}"))
+    }
+}
+
+impl From<serde_json::Error> for SinkError {
+    fn from(e: serde_json::Error) -> Self {
+        SinkError(format!("failed to serialize snapshot: {e}"))
+    }
+}
+
+/// Why `State::from_json` refused a state file.
+#[derive(Debug, Error)]
+pub enum StateLoadError {
+    /// Not JSON, or not a state file (including one without `schema_version`).
+    #[error("invalid state file: {0}")]
+    Parse(#[from] serde_json::Error),
+
+    #[error("state schema version {found} is not supported (expected {supported})")]
+    SchemaVersion { found: u32, supported: u32 },
+
+    /// Positions in markets the state does not register, as `(account, market)`.
+    #[error("positions in unknown markets: {}", format_positions(.0))]
+    UnknownMarkets(Vec<(AccountId, MarketId)>),
+
+    /// Accounts whose `group_id` and the member lists of the groups disagree: a group
+    /// that does not exist or does not list the account, or a member of a group that
+    /// names another group or none.
+    #[error("group membership disagrees with the groups for accounts: {}", .0.join(", "))]
+    GroupMembership(Vec<AccountId>),
+
+    #[error(transparent)]
+    InvalidMarket(#[from] MarketConfigError),
+}
+
+/// Why `Engine::resume_from_snapshot` could not resume.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ResumeError {
+    /// The snapshot prices a market that is not among the markets given.
+    #[error("snapshot at seq {after_sequence} has market {market_id}, which was not given")]
+    UnknownMarket {
+        after_sequence: u64,
+        market_id: MarketId,
+    },
+
+    /// The state restored from the snapshot does not capture back to it: the snapshot
+    /// was altered, or the markets given differ from the ones it was taken under.
+    /// `part` names the first account, market or engine-level section that differs.
+    #[error("snapshot at seq {after_sequence} does not capture back from its restored state: {part} differs")]
+    Inconsistent { after_sequence: u64, part: String },
+
+    /// The log tail does not start right after the snapshot.
+    #[error("log tail starts at seq {found}, expected seq {expected}")]
+    TailMismatch { expected: u64, found: u64 },
+}
+
+/// Why `events::merge` refused to merge shard logs.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum MergeError {
+    /// Accounts named in more than one log, each with the shards naming it.
+    #[error("accounts in more than one shard: {}", format_shared(.0))]
+    SharedAccounts(Vec<(AccountId, Vec<usize>)>),
+
+    /// The shard's log was written under a config other than the first shard's.
+    #[error("shard {shard} has a different config in: {}", fields.join(", "))]
+    ConfigMismatch { shard: usize, fields: Vec<String> },
+
+    /// An external event without a timestamp, under `MergeKey::Timestamp`.
+    #[error("shard {shard} seq {sequence} has no timestamp")]
+    MissingTimestamp { shard: usize, sequence: u64 },
+
+    /// An engine-generated record with no external event before it.
+    #[error("shard {shard} seq {sequence} is an engine-generated event without a trigger")]
+    Orphaned { shard: usize, sequence: u64 },
+
+    /// Two shards logged the same market events in different orders.
+    #[error("shards {shard} and {other_shard} order market events differently, at shard {shard} seq {sequence}")]
+    MarketEventOrder {
+        shard: usize,
+        sequence: u64,
+        other_shard: usize,
+    },
+
+    /// Two shards derived different market-level records (a rejection, skipped
+    /// markets) from the same market event.
+    #[error(
+        "shards {shard} and {other_shard} disagree on the outcome of shard {shard} seq {sequence}"
+    )]
+    DivergentOutcome {
+        shard: usize,
+        sequence: u64,
+        other_shard: usize,
+    },
+}
+
+/// Why `events::split_by_account` refused to split a log.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum SplitError {
+    /// An external event naming accounts `assignment` puts in different shards,
+    /// such as a takeover whose keeper is assigned elsewhere than the liquidated
+    /// account.
+    #[error("seq {sequence} names accounts in shards {shards:?}")]
+    CrossShard { sequence: u64, shards: Vec<usize> },
+}
+
+fn format_shared(accounts: &[(AccountId, Vec<usize>)]) -> String {
+    accounts
+        .iter()
+        .map(|(account_id, shards)| format!("{account_id} in shards {shards:?}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn format_positions(positions: &[(AccountId, MarketId)]) -> String {
+    positions
+        .iter()
+        .map(|(account_id, market_id)| format!("{account_id} in {market_id}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
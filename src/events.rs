@@ -1,8 +1,14 @@
 use rust_decimal::Decimal;
-use rust_decimal::serde::str;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::Arc;
 
-use crate::types::{AccountId, MarketId};
+use crate::config::EngineConfig;
+use crate::decimal_str;
+use crate::error::{MergeError, SplitError};
+use crate::types::{
+    default_pool, AccountId, GroupId, ImportedPosition, Market, MarketId, OrderId, PoolId,
+};
 
 /// A fully ordered, replayable event.
 /// The event log is the sole source of truth for state reconstruction.
@@ -10,60 +16,1776 @@ use crate::types::{AccountId, MarketId};
 pub struct Event {
     pub sequence: u64,
     pub event_type: EventType,
+
+    /// Set on every event produced by a `DryRun` engine. Dry-run output is
+    /// non-authoritative and must never be persisted as a real log.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+
+    /// Caller-supplied key for an externally submitted event. A later submission
+    /// with a key still in the engine's window is logged as `DuplicateIgnored`
+    /// instead of being applied.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+
+    /// Submission time in Unix milliseconds, if the submitter supplied one.
+    /// Engine-generated events carry none; they happen at their trigger's time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+
+    /// Sequence of the external event whose processing generated this one: its
+    /// rejection, the records derived while applying it, and the liquidations it set
+    /// off. `None` on external events, on the `ConfigMarker`, `DuplicateIgnored` and
+    /// `RejectionSuppressed`, and throughout logs written before the field existed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caused_by: Option<u64>,
+
+    /// Whether a caller submitted the event or the engine wrote it. Only `Engine` is
+    /// written out, so a submitted event serializes as it did before the field
+    /// existed; logs from before it read as `External` throughout.
+    #[serde(default, skip_serializing_if = "Origin::is_external")]
+    pub origin: Origin,
+
+    /// Index of the shard log this event came from, set by `merge`. `None` on the
+    /// merged log's `ConfigMarker` and on every event an engine writes itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin_shard: Option<usize>,
+}
+
+/// Lets log-reading functions take `&[impl AsRef<Event>]`: owned logs, borrowed
+/// events, and `Engine::event_log`'s shared `Arc<Event>`s alike.
+impl AsRef<Event> for Event {
+    fn as_ref(&self) -> &Event {
+        self
+    }
+}
+
+/// `Engine::event_log` compares equal to a log read back from a file.
+impl PartialEq<Event> for Arc<Event> {
+    fn eq(&self, other: &Event) -> bool {
+        **self == *other
+    }
+}
+
+impl PartialEq<Arc<Event>> for Event {
+    fn eq(&self, other: &Arc<Event>) -> bool {
+        *self == **other
+    }
+}
+
+impl Event {
+    pub fn new(sequence: u64, event_type: EventType) -> Self {
+        Self {
+            sequence,
+            event_type,
+            dry_run: false,
+            idempotency_key: None,
+            timestamp: None,
+            caused_by: None,
+            origin: Origin::External,
+            origin_shard: None,
+        }
+    }
+
+    /// An engine-generated event triggered by the external event at `caused_by`.
+    pub fn derived(sequence: u64, event_type: EventType, caused_by: u64) -> Self {
+        Self {
+            caused_by: Some(caused_by),
+            ..Self::generated(sequence, event_type)
+        }
+    }
+
+    /// An event the engine writes without a cause: the config marker, a duplicate
+    /// marker or a rejection summary.
+    pub fn generated(sequence: u64, event_type: EventType) -> Self {
+        Self {
+            origin: Origin::Engine,
+            ..Self::new(sequence, event_type)
+        }
+    }
+}
+
+/// Who put an event in the log: a caller, through `Engine::process`, or the engine
+/// itself. `Engine::regenerate` resubmits the `External` events of a log and nothing
+/// else.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Origin {
+    #[default]
+    External,
+    Engine,
+}
+
+impl Origin {
+    pub fn is_external(&self) -> bool {
+        *self == Origin::External
+    }
+}
+
+/// The side of a match a `TradeFill` was on: the resting order (`Maker`) or the one
+/// that crossed it (`Taker`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Liquidity {
+    Maker,
+    Taker,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(tag = "type")]
 pub enum EventType {
+    /// Written by a live engine as the first event of its log: the engine config the
+    /// log was produced under. Replay checks it against its own config.
+    ConfigMarker {
+        config_hash: String,
+        config: EngineConfig,
+    },
+    /// Install `config` from this sequence on, on a running engine. Rejected if it
+    /// fails `EngineConfig::validate` or changes a field in
+    /// `EngineConfig::CONSTRUCTION_ONLY`. The config is logged whole, so replay
+    /// switches at the same sequence.
+    ConfigUpdated {
+        config: EngineConfig,
+    },
     Deposit {
         account_id: AccountId,
-        #[serde(with = "str")]
+        #[serde(with = "decimal_str")]
         amount: Decimal,
     },
     Withdraw {
         account_id: AccountId,
-        #[serde(with = "str")]
+        #[serde(with = "decimal_str")]
         amount: Decimal,
     },
     TradeFill {
         account_id: AccountId,
         market_id: MarketId,
-        #[serde(with = "str")]
+        #[serde(with = "decimal_str")]
         quantity: Decimal,
-        #[serde(with = "str")]
+        #[serde(with = "decimal_str")]
         price: Decimal,
+        /// Which side of the match the account was on, if the venue says. Counted in
+        /// the maker/taker split of `AccountStats`; omitted when unknown.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        liquidity: Option<Liquidity>,
+    },
+    /// Engine-generated record of the fee charged for an accepted `TradeFill`:
+    /// `rate` times the fill's notional, rounded up to collateral precision (negative
+    /// for a rebate). Informational: the fill itself moves the collateral.
+    FeeCharged {
+        account_id: AccountId,
+        market_id: MarketId,
+        #[serde(with = "decimal_str")]
+        rate: Decimal,
+        #[serde(with = "decimal_str")]
+        amount: Decimal,
     },
     MarkPriceUpdate {
         market_id: MarketId,
-        #[serde(with = "str")]
+        #[serde(with = "decimal_str")]
         price: Decimal,
     },
+    /// Marks for several markets from one feed tick, applied atomically under one
+    /// sequence and followed by a single liquidation scan. Unregistered markets are
+    /// skipped (see `MarkPriceBatchSkipped`); one invalid price rejects the batch.
+    MarkPriceBatch {
+        #[serde(with = "decimal_str::map")]
+        updates: BTreeMap<MarketId, Decimal>,
+    },
     FundingUpdate {
         market_id: MarketId,
-        #[serde(with = "str")]
+        #[serde(with = "decimal_str")]
         new_cumulative_index: Decimal,
     },
+    /// Funding quoted as a rate for one interval. The engine derives the index
+    /// increment from the market's `funding_rate_formula` and settles it like
+    /// `FundingUpdate`.
+    FundingRate {
+        market_id: MarketId,
+        #[serde(with = "decimal_str")]
+        rate: Decimal,
+        interval_id: u64,
+    },
+    /// The venue's running funding index, accrued but not settled: each holder's
+    /// funding since it last settled or accrued is added to its pending funding, and
+    /// only the next `FundingUpdate` or `FundingRate` pays it out. Counts toward equity
+    /// at once in a `FundingMode::Continuous` market. Rejected for an unknown market or
+    /// a future.
+    FundingAccrual {
+        market_id: MarketId,
+        #[serde(with = "decimal_str")]
+        accrued_index: Decimal,
+    },
+    /// Engine-generated record of one account's settled funding for a funding event,
+    /// or of the pending funding a `PositionTransfer` settled first. `amount` is the
+    /// signed collateral change (positive = received). Informational: the settlement
+    /// is applied by the event that caused it.
+    FundingPayment {
+        account_id: AccountId,
+        market_id: MarketId,
+        #[serde(with = "decimal_str")]
+        amount: Decimal,
+    },
+    /// Engine-generated record of the markets a `MarkPriceBatch` skipped because they
+    /// are not registered. Informational.
+    MarkPriceBatchSkipped {
+        market_ids: Vec<MarketId>,
+    },
+    /// Engine-generated record that the `MarkPriceUpdate` or `FundingUpdate` at
+    /// `original_sequence` names a market that is not registered, so it changed
+    /// nothing. Informational; only logged under `UnknownMarketPolicy::Ignore`.
+    UnknownMarketIgnored {
+        market_id: MarketId,
+        original_sequence: u64,
+    },
+    /// Set (or clear, with `None`) compliance limits on an account.
+    SetAccountLimits {
+        account_id: AccountId,
+        #[serde(with = "decimal_str::option")]
+        max_leverage: Option<Decimal>,
+        #[serde(with = "decimal_str::option")]
+        max_total_notional: Option<Decimal>,
+    },
+    /// Rest an order of `quantity` (signed like a fill) in `market_id` for an account,
+    /// reserving the margin a fill of all of it would need. Rejected for an id the
+    /// account already rests, or when equity does not cover IM plus every reservation
+    /// including the new one. Fills do not consume orders; the submitter cancels them.
+    OrderPlaced {
+        account_id: AccountId,
+        order_id: OrderId,
+        market_id: MarketId,
+        #[serde(with = "decimal_str")]
+        quantity: Decimal,
+    },
+    /// Take a resting order off, releasing its reservation.
+    OrderCancelled {
+        account_id: AccountId,
+        order_id: OrderId,
+    },
+    /// Set an operator-facing metadata entry on an account (empty `value` removes
+    /// the key). Has no effect on margin.
+    AccountMetadata {
+        account_id: AccountId,
+        key: String,
+        value: String,
+    },
+    /// Create `account_id` in collateral pool `pool_id`. Rejected once the account
+    /// exists: an account's pool never changes.
+    AssignPool {
+        account_id: AccountId,
+        pool_id: PoolId,
+    },
+    /// Create account group `group_id`, whose members share `max_group_notional`
+    /// (`None` = uncapped) and pay `fee_override` as their trade fee rate. Rejected
+    /// if the group exists, the cap is negative or the rate is not inside (-1, 1).
+    GroupCreated {
+        group_id: GroupId,
+        #[serde(default, with = "decimal_str::option")]
+        max_group_notional: Option<Decimal>,
+        #[serde(default, with = "decimal_str::option")]
+        fee_override: Option<Decimal>,
+    },
+    /// Move `account_id` into `group_id`, or out of its group with `None`. Rejected
+    /// for an account or group that does not exist.
+    GroupMembershipSet {
+        account_id: AccountId,
+        group_id: Option<GroupId>,
+    },
+    /// Margin `account_id`'s position in `market_id` at `leverage`: its IM becomes
+    /// notional / leverage in place of the market's fraction. Rejected for an unknown
+    /// account or market, a market without `max_leverage` or a leverage outside
+    /// `[1, max_leverage]`, or a lower leverage the account's equity cannot cover.
+    SetPositionLeverage {
+        account_id: AccountId,
+        market_id: MarketId,
+        #[serde(with = "decimal_str")]
+        leverage: Decimal,
+    },
+    /// Commit `account_id` as a backstop in `market_id`: liquidations there offer it
+    /// their closes, up to `max_notional` held at mark, before any keeper or the engine
+    /// close. Registering again changes the cap and keeps the place in the queue.
+    /// Rejected for an unknown account or market or a negative cap.
+    BackstopRegistered {
+        account_id: AccountId,
+        market_id: MarketId,
+        #[serde(with = "decimal_str")]
+        max_notional: Decimal,
+    },
+    /// Merge `from` into `to`, as support does with a customer's duplicate account:
+    /// `from`'s balances, positions, funding and statistics move to `to`, which is
+    /// created in `from`'s pool if it does not exist, and `from` is closed. Opposite
+    /// positions in one market net, realizing PnL. Rejected unless the accounts differ
+    /// and share a pool, neither is frozen, suspended or in liquidation, and the merged
+    /// account is above maintenance margin.
+    AccountsMerged {
+        from: AccountId,
+        to: AccountId,
+    },
+    /// Move `quantity` of `from`'s position in `market_id` to `to` at an agreed
+    /// `transfer_price`, as a prime broker moves a client's position between accounts
+    /// off market. `quantity` is signed like the position it is taken from. `from`
+    /// realizes PnL as if it closed that much at the price, and `to` fills the same
+    /// quantity at it, after both have their pending funding in the market settled.
+    /// Rejected unless the accounts differ and share a pool, neither is frozen, the
+    /// quantity is part of `from`'s position, and each side passes its margin check
+    /// afterwards (see `risk::check_position_transfer`).
+    PositionTransfer {
+        from: AccountId,
+        to: AccountId,
+        market_id: MarketId,
+        #[serde(with = "decimal_str")]
+        quantity: Decimal,
+        #[serde(with = "decimal_str")]
+        transfer_price: Decimal,
+    },
+    /// Add `amount` to `pool_id`'s insurance fund.
+    InsuranceFundDeposit {
+        pool_id: PoolId,
+        #[serde(with = "decimal_str")]
+        amount: Decimal,
+    },
+    /// Create `account_id` in `pool_id` with collateral and open positions carried
+    /// over from another system, without a trade history. Rejected once the account
+    /// exists or if a position names an unknown market; an import at or under
+    /// maintenance margin is handled per `EngineConfig::import_margin_check`.
+    StateImport {
+        account_id: AccountId,
+        #[serde(default = "default_pool")]
+        pool_id: PoolId,
+        #[serde(with = "decimal_str")]
+        collateral: Decimal,
+        positions: Vec<ImportedPosition>,
+    },
+    /// Engine-generated under `ImportMarginCheck::Warn`: the `StateImport` just applied
+    /// left `account_id` at or under maintenance margin. Informational; the account is
+    /// liquidated like any other.
+    StateImportBelowMaintenance {
+        account_id: AccountId,
+        #[serde(with = "decimal_str")]
+        equity: Decimal,
+        #[serde(with = "decimal_str")]
+        maintenance_margin: Decimal,
+    },
+    /// Register `market` on a live engine (`Engine::add_market` after the first event).
+    /// Rejected if its parameters fail `Market::validate` or its id is registered.
+    MarketAdded {
+        market: Box<Market>,
+    },
+    /// Deregister a market. Rejected while any account holds a position in it. Hedge
+    /// pairs it is in are dropped, as are accounts' leverage selections in it.
+    MarketRemoved {
+        market_id: MarketId,
+    },
+    /// Open a market's trading session. Under `ClosedSessionLiquidation::DeferUntilOpen`
+    /// this is when liquidations deferred in the market execute.
+    SessionOpen {
+        market_id: MarketId,
+    },
+    /// Close a market's trading session: only risk-reducing fills are accepted until
+    /// the next `SessionOpen`. Marks keep applying.
+    SessionClose {
+        market_id: MarketId,
+    },
+    /// Give opposite positions in `market_a` and `market_b` margin relief of
+    /// `offset_fraction` on their overlapping notional. Rejected unless both markets
+    /// are registered, distinct and in no other pair, and the fraction is in `(0, 1]`.
+    HedgePairAdded {
+        market_a: MarketId,
+        market_b: MarketId,
+        #[serde(with = "decimal_str")]
+        offset_fraction: Decimal,
+    },
+    /// Settle every position in a dated future at `settlement_price`, which becomes
+    /// the final mark, and retire the market. Rejected for a perpetual, a market that
+    /// already expired, or before the market's expiry on the log clock.
+    Expiry {
+        market_id: MarketId,
+        #[serde(with = "decimal_str")]
+        settlement_price: Decimal,
+    },
+    /// Engine-generated record of one account's position closed by an `Expiry`:
+    /// `quantity` is the closing fill (the position, negated) and `realized_pnl` the
+    /// collateral change. Informational: the expiry itself applies the settlement.
+    ExpirySettlement {
+        account_id: AccountId,
+        market_id: MarketId,
+        #[serde(with = "decimal_str")]
+        quantity: Decimal,
+        #[serde(with = "decimal_str")]
+        price: Decimal,
+        #[serde(with = "decimal_str")]
+        realized_pnl: Decimal,
+    },
+    /// Accrue one interval of interest on collateral balances under the engine's
+    /// `InterestAccrual` config. Idempotent on `interval_id`.
+    InterestTick {
+        interval_id: u64,
+    },
+    /// Engine-generated record of one account's interest for an `InterestTick`.
+    /// `amount` is the signed collateral change (negative = charged). Informational:
+    /// the tick itself moves the collateral.
+    InterestCharged {
+        account_id: AccountId,
+        #[serde(with = "decimal_str")]
+        amount: Decimal,
+    },
+    /// Pay `rate` on collateral, as the venue's interest on idle balances: positive
+    /// balances, or every balance under `YieldBasis::AllBalances`. Idempotent on
+    /// `interval_id`.
+    YieldDistribution {
+        #[serde(with = "decimal_str")]
+        rate: Decimal,
+        interval_id: u64,
+    },
+    /// Engine-generated record of one account's payment from a `YieldDistribution`,
+    /// rounded down to collateral precision. `amount` is the signed collateral change.
+    /// Informational: the distribution itself moves the collateral.
+    YieldPaid {
+        account_id: AccountId,
+        interval_id: u64,
+        #[serde(with = "decimal_str")]
+        amount: Decimal,
+    },
+    /// Engine-generated record of what rounding kept back from one pool's payments in
+    /// a `YieldDistribution`: `rate` times the pool's yield basis, less its
+    /// `YieldPaid` amounts. It stays in the pool's interest revenue. Informational.
+    YieldResidual {
+        pool_id: PoolId,
+        interval_id: u64,
+        #[serde(with = "decimal_str")]
+        amount: Decimal,
+    },
+    /// Lift an account's suspension after bankruptcy. Accepted only once the
+    /// bankruptcy deficit has been repaid in full.
+    AccountReinstated {
+        account_id: AccountId,
+    },
+    /// Compliance kill switch: close every position of the account at its market's
+    /// mark, healthy or not, and freeze the account with `reason`. The collateral
+    /// stays where it is.
+    ForceClose {
+        account_id: AccountId,
+        reason: String,
+    },
     LiquidationFill {
         account_id: AccountId,
         market_id: MarketId,
-        #[serde(with = "str")]
+        #[serde(with = "decimal_str")]
         quantity: Decimal,
-        #[serde(with = "str")]
+        #[serde(with = "decimal_str")]
+        price: Decimal,
+    },
+    /// Engine-generated for a `ForceClose`: one whole position closed at mark, tagged
+    /// with the force close's reason.
+    ForceCloseFill {
+        account_id: AccountId,
+        market_id: MarketId,
+        #[serde(with = "decimal_str")]
+        quantity: Decimal,
+        #[serde(with = "decimal_str")]
+        price: Decimal,
+        reason: String,
+    },
+    /// Engine-generated under `ReservationBreach::AutoCancel`, ahead of any
+    /// liquidation: the resting orders cancelled, in cancellation order, to bring an
+    /// account whose positions still cover IM back above IM plus reservations.
+    OrdersAutoCancelled {
+        account_id: AccountId,
+        order_ids: Vec<OrderId>,
+        reason: String,
+    },
+    /// Engine-generated: a liquidatable account left with positions only in
+    /// `market_ids`, whose sessions are closed, is queued until one of them opens.
+    LiquidationDeferred {
+        account_id: AccountId,
+        market_ids: Vec<MarketId>,
+    },
+    /// Engine-generated after a liquidation leaves a bankruptcy deficit: the account's
+    /// own pool's insurance fund pays `amount` of it.
+    InsuranceFundPayout {
+        pool_id: PoolId,
+        account_id: AccountId,
+        #[serde(with = "decimal_str")]
+        amount: Decimal,
+    },
+    /// Engine-generated after the pool's insurance fund is exhausted, under
+    /// `ResidualDeficit::Socialize` or `SocializeEquity`: `amount` of the account's
+    /// remaining deficit is charged to the pool's other accounts, each `charges` entry
+    /// from its collateral.
+    LossSocialized {
+        pool_id: PoolId,
+        account_id: AccountId,
+        #[serde(with = "decimal_str")]
+        amount: Decimal,
+        #[serde(with = "decimal_str::map")]
+        charges: BTreeMap<AccountId, Decimal>,
+    },
+    /// Engine-generated after an event and its liquidations leave the account's margin
+    /// usage at a higher `RiskAlertLadder` level. `margin_usage` is `None` when it is
+    /// unbounded (maintenance margin against no equity).
+    RiskAlert {
+        account_id: AccountId,
+        level: usize,
+        #[serde(with = "decimal_str::option")]
+        margin_usage: Option<Decimal>,
+    },
+    /// Engine-generated when usage has fallen far enough below the account's alert
+    /// level to lower it. `level` is the new one, zero once every alert is cleared.
+    RiskAlertCleared {
+        account_id: AccountId,
+        level: usize,
+        #[serde(with = "decimal_str::option")]
+        margin_usage: Option<Decimal>,
+    },
+    /// Engine-generated under `EngineConfig::margin_call` when an event leaves an
+    /// account without an open call at or under maintenance margin. Rather than being
+    /// liquidated, it has the policy's `GracePeriod` to recover. `equity` and
+    /// `maintenance_margin` are its figures at the call.
+    MarginCall {
+        account_id: AccountId,
+        #[serde(with = "decimal_str")]
+        equity: Decimal,
+        #[serde(with = "decimal_str")]
+        maintenance_margin: Decimal,
+    },
+    /// Engine-generated when an account with an open margin call is above maintenance
+    /// margin again, or flat: after a deposit, a reducing trade, a mark or its
+    /// liquidation.
+    MarginCallCleared {
+        account_id: AccountId,
+        #[serde(with = "decimal_str")]
+        equity: Decimal,
+        #[serde(with = "decimal_str")]
+        maintenance_margin: Decimal,
+    },
+    /// Engine-generated after an event and its liquidations leave the market's net
+    /// open interest notional (`State::net_notional`, positive when long-heavy) beyond
+    /// its `skew_limit_notional` either way.
+    SkewLimitBreached {
+        market_id: MarketId,
+        #[serde(with = "decimal_str")]
+        net_notional: Decimal,
+    },
+    /// Engine-generated when a breached market's net notional is back within its limit.
+    SkewLimitCleared {
+        market_id: MarketId,
+        #[serde(with = "decimal_str")]
+        net_notional: Decimal,
+    },
+    /// A keeper absorbs `quantity` (the close fill from the liquidated account's
+    /// perspective) of a liquidatable account's position at the discounted `price`.
+    LiquidationTakeover {
+        liquidated_account: AccountId,
+        keeper_account: AccountId,
+        market_id: MarketId,
+        #[serde(with = "decimal_str")]
+        quantity: Decimal,
+        #[serde(with = "decimal_str")]
         price: Decimal,
     },
     TradeRejected {
         account_id: AccountId,
         market_id: MarketId,
-        #[serde(with = "str")]
+        #[serde(with = "decimal_str")]
         quantity: Decimal,
-        #[serde(with = "str")]
+        #[serde(with = "decimal_str")]
         price: Decimal,
         reason: String,
     },
     WithdrawalRejected {
         account_id: AccountId,
-        #[serde(with = "str")]
+        #[serde(with = "decimal_str")]
         amount: Decimal,
         reason: String,
     },
+    MarkPriceRejected {
+        market_id: MarketId,
+        #[serde(with = "decimal_str")]
+        price: Decimal,
+        reason: String,
+    },
+    MarkPriceBatchRejected {
+        #[serde(with = "decimal_str::map")]
+        updates: BTreeMap<MarketId, Decimal>,
+        reason: String,
+    },
+    LiquidationTakeoverRejected {
+        liquidated_account: AccountId,
+        keeper_account: AccountId,
+        market_id: MarketId,
+        #[serde(with = "decimal_str")]
+        quantity: Decimal,
+        #[serde(with = "decimal_str")]
+        price: Decimal,
+        reason: String,
+    },
+    FundingRateRejected {
+        market_id: MarketId,
+        #[serde(with = "decimal_str")]
+        rate: Decimal,
+        interval_id: u64,
+        reason: String,
+    },
+    FundingUpdateRejected {
+        market_id: MarketId,
+        #[serde(with = "decimal_str")]
+        new_cumulative_index: Decimal,
+        reason: String,
+    },
+    /// A submission whose idempotency key was already applied at `original_sequence`.
+    /// Informational: nothing was applied.
+    DuplicateIgnored {
+        key: String,
+        original_sequence: u64,
+    },
+    /// Engine-generated summary of `count` submissions from `account_id` that were
+    /// rejected exactly as its latest logged rejection, and left out of the log under
+    /// `EngineConfig::rejection_throttle`. `first_sequence` and `last_sequence` are the
+    /// last events logged before the first and the last of them arrived. Like
+    /// `DuplicateIgnored`, it has no cause.
+    RejectionSuppressed {
+        account_id: AccountId,
+        count: u64,
+        first_sequence: u64,
+        last_sequence: u64,
+    },
+    /// Engine-generated opening of an `Engine::process_batch` of `submissions`
+    /// submissions. Until its `BatchEnded`, accepted events are not followed by a
+    /// liquidation scan. No cause.
+    BatchStarted {
+        submissions: u64,
+    },
+    /// Engine-generated close of the batch its `BatchStarted` opened. The scan the
+    /// batch's events called for follows it, its liquidations, margin calls and
+    /// alerts caused by it. No cause.
+    BatchEnded {
+        submissions: u64,
+    },
+    AccountMetadataRejected {
+        account_id: AccountId,
+        key: String,
+        value: String,
+        reason: String,
+    },
+    AccountReinstatementRejected {
+        account_id: AccountId,
+        reason: String,
+    },
+    AssignPoolRejected {
+        account_id: AccountId,
+        pool_id: PoolId,
+        reason: String,
+    },
+    StateImportRejected {
+        account_id: AccountId,
+        pool_id: PoolId,
+        #[serde(with = "decimal_str")]
+        collateral: Decimal,
+        positions: Vec<ImportedPosition>,
+        reason: String,
+    },
+    HedgePairRejected {
+        market_a: MarketId,
+        market_b: MarketId,
+        #[serde(with = "decimal_str")]
+        offset_fraction: Decimal,
+        reason: String,
+    },
+    ExpiryRejected {
+        market_id: MarketId,
+        #[serde(with = "decimal_str")]
+        settlement_price: Decimal,
+        reason: String,
+    },
+    InterestTickRejected {
+        interval_id: u64,
+        reason: String,
+    },
+    YieldDistributionRejected {
+        #[serde(with = "decimal_str")]
+        rate: Decimal,
+        interval_id: u64,
+        reason: String,
+    },
+    GroupCreatedRejected {
+        group_id: GroupId,
+        #[serde(default, with = "decimal_str::option")]
+        max_group_notional: Option<Decimal>,
+        #[serde(default, with = "decimal_str::option")]
+        fee_override: Option<Decimal>,
+        reason: String,
+    },
+    GroupMembershipRejected {
+        account_id: AccountId,
+        group_id: Option<GroupId>,
+        reason: String,
+    },
+    PositionLeverageRejected {
+        account_id: AccountId,
+        market_id: MarketId,
+        #[serde(with = "decimal_str")]
+        leverage: Decimal,
+        reason: String,
+    },
+    /// The rejection of an event without a rejection variant of its own: a value out
+    /// of range in a deposit, account limits or an insurance deposit, a market that
+    /// cannot be added or removed, or an engine-generated event submitted from outside.
+    /// Carries the event as submitted.
+    EventRejected {
+        event: Box<EventType>,
+        reason: String,
+    },
+}
+
+impl EventType {
+    /// The variant name (`"TradeFill"`, `"LiquidationTakeover"`, ...), the `type` tag
+    /// the event is serialized under.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            EventType::ConfigMarker { .. } => "ConfigMarker",
+            EventType::ConfigUpdated { .. } => "ConfigUpdated",
+            EventType::Deposit { .. } => "Deposit",
+            EventType::Withdraw { .. } => "Withdraw",
+            EventType::TradeFill { .. } => "TradeFill",
+            EventType::FeeCharged { .. } => "FeeCharged",
+            EventType::MarkPriceUpdate { .. } => "MarkPriceUpdate",
+            EventType::MarkPriceBatch { .. } => "MarkPriceBatch",
+            EventType::FundingUpdate { .. } => "FundingUpdate",
+            EventType::FundingRate { .. } => "FundingRate",
+            EventType::FundingAccrual { .. } => "FundingAccrual",
+            EventType::FundingPayment { .. } => "FundingPayment",
+            EventType::MarkPriceBatchSkipped { .. } => "MarkPriceBatchSkipped",
+            EventType::UnknownMarketIgnored { .. } => "UnknownMarketIgnored",
+            EventType::SetAccountLimits { .. } => "SetAccountLimits",
+            EventType::OrderPlaced { .. } => "OrderPlaced",
+            EventType::OrderCancelled { .. } => "OrderCancelled",
+            EventType::AccountMetadata { .. } => "AccountMetadata",
+            EventType::AssignPool { .. } => "AssignPool",
+            EventType::GroupCreated { .. } => "GroupCreated",
+            EventType::GroupMembershipSet { .. } => "GroupMembershipSet",
+            EventType::SetPositionLeverage { .. } => "SetPositionLeverage",
+            EventType::BackstopRegistered { .. } => "BackstopRegistered",
+            EventType::AccountsMerged { .. } => "AccountsMerged",
+            EventType::PositionTransfer { .. } => "PositionTransfer",
+            EventType::InsuranceFundDeposit { .. } => "InsuranceFundDeposit",
+            EventType::StateImport { .. } => "StateImport",
+            EventType::StateImportBelowMaintenance { .. } => "StateImportBelowMaintenance",
+            EventType::MarketAdded { .. } => "MarketAdded",
+            EventType::MarketRemoved { .. } => "MarketRemoved",
+            EventType::SessionOpen { .. } => "SessionOpen",
+            EventType::SessionClose { .. } => "SessionClose",
+            EventType::HedgePairAdded { .. } => "HedgePairAdded",
+            EventType::Expiry { .. } => "Expiry",
+            EventType::ExpirySettlement { .. } => "ExpirySettlement",
+            EventType::InterestTick { .. } => "InterestTick",
+            EventType::InterestCharged { .. } => "InterestCharged",
+            EventType::YieldDistribution { .. } => "YieldDistribution",
+            EventType::YieldPaid { .. } => "YieldPaid",
+            EventType::YieldResidual { .. } => "YieldResidual",
+            EventType::AccountReinstated { .. } => "AccountReinstated",
+            EventType::ForceClose { .. } => "ForceClose",
+            EventType::LiquidationFill { .. } => "LiquidationFill",
+            EventType::ForceCloseFill { .. } => "ForceCloseFill",
+            EventType::OrdersAutoCancelled { .. } => "OrdersAutoCancelled",
+            EventType::LiquidationDeferred { .. } => "LiquidationDeferred",
+            EventType::InsuranceFundPayout { .. } => "InsuranceFundPayout",
+            EventType::LossSocialized { .. } => "LossSocialized",
+            EventType::RiskAlert { .. } => "RiskAlert",
+            EventType::RiskAlertCleared { .. } => "RiskAlertCleared",
+            EventType::MarginCall { .. } => "MarginCall",
+            EventType::MarginCallCleared { .. } => "MarginCallCleared",
+            EventType::SkewLimitBreached { .. } => "SkewLimitBreached",
+            EventType::SkewLimitCleared { .. } => "SkewLimitCleared",
+            EventType::LiquidationTakeover { .. } => "LiquidationTakeover",
+            EventType::TradeRejected { .. } => "TradeRejected",
+            EventType::WithdrawalRejected { .. } => "WithdrawalRejected",
+            EventType::MarkPriceRejected { .. } => "MarkPriceRejected",
+            EventType::MarkPriceBatchRejected { .. } => "MarkPriceBatchRejected",
+            EventType::LiquidationTakeoverRejected { .. } => "LiquidationTakeoverRejected",
+            EventType::FundingRateRejected { .. } => "FundingRateRejected",
+            EventType::FundingUpdateRejected { .. } => "FundingUpdateRejected",
+            EventType::DuplicateIgnored { .. } => "DuplicateIgnored",
+            EventType::RejectionSuppressed { .. } => "RejectionSuppressed",
+            EventType::BatchStarted { .. } => "BatchStarted",
+            EventType::BatchEnded { .. } => "BatchEnded",
+            EventType::AccountMetadataRejected { .. } => "AccountMetadataRejected",
+            EventType::AccountReinstatementRejected { .. } => "AccountReinstatementRejected",
+            EventType::AssignPoolRejected { .. } => "AssignPoolRejected",
+            EventType::StateImportRejected { .. } => "StateImportRejected",
+            EventType::HedgePairRejected { .. } => "HedgePairRejected",
+            EventType::ExpiryRejected { .. } => "ExpiryRejected",
+            EventType::InterestTickRejected { .. } => "InterestTickRejected",
+            EventType::YieldDistributionRejected { .. } => "YieldDistributionRejected",
+            EventType::GroupCreatedRejected { .. } => "GroupCreatedRejected",
+            EventType::GroupMembershipRejected { .. } => "GroupMembershipRejected",
+            EventType::PositionLeverageRejected { .. } => "PositionLeverageRejected",
+            EventType::EventRejected { .. } => "EventRejected",
+        }
+    }
+
+    /// Whether the event names `account_id` in one of its account fields. Events that
+    /// affect accounts only through their positions (marks, funding) do not count.
+    pub fn involves_account(&self, account_id: &str) -> bool {
+        self.accounts().iter().any(|id| *id == account_id)
+    }
+
+    /// The accounts the event names in its account fields (see `involves_account`).
+    pub fn accounts(&self) -> Vec<&AccountId> {
+        match self {
+            EventType::Deposit { account_id: id, .. }
+            | EventType::Withdraw { account_id: id, .. }
+            | EventType::TradeFill { account_id: id, .. }
+            | EventType::FeeCharged { account_id: id, .. }
+            | EventType::FundingPayment { account_id: id, .. }
+            | EventType::ExpirySettlement { account_id: id, .. }
+            | EventType::InterestCharged { account_id: id, .. }
+            | EventType::YieldPaid { account_id: id, .. }
+            | EventType::SetAccountLimits { account_id: id, .. }
+            | EventType::OrderPlaced { account_id: id, .. }
+            | EventType::OrderCancelled { account_id: id, .. }
+            | EventType::OrdersAutoCancelled { account_id: id, .. }
+            | EventType::AccountMetadata { account_id: id, .. }
+            | EventType::AccountReinstated { account_id: id }
+            | EventType::ForceClose { account_id: id, .. }
+            | EventType::LiquidationFill { account_id: id, .. }
+            | EventType::ForceCloseFill { account_id: id, .. }
+            | EventType::LiquidationDeferred { account_id: id, .. }
+            | EventType::AssignPool { account_id: id, .. }
+            | EventType::AssignPoolRejected { account_id: id, .. }
+            | EventType::StateImport { account_id: id, .. }
+            | EventType::StateImportBelowMaintenance { account_id: id, .. }
+            | EventType::StateImportRejected { account_id: id, .. }
+            | EventType::InsuranceFundPayout { account_id: id, .. }
+            | EventType::RiskAlert { account_id: id, .. }
+            | EventType::RiskAlertCleared { account_id: id, .. }
+            | EventType::MarginCall { account_id: id, .. }
+            | EventType::MarginCallCleared { account_id: id, .. }
+            | EventType::TradeRejected { account_id: id, .. }
+            | EventType::WithdrawalRejected { account_id: id, .. }
+            | EventType::AccountMetadataRejected { account_id: id, .. }
+            | EventType::AccountReinstatementRejected { account_id: id, .. }
+            | EventType::GroupMembershipSet { account_id: id, .. }
+            | EventType::GroupMembershipRejected { account_id: id, .. }
+            | EventType::SetPositionLeverage { account_id: id, .. }
+            | EventType::PositionLeverageRejected { account_id: id, .. }
+            | EventType::BackstopRegistered { account_id: id, .. }
+            | EventType::RejectionSuppressed { account_id: id, .. } => vec![id],
+            EventType::LiquidationTakeover {
+                liquidated_account,
+                keeper_account,
+                ..
+            }
+            | EventType::LiquidationTakeoverRejected {
+                liquidated_account,
+                keeper_account,
+                ..
+            } => vec![liquidated_account, keeper_account],
+            EventType::AccountsMerged { from, to }
+            | EventType::PositionTransfer { from, to, .. } => {
+                vec![from, to]
+            }
+            EventType::LossSocialized {
+                account_id,
+                charges,
+                ..
+            } => std::iter::once(account_id).chain(charges.keys()).collect(),
+            EventType::EventRejected { event, .. } => event.accounts(),
+            EventType::ConfigMarker { .. }
+            | EventType::MarkPriceUpdate { .. }
+            | EventType::MarkPriceBatch { .. }
+            | EventType::FundingUpdate { .. }
+            | EventType::FundingRate { .. }
+            | EventType::FundingAccrual { .. }
+            | EventType::MarketAdded { .. }
+            | EventType::MarketRemoved { .. }
+            | EventType::ConfigUpdated { .. }
+            | EventType::SessionOpen { .. }
+            | EventType::SessionClose { .. }
+            | EventType::InsuranceFundDeposit { .. }
+            | EventType::HedgePairAdded { .. }
+            | EventType::HedgePairRejected { .. }
+            | EventType::GroupCreated { .. }
+            | EventType::GroupCreatedRejected { .. }
+            | EventType::Expiry { .. }
+            | EventType::ExpiryRejected { .. }
+            | EventType::InterestTick { .. }
+            | EventType::InterestTickRejected { .. }
+            | EventType::YieldDistribution { .. }
+            | EventType::YieldDistributionRejected { .. }
+            | EventType::YieldResidual { .. }
+            | EventType::MarkPriceBatchSkipped { .. }
+            | EventType::UnknownMarketIgnored { .. }
+            | EventType::MarkPriceRejected { .. }
+            | EventType::MarkPriceBatchRejected { .. }
+            | EventType::FundingRateRejected { .. }
+            | EventType::FundingUpdateRejected { .. }
+            | EventType::SkewLimitBreached { .. }
+            | EventType::SkewLimitCleared { .. }
+            | EventType::DuplicateIgnored { .. }
+            | EventType::BatchStarted { .. }
+            | EventType::BatchEnded { .. } => Vec::new(),
+        }
+    }
+
+    /// Whether this is the informational record of a rejected attempt. It is always
+    /// logged immediately after the attempt it rejects.
+    pub fn is_rejection(&self) -> bool {
+        matches!(
+            self,
+            EventType::TradeRejected { .. }
+                | EventType::WithdrawalRejected { .. }
+                | EventType::MarkPriceRejected { .. }
+                | EventType::MarkPriceBatchRejected { .. }
+                | EventType::LiquidationTakeoverRejected { .. }
+                | EventType::FundingRateRejected { .. }
+                | EventType::FundingUpdateRejected { .. }
+                | EventType::AccountMetadataRejected { .. }
+                | EventType::AccountReinstatementRejected { .. }
+                | EventType::AssignPoolRejected { .. }
+                | EventType::StateImportRejected { .. }
+                | EventType::HedgePairRejected { .. }
+                | EventType::ExpiryRejected { .. }
+                | EventType::InterestTickRejected { .. }
+                | EventType::YieldDistributionRejected { .. }
+                | EventType::GroupCreatedRejected { .. }
+                | EventType::GroupMembershipRejected { .. }
+                | EventType::PositionLeverageRejected { .. }
+                | EventType::EventRejected { .. }
+        )
+    }
+
+    /// Whether the event only records something: a rejection, an ignored duplicate,
+    /// or a record derived while its trigger was applied (funding payments, expiry
+    /// settlements, interest, yield payments and residuals, skipped batch markets, an
+    /// import below maintenance).
+    /// Applying one changes nothing, so `apply_event` returns before looking at it;
+    /// `replay_verified` checks instead that replay derives the same records. Every
+    /// variant is listed, so a new one must be classified here.
+    pub fn is_informational(&self) -> bool {
+        match self {
+            EventType::TradeRejected { .. }
+            | EventType::WithdrawalRejected { .. }
+            | EventType::MarkPriceRejected { .. }
+            | EventType::MarkPriceBatchRejected { .. }
+            | EventType::LiquidationTakeoverRejected { .. }
+            | EventType::FundingRateRejected { .. }
+            | EventType::FundingUpdateRejected { .. }
+            | EventType::AccountMetadataRejected { .. }
+            | EventType::AccountReinstatementRejected { .. }
+            | EventType::AssignPoolRejected { .. }
+            | EventType::StateImportRejected { .. }
+            | EventType::HedgePairRejected { .. }
+            | EventType::ExpiryRejected { .. }
+            | EventType::InterestTickRejected { .. }
+            | EventType::YieldDistributionRejected { .. }
+            | EventType::GroupCreatedRejected { .. }
+            | EventType::GroupMembershipRejected { .. }
+            | EventType::PositionLeverageRejected { .. }
+            | EventType::EventRejected { .. }
+            | EventType::DuplicateIgnored { .. }
+            | EventType::FundingPayment { .. }
+            | EventType::FeeCharged { .. }
+            | EventType::ExpirySettlement { .. }
+            | EventType::InterestCharged { .. }
+            | EventType::YieldPaid { .. }
+            | EventType::YieldResidual { .. }
+            | EventType::MarkPriceBatchSkipped { .. }
+            | EventType::StateImportBelowMaintenance { .. } => true,
+            // The config marker is checked against the replay config, an
+            // unknown-market marker against the registered markets, a rejection
+            // summary against the account's rejections, and batch markers against
+            // each other.
+            EventType::ConfigMarker { .. }
+            | EventType::UnknownMarketIgnored { .. }
+            | EventType::RejectionSuppressed { .. }
+            | EventType::BatchStarted { .. }
+            | EventType::BatchEnded { .. }
+            | EventType::Deposit { .. }
+            | EventType::Withdraw { .. }
+            | EventType::TradeFill { .. }
+            | EventType::MarkPriceUpdate { .. }
+            | EventType::MarkPriceBatch { .. }
+            | EventType::FundingUpdate { .. }
+            | EventType::FundingRate { .. }
+            | EventType::FundingAccrual { .. }
+            | EventType::SetAccountLimits { .. }
+            | EventType::OrderPlaced { .. }
+            | EventType::OrderCancelled { .. }
+            | EventType::AccountMetadata { .. }
+            | EventType::AssignPool { .. }
+            | EventType::InsuranceFundDeposit { .. }
+            | EventType::StateImport { .. }
+            | EventType::MarketAdded { .. }
+            | EventType::MarketRemoved { .. }
+            | EventType::ConfigUpdated { .. }
+            | EventType::SessionOpen { .. }
+            | EventType::SessionClose { .. }
+            | EventType::HedgePairAdded { .. }
+            | EventType::GroupCreated { .. }
+            | EventType::GroupMembershipSet { .. }
+            | EventType::SetPositionLeverage { .. }
+            | EventType::BackstopRegistered { .. }
+            | EventType::AccountsMerged { .. }
+            | EventType::PositionTransfer { .. }
+            | EventType::Expiry { .. }
+            | EventType::InterestTick { .. }
+            | EventType::YieldDistribution { .. }
+            | EventType::AccountReinstated { .. }
+            | EventType::ForceClose { .. }
+            | EventType::LiquidationFill { .. }
+            | EventType::ForceCloseFill { .. }
+            | EventType::OrdersAutoCancelled { .. }
+            | EventType::LiquidationDeferred { .. }
+            | EventType::InsuranceFundPayout { .. }
+            | EventType::LossSocialized { .. }
+            | EventType::RiskAlert { .. }
+            | EventType::RiskAlertCleared { .. }
+            | EventType::MarginCall { .. }
+            | EventType::MarginCallCleared { .. }
+            | EventType::SkewLimitBreached { .. }
+            | EventType::SkewLimitCleared { .. }
+            | EventType::LiquidationTakeover { .. } => false,
+        }
+    }
+
+    /// Whether only the engine writes this event: the config marker, suppression
+    /// summaries, batch markers, derived records, liquidation and force-close fills,
+    /// auto-cancelled orders, payouts and socialized losses, risk alerts, margin calls,
+    /// skew limit records, and rejection records.
+    /// Submitting one to `Engine::process` is a caller bug.
+    pub fn is_engine_generated(&self) -> bool {
+        self.is_rejection()
+            || matches!(
+                self,
+                EventType::ConfigMarker { .. }
+                    | EventType::FundingPayment { .. }
+                    | EventType::FeeCharged { .. }
+                    | EventType::ExpirySettlement { .. }
+                    | EventType::InterestCharged { .. }
+                    | EventType::YieldPaid { .. }
+                    | EventType::YieldResidual { .. }
+                    | EventType::MarkPriceBatchSkipped { .. }
+                    | EventType::UnknownMarketIgnored { .. }
+                    | EventType::StateImportBelowMaintenance { .. }
+                    | EventType::LiquidationFill { .. }
+                    | EventType::ForceCloseFill { .. }
+                    | EventType::OrdersAutoCancelled { .. }
+                    | EventType::LiquidationDeferred { .. }
+                    | EventType::InsuranceFundPayout { .. }
+                    | EventType::LossSocialized { .. }
+                    | EventType::RiskAlert { .. }
+                    | EventType::RiskAlertCleared { .. }
+                    | EventType::MarginCall { .. }
+                    | EventType::MarginCallCleared { .. }
+                    | EventType::SkewLimitBreached { .. }
+                    | EventType::SkewLimitCleared { .. }
+                    | EventType::DuplicateIgnored { .. }
+                    | EventType::RejectionSuppressed { .. }
+                    | EventType::BatchStarted { .. }
+                    | EventType::BatchEnded { .. }
+            )
+    }
+
+    /// Whether this is one of the engine's records that have no cause: the config
+    /// marker, a duplicate marker, a rejection summary or a batch marker. Submitted from outside, one
+    /// is refused without being applied.
+    pub fn is_uncaused_marker(&self) -> bool {
+        matches!(
+            self,
+            EventType::ConfigMarker { .. }
+                | EventType::DuplicateIgnored { .. }
+                | EventType::RejectionSuppressed { .. }
+                | EventType::BatchStarted { .. }
+                | EventType::BatchEnded { .. }
+        )
+    }
+}
+
+/// The `*Rejected` record `Engine::process` logs when `event_type` is rejected for
+/// `reason`: its own rejection variant, or `EventRejected` carrying it when it has
+/// none. Every variant is listed, so a new event type must choose one or the other
+/// before the crate builds. There is no unrejectable event: a submitted rejection
+/// record or engine-generated event is refused, and recorded, as `EventRejected`.
+pub fn rejection_for(event_type: &EventType, reason: String) -> EventType {
+    match event_type {
+        EventType::TradeFill {
+            account_id,
+            market_id,
+            quantity,
+            price,
+            ..
+        } => EventType::TradeRejected {
+            account_id: account_id.clone(),
+            market_id: market_id.clone(),
+            quantity: *quantity,
+            price: *price,
+            reason,
+        },
+        EventType::Withdraw { account_id, amount } => EventType::WithdrawalRejected {
+            account_id: account_id.clone(),
+            amount: *amount,
+            reason,
+        },
+        EventType::MarkPriceUpdate { market_id, price } => EventType::MarkPriceRejected {
+            market_id: market_id.clone(),
+            price: *price,
+            reason,
+        },
+        EventType::MarkPriceBatch { updates } => EventType::MarkPriceBatchRejected {
+            updates: updates.clone(),
+            reason,
+        },
+        EventType::LiquidationTakeover {
+            liquidated_account,
+            keeper_account,
+            market_id,
+            quantity,
+            price,
+        } => EventType::LiquidationTakeoverRejected {
+            liquidated_account: liquidated_account.clone(),
+            keeper_account: keeper_account.clone(),
+            market_id: market_id.clone(),
+            quantity: *quantity,
+            price: *price,
+            reason,
+        },
+        EventType::FundingRate {
+            market_id,
+            rate,
+            interval_id,
+        } => EventType::FundingRateRejected {
+            market_id: market_id.clone(),
+            rate: *rate,
+            interval_id: *interval_id,
+            reason,
+        },
+        EventType::FundingUpdate {
+            market_id,
+            new_cumulative_index,
+        } => EventType::FundingUpdateRejected {
+            market_id: market_id.clone(),
+            new_cumulative_index: *new_cumulative_index,
+            reason,
+        },
+        EventType::AccountMetadata {
+            account_id,
+            key,
+            value,
+        } => EventType::AccountMetadataRejected {
+            account_id: account_id.clone(),
+            key: key.clone(),
+            value: value.clone(),
+            reason,
+        },
+        EventType::AccountReinstated { account_id } => EventType::AccountReinstatementRejected {
+            account_id: account_id.clone(),
+            reason,
+        },
+        EventType::AssignPool {
+            account_id,
+            pool_id,
+        } => EventType::AssignPoolRejected {
+            account_id: account_id.clone(),
+            pool_id: pool_id.clone(),
+            reason,
+        },
+        EventType::GroupCreated {
+            group_id,
+            max_group_notional,
+            fee_override,
+        } => EventType::GroupCreatedRejected {
+            group_id: group_id.clone(),
+            max_group_notional: *max_group_notional,
+            fee_override: *fee_override,
+            reason,
+        },
+        EventType::GroupMembershipSet {
+            account_id,
+            group_id,
+        } => EventType::GroupMembershipRejected {
+            account_id: account_id.clone(),
+            group_id: group_id.clone(),
+            reason,
+        },
+        EventType::SetPositionLeverage {
+            account_id,
+            market_id,
+            leverage,
+        } => EventType::PositionLeverageRejected {
+            account_id: account_id.clone(),
+            market_id: market_id.clone(),
+            leverage: *leverage,
+            reason,
+        },
+        EventType::StateImport {
+            account_id,
+            pool_id,
+            collateral,
+            positions,
+        } => EventType::StateImportRejected {
+            account_id: account_id.clone(),
+            pool_id: pool_id.clone(),
+            collateral: *collateral,
+            positions: positions.clone(),
+            reason,
+        },
+        EventType::HedgePairAdded {
+            market_a,
+            market_b,
+            offset_fraction,
+        } => EventType::HedgePairRejected {
+            market_a: market_a.clone(),
+            market_b: market_b.clone(),
+            offset_fraction: *offset_fraction,
+            reason,
+        },
+        EventType::Expiry {
+            market_id,
+            settlement_price,
+        } => EventType::ExpiryRejected {
+            market_id: market_id.clone(),
+            settlement_price: *settlement_price,
+            reason,
+        },
+        EventType::InterestTick { interval_id } => EventType::InterestTickRejected {
+            interval_id: *interval_id,
+            reason,
+        },
+        EventType::YieldDistribution { rate, interval_id } => {
+            EventType::YieldDistributionRejected {
+                rate: *rate,
+                interval_id: *interval_id,
+                reason,
+            }
+        }
+        EventType::Deposit { .. }
+        | EventType::SetAccountLimits { .. }
+        | EventType::OrderPlaced { .. }
+        | EventType::OrderCancelled { .. }
+        | EventType::OrdersAutoCancelled { .. }
+        | EventType::InsuranceFundDeposit { .. }
+        | EventType::MarketAdded { .. }
+        | EventType::MarketRemoved { .. }
+        | EventType::ConfigUpdated { .. }
+        | EventType::SessionOpen { .. }
+        | EventType::SessionClose { .. }
+        | EventType::BackstopRegistered { .. }
+        | EventType::AccountsMerged { .. }
+        | EventType::PositionTransfer { .. }
+        | EventType::FundingAccrual { .. }
+        | EventType::ConfigMarker { .. }
+        | EventType::FundingPayment { .. }
+        | EventType::FeeCharged { .. }
+        | EventType::ExpirySettlement { .. }
+        | EventType::InterestCharged { .. }
+        | EventType::YieldPaid { .. }
+        | EventType::YieldResidual { .. }
+        | EventType::MarkPriceBatchSkipped { .. }
+        | EventType::UnknownMarketIgnored { .. }
+        | EventType::StateImportBelowMaintenance { .. }
+        | EventType::ForceClose { .. }
+        | EventType::LiquidationFill { .. }
+        | EventType::ForceCloseFill { .. }
+        | EventType::LiquidationDeferred { .. }
+        | EventType::InsuranceFundPayout { .. }
+        | EventType::LossSocialized { .. }
+        | EventType::RiskAlert { .. }
+        | EventType::RiskAlertCleared { .. }
+        | EventType::MarginCall { .. }
+        | EventType::MarginCallCleared { .. }
+        | EventType::SkewLimitBreached { .. }
+        | EventType::SkewLimitCleared { .. }
+        | EventType::DuplicateIgnored { .. }
+        | EventType::RejectionSuppressed { .. }
+        | EventType::BatchStarted { .. }
+        | EventType::BatchEnded { .. }
+        | EventType::TradeRejected { .. }
+        | EventType::WithdrawalRejected { .. }
+        | EventType::MarkPriceRejected { .. }
+        | EventType::MarkPriceBatchRejected { .. }
+        | EventType::LiquidationTakeoverRejected { .. }
+        | EventType::FundingRateRejected { .. }
+        | EventType::FundingUpdateRejected { .. }
+        | EventType::AccountMetadataRejected { .. }
+        | EventType::AccountReinstatementRejected { .. }
+        | EventType::AssignPoolRejected { .. }
+        | EventType::StateImportRejected { .. }
+        | EventType::HedgePairRejected { .. }
+        | EventType::ExpiryRejected { .. }
+        | EventType::InterestTickRejected { .. }
+        | EventType::YieldDistributionRejected { .. }
+        | EventType::GroupCreatedRejected { .. }
+        | EventType::GroupMembershipRejected { .. }
+        | EventType::PositionLeverageRejected { .. }
+        | EventType::EventRejected { .. } => EventType::EventRejected {
+            event: Box::new(event_type.clone()),
+            reason,
+        },
+    }
+}
+
+/// How `merge` orders the logs it interleaves.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum MergeKey {
+    /// By submission timestamp. Every external event must carry one.
+    Timestamp,
+    /// By each log's own sequence numbers, for logs without timestamps.
+    Sequence,
+}
+
+/// Interleave the logs of engines that each own a disjoint set of accounts into one
+/// log a single engine can replay.
+///
+/// An external event moves together with the records it generated. Events are taken
+/// in `key` order, ties going to the lower shard, and each log keeps its own order even
+/// where its keys go backwards. A market-level event (mark, funding, session, hedge
+/// pair, insurance deposit) or batch marker logged identically by several shards — same event,
+/// timestamp and idempotency key, matched occurrence by occurrence — is kept once. The
+/// records the shards derived from it follow: every shard's account-level records
+/// (funding payments, liquidations) and yield residuals in shard order, and its market-level records
+/// (rejections, skipped markets) once. Sequences are renumbered from 1, as an engine numbers its log, with
+/// `caused_by` and the sequences events name following. Every event but the
+/// `ConfigMarker` records its `origin_shard`; a shared market event records the lowest
+/// shard that logged it.
+///
+/// Fails if an account is named in more than one log, the logs' configs differ, two
+/// logs order shared market events differently or derive different market-level
+/// records from one, or, under `MergeKey::Timestamp`, an external event has no
+/// timestamp. Insurance funds are not sharded: a deposit every shard logged is applied
+/// once, but each shard's payouts drew on its own copy of the fund.
+/// The events of `log`, borrowed, whether it holds them or shares them as
+/// `Engine::event_log` does.
+pub(crate) fn borrowed(log: &[impl AsRef<Event>]) -> Vec<&Event> {
+    log.iter().map(AsRef::as_ref).collect()
+}
+
+pub fn merge(logs: &[&[impl AsRef<Event>]], key: MergeKey) -> Result<Vec<Event>, MergeError> {
+    let logs: Vec<Vec<&Event>> = logs.iter().map(|log| borrowed(log)).collect();
+    let mut marker = None;
+    let mut base_config = None;
+    let mut shards = Vec::with_capacity(logs.len());
+    for (shard, log) in logs.iter().enumerate() {
+        let (shard_marker, units) = units(shard, log)?;
+        let config = match shard_marker.map(|e| &e.event_type) {
+            Some(EventType::ConfigMarker { config, .. }) => config.clone(),
+            _ => EngineConfig::default(),
+        };
+        let base_config = base_config.get_or_insert(config.clone());
+        let fields = config.diff(base_config);
+        if !fields.is_empty() {
+            return Err(MergeError::ConfigMismatch { shard, fields });
+        }
+        marker = marker.or(shard_marker);
+        if key == MergeKey::Timestamp {
+            let mut heads = units.iter().map(|unit| &unit[0]);
+            if let Some(head) =
+                heads.find(|e| e.timestamp.is_none() && !e.event_type.is_engine_generated())
+            {
+                return Err(MergeError::MissingTimestamp {
+                    shard,
+                    sequence: head.sequence,
+                });
+            }
+        }
+        shards.push(ShardLog::new(units, key));
+    }
+
+    let mut named: BTreeMap<&AccountId, BTreeSet<usize>> = BTreeMap::new();
+    for (shard, log) in logs.iter().enumerate() {
+        for account_id in log.iter().flat_map(|e| e.event_type.accounts()) {
+            named.entry(account_id).or_default().insert(shard);
+        }
+    }
+    let shared_accounts: Vec<(AccountId, Vec<usize>)> = named
+        .into_iter()
+        .filter(|(_, shards)| shards.len() > 1)
+        .map(|(account_id, shards)| (account_id.clone(), shards.into_iter().collect()))
+        .collect();
+    if !shared_accounts.is_empty() {
+        return Err(MergeError::SharedAccounts(shared_accounts));
+    }
+
+    let mut merged = Vec::new();
+    if let Some(marker) = marker {
+        merged.push(Event {
+            sequence: 1,
+            origin_shard: None,
+            ..marker.clone()
+        });
+    }
+    while let Some(next) = (0..shards.len())
+        .filter_map(|s| shards[s].keys.get(shards[s].next).map(|&key| (key, s)))
+        .min()
+    {
+        let copies = next_copies(&shards, next.1)?;
+        let lead = copies[0];
+        let lead_head = shards[lead].head().expect("a copy is at its shard's head");
+        let trigger = merged.len() as u64 + 1;
+        let lead_sequences = &shards[lead].sequences;
+        merged.push(Event {
+            sequence: trigger,
+            event_type: renumber(&lead_head.event_type, lead_sequences),
+            caused_by: None,
+            origin_shard: Some(lead),
+            ..lead_head.clone()
+        });
+
+        let mut lead_records = None;
+        for &shard in &copies {
+            let log = &mut shards[shard];
+            let unit = log.units[log.next];
+            log.sequences.insert(unit[0].sequence, trigger);
+            let mut market_records = Vec::new();
+            for event in &unit[1..] {
+                let event_type = renumber(&event.event_type, &log.sequences);
+                // A pool's yield residual is its shard's own, like an account's record.
+                let pooled = matches!(event_type, EventType::YieldResidual { .. });
+                if event_type.accounts().is_empty() && !pooled {
+                    market_records.push(event_type.clone());
+                    if shard != lead {
+                        continue;
+                    }
+                }
+                merged.push(Event {
+                    sequence: merged.len() as u64 + 1,
+                    event_type,
+                    caused_by: event.caused_by.map(|_| trigger),
+                    origin_shard: Some(shard),
+                    ..(*event).clone()
+                });
+            }
+            match &lead_records {
+                None => lead_records = Some(market_records),
+                Some(records) if *records != market_records => {
+                    return Err(MergeError::DivergentOutcome {
+                        shard: lead,
+                        sequence: shards[lead].units[shards[lead].next][0].sequence,
+                        other_shard: shard,
+                    });
+                }
+                Some(_) => {}
+            }
+            log.advance();
+        }
+    }
+    Ok(merged)
+}
+
+/// Split a log into one log per shard, the reverse of `merge`.
+///
+/// An event naming an account goes to the shard `assignment` gives that account,
+/// together with the records it generated. The `ConfigMarker`, batch markers and
+/// market-level events go to every shard, since any account may hold or open a
+/// position in any market. So do the market-level records derived from them, while
+/// their account-level records (funding payments, liquidations) go to each
+/// account's shard. A `YieldResidual` goes to the shards of its pool's accounts,
+/// and replays only in a shard that holds the whole pool. There is one log per
+/// shard up to the highest index `assignment` returns for an account in `log`. Each
+/// is renumbered from 1, with `caused_by` and the sequences events name following,
+/// and replays on its own.
+///
+/// Fails with `SplitError::CrossShard` on the first event naming accounts in two
+/// shards, such as a takeover whose keeper is assigned elsewhere than the
+/// liquidated account.
+pub fn split_by_account(
+    log: &[impl AsRef<Event>],
+    assignment: impl Fn(&str) -> usize,
+) -> Result<Vec<Vec<Event>>, SplitError> {
+    let log = borrowed(log);
+    let shard_count = log
+        .iter()
+        .flat_map(|e| e.event_type.accounts())
+        .map(|account_id| assignment(account_id))
+        .max()
+        .map_or(1, |max| max + 1);
+    let every_shard: BTreeSet<usize> = (0..shard_count).collect();
+    let mut shards: Vec<Vec<Event>> = vec![Vec::new(); shard_count];
+    let mut sequences = vec![BTreeMap::new(); shard_count];
+
+    // The shards the current trigger went to, its sequence in each, and whether it
+    // is a market-level event.
+    let mut trigger_shards = every_shard.clone();
+    let mut trigger_sequences = vec![0; shard_count];
+    let mut trigger_shared = false;
+    // Each account's pool, taken from the event that first names it.
+    let mut pools: BTreeMap<AccountId, PoolId> = BTreeMap::new();
+    for event in log {
+        let named: BTreeSet<usize> = event
+            .event_type
+            .accounts()
+            .into_iter()
+            .map(|id| assignment(id))
+            .collect();
+        // A merge creates its destination in the source's pool.
+        let merged_pool = match &event.event_type {
+            EventType::AccountsMerged { from, .. } => pools.get(from).cloned(),
+            _ => None,
+        };
+        for account_id in event.event_type.accounts() {
+            pools
+                .entry(account_id.clone())
+                .or_insert_with(|| match &event.event_type {
+                    EventType::AssignPool { pool_id, .. }
+                    | EventType::StateImport { pool_id, .. } => pool_id.clone(),
+                    EventType::AccountsMerged { .. } => {
+                        merged_pool.clone().unwrap_or_else(default_pool)
+                    }
+                    _ => default_pool(),
+                });
+        }
+
+        let is_trigger = starts_unit(event);
+        let targets = if let EventType::ConfigMarker { .. } = event.event_type {
+            every_shard.clone()
+        } else if is_trigger {
+            trigger_shared = is_shared(&event.event_type);
+            trigger_shards = match &event.event_type {
+                EventType::DuplicateIgnored {
+                    original_sequence, ..
+                } => {
+                    let holders: BTreeSet<usize> = (0..shard_count)
+                        .filter(|&s| sequences[s].contains_key(original_sequence))
+                        .collect();
+                    if holders.is_empty() {
+                        every_shard.clone()
+                    } else {
+                        holders
+                    }
+                }
+                _ if trigger_shared => every_shard.clone(),
+                _ if named.len() != 1 => {
+                    return Err(SplitError::CrossShard {
+                        sequence: event.sequence,
+                        shards: named.into_iter().collect(),
+                    });
+                }
+                _ => named,
+            };
+            trigger_shards.clone()
+        } else if trigger_shared && !named.is_empty() {
+            named
+        } else if let EventType::YieldResidual { pool_id, .. } = &event.event_type {
+            pools
+                .iter()
+                .filter(|(_, pool)| *pool == pool_id)
+                .map(|(account_id, _)| assignment(account_id))
+                .filter(|shard| trigger_shards.contains(shard))
+                .collect()
+        } else {
+            trigger_shards.clone()
+        };
+
+        for shard in targets {
+            let sequence = shards[shard].len() as u64 + 1;
+            sequences[shard].insert(event.sequence, sequence);
+            if is_trigger {
+                trigger_sequences[shard] = sequence;
+            }
+            shards[shard].push(Event {
+                sequence,
+                event_type: renumber(&event.event_type, &sequences[shard]),
+                caused_by: event.caused_by.map(|_| trigger_sequences[shard]),
+                origin_shard: None,
+                ..event.clone()
+            });
+        }
+    }
+    Ok(shards)
+}
+
+/// One shard's log as `merge` consumes it.
+struct ShardLog<'a> {
+    /// Each external event with the records it generated, in log order.
+    units: Vec<&'a [&'a Event]>,
+    /// Each unit's `MergeKey`. A `DuplicateIgnored` or `RejectionSuppressed` has no
+    /// timestamp and takes the one before it.
+    keys: Vec<u64>,
+    next: usize,
+    /// Units headed by a market-level event, by `fingerprint`, not yet merged.
+    shared: BTreeMap<String, VecDeque<usize>>,
+    /// Merged sequence of each of the shard's triggers merged so far.
+    sequences: BTreeMap<u64, u64>,
+}
+
+impl<'a> ShardLog<'a> {
+    fn new(units: Vec<&'a [&'a Event]>, key: MergeKey) -> Self {
+        let mut last = 0;
+        let keys = units
+            .iter()
+            .map(|unit| match key {
+                MergeKey::Timestamp => {
+                    last = unit[0].timestamp.unwrap_or(last);
+                    last
+                }
+                MergeKey::Sequence => unit[0].sequence,
+            })
+            .collect();
+        let mut shared: BTreeMap<String, VecDeque<usize>> = BTreeMap::new();
+        for (i, unit) in units.iter().enumerate() {
+            if is_shared(&unit[0].event_type) {
+                shared.entry(fingerprint(unit[0])).or_default().push_back(i);
+            }
+        }
+        Self {
+            units,
+            keys,
+            next: 0,
+            shared,
+            sequences: BTreeMap::new(),
+        }
+    }
+
+    fn head(&self) -> Option<&'a Event> {
+        self.units.get(self.next).map(|unit| unit[0])
+    }
+
+    fn advance(&mut self) {
+        let head = self.head().expect("a shard with events left");
+        if is_shared(&head.event_type) {
+            if let Some(queue) = self.shared.get_mut(&fingerprint(head)) {
+                queue.pop_front();
+            }
+        }
+        self.next += 1;
+    }
+}
+
+/// `log` without its `ConfigMarker`, cut into units: an external event (or a
+/// `DuplicateIgnored`) followed by the records it generated.
+fn units<'a>(
+    shard: usize,
+    log: &'a [&'a Event],
+) -> Result<(Option<&'a Event>, Vec<&'a [&'a Event]>), MergeError> {
+    let (marker, rest) = match log.split_first() {
+        Some((first, rest)) if matches!(first.event_type, EventType::ConfigMarker { .. }) => {
+            (Some(*first), rest)
+        }
+        _ => (None, log),
+    };
+    let mut units = Vec::new();
+    let mut start = 0;
+    for (i, event) in rest.iter().enumerate() {
+        if starts_unit(event) {
+            if i > start {
+                units.push(&rest[start..i]);
+            }
+            start = i;
+        } else if i == 0 {
+            return Err(MergeError::Orphaned {
+                shard,
+                sequence: event.sequence,
+            });
+        }
+    }
+    if start < rest.len() {
+        units.push(&rest[start..]);
+    }
+    Ok((marker, units))
+}
+
+/// The shards whose head is the next occurrence of `shard`'s head, `shard` included,
+/// in shard order. Only a market-level head has copies. A shard that logged other
+/// events before its occurrence has to go first, so its head is tried instead.
+fn next_copies(shards: &[ShardLog], mut shard: usize) -> Result<Vec<usize>, MergeError> {
+    let mut tried = BTreeSet::new();
+    loop {
+        tried.insert(shard);
+        let head = shards[shard].head().expect("a shard with events left");
+        if !is_shared(&head.event_type) {
+            return Ok(vec![shard]);
+        }
+        let fingerprint = fingerprint(head);
+        let mut copies = vec![shard];
+        let mut first = None;
+        for (other, log) in shards
+            .iter()
+            .enumerate()
+            .filter(|(other, _)| *other != shard)
+        {
+            match log.shared.get(&fingerprint).and_then(VecDeque::front) {
+                Some(&unit) if unit == log.next => copies.push(other),
+                Some(_) => {
+                    first = Some(other);
+                    break;
+                }
+                None => {}
+            }
+        }
+        match first {
+            None => {
+                copies.sort_unstable();
+                return Ok(copies);
+            }
+            Some(other) if tried.contains(&other) => {
+                return Err(MergeError::MarketEventOrder {
+                    shard,
+                    sequence: head.sequence,
+                    other_shard: other,
+                });
+            }
+            Some(other) => shard = other,
+        }
+    }
+}
+
+/// An external event or an uncaused marker. A `LiquidationTakeover` can be either: a
+/// keeper's submission starts a unit, the engine's own takeover carries its trigger.
+fn starts_unit(event: &Event) -> bool {
+    let event_type = &event.event_type;
+    (!event_type.is_engine_generated() && event.caused_by.is_none())
+        || matches!(
+            event_type,
+            EventType::DuplicateIgnored { .. }
+                | EventType::RejectionSuppressed { .. }
+                | EventType::BatchStarted { .. }
+                | EventType::BatchEnded { .. }
+        )
+}
+
+/// An external event that names no account, or a batch marker: every shard logs it.
+/// A batch's end scan is split like a mark's, each liquidation to its account's shard.
+fn is_shared(event_type: &EventType) -> bool {
+    matches!(
+        event_type,
+        EventType::BatchStarted { .. } | EventType::BatchEnded { .. }
+    ) || (!event_type.is_engine_generated() && event_type.accounts().is_empty())
+}
+
+/// What makes two shards' market events the same event.
+fn fingerprint(event: &Event) -> String {
+    serde_json::to_string(&(&event.event_type, event.timestamp, &event.idempotency_key))
+        .expect("an event always serializes")
+}
+
+/// `event_type` with the sequences it names mapped to their new numbers, where known.
+fn renumber(event_type: &EventType, sequences: &BTreeMap<u64, u64>) -> EventType {
+    let mut event_type = event_type.clone();
+    if let EventType::UnknownMarketIgnored {
+        original_sequence, ..
+    }
+    | EventType::DuplicateIgnored {
+        original_sequence, ..
+    } = &mut event_type
+    {
+        *original_sequence = sequences
+            .get(original_sequence)
+            .copied()
+            .unwrap_or(*original_sequence);
+    }
+    // The events they name may have gone to another log: take this log's last event
+    // logged by then.
+    if let EventType::RejectionSuppressed {
+        first_sequence,
+        last_sequence,
+        ..
+    } = &mut event_type
+    {
+        for sequence in [first_sequence, last_sequence] {
+            *sequence = sequences
+                .range(..=*sequence)
+                .next_back()
+                .map_or(0, |(_, s)| *s);
+        }
+    }
+    event_type
 }
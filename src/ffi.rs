@@ -0,0 +1,90 @@
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+
+use crate::command::{CommandErrorKind, Response};
+use crate::config::EngineConfig;
+use crate::engine::Engine;
+
+// C entry points over `Engine::handle`, built with `--features cffi`. Strings cross
+// the boundary as NUL-terminated UTF-8. Every string returned here is owned by the
+// caller and must go back through `cme_string_free`; every engine through `cme_free`.
+
+/// Create an engine. `config_json` is an `EngineConfig` as JSON, or null for the
+/// default config. Returns null if the config does not parse.
+///
+/// # Safety
+///
+/// `config_json` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn cme_new(config_json: *const c_char) -> *mut Engine {
+    let config = if config_json.is_null() {
+        EngineConfig::default()
+    } else {
+        let parsed = CStr::from_ptr(config_json)
+            .to_str()
+            .ok()
+            .and_then(|json| serde_json::from_str(json).ok());
+        match parsed {
+            Some(config) => config,
+            None => return ptr::null_mut(),
+        }
+    };
+    Box::into_raw(Box::new(Engine::with_config(config)))
+}
+
+/// Run one JSON command (see `Command`) and return its JSON response. A null
+/// engine or command, or a command that is not UTF-8, gets an `Error` response.
+///
+/// # Safety
+///
+/// `engine` must be null or come from `cme_new` and not yet be freed, with no other
+/// call using it concurrently. `command_json` must be null or point to a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn cme_handle(
+    engine: *mut Engine,
+    command_json: *const c_char,
+) -> *mut c_char {
+    let response = match (engine.as_mut(), command_json.is_null()) {
+        (None, _) => error_json(CommandErrorKind::Parse, "null engine"),
+        (_, true) => error_json(CommandErrorKind::Parse, "null command"),
+        (Some(engine), false) => match CStr::from_ptr(command_json).to_str() {
+            Ok(json) => engine.handle(json),
+            Err(e) => error_json(CommandErrorKind::Parse, &e.to_string()),
+        },
+    };
+    // JSON escapes NUL, so the response never contains one.
+    CString::new(response).unwrap_or_default().into_raw()
+}
+
+/// Free a string returned by `cme_handle`. Null is ignored.
+///
+/// # Safety
+///
+/// `s` must be null or come from `cme_handle` and not yet be freed.
+#[no_mangle]
+pub unsafe extern "C" fn cme_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Free an engine created by `cme_new`. Null is ignored.
+///
+/// # Safety
+///
+/// `engine` must be null or come from `cme_new` and not yet be freed.
+#[no_mangle]
+pub unsafe extern "C" fn cme_free(engine: *mut Engine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+fn error_json(kind: CommandErrorKind, message: &str) -> String {
+    let response = Response::Error {
+        kind,
+        message: message.to_string(),
+    };
+    serde_json::to_string(&response).unwrap_or_default()
+}
@@ -0,0 +1,3 @@
+//! Conversions between the engine's log and the formats other systems consume.
+
+pub mod dropcopy;
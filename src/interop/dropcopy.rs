@@ -0,0 +1,978 @@
+//! A flat, FIX-like drop-copy feed of the log, for surveillance systems.
+//!
+//! Every record is a list of `tag=value` fields, written one record per line either
+//! pipe-delimited (`MsgType=ExecutionReport|SeqNo=4|...`) or as a JSON object with
+//! the same tags in the same order. Each record starts with `MsgType`, `SeqNo` (the
+//! sequence of the event it came from) and, when set, `CausedBy` and `TransactTime`
+//! (the event's submission time in Unix ms). The rest depends on `MsgType`:
+//!
+//! - `ExecutionReport`: `ExecType`, `Account`, `Symbol`, `Side` (`Buy`/`Sell`),
+//!   `LastQty` (unsigned), `LastPx` and, for a takeover or transfer, `ContraAccount`.
+//! - `OrderReject`: `RejectedType` (the kind of event rejected), `Account` and
+//!   `Symbol` when the event names them, `RejectCode` and `Text` (the reason).
+//! - `CashMovement`: `MovementType`, `Account` or `Pool` or both, `Symbol` for
+//!   funding, and the signed `Amount` (positive into the account or pool).
+//! - `MarginCall`: `Status` (`Issued`/`Cleared`), `Account`, `Equity` and
+//!   `MaintenanceMargin`.
+//! - `AccountUpdate`: `UpdateType` (the event kind), `Account` and a free-text
+//!   `Detail`.
+//!
+//! Decimals are written normalized. In the pipe form `\`, `|` and line breaks in a
+//! value are escaped as `\\`, `\|` and `\n`. JSON values are strings, except the
+//! sequence numbers and the timestamp.
+
+use rust_decimal::Decimal;
+use serde_json::Value;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::engine::{EngineObserver, RejectReason};
+use crate::events::{Event, EventType};
+use crate::snapshot::Snapshot;
+use crate::types::{AccountId, MarketId, PoolId};
+
+/// How records are written: one per line either way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DropCopyFormat {
+    /// `Tag=value` fields separated by `|`.
+    #[default]
+    Pipe,
+    /// A JSON object per record.
+    Json,
+}
+
+/// One drop-copy record and the envelope of the event it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DropCopyRecord {
+    pub sequence: u64,
+    pub caused_by: Option<u64>,
+    pub timestamp: Option<u64>,
+    pub body: RecordBody,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordBody {
+    /// A change of position: a fill, liquidation, takeover, transfer, settlement or
+    /// import. `quantity` is unsigned; `side` says which way.
+    ExecutionReport {
+        exec_type: ExecType,
+        account_id: AccountId,
+        market_id: MarketId,
+        side: Side,
+        quantity: Decimal,
+        price: Decimal,
+        contra_account: Option<AccountId>,
+    },
+    /// A rejected submission, from its `*Rejected` record.
+    OrderReject {
+        /// The kind of the event rejected (`"TradeFill"`, `"Withdraw"`, ...).
+        rejected: &'static str,
+        account_id: Option<AccountId>,
+        market_id: Option<MarketId>,
+        code: &'static str,
+        text: String,
+    },
+    /// Collateral or insurance moving. `amount` is signed, positive into the account
+    /// or, without one, into the pool.
+    CashMovement {
+        movement: Movement,
+        account_id: Option<AccountId>,
+        pool_id: Option<PoolId>,
+        market_id: Option<MarketId>,
+        amount: Decimal,
+    },
+    MarginCall {
+        status: MarginCallStatus,
+        account_id: AccountId,
+        equity: Decimal,
+        maintenance_margin: Decimal,
+    },
+    /// Anything else that changes an account without moving positions or cash.
+    AccountUpdate {
+        /// The kind of the event (`"ForceClose"`, `"RiskAlert"`, ...).
+        update: &'static str,
+        account_id: AccountId,
+        detail: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecType {
+    Trade,
+    Liquidation,
+    Takeover,
+    ForceClose,
+    Expiry,
+    Transfer,
+    Import,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+impl Side {
+    /// The side of a fill of signed `quantity`.
+    fn of(quantity: Decimal) -> Self {
+        if quantity.is_sign_negative() {
+            Side::Sell
+        } else {
+            Side::Buy
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Movement {
+    Deposit,
+    Withdrawal,
+    Funding,
+    /// A trade fee (negative) or maker rebate.
+    Fee,
+    Interest,
+    Yield,
+    /// What rounding kept back from a pool's yield payments.
+    YieldResidual,
+    InsuranceDeposit,
+    InsurancePayout,
+    /// A bankrupt account's deficit charged to the others in its pool: a credit to
+    /// the bankrupt account, a debit to each charged one.
+    LossSocialization,
+    Import,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarginCallStatus {
+    Issued,
+    Cleared,
+}
+
+/// The drop-copy code of a rejection reason.
+pub fn reject_code(reason: &RejectReason) -> &'static str {
+    match reason {
+        RejectReason::Trade(_) => "TRADE_CHECK",
+        RejectReason::Withdrawal(_) => "WITHDRAWAL_CHECK",
+        RejectReason::MarkPrice(_) => "INVALID_MARK",
+        RejectReason::Takeover(_) => "TAKEOVER_CHECK",
+        RejectReason::FundingRate(_) => "INVALID_FUNDING_RATE",
+        RejectReason::FundingUpdate(_) => "INVALID_FUNDING_UPDATE",
+        RejectReason::AccountMetadata(_) => "INVALID_METADATA",
+        RejectReason::AccountInLiquidation(_) => "ACCOUNT_IN_LIQUIDATION",
+        RejectReason::AccountSuspendedAfterBankruptcy(_) => "ACCOUNT_SUSPENDED",
+        RejectReason::AccountFrozen(_) => "ACCOUNT_FROZEN",
+        RejectReason::MarketClosed(_) => "MARKET_CLOSED",
+        RejectReason::SkewLimit(_) => "SKEW_LIMIT",
+        RejectReason::RiskCheck(_) => "CUSTOM_RISK_CHECK",
+        RejectReason::Reinstatement(_) => "REINSTATEMENT_REFUSED",
+        RejectReason::AssignPool(_) => "ACCOUNT_EXISTS",
+        RejectReason::StateImport(_) => "IMPORT_REFUSED",
+        RejectReason::HedgePair(_) => "INVALID_HEDGE_PAIR",
+        RejectReason::Expiry(_) => "EXPIRY_REFUSED",
+        RejectReason::InterestTick(_) => "INTEREST_REFUSED",
+        RejectReason::YieldDistribution(_) => "YIELD_REFUSED",
+        RejectReason::Group(_) => "GROUP_REFUSED",
+        RejectReason::Leverage(_) => "LEVERAGE_REFUSED",
+        RejectReason::InvalidEvent(_) => "INVALID_EVENT",
+    }
+}
+
+/// The records of `event`, taken as applied. A submission followed by its
+/// rejection was not applied and has none of its own; its `*Rejected` record gives
+/// the `OrderReject`. `convert_log` and `Converter` tell the two apart.
+///
+/// Market-wide events (marks, funding indexes, sessions, market and config changes,
+/// expiry and interval ticks, skew records) and duplicate markers have no records:
+/// what they do to an account comes as records of its own, such as each
+/// `FundingPayment`.
+pub fn records(event: &Event) -> Vec<DropCopyRecord> {
+    let cash =
+        |movement, account_id: Option<&AccountId>, pool_id: Option<&PoolId>, amount: Decimal| {
+            RecordBody::CashMovement {
+                movement,
+                account_id: account_id.cloned(),
+                pool_id: pool_id.cloned(),
+                market_id: None,
+                amount: amount.normalize(),
+            }
+        };
+    let update = |account_id: &AccountId, detail: String| RecordBody::AccountUpdate {
+        update: event.event_type.kind(),
+        account_id: account_id.clone(),
+        detail,
+    };
+    let bodies = match &event.event_type {
+        EventType::TradeFill {
+            account_id,
+            market_id,
+            quantity,
+            price,
+            ..
+        } => vec![execution(
+            ExecType::Trade,
+            account_id,
+            market_id,
+            *quantity,
+            *price,
+        )],
+        EventType::LiquidationFill {
+            account_id,
+            market_id,
+            quantity,
+            price,
+        } => vec![execution(
+            ExecType::Liquidation,
+            account_id,
+            market_id,
+            *quantity,
+            *price,
+        )],
+        EventType::ForceCloseFill {
+            account_id,
+            market_id,
+            quantity,
+            price,
+            ..
+        } => vec![execution(
+            ExecType::ForceClose,
+            account_id,
+            market_id,
+            *quantity,
+            *price,
+        )],
+        EventType::ExpirySettlement {
+            account_id,
+            market_id,
+            quantity,
+            price,
+            ..
+        } => vec![execution(
+            ExecType::Expiry,
+            account_id,
+            market_id,
+            *quantity,
+            *price,
+        )],
+        EventType::LiquidationTakeover {
+            liquidated_account,
+            keeper_account,
+            market_id,
+            quantity,
+            price,
+        } => vec![
+            execution(
+                ExecType::Takeover,
+                liquidated_account,
+                market_id,
+                *quantity,
+                *price,
+            )
+            .against(keeper_account),
+            execution(
+                ExecType::Takeover,
+                keeper_account,
+                market_id,
+                -*quantity,
+                *price,
+            )
+            .against(liquidated_account),
+        ],
+        EventType::PositionTransfer {
+            from,
+            to,
+            market_id,
+            quantity,
+            transfer_price,
+        } => vec![
+            execution(
+                ExecType::Transfer,
+                from,
+                market_id,
+                -*quantity,
+                *transfer_price,
+            )
+            .against(to),
+            execution(
+                ExecType::Transfer,
+                to,
+                market_id,
+                *quantity,
+                *transfer_price,
+            )
+            .against(from),
+        ],
+        EventType::StateImport {
+            account_id,
+            pool_id,
+            collateral,
+            positions,
+        } => {
+            let mut bodies = vec![
+                update(account_id, format!("pool={pool_id}")),
+                cash(
+                    Movement::Import,
+                    Some(account_id),
+                    Some(pool_id),
+                    *collateral,
+                ),
+            ];
+            for position in positions {
+                let price = position
+                    .cost_basis
+                    .checked_div(position.quantity)
+                    .unwrap_or_default();
+                bodies.push(execution(
+                    ExecType::Import,
+                    account_id,
+                    &position.market_id,
+                    position.quantity,
+                    price,
+                ));
+            }
+            bodies
+        }
+
+        EventType::Deposit { account_id, amount } => {
+            vec![cash(Movement::Deposit, Some(account_id), None, *amount)]
+        }
+        EventType::Withdraw { account_id, amount } => {
+            vec![cash(Movement::Withdrawal, Some(account_id), None, -*amount)]
+        }
+        EventType::FundingPayment {
+            account_id,
+            market_id,
+            amount,
+        } => vec![RecordBody::CashMovement {
+            movement: Movement::Funding,
+            account_id: Some(account_id.clone()),
+            pool_id: None,
+            market_id: Some(market_id.clone()),
+            amount: amount.normalize(),
+        }],
+        EventType::FeeCharged {
+            account_id,
+            market_id,
+            amount,
+            ..
+        } => vec![RecordBody::CashMovement {
+            movement: Movement::Fee,
+            account_id: Some(account_id.clone()),
+            pool_id: None,
+            market_id: Some(market_id.clone()),
+            amount: (-*amount).normalize(),
+        }],
+        EventType::InterestCharged { account_id, amount } => {
+            vec![cash(Movement::Interest, Some(account_id), None, *amount)]
+        }
+        EventType::YieldPaid {
+            account_id, amount, ..
+        } => vec![cash(Movement::Yield, Some(account_id), None, *amount)],
+        EventType::YieldResidual {
+            pool_id, amount, ..
+        } => {
+            vec![cash(Movement::YieldResidual, None, Some(pool_id), *amount)]
+        }
+        EventType::InsuranceFundDeposit { pool_id, amount } => {
+            vec![cash(
+                Movement::InsuranceDeposit,
+                None,
+                Some(pool_id),
+                *amount,
+            )]
+        }
+        EventType::InsuranceFundPayout {
+            pool_id,
+            account_id,
+            amount,
+        } => vec![cash(
+            Movement::InsurancePayout,
+            Some(account_id),
+            Some(pool_id),
+            *amount,
+        )],
+        EventType::LossSocialized {
+            pool_id,
+            account_id,
+            amount,
+            charges,
+        } => std::iter::once(cash(
+            Movement::LossSocialization,
+            Some(account_id),
+            Some(pool_id),
+            *amount,
+        ))
+        .chain(charges.iter().map(|(charged, charge)| {
+            cash(
+                Movement::LossSocialization,
+                Some(charged),
+                Some(pool_id),
+                -*charge,
+            )
+        }))
+        .collect(),
+
+        EventType::MarginCall {
+            account_id,
+            equity,
+            maintenance_margin,
+        } => vec![margin_call(
+            MarginCallStatus::Issued,
+            account_id,
+            *equity,
+            *maintenance_margin,
+        )],
+        EventType::MarginCallCleared {
+            account_id,
+            equity,
+            maintenance_margin,
+        } => vec![margin_call(
+            MarginCallStatus::Cleared,
+            account_id,
+            *equity,
+            *maintenance_margin,
+        )],
+
+        EventType::SetAccountLimits {
+            account_id,
+            max_leverage,
+            max_total_notional,
+        } => vec![update(
+            account_id,
+            format!(
+                "max_leverage={} max_total_notional={}",
+                optional(*max_leverage),
+                optional(*max_total_notional)
+            ),
+        )],
+        EventType::OrderPlaced {
+            account_id,
+            order_id,
+            market_id,
+            quantity,
+        } => vec![update(
+            account_id,
+            format!(
+                "order={order_id} market={market_id} quantity={}",
+                quantity.normalize()
+            ),
+        )],
+        EventType::OrderCancelled {
+            account_id,
+            order_id,
+        } => vec![update(account_id, format!("cancelled={order_id}"))],
+        EventType::AccountMetadata {
+            account_id,
+            key,
+            value,
+        } => vec![update(account_id, format!("{key}={value}"))],
+        EventType::AssignPool {
+            account_id,
+            pool_id,
+        } => vec![update(account_id, format!("pool={pool_id}"))],
+        EventType::GroupMembershipSet {
+            account_id,
+            group_id,
+        } => {
+            let group = group_id
+                .as_ref()
+                .map_or("none".to_string(), |g| g.to_string());
+            vec![update(account_id, format!("group={group}"))]
+        }
+        EventType::SetPositionLeverage {
+            account_id,
+            market_id,
+            leverage,
+        } => vec![update(
+            account_id,
+            format!("market={market_id} leverage={}", leverage.normalize()),
+        )],
+        EventType::BackstopRegistered {
+            account_id,
+            market_id,
+            max_notional,
+        } => vec![update(
+            account_id,
+            format!(
+                "market={market_id} max_notional={}",
+                max_notional.normalize()
+            ),
+        )],
+        EventType::AccountsMerged { from, to } => vec![
+            update(from, format!("merged_into={to}")),
+            update(to, format!("merged_from={from}")),
+        ],
+        EventType::StateImportBelowMaintenance {
+            account_id,
+            equity,
+            maintenance_margin,
+        } => vec![update(
+            account_id,
+            format!(
+                "equity={} maintenance_margin={}",
+                equity.normalize(),
+                maintenance_margin.normalize()
+            ),
+        )],
+        EventType::AccountReinstated { account_id } => vec![update(account_id, String::new())],
+        EventType::ForceClose { account_id, reason } => {
+            vec![update(account_id, format!("reason={reason}"))]
+        }
+        EventType::LiquidationDeferred {
+            account_id,
+            market_ids,
+        } => {
+            let markets: Vec<String> = market_ids.iter().map(|m| m.to_string()).collect();
+            vec![update(account_id, format!("markets={}", markets.join(",")))]
+        }
+        EventType::OrdersAutoCancelled {
+            account_id,
+            order_ids,
+            reason,
+        } => vec![update(
+            account_id,
+            format!("cancelled={} reason={reason}", order_ids.join(",")),
+        )],
+        EventType::RiskAlert {
+            account_id,
+            level,
+            margin_usage,
+        }
+        | EventType::RiskAlertCleared {
+            account_id,
+            level,
+            margin_usage,
+        } => {
+            let usage = margin_usage.map_or("unbounded".to_string(), |u| u.normalize().to_string());
+            vec![update(
+                account_id,
+                format!("level={level} margin_usage={usage}"),
+            )]
+        }
+        EventType::RejectionSuppressed {
+            account_id,
+            count,
+            first_sequence,
+            last_sequence,
+        } => vec![update(
+            account_id,
+            format!("count={count} first_sequence={first_sequence} last_sequence={last_sequence}"),
+        )],
+
+        EventType::TradeRejected { market_id, .. } => {
+            order_reject(&event.event_type, "TradeFill", Some(market_id))
+        }
+        EventType::WithdrawalRejected { .. } => order_reject(&event.event_type, "Withdraw", None),
+        EventType::MarkPriceRejected { market_id, .. } => {
+            order_reject(&event.event_type, "MarkPriceUpdate", Some(market_id))
+        }
+        EventType::MarkPriceBatchRejected { .. } => {
+            order_reject(&event.event_type, "MarkPriceBatch", None)
+        }
+        EventType::LiquidationTakeoverRejected { market_id, .. } => {
+            order_reject(&event.event_type, "LiquidationTakeover", Some(market_id))
+        }
+        EventType::FundingRateRejected { market_id, .. } => {
+            order_reject(&event.event_type, "FundingRate", Some(market_id))
+        }
+        EventType::FundingUpdateRejected { market_id, .. } => {
+            order_reject(&event.event_type, "FundingUpdate", Some(market_id))
+        }
+        EventType::AccountMetadataRejected { .. } => {
+            order_reject(&event.event_type, "AccountMetadata", None)
+        }
+        EventType::AccountReinstatementRejected { .. } => {
+            order_reject(&event.event_type, "AccountReinstated", None)
+        }
+        EventType::AssignPoolRejected { .. } => order_reject(&event.event_type, "AssignPool", None),
+        EventType::StateImportRejected { .. } => {
+            order_reject(&event.event_type, "StateImport", None)
+        }
+        EventType::HedgePairRejected { .. } => {
+            order_reject(&event.event_type, "HedgePairAdded", None)
+        }
+        EventType::ExpiryRejected { market_id, .. } => {
+            order_reject(&event.event_type, "Expiry", Some(market_id))
+        }
+        EventType::InterestTickRejected { .. } => {
+            order_reject(&event.event_type, "InterestTick", None)
+        }
+        EventType::YieldDistributionRejected { .. } => {
+            order_reject(&event.event_type, "YieldDistribution", None)
+        }
+        EventType::GroupCreatedRejected { .. } => {
+            order_reject(&event.event_type, "GroupCreated", None)
+        }
+        EventType::GroupMembershipRejected { .. } => {
+            order_reject(&event.event_type, "GroupMembershipSet", None)
+        }
+        EventType::PositionLeverageRejected { market_id, .. } => {
+            order_reject(&event.event_type, "SetPositionLeverage", Some(market_id))
+        }
+        EventType::EventRejected {
+            event: submitted, ..
+        } => order_reject(&event.event_type, submitted.kind(), None),
+
+        EventType::ConfigMarker { .. }
+        | EventType::ConfigUpdated { .. }
+        | EventType::MarkPriceUpdate { .. }
+        | EventType::MarkPriceBatch { .. }
+        | EventType::FundingUpdate { .. }
+        | EventType::FundingRate { .. }
+        | EventType::FundingAccrual { .. }
+        | EventType::MarkPriceBatchSkipped { .. }
+        | EventType::UnknownMarketIgnored { .. }
+        | EventType::GroupCreated { .. }
+        | EventType::MarketAdded { .. }
+        | EventType::MarketRemoved { .. }
+        | EventType::SessionOpen { .. }
+        | EventType::SessionClose { .. }
+        | EventType::HedgePairAdded { .. }
+        | EventType::Expiry { .. }
+        | EventType::InterestTick { .. }
+        | EventType::YieldDistribution { .. }
+        | EventType::SkewLimitBreached { .. }
+        | EventType::SkewLimitCleared { .. }
+        | EventType::DuplicateIgnored { .. }
+        | EventType::BatchStarted { .. }
+        | EventType::BatchEnded { .. } => Vec::new(),
+    };
+    bodies
+        .into_iter()
+        .map(|body| DropCopyRecord {
+            sequence: event.sequence,
+            caused_by: event.caused_by,
+            timestamp: event.timestamp,
+            body,
+        })
+        .collect()
+}
+
+/// An `ExecutionReport` for `account_id`'s fill of signed `fill` at `price`.
+fn execution(
+    exec_type: ExecType,
+    account_id: &AccountId,
+    market_id: &MarketId,
+    fill: Decimal,
+    price: Decimal,
+) -> RecordBody {
+    RecordBody::ExecutionReport {
+        exec_type,
+        account_id: account_id.clone(),
+        market_id: market_id.clone(),
+        side: Side::of(fill),
+        quantity: fill.abs().normalize(),
+        price: price.normalize(),
+        contra_account: None,
+    }
+}
+
+impl RecordBody {
+    /// An `ExecutionReport` with `contra` as the other side.
+    fn against(mut self, contra: &AccountId) -> Self {
+        if let RecordBody::ExecutionReport { contra_account, .. } = &mut self {
+            *contra_account = Some(contra.clone());
+        }
+        self
+    }
+}
+
+fn margin_call(
+    status: MarginCallStatus,
+    account_id: &AccountId,
+    equity: Decimal,
+    mm: Decimal,
+) -> RecordBody {
+    RecordBody::MarginCall {
+        status,
+        account_id: account_id.clone(),
+        equity: equity.normalize(),
+        maintenance_margin: mm.normalize(),
+    }
+}
+
+fn optional(value: Option<Decimal>) -> String {
+    value.map_or("none".to_string(), |v| v.normalize().to_string())
+}
+
+/// The `OrderReject` of a `*Rejected` record, given the name of the event it rejects
+/// and its market, if any.
+fn order_reject(
+    rejection: &EventType,
+    rejected: &'static str,
+    market_id: Option<&MarketId>,
+) -> Vec<RecordBody> {
+    RejectReason::from_event(rejection)
+        .map(|reason| RecordBody::OrderReject {
+            rejected,
+            account_id: rejection.accounts().first().map(|a| (*a).clone()),
+            market_id: market_id.cloned(),
+            code: reject_code(&reason),
+            text: reason.message().to_string(),
+        })
+        .into_iter()
+        .collect()
+}
+
+/// Converts a log in order, holding back each event that may yet be rejected until
+/// the next event shows whether it was: a rejection is always logged right after the
+/// submission it rejects, caused by it. Only events without a cause are held.
+#[derive(Debug, Default)]
+pub struct Converter {
+    held: Option<Event>,
+}
+
+impl Converter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The records `event` completes: those of the held event, unless `event` rejects
+    /// it, then its own, or none yet if it is held in turn.
+    pub fn push(&mut self, event: &Event) -> Vec<DropCopyRecord> {
+        let mut out = match self.held.take() {
+            Some(held)
+                if !(event.caused_by == Some(held.sequence) && event.event_type.is_rejection()) =>
+            {
+                records(&held)
+            }
+            _ => Vec::new(),
+        };
+        if event.caused_by.is_none() {
+            self.held = Some(event.clone());
+        } else {
+            out.extend(records(event));
+        }
+        out
+    }
+
+    /// The records of the held event, if any: the end of the log shows it applied.
+    pub fn finish(&mut self) -> Vec<DropCopyRecord> {
+        self.held
+            .take()
+            .map(|held| records(&held))
+            .unwrap_or_default()
+    }
+}
+
+/// Every drop-copy record of `log`, in log order.
+pub fn convert_log(log: &[impl AsRef<Event>]) -> Vec<DropCopyRecord> {
+    let mut converter = Converter::new();
+    let mut out: Vec<DropCopyRecord> = log
+        .iter()
+        .flat_map(|event| converter.push(event.as_ref()))
+        .collect();
+    out.extend(converter.finish());
+    out
+}
+
+impl DropCopyRecord {
+    /// The record's fields in order, as documented in the module.
+    pub fn fields(&self) -> Vec<(&'static str, Value)> {
+        let text = |s: &dyn std::fmt::Display| Value::String(s.to_string());
+        let msg_type = match &self.body {
+            RecordBody::ExecutionReport { .. } => "ExecutionReport",
+            RecordBody::OrderReject { .. } => "OrderReject",
+            RecordBody::CashMovement { .. } => "CashMovement",
+            RecordBody::MarginCall { .. } => "MarginCall",
+            RecordBody::AccountUpdate { .. } => "AccountUpdate",
+        };
+        let mut fields = vec![
+            ("MsgType", text(&msg_type)),
+            ("SeqNo", Value::from(self.sequence)),
+        ];
+        if let Some(caused_by) = self.caused_by {
+            fields.push(("CausedBy", Value::from(caused_by)));
+        }
+        if let Some(timestamp) = self.timestamp {
+            fields.push(("TransactTime", Value::from(timestamp)));
+        }
+        match &self.body {
+            RecordBody::ExecutionReport {
+                exec_type,
+                account_id,
+                market_id,
+                side,
+                quantity,
+                price,
+                contra_account,
+            } => {
+                fields.push(("ExecType", text(&format!("{exec_type:?}"))));
+                fields.push(("Account", text(account_id)));
+                fields.push(("Symbol", text(market_id)));
+                fields.push(("Side", text(&format!("{side:?}"))));
+                fields.push(("LastQty", text(quantity)));
+                fields.push(("LastPx", text(price)));
+                if let Some(contra) = contra_account {
+                    fields.push(("ContraAccount", text(contra)));
+                }
+            }
+            RecordBody::OrderReject {
+                rejected,
+                account_id,
+                market_id,
+                code,
+                text: reason,
+            } => {
+                fields.push(("RejectedType", text(rejected)));
+                if let Some(account_id) = account_id {
+                    fields.push(("Account", text(account_id)));
+                }
+                if let Some(market_id) = market_id {
+                    fields.push(("Symbol", text(market_id)));
+                }
+                fields.push(("RejectCode", text(code)));
+                fields.push(("Text", text(reason)));
+            }
+            RecordBody::CashMovement {
+                movement,
+                account_id,
+                pool_id,
+                market_id,
+                amount,
+            } => {
+                fields.push(("MovementType", text(&format!("{movement:?}"))));
+                if let Some(account_id) = account_id {
+                    fields.push(("Account", text(account_id)));
+                }
+                if let Some(pool_id) = pool_id {
+                    fields.push(("Pool", text(pool_id)));
+                }
+                if let Some(market_id) = market_id {
+                    fields.push(("Symbol", text(market_id)));
+                }
+                fields.push(("Amount", text(amount)));
+            }
+            RecordBody::MarginCall {
+                status,
+                account_id,
+                equity,
+                maintenance_margin,
+            } => {
+                fields.push(("Status", text(&format!("{status:?}"))));
+                fields.push(("Account", text(account_id)));
+                fields.push(("Equity", text(equity)));
+                fields.push(("MaintenanceMargin", text(maintenance_margin)));
+            }
+            RecordBody::AccountUpdate {
+                update,
+                account_id,
+                detail,
+            } => {
+                fields.push(("UpdateType", text(update)));
+                fields.push(("Account", text(account_id)));
+                fields.push(("Detail", text(detail)));
+            }
+        }
+        fields
+    }
+
+    /// The record as one line in `format`, without the line break.
+    pub fn render(&self, format: DropCopyFormat) -> String {
+        let fields = self.fields();
+        match format {
+            DropCopyFormat::Pipe => fields
+                .iter()
+                .map(|(tag, value)| match value {
+                    Value::String(s) => format!("{tag}={}", escape(s)),
+                    other => format!("{tag}={other}"),
+                })
+                .collect::<Vec<_>>()
+                .join("|"),
+            DropCopyFormat::Json => {
+                let members: Vec<String> = fields
+                    .iter()
+                    .map(|(tag, value)| format!("\"{tag}\":{value}"))
+                    .collect();
+                format!("{{{}}}", members.join(","))
+            }
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace('\n', "\\n")
+}
+
+/// Writes the drop copy of every event an engine records, as an observer
+/// (`Engine::add_observer`). Clones share the output, so one clone can be handed to
+/// the engine and the other kept to `flush` it.
+///
+/// A submission's records are written once the next event shows it was not
+/// rejected (see `Converter`), so the last one waits for the next submission or for
+/// `flush`. Dry-run events are never written. The first write error stops the
+/// writer until `flush` returns it.
+pub struct DropCopyWriter<W: Write> {
+    inner: Arc<Mutex<WriterState<W>>>,
+}
+
+struct WriterState<W> {
+    out: W,
+    format: DropCopyFormat,
+    converter: Converter,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Clone for DropCopyWriter<W> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<W: Write> WriterState<W> {
+    fn write(&mut self, records: Vec<DropCopyRecord>) {
+        if self.error.is_some() {
+            return;
+        }
+        for record in records {
+            if let Err(e) = writeln!(self.out, "{}", record.render(self.format)) {
+                self.error = Some(e);
+                return;
+            }
+        }
+    }
+}
+
+impl<W: Write> DropCopyWriter<W> {
+    pub fn new(out: W, format: DropCopyFormat) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(WriterState {
+                out,
+                format,
+                converter: Converter::new(),
+                error: None,
+            })),
+        }
+    }
+
+    /// Convert `event` as if the engine had recorded it: for writing a log out.
+    pub fn write_event(&self, event: &Event) {
+        let mut state = self.inner.lock().unwrap();
+        let records = state.converter.push(event);
+        state.write(records);
+    }
+
+    /// Write the held submission's records and flush the output. Between `process`
+    /// calls the held submission is settled: a rejection would already have been
+    /// recorded. Returns the first write error since the last flush, if any.
+    pub fn flush(&self) -> io::Result<()> {
+        let mut state = self.inner.lock().unwrap();
+        let records = state.converter.finish();
+        state.write(records);
+        if let Some(e) = state.error.take() {
+            return Err(e);
+        }
+        state.out.flush()
+    }
+}
+
+impl<W: Write> EngineObserver for DropCopyWriter<W> {
+    fn on_event(&mut self, event: &Event, _snapshot: &Snapshot) {
+        self.write_event(event);
+    }
+}
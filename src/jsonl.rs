@@ -0,0 +1,264 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::error::EngineError;
+use crate::events::Event;
+
+/// Options for `write_jsonl`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriteOptions {
+    /// Permit writing a log that contains `dry_run` events. Off by default so a
+    /// simulated log can't be persisted where an authoritative one is expected.
+    pub allow_dry_run: bool,
+}
+
+/// Write an event log as JSONL, one event per line.
+pub fn write_jsonl(
+    path: impl AsRef<Path>,
+    events: &[impl AsRef<Event>],
+    options: WriteOptions,
+) -> Result<(), EngineError> {
+    let events = events.iter().map(AsRef::as_ref);
+    if !options.allow_dry_run {
+        if let Some(event) = events.clone().find(|e| e.dry_run) {
+            return Err(EngineError::DryRunLog {
+                sequence: event.sequence,
+            });
+        }
+    }
+
+    let lines = events
+        .map(serde_json::to_string)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(EngineError::Serialize)?;
+    fs::write(path, lines.join("\n"))?;
+    Ok(())
+}
+
+/// Read a JSONL event log. Blank lines are skipped.
+pub fn read_jsonl(path: impl AsRef<Path>) -> Result<Vec<Event>, EngineError> {
+    let content = fs::read_to_string(path)?;
+    parse_jsonl(&content)
+}
+
+/// Parse JSONL content already in memory.
+pub fn parse_jsonl(content: &str) -> Result<Vec<Event>, EngineError> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line).map_err(|source| EngineError::Parse {
+                line: i + 1,
+                source,
+            })
+        })
+        .collect()
+}
+
+/// A defect `read_jsonl_recover` found in a log. Lines are 1-based.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "defect")]
+pub enum LogDefect {
+    /// The last line has no trailing newline and stops part-way through an event,
+    /// possibly mid-way through a UTF-8 character: a write cut short by a crash.
+    /// Dropping it loses only the event that was being written.
+    TruncatedLastLine { line: usize, bytes: usize },
+    /// A line that is not UTF-8 or not an event, anywhere but a torn tail. Whatever
+    /// it held is lost, so it is only dropped under `RepairPolicy::DropCorrupt`.
+    MalformedLine { line: usize, error: String },
+    /// The event on `line` does not follow the previous readable event by one.
+    SequenceGap {
+        line: usize,
+        expected: u64,
+        found: u64,
+    },
+}
+
+impl LogDefect {
+    /// Whether `repair` under `policy` writes a log without this defect.
+    pub fn is_repaired_by(&self, policy: RepairPolicy) -> bool {
+        match self {
+            LogDefect::TruncatedLastLine { .. } => true,
+            LogDefect::MalformedLine { .. } | LogDefect::SequenceGap { .. } => {
+                policy == RepairPolicy::DropCorrupt
+            }
+        }
+    }
+}
+
+impl fmt::Display for LogDefect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogDefect::TruncatedLastLine { line, bytes } => {
+                write!(f, "line {line}: truncated last line ({bytes} bytes)")
+            }
+            LogDefect::MalformedLine { line, error } => write!(f, "line {line}: {error}"),
+            LogDefect::SequenceGap {
+                line,
+                expected,
+                found,
+            } => {
+                write!(f, "line {line}: expected seq {expected}, found seq {found}")
+            }
+        }
+    }
+}
+
+/// What `repair` may drop. Under either policy the cleaned log is only written if
+/// every defect is covered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RepairPolicy {
+    /// Drop a truncated last line and nothing else.
+    #[default]
+    Strict,
+    /// Also drop malformed lines, and keep the events around a sequence gap. The
+    /// cleaned log no longer holds everything the engine recorded, so `replay_verified`
+    /// still refuses it if a gap remains.
+    DropCorrupt,
+}
+
+/// What `repair` wrote.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RepairReport {
+    pub events: usize,
+    pub defects: Vec<LogDefect>,
+}
+
+/// Read a JSONL event log without stopping at the first bad line: every readable
+/// event, plus every defect found on the way. Only I/O fails.
+pub fn read_jsonl_recover(
+    path: impl AsRef<Path>,
+) -> Result<(Vec<Event>, Vec<LogDefect>), EngineError> {
+    Ok(parse_jsonl_recover(&fs::read(path)?))
+}
+
+/// `read_jsonl_recover` on content already in memory. Bytes rather than `&str`,
+/// since a torn write can leave the file ending inside a UTF-8 character.
+pub fn parse_jsonl_recover(content: &[u8]) -> (Vec<Event>, Vec<LogDefect>) {
+    let mut events: Vec<Event> = Vec::new();
+    let mut defects = Vec::new();
+    let mut lines = content.split(|b| *b == b'\n').enumerate().peekable();
+    while let Some((i, raw)) = lines.next() {
+        let line = i + 1;
+        if raw.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+        // Only the final segment lacks a newline. It is torn, rather than corrupt,
+        // when the bytes stop early: mid-character or mid-value.
+        let last = lines.peek().is_none();
+        let event = match std::str::from_utf8(raw) {
+            Ok(text) => {
+                serde_json::from_str::<Event>(text).map_err(|e| (e.is_eof(), e.to_string()))
+            }
+            Err(e) => Err((e.error_len().is_none(), e.to_string())),
+        };
+        match event {
+            Ok(event) => {
+                if let Some(previous) = events.last() {
+                    let expected = previous.sequence + 1;
+                    if event.sequence != expected {
+                        defects.push(LogDefect::SequenceGap {
+                            line,
+                            expected,
+                            found: event.sequence,
+                        });
+                    }
+                }
+                events.push(event);
+            }
+            Err((true, _)) if last => defects.push(LogDefect::TruncatedLastLine {
+                line,
+                bytes: raw.len(),
+            }),
+            Err((_, error)) => defects.push(LogDefect::MalformedLine { line, error }),
+        }
+    }
+    (events, defects)
+}
+
+/// Write the readable events of the log at `path` to `output_path`, one per
+/// newline-terminated line so a `LogStore` can append to it. Refuses with
+/// `EngineError::CorruptLog`, writing nothing, if `policy` does not cover every
+/// defect. `output_path` may be `path` itself.
+pub fn repair(
+    path: impl AsRef<Path>,
+    output_path: impl AsRef<Path>,
+    policy: RepairPolicy,
+) -> Result<RepairReport, EngineError> {
+    let (events, defects) = read_jsonl_recover(path)?;
+    if !defects.iter().all(|d| d.is_repaired_by(policy)) {
+        return Err(EngineError::CorruptLog { defects });
+    }
+
+    let mut content = String::new();
+    for event in &events {
+        content.push_str(&serde_json::to_string(event).map_err(EngineError::Serialize)?);
+        content.push('\n');
+    }
+    fs::write(output_path, content)?;
+    Ok(RepairReport {
+        events: events.len(),
+        defects,
+    })
+}
+
+/// Open a JSONL event log for streaming, one event at a time.
+pub fn stream_jsonl(path: impl AsRef<Path>) -> Result<JsonlStream<BufReader<File>>, EngineError> {
+    Ok(JsonlStream::new(BufReader::new(File::open(path)?)))
+}
+
+/// Iterator over the events of a JSONL source without materializing the whole log.
+/// Yields `Err` once for the first unreadable or unparseable line, then ends.
+pub struct JsonlStream<R> {
+    reader: R,
+    line: usize,
+    buf: String,
+    failed: bool,
+}
+
+impl<R: BufRead> JsonlStream<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: 0,
+            buf: String::new(),
+            failed: false,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for JsonlStream<R> {
+    type Item = Result<Event, EngineError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        loop {
+            self.buf.clear();
+            self.line += 1;
+            match self.reader.read_line(&mut self.buf) {
+                Ok(0) => return None,
+                Ok(_) if self.buf.trim().is_empty() => continue,
+                Ok(_) => {
+                    let parsed =
+                        serde_json::from_str(&self.buf).map_err(|source| EngineError::Parse {
+                            line: self.line,
+                            source,
+                        });
+                    self.failed = parsed.is_err();
+                    return Some(parsed);
+                }
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(EngineError::Io(e)));
+                }
+            }
+        }
+    }
+}
@@ -1,8 +1,29 @@
+pub mod backtest;
+pub mod checkpoint;
+pub mod command;
+pub mod config;
+pub mod decimal_str;
+pub mod demo;
+pub mod durable;
 pub mod engine;
+pub mod error;
 pub mod events;
+#[cfg(feature = "cffi")]
+pub mod ffi;
+pub mod interop;
+pub mod jsonl;
 pub mod liquidation;
+pub mod log_store;
 pub mod margin;
+pub mod prelude;
+pub mod regenerate;
+pub mod report;
 pub mod risk;
+pub mod rng;
+pub mod scenario;
+pub mod sink;
 pub mod snapshot;
 pub mod state;
+mod trace;
 pub mod types;
+pub mod view;
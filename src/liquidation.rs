@@ -1,148 +1,731 @@
-use rust_decimal::Decimal;
+use rust_decimal::{Decimal, RoundingStrategy};
 
-use crate::events::{Event, EventType};
+use crate::config::{ClosedSessionLiquidation, LiquidationStrategy, ResidualDeficit};
+use crate::events::EventType;
 use crate::margin;
-use crate::risk::apply_trade_to;
+use crate::risk::{self, apply_trade_to, TradeCheck};
 use crate::state::State;
-use crate::types::AccountId;
+use crate::trace;
+use crate::types::{Account, AccountId, Market, MarketId, Position};
+use std::collections::BTreeMap;
 
-/// Scan an account for liquidation. If liquidatable, close positions
-/// largest-notional-first and return the generated LiquidationFill events.
+/// One close the liquidation engine would perform, with the account's projected
+/// position after it executes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiquidationStep {
+    pub market_id: MarketId,
+    pub close_quantity: Decimal,
+    pub price: Decimal,
+    pub projected_collateral: Decimal,
+    pub projected_equity: Decimal,
+    pub projected_maintenance_margin: Decimal,
+}
+
+/// The ordered closes `check_and_liquidate` would perform for an account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiquidationPlan {
+    pub account_id: AccountId,
+    pub steps: Vec<LiquidationStep>,
+    /// True when the plan ends with the account still liquidatable because nothing
+    /// closable remains (all positions closed, or only positions in unknown markets).
+    /// Executing such a plan finalizes the bankruptcy deficit.
+    pub exhausted: bool,
+    /// Under `ClosedSessionLiquidation::DeferUntilOpen`, the closed markets of the
+    /// positions left open because the plan may not close them. Non-empty only when
+    /// the plan ends with the account still liquidatable; such a plan is not exhausted.
+    pub deferred: Vec<MarketId>,
+}
+
+/// Compute, without mutating anything, the liquidation `check_and_liquidate` would
+/// perform on an account under the default strategy. Returns `None` if the account
+/// is missing or not liquidatable.
+pub fn plan(state: &State, account_id: &AccountId) -> Option<LiquidationPlan> {
+    plan_with(state, account_id, LiquidationStrategy::default())
+}
+
+/// `plan` under an explicit `LiquidationStrategy`.
 ///
 /// Determinism notes:
 /// - Positions are stored in a BTreeMap, so iteration is deterministic.
-/// - When notionals tie, we break ties by market_id (lexicographic) explicitly.
-/// - Bankruptcy deficit is updated deterministically when positions are fully closed.
-pub fn check_and_liquidate(
-    state: &mut State,
+/// - Every strategy breaks its final ties by market_id (lexicographic) explicitly.
+pub fn plan_with(
+    state: &State,
     account_id: &AccountId,
-    next_sequence: &mut u64,
-) -> Vec<Event> {
-    let mut events = Vec::new();
+    strategy: LiquidationStrategy,
+) -> Option<LiquidationPlan> {
+    plan_with_sessions(
+        state,
+        account_id,
+        strategy,
+        ClosedSessionLiquidation::default(),
+    )
+}
+
+/// `plan_with` under a `ClosedSessionLiquidation` policy. With `DeferUntilOpen`,
+/// positions in markets whose session is closed are never selected.
+pub fn plan_with_sessions(
+    state: &State,
+    account_id: &AccountId,
+    strategy: LiquidationStrategy,
+    closed_sessions: ClosedSessionLiquidation,
+) -> Option<LiquidationPlan> {
+    let defer = closed_sessions == ClosedSessionLiquidation::DeferUntilOpen;
+    let account = state.accounts.get(account_id)?;
+    if !margin::is_liquidatable(account, state) {
+        return None;
+    }
+
+    // Work on a copy so every step sees the effect of the previous ones.
+    let mut sim = account.clone();
+    let mut steps = Vec::new();
 
     loop {
-        // Immutable read to decide whether we should liquidate.
-        let account = match state.accounts.get(account_id) {
-            Some(a) => a,
-            None => return events,
+        if sim.positions.is_empty() {
+            break;
+        }
+        if !margin::is_liquidatable(&sim, state) {
+            return Some(LiquidationPlan {
+                account_id: account_id.clone(),
+                steps,
+                exhausted: false,
+                deferred: Vec::new(),
+            });
+        }
+
+        let market_id = match select_position(&sim, state, strategy, defer) {
+            Some(market_id) => market_id,
+            None => break, // No positions with known (and, if deferring, open) markets
         };
 
-        // Nothing to liquidate if there are no positions.
-        if account.positions.is_empty() {
-            // If your engine defines bankruptcy as "no positions and negative collateral",
-            // ensure deficit is set deterministically.
-            // (Requires Account.bankruptcy_deficit field.)
-            // drop(account);
-            let _ = account;
-            if let Some(acct) = state.accounts.get_mut(account_id) {
-                acct.bankruptcy_deficit = if acct.collateral < Decimal::ZERO {
-                    -acct.collateral
-                } else {
-                    Decimal::ZERO
-                };
-            }
-            return events;
+        // Close the entire position, in whole steps where the market has a lot size,
+        // and nothing worth less than its `min_liquidation_notional`.
+        let market = &state.markets[&market_id];
+        let close_quantity = market.liquidation_close(sim.positions[&market_id].quantity);
+        let price = liquidation_price(&state.markets[&market_id], close_quantity);
+        apply_trade_to(
+            &mut sim.trading_balance,
+            &mut sim.positions,
+            &market_id,
+            close_quantity,
+            price,
+        );
+
+        steps.push(LiquidationStep {
+            market_id,
+            close_quantity,
+            price,
+            projected_collateral: sim.collateral(),
+            projected_equity: margin::equity(&sim, state),
+            projected_maintenance_margin: margin::maintenance_margin_required(&sim, state),
+        });
+    }
+
+    let deferred: Vec<MarketId> = sim
+        .positions
+        .keys()
+        .filter(|mid| defer && state.markets.get(*mid).is_some_and(|m| m.session_closed))
+        .cloned()
+        .collect();
+    // Closing the last position can leave the account healthy, with nothing to finalize.
+    let liquidatable = margin::is_liquidatable(&sim, state);
+    Some(LiquidationPlan {
+        account_id: account_id.clone(),
+        steps,
+        exhausted: liquidatable && deferred.is_empty(),
+        deferred: if liquidatable { deferred } else { Vec::new() },
+    })
+}
+
+/// The closes a `ForceClose` performs: every position in a registered market, whole
+/// and at mark, in the order `strategy` would liquidate them, whether or not the
+/// account is liquidatable. Empty for an unknown or flat account.
+pub fn force_close_steps(
+    state: &State,
+    account_id: &AccountId,
+    strategy: LiquidationStrategy,
+) -> Vec<LiquidationStep> {
+    let Some(account) = state.accounts.get(account_id) else {
+        return Vec::new();
+    };
+    let mut sim = account.clone();
+    let mut steps = Vec::new();
+    while let Some(market_id) = select_position(&sim, state, strategy, false) {
+        let close_quantity = -sim.positions[&market_id].quantity;
+        let price = state.markets[&market_id].mark_price;
+        apply_trade_to(
+            &mut sim.trading_balance,
+            &mut sim.positions,
+            &market_id,
+            close_quantity,
+            price,
+        );
+        steps.push(LiquidationStep {
+            market_id,
+            close_quantity,
+            price,
+            projected_collateral: sim.collateral(),
+            projected_equity: margin::equity(&sim, state),
+            projected_maintenance_margin: margin::maintenance_margin_required(&sim, state),
+        });
+    }
+    steps
+}
+
+/// Plans for every currently liquidatable account, in account_id order.
+pub fn plan_all(state: &State) -> Vec<LiquidationPlan> {
+    state
+        .accounts
+        .keys()
+        .filter_map(|account_id| plan(state, account_id))
+        .collect()
+}
+
+/// Select the next position to close under `strategy`, returning its market.
+/// Both strategies rank by a score (higher is better), then by notional
+/// (abs(mark * qty)), then by market_id lexicographically (canonical). Both scores
+/// see hedge-pair relief, as `is_liquidatable` does: `LargestNotionalFirst` scores
+/// the notional still charged margin, so a hedged leg goes after unhedged exposure.
+/// Positions in unknown markets are skipped deterministically, and so are positions
+/// in closed markets when `skip_closed`.
+fn select_position(
+    account: &Account,
+    state: &State,
+    strategy: LiquidationStrategy,
+    skip_closed: bool,
+) -> Option<MarketId> {
+    let mut chosen: Option<(&MarketId, Decimal, Decimal)> = None;
+
+    for (mid, pos) in &account.positions {
+        let market = match state.markets.get(mid) {
+            Some(m) => m,
+            None => continue, // deterministic skip for malformed state
+        };
+        if skip_closed && market.session_closed {
+            continue;
         }
 
-        // Only proceed if account is liquidatable.
-        if !margin::is_liquidatable(account, state) {
-            return events;
+        let notional = margin::position_notional(pos.quantity, market.mark_price);
+        let score = match strategy {
+            LiquidationStrategy::LargestNotionalFirst => {
+                margin::charged_notional(&account.positions, mid, state)
+            }
+            LiquidationStrategy::BestMarginImprovementFirst => {
+                margin_after_close(account, state, market, pos.quantity)
+            }
+        };
+
+        let better = match &chosen {
+            None => true,
+            Some((best_mid, best_score, best_notional)) => {
+                (score, notional) > (*best_score, *best_notional)
+                    || (score == *best_score && notional == *best_notional && mid < *best_mid)
+            }
+        };
+        if better {
+            chosen = Some((mid, score, notional));
         }
+    }
 
-        // Select the position with the largest notional (abs(mark * qty)).
-        // Tie-break: market_id lexicographically (canonical).
-        let mut chosen_market: Option<String> = None;
-        let mut chosen_notional: Option<Decimal> = None;
-        let mut chosen_mark: Option<Decimal> = None;
+    chosen.map(|(mid, _, _)| mid.clone())
+}
 
-        for (mid, pos) in &account.positions {
-            let market = match state.markets.get(mid) {
-                Some(m) => m,
-                None => continue, // deterministic skip for malformed state
-            };
+/// Equity minus maintenance margin after fully closing the position in `market` at
+/// its liquidation price, simulated on a copy of the account with the same
+/// `apply_trade_to` that executes the close.
+fn margin_after_close(
+    account: &Account,
+    state: &State,
+    market: &Market,
+    quantity: Decimal,
+) -> Decimal {
+    let price = liquidation_price(market, -quantity);
+    let mut sim = account.clone();
+    apply_trade_to(
+        &mut sim.trading_balance,
+        &mut sim.positions,
+        &market.market_id,
+        -quantity,
+        price,
+    );
+    margin::equity(&sim, state) - margin::maintenance_margin_required(&sim, state)
+}
 
-            let notional = margin::position_notional(pos.quantity, market.mark_price);
+/// If all positions are closed and collateral is negative, record the deficit as a
+/// non-negative number; otherwise zero.
+fn settle_bankruptcy_deficit(account: &mut Account) {
+    account.bankruptcy_deficit = if account.collateral() < Decimal::ZERO {
+        -account.collateral()
+    } else {
+        Decimal::ZERO
+    };
+}
 
-            match (&chosen_market, &chosen_notional) {
-                (None, None) => {
-                    chosen_market = Some(mid.clone());
-                    chosen_notional = Some(notional);
-                    chosen_mark = Some(market.mark_price);
-                }
-                (Some(best_mid), Some(best_notional)) => {
-                    if notional > *best_notional || (notional == *best_notional && mid < best_mid) {
-                        chosen_market = Some(mid.clone());
-                        chosen_notional = Some(notional);
-                        chosen_mark = Some(market.mark_price);
-                    }
-                }
-                _ => unreachable!("chosen_market and chosen_notional should be set together"),
+/// Suspend an account that now carries a bankruptcy deficit, remembering the
+/// markets its current cascade liquidated it in. A suspension only ever grows here.
+fn suspend_if_bankrupt(state: &mut State, account_id: &AccountId) {
+    let markets = state
+        .liquidated_markets
+        .get(account_id)
+        .cloned()
+        .unwrap_or_default();
+    let account = state.accounts.get_mut(account_id).unwrap();
+    if account.bankruptcy_deficit > Decimal::ZERO {
+        account.suspended = true;
+        account.suspended_markets.extend(markets);
+    }
+}
+
+/// Apply a liquidation close directly (no risk check), keeping the bankruptcy
+/// deficit zero until the account is fully closed.
+fn apply_close(account: &mut Account, market_id: &MarketId, quantity: Decimal, price: Decimal) {
+    apply_trade_to(
+        &mut account.trading_balance,
+        &mut account.positions,
+        market_id,
+        quantity,
+        price,
+    );
+
+    if account.positions.is_empty() {
+        settle_bankruptcy_deficit(account);
+    } else {
+        account.bankruptcy_deficit = Decimal::ZERO;
+    }
+}
+
+/// Price at which the engine closes `close_quantity` (signed, the liquidated account's
+/// fill): mark moved against the account by `notional * slippage_bps_per_notional`
+/// basis points, so bigger closes execute at worse prices. The slippage is capped at
+/// 100% of mark. With zero slippage (the default) this is the mark.
+pub fn liquidation_price(market: &Market, close_quantity: Decimal) -> Decimal {
+    if market.slippage_bps_per_notional.is_zero() {
+        return market.mark_price;
+    }
+    let notional = margin::position_notional(close_quantity, market.mark_price);
+    let fraction =
+        (notional * market.slippage_bps_per_notional / Decimal::from(10_000)).min(Decimal::ONE);
+    let impact = market.mark_price.abs() * fraction;
+    if close_quantity > Decimal::ZERO {
+        market.mark_price + impact
+    } else {
+        market.mark_price - impact
+    }
+}
+
+/// Price at which a keeper takes over `keeper_quantity` (signed, from the keeper's
+/// perspective): below mark when the keeper goes long, above mark when it goes short.
+/// The discount is a fraction of the absolute mark, so it stays in the keeper's favor
+/// at a negative mark.
+pub fn takeover_price(market: &Market, keeper_quantity: Decimal) -> Decimal {
+    let discount = market.mark_price.abs() * market.liquidation_discount;
+    if keeper_quantity > Decimal::ZERO {
+        market.mark_price - discount
+    } else {
+        market.mark_price + discount
+    }
+}
+
+/// Keeper side of a takeover: the position is entered at mark, and the difference
+/// between mark and the takeover price is credited as realized PnL. Economically
+/// identical to entering at the takeover price, but keeps the keeper's cost basis at
+/// mark so the discount shows up in collateral rather than as unrealized PnL.
+pub(crate) fn apply_keeper_side(
+    collateral: &mut Decimal,
+    positions: &mut BTreeMap<MarketId, Position>,
+    market: &Market,
+    keeper_quantity: Decimal,
+    price: Decimal,
+) {
+    apply_trade_to(
+        collateral,
+        positions,
+        &market.market_id,
+        keeper_quantity,
+        market.mark_price,
+    );
+    *collateral += (market.mark_price - price) * keeper_quantity;
+}
+
+/// Transfer `quantity` (liquidated account's close fill) from the liquidated account
+/// to the keeper at `price`. Callers must have validated it with `risk::check_takeover`.
+pub(crate) fn apply_takeover(
+    state: &mut State,
+    liquidated_account: &AccountId,
+    keeper_account: &AccountId,
+    market_id: &MarketId,
+    quantity: Decimal,
+    price: Decimal,
+) {
+    let market = state.markets[market_id].clone();
+
+    state
+        .liquidated_markets
+        .entry(liquidated_account.clone())
+        .or_default()
+        .insert(market_id.clone());
+    let liquidated = state.accounts.get_mut(liquidated_account).unwrap();
+    apply_close(liquidated, market_id, quantity, price);
+    suspend_if_bankrupt(state, liquidated_account);
+
+    let keeper = state.accounts.get_mut(keeper_account).unwrap();
+    apply_keeper_side(
+        &mut keeper.trading_balance,
+        &mut keeper.positions,
+        &market,
+        -quantity,
+        price,
+    );
+
+    // Whatever offered it, a backstop's takeover in its market counts against its cap.
+    if let Some(backstop) = state
+        .backstops
+        .iter_mut()
+        .find(|b| b.account_id == *keeper_account && b.market_id == *market_id)
+    {
+        backstop.absorbed += quantity.abs();
+    }
+}
+
+/// The next liquidation event for an account, or `None` once it is healthy or has
+/// nothing closable left. Takes the first step of the account's `plan_with`. That
+/// close is first offered to the market's backstops (see `backstop_takeover`), then,
+/// with `keepers`, to each keeper in order as a `LiquidationTakeover`, and the first
+/// that passes `risk::check_takeover` takes it. Otherwise the engine closes at
+/// `liquidation_price` with a `LiquidationFill`.
+///
+/// The engine applies each returned event through the same path as replay, then asks
+/// again, so every step is planned on the state the previous one left. (A takeover
+/// executes at a discount and leaves the account worse off than a close at mark, so
+/// a fixed plan would not do.)
+pub fn next_liquidation(
+    state: &State,
+    account_id: &AccountId,
+    keepers: &[AccountId],
+    strategy: LiquidationStrategy,
+) -> Option<EventType> {
+    next_liquidation_with_sessions(
+        state,
+        account_id,
+        keepers,
+        strategy,
+        ClosedSessionLiquidation::default(),
+    )
+}
+
+/// `next_liquidation` under a `ClosedSessionLiquidation` policy: with
+/// `DeferUntilOpen`, positions in closed markets are left alone.
+pub fn next_liquidation_with_sessions(
+    state: &State,
+    account_id: &AccountId,
+    keepers: &[AccountId],
+    strategy: LiquidationStrategy,
+    closed_sessions: ClosedSessionLiquidation,
+) -> Option<EventType> {
+    let step = plan_with_sessions(state, account_id, strategy, closed_sessions)?
+        .steps
+        .into_iter()
+        .next()?;
+    trace::event!(
+        market_id = %step.market_id,
+        close_quantity = %step.close_quantity,
+        strategy = ?strategy,
+        candidates = %trace::Notionals { state, account_id },
+        "liquidation selected"
+    );
+    if let Some(takeover) = backstop_takeover(state, account_id, &step) {
+        trace::event!(route = "backstop", liquidation = ?takeover, "liquidation route");
+        return Some(takeover);
+    }
+
+    let price = takeover_price(&state.markets[&step.market_id], -step.close_quantity);
+    let keeper = keepers.iter().find(|keeper| {
+        matches!(
+            risk::check_takeover(
+                state,
+                account_id,
+                keeper,
+                &step.market_id,
+                step.close_quantity,
+                price,
+            ),
+            TradeCheck::Accepted
+        )
+    });
+
+    Some(match keeper {
+        Some(keeper) => {
+            trace::event!(route = "keeper", keeper = %keeper, price = %price, "liquidation route");
+            EventType::LiquidationTakeover {
+                liquidated_account: account_id.clone(),
+                keeper_account: keeper.clone(),
+                market_id: step.market_id,
+                quantity: step.close_quantity,
+                price,
             }
         }
+        None => {
+            trace::event!(route = "engine", price = %step.price, "liquidation route");
+            EventType::LiquidationFill {
+                account_id: account_id.clone(),
+                market_id: step.market_id,
+                quantity: step.close_quantity,
+                price: step.price,
+            }
+        }
+    })
+}
 
-        let market_id = match chosen_market {
-            Some(m) => m,
-            None => {
-                // No positions with known markets; update deficit if desired and stop.
-                // drop(account);
-                let _ = account;
-                if let Some(acct) = state.accounts.get_mut(account_id) {
-                    acct.bankruptcy_deficit = if acct.collateral < Decimal::ZERO {
-                        -acct.collateral
-                    } else {
-                        Decimal::ZERO
-                    };
+/// Quantity a backstop's takeover may be rounded to when the market has no
+/// `quantity_step`.
+pub const BACKSTOP_QUANTITY_DECIMALS: u32 = 8;
+
+/// The part of `step`'s close the first able backstop in its market takes over, as a
+/// `LiquidationTakeover` at `takeover_price`. Backstops are asked in `State::backstops`
+/// order. Each is offered as much of the close as its remaining notional covers at
+/// mark, rounded down to whole quantity steps (or to `BACKSTOP_QUANTITY_DECIMALS`),
+/// and takes it if it passes `risk::check_takeover`. A part worth less than the
+/// market's `min_liquidation_notional` is rounded up to it (`Market::min_close_quantity`,
+/// then to `BACKSTOP_QUANTITY_DECIMALS` without a step), or to the whole close, even
+/// past the remaining cap. What it cannot take stays with the account for the next
+/// step, so a large close can be split across backstops before the rest goes to
+/// keepers or the engine close.
+pub fn backstop_takeover(
+    state: &State,
+    account_id: &AccountId,
+    step: &LiquidationStep,
+) -> Option<EventType> {
+    let market = &state.markets[&step.market_id];
+    let mark = market.mark_price.abs();
+    state
+        .backstops
+        .iter()
+        .filter(|backstop| {
+            backstop.market_id == step.market_id && backstop.account_id != *account_id
+        })
+        .find_map(|backstop| {
+            let close = step.close_quantity.abs();
+            // A zero or tiny mark leaves the whole close within any cap.
+            let covered = backstop.remaining(mark).checked_div(mark).unwrap_or(close);
+            let whole = if covered >= close {
+                close
+            } else {
+                match market.quantity_step.filter(|step| *step > Decimal::ZERO) {
+                    Some(quantity_step) => covered - covered % quantity_step,
+                    None => covered.round_dp_with_strategy(
+                        BACKSTOP_QUANTITY_DECIMALS,
+                        RoundingStrategy::ToZero,
+                    ),
                 }
-                return events;
+            };
+            if whole.is_zero() {
+                return None;
             }
-        };
+            let whole = match market.min_close_quantity(whole) {
+                Some(least) if market.quantity_step.is_none() => least
+                    .round_dp_with_strategy(
+                        BACKSTOP_QUANTITY_DECIMALS,
+                        RoundingStrategy::AwayFromZero,
+                    )
+                    .min(close),
+                Some(least) => least.min(close),
+                None => whole,
+            };
+            let quantity = if step.close_quantity.is_sign_negative() {
+                -whole
+            } else {
+                whole
+            };
+            let price = takeover_price(market, -quantity);
+            let check = risk::check_takeover(
+                state,
+                account_id,
+                &backstop.account_id,
+                &step.market_id,
+                quantity,
+                price,
+            );
+            matches!(check, TradeCheck::Accepted).then(|| EventType::LiquidationTakeover {
+                liquidated_account: account_id.clone(),
+                keeper_account: backstop.account_id.clone(),
+                market_id: step.market_id.clone(),
+                quantity,
+                price,
+            })
+        })
+}
 
-        let mark_price = chosen_mark.unwrap_or(Decimal::ZERO);
+/// Apply a `LiquidationFill`. Callers must have checked that the account holds the
+/// position being reduced.
+pub(crate) fn apply_fill(
+    state: &mut State,
+    account_id: &AccountId,
+    market_id: &MarketId,
+    quantity: Decimal,
+    price: Decimal,
+) {
+    state
+        .liquidated_markets
+        .entry(account_id.clone())
+        .or_default()
+        .insert(market_id.clone());
+    let account = state.accounts.get_mut(account_id).unwrap();
+    apply_close(account, market_id, quantity, price);
+    suspend_if_bankrupt(state, account_id);
+}
 
-        // Close the entire position: fill quantity is the negative of current quantity.
-        let close_qty = {
-            let acct = state.accounts.get(account_id).unwrap();
-            let pos = acct.positions.get(&market_id).unwrap();
-            -pos.quantity
-        };
+/// Closed markets an account's liquidation is waiting on under `DeferUntilOpen`:
+/// non-empty when it is still liquidatable and only positions in closed markets are
+/// left to close.
+pub(crate) fn deferred_markets(
+    state: &State,
+    account_id: &AccountId,
+    closed_sessions: ClosedSessionLiquidation,
+) -> Vec<MarketId> {
+    plan_with_sessions(
+        state,
+        account_id,
+        LiquidationStrategy::default(),
+        closed_sessions,
+    )
+    .map(|plan| plan.deferred)
+    .unwrap_or_default()
+}
 
-        // Apply the liquidation close directly (no risk check).
-        {
-            let acct = state.accounts.get_mut(account_id).unwrap();
-            apply_trade_to(
-                &mut acct.collateral,
-                &mut acct.positions,
-                &market_id,
-                close_qty,
-                mark_price,
-            );
+/// Draw on the account's own pool's insurance fund for its bankruptcy deficit: the
+/// payout for the smaller of the two, or `None` if either is zero.
+pub(crate) fn insurance_payout(state: &State, account_id: &AccountId) -> Option<EventType> {
+    let account = state.accounts.get(account_id)?;
+    let amount = account
+        .bankruptcy_deficit
+        .min(state.insurance_fund(&account.pool_id));
+    (amount > Decimal::ZERO).then(|| EventType::InsuranceFundPayout {
+        pool_id: account.pool_id.clone(),
+        account_id: account_id.clone(),
+        amount,
+    })
+}
 
-            // If liquidation finishes the account (no positions left), update deficit deterministically.
-            if acct.positions.is_empty() {
-                acct.bankruptcy_deficit = if acct.collateral < Decimal::ZERO {
-                    -acct.collateral
-                } else {
-                    Decimal::ZERO
-                };
-            } else {
-                // Optional: keep it zero until fully closed, but ensure determinism.
-                acct.bankruptcy_deficit = Decimal::ZERO;
-            }
-        }
+/// An `InsuranceFundPayout` must come from the account's own pool and cover no more
+/// than the fund holds or the account owes.
+pub(crate) fn check_insurance_payout(
+    state: &State,
+    pool_id: &str,
+    account_id: &AccountId,
+    amount: Decimal,
+) -> Result<(), String> {
+    let account = state
+        .accounts
+        .get(account_id)
+        .ok_or_else(|| format!("insurance payout to unknown account {account_id}"))?;
+    if account.pool_id != pool_id {
+        return Err(format!(
+            "insurance payout from pool {pool_id} to {account_id}, which is in pool {}",
+            account.pool_id
+        ));
+    }
+    if amount <= Decimal::ZERO {
+        return Err(format!(
+            "insurance payout of {amount} to {account_id} is not positive"
+        ));
+    }
+    if amount > account.bankruptcy_deficit || amount > state.insurance_fund(pool_id) {
+        return Err(format!(
+            "insurance payout of {amount} to {account_id} exceeds its deficit {} or pool {pool_id}'s fund {}",
+            account.bankruptcy_deficit,
+            state.insurance_fund(pool_id)
+        ));
+    }
+    Ok(())
+}
 
-        // Emit liquidation event.
-        let event = Event {
-            sequence: *next_sequence,
-            event_type: EventType::LiquidationFill {
-                account_id: account_id.clone(),
-                market_id: market_id.clone(),
-                quantity: close_qty,
-                price: mark_price,
-            },
-        };
-        *next_sequence += 1;
-        events.push(event);
+/// Under `policy`, charge what the insurance fund left of the account's bankruptcy
+/// deficit to the other accounts of its pool, in proportion to what each could
+/// withdraw (`margin::max_withdrawable` at the policy's `charge_buffer`) and never
+/// more. Under `Socialize` no charged account falls under IM, so nothing new becomes
+/// liquidatable; under `SocializeEquity` one may, and the cascade rescans it.
+/// Charges are rounded down to collateral precision and the account is credited
+/// their sum, so a rounding remainder stays on it. `None` while the fund holds
+/// anything, or when there is nothing to charge or no one to charge it to.
+pub(crate) fn loss_socialization(
+    state: &State,
+    account_id: &AccountId,
+    policy: ResidualDeficit,
+) -> Option<EventType> {
+    let account = state.accounts.get(account_id)?;
+    let pool_id = &account.pool_id;
+    if account.bankruptcy_deficit <= Decimal::ZERO || state.insurance_fund(pool_id) > Decimal::ZERO
+    {
+        return None;
+    }
+    let capacity: Vec<(&AccountId, Decimal)> = state
+        .accounts
+        .values()
+        .filter(|other| other.pool_id == *pool_id && other.account_id != *account_id)
+        .map(|other| {
+            (
+                &other.account_id,
+                margin::max_withdrawable(other, state, policy.charge_buffer()),
+            )
+        })
+        .filter(|(_, available)| *available > Decimal::ZERO)
+        .collect();
+    let total: Decimal = capacity.iter().map(|(_, available)| available).sum();
+    let owed = account.bankruptcy_deficit.min(total);
+    let charges: BTreeMap<AccountId, Decimal> = capacity
+        .into_iter()
+        .map(|(other, available)| {
+            // The share of the total first, so the product never exceeds `owed`.
+            let charge = (owed * (available / total))
+                .round_dp_with_strategy(margin::COLLATERAL_DECIMALS, RoundingStrategy::ToZero)
+                .min(available)
+                .normalize();
+            (other.clone(), charge)
+        })
+        .filter(|(_, charge)| *charge > Decimal::ZERO)
+        .collect();
+    let amount = charges.values().sum::<Decimal>().normalize();
+    (amount > Decimal::ZERO).then(|| EventType::LossSocialized {
+        pool_id: pool_id.clone(),
+        account_id: account_id.clone(),
+        amount,
+        charges,
+    })
+}
+
+/// A `LossSocialized` must be exactly the one `loss_socialization` derives from the
+/// current state under `policy`: charges are set by the pool's balances, not chosen.
+pub(crate) fn check_loss_socialization(
+    state: &State,
+    event_type: &EventType,
+    policy: ResidualDeficit,
+) -> Result<(), String> {
+    let EventType::LossSocialized { account_id, .. } = event_type else {
+        unreachable!("only called for LossSocialized")
+    };
+    match loss_socialization(state, account_id, policy) {
+        Some(expected) if expected == *event_type => Ok(()),
+        Some(EventType::LossSocialized {
+            pool_id,
+            amount,
+            charges,
+            ..
+        }) => Err(format!(
+            "loss socialization for {account_id} differs from pool {pool_id}'s pro rata split: \
+             {amount} charged as {charges:?}"
+        )),
+        _ => Err(format!("no loss of {account_id} to socialize")),
+    }
+}
 
-        // Loop back to recheck — there may be more positions to close.
+/// After the last liquidation step: an account left flat, or still liquidatable with
+/// nothing closable, has its bankruptcy deficit finalized.
+pub(crate) fn finish_liquidation(state: &mut State, account_id: &AccountId) {
+    let exhausted = match state.accounts.get(account_id) {
+        None => return,
+        Some(acct) => acct.positions.is_empty() || margin::is_liquidatable(acct, state),
+    };
+    if exhausted {
+        settle_bankruptcy_deficit(state.accounts.get_mut(account_id).unwrap());
+        suspend_if_bankrupt(state, account_id);
     }
 }
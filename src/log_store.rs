@@ -0,0 +1,135 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::EngineError;
+use crate::events::Event;
+
+/// When a `LogStore` flushes its buffered writes to the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlushPolicy {
+    /// Flush after every appended event.
+    #[default]
+    EveryEvent,
+    /// Flush after every Nth appended event (and whenever events leave memory).
+    EveryN(u64),
+}
+
+impl FlushPolicy {
+    fn should_flush(&self, appended: u64) -> bool {
+        match self {
+            FlushPolicy::EveryEvent => true,
+            FlushPolicy::EveryN(n) => *n <= 1 || appended.is_multiple_of(*n),
+        }
+    }
+}
+
+/// Options for a `LogStore`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogStoreOptions {
+    /// Number of most recent events kept in `Engine::event_log`. At least 1.
+    pub memory_capacity: usize,
+    pub flush: FlushPolicy,
+}
+
+impl Default for LogStoreOptions {
+    fn default() -> Self {
+        Self {
+            memory_capacity: 10_000,
+            flush: FlushPolicy::default(),
+        }
+    }
+}
+
+/// Append-only JSONL spill file behind an engine's in-memory log.
+///
+/// Every event the engine records is appended to the file immediately; the engine
+/// then keeps only the most recent `memory_capacity` events (and their snapshots)
+/// in memory. The file always holds the complete log, in the same format
+/// `jsonl::read_jsonl` reads.
+#[derive(Debug)]
+pub struct LogStore {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    options: LogStoreOptions,
+    appended: u64,
+    /// Events at the start of the file that are no longer in memory.
+    spilled: u64,
+}
+
+impl LogStore {
+    /// Create (or truncate) the spill file at `path`.
+    pub fn create(path: impl AsRef<Path>, options: LogStoreOptions) -> Result<Self, EngineError> {
+        let path = path.as_ref().to_path_buf();
+        let file = File::create(&path)?;
+        Ok(Self::with_file(path, file, options, 0))
+    }
+
+    /// Reopen an existing spill file for appending, e.g. after recovery. The first
+    /// `spilled` events in the file are the ones not held in memory.
+    pub(crate) fn append_to(
+        path: impl AsRef<Path>,
+        options: LogStoreOptions,
+        spilled: u64,
+    ) -> Result<Self, EngineError> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().append(true).open(&path)?;
+        Ok(Self::with_file(path, file, options, spilled))
+    }
+
+    fn with_file(path: PathBuf, file: File, options: LogStoreOptions, spilled: u64) -> Self {
+        Self {
+            path,
+            writer: BufWriter::new(file),
+            options: LogStoreOptions {
+                memory_capacity: options.memory_capacity.max(1),
+                ..options
+            },
+            appended: 0,
+            spilled,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn options(&self) -> LogStoreOptions {
+        self.options
+    }
+
+    /// Number of events that live only in the file.
+    pub fn spilled(&self) -> u64 {
+        self.spilled
+    }
+
+    /// Append one event as a JSONL line, flushing per the policy.
+    pub(crate) fn append(&mut self, event: &Event) -> Result<(), EngineError> {
+        let line = serde_json::to_string(event).map_err(EngineError::Serialize)?;
+        writeln!(self.writer, "{line}")?;
+        self.appended += 1;
+        if self.options.flush.should_flush(self.appended) {
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), EngineError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// How many of `in_memory` events to drop, if any. Memory is trimmed back to
+    /// `memory_capacity` once it reaches twice that, so trimming is amortized.
+    /// Everything dropped is flushed to the file first.
+    pub(crate) fn spill(&mut self, in_memory: usize) -> Result<usize, EngineError> {
+        let capacity = self.options.memory_capacity;
+        if in_memory < capacity * 2 {
+            return Ok(0);
+        }
+        self.flush()?;
+        let excess = in_memory - capacity;
+        self.spilled += excess as u64;
+        Ok(excess)
+    }
+}
@@ -1,158 +1,495 @@
-use cross_margin_engine::engine::Engine;
-use cross_margin_engine::events::EventType;
+use cross_margin_engine::checkpoint::Checkpoint;
+use cross_margin_engine::demo;
+use cross_margin_engine::engine::{
+    Engine, EngineConfig, ProcessOutcome, ReplayOptions, ReplayResult,
+};
+use cross_margin_engine::error::EngineError;
+use cross_margin_engine::events::{Event, EventType};
+use cross_margin_engine::interop::dropcopy::{self, DropCopyFormat};
+use cross_margin_engine::jsonl::{self, RepairPolicy, WriteOptions};
 use cross_margin_engine::margin;
-use cross_margin_engine::snapshot::Snapshot;
-use cross_margin_engine::types::Market;
+use cross_margin_engine::regenerate;
+use cross_margin_engine::report;
+use cross_margin_engine::risk;
+use cross_margin_engine::scenario;
+use cross_margin_engine::snapshot::{self, Snapshot};
+use cross_margin_engine::state::{self, State};
+use cross_margin_engine::types::AccountId;
 
 use rust_decimal_macros::dec;
 
 fn main() {
-    println!("=== Cross-Margin Perpetual Risk Engine Demo ===\n");
-
-    let mut engine = Engine::new();
-
-    // Configure markets
-    engine.add_market(Market::new("BTC-PERP".into(), dec!(0.05), dec!(0.03)));
-    engine.add_market(Market::new("ETH-PERP".into(), dec!(0.10), dec!(0.05)));
-
-    // ─── Scenario 1: Healthy portfolio becomes liquidatable ─────────────────
-
-    println!("--- Scenario 1: Liquidation after adverse price move ---\n");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("account") => run_account(&args[1..]),
+        Some("attribution") => run_attribution(&args[1..]),
+        Some("dropcopy") => run_dropcopy(&args[1..]),
+        Some("fsck") => run_fsck(&args[1..]),
+        Some("funding-report") => run_funding_report(&args[1..]),
+        Some("run-scenario") => run_scenario(&args[1..]),
+        Some("solvency") => run_solvency(&args[1..]),
+        Some("statement") => run_statement(&args[1..]),
+        Some("validate-checkpoint") => run_validate_checkpoint(&args[1..]),
+        Some("var") => run_var(&args[1..]),
+        Some("verify") => run_verify(&args[1..]),
+        _ => run_demo(),
+    }
+}
 
-    engine.process(EventType::Deposit {
-        account_id: "alice".into(),
-        amount: dec!(100000),
+/// `account <log.jsonl> <account_id> [--csv] [field ...]`: replay a log under the demo
+/// markets and print the account's time series, JSON unless `--csv` is given. Fields
+/// default to equity, collateral, im, mm and margin_ratio.
+fn run_account(args: &[String]) {
+    let usage = "usage: cross-margin-engine account <log.jsonl> <account_id> [--csv] [field ...]";
+    let [path, account_id, rest @ ..] = args else {
+        eprintln!("{usage}");
+        std::process::exit(2);
+    };
+    let account_id: AccountId = account_id.parse().unwrap_or_else(|e| {
+        eprintln!("{e}\n{usage}");
+        std::process::exit(2);
     });
-    print_account(&engine, "alice", "Alice deposits 100,000");
+    let csv = rest.iter().any(|a| a == "--csv");
+    let mut fields: Vec<snapshot::Field> = Vec::new();
+    for name in rest.iter().filter(|a| *a != "--csv") {
+        match name.parse() {
+            Ok(field) => fields.push(field),
+            Err(e) => {
+                eprintln!("{e}\n{usage}");
+                std::process::exit(2);
+            }
+        }
+    }
+    if fields.is_empty() {
+        fields = ["equity", "collateral", "im", "mm", "margin_ratio"]
+            .iter()
+            .map(|name| name.parse().unwrap())
+            .collect();
+    }
 
-    engine.process(EventType::MarkPriceUpdate {
-        market_id: "BTC-PERP".into(),
-        price: dec!(50000),
+    let log = jsonl::read_jsonl(path).unwrap_or_else(|e| {
+        eprintln!("failed to read {path}: {e}");
+        std::process::exit(1);
     });
+    let (_, snapshots) = Engine::replay(&log, demo::markets());
 
-    engine.process(EventType::TradeFill {
-        account_id: "alice".into(),
-        market_id: "BTC-PERP".into(),
-        quantity: dec!(10),
-        price: dec!(50000),
-    });
-    print_account(&engine, "alice", "Alice longs 10 BTC-PERP @ 50,000");
+    let series = snapshot::series(&snapshots, &account_id, &fields);
+    if csv {
+        print!("{}", series.to_csv());
+    } else {
+        println!("{}", series.to_json());
+    }
+}
 
-    engine.process(EventType::MarkPriceUpdate {
-        market_id: "BTC-PERP".into(),
-        price: dec!(42000),
+/// `attribution <log.jsonl> <account_id> <from_seq> <to_seq>`: replay a log under the
+/// demo markets and print the account's PnL attribution over the window as JSON.
+fn run_attribution(args: &[String]) {
+    let usage =
+        "usage: cross-margin-engine attribution <log.jsonl> <account_id> <from_seq> <to_seq>";
+    let [path, account_id, from_seq, to_seq] = args else {
+        eprintln!("{usage}");
+        std::process::exit(2);
+    };
+    let (Ok(from_seq), Ok(to_seq)) = (from_seq.parse::<u64>(), to_seq.parse::<u64>()) else {
+        eprintln!("{usage}");
+        std::process::exit(2);
+    };
+    let account_id: AccountId = account_id.parse().unwrap_or_else(|e| {
+        eprintln!("{e}\n{usage}");
+        std::process::exit(2);
     });
-    print_account(&engine, "alice", "BTC drops to 42,000 — still healthy");
 
-    engine.process(EventType::MarkPriceUpdate {
-        market_id: "BTC-PERP".into(),
-        price: dec!(41000),
+    let log = jsonl::read_jsonl(path).unwrap_or_else(|e| {
+        eprintln!("failed to read {path}: {e}");
+        std::process::exit(1);
     });
-    print_account(&engine, "alice", "BTC drops to 41,000 — LIQUIDATED");
+    let (_, snapshots) = Engine::replay(&log, demo::markets());
 
-    // ─── Scenario 2: Trade rejected on margin ──────────────────────────────
+    let report = report::attribution(&log, &snapshots, &account_id, from_seq, to_seq);
+    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+}
 
-    println!("--- Scenario 2: Trade rejected due to insufficient margin ---\n");
+/// `dropcopy <log.jsonl> [--json]`: print the log's drop-copy records, pipe-delimited
+/// unless `--json` is given (see `interop::dropcopy`).
+fn run_dropcopy(args: &[String]) {
+    let usage = "usage: cross-margin-engine dropcopy <log.jsonl> [--json]";
+    let (path, format) = match args {
+        [path] => (path, DropCopyFormat::Pipe),
+        [path, flag] if flag == "--json" => (path, DropCopyFormat::Json),
+        _ => {
+            eprintln!("{usage}");
+            std::process::exit(2);
+        }
+    };
 
-    engine.process(EventType::Deposit {
-        account_id: "bob".into(),
-        amount: dec!(10000),
+    let log = jsonl::read_jsonl(path).unwrap_or_else(|e| {
+        eprintln!("failed to read {path}: {e}");
+        std::process::exit(1);
     });
-    print_account(&engine, "bob", "Bob deposits 10,000");
+    for record in dropcopy::convert_log(&log) {
+        println!("{}", record.render(format));
+    }
+}
 
-    engine.process(EventType::MarkPriceUpdate {
-        market_id: "ETH-PERP".into(),
-        price: dec!(3000),
+/// `statement <log.jsonl> <account_id>`: print the account's collateral ledger with
+/// its principal and trading balance, replaying the log under the demo markets, and
+/// exit 1 if either balance does not reconcile.
+fn run_statement(args: &[String]) {
+    let [path, account_id] = args else {
+        eprintln!("usage: cross-margin-engine statement <log.jsonl> <account_id>");
+        std::process::exit(2);
+    };
+
+    let log = jsonl::read_jsonl(path).unwrap_or_else(|e| {
+        eprintln!("failed to read {path}: {e}");
+        std::process::exit(1);
     });
 
-    engine.process(EventType::TradeFill {
-        account_id: "bob".into(),
-        market_id: "ETH-PERP".into(),
-        quantity: dec!(20),
-        price: dec!(3000),
-    });
-    print_account(&engine, "bob", "Bob longs 20 ETH-PERP @ 3,000 — accepted");
+    println!(
+        "{:>8}  {:<15} {:<10} {:>16} {:>16} {:>16} {:>16}",
+        "seq", "kind", "market", "amount", "balance", "principal", "trading"
+    );
+    let lines = report::statement(&log, account_id, demo::markets());
+    for line in &lines {
+        println!(
+            "{:>8}  {:<15} {:<10} {:>16} {:>16} {:>16} {:>16}",
+            line.sequence,
+            format!("{:?}", line.kind),
+            line.market_id.as_deref().unwrap_or("-"),
+            line.amount.normalize(),
+            line.balance_after.normalize(),
+            line.principal_after.normalize(),
+            line.trading_balance_after.normalize()
+        );
+    }
+    let reconciliation = report::reconcile(&lines);
+    if !reconciliation.reconciled {
+        eprintln!("balances do not reconcile: {reconciliation:?}");
+        std::process::exit(1);
+    }
+}
 
-    engine.process(EventType::TradeFill {
-        account_id: "bob".into(),
-        market_id: "ETH-PERP".into(),
-        quantity: dec!(20),
-        price: dec!(3000),
-    });
-    print_account(&engine, "bob", "Bob tries 20 more ETH-PERP — REJECTED");
-
-    // Show the rejection reason
-    if let Some(event) = engine.event_log.iter().rev().find(|e| {
-        matches!(e.event_type, EventType::TradeRejected { .. })
-    }) {
-        if let EventType::TradeRejected { reason, .. } = &event.event_type {
-            println!("    Rejection reason: {reason}\n");
+/// `fsck <log.jsonl> [--repair <out.jsonl>] [--drop-corrupt]`: list the log's defects
+/// as JSON, exiting 1 if there are any. With `--repair`, write the cleaned log instead
+/// and exit 1 only if the policy refuses a defect.
+fn run_fsck(args: &[String]) {
+    let usage =
+        "usage: cross-margin-engine fsck <log.jsonl> [--repair <out.jsonl>] [--drop-corrupt]";
+    let [path, rest @ ..] = args else {
+        eprintln!("{usage}");
+        std::process::exit(2);
+    };
+    let mut output = None;
+    let mut policy = RepairPolicy::Strict;
+    let mut rest = rest.iter();
+    while let Some(arg) = rest.next() {
+        match (arg.as_str(), rest.clone().next()) {
+            ("--repair", Some(out)) => {
+                output = Some(out);
+                rest.next();
+            }
+            ("--drop-corrupt", _) => policy = RepairPolicy::DropCorrupt,
+            _ => {
+                eprintln!("{usage}");
+                std::process::exit(2);
+            }
         }
     }
 
-    // ─── Scenario 3: Cross-margin portfolio-level rejection ────────────────
+    let Some(output) = output else {
+        let (events, defects) = jsonl::read_jsonl_recover(path).unwrap_or_else(|e| {
+            eprintln!("failed to read {path}: {e}");
+            std::process::exit(1);
+        });
+        let report = serde_json::json!({ "events": events.len(), "defects": defects });
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        if !defects.is_empty() {
+            std::process::exit(1);
+        }
+        return;
+    };
+    match jsonl::repair(path, output, policy) {
+        Ok(report) => println!("{}", serde_json::to_string_pretty(&report).unwrap()),
+        Err(EngineError::CorruptLog { defects }) => {
+            let refused: Vec<_> = defects
+                .iter()
+                .filter(|d| !d.is_repaired_by(policy))
+                .collect();
+            let report = serde_json::json!({ "defects": defects, "refused": refused });
+            println!("{}", serde_json::to_string_pretty(&report).unwrap());
+            eprintln!(
+                "not repaired: {} defect(s) need --drop-corrupt",
+                refused.len()
+            );
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("failed to repair {path}: {e}");
+            std::process::exit(1);
+        }
+    }
+}
 
-    println!("--- Scenario 3: Cross-margin portfolio constraint ---\n");
+/// `funding-report <log.jsonl>`: print every accepted funding event as CSV, replaying
+/// the log under the demo markets.
+fn run_funding_report(args: &[String]) {
+    let [path] = args else {
+        eprintln!("usage: cross-margin-engine funding-report <log.jsonl>");
+        std::process::exit(2);
+    };
+
+    let log = jsonl::read_jsonl(path).unwrap_or_else(|e| {
+        eprintln!("failed to read {path}: {e}");
+        std::process::exit(1);
+    });
 
-    engine.process(EventType::Deposit {
-        account_id: "charlie".into(),
-        amount: dec!(20000),
+    println!(
+        "sequence,market,old_index,new_index,mark_price,implied_rate,\
+         paid_by_longs,received_by_shorts,residual,balanced"
+    );
+    for period in report::funding_history(&log, demo::markets()) {
+        println!(
+            "{},{},{},{},{},{},{},{},{},{}",
+            period.sequence,
+            period.market_id,
+            period.old_index.normalize(),
+            period.new_index.normalize(),
+            period.mark_price.normalize(),
+            period
+                .implied_rate
+                .map(|r| r.normalize().to_string())
+                .unwrap_or_default(),
+            period.paid_by_longs.normalize(),
+            period.received_by_shorts.normalize(),
+            period.residual.normalize(),
+            period.balanced
+        );
+    }
+}
+
+/// `solvency <log.jsonl>`: replay a log under the demo markets (and the config in its
+/// `ConfigMarker`, if any) and print the solvency report as JSON, for the whole book
+/// and per collateral pool.
+fn run_solvency(args: &[String]) {
+    let [path] = args else {
+        eprintln!("usage: cross-margin-engine solvency <log.jsonl>");
+        std::process::exit(2);
+    };
+
+    let log = jsonl::read_jsonl(path).unwrap_or_else(|e| {
+        eprintln!("failed to read {path}: {e}");
+        std::process::exit(1);
+    });
+    let result = replay_under_marker(&log);
+
+    let report = state::solvency(&result.state, &result.metrics);
+    let pools = state::solvency_by_pool(&result.state, &result.metrics);
+    let output = serde_json::json!({ "total": report, "pools": pools });
+    println!("{}", serde_json::to_string_pretty(&output).unwrap());
+    if !report.is_balanced() || !pools.values().all(|p| p.is_balanced()) {
+        std::process::exit(1);
+    }
+}
+
+/// `var <log.jsonl> <account_id> [window]`: replay a log as `solvency` does and print
+/// the account's historical VaR at the 95th and 99th percentiles as JSON, over the
+/// last `window` mark moves per market (default 250).
+fn run_var(args: &[String]) {
+    let usage = "usage: cross-margin-engine var <log.jsonl> <account_id> [window]";
+    let (path, account_id, window) = match args {
+        [path, account_id] => (path, account_id, Ok(250)),
+        [path, account_id, window] => (path, account_id, window.parse::<usize>()),
+        _ => {
+            eprintln!("{usage}");
+            std::process::exit(2);
+        }
+    };
+    let Ok(window) = window else {
+        eprintln!("{usage}");
+        std::process::exit(2);
+    };
+    let account_id: AccountId = account_id.parse().unwrap_or_else(|e| {
+        eprintln!("{e}\n{usage}");
+        std::process::exit(2);
     });
-    print_account(&engine, "charlie", "Charlie deposits 20,000");
 
-    // BTC-PERP mark is still 41,000 from scenario 1 — set it to 50,000 for clean math
-    engine.process(EventType::MarkPriceUpdate {
-        market_id: "BTC-PERP".into(),
-        price: dec!(50000),
+    let log = jsonl::read_jsonl(path).unwrap_or_else(|e| {
+        eprintln!("failed to read {path}: {e}");
+        std::process::exit(1);
     });
+    let result = replay_under_marker(&log);
+    if !result.state.accounts.contains_key(&account_id) {
+        eprintln!("no account {account_id} in {path}");
+        std::process::exit(1);
+    }
+
+    let var =
+        |percentile| risk::historical_var(&result.state, &log, &account_id, window, percentile);
+    let output = serde_json::json!({ "var_95": var(dec!(0.95)), "var_99": var(dec!(0.99)) });
+    println!("{}", serde_json::to_string_pretty(&output).unwrap());
+}
 
-    engine.process(EventType::TradeFill {
-        account_id: "charlie".into(),
-        market_id: "BTC-PERP".into(),
-        quantity: dec!(5),
-        price: dec!(50000),
+/// `verify <log.jsonl> <snapshots.json>`: regenerate the log from its external events
+/// under the demo markets and its config marker, and check it is the file byte for
+/// byte; then replay it as `solvency` does and check every stored snapshot against
+/// the replay's at the same sequence. The file may be a JSON array of snapshots or
+/// `snapshot::compress` output.
+fn run_verify(args: &[String]) {
+    let [path, snapshots_path] = args else {
+        eprintln!("usage: cross-margin-engine verify <log.jsonl> <snapshots.json>");
+        std::process::exit(2);
+    };
+
+    let log = jsonl::read_jsonl(path).unwrap_or_else(|e| {
+        eprintln!("failed to read {path}: {e}");
+        std::process::exit(1);
     });
-    print_account(&engine, "charlie", "Charlie longs 5 BTC-PERP @ 50,000 (IM: 12,500)");
-
-    // This trade alone would need IM of 9,000 — but combined with BTC position,
-    // total IM would be 21,500 which exceeds equity of 20,000
-    engine.process(EventType::TradeFill {
-        account_id: "charlie".into(),
-        market_id: "ETH-PERP".into(),
-        quantity: dec!(30),
-        price: dec!(3000),
+    let stored = snapshot::read_snapshots(snapshots_path).unwrap_or_else(|e| {
+        eprintln!("failed to read {snapshots_path}: {e}");
+        std::process::exit(1);
     });
-    print_account(&engine, "charlie", "Charlie tries 30 ETH-PERP — REJECTED (combined IM too high)");
-
-    if let Some(event) = engine.event_log.iter().rev().find(|e| {
-        matches!(e.event_type, EventType::TradeRejected { .. })
-    }) {
-        if let EventType::TradeRejected { reason, .. } = &event.event_type {
-            println!("    Rejection reason: {reason}");
-            println!("    (Either position alone would pass — it's the cross-margin total that fails)\n");
+    let regenerated = Engine::regenerate(&log, demo::markets(), marker_config(&log))
+        .expect("demo markets are valid");
+    if let Some(divergence) = regenerate::diff_logs(&log, &regenerated) {
+        eprintln!("{path} does not regenerate from its external events: {divergence}");
+        std::process::exit(1);
+    }
+    let result = replay_under_marker(&log);
+    for snapshot in &stored {
+        let seq = snapshot.after_sequence;
+        match result.snapshots.iter().find(|s| s.after_sequence == seq) {
+            Some(replayed) if replayed == snapshot => {}
+            Some(_) => {
+                eprintln!("snapshot after seq {seq} differs from the replay");
+                std::process::exit(1);
+            }
+            None => {
+                eprintln!("snapshot after seq {seq} has no counterpart in the replay");
+                std::process::exit(1);
+            }
         }
     }
+    println!(
+        "{path} regenerates byte for byte and {} snapshots match its replay",
+        stored.len()
+    );
+}
 
-    // Smaller ETH position that fits within remaining margin
-    engine.process(EventType::TradeFill {
-        account_id: "charlie".into(),
-        market_id: "ETH-PERP".into(),
-        quantity: dec!(15),
-        price: dec!(3000),
+/// `validate-checkpoint <log.jsonl> <state.json> <after_sequence>`: check that a state
+/// file is the log's state after `after_sequence` under the demo markets, and that
+/// the rest of the log replays from it. Exits 1 on the first divergence.
+fn run_validate_checkpoint(args: &[String]) {
+    let usage =
+        "usage: cross-margin-engine validate-checkpoint <log.jsonl> <state.json> <after_sequence>";
+    let [path, state_path, after_sequence] = args else {
+        eprintln!("{usage}");
+        std::process::exit(2);
+    };
+    let Ok(after_sequence) = after_sequence.parse() else {
+        eprintln!("{usage}");
+        std::process::exit(2);
+    };
+
+    let log = jsonl::read_jsonl(path).unwrap_or_else(|e| {
+        eprintln!("failed to read {path}: {e}");
+        std::process::exit(1);
     });
-    print_account(&engine, "charlie", "Charlie longs 15 ETH-PERP — ACCEPTED (combined IM fits)");
+    let state = std::fs::read_to_string(state_path)
+        .map_err(|e| e.to_string())
+        .and_then(|json| State::from_json(&json).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| {
+            eprintln!("failed to read {state_path}: {e}");
+            std::process::exit(1);
+        });
+    let checkpoint = Checkpoint {
+        after_sequence,
+        state,
+    };
+    let report =
+        Engine::validate_checkpoint(&checkpoint, &log, demo::markets()).unwrap_or_else(|e| {
+            eprintln!("cannot validate against {path}: {e}");
+            std::process::exit(1);
+        });
+    println!(
+        "replayed {} events to seq {after_sequence} in {:?}, compared {} checksums in {:?}",
+        report.prefix_events, report.prefix_replay, report.checksums_compared, report.comparison
+    );
+    println!(
+        "checkpoint {} / replay {}",
+        report.checkpoint_checksum, report.replayed_checksum
+    );
+    if let Some(divergence) = &report.divergence {
+        eprintln!("checkpoint diverges: {divergence}");
+        std::process::exit(1);
+    }
+    println!(
+        "replayed {} events from the checkpoint to seq {} in {:?}: the checkpoint is valid",
+        report.suffix_events, report.last_sequence, report.suffix_replay
+    );
+}
 
-    // ─── Scenario 4: Funding payment ───────────────────────────────────────
+/// The config in a log's `ConfigMarker`, or the default without one.
+fn marker_config(log: &[Event]) -> EngineConfig {
+    match log.first().map(|e| &e.event_type) {
+        Some(EventType::ConfigMarker { config, .. }) => config.clone(),
+        _ => EngineConfig::default(),
+    }
+}
+
+/// Replay a log under the demo markets and the config in its `ConfigMarker`, if any.
+fn replay_under_marker(log: &[Event]) -> ReplayResult {
+    let options = ReplayOptions {
+        config: marker_config(log),
+        ..ReplayOptions::default()
+    };
+    Engine::replay_with(options, log, demo::markets())
+}
+
+/// `run-scenario <file.toml>`: run a scenario and report its expectations.
+fn run_scenario(args: &[String]) {
+    let [path] = args else {
+        eprintln!("usage: cross-margin-engine run-scenario <file.toml>");
+        std::process::exit(2);
+    };
+
+    let outcome = scenario::load(path).and_then(|s| scenario::run(&s).map(|run| (s, run)));
+    match outcome {
+        Ok((s, run)) => println!(
+            "PASS {path}: {} ({} steps, {} expectations, {} events)",
+            s.name,
+            s.steps.len(),
+            run.expectations_checked,
+            run.engine.event_log.len()
+        ),
+        Err(e) => {
+            eprintln!("FAIL {path}: {e}");
+            std::process::exit(1);
+        }
+    }
+}
 
-    println!("--- Scenario 4: Funding payment applied ---\n");
+fn run_demo() {
+    println!("=== Cross-Margin Perpetual Risk Engine Demo ===\n");
 
-    print_account(&engine, "bob", "Bob before funding (long ETH)");
+    let mut engine = Engine::new();
 
-    engine.process(EventType::FundingUpdate {
-        market_id: "ETH-PERP".into(),
-        new_cumulative_index: dec!(1.50),
-    });
-    print_account(&engine, "bob", "After funding — Bob (long) pays");
+    // Configure markets
+    for market in demo::markets() {
+        engine.add_market(market).expect("demo markets are valid");
+    }
+
+    for section in demo::sections() {
+        println!("--- {} ---\n", section.title);
+        for step in section.steps {
+            let outcome = engine.process(step.event_type);
+            if let Some((account_id, caption)) = step.shows {
+                print_account(&engine, account_id, caption);
+            }
+            if let ProcessOutcome::Rejected { reason, .. } = outcome {
+                println!("    Rejection reason: {reason}\n");
+            }
+        }
+    }
 
     // ─── Replay Determinism Verification ───────────────────────────────────
 
@@ -162,12 +499,7 @@ fn main() {
     let original_snapshots = engine.snapshots.clone();
     let original_state = engine.state.clone();
 
-    let markets = vec![
-        Market::new("BTC-PERP".into(), dec!(0.05), dec!(0.03)),
-        Market::new("ETH-PERP".into(), dec!(0.10), dec!(0.05)),
-    ];
-
-    let (replay_state, replay_snapshots) = Engine::replay(&original_log, markets);
+    let (replay_state, replay_snapshots) = Engine::replay(&original_log, demo::markets());
 
     let states_match = original_state == replay_state;
     println!(
@@ -179,7 +511,46 @@ fn main() {
     println!(
         "  Path determinism ({} snapshots): {}",
         original_snapshots.len(),
-        if snapshots_match { "✓ PASS" } else { "✗ FAIL" }
+        if snapshots_match {
+            "✓ PASS"
+        } else {
+            "✗ FAIL"
+        }
+    );
+
+    // Resubmitting only what was submitted writes the same log again, byte for byte:
+    // the rejection, the liquidation and the funding payments are produced anew.
+    let regenerated = Engine::regenerate(&original_log, demo::markets(), engine.config().clone())
+        .expect("demo markets are valid");
+    let regenerates = regenerate::diff_logs(&original_log, &regenerated).is_none();
+    println!(
+        "  Log regeneration ({} events): {}",
+        regenerated.len(),
+        if regenerates { "✓ PASS" } else { "✗ FAIL" }
+    );
+
+    // Alice's equity curve: at the sequence of her liquidation fill, equity is what is
+    // left of 100,000 after 10 BTC fell 9,000.
+    let equity = snapshot::Field::Equity;
+    let alice_equity = snapshot::series(
+        &original_snapshots,
+        &"alice".parse().unwrap(),
+        std::slice::from_ref(&equity),
+    );
+    let liquidation_seq = original_log
+        .iter()
+        .find(|e| matches!(&e.event_type, EventType::LiquidationFill { account_id, .. } if account_id == "alice"))
+        .map(|e| e.sequence);
+    let at_liquidation = liquidation_seq.and_then(|seq| alice_equity.value_at(&equity, seq));
+    println!(
+        "  Alice equity series ({} points, {} at liquidation): {}",
+        alice_equity.points.len(),
+        at_liquidation.unwrap_or_default().normalize(),
+        if at_liquidation == Some(dec!(10000)) {
+            "✓ PASS"
+        } else {
+            "✗ FAIL"
+        }
     );
 
     // ─── Event Log ─────────────────────────────────────────────────────────
@@ -193,13 +564,25 @@ fn main() {
     // Write event log to file
     let log_path = "scenarios/demo.jsonl";
     std::fs::create_dir_all("scenarios").ok();
-    let log_content: String = original_log
-        .iter()
-        .map(|e| serde_json::to_string(e).unwrap())
-        .collect::<Vec<_>>()
-        .join("\n");
-    std::fs::write(log_path, log_content).expect("Failed to write event log");
+    jsonl::write_jsonl(log_path, &original_log, WriteOptions::default())
+        .expect("Failed to write event log");
     println!("\n  Event log written to {log_path}");
+
+    // Its snapshots are not written: scenarios/demo.snapshots.json is the tracked
+    // golden copy, which `verify` checks this log against.
+    let compressed = serde_json::to_string(&snapshot::compress(&original_snapshots)).unwrap();
+    let plain = serde_json::to_string(&original_snapshots).unwrap().len();
+    println!(
+        "  {} snapshots ({} bytes delta-compressed, {plain} uncompressed); check them with `verify {log_path} scenarios/demo.snapshots.json`",
+        original_snapshots.len(),
+        compressed.len()
+    );
+
+    // tests/demo.rs asserts these and more; a failure here still fails the run.
+    if !(states_match && snapshots_match && regenerates && at_liquidation == Some(dec!(10000))) {
+        eprintln!("\nDemo verification failed");
+        std::process::exit(1);
+    }
 }
 
 fn print_account(engine: &Engine, account_id: &str, label: &str) {
@@ -211,16 +594,22 @@ fn print_account(engine: &Engine, account_id: &str, label: &str) {
         let upnl = margin::total_unrealized_pnl(account, &engine.state);
         let liq = margin::is_liquidatable(account, &engine.state);
 
-        println!("    Collateral:   {}", account.collateral);
+        println!("    Collateral:   {}", account.collateral());
         println!("    Unrealized:   {upnl}");
         println!("    Equity:       {eq}");
         println!("    IM Required:  {im}");
         println!("    MM Required:  {mm}");
         println!("    Liquidatable: {liq}");
+        for (key, value) in &account.metadata {
+            println!("    Metadata {key}: {value}");
+        }
         for (mid, pos) in &account.positions {
             println!(
-                "    Position {mid}: qty={} cost_basis={}",
-                pos.quantity, pos.cost_basis
+                "    Position {mid}: qty={} cost_basis={} entry={} funding_paid={}",
+                pos.quantity,
+                pos.cost_basis,
+                pos.entry_price().unwrap_or_default().normalize(),
+                pos.funding_paid
             );
         }
     } else {
@@ -230,22 +619,20 @@ fn print_account(engine: &Engine, account_id: &str, label: &str) {
 }
 
 fn compare_snapshots(a: &[Snapshot], b: &[Snapshot]) -> bool {
-    if a.len() != b.len() {
+    // Equality covers market state too, but only if it was captured.
+    let markets = demo::markets().len();
+    if let Some(s) = a.iter().find(|s| s.markets.len() != markets) {
         println!(
-            "    Snapshot count mismatch: {} vs {}",
-            a.len(),
-            b.len()
+            "    Snapshot after seq {} is missing markets",
+            s.after_sequence
         );
         return false;
     }
-    for (i, (sa, sb)) in a.iter().zip(b.iter()).enumerate() {
-        if sa != sb {
-            println!(
-                "    Snapshot mismatch at index {i} (after seq {})",
-                sa.after_sequence
-            );
-            return false;
+    match snapshot::first_divergence(a, b) {
+        None => true,
+        Some(seq) => {
+            println!("    Snapshot mismatch after seq {seq}");
+            false
         }
     }
-    true
 }
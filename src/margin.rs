@@ -1,7 +1,12 @@
-use rust_decimal::Decimal;
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
+use crate::decimal_str;
 use crate::state::State;
-use crate::types::Account;
+use crate::types::{
+    Account, AccountId, FundingMode, FundingRateFormula, Market, MarketId, Position, RestingOrder,
+};
 
 /// Unrealized PnL for a single position.
 pub fn position_unrealized_pnl(
@@ -12,11 +17,167 @@ pub fn position_unrealized_pnl(
     mark_price * quantity - cost_basis
 }
 
-/// Notional value of a single position.
+/// Notional value of a single position. Always non-negative: at a negative mark a
+/// long still carries exposure equal to the absolute value of the position.
 pub fn position_notional(quantity: Decimal, mark_price: Decimal) -> Decimal {
     (mark_price * quantity).abs()
 }
 
+/// Concentration add-on for a single position: `add_on_fraction * (notional - threshold)`
+/// for the portion of notional above the market's threshold, zero otherwise.
+pub fn position_concentration_add_on(quantity: Decimal, market: &Market) -> Decimal {
+    if market.concentration_add_on_fraction.is_zero() {
+        return Decimal::ZERO;
+    }
+    let excess =
+        position_notional(quantity, market.mark_price) - market.concentration_threshold_notional;
+    if excess > Decimal::ZERO {
+        excess * market.concentration_add_on_fraction
+    } else {
+        Decimal::ZERO
+    }
+}
+
+/// The IM fraction of a position in `market` held at `leverage`: `1 / leverage` when
+/// the account selected one (`Account::leverage`), else the market's own fraction.
+pub fn initial_margin_fraction(market: &Market, leverage: Option<Decimal>) -> Decimal {
+    match leverage {
+        Some(leverage) => Decimal::ONE / leverage,
+        None => market.initial_margin_fraction,
+    }
+}
+
+/// The leverage a position in `market` held at `leverage` is margined at: the
+/// selected one, else the inverse of the market's IM fraction. `None` for a market
+/// that charges no IM.
+pub fn effective_leverage(market: &Market, leverage: Option<Decimal>) -> Option<Decimal> {
+    leverage.or_else(|| Decimal::ONE.checked_div(market.initial_margin_fraction))
+}
+
+/// Initial margin for a single position at the market's own fraction, including
+/// any concentration add-on, scaled by `stale_im_multiplier` while the market's mark
+/// is stale.
+pub fn position_initial_margin(quantity: Decimal, market: &Market) -> Decimal {
+    position_initial_margin_with(quantity, market, None)
+}
+
+/// `position_initial_margin` for a position held at `leverage` (see
+/// `initial_margin_fraction`). Add-ons and the stale multiplier apply as before, and
+/// the result is raised to the market's `min_initial_margin`.
+pub fn position_initial_margin_with(
+    quantity: Decimal,
+    market: &Market,
+    leverage: Option<Decimal>,
+) -> Decimal {
+    floored(
+        position_initial_margin_unfloored(quantity, market, leverage),
+        market.min_initial_margin,
+    )
+}
+
+/// `position_initial_margin_with` before the market's `min_initial_margin`.
+fn position_initial_margin_unfloored(
+    quantity: Decimal,
+    market: &Market,
+    leverage: Option<Decimal>,
+) -> Decimal {
+    let notional = position_notional(quantity, market.mark_price);
+    let im = notional * initial_margin_fraction(market, leverage)
+        + position_concentration_add_on(quantity, market);
+    if market.stale {
+        im * market.stale_im_multiplier
+    } else {
+        im
+    }
+}
+
+/// Maintenance margin for a single position: notional times the market's fraction,
+/// raised to its `min_maintenance_margin`.
+pub fn position_maintenance_margin(quantity: Decimal, market: &Market) -> Decimal {
+    floored(
+        position_maintenance_margin_unfloored(quantity, market),
+        market.min_maintenance_margin,
+    )
+}
+
+fn position_maintenance_margin_unfloored(quantity: Decimal, market: &Market) -> Decimal {
+    position_notional(quantity, market.mark_price) * market.maintenance_margin_fraction
+}
+
+/// `margin` raised to `floor`, if there is one.
+fn floored(margin: Decimal, floor: Option<Decimal>) -> Decimal {
+    floor.map_or(margin, |floor| margin.max(floor))
+}
+
+/// Cumulative funding index increment implied by a per-interval funding rate.
+///
+/// `RateTimesMark` scales by the absolute mark so that a positive rate always
+/// means longs pay, even when a market trades at a negative price.
+pub fn funding_index_increment(market: &Market, rate: Decimal) -> Decimal {
+    match market.funding_rate_formula {
+        FundingRateFormula::RateTimesMark => rate * market.mark_price.abs(),
+        FundingRateFormula::RateIsIndexDelta => rate,
+    }
+}
+
+/// Funding owed by a position when the cumulative index moves from `last_index` to
+/// `new_index`, as a signed collateral change (positive = received).
+///
+/// A rising index charges longs and pays shorts; a falling index does the opposite.
+/// This is the single definition of the sign convention — every settlement path
+/// goes through it.
+pub fn funding_delta(quantity: Decimal, last_index: Decimal, new_index: Decimal) -> Decimal {
+    (last_index - new_index) * quantity
+}
+
+/// Decimal places at which cash movements are committed to collateral.
+pub const COLLATERAL_DECIMALS: u32 = 8;
+
+/// Round a set of raw per-account funding deltas to `COLLATERAL_DECIMALS` while
+/// conserving their total.
+///
+/// Each delta is floored (never overstating a receipt or understating a payment),
+/// then the residual between the rounded total of the raw deltas and the sum of the
+/// floored deltas is assigned to the largest absolute payer (ties broken by
+/// account_id), or to the largest receiver if nobody pays. The returned amounts
+/// therefore sum exactly to the rounded raw total — zero on a balanced book.
+pub fn allocate_funding(raw: &[(AccountId, Decimal)]) -> Vec<(AccountId, Decimal)> {
+    let mut settled: Vec<(AccountId, Decimal)> = raw
+        .iter()
+        .map(|(id, d)| {
+            (
+                id.clone(),
+                d.round_dp_with_strategy(COLLATERAL_DECIMALS, RoundingStrategy::ToNegativeInfinity),
+            )
+        })
+        .collect();
+
+    let target = raw
+        .iter()
+        .map(|(_, d)| *d)
+        .sum::<Decimal>()
+        .round_dp(COLLATERAL_DECIMALS);
+    let residual = target - settled.iter().map(|(_, d)| *d).sum::<Decimal>();
+
+    if !residual.is_zero() {
+        // Payers first (most negative), then receivers (largest); account_id breaks ties.
+        let absorber = settled
+            .iter()
+            .enumerate()
+            .min_by(|(_, (a_id, a)), (_, (b_id, b))| {
+                let a_key = (*a >= Decimal::ZERO, -a.abs());
+                let b_key = (*b >= Decimal::ZERO, -b.abs());
+                a_key.cmp(&b_key).then_with(|| a_id.cmp(b_id))
+            })
+            .map(|(i, _)| i);
+        if let Some(i) = absorber {
+            settled[i].1 += residual;
+        }
+    }
+
+    settled
+}
+
 /// Total unrealized PnL across all positions in an account.
 ///
 /// Note: Markets are configured out-of-band. If a market is missing, we treat it as
@@ -37,14 +198,84 @@ pub fn total_unrealized_pnl(account: &Account, state: &State) -> Decimal {
         .sum()
 }
 
-/// Portfolio equity = collateral + total unrealized PnL.
+/// Gross notional across all positions in an account.
+pub fn total_notional(account: &Account, state: &State) -> Decimal {
+    account
+        .positions
+        .values()
+        .map(|pos| match state.markets.get(&pos.market_id) {
+            Some(market) => position_notional(pos.quantity, market.mark_price),
+            None => Decimal::ZERO,
+        })
+        .sum()
+}
+
+/// Combined gross notional of a group's members (zero for an unknown group).
+pub fn group_notional(group_id: &str, state: &State) -> Decimal {
+    let Some(group) = state.groups.get(group_id) else {
+        return Decimal::ZERO;
+    };
+    group
+        .members
+        .iter()
+        .filter_map(|account_id| state.accounts.get(account_id))
+        .map(|account| total_notional(account, state))
+        .sum()
+}
+
+/// Leverage = gross notional / equity. `None` when equity is zero or negative
+/// and there is exposure, or too small for the ratio to fit in a `Decimal`
+/// (leverage is unbounded).
+pub fn leverage(total_notional: Decimal, equity: Decimal) -> Option<Decimal> {
+    if total_notional.is_zero() {
+        Some(Decimal::ZERO)
+    } else if equity > Decimal::ZERO {
+        total_notional.checked_div(equity)
+    } else {
+        None
+    }
+}
+
+/// Margin usage = maintenance margin / equity, the figure `RiskAlertLadder` levels
+/// are set on. Zero without maintenance margin; `None` when there is some and equity
+/// is zero or negative (usage is unbounded).
+pub fn margin_usage(account: &Account, state: &State) -> Option<Decimal> {
+    let mm = maintenance_margin_required(account, state);
+    let equity = equity(account, state);
+    if mm.is_zero() {
+        Some(Decimal::ZERO)
+    } else if equity > Decimal::ZERO {
+        mm.checked_div(equity)
+    } else {
+        None
+    }
+}
+
+/// Portfolio equity = collateral + total unrealized PnL + accrued funding.
 pub fn equity(account: &Account, state: &State) -> Decimal {
-    account.collateral + total_unrealized_pnl(account, state)
+    account.collateral() + total_unrealized_pnl(account, state) + accrued_funding(account, state)
 }
 
-/// Initial margin required across all positions.
-pub fn initial_margin_required(account: &Account, state: &State) -> Decimal {
+/// The part of `Account::pending_funding` that counts toward equity: what has accrued
+/// in markets whose `funding_mode` is `Continuous`.
+pub fn accrued_funding(account: &Account, state: &State) -> Decimal {
     account
+        .pending_funding
+        .iter()
+        .filter(|(market_id, _)| {
+            state
+                .markets
+                .get(*market_id)
+                .is_some_and(|market| market.funding_mode == FundingMode::Continuous)
+        })
+        .map(|(_, amount)| *amount)
+        .sum()
+}
+
+/// Initial margin required across all positions (concentration add-ons included),
+/// each at the account's selected leverage if any, less the hedge-pair offset.
+pub fn initial_margin_required(account: &Account, state: &State) -> Decimal {
+    let gross: Decimal = account
         .positions
         .values()
         .map(|pos| {
@@ -52,14 +283,63 @@ pub fn initial_margin_required(account: &Account, state: &State) -> Decimal {
                 Some(m) => m,
                 None => return Decimal::ZERO,
             };
-            position_notional(pos.quantity, market.mark_price) * market.initial_margin_fraction
+            let leverage = account.leverage.get(&pos.market_id).copied();
+            position_initial_margin_with(pos.quantity, market, leverage)
         })
+        .sum();
+    gross - hedge_offset(&account.positions, &account.leverage, state).initial
+}
+
+/// Margin reserved for one of `account`'s resting orders: the IM a position of the
+/// order's size would carry in its market, at the account's selected leverage. Zero
+/// in a market that is not registered.
+pub fn order_reservation(account: &Account, order: &RestingOrder, state: &State) -> Decimal {
+    match state.markets.get(&order.market_id) {
+        Some(market) => {
+            let leverage = account.leverage.get(&order.market_id).copied();
+            position_initial_margin_with(order.quantity, market, leverage)
+        }
+        None => Decimal::ZERO,
+    }
+}
+
+/// Margin reserved for all of the account's resting orders. Unlike IM it gets no
+/// hedge-pair offset.
+pub fn reserved_margin(account: &Account, state: &State) -> Decimal {
+    account
+        .orders
+        .values()
+        .map(|order| order_reservation(account, order, state))
         .sum()
 }
 
-/// Maintenance margin required across all positions.
-pub fn maintenance_margin_required(account: &Account, state: &State) -> Decimal {
+/// The most a withdrawal can take while leaving equity of at least
+/// `(IM + reserved margin) × buffer`, and never more than the collateral. Zero when
+/// the account is already short of that. `risk::check_withdrawal_with` also refuses
+/// an account awaiting liquidation, which this does not look at.
+pub fn max_withdrawable(account: &Account, state: &State, buffer: Decimal) -> Decimal {
+    let required =
+        (initial_margin_required(account, state) + reserved_margin(account, state)) * buffer;
+    (equity(account, state) - required)
+        .min(account.collateral())
+        .max(Decimal::ZERO)
+}
+
+/// Portion of `initial_margin_required` that comes from concentration add-ons.
+pub fn concentration_add_on(account: &Account, state: &State) -> Decimal {
     account
+        .positions
+        .values()
+        .map(|pos| match state.markets.get(&pos.market_id) {
+            Some(market) => position_concentration_add_on(pos.quantity, market),
+            None => Decimal::ZERO,
+        })
+        .sum()
+}
+
+/// Maintenance margin required across all positions, less the hedge-pair offset.
+pub fn maintenance_margin_required(account: &Account, state: &State) -> Decimal {
+    let gross: Decimal = account
         .positions
         .values()
         .map(|pos| {
@@ -67,13 +347,137 @@ pub fn maintenance_margin_required(account: &Account, state: &State) -> Decimal
                 Some(m) => m,
                 None => return Decimal::ZERO,
             };
-            position_notional(pos.quantity, market.mark_price) * market.maintenance_margin_fraction
+            position_maintenance_margin(pos.quantity, market)
         })
-        .sum()
+        .sum();
+    gross - hedge_offset(&account.positions, &account.leverage, state).maintenance
+}
+
+/// Margin the markets' per-position minimums add, already included in
+/// `initial_margin_required` and `maintenance_margin_required`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MarginFloors {
+    pub initial: Decimal,
+    pub maintenance: Decimal,
+}
+
+/// What `min_initial_margin` and `min_maintenance_margin` raise the account's
+/// positions by, over what their notionals alone require.
+pub fn margin_floors(account: &Account, state: &State) -> MarginFloors {
+    let mut floors = MarginFloors::default();
+    for pos in account.positions.values() {
+        let Some(market) = state.markets.get(&pos.market_id) else {
+            continue;
+        };
+        let leverage = account.leverage.get(&pos.market_id).copied();
+        floors.initial += position_initial_margin_with(pos.quantity, market, leverage)
+            - position_initial_margin_unfloored(pos.quantity, market, leverage);
+        floors.maintenance += position_maintenance_margin(pos.quantity, market)
+            - position_maintenance_margin_unfloored(pos.quantity, market);
+    }
+    floors
+}
+
+/// Margin waived by `State::hedge_pairs`, already deducted from
+/// `initial_margin_required` and `maintenance_margin_required`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HedgeOffset {
+    pub initial: Decimal,
+    pub maintenance: Decimal,
+}
+
+/// Hedge-pair relief over `positions`, held at the selected `leverage` per market.
+/// For each pair held in opposite directions, the overlapping notional
+/// `min(notional_a, notional_b)` is waived `offset_fraction` of each leg's margin
+/// fraction; the rest of each leg is charged in full. A leg's IM fraction is the one
+/// it is charged at (see `initial_margin_fraction`), and a stale leg's IM relief
+/// carries its stale multiplier, as its IM does. Concentration add-ons and margin
+/// floors get no relief: it is worked out on notional alone and deducted from the
+/// floored total. Pairs never share a market, so the total does not depend on their order.
+pub fn hedge_offset(
+    positions: &BTreeMap<MarketId, Position>,
+    leverage: &BTreeMap<MarketId, Decimal>,
+    state: &State,
+) -> HedgeOffset {
+    let mut offset = HedgeOffset::default();
+    for pair in &state.hedge_pairs {
+        let leg = |market_id: &MarketId| {
+            let position = positions.get(market_id)?;
+            let market = state.markets.get(market_id)?;
+            Some((position.quantity, market))
+        };
+        let (Some((qty_a, market_a)), Some((qty_b, market_b))) =
+            (leg(&pair.market_a), leg(&pair.market_b))
+        else {
+            continue;
+        };
+        if qty_a.is_sign_negative() == qty_b.is_sign_negative() {
+            continue;
+        }
+        let overlap = position_notional(qty_a, market_a.mark_price)
+            .min(position_notional(qty_b, market_b.mark_price));
+        let im_fraction = |market: &Market| {
+            let selected = leverage.get(&market.market_id).copied();
+            let fraction = initial_margin_fraction(market, selected);
+            if market.stale {
+                fraction * market.stale_im_multiplier
+            } else {
+                fraction
+            }
+        };
+        let waived = overlap * pair.offset_fraction;
+        offset.initial += waived * (im_fraction(market_a) + im_fraction(market_b));
+        offset.maintenance +=
+            waived * (market_a.maintenance_margin_fraction + market_b.maintenance_margin_fraction);
+    }
+    offset
+}
+
+/// The notional of the position in `market_id` that is still charged margin after
+/// hedge-pair relief: its notional less `offset_fraction` of the overlap with the
+/// opposite leg of its pair, if held. A fully hedged leg of a pair at fraction 1
+/// has none. Zero for a market not held or not registered.
+pub fn charged_notional(
+    positions: &BTreeMap<MarketId, Position>,
+    market_id: &MarketId,
+    state: &State,
+) -> Decimal {
+    let notional = |market_id: &MarketId| {
+        let position = positions.get(market_id)?;
+        let market = state.markets.get(market_id)?;
+        Some((
+            position.quantity,
+            position_notional(position.quantity, market.mark_price),
+        ))
+    };
+    let Some((quantity, gross)) = notional(market_id) else {
+        return Decimal::ZERO;
+    };
+    let waived: Decimal = state
+        .hedge_pairs
+        .iter()
+        .filter_map(|pair| {
+            let other = if pair.market_a == *market_id {
+                &pair.market_b
+            } else if pair.market_b == *market_id {
+                &pair.market_a
+            } else {
+                return None;
+            };
+            let (other_quantity, other_notional) = notional(other)?;
+            (quantity.is_sign_negative() != other_quantity.is_sign_negative())
+                .then(|| gross.min(other_notional) * pair.offset_fraction)
+        })
+        .sum();
+    gross - waived
 }
 
 /// Returns true if the account is liquidatable under the engine's definition:
 /// liquidatable when equity <= maintenance margin AND there is at least one open position.
+/// A margin floor counts like any other maintenance margin: an account holding dust
+/// whose floors its equity no longer covers is liquidatable, and since each close
+/// releases its position's whole floor, the liquidation ends as soon as the floors of
+/// the positions left are covered.
 pub fn is_liquidatable(account: &Account, state: &State) -> bool {
     if account.positions.is_empty() {
         return false;
@@ -82,3 +486,49 @@ pub fn is_liquidatable(account: &Account, state: &State) -> bool {
     let mm = maintenance_margin_required(account, state);
     eq <= mm
 }
+
+/// A liquidatable account and how far under water it is, from `liquidation_candidates`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LiquidationCandidate {
+    pub account_id: AccountId,
+    /// `equity / maintenance_margin`; `None` when no maintenance margin is required
+    /// (only positions in unknown markets).
+    #[serde(with = "decimal_str::option")]
+    pub margin_ratio: Option<Decimal>,
+    /// `maintenance_margin - equity`, never negative.
+    #[serde(with = "decimal_str")]
+    pub shortfall: Decimal,
+    /// Waiting in `State::deferred_liquidations` for a closed session to open. The
+    /// next event does not liquidate it unless that event opens the session.
+    pub deferred: bool,
+}
+
+/// Every account `is_liquidatable` holds for, in account_id order. A pure read of
+/// `state`: nothing is planned or executed.
+pub fn liquidatable_accounts(state: &State) -> Vec<AccountId> {
+    state
+        .accounts
+        .values()
+        .filter(|account| is_liquidatable(account, state))
+        .map(|account| account.account_id.clone())
+        .collect()
+}
+
+/// `liquidatable_accounts` with each account's margin ratio and shortfall.
+pub fn liquidation_candidates(state: &State) -> Vec<LiquidationCandidate> {
+    state
+        .accounts
+        .values()
+        .filter(|account| is_liquidatable(account, state))
+        .map(|account| {
+            let equity = equity(account, state);
+            let mm = maintenance_margin_required(account, state);
+            LiquidationCandidate {
+                account_id: account.account_id.clone(),
+                margin_ratio: equity.checked_div(mm),
+                shortfall: mm - equity,
+                deferred: state.deferred_liquidations.contains(&account.account_id),
+            }
+        })
+        .collect()
+}
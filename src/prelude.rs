@@ -0,0 +1,38 @@
+/// The types most embedders need, in one import: `use cross_margin_engine::prelude::*;`.
+///
+/// Versioned like `std::prelude`: `v1` only ever grows, and anything that would
+/// break a glob import of it lands in a new version instead.
+pub mod v1 {
+    pub use crate::checkpoint::{Checkpoint, Divergence, ValidationReport};
+    pub use crate::config::{
+        BankruptcySuspension, ClosedSessionLiquidation, EngineConfig, EngineMode, FeeTier,
+        GracePeriod, ImportMarginCheck, InterestAccrual, LiquidationPath, LiquidationStrategy,
+        MarginCallPolicy, RejectedAttemptSnapshots, RejectionThrottle, ReservationBreach,
+        ResidualDeficit, RiskAlertLadder, RiskChecks, RiskDeltaPolicy, ScanOrder, SkewResponse,
+        StatsWindow, TradeMarginPolicy, TradeStatistics, UnknownMarketPolicy, WithdrawalOrder,
+        YieldBasis,
+    };
+    pub use crate::durable::{DurableEngine, Recovery, SyncMetrics};
+    pub use crate::engine::{
+        Engine, EngineBuilder, EngineObserver, ProcessOutcome, RejectReason, ReplayOptions,
+        ReplayResult, ReplayStatus, Submission,
+    };
+    pub use crate::error::{
+        EngineError, IdError, MarketConfigError, MarketError, MergeError, ResumeError, SinkError,
+        SplitError, StateLoadError,
+    };
+    pub use crate::events::{Event, EventType, Liquidity, Origin};
+    pub use crate::log_store::{FlushPolicy, LogStore, LogStoreOptions};
+    pub use crate::regenerate::LogDivergence;
+    pub use crate::risk::{RiskCheck, SimulatedPortfolio, TradeCheck, TradeContext};
+    pub use crate::sink::{InMemorySink, JsonlFileSink, NullSink, SnapshotSink};
+    pub use crate::snapshot::{RiskDelta, RiskFigures, Snapshot, SnapshotPolicy};
+    pub use crate::state::{AccountStats, CashFlows, EngineMetrics, SolvencyReport, State};
+    pub use crate::types::{
+        Account, AccountGroup, AccountId, Backstop, GroupId, HedgePair, ImportedPosition, Market,
+        MarketId, OrderId, PoolId, Position, RestingOrder,
+    };
+    pub use crate::view::{StateView, StateViews};
+}
+
+pub use v1::*;
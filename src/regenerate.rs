@@ -0,0 +1,148 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::config::EngineConfig;
+use crate::engine::{Engine, Submission};
+use crate::error::EngineError;
+use crate::events::{self, Event, EventType, Origin};
+use crate::types::Market;
+
+/// The first line at which two logs serialize differently. `sequence` is the
+/// original's event there, or the regenerated one's past the end of the original.
+/// The lines are the events' JSON as a log file holds them, `None` past the end of
+/// a log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogDivergence {
+    pub sequence: u64,
+    pub original: Option<String>,
+    pub regenerated: Option<String>,
+}
+
+impl fmt::Display for LogDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line = |l: &Option<String>| l.clone().unwrap_or_else(|| "nothing".to_string());
+        write!(
+            f,
+            "at seq {}, the log has {} but regeneration wrote {}",
+            self.sequence,
+            line(&self.original),
+            line(&self.regenerated)
+        )
+    }
+}
+
+impl Engine {
+    /// Run the external events of `log` through a live engine over `markets` and
+    /// `config`, and return the log it writes. Unlike replay, nothing the log records
+    /// is applied as given: rejections, liquidations, funding payments and the other
+    /// engine records are all produced again. Every `External` event is resubmitted,
+    /// with its key and timestamp, engine-generated types a caller submitted included,
+    /// so they are refused again. A log with no `Engine` event at all was written
+    /// before `Event::origin` existed; its submissions are the events without a
+    /// `caused_by` whose type is not one only the engine writes. A `DuplicateIgnored`
+    /// is resubmitted as the event it duplicates, under its key, so the engine ignores
+    /// it again. The submissions between a `BatchStarted` and its `BatchEnded` are
+    /// resubmitted together through `process_batch`.
+    ///
+    /// A deterministic engine regenerates a log written under `config` with the
+    /// built-in liquidator and risk checks byte for byte; `diff_logs` finds where it
+    /// does not. Submissions a rejection throttle kept out of the log cannot be
+    /// resubmitted, so a log with a `RejectionSuppressed` does not regenerate. Fails
+    /// only when `add_market` refuses a genesis market.
+    pub fn regenerate(
+        log: &[impl AsRef<Event>],
+        markets: Vec<Market>,
+        config: EngineConfig,
+    ) -> Result<Vec<Event>, EngineError> {
+        let log = &events::borrowed(log);
+        let mut engine = Engine::with_config(config);
+        for market in markets {
+            engine.add_market(market)?;
+        }
+        let has_origins = log.iter().any(|e| e.origin == Origin::Engine);
+        let mut batch: Option<Vec<(EventType, Submission)>> = None;
+        for event in log {
+            let (event_type, idempotency_key, timestamp) = match &event.event_type {
+                _ if is_submitted(event, has_origins) => (
+                    event.event_type.clone(),
+                    event.idempotency_key.clone(),
+                    event.timestamp,
+                ),
+                EventType::DuplicateIgnored {
+                    key,
+                    original_sequence,
+                } => {
+                    let index = log.partition_point(|e| e.sequence < *original_sequence);
+                    let Some(original) =
+                        log.get(index).filter(|e| e.sequence == *original_sequence)
+                    else {
+                        continue;
+                    };
+                    (
+                        original.event_type.clone(),
+                        Some(key.clone()),
+                        event.timestamp,
+                    )
+                }
+                EventType::BatchStarted { .. } => {
+                    batch = Some(Vec::new());
+                    continue;
+                }
+                EventType::BatchEnded { .. } => {
+                    if let Some(submissions) = batch.take() {
+                        engine.process_batch(submissions);
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            let submission = Submission {
+                idempotency_key,
+                timestamp,
+            };
+            match &mut batch {
+                Some(submissions) => submissions.push((event_type, submission)),
+                None => {
+                    engine.process_with(event_type, submission);
+                }
+            }
+        }
+        Ok(engine
+            .event_log
+            .into_iter()
+            .map(Arc::unwrap_or_clone)
+            .collect())
+    }
+}
+
+/// Whether a caller submitted `event`: its origin says so or, in a log without
+/// origins, its envelope and type do.
+fn is_submitted(event: &Event, has_origins: bool) -> bool {
+    if has_origins {
+        event.origin == Origin::External
+    } else {
+        event.caused_by.is_none() && !event.event_type.is_engine_generated()
+    }
+}
+
+/// The first event at which `regenerated` differs from `original` in its serialized
+/// form, or where one log ends before the other; `None` when they are identical byte
+/// for byte.
+pub fn diff_logs(
+    original: &[impl AsRef<Event>],
+    regenerated: &[impl AsRef<Event>],
+) -> Option<LogDivergence> {
+    let line = |event: Option<&Event>| {
+        event.map(|e| serde_json::to_string(e).expect("an event always serializes"))
+    };
+    (0..original.len().max(regenerated.len())).find_map(|i| {
+        let ours = original.get(i).map(AsRef::as_ref);
+        let theirs = regenerated.get(i).map(AsRef::as_ref);
+        let (original_line, regenerated_line) = (line(ours), line(theirs));
+        (original_line != regenerated_line).then(|| LogDivergence {
+            sequence: ours.or(theirs).map_or(0, |e| e.sequence),
+            original: original_line,
+            regenerated: regenerated_line,
+        })
+    })
+}
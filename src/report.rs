@@ -0,0 +1,766 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::decimal_str;
+use crate::engine::{Engine, EngineConfig, ReplayOptions, ReplayResult};
+use crate::events::{self, Event, EventType};
+use crate::margin::COLLATERAL_DECIMALS;
+use crate::snapshot::Snapshot;
+use crate::types::{AccountId, Market, MarketId};
+
+/// Breakdown of one account's equity change between two sequences by cause.
+///
+/// The components sum to `equity_change` up to `residual`; `reconciled` is false
+/// when the residual exceeds the collateral rounding unit, which means some equity
+/// movement could not be explained from the log.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AttributionReport {
+    pub account_id: AccountId,
+    /// Effective window `(from_sequence, to_sequence]`, snapped back to the nearest
+    /// available snapshots.
+    pub from_sequence: u64,
+    pub to_sequence: u64,
+
+    #[serde(with = "decimal_str")]
+    pub starting_equity: Decimal,
+    #[serde(with = "decimal_str")]
+    pub ending_equity: Decimal,
+    #[serde(with = "decimal_str")]
+    pub equity_change: Decimal,
+
+    /// Mark moves on positions held: `quantity * (new_mark - old_mark)`.
+    #[serde(with = "decimal_str")]
+    pub price_moves: Decimal,
+    /// Settled funding: the change in the account's `funding_paid` totals, negated.
+    #[serde(with = "decimal_str")]
+    pub funding: Decimal,
+    /// Accepted fills executed away from mark: `quantity * (mark - price)`.
+    #[serde(with = "decimal_str")]
+    pub trading: Decimal,
+    /// Liquidation closes and keeper takeovers executed away from mark.
+    #[serde(with = "decimal_str")]
+    pub liquidation: Decimal,
+    /// Trade fees charged (negative) or rebates credited by `FeeCharged`s.
+    #[serde(default, with = "decimal_str")]
+    pub fees: Decimal,
+    /// Interest charged (negative) or credited by `InterestTick`s.
+    #[serde(default, with = "decimal_str")]
+    pub interest: Decimal,
+    /// Paid (or, under `YieldBasis::AllBalances`, charged) by `YieldDistribution`s.
+    #[serde(default, with = "decimal_str")]
+    pub collateral_yield: Decimal,
+    /// Deposits less accepted withdrawals, plus the equity (collateral and unrealized
+    /// PnL at mark) an accepted `StateImport` brought in.
+    #[serde(with = "decimal_str")]
+    pub transfers: Decimal,
+
+    /// `equity_change` minus the sum of the components.
+    #[serde(with = "decimal_str")]
+    pub residual: Decimal,
+    pub reconciled: bool,
+}
+
+/// Attribute `account_id`'s equity change over `(from_seq, to_seq]` to its causes.
+///
+/// Works purely on artifacts (a log and the snapshots produced alongside it, live or
+/// replayed). Starting and ending equity come from the latest snapshot at or before
+/// each bound; with sparse snapshots the window widens to those sequences, and with
+/// none the start is the empty state at sequence 0. Marks and the account's positions
+/// start from the starting snapshot, so the log only needs to cover the window. A
+/// snapshot without market data (written before snapshots captured markets) cannot
+/// seed them; they are then tracked by walking the log from sequence 1.
+pub fn attribution(
+    log: &[impl AsRef<Event>],
+    snapshots: &[Snapshot],
+    account_id: &AccountId,
+    from_seq: u64,
+    to_seq: u64,
+) -> AttributionReport {
+    let log = &events::borrowed(log);
+    let (from_sequence, starting_equity, funding_paid_before) =
+        equity_at(snapshots, account_id, from_seq);
+    let (to_sequence, ending_equity, funding_paid_after) = equity_at(snapshots, account_id, to_seq);
+    // Funding comes straight from the engine's per-market ledger rather than the log.
+    let mut funding = funding_paid_before - funding_paid_after;
+
+    // A rejected attempt is always immediately followed by its rejection event.
+    let rejected: BTreeSet<u64> = log
+        .windows(2)
+        .filter(|pair| pair[1].event_type.is_rejection())
+        .map(|pair| pair[0].sequence)
+        .collect();
+
+    let seed = snapshot_at(snapshots, from_seq).filter(|s| !s.markets.is_empty());
+    let mut marks: BTreeMap<MarketId, Decimal> = seed
+        .map(|s| {
+            s.markets
+                .iter()
+                .map(|(id, m)| (id.clone(), m.mark_price))
+                .collect()
+        })
+        .unwrap_or_default();
+    let mut positions: BTreeMap<MarketId, Decimal> = seed
+        .and_then(|s| s.accounts.get(account_id))
+        .map(|a| {
+            a.positions
+                .iter()
+                .map(|(id, p)| (id.clone(), p.quantity))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut price_moves = Decimal::ZERO;
+    let mut trading = Decimal::ZERO;
+    let mut liquidation = Decimal::ZERO;
+    let mut fees = Decimal::ZERO;
+    let mut interest = Decimal::ZERO;
+    let mut collateral_yield = Decimal::ZERO;
+    let mut transfers = Decimal::ZERO;
+
+    for event in log {
+        if event.sequence > to_sequence {
+            break;
+        }
+        let in_window = event.sequence > from_sequence;
+        if rejected.contains(&event.sequence) || (seed.is_some() && !in_window) {
+            continue;
+        }
+
+        match &event.event_type {
+            EventType::MarkPriceUpdate { market_id, price } => {
+                price_moves += mark_move(&mut marks, &positions, market_id, *price, in_window);
+            }
+
+            EventType::MarkPriceBatch { updates } => {
+                for (market_id, price) in updates {
+                    price_moves += mark_move(&mut marks, &positions, market_id, *price, in_window);
+                }
+            }
+
+            // The final mark; the settlement that follows closes at it.
+            EventType::Expiry {
+                market_id,
+                settlement_price,
+            } => {
+                price_moves += mark_move(
+                    &mut marks,
+                    &positions,
+                    market_id,
+                    *settlement_price,
+                    in_window,
+                );
+            }
+
+            EventType::ExpirySettlement {
+                account_id: id,
+                market_id,
+                quantity,
+                ..
+            } if id == account_id => {
+                adjust(&mut positions, market_id, *quantity);
+            }
+
+            EventType::FeeCharged {
+                account_id: id,
+                amount,
+                ..
+            } if id == account_id && in_window => {
+                fees -= *amount;
+            }
+
+            EventType::InterestCharged {
+                account_id: id,
+                amount,
+            } if id == account_id && in_window => {
+                interest += *amount;
+            }
+
+            EventType::YieldPaid {
+                account_id: id,
+                amount,
+                ..
+            } if id == account_id && in_window => {
+                collateral_yield += *amount;
+            }
+
+            EventType::Deposit {
+                account_id: id,
+                amount,
+            } if id == account_id && in_window => {
+                transfers += *amount;
+            }
+
+            EventType::Withdraw {
+                account_id: id,
+                amount,
+            } if id == account_id && in_window => {
+                transfers -= *amount;
+            }
+
+            EventType::StateImport {
+                account_id: id,
+                collateral,
+                positions: imported,
+                ..
+            } if id == account_id => {
+                for position in imported {
+                    if in_window {
+                        let mark = marks.get(&position.market_id).copied().unwrap_or_default();
+                        transfers += position.quantity * mark - position.cost_basis;
+                    }
+                    adjust(&mut positions, &position.market_id, position.quantity);
+                }
+                if in_window {
+                    transfers += *collateral;
+                }
+            }
+
+            // A force close trades out at mark.
+            EventType::TradeFill {
+                account_id: id,
+                market_id,
+                quantity,
+                price,
+                ..
+            }
+            | EventType::ForceCloseFill {
+                account_id: id,
+                market_id,
+                quantity,
+                price,
+                ..
+            } if id == account_id => {
+                if in_window {
+                    trading += fill_vs_mark(&marks, market_id, *quantity, *price);
+                }
+                adjust(&mut positions, market_id, *quantity);
+            }
+
+            EventType::LiquidationFill {
+                account_id: id,
+                market_id,
+                quantity,
+                price,
+            } if id == account_id => {
+                if in_window {
+                    liquidation += fill_vs_mark(&marks, market_id, *quantity, *price);
+                }
+                adjust(&mut positions, market_id, *quantity);
+            }
+
+            // A merge hands the source's equity, positions and funding totals to the
+            // destination. They are read from the latest snapshot before it, so they
+            // are exact when that snapshot is the one right before the merge.
+            EventType::AccountsMerged { from, to } if from == account_id || to == account_id => {
+                let sign = if to == account_id {
+                    Decimal::ONE
+                } else {
+                    Decimal::NEGATIVE_ONE
+                };
+                let source =
+                    snapshot_at(snapshots, event.sequence - 1).and_then(|s| s.accounts.get(from));
+                if let Some(source) = source {
+                    if in_window {
+                        transfers += sign * source.equity;
+                        funding += sign * source.funding_paid.values().sum::<Decimal>();
+                    }
+                    for (market_id, position) in &source.positions {
+                        adjust(&mut positions, market_id, sign * position.quantity);
+                    }
+                }
+            }
+
+            // Each side of a transfer fills at the agreed price, off market.
+            EventType::PositionTransfer {
+                from,
+                to,
+                market_id,
+                quantity,
+                transfer_price,
+            } => {
+                let side = if from == account_id {
+                    -*quantity
+                } else if to == account_id {
+                    *quantity
+                } else {
+                    continue;
+                };
+                if in_window {
+                    trading += fill_vs_mark(&marks, market_id, side, *transfer_price);
+                }
+                adjust(&mut positions, market_id, side);
+            }
+
+            EventType::LiquidationTakeover {
+                liquidated_account,
+                keeper_account,
+                market_id,
+                quantity,
+                price,
+            } => {
+                // The keeper takes the opposite side of the liquidated account's close.
+                let side = if liquidated_account == account_id {
+                    *quantity
+                } else if keeper_account == account_id {
+                    -*quantity
+                } else {
+                    continue;
+                };
+                if in_window {
+                    liquidation += fill_vs_mark(&marks, market_id, side, *price);
+                }
+                adjust(&mut positions, market_id, side);
+            }
+
+            _ => {}
+        }
+    }
+
+    let equity_change = ending_equity - starting_equity;
+    let residual = equity_change
+        - (price_moves
+            + funding
+            + trading
+            + liquidation
+            + fees
+            + interest
+            + collateral_yield
+            + transfers);
+
+    AttributionReport {
+        account_id: account_id.clone(),
+        from_sequence,
+        to_sequence,
+        starting_equity,
+        ending_equity,
+        equity_change,
+        price_moves,
+        funding,
+        trading,
+        liquidation,
+        fees,
+        interest,
+        collateral_yield,
+        transfers,
+        residual,
+        reconciled: residual.abs() <= Decimal::new(1, COLLATERAL_DECIMALS),
+    }
+}
+
+/// Cause of one collateral movement in a statement.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum LedgerKind {
+    Deposit,
+    Withdrawal,
+    /// PnL realized by a reducing, closing or flipping fill, or by the settlement of
+    /// a future at expiry.
+    RealizedPnl,
+    Funding,
+    /// Loss (or gain) realized by a liquidation close or a takeover of this account.
+    Liquidation,
+    /// Discount credited to this account as the keeper in a takeover.
+    KeeperTakeover,
+    /// A trade fee (negative) or rebate, split out of the fill that charged it.
+    Fee,
+    /// Part of a bankruptcy deficit covered by the account's pool's insurance fund.
+    InsurancePayout,
+    /// Part of a bankruptcy deficit charged to the pool's other accounts: a credit to
+    /// the bankrupt account, a debit to each charged one.
+    LossSocialization,
+    /// Collateral carried over by a `StateImport`.
+    Import,
+    /// Interest charged on a negative balance or credited on a positive one.
+    Interest,
+    /// Yield paid by a `YieldDistribution`.
+    Yield,
+    /// Balances an `AccountsMerged` moved: out of the closed source, into the
+    /// destination together with the PnL that netting its positions realized.
+    Merge,
+    /// PnL a `PositionTransfer` realized for either side at the transfer price, with
+    /// the pending funding in the market it settled first.
+    PositionTransfer,
+    /// A collateral change at an event type that should not move collateral.
+    Unexplained,
+}
+
+/// One collateral movement.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LedgerLine {
+    pub sequence: u64,
+    pub kind: LedgerKind,
+    pub market_id: Option<MarketId>,
+    #[serde(with = "decimal_str")]
+    pub amount: Decimal,
+    #[serde(with = "decimal_str")]
+    pub balance_after: Decimal,
+    /// The part of `amount` that moved `Account::principal`; the rest moved its
+    /// `trading_balance`.
+    #[serde(with = "decimal_str")]
+    pub principal_amount: Decimal,
+    #[serde(with = "decimal_str")]
+    pub principal_after: Decimal,
+    #[serde(with = "decimal_str")]
+    pub trading_balance_after: Decimal,
+}
+
+impl LedgerLine {
+    /// The part of `amount` that moved `Account::trading_balance`.
+    pub fn trading_amount(&self) -> Decimal {
+        self.amount - self.principal_amount
+    }
+}
+
+/// Every change to `account_id`'s collateral, in log order, with a running balance.
+///
+/// The log is replayed under `markets` (and the config from its `ConfigMarker`, if
+/// any) with a snapshot after every event; each change in the account's principal or
+/// trading balance between consecutive snapshots becomes one line, classified by the
+/// event that caused it. The last `balance_after` therefore equals the replayed
+/// collateral exactly, and likewise for each balance. Funding appears at the funding
+/// event that settled it, interest at the tick that accrued it, and yield at the
+/// distribution that paid it. A fill's fee is its own line, at its `FeeCharged`
+/// record, after the fill's realized PnL.
+pub fn statement(
+    log: &[impl AsRef<Event>],
+    account_id: &str,
+    markets: Vec<Market>,
+) -> Vec<LedgerLine> {
+    let log = &events::borrowed(log);
+    let replayed = replay_log(log, markets);
+    let events: BTreeMap<u64, &Event> = log.iter().map(|e| (e.sequence, *e)).collect();
+    // The fee each fill charged the account: its record's sequence and the debit.
+    let fees: BTreeMap<u64, (u64, Decimal)> = log
+        .iter()
+        .filter_map(|e| match &e.event_type {
+            EventType::FeeCharged {
+                account_id: id,
+                amount,
+                ..
+            } if id == account_id => Some((e.caused_by?, (e.sequence, -*amount))),
+            _ => None,
+        })
+        .collect();
+
+    let mut lines = Vec::new();
+    let (mut principal, mut trading_balance) = (Decimal::ZERO, Decimal::ZERO);
+    for snapshot in &replayed.snapshots {
+        let (principal_after, trading_balance_after) = snapshot
+            .accounts
+            .get(account_id)
+            .map_or((Decimal::ZERO, Decimal::ZERO), |a| {
+                (a.principal, a.trading_balance)
+            });
+        let principal_amount = principal_after - principal;
+        let amount = principal_amount + trading_balance_after - trading_balance;
+        if principal_amount.is_zero() && trading_balance_after == trading_balance {
+            continue;
+        }
+        (principal, trading_balance) = (principal_after, trading_balance_after);
+
+        let (kind, market_id) = match events.get(&snapshot.after_sequence).map(|e| &e.event_type) {
+            Some(EventType::Deposit { .. }) => (LedgerKind::Deposit, None),
+            Some(EventType::Withdraw { .. }) => (LedgerKind::Withdrawal, None),
+            Some(EventType::TradeFill { market_id, .. })
+            | Some(EventType::ForceCloseFill { market_id, .. })
+            | Some(EventType::Expiry { market_id, .. }) => {
+                (LedgerKind::RealizedPnl, Some(market_id.clone()))
+            }
+            Some(EventType::FundingUpdate { market_id, .. })
+            | Some(EventType::FundingRate { market_id, .. }) => {
+                (LedgerKind::Funding, Some(market_id.clone()))
+            }
+            Some(EventType::LiquidationFill { market_id, .. }) => {
+                (LedgerKind::Liquidation, Some(market_id.clone()))
+            }
+            Some(EventType::LiquidationTakeover {
+                keeper_account,
+                market_id,
+                ..
+            }) => {
+                let kind = if keeper_account == account_id {
+                    LedgerKind::KeeperTakeover
+                } else {
+                    LedgerKind::Liquidation
+                };
+                (kind, Some(market_id.clone()))
+            }
+            Some(EventType::InsuranceFundPayout { .. }) => (LedgerKind::InsurancePayout, None),
+            Some(EventType::LossSocialized { .. }) => (LedgerKind::LossSocialization, None),
+            Some(EventType::StateImport { .. }) => (LedgerKind::Import, None),
+            Some(EventType::InterestTick { .. }) => (LedgerKind::Interest, None),
+            Some(EventType::YieldDistribution { .. }) => (LedgerKind::Yield, None),
+            Some(EventType::AccountsMerged { .. }) => (LedgerKind::Merge, None),
+            Some(EventType::PositionTransfer { market_id, .. }) => {
+                (LedgerKind::PositionTransfer, Some(market_id.clone()))
+            }
+            _ => (LedgerKind::Unexplained, None),
+        };
+
+        let fee = fees
+            .get(&snapshot.after_sequence)
+            .filter(|_| kind == LedgerKind::RealizedPnl);
+        let debit = fee.map_or(Decimal::ZERO, |(_, debit)| *debit);
+        if amount != debit {
+            lines.push(LedgerLine {
+                sequence: snapshot.after_sequence,
+                kind,
+                market_id: market_id.clone(),
+                amount: amount - debit,
+                balance_after: principal + trading_balance - debit,
+                principal_amount,
+                principal_after,
+                trading_balance_after: trading_balance_after - debit,
+            });
+        }
+        if let Some((sequence, debit)) = fee {
+            lines.push(LedgerLine {
+                sequence: *sequence,
+                kind: LedgerKind::Fee,
+                market_id,
+                amount: *debit,
+                balance_after: principal + trading_balance,
+                principal_amount: Decimal::ZERO,
+                principal_after,
+                trading_balance_after,
+            });
+        }
+    }
+    lines
+}
+
+/// A statement's two balances, each reconciled on its own against the lines allowed
+/// to move it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StatementReconciliation {
+    /// Closing principal.
+    #[serde(with = "decimal_str")]
+    pub principal: Decimal,
+    /// Net principal moved by deposits, withdrawals, imports and merges.
+    #[serde(with = "decimal_str")]
+    pub transfers: Decimal,
+    /// Closing trading balance.
+    #[serde(with = "decimal_str")]
+    pub trading_balance: Decimal,
+    /// Net trading balance moved by every line other than a deposit or an import.
+    #[serde(with = "decimal_str")]
+    pub trading: Decimal,
+    /// Both balances equal their flows: no line moved a balance its kind may not.
+    pub reconciled: bool,
+}
+
+/// Reconcile the principal and the trading balance of `lines` (a `statement`)
+/// separately. Principal may only move by transfers, so a funding or PnL line that
+/// touches it, or a deposit credited as trading balance, leaves it unreconciled.
+pub fn reconcile(lines: &[LedgerLine]) -> StatementReconciliation {
+    let (principal, trading_balance) =
+        lines.last().map_or((Decimal::ZERO, Decimal::ZERO), |line| {
+            (line.principal_after, line.trading_balance_after)
+        });
+    let mut transfers = Decimal::ZERO;
+    let mut trading = Decimal::ZERO;
+    for line in lines {
+        match line.kind {
+            LedgerKind::Deposit | LedgerKind::Import => transfers += line.principal_amount,
+            LedgerKind::Withdrawal | LedgerKind::Merge => {
+                transfers += line.principal_amount;
+                trading += line.trading_amount();
+            }
+            _ => trading += line.trading_amount(),
+        }
+    }
+    StatementReconciliation {
+        principal,
+        transfers,
+        trading_balance,
+        trading,
+        reconciled: principal == transfers && trading_balance == trading,
+    }
+}
+
+/// One accepted funding event: how far the index moved and who paid whom.
+///
+/// Payments are the event's `FundingPayment` records, split by the side of the
+/// position each account held. With equal long and short open interest they net to
+/// zero; `residual` is what they net to, and `balanced` is false when it is not zero.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FundingPeriodReport {
+    pub sequence: u64,
+    pub market_id: MarketId,
+    #[serde(with = "decimal_str")]
+    pub old_index: Decimal,
+    #[serde(with = "decimal_str")]
+    pub new_index: Decimal,
+    /// Mark at settlement.
+    #[serde(with = "decimal_str")]
+    pub mark_price: Decimal,
+    /// Index move as a fraction of the mark, which is the quoted rate of a
+    /// `RateTimesMark` `FundingRate`. `None` when the mark is zero, or so close to
+    /// zero that the rate overflows.
+    #[serde(with = "decimal_str::option")]
+    pub implied_rate: Option<Decimal>,
+    /// Funding paid by long positions (negative when longs received).
+    #[serde(with = "decimal_str")]
+    pub paid_by_longs: Decimal,
+    /// Funding received by short positions (negative when shorts paid).
+    #[serde(with = "decimal_str")]
+    pub received_by_shorts: Decimal,
+    /// `received_by_shorts - paid_by_longs`: the sum of all payments.
+    #[serde(with = "decimal_str")]
+    pub residual: Decimal,
+    pub balanced: bool,
+}
+
+/// Every accepted `FundingUpdate` and `FundingRate` in `log`, in log order.
+///
+/// The log is replayed under `markets` (and its `ConfigMarker` config, if any) so that
+/// indexes and marks come from the snapshot of each funding event and the one before
+/// it, and positions tell longs from shorts. Rejected funding events and events for
+/// unknown markets are left out.
+pub fn funding_history(
+    log: &[impl AsRef<Event>],
+    markets: Vec<Market>,
+) -> Vec<FundingPeriodReport> {
+    let log = &events::borrowed(log);
+    let mut indexes: BTreeMap<MarketId, Decimal> = markets
+        .iter()
+        .map(|m| (m.market_id.clone(), m.cumulative_funding_index))
+        .collect();
+    let replayed = replay_log(log, markets);
+
+    let mut payments: BTreeMap<u64, Vec<(&AccountId, Decimal)>> = BTreeMap::new();
+    let mut funding_seq = None;
+    for event in log {
+        match &event.event_type {
+            EventType::FundingUpdate { .. } | EventType::FundingRate { .. } => {
+                funding_seq = Some(event.sequence);
+            }
+            // Logs written before `caused_by` existed rely on adjacency alone.
+            EventType::FundingPayment {
+                account_id, amount, ..
+            } => {
+                if let Some(seq) = event.caused_by.or(funding_seq) {
+                    payments.entry(seq).or_default().push((account_id, *amount));
+                }
+            }
+            _ => funding_seq = None,
+        }
+    }
+
+    let events: BTreeMap<u64, &Event> = log.iter().map(|e| (e.sequence, *e)).collect();
+    let mut reports = Vec::new();
+    for snapshot in &replayed.snapshots {
+        let seq = snapshot.after_sequence;
+        let funded = match events.get(&seq).map(|e| &e.event_type) {
+            Some(EventType::FundingUpdate { market_id, .. })
+            | Some(EventType::FundingRate { market_id, .. }) => snapshot
+                .markets
+                .get(market_id)
+                .map(|market| (market_id, market)),
+            _ => None,
+        };
+        if let Some((market_id, market)) = funded {
+            let old_index = indexes.get(market_id).copied().unwrap_or_default();
+            let new_index = market.cumulative_funding_index;
+            let mut paid_by_longs = Decimal::ZERO;
+            let mut received_by_shorts = Decimal::ZERO;
+            for (account_id, amount) in payments.get(&seq).into_iter().flatten() {
+                let quantity = snapshot
+                    .accounts
+                    .get(*account_id)
+                    .and_then(|a| a.positions.get(market_id))
+                    .map_or(Decimal::ZERO, |p| p.quantity);
+                if quantity.is_sign_negative() {
+                    received_by_shorts += *amount;
+                } else {
+                    paid_by_longs -= *amount;
+                }
+            }
+            let residual = received_by_shorts - paid_by_longs;
+            reports.push(FundingPeriodReport {
+                sequence: seq,
+                market_id: market_id.clone(),
+                old_index,
+                new_index,
+                mark_price: market.mark_price,
+                implied_rate: (new_index - old_index).checked_div(market.mark_price),
+                paid_by_longs,
+                received_by_shorts,
+                residual,
+                balanced: residual.is_zero(),
+            });
+        }
+        for (market_id, market) in &snapshot.markets {
+            indexes.insert(market_id.clone(), market.cumulative_funding_index);
+        }
+    }
+    reports
+}
+
+/// Replay `log` under `markets` and the config in its `ConfigMarker` (the default
+/// config without one), with a snapshot after every event.
+fn replay_log(log: &[&Event], markets: Vec<Market>) -> ReplayResult {
+    let config = match log.first().map(|e| &e.event_type) {
+        Some(EventType::ConfigMarker { config, .. }) => config.clone(),
+        _ => EngineConfig::default(),
+    };
+    let options = ReplayOptions {
+        config,
+        ..ReplayOptions::default()
+    };
+    Engine::replay_with(options, log.iter().copied(), markets)
+}
+
+/// Latest snapshot at or before `seq`, with the account's equity and total lifetime
+/// funding paid in it (zero if the account does not exist yet). `(0, 0, 0)` when no
+/// such snapshot exists.
+fn equity_at(snapshots: &[Snapshot], account_id: &str, seq: u64) -> (u64, Decimal, Decimal) {
+    snapshot_at(snapshots, seq)
+        .map(|s| match s.accounts.get(account_id) {
+            Some(a) => (s.after_sequence, a.equity, a.funding_paid.values().sum()),
+            None => (s.after_sequence, Decimal::ZERO, Decimal::ZERO),
+        })
+        .unwrap_or((0, Decimal::ZERO, Decimal::ZERO))
+}
+
+/// Latest snapshot at or before `seq`.
+fn snapshot_at(snapshots: &[Snapshot], seq: u64) -> Option<&Snapshot> {
+    snapshots
+        .iter()
+        .filter(|s| s.after_sequence <= seq)
+        .max_by_key(|s| s.after_sequence)
+}
+
+/// Record a new mark and return the move on the account's position, if in window.
+fn mark_move(
+    marks: &mut BTreeMap<MarketId, Decimal>,
+    positions: &BTreeMap<MarketId, Decimal>,
+    market_id: &MarketId,
+    price: Decimal,
+    in_window: bool,
+) -> Decimal {
+    let old = marks.insert(market_id.clone(), price).unwrap_or(price);
+    match positions.get(market_id) {
+        Some(qty) if in_window => *qty * (price - old),
+        _ => Decimal::ZERO,
+    }
+}
+
+/// Equity impact of a fill relative to mark: a fill at mark changes nothing.
+fn fill_vs_mark(
+    marks: &BTreeMap<MarketId, Decimal>,
+    market_id: &str,
+    quantity: Decimal,
+    price: Decimal,
+) -> Decimal {
+    let mark = marks.get(market_id).copied().unwrap_or(price);
+    quantity * (mark - price)
+}
+
+fn adjust(positions: &mut BTreeMap<MarketId, Decimal>, market_id: &MarketId, quantity: Decimal) {
+    let qty = positions.entry(market_id.clone()).or_insert(Decimal::ZERO);
+    *qty += quantity;
+    if qty.is_zero() {
+        positions.remove(market_id);
+    }
+}
@@ -1,10 +1,22 @@
-use rust_decimal::prelude::Signed;
-use rust_decimal::Decimal;
-use std::collections::BTreeMap;
+use rust_decimal::{Decimal, RoundingStrategy};
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
 
+use crate::config::{
+    BankruptcySuspension, EngineConfig, ImportMarginCheck, SkewResponse, TradeMarginPolicy,
+};
+use crate::decimal_str;
+use crate::error::MarketError;
+use crate::events::{self, Event, EventType};
+use crate::liquidation;
 use crate::margin;
 use crate::state::State;
-use crate::types::{AccountId, MarketId, Position};
+use crate::trace;
+use crate::types::{
+    Account, AccountId, AccountLimits, ImportedPosition, Market, MarketId, OrderId, Position,
+};
 
 /// Result of a pre-trade risk check.
 pub enum TradeCheck {
@@ -12,26 +24,1291 @@ pub enum TradeCheck {
     Rejected(String),
 }
 
-/// Determines if a trade reduces the absolute position size without flipping.
-fn is_risk_reducing(current_qty: Decimal, fill_qty: Decimal) -> bool {
+/// Leading text of every rejection caused by the account already being
+/// liquidatable. `RejectReason::from_event` keys on it, so it is part of the log format.
+pub const IN_LIQUIDATION: &str = "Account in liquidation";
+
+/// Leading text of every trade rejection caused by a suspension after bankruptcy.
+/// `RejectReason::from_event` keys on it, so it is part of the log format.
+pub const SUSPENDED_AFTER_BANKRUPTCY: &str = "Account suspended after bankruptcy";
+
+/// Leading text of every trade rejection caused by the market's session being closed.
+/// `RejectReason::from_event` keys on it, so it is part of the log format.
+pub const MARKET_CLOSED: &str = "Market closed";
+
+/// Leading text of every trade rejection caused by a breached skew limit under
+/// `SkewResponse::ReduceOnly`. `RejectReason::from_event` keys on it, so it is part of
+/// the log format.
+pub const SKEW_LIMIT: &str = "Skew limit breached";
+
+/// Leading text of every trade, withdrawal and takeover rejection caused by a frozen
+/// account (see `Account::frozen`). `RejectReason::from_event` keys on it, so it is
+/// part of the log format.
+pub const FROZEN: &str = "Account frozen";
+
+/// Largest magnitude of any quantity, price, amount, rate or funding index an external
+/// event may carry, and of any position or funding index an event may leave behind.
+/// The largest product the engine forms from one event, a notional or a realized PnL,
+/// then stays near 1e24, far inside `Decimal`'s range of about 7.9e28.
+pub const MAX_EVENT_VALUE: Decimal = dec!(1_000_000_000_000);
+
+/// Reject an external event carrying a decimal of magnitude beyond `MAX_EVENT_VALUE`.
+/// An imported position's cost basis is bounded through its entry price instead, by
+/// `check_state_import`. Checked before anything else, so no arithmetic on the event
+/// can overflow.
+pub fn check_event_values(event_type: &EventType) -> TradeCheck {
+    let values: Vec<(&str, Decimal)> = match event_type {
+        EventType::Deposit { amount, .. }
+        | EventType::Withdraw { amount, .. }
+        | EventType::InsuranceFundDeposit { amount, .. } => vec![("Amount", *amount)],
+        EventType::TradeFill {
+            quantity, price, ..
+        }
+        | EventType::LiquidationTakeover {
+            quantity, price, ..
+        }
+        | EventType::PositionTransfer {
+            quantity,
+            transfer_price: price,
+            ..
+        } => {
+            vec![("Quantity", *quantity), ("Price", *price)]
+        }
+        EventType::OrderPlaced { quantity, .. } => vec![("Quantity", *quantity)],
+        EventType::MarkPriceUpdate { price, .. } => vec![("Price", *price)],
+        EventType::MarkPriceBatch { updates } => updates.values().map(|p| ("Price", *p)).collect(),
+        EventType::Expiry {
+            settlement_price, ..
+        } => vec![("Settlement price", *settlement_price)],
+        EventType::FundingUpdate {
+            new_cumulative_index,
+            ..
+        } => vec![("Funding index", *new_cumulative_index)],
+        EventType::FundingRate { rate, .. } => vec![("Funding rate", *rate)],
+        EventType::FundingAccrual { accrued_index, .. } => vec![("Funding index", *accrued_index)],
+        EventType::YieldDistribution { rate, .. } => vec![("Yield rate", *rate)],
+        EventType::SetAccountLimits {
+            max_leverage,
+            max_total_notional,
+            ..
+        } => [
+            ("Max leverage", *max_leverage),
+            ("Max total notional", *max_total_notional),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, value?)))
+        .collect(),
+        EventType::StateImport {
+            collateral,
+            positions,
+            ..
+        } => std::iter::once(("Collateral", *collateral))
+            .chain(positions.iter().flat_map(|p| {
+                [
+                    ("Imported quantity", p.quantity),
+                    ("Imported funding index", p.last_funding),
+                ]
+            }))
+            .collect(),
+        EventType::HedgePairAdded {
+            offset_fraction, ..
+        } => vec![("Offset fraction", *offset_fraction)],
+        EventType::SetPositionLeverage { leverage, .. } => vec![("Leverage", *leverage)],
+        EventType::BackstopRegistered { max_notional, .. } => vec![("Max notional", *max_notional)],
+        EventType::MarketAdded { market } => market_values(market),
+        EventType::ConfigUpdated { config } => config_values(config),
+        EventType::GroupCreated {
+            max_group_notional,
+            fee_override,
+            ..
+        } => [
+            ("Max group notional", *max_group_notional),
+            ("Fee override", *fee_override),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, value?)))
+        .collect(),
+        _ => Vec::new(),
+    };
+    check_values(values)
+}
+
+fn check_values(values: Vec<(&str, Decimal)>) -> TradeCheck {
+    match values
+        .into_iter()
+        .find(|(_, value)| value.abs() > MAX_EVENT_VALUE)
+    {
+        Some((name, value)) => TradeCheck::Rejected(format!(
+            "{name} {value} is out of range: magnitudes above {MAX_EVENT_VALUE} are not accepted"
+        )),
+        None => TradeCheck::Accepted,
+    }
+}
+
+/// Every decimal a `MarketAdded` carries into the engine's arithmetic.
+fn market_values(market: &Market) -> Vec<(&'static str, Decimal)> {
+    [
+        ("Mark price", Some(market.mark_price)),
+        ("Funding index", Some(market.cumulative_funding_index)),
+        ("Accrued funding index", market.accrued_funding_index),
+        (
+            "Concentration threshold",
+            Some(market.concentration_threshold_notional),
+        ),
+        (
+            "Concentration add-on",
+            Some(market.concentration_add_on_fraction),
+        ),
+        ("Max open interest", market.max_open_interest_notional),
+        ("Skew limit", market.skew_limit_notional),
+        ("Min initial margin", market.min_initial_margin),
+        ("Min maintenance margin", market.min_maintenance_margin),
+        ("Quantity step", market.quantity_step),
+        ("Min liquidation notional", market.min_liquidation_notional),
+        ("Liquidation discount", Some(market.liquidation_discount)),
+        ("Slippage", Some(market.slippage_bps_per_notional)),
+        ("Stale IM multiplier", Some(market.stale_im_multiplier)),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| Some((name, value?)))
+    .collect()
+}
+
+/// Every decimal a `ConfigUpdated` carries into the engine's arithmetic.
+fn config_values(config: &EngineConfig) -> Vec<(&'static str, Decimal)> {
+    let mut values = vec![("Withdrawal buffer", config.withdrawal_buffer)];
+    if let Some(interest) = &config.interest {
+        values.push(("Interest rate", interest.rate_per_interval));
+        values.push(("Interest credit rate", interest.credit_rate_per_interval));
+    }
+    if let Some(ladder) = &config.risk_alerts {
+        values.extend(ladder.thresholds.iter().map(|t| ("Alert threshold", *t)));
+        values.push(("Alert hysteresis", ladder.hysteresis));
+    }
+    if let Some(stats) = &config.trade_stats {
+        for tier in &stats.fee_tiers {
+            values.push(("Fee tier turnover", tier.min_turnover));
+            values.push(("Fee tier rate", tier.rate));
+        }
+    }
+    values
+}
+
+/// Reject a fill of `quantity` against a position of `current` that is off the
+/// market's `quantity_step` (see `Market::on_step`).
+pub(crate) fn check_step(market: &Market, current: Decimal, quantity: Decimal) -> TradeCheck {
+    match market.quantity_step {
+        Some(step) if !market.on_step(current, quantity) => TradeCheck::Rejected(format!(
+            "Quantity {quantity} in {} is not a multiple of its quantity step {step}",
+            market.market_id
+        )),
+        _ => TradeCheck::Accepted,
+    }
+}
+
+/// Reject a fill that would leave a position larger than `MAX_EVENT_VALUE`.
+fn check_position_size(market_id: &MarketId, new_qty: Decimal) -> TradeCheck {
+    if new_qty.abs() > MAX_EVENT_VALUE {
+        TradeCheck::Rejected(format!(
+            "Position {new_qty} in {market_id} would exceed the largest accepted size {MAX_EVENT_VALUE}"
+        ))
+    } else {
+        TradeCheck::Accepted
+    }
+}
+
+/// Reject anything that adds risk or removes collateral from an account that is
+/// already at or under maintenance margin and only waiting for its liquidation scan.
+fn check_not_liquidatable(account: &Account, state: &State) -> TradeCheck {
+    if margin::is_liquidatable(account, state) {
+        TradeCheck::Rejected(format!(
+            "{IN_LIQUIDATION}: equity {} <= MM {}",
+            margin::equity(account, state),
+            margin::maintenance_margin_required(account, state)
+        ))
+    } else {
+        TradeCheck::Accepted
+    }
+}
+
+/// Reject new risk in `market_id` from an account suspended after bankruptcy, as far
+/// as `policy` enforces the suspension.
+fn check_not_suspended(
+    account: &Account,
+    market_id: &MarketId,
+    policy: BankruptcySuspension,
+) -> TradeCheck {
+    let applies = match policy {
+        BankruptcySuspension::Off => false,
+        BankruptcySuspension::AllMarkets => account.suspended,
+        BankruptcySuspension::BankruptedMarkets => {
+            account.suspended && account.suspended_markets.contains(market_id)
+        }
+    };
+    if applies {
+        TradeCheck::Rejected(format!(
+            "{SUSPENDED_AFTER_BANKRUPTCY}: {} may not add risk in {market_id} until reinstated",
+            account.account_id
+        ))
+    } else {
+        TradeCheck::Accepted
+    }
+}
+
+/// Reject anything from an account frozen by a `ForceClose`, reducing or not.
+fn check_not_frozen(account: &Account) -> TradeCheck {
+    match &account.frozen {
+        Some(reason) => TradeCheck::Rejected(format!(
+            "{FROZEN}: {} was force-closed ({reason}) and is frozen until reinstated",
+            account.account_id
+        )),
+        None => TradeCheck::Accepted,
+    }
+}
+
+/// Validate an `AccountReinstated`: the account must be suspended or frozen and its
+/// bankruptcy deficit fully repaid.
+pub fn check_reinstatement(state: &State, account_id: &AccountId) -> TradeCheck {
+    let account = match state.accounts.get(account_id) {
+        Some(a) => a,
+        None => return TradeCheck::Rejected("Account does not exist".to_string()),
+    };
+    if !account.suspended && account.frozen.is_none() {
+        return TradeCheck::Rejected(format!("Account {account_id} is not suspended or frozen"));
+    }
+    if account.bankruptcy_deficit > Decimal::ZERO {
+        return TradeCheck::Rejected(format!(
+            "Account {account_id} still has a bankruptcy deficit of {}",
+            account.bankruptcy_deficit
+        ));
+    }
+    TradeCheck::Accepted
+}
+
+/// Validate an `AssignPool`: the pool must be named and the account must not exist
+/// yet, since a pool is fixed once the account has been created.
+pub fn check_pool_assignment(state: &State, account_id: &AccountId, pool_id: &str) -> TradeCheck {
+    if pool_id.is_empty() {
+        return TradeCheck::Rejected("Pool id must not be empty".to_string());
+    }
+    if let Some(account) = state.accounts.get(account_id) {
+        return TradeCheck::Rejected(format!(
+            "Account {account_id} already exists in pool {}",
+            account.pool_id
+        ));
+    }
+    TradeCheck::Accepted
+}
+
+/// Validate a `HedgePairAdded`: two distinct registered markets, neither already in
+/// a pair, and a fraction in `(0, 1]`. Keeping pairs disjoint means no notional can
+/// be offset twice.
+pub fn check_hedge_pair(
+    state: &State,
+    market_a: &MarketId,
+    market_b: &MarketId,
+    offset_fraction: Decimal,
+) -> TradeCheck {
+    if market_a == market_b {
+        return TradeCheck::Rejected(format!(
+            "Hedge pair needs two markets, got {market_a} twice"
+        ));
+    }
+    for market_id in [market_a, market_b] {
+        if !state.markets.contains_key(market_id) {
+            return TradeCheck::Rejected(format!("Unknown market_id: {market_id}"));
+        }
+        if let Some(pair) = state
+            .hedge_pairs
+            .iter()
+            .find(|p| &p.market_a == market_id || &p.market_b == market_id)
+        {
+            return TradeCheck::Rejected(format!(
+                "{market_id} is already paired ({} / {})",
+                pair.market_a, pair.market_b
+            ));
+        }
+    }
+    if offset_fraction <= Decimal::ZERO || offset_fraction > Decimal::ONE {
+        return TradeCheck::Rejected(format!(
+            "Hedge offset fraction must be in (0, 1], got {offset_fraction}"
+        ));
+    }
+    TradeCheck::Accepted
+}
+
+/// Validate a market to register: parameters that pass `Market::validate`, values in
+/// range, and an id not yet registered. Shared by `Engine::add_market` and
+/// `MarketAdded`.
+pub fn check_market_addition(state: &State, market: &Market) -> Result<(), MarketError> {
+    market.validate()?;
+    if let TradeCheck::Rejected(reason) = check_values(market_values(market)) {
+        return Err(MarketError::OutOfRange(reason));
+    }
+    if state.markets.contains_key(&market.market_id) {
+        return Err(MarketError::Duplicate {
+            market_id: market.market_id.clone(),
+        });
+    }
+    Ok(())
+}
+
+/// Validate a market to deregister: registered, with no open interest, i.e. no
+/// account holding a position in it, and no accrued funding left to settle. Shared by `Engine::remove_market` and
+/// `MarketRemoved`.
+pub fn check_market_removal(state: &State, market_id: &MarketId) -> Result<(), MarketError> {
+    if !state.markets.contains_key(market_id) {
+        return Err(MarketError::Unknown {
+            market_id: market_id.clone(),
+        });
+    }
+    let accounts = state.accounts_with_position_in(market_id);
+    if !accounts.is_empty() {
+        return Err(MarketError::OpenPositions {
+            market_id: market_id.clone(),
+            accounts,
+        });
+    }
+    let accounts: Vec<AccountId> = state
+        .accounts
+        .values()
+        .filter(|account| account.pending_funding.contains_key(market_id))
+        .map(|account| account.account_id.clone())
+        .collect();
+    if !accounts.is_empty() {
+        return Err(MarketError::PendingFunding {
+            market_id: market_id.clone(),
+            accounts,
+        });
+    }
+    Ok(())
+}
+
+/// Validate a `GroupCreated`: a named group that does not exist yet, a non-negative
+/// cap, and a fee rate inside (-1, 1). A group's settings never change once created.
+pub fn check_group_creation(
+    state: &State,
+    group_id: &str,
+    max_group_notional: Option<Decimal>,
+    fee_override: Option<Decimal>,
+) -> TradeCheck {
+    if group_id.is_empty() {
+        return TradeCheck::Rejected("Group id must not be empty".to_string());
+    }
+    if state.groups.contains_key(group_id) {
+        return TradeCheck::Rejected(format!("Group {group_id} already exists"));
+    }
+    if let Some(cap) = max_group_notional.filter(|cap| cap.is_sign_negative() && !cap.is_zero()) {
+        return TradeCheck::Rejected(format!(
+            "Group notional cap must not be negative, got {cap}"
+        ));
+    }
+    if let Some(rate) = fee_override.filter(|rate| rate.abs() >= Decimal::ONE) {
+        return TradeCheck::Rejected(format!("Group fee override must be in (-1, 1), got {rate}"));
+    }
+    TradeCheck::Accepted
+}
+
+/// Validate a `GroupMembershipSet`: the account and, unless it is leaving, the group
+/// must exist. Joining is not checked against the group's cap, which only refuses new
+/// risk, as `SetAccountLimits` below current exposure does.
+pub fn check_group_membership(
+    state: &State,
+    account_id: &AccountId,
+    group_id: Option<&str>,
+) -> TradeCheck {
+    if !state.accounts.contains_key(account_id) {
+        return TradeCheck::Rejected("Account does not exist".to_string());
+    }
+    match group_id {
+        Some(group_id) if !state.groups.contains_key(group_id) => {
+            TradeCheck::Rejected(format!("Unknown group_id: {group_id}"))
+        }
+        _ => TradeCheck::Accepted,
+    }
+}
+
+/// Validate a `SetPositionLeverage`: the account and market must exist, the market
+/// must offer a selection (`Market::max_leverage`), and `leverage` must lie in
+/// `[1, max_leverage]`. Raising the leverage only lowers IM, so it is always allowed;
+/// lowering it on an open position needs equity covering the full portfolio's IM at
+/// the new leverage.
+pub fn check_position_leverage(
+    state: &State,
+    account_id: &AccountId,
+    market_id: &MarketId,
+    leverage: Decimal,
+) -> TradeCheck {
+    let Some(account) = state.accounts.get(account_id) else {
+        return TradeCheck::Rejected("Account does not exist".to_string());
+    };
+    let Some(market) = state.markets.get(market_id) else {
+        return TradeCheck::Rejected(format!("Unknown market_id: {market_id}"));
+    };
+    if market.expired {
+        return TradeCheck::Rejected(format!("Market {market_id} has expired"));
+    }
+    let Some(max_leverage) = market.max_leverage else {
+        return TradeCheck::Rejected(format!("Market {market_id} offers no leverage selection"));
+    };
+    if leverage < Decimal::ONE || leverage > max_leverage {
+        return TradeCheck::Rejected(format!(
+            "Leverage {leverage} for {market_id} is outside [1, {max_leverage}]"
+        ));
+    }
+
+    let selected = account.leverage.get(market_id).copied();
+    let current = margin::initial_margin_fraction(market, selected);
+    if margin::initial_margin_fraction(market, Some(leverage)) <= current
+        || !account.positions.contains_key(market_id)
+    {
+        return TradeCheck::Accepted;
+    }
+    let mut selected = account.leverage.clone();
+    selected.insert(market_id.clone(), leverage);
+    let balance = account.collateral() + margin::accrued_funding(account, state);
+    let sim = match simulated_portfolio(state, balance, &account.positions, &selected) {
+        Ok(sim) => sim,
+        Err(reason) => return TradeCheck::Rejected(reason),
+    };
+    if sim.equity < sim.initial_margin {
+        return TradeCheck::Rejected(format!(
+            "Insufficient margin for leverage {leverage} in {market_id}: equity {} < IM required {}",
+            sim.equity, sim.initial_margin
+        ));
+    }
+    TradeCheck::Accepted
+}
+
+/// Validate a `BackstopRegistered`: an existing account, a registered market that
+/// has not expired, and a cap that is not negative. A cap of zero keeps the backstop
+/// registered but offers it nothing.
+pub fn check_backstop_registration(
+    state: &State,
+    account_id: &AccountId,
+    market_id: &MarketId,
+    max_notional: Decimal,
+) -> TradeCheck {
+    if !state.accounts.contains_key(account_id) {
+        return TradeCheck::Rejected("Account does not exist".to_string());
+    }
+    let Some(market) = state.markets.get(market_id) else {
+        return TradeCheck::Rejected(format!("Unknown market_id: {market_id}"));
+    };
+    if market.expired {
+        return TradeCheck::Rejected(format!("Market {market_id} has expired"));
+    }
+    if max_notional < Decimal::ZERO {
+        return TradeCheck::Rejected(format!(
+            "Backstop notional cap must not be negative, got {max_notional}"
+        ));
+    }
+    TradeCheck::Accepted
+}
+
+/// Validate a `StateImport`: a named pool, an account that does not exist yet, and
+/// one nonzero position per registered market at a price the market accepts. Under
+/// `ImportMarginCheck::Reject` the imported account must also be above maintenance
+/// margin.
+pub fn check_state_import(
+    state: &State,
+    account_id: &AccountId,
+    pool_id: &str,
+    collateral: Decimal,
+    positions: &[ImportedPosition],
+    margin_check: ImportMarginCheck,
+) -> TradeCheck {
+    if let TradeCheck::Rejected(reason) = check_pool_assignment(state, account_id, pool_id) {
+        return TradeCheck::Rejected(reason);
+    }
+    let mut seen = BTreeSet::new();
+    for position in positions {
+        let market_id = &position.market_id;
+        let market = match state.markets.get(market_id) {
+            Some(m) => m,
+            None => return TradeCheck::Rejected(format!("Unknown market_id: {market_id}")),
+        };
+        if !seen.insert(market_id) {
+            return TradeCheck::Rejected(format!("Duplicate imported position in {market_id}"));
+        }
+        if position.quantity.is_zero() {
+            return TradeCheck::Rejected(format!(
+                "Imported position in {market_id} has zero quantity"
+            ));
+        }
+        let Some(entry_price) = position
+            .cost_basis
+            .checked_div(position.quantity)
+            .filter(|price| price.abs() <= MAX_EVENT_VALUE)
+        else {
+            return TradeCheck::Rejected(format!(
+                "Imported position in {market_id} has an entry price out of range"
+            ));
+        };
+        if let TradeCheck::Rejected(reason) = check_price(market, entry_price) {
+            return TradeCheck::Rejected(format!("Imported entry price: {reason}"));
+        }
+    }
+
+    let account = imported_account(account_id, pool_id, collateral, positions);
+    if margin_check == ImportMarginCheck::Reject && margin::is_liquidatable(&account, state) {
+        return TradeCheck::Rejected(format!(
+            "Imported account would be liquidatable: equity {} <= MM {}",
+            margin::equity(&account, state),
+            margin::maintenance_margin_required(&account, state)
+        ));
+    }
+    TradeCheck::Accepted
+}
+
+/// The account a `StateImport` creates. Funding paid starts at zero: the engine only
+/// tracks funding it settled itself.
+pub(crate) fn imported_account(
+    account_id: &AccountId,
+    pool_id: &str,
+    collateral: Decimal,
+    positions: &[ImportedPosition],
+) -> Account {
+    let mut account = Account::in_pool(account_id.clone(), pool_id.to_string());
+    account.principal = collateral;
+    for position in positions {
+        let market_id = &position.market_id;
+        account.positions.insert(
+            market_id.clone(),
+            Position {
+                market_id: market_id.clone(),
+                quantity: position.quantity,
+                cost_basis: position.cost_basis,
+                funding_paid: Decimal::ZERO,
+            },
+        );
+        account
+            .last_funding
+            .insert(market_id.clone(), position.last_funding);
+    }
+    account
+}
+
+/// Validate an `AccountsMerged`: two different accounts in one pool, of which `from`
+/// exists, neither frozen, suspended, holding resting orders nor in liquidation, and a
+/// merged account above maintenance margin. A destination that does not exist yet is
+/// created by the merge.
+pub fn check_account_merge(state: &State, from: &AccountId, to: &AccountId) -> TradeCheck {
+    if from == to {
+        return TradeCheck::Rejected(format!("Cannot merge account {from} into itself"));
+    }
+    let Some(source) = state.accounts.get(from) else {
+        return TradeCheck::Rejected(format!("Account {from} does not exist"));
+    };
+    let destination = state.accounts.get(to);
+    for account in std::iter::once(source).chain(destination) {
+        let account_id = &account.account_id;
+        if account.frozen.is_some() || account.suspended {
+            return TradeCheck::Rejected(format!("Account {account_id} is frozen or suspended"));
+        }
+        if !account.orders.is_empty() {
+            return TradeCheck::Rejected(format!(
+                "Account {account_id} has {} resting orders",
+                account.orders.len()
+            ));
+        }
+        if state.deferred_liquidations.contains(account_id)
+            || margin::is_liquidatable(account, state)
+        {
+            return TradeCheck::Rejected(format!("Account {account_id} is in liquidation"));
+        }
+    }
+    if let Some(destination) = destination.filter(|d| d.pool_id != source.pool_id) {
+        return TradeCheck::Rejected(format!(
+            "Accounts {from} and {to} are in different pools ({} and {})",
+            source.pool_id, destination.pool_id
+        ));
+    }
+    let merged = merged_account(state, source, to);
+    if margin::is_liquidatable(&merged, state) {
+        return TradeCheck::Rejected(format!(
+            "Merged account would be below maintenance margin: equity {} < MM {}",
+            margin::equity(&merged, state),
+            margin::maintenance_margin_required(&merged, state)
+        ));
+    }
+    TradeCheck::Accepted
+}
+
+/// The account `to` becomes when `source` merges into it, starting from an empty
+/// account in the source's pool if `to` does not exist.
+///
+/// Balances, pending funding and lifetime funding totals add up. So do positions on
+/// the same side of a market, with their cost bases and funding paid. Opposite
+/// positions net: the smaller closes against the larger at the larger's average
+/// entry (see `net_position`). A held market's funding checkpoint is the more
+/// conservative of the holders' two, the lower index for a net long and the higher
+/// for a net short, so the next settlement never credits the merged position more
+/// than either side would have received. Limits, group, alert level and metadata
+/// stay the destination's, and so does its leverage where it selected one.
+pub(crate) fn merged_account(state: &State, source: &Account, to: &AccountId) -> Account {
+    let mut merged = state
+        .accounts
+        .get(to)
+        .cloned()
+        .unwrap_or_else(|| Account::in_pool(to.clone(), source.pool_id.clone()));
+    merged.principal += source.principal;
+    merged.trading_balance += source.trading_balance;
+    for (market_id, amount) in &source.pending_funding {
+        let pending = merged.pending_funding.entry(market_id.clone()).or_default();
+        *pending += amount;
+        if pending.is_zero() {
+            merged.pending_funding.remove(market_id);
+        }
+    }
+    for (market_id, paid) in &source.funding_paid {
+        *merged.funding_paid.entry(market_id.clone()).or_default() += paid;
+    }
+    for (market_id, leverage) in &source.leverage {
+        merged
+            .leverage
+            .entry(market_id.clone())
+            .or_insert(*leverage);
+    }
+
+    for (market_id, position) in &source.positions {
+        let reached = state
+            .markets
+            .get(market_id)
+            .map_or(Decimal::ZERO, Market::funding_index_reached);
+        let checkpoint = |account: &Account| {
+            account
+                .last_funding
+                .get(market_id)
+                .copied()
+                .unwrap_or(reached)
+        };
+        let mut indices = vec![checkpoint(source)];
+        if merged.positions.contains_key(market_id) {
+            indices.push(checkpoint(&merged));
+        }
+        merged.trading_balance += net_position(&mut merged.positions, position);
+        if let Some(held) = merged.positions.get(market_id) {
+            let index = if held.quantity.is_sign_positive() {
+                indices.into_iter().min()
+            } else {
+                indices.into_iter().max()
+            };
+            merged
+                .last_funding
+                .insert(market_id.clone(), index.unwrap_or(reached));
+        }
+    }
+    merged
+}
+
+/// Net `incoming` into the position `positions` hold in its market, returning the
+/// PnL realized.
+///
+/// Same-side positions add. Opposite ones close the smaller against the larger at
+/// the larger's average entry, and the rest keeps that entry and the larger's funding
+/// paid. Whatever the mark, the merged equity is the two accounts' equity summed:
+/// realized PnL and cost basis move by the same amount. As in a partial close, the
+/// PnL is exact whenever the entry terminates, and otherwise is floored to collateral
+/// precision with the remainder left in the cost basis.
+fn net_position(positions: &mut BTreeMap<MarketId, Position>, incoming: &Position) -> Decimal {
+    let market_id = &incoming.market_id;
+    let Some(held) = positions.get_mut(market_id) else {
+        positions.insert(market_id.clone(), incoming.clone());
+        return Decimal::ZERO;
+    };
+    if held.quantity.is_sign_positive() == incoming.quantity.is_sign_positive() {
+        held.quantity += incoming.quantity;
+        held.cost_basis += incoming.cost_basis;
+        held.funding_paid += incoming.funding_paid;
+        return Decimal::ZERO;
+    }
+
+    let quantity = held.quantity + incoming.quantity;
+    if quantity.is_zero() {
+        let realized_pnl = -(held.cost_basis + incoming.cost_basis);
+        positions.remove(market_id);
+        return realized_pnl;
+    }
+    let (larger, smaller) = if held.quantity.abs() > incoming.quantity.abs() {
+        (held.clone(), incoming)
+    } else {
+        (incoming.clone(), &*held)
+    };
+    //   closed_cost = larger_cost * |smaller| / |larger|   (the larger's entry, on the smaller's size)
+    //   realized    = -(closed_cost + smaller_cost)
+    let closed_cost = larger.cost_basis * smaller.quantity.abs() / larger.quantity.abs();
+    let realized_pnl = (-(closed_cost + smaller.cost_basis)).round_dp_with_strategy(
+        margin::COLLATERAL_DECIMALS,
+        RoundingStrategy::ToNegativeInfinity,
+    );
+    let cost_basis = larger.cost_basis + smaller.cost_basis + realized_pnl;
+    *held = Position {
+        market_id: market_id.clone(),
+        quantity,
+        cost_basis,
+        funding_paid: larger.funding_paid,
+    };
+    realized_pnl
+}
+
+/// Validate a `PositionTransfer`: two different accounts in one pool, neither frozen,
+/// a market with a mark that takes `price`, and a `quantity` that closes part or all
+/// of `from`'s position there. Each side is then checked as the transfer leaves it
+/// (see `transferred_accounts`). A side whose fill only reduces its position must
+/// stay above maintenance margin, and a flat one must not be left with negative
+/// equity. A side whose fill opens, adds to or flips a position must cover its
+/// initial margin. The rejection names the side that fails.
+pub fn check_position_transfer(
+    state: &State,
+    from: &AccountId,
+    to: &AccountId,
+    market_id: &MarketId,
+    quantity: Decimal,
+    price: Decimal,
+) -> TradeCheck {
+    if from == to {
+        return TradeCheck::Rejected(format!(
+            "Cannot transfer a position from account {from} to itself"
+        ));
+    }
+    let Some(market) = state.markets.get(market_id) else {
+        return TradeCheck::Rejected(format!("Unknown market_id: {market_id}"));
+    };
+    if market.last_mark_sequence.is_none() {
+        return TradeCheck::Rejected(format!("Market {market_id} has no mark price yet"));
+    }
+    if let TradeCheck::Rejected(reason) = check_price(market, price) {
+        return TradeCheck::Rejected(reason);
+    }
+    let (Some(source), Some(destination)) = (state.accounts.get(from), state.accounts.get(to))
+    else {
+        let missing = if state.accounts.contains_key(from) {
+            to
+        } else {
+            from
+        };
+        return TradeCheck::Rejected(format!("Account {missing} does not exist"));
+    };
+    for account in [source, destination] {
+        if let TradeCheck::Rejected(reason) = check_not_frozen(account) {
+            return TradeCheck::Rejected(reason);
+        }
+    }
+    if source.pool_id != destination.pool_id {
+        return TradeCheck::Rejected(format!(
+            "Accounts {from} and {to} are in different pools ({} and {})",
+            source.pool_id, destination.pool_id
+        ));
+    }
+
+    let held = |account: &Account| {
+        account
+            .positions
+            .get(market_id)
+            .map_or(Decimal::ZERO, |p| p.quantity)
+    };
+    let (source_held, destination_held) = (held(source), held(destination));
+    if !classify_fill(source_held, -quantity).is_risk_reducing() {
+        return TradeCheck::Rejected(format!(
+            "Transfer quantity {quantity} is not part of {from}'s position {source_held} in {market_id}"
+        ));
+    }
+    if let TradeCheck::Rejected(reason) = check_step(market, source_held, -quantity) {
+        return TradeCheck::Rejected(reason);
+    }
+    if let TradeCheck::Rejected(reason) =
+        check_position_size(market_id, destination_held + quantity)
+    {
+        return TradeCheck::Rejected(reason);
+    }
+
+    let [(source_after, _), (destination_after, _)] =
+        transferred_accounts(state, from, to, market_id, quantity, price);
+    let sides = [
+        ("source", from, source_after, true),
+        (
+            "destination",
+            to,
+            destination_after,
+            classify_fill(destination_held, quantity).is_risk_reducing(),
+        ),
+    ];
+    for (side, account_id, after, reducing) in sides {
+        let equity = margin::equity(&after, state);
+        if reducing {
+            let maintenance_margin = margin::maintenance_margin_required(&after, state);
+            if margin::is_liquidatable(&after, state) || equity < Decimal::ZERO {
+                return TradeCheck::Rejected(format!(
+                    "Transfer {side} {account_id} fails MM after transfer: equity {equity} <= MM {maintenance_margin}"
+                ));
+            }
+        } else {
+            let initial_margin = margin::initial_margin_required(&after, state);
+            if equity < initial_margin {
+                return TradeCheck::Rejected(format!(
+                    "Transfer {side} {account_id} fails IM after transfer: equity {equity} < IM required {initial_margin}"
+                ));
+            }
+        }
+    }
+    TradeCheck::Accepted
+}
+
+/// `from` and `to` as a `PositionTransfer` of `quantity` at `price` leaves them, each
+/// with the pending funding it settled.
+///
+/// Each first settles its funding pending in the market, so what `from`'s position
+/// accrued before the transfer stays with `from`. Then `from` fills `-quantity` and
+/// `to` fills `quantity` at `price`, through `apply_trade_to` like any trade. The
+/// cash one side's fill exchanges is the other's, so the two accounts' collateral
+/// less cost basis sums to what it did, exactly.
+pub(crate) fn transferred_accounts(
+    state: &State,
+    from: &AccountId,
+    to: &AccountId,
+    market_id: &MarketId,
+    quantity: Decimal,
+    price: Decimal,
+) -> [(Account, Decimal); 2] {
+    [(from, -quantity), (to, quantity)].map(|(account_id, fill)| {
+        let mut account = state.accounts[account_id].clone();
+        let funding = account.settle_pending_funding(market_id);
+        apply_trade_to(
+            &mut account.trading_balance,
+            &mut account.positions,
+            market_id,
+            fill,
+            price,
+        );
+        (account, funding)
+    })
+}
+
+/// What a fill does to the position it lands on. This is the engine's one rule for
+/// "reduce" versus "increase": the pre-trade check, trade application and the
+/// takeover and liquidation fill checks all go through `classify_fill`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FillClass {
+    /// No position before the fill.
+    Open,
+    /// Same direction as the position, or a zero fill on one.
+    Increase,
+    /// Opposite direction and smaller than the position: it stays on the same side.
+    Reduce,
+    /// Opposite direction and exactly the size of the position.
+    Close,
+    /// Opposite direction and larger than the position: it closes it and opens the
+    /// remainder on the other side.
+    Flip,
+}
+
+impl FillClass {
+    /// Whether the fill only takes risk off. A flip does not count, since it opens a
+    /// new position, so only `Reduce` and `Close` are let through a failing margin
+    /// check or a closed session.
+    pub fn is_risk_reducing(self) -> bool {
+        matches!(self, FillClass::Reduce | FillClass::Close)
+    }
+}
+
+/// Classify a fill of `fill_qty` against a position of `current_qty` (both signed).
+///
+/// Edge cases:
+/// - `current_qty` zero: always `Open`, whatever the fill, including a zero fill.
+/// - a zero fill on a position: `Increase`. It takes no risk off, so it is checked as
+///   an increase, and applying it changes nothing.
+/// - a fill exactly the opposite of the position: `Close`, never `Flip`.
+/// - a fill overshooting the position: `Flip`, however small the overshoot.
+pub fn classify_fill(current_qty: Decimal, fill_qty: Decimal) -> FillClass {
     if current_qty.is_zero() {
-        return false;
+        return FillClass::Open;
+    }
+    if fill_qty.is_zero() || fill_qty.is_sign_negative() == current_qty.is_sign_negative() {
+        return FillClass::Increase;
+    }
+    match fill_qty.abs().cmp(&current_qty.abs()) {
+        Ordering::Less => FillClass::Reduce,
+        Ordering::Equal => FillClass::Close,
+        Ordering::Greater => FillClass::Flip,
+    }
+}
+
+/// Validate a mark or fill price against the market's price semantics.
+/// Prices must be strictly positive unless the market sets `allow_negative_prices`,
+/// in which case zero and negative prices are accepted. An expired market takes no
+/// price at all.
+pub fn check_price(market: &Market, price: Decimal) -> TradeCheck {
+    if market.expired {
+        TradeCheck::Rejected(format!("Market {} has expired", market.market_id))
+    } else if market.allow_negative_prices || price > Decimal::ZERO {
+        TradeCheck::Accepted
+    } else {
+        TradeCheck::Rejected(format!(
+            "Non-positive price {price} not allowed for {}",
+            market.market_id
+        ))
+    }
+}
+
+/// Leading text of every trade rejection caused by a custom stage of
+/// `EngineConfig::risk_checks`, followed by the stage's name. `RejectReason::from_event`
+/// keys on it, so it is part of the log format.
+pub const RISK_CHECK: &str = "Rejected by risk check";
+
+/// One stage of the pre-trade pipeline. `check_trade_with` runs the built-in stages
+/// (market, position-size, reduce-only, stale-mark, open-interest, skew, margin,
+/// account-limits, group-notional) and then the custom ones of
+/// `EngineConfig::risk_checks`, in that order; the first rejection wins.
+///
+/// Stages run on replay exactly as live, so a stage must decide from its context
+/// alone: no clocks, randomness or outside state.
+pub trait RiskCheck: Send + Sync {
+    /// Names the stage in the rejections it causes and in the logged config.
+    fn name(&self) -> &str;
+
+    fn check(&self, ctx: &TradeContext) -> TradeCheck;
+
+    /// Whether the stage also screens risk-reducing fills. The built-in stages after
+    /// `market` do not, which is what lets a reducing fill through any of them.
+    fn checks_reducing(&self) -> bool {
+        true
+    }
+}
+
+/// A fill under pre-trade check, as each `RiskCheck` sees it.
+pub struct TradeContext<'a> {
+    pub state: &'a State,
+    pub config: &'a EngineConfig,
+    pub account: &'a Account,
+    pub market: &'a Market,
+    pub quantity: Decimal,
+    pub price: Decimal,
+    /// The account's position in the market before the fill.
+    pub current_quantity: Decimal,
+    /// Whether the fill only reduces or closes that position (see `classify_fill`).
+    pub risk_reducing: bool,
+    /// The account's portfolio as the fill would leave it, or why it cannot be
+    /// valued (a position in a market the state does not know).
+    pub post_trade: Result<SimulatedPortfolio, String>,
+}
+
+/// The built-in stages, in the order they run.
+const BUILT_IN_CHECKS: [&dyn RiskCheck; 9] = [
+    &MarketCheck,
+    &PositionSizeCheck,
+    &ReduceOnlyCheck,
+    &StaleMarkCheck,
+    &OpenInterestCheck,
+    &SkewCheck,
+    &MarginCheck,
+    &AccountLimitsCheck,
+    &GroupNotionalCheck,
+];
+
+/// The market has a mark, has not expired, and takes the fill price and quantity.
+struct MarketCheck;
+
+impl RiskCheck for MarketCheck {
+    fn name(&self) -> &str {
+        "market"
+    }
+
+    fn check(&self, ctx: &TradeContext) -> TradeCheck {
+        let market_id = &ctx.market.market_id;
+        // A market that has never received a mark cannot be margined. Tracked separately
+        // from mark_price because zero is a legitimate mark on negative-price markets.
+        if ctx.market.last_mark_sequence.is_none() {
+            return TradeCheck::Rejected(format!("Market {market_id} has no mark price yet"));
+        }
+        if ctx.market.expired {
+            return TradeCheck::Rejected(format!("{MARKET_CLOSED}: {market_id} has expired"));
+        }
+        let on_step = check_step(ctx.market, ctx.current_quantity, ctx.quantity);
+        if let TradeCheck::Rejected(reason) = on_step {
+            return TradeCheck::Rejected(reason);
+        }
+        check_price(ctx.market, ctx.price)
+    }
+}
+
+struct PositionSizeCheck;
+
+impl RiskCheck for PositionSizeCheck {
+    fn name(&self) -> &str {
+        "position-size"
+    }
+
+    fn check(&self, ctx: &TradeContext) -> TradeCheck {
+        check_position_size(&ctx.market.market_id, ctx.current_quantity + ctx.quantity)
+    }
+
+    fn checks_reducing(&self) -> bool {
+        false
+    }
+}
+
+/// Closed sessions, suspensions after bankruptcy and liquidatable accounts, which all
+/// accept reducing fills only.
+struct ReduceOnlyCheck;
+
+impl RiskCheck for ReduceOnlyCheck {
+    fn name(&self) -> &str {
+        "reduce-only"
+    }
+
+    fn check(&self, ctx: &TradeContext) -> TradeCheck {
+        let market_id = &ctx.market.market_id;
+        if ctx.market.session_closed {
+            return TradeCheck::Rejected(format!(
+                "{MARKET_CLOSED}: {market_id} is outside its trading session; only reducing fills are accepted"
+            ));
+        }
+        if let TradeCheck::Rejected(reason) =
+            check_not_suspended(ctx.account, market_id, ctx.config.bankruptcy_suspension)
+        {
+            return TradeCheck::Rejected(reason);
+        }
+        check_not_liquidatable(ctx.account, ctx.state)
+    }
+
+    fn checks_reducing(&self) -> bool {
+        false
+    }
+}
+
+struct StaleMarkCheck;
+
+impl RiskCheck for StaleMarkCheck {
+    fn name(&self) -> &str {
+        "stale-mark"
+    }
+
+    fn check(&self, ctx: &TradeContext) -> TradeCheck {
+        let market = ctx.market;
+        if market.stale {
+            return TradeCheck::Rejected(format!(
+                "Market {} mark is stale: last mark at {} ms, clock {} ms, threshold {} ms",
+                market.market_id,
+                market.last_mark_timestamp.unwrap_or_default(),
+                ctx.state.clock.unwrap_or_default(),
+                market.staleness_threshold_ms.unwrap_or_default()
+            ));
+        }
+        TradeCheck::Accepted
+    }
+
+    fn checks_reducing(&self) -> bool {
+        false
+    }
+}
+
+struct OpenInterestCheck;
+
+impl RiskCheck for OpenInterestCheck {
+    fn name(&self) -> &str {
+        "open-interest"
+    }
+
+    fn check(&self, ctx: &TradeContext) -> TradeCheck {
+        let new_qty = ctx.current_quantity + ctx.quantity;
+        if let TradeCheck::Rejected(reason) =
+            check_open_interest(ctx.state, ctx.market, ctx.current_quantity, new_qty)
+        {
+            return TradeCheck::Rejected(reason);
+        }
+        check_total_open_interest(
+            ctx.state,
+            ctx.config.max_total_open_interest_notional,
+            ctx.market,
+            ctx.current_quantity,
+            new_qty,
+        )
+    }
+
+    fn checks_reducing(&self) -> bool {
+        false
+    }
+}
+
+/// Under `SkewResponse::ReduceOnly`, new exposure on the heavy side of a market whose
+/// skew limit is breached: a fill that leaves the account longer when longs are heavy,
+/// or shorter when shorts are.
+struct SkewCheck;
+
+impl RiskCheck for SkewCheck {
+    fn name(&self) -> &str {
+        "skew"
+    }
+
+    fn check(&self, ctx: &TradeContext) -> TradeCheck {
+        if ctx.config.skew_response != SkewResponse::ReduceOnly || !ctx.market.skew_breached {
+            return TradeCheck::Accepted;
+        }
+        let net = ctx.state.net_open_interest(&ctx.market.market_id);
+        let long_heavy = net.is_sign_positive();
+        let heavy =
+            |quantity: Decimal| if long_heavy { quantity } else { -quantity }.max(Decimal::ZERO);
+        if heavy(ctx.current_quantity + ctx.quantity) <= heavy(ctx.current_quantity) {
+            return TradeCheck::Accepted;
+        }
+        let side = if long_heavy { "long" } else { "short" };
+        TradeCheck::Rejected(format!(
+            "{SKEW_LIMIT}: {} is {side}-heavy at net open interest {net}; \
+             fills adding {side} exposure are rejected",
+            ctx.market.market_id
+        ))
+    }
+
+    fn checks_reducing(&self) -> bool {
+        false
+    }
+}
+
+/// Post-trade equity against margin, over the full portfolio (cross-margin), under
+/// `EngineConfig::trade_margin_policy`.
+struct MarginCheck;
+
+impl RiskCheck for MarginCheck {
+    fn name(&self) -> &str {
+        "margin"
+    }
+
+    fn check(&self, ctx: &TradeContext) -> TradeCheck {
+        let sim = match &ctx.post_trade {
+            Ok(sim) => sim,
+            Err(reason) => return TradeCheck::Rejected(reason.clone()),
+        };
+        match ctx.config.trade_margin_policy {
+            TradeMarginPolicy::FullPortfolio => {
+                if sim.equity < sim.initial_margin {
+                    return TradeCheck::Rejected(format!(
+                        "Insufficient margin: equity {} < IM required {}",
+                        sim.equity, sim.initial_margin
+                    ));
+                }
+            }
+            TradeMarginPolicy::IncrementalIm => {
+                let stock_mm = margin::maintenance_margin_required(ctx.account, ctx.state);
+                let delta_im = (sim.initial_margin
+                    - margin::initial_margin_required(ctx.account, ctx.state))
+                .max(Decimal::ZERO);
+                if sim.equity < stock_mm + delta_im {
+                    return TradeCheck::Rejected(format!(
+                        "Insufficient margin: equity {} < MM on existing positions {} + incremental IM {}",
+                        sim.equity, stock_mm, delta_im
+                    ));
+                }
+            }
+        }
+        TradeCheck::Accepted
+    }
+
+    fn checks_reducing(&self) -> bool {
+        false
+    }
+}
+
+struct AccountLimitsCheck;
+
+impl RiskCheck for AccountLimitsCheck {
+    fn name(&self) -> &str {
+        "account-limits"
+    }
+
+    fn check(&self, ctx: &TradeContext) -> TradeCheck {
+        match &ctx.post_trade {
+            Ok(sim) => check_account_limits(&ctx.account.limits, sim.notional, sim.equity),
+            Err(reason) => TradeCheck::Rejected(reason.clone()),
+        }
     }
-    let new_qty = current_qty + fill_qty;
 
-    // Reducing: same sign as before, but smaller absolute size (or closes exactly to zero).
-    // Flips are NOT considered risk-reducing for the "always allow" rule.
-    new_qty.is_zero()
-        || (new_qty.signum() == current_qty.signum() && new_qty.abs() < current_qty.abs())
+    fn checks_reducing(&self) -> bool {
+        false
+    }
+}
+
+struct GroupNotionalCheck;
+
+impl RiskCheck for GroupNotionalCheck {
+    fn name(&self) -> &str {
+        "group-notional"
+    }
+
+    fn check(&self, ctx: &TradeContext) -> TradeCheck {
+        match &ctx.post_trade {
+            Ok(sim) => check_group_notional(ctx.state, ctx.account, sim.notional),
+            Err(reason) => TradeCheck::Rejected(reason.clone()),
+        }
+    }
+
+    fn checks_reducing(&self) -> bool {
+        false
+    }
 }
 
-/// Simulate post-trade state and check initial margin.
+/// Simulate post-trade state and check initial margin over the full portfolio.
 pub fn check_trade(
     state: &State,
     account_id: &AccountId,
     market_id: &MarketId,
     fill_quantity: Decimal,
     fill_price: Decimal,
+) -> TradeCheck {
+    check_trade_with(
+        state,
+        account_id,
+        market_id,
+        fill_quantity,
+        fill_price,
+        &EngineConfig::default(),
+    )
+}
+
+/// `check_trade` under the trade rules of `config` (`trade_margin_policy`,
+/// `bankruptcy_suspension`), followed by its custom `risk_checks`. A custom stage's
+/// rejection is reported as `RISK_CHECK`, the stage's name and its reason.
+pub fn check_trade_with(
+    state: &State,
+    account_id: &AccountId,
+    market_id: &MarketId,
+    fill_quantity: Decimal,
+    fill_price: Decimal,
+    config: &EngineConfig,
+) -> TradeCheck {
+    let _span = trace::span!(
+        "check_trade",
+        account_id = %account_id,
+        market_id = %market_id,
+        quantity = %fill_quantity,
+        price = %fill_price
+    );
+    let check = trade_verdict(
+        state,
+        account_id,
+        market_id,
+        fill_quantity,
+        fill_price,
+        config,
+    );
+    #[cfg(feature = "trace")]
+    match &check {
+        TradeCheck::Accepted => trace::event!(accepted = true, "trade check"),
+        TradeCheck::Rejected(reason) => {
+            trace::event!(accepted = false, reason = %reason, "trade check")
+        }
+    }
+    check
+}
+
+/// The body of `check_trade_with`.
+fn trade_verdict(
+    state: &State,
+    account_id: &AccountId,
+    market_id: &MarketId,
+    fill_quantity: Decimal,
+    fill_price: Decimal,
+    config: &EngineConfig,
 ) -> TradeCheck {
     let account = match state.accounts.get(account_id) {
         Some(a) => a,
@@ -41,88 +1318,472 @@ pub fn check_trade(
         }
     };
 
-    // Market must exist (configured out-of-band)
-    if !state.markets.contains_key(market_id) {
-        return TradeCheck::Rejected(format!("Unknown market_id: {market_id}"));
+    if let TradeCheck::Rejected(reason) = check_not_frozen(account) {
+        return TradeCheck::Rejected(reason);
     }
 
-    let current_qty = account
+    // Market must exist (configured out-of-band)
+    let market = match state.markets.get(market_id) {
+        Some(m) => m,
+        None => return TradeCheck::Rejected(format!("Unknown market_id: {market_id}")),
+    };
+
+    let current_quantity = account
         .positions
         .get(market_id)
         .map(|p| p.quantity)
         .unwrap_or(Decimal::ZERO);
+    let (sim_collateral, sim_positions) =
+        simulate_trade(account, market_id, fill_quantity, fill_price);
+    let ctx = TradeContext {
+        state,
+        config,
+        account,
+        market,
+        quantity: fill_quantity,
+        price: fill_price,
+        current_quantity,
+        risk_reducing: classify_fill(current_quantity, fill_quantity).is_risk_reducing(),
+        post_trade: simulated_portfolio(
+            state,
+            sim_collateral + margin::accrued_funding(account, state),
+            &sim_positions,
+            &account.leverage,
+        ),
+    };
+    #[cfg(feature = "trace")]
+    if let Ok(post) = &ctx.post_trade {
+        trace::event!(
+            equity = %post.equity,
+            initial_margin = %post.initial_margin,
+            notional = %post.notional,
+            "post-trade portfolio"
+        );
+    }
 
-    // Risk-reducing trades are always allowed
-    if is_risk_reducing(current_qty, fill_quantity) {
-        return TradeCheck::Accepted;
+    for stage in BUILT_IN_CHECKS {
+        if let TradeCheck::Rejected(reason) = run_stage(stage, &ctx) {
+            return TradeCheck::Rejected(reason);
+        }
+    }
+    for stage in config.risk_checks.iter() {
+        if let TradeCheck::Rejected(reason) = run_stage(stage, &ctx) {
+            return TradeCheck::Rejected(format!("{RISK_CHECK} {}: {reason}", stage.name()));
+        }
     }
+    TradeCheck::Accepted
+}
 
-    // Simulate post-trade state
-    let (sim_collateral, sim_positions) =
-        simulate_trade(account, market_id, fill_quantity, fill_price);
+/// `stage`'s verdict on the fill in `ctx`, which passes a stage that does not screen
+/// risk-reducing fills when it is one.
+fn run_stage(stage: &dyn RiskCheck, ctx: &TradeContext) -> TradeCheck {
+    if ctx.risk_reducing && !stage.checks_reducing() {
+        TradeCheck::Accepted
+    } else {
+        stage.check(ctx)
+    }
+}
+
+/// Margin figures for a simulated (not yet committed) portfolio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimulatedPortfolio {
+    pub equity: Decimal,
+    pub initial_margin: Decimal,
+    /// Gross notional over every position.
+    pub notional: Decimal,
+}
 
-    // Compute simulated equity and IM over the FULL portfolio (cross-margin)
-    let mut sim_unrealized = Decimal::ZERO;
-    let mut sim_im = Decimal::ZERO;
+/// Evaluate equity, IM and gross notional over a full simulated portfolio, each
+/// position margined at its selected `leverage` if any. `balance` is the simulated
+/// collateral plus `margin::accrued_funding`, which no fill changes.
+fn simulated_portfolio(
+    state: &State,
+    balance: Decimal,
+    positions: &BTreeMap<MarketId, Position>,
+    leverage: &BTreeMap<MarketId, Decimal>,
+) -> Result<SimulatedPortfolio, String> {
+    let mut unrealized = Decimal::ZERO;
+    let mut initial_margin = Decimal::ZERO;
+    let mut notional = Decimal::ZERO;
 
-    for (mid, pos) in sim_positions.iter() {
+    for (mid, pos) in positions.iter() {
         let market = match state.markets.get(mid) {
             Some(m) => m,
             None => {
                 // Deterministic rejection instead of panicking
-                return TradeCheck::Rejected(format!("Unknown market in portfolio: {mid}"));
+                return Err(format!("Unknown market in portfolio: {mid}"));
             }
         };
 
-        sim_unrealized += margin::position_unrealized_pnl(pos.quantity, pos.cost_basis, market.mark_price);
-        sim_im += margin::position_notional(pos.quantity, market.mark_price) * market.initial_margin_fraction;
+        unrealized +=
+            margin::position_unrealized_pnl(pos.quantity, pos.cost_basis, market.mark_price);
+        let selected = leverage.get(mid).copied();
+        initial_margin += margin::position_initial_margin_with(pos.quantity, market, selected);
+        notional += margin::position_notional(pos.quantity, market.mark_price);
     }
 
-    let sim_equity = sim_collateral + sim_unrealized;
+    Ok(SimulatedPortfolio {
+        equity: balance + unrealized,
+        initial_margin: initial_margin - margin::hedge_offset(positions, leverage, state).initial,
+        notional,
+    })
+}
 
-    if sim_equity >= sim_im {
-        TradeCheck::Accepted
-    } else {
-        TradeCheck::Rejected(format!(
-            "Insufficient margin: equity {sim_equity} < IM required {sim_im}"
-        ))
+/// Validate a keeper takeover of (part of) a liquidatable account's position.
+///
+/// `quantity` is the close fill from the liquidated account's perspective; the keeper
+/// receives `-quantity`. The price must be exactly the market's takeover price (mark
+/// adjusted by `liquidation_discount` in the keeper's favor), and the keeper must pass
+/// its own IM check on the post-takeover portfolio.
+pub fn check_takeover(
+    state: &State,
+    liquidated_account: &AccountId,
+    keeper_account: &AccountId,
+    market_id: &MarketId,
+    quantity: Decimal,
+    price: Decimal,
+) -> TradeCheck {
+    if liquidated_account == keeper_account {
+        return TradeCheck::Rejected("Keeper cannot take over its own position".to_string());
+    }
+
+    let market = match state.markets.get(market_id) {
+        Some(m) => m,
+        None => return TradeCheck::Rejected(format!("Unknown market_id: {market_id}")),
+    };
+
+    let liquidated = match state.accounts.get(liquidated_account) {
+        Some(a) => a,
+        None => return TradeCheck::Rejected("Liquidated account does not exist".to_string()),
+    };
+    if !margin::is_liquidatable(liquidated, state) {
+        return TradeCheck::Rejected(format!("Account {liquidated_account} is not liquidatable"));
+    }
+
+    let position_qty = liquidated
+        .positions
+        .get(market_id)
+        .map(|p| p.quantity)
+        .unwrap_or(Decimal::ZERO);
+    if !classify_fill(position_qty, quantity).is_risk_reducing() {
+        return TradeCheck::Rejected(format!(
+            "Takeover quantity {quantity} does not close position {position_qty} in {market_id}"
+        ));
+    }
+
+    if let TradeCheck::Rejected(reason) = check_step(market, position_qty, quantity) {
+        return TradeCheck::Rejected(reason);
+    }
+
+    let keeper_qty = -quantity;
+    let expected_price = liquidation::takeover_price(market, keeper_qty);
+    if price != expected_price {
+        return TradeCheck::Rejected(format!(
+            "Takeover price {price} != expected {expected_price}"
+        ));
+    }
+
+    let keeper = match state.accounts.get(keeper_account) {
+        Some(a) => a,
+        None => return TradeCheck::Rejected("Keeper account does not exist".to_string()),
+    };
+    if let TradeCheck::Rejected(reason) = check_not_frozen(keeper) {
+        return TradeCheck::Rejected(reason);
+    }
+    // The discount the keeper earns is the liquidated account's loss.
+    if keeper.pool_id != liquidated.pool_id {
+        return TradeCheck::Rejected(format!(
+            "Keeper {keeper_account} is in pool {}, not {}; takeovers cannot cross pools",
+            keeper.pool_id, liquidated.pool_id
+        ));
+    }
+
+    let keeper_position = keeper
+        .positions
+        .get(market_id)
+        .map_or(Decimal::ZERO, |p| p.quantity);
+    if let TradeCheck::Rejected(reason) =
+        check_position_size(market_id, keeper_position + keeper_qty)
+    {
+        return TradeCheck::Rejected(reason);
+    }
+
+    let mut sim_collateral = keeper.collateral();
+    let mut sim_positions = keeper.positions.clone();
+    liquidation::apply_keeper_side(
+        &mut sim_collateral,
+        &mut sim_positions,
+        market,
+        keeper_qty,
+        price,
+    );
+
+    let balance = sim_collateral + margin::accrued_funding(keeper, state);
+    let sim = match simulated_portfolio(state, balance, &sim_positions, &keeper.leverage) {
+        Ok(sim) => sim,
+        Err(reason) => return TradeCheck::Rejected(reason),
+    };
+
+    if sim.equity < sim.initial_margin {
+        return TradeCheck::Rejected(format!(
+            "Keeper {keeper_account} fails IM after takeover: equity {} < IM required {}",
+            sim.equity, sim.initial_margin
+        ));
+    }
+
+    TradeCheck::Accepted
+}
+
+/// Check the market's open interest cap for a fill that moves one account's position
+/// in `market` from `current_qty` to `new_qty`.
+///
+/// Post-trade OI = current OI - |current_qty| + |new_qty|, which is exact for opens,
+/// increases, partial closes and flips alike (a flip removes the old position and
+/// adds the remainder on the other side).
+fn check_open_interest(
+    state: &State,
+    market: &Market,
+    current_qty: Decimal,
+    new_qty: Decimal,
+) -> TradeCheck {
+    if let Some(cap) = market.max_open_interest_notional {
+        let mark = market.mark_price.abs();
+        let current_oi = state.open_interest(&market.market_id) * mark;
+        let post_oi = current_oi + (new_qty.abs() - current_qty.abs()) * mark;
+        if post_oi > cap {
+            return TradeCheck::Rejected(format!(
+                "Open interest cap exceeded for {}: post-trade OI {post_oi} > cap {cap} (current OI {current_oi})",
+                market.market_id
+            ));
+        }
+    }
+
+    TradeCheck::Accepted
+}
+
+/// Check the venue-wide open interest cap, `cap`, for the same fill as
+/// `check_open_interest`. Only `market`'s share of the total moves, by the same
+/// `(|new_qty| - |current_qty|) × |mark|`.
+fn check_total_open_interest(
+    state: &State,
+    cap: Option<Decimal>,
+    market: &Market,
+    current_qty: Decimal,
+    new_qty: Decimal,
+) -> TradeCheck {
+    if let Some(cap) = cap {
+        let current_oi = state.total_open_interest_notional();
+        let post_oi = current_oi + (new_qty.abs() - current_qty.abs()) * market.mark_price.abs();
+        if post_oi > cap {
+            return TradeCheck::Rejected(format!(
+                "Total open interest cap exceeded: post-trade OI {post_oi} > cap {cap} (current OI {current_oi})"
+            ));
+        }
+    }
+
+    TradeCheck::Accepted
+}
+
+/// Check the cap of the account's group for a fill that takes the account's gross
+/// notional to `sim_notional`. The other members' notional is unchanged by the fill.
+fn check_group_notional(state: &State, account: &Account, sim_notional: Decimal) -> TradeCheck {
+    let Some((group_id, cap)) = account.group_id.as_ref().and_then(|group_id| {
+        let cap = state.groups.get(group_id)?.max_group_notional?;
+        Some((group_id, cap))
+    }) else {
+        return TradeCheck::Accepted;
+    };
+    let current = margin::group_notional(group_id, state);
+    let post = current - margin::total_notional(account, state) + sim_notional;
+    if post > cap {
+        return TradeCheck::Rejected(format!(
+            "Group notional cap exceeded for {group_id}: post-trade notional {post} > cap {cap} (current {current})"
+        ));
+    }
+    TradeCheck::Accepted
+}
+
+/// Check a simulated post-trade portfolio against the account's compliance limits.
+fn check_account_limits(
+    limits: &AccountLimits,
+    sim_notional: Decimal,
+    sim_equity: Decimal,
+) -> TradeCheck {
+    if let Some(max_notional) = limits.max_total_notional {
+        if sim_notional > max_notional {
+            return TradeCheck::Rejected(format!(
+                "Notional limit exceeded: total notional {sim_notional} > max {max_notional} (by {})",
+                sim_notional - max_notional
+            ));
+        }
+    }
+
+    if let Some(max_leverage) = limits.max_leverage {
+        match margin::leverage(sim_notional, sim_equity) {
+            Some(lev) if lev <= max_leverage => {}
+            Some(lev) => {
+                return TradeCheck::Rejected(format!(
+                    "Leverage limit exceeded: leverage {lev} > max {max_leverage} (by {})",
+                    lev - max_leverage
+                ));
+            }
+            None => {
+                return TradeCheck::Rejected(format!(
+                    "Leverage limit exceeded: non-positive equity {sim_equity} with notional {sim_notional} > max {max_leverage}"
+                ));
+            }
+        }
     }
+
+    TradeCheck::Accepted
 }
 
-/// Check whether a withdrawal is allowed.
+/// Check whether a withdrawal is allowed under the default config: equity after it
+/// must cover IM.
 pub fn check_withdrawal(state: &State, account_id: &AccountId, amount: Decimal) -> TradeCheck {
+    check_withdrawal_with(state, account_id, amount, &EngineConfig::default())
+}
+
+/// `check_withdrawal` under `config`: equity after the withdrawal must cover
+/// `(IM + reserved margin) × withdrawal_buffer`. The accepted amounts are exactly those up to
+/// `margin::max_withdrawable`, unless the account is awaiting liquidation.
+pub fn check_withdrawal_with(
+    state: &State,
+    account_id: &AccountId,
+    amount: Decimal,
+    config: &EngineConfig,
+) -> TradeCheck {
     let account = match state.accounts.get(account_id) {
         Some(a) => a,
         None => return TradeCheck::Rejected("Account does not exist".to_string()),
     };
 
-    if amount > account.collateral {
+    if let TradeCheck::Rejected(reason) = check_not_frozen(account) {
+        return TradeCheck::Rejected(reason);
+    }
+    if let TradeCheck::Rejected(reason) = check_not_liquidatable(account, state) {
+        return TradeCheck::Rejected(reason);
+    }
+
+    if amount > account.collateral() {
         return TradeCheck::Rejected("Withdrawal exceeds collateral balance".to_string());
     }
 
     let eq = margin::equity(account, state);
     let im = margin::initial_margin_required(account, state);
+    let reserved = margin::reserved_margin(account, state);
+    let buffer = config.withdrawal_buffer;
+    let required = (im + reserved) * buffer;
 
     let eq_after = eq - amount;
 
-    if eq_after >= im {
+    if eq_after >= required {
         TradeCheck::Accepted
-    } else {
+    } else if !reserved.is_zero() {
+        TradeCheck::Rejected(format!(
+            "Withdrawal would violate IM: equity after {eq_after} < (IM {im} + reserved {reserved}) x buffer {buffer} = {required}"
+        ))
+    } else if buffer == Decimal::ONE {
         TradeCheck::Rejected(format!(
             "Withdrawal would violate IM: equity after {eq_after} < IM {im}"
         ))
+    } else {
+        TradeCheck::Rejected(format!(
+            "Withdrawal would violate IM buffer: equity after {eq_after} < IM {im} x buffer {buffer} = {required}"
+        ))
+    }
+}
+
+/// Validate an `OrderPlaced`: an existing account that is neither frozen nor awaiting
+/// liquidation, a registered market that has not expired, a non-zero quantity, an id
+/// the account does not already rest, and equity covering IM plus every reservation,
+/// the new order's included.
+pub fn check_order(
+    state: &State,
+    account_id: &AccountId,
+    order_id: &str,
+    market_id: &MarketId,
+    quantity: Decimal,
+) -> TradeCheck {
+    let Some(account) = state.accounts.get(account_id) else {
+        return TradeCheck::Rejected("Account does not exist".to_string());
+    };
+    if let TradeCheck::Rejected(reason) = check_not_frozen(account) {
+        return TradeCheck::Rejected(reason);
+    }
+    if let TradeCheck::Rejected(reason) = check_not_liquidatable(account, state) {
+        return TradeCheck::Rejected(reason);
+    }
+    let Some(market) = state.markets.get(market_id) else {
+        return TradeCheck::Rejected(format!("Unknown market_id: {market_id}"));
+    };
+    if market.expired {
+        return TradeCheck::Rejected(format!("Market {market_id} has expired"));
     }
+    if quantity.is_zero() {
+        return TradeCheck::Rejected("Order quantity must not be zero".to_string());
+    }
+    if order_id.is_empty() {
+        return TradeCheck::Rejected("Order id must not be empty".to_string());
+    }
+    if account.orders.contains_key(order_id) {
+        return TradeCheck::Rejected(format!(
+            "Account {account_id} already rests order {order_id}"
+        ));
+    }
+
+    let eq = margin::equity(account, state);
+    let im = margin::initial_margin_required(account, state);
+    let leverage = account.leverage.get(market_id).copied();
+    let reserved = margin::reserved_margin(account, state)
+        + margin::position_initial_margin_with(quantity, market, leverage);
+    if eq < im + reserved {
+        return TradeCheck::Rejected(format!(
+            "Insufficient margin for order {order_id}: equity {eq} < IM {im} + reserved {reserved}"
+        ));
+    }
+    TradeCheck::Accepted
+}
+
+/// The resting orders `ReservationBreach::AutoCancel` cancels on `account`, in
+/// cancellation order: none unless equity covers IM but not IM plus reservations, and
+/// otherwise the largest reservations first, ties by order id, until what is left is
+/// covered. Taking the largest first makes this the fewest orders that will do.
+pub fn orders_to_auto_cancel(account: &Account, state: &State) -> Vec<OrderId> {
+    if account.orders.is_empty() {
+        return Vec::new();
+    }
+    let room = margin::equity(account, state) - margin::initial_margin_required(account, state);
+    let mut reserved = margin::reserved_margin(account, state);
+    if room < Decimal::ZERO || reserved <= room {
+        return Vec::new();
+    }
+    let mut orders: Vec<(Decimal, &OrderId)> = account
+        .orders
+        .iter()
+        .map(|(order_id, order)| (margin::order_reservation(account, order, state), order_id))
+        .collect();
+    orders.sort_by(|(a, a_id), (b, b_id)| b.cmp(a).then_with(|| a_id.cmp(b_id)));
+    let mut cancelled = Vec::new();
+    for (reservation, order_id) in orders {
+        if reserved <= room {
+            break;
+        }
+        reserved -= reservation;
+        cancelled.push(order_id.clone());
+    }
+    cancelled
 }
 
 /// Simulate the effect of a trade on an account's collateral and positions.
 /// Returns (simulated_collateral, simulated_positions).
 fn simulate_trade(
-    account: &crate::types::Account,
+    account: &Account,
     market_id: &MarketId,
     fill_quantity: Decimal,
     fill_price: Decimal,
 ) -> (Decimal, BTreeMap<MarketId, Position>) {
-    let mut sim_collateral = account.collateral;
+    let mut sim_collateral = account.collateral();
     let mut sim_positions = account.positions.clone();
 
     apply_trade_to(
@@ -137,7 +1798,7 @@ fn simulate_trade(
 }
 
 /// Core trade application logic, shared between simulation and actual execution.
-pub fn apply_trade_to(
+pub(crate) fn apply_trade_to(
     collateral: &mut Decimal,
     positions: &mut BTreeMap<MarketId, Position>,
     market_id: &MarketId,
@@ -150,89 +1811,328 @@ pub fn apply_trade_to(
         None => (Decimal::ZERO, Decimal::ZERO),
     };
 
-    let new_qty = current_qty + fill_quantity;
-
-    if new_qty.is_zero() {
-        // Full close
-        // PnL = (value at close price) - (what we paid)
-        // Long 10 @ cost 500k, close at 41k: (41000*10) - 500000 = -90000 ✓
-        // Short 10 @ cost -500k, close at 41k: (41000*-10) - (-500000) = +90000 ✓
-        let realized_pnl = (fill_price * current_qty) - current_cost;
-        *collateral += realized_pnl;
-        positions.remove(market_id);
+    // A zero fill moves nothing: no position to open, no cost to take off.
+    if fill_quantity.is_zero() {
         return;
     }
 
-    if current_qty.is_zero() {
-        // Fresh open — no PnL, just record the position
-        positions.insert(
-            market_id.clone(),
-            Position {
-                market_id: market_id.clone(),
-                quantity: fill_quantity,
-                cost_basis: fill_quantity * fill_price,
-            },
-        );
-        return;
-    }
+    let new_qty = current_qty + fill_quantity;
 
-    // If fill is same direction as current position => increase
-    if current_qty.signum() == fill_quantity.signum() {
-        let pos = positions.get_mut(market_id).expect("position must exist");
-        pos.quantity = new_qty;
-        pos.cost_basis += fill_quantity * fill_price;
-        return;
-    }
+    match classify_fill(current_qty, fill_quantity) {
+        FillClass::Open => {
+            // Fresh open — no PnL, just record the position
+            positions.insert(
+                market_id.clone(),
+                Position {
+                    market_id: market_id.clone(),
+                    quantity: fill_quantity,
+                    cost_basis: fill_quantity * fill_price,
+                    funding_paid: Decimal::ZERO,
+                },
+            );
+        }
 
-    // Opposite direction: either partial close or flip
-    if new_qty.signum() == current_qty.signum() {
-        // Partial close: closes a fraction of the existing position (no flip).
-        //
-        // Use a sign-safe fraction-based realization:
-        //   closed_fraction = |fill| / |current|
-        //   closed_qty      = current_qty * closed_fraction   (same sign as current)
-        //   closed_cost     = current_cost * closed_fraction  (same sign as current_cost)
-        //   realized_pnl    = closed_qty*price - closed_cost
-        //
-        // This works for both longs and shorts without relying on negative ratios.
-        let closed_fraction = fill_quantity.abs() / current_qty.abs();
-
-        // Defensive: if precision causes slight overshoot, cap at 1.
-        let closed_fraction = if closed_fraction > Decimal::ONE {
-            Decimal::ONE
-        } else {
-            closed_fraction
-        };
+        FillClass::Increase => {
+            let pos = positions.get_mut(market_id).expect("position must exist");
+            pos.quantity = new_qty;
+            pos.cost_basis += fill_quantity * fill_price;
+        }
+
+        FillClass::Close => {
+            // Full close
+            // PnL = (value at close price) - (what we paid)
+            // Long 10 @ cost 500k, close at 41k: (41000*10) - 500000 = -90000 ✓
+            // Short 10 @ cost -500k, close at 41k: (41000*-10) - (-500000) = +90000 ✓
+            let realized_pnl = (fill_price * current_qty) - current_cost;
+            *collateral += realized_pnl;
+            positions.remove(market_id);
+        }
 
-        let closed_qty = current_qty * closed_fraction;
-        let closed_cost = current_cost * closed_fraction;
+        FillClass::Reduce => {
+            // Partial close: closes part of the existing position (no flip).
+            //
+            //   closed_qty  = -fill                                    (same sign as current)
+            //   closed_cost = current_cost * |fill| / |current|        (same sign as current_cost)
+            //   realized    = closed_qty*price - closed_cost
+            //
+            // Multiplying before the one division keeps closed_cost exact whenever the
+            // position's average entry is: a position opened at 50000.25 closes at exactly
+            // 50000.25 a unit, however many times it is cut. |fill| < |current| here, so
+            // closed_cost never exceeds the cost basis.
+            //
+            // When the entry does not terminate (1/3), the realized PnL is rounded down to
+            // collateral precision and whatever rounding leaves over stays in the cost
+            // basis. Collateral less cost basis then moves by exactly `closed_qty * price`,
+            // the cash the fill exchanged: rounding never creates value.
+            let closed_qty = -fill_quantity;
+            let closed_value = closed_qty * fill_price;
+            let closed_cost = current_cost * fill_quantity.abs() / current_qty.abs();
+            let realized_pnl = (closed_value - closed_cost).round_dp_with_strategy(
+                margin::COLLATERAL_DECIMALS,
+                RoundingStrategy::ToNegativeInfinity,
+            );
+            *collateral += realized_pnl;
 
-        let realized_pnl = (closed_qty * fill_price) - closed_cost;
-        *collateral += realized_pnl;
+            let pos = positions.get_mut(market_id).expect("position must exist");
+            pos.quantity = new_qty;
+            pos.cost_basis = current_cost - (closed_value - realized_pnl);
+        }
 
-        let pos = positions.get_mut(market_id).expect("position must exist");
-        pos.quantity = new_qty;
-        pos.cost_basis = current_cost - closed_cost;
+        FillClass::Flip => {
+            // Close entire old position, open remainder in opposite direction
+            let close_pnl = (fill_price * current_qty) - current_cost;
+            *collateral += close_pnl;
 
-        // If we somehow ended up at (very close to) zero, remove the position.
-        // (Normally the earlier new_qty.is_zero() branch handles exact closure.)
-        if pos.quantity.is_zero() {
-            positions.remove(market_id);
+            positions.insert(
+                market_id.clone(),
+                Position {
+                    market_id: market_id.clone(),
+                    quantity: new_qty,
+                    cost_basis: new_qty * fill_price,
+                    funding_paid: Decimal::ZERO,
+                },
+            );
         }
+    }
+}
 
-        return;
+/// Historical value-at-risk of one account, from `historical_var`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct VarReport {
+    pub account_id: AccountId,
+    /// Most recent mark moves used per market.
+    pub window: usize,
+    /// The loss quantile reported, as a fraction (0.99 for the 99th percentile).
+    #[serde(with = "decimal_str")]
+    pub percentile: Decimal,
+    /// Equity at current marks.
+    #[serde(with = "decimal_str")]
+    pub equity: Decimal,
+    /// Historical moves applied: the most observations any held market has, at most
+    /// `window`.
+    pub scenarios: usize,
+    /// Moves observed per held market, at most `window`. A market with fewer than
+    /// `scenarios` stays at its mark in the older scenarios.
+    pub observations: BTreeMap<MarketId, usize>,
+    /// Equity loss at `percentile` across the scenarios, interpolated linearly between
+    /// the two nearest. Negative when even that scenario is a gain; zero without any.
+    #[serde(with = "decimal_str")]
+    pub var: Decimal,
+    /// Largest loss of any scenario (zero without any).
+    #[serde(with = "decimal_str")]
+    pub worst_loss: Decimal,
+}
+
+/// Value-at-risk of `account_id` from the mark moves in its own history.
+///
+/// Every accepted mark in `log` (single or batched) of a market the account holds is
+/// turned into a return against the market's previous mark, and the last `window`
+/// returns per market are kept. A mark following a zero or negative one has no return.
+/// Scenario `k` moves each held market by its `k`-th latest return, all at once, so a
+/// venue that marks in batches replays its joint moves exactly; a market without that
+/// many returns stays at its mark. Each scenario is applied to the current portfolio
+/// on a copy of `state` whose marks are moved, clamped to `MAX_EVENT_VALUE` in
+/// magnitude, and the equity lost is the scenario's loss. `percentile` is clamped into
+/// [0, 1]. Losses are sorted and the quantile taken at rank `percentile * (n - 1)`,
+/// interpolating linearly, so the result never depends on scenario order.
+pub fn historical_var(
+    state: &State,
+    log: &[impl AsRef<Event>],
+    account_id: &AccountId,
+    window: usize,
+    percentile: Decimal,
+) -> VarReport {
+    let log = &events::borrowed(log);
+    let percentile = percentile.clamp(Decimal::ZERO, Decimal::ONE);
+    let account = state.accounts.get(account_id);
+    let held: BTreeSet<&MarketId> = account
+        .map(|a| {
+            a.positions
+                .keys()
+                .filter(|id| state.markets.contains_key(*id))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    // A rejected attempt is always immediately followed by its rejection event.
+    let rejected: BTreeSet<u64> = log
+        .windows(2)
+        .filter(|pair| pair[1].event_type.is_rejection())
+        .map(|pair| pair[0].sequence)
+        .collect();
+    let mut last_mark: BTreeMap<&MarketId, Decimal> = BTreeMap::new();
+    let mut returns: BTreeMap<&MarketId, Vec<Decimal>> = BTreeMap::new();
+    for event in log.iter().filter(|e| !rejected.contains(&e.sequence)) {
+        let marks: Vec<(&MarketId, Decimal)> = match &event.event_type {
+            EventType::MarkPriceUpdate { market_id, price } => vec![(market_id, *price)],
+            EventType::MarkPriceBatch { updates } => {
+                updates.iter().map(|(id, price)| (id, *price)).collect()
+            }
+            _ => continue,
+        };
+        for (market_id, price) in marks {
+            let Some(&market_id) = held.get(market_id) else {
+                continue;
+            };
+            if let Some(previous) = last_mark.insert(market_id, price) {
+                if previous > Decimal::ZERO {
+                    // Only a vanishing previous mark overflows. A move of MAX_EVENT_VALUE
+                    // times already takes any mark to the clamp below.
+                    let ratio =
+                        price
+                            .checked_div(previous)
+                            .unwrap_or(if price.is_sign_negative() {
+                                -MAX_EVENT_VALUE
+                            } else {
+                                MAX_EVENT_VALUE
+                            });
+                    returns
+                        .entry(market_id)
+                        .or_default()
+                        .push(ratio.clamp(-MAX_EVENT_VALUE, MAX_EVENT_VALUE));
+                }
+            }
+        }
     }
+    for ratios in returns.values_mut() {
+        ratios.drain(..ratios.len().saturating_sub(window));
+        ratios.reverse();
+    }
+    let observations: BTreeMap<MarketId, usize> = held
+        .iter()
+        .map(|id| ((*id).clone(), returns.get(id).map_or(0, Vec::len)))
+        .collect();
+    let scenarios = observations.values().copied().max().unwrap_or(0);
 
-    // Flip: close entire old position, open remainder in opposite direction
-    let close_pnl = (fill_price * current_qty) - current_cost;
-    *collateral += close_pnl;
+    let equity = account.map_or(Decimal::ZERO, |a| margin::equity(a, state));
+    let mut losses: Vec<Decimal> = Vec::with_capacity(scenarios);
+    if let Some(account) = account {
+        let mut shocked = state.clone();
+        for k in 0..scenarios {
+            for market_id in &held {
+                let ratio = returns.get(market_id).and_then(|r| r.get(k)).copied();
+                let mark = state.markets[*market_id].mark_price;
+                let moved = match ratio {
+                    Some(ratio) => (mark * ratio).clamp(-MAX_EVENT_VALUE, MAX_EVENT_VALUE),
+                    None => mark,
+                };
+                if let Some(market) = shocked.markets.get_mut(*market_id) {
+                    market.mark_price = moved;
+                }
+            }
+            losses.push(equity - margin::equity(account, &shocked));
+        }
+    }
+    losses.sort();
 
-    positions.insert(
-        market_id.clone(),
-        Position {
-            market_id: market_id.clone(),
-            quantity: new_qty,
-            cost_basis: new_qty * fill_price,
-        },
-    );
+    VarReport {
+        account_id: account_id.clone(),
+        window,
+        percentile,
+        equity,
+        scenarios,
+        observations,
+        var: quantile(&losses, percentile),
+        worst_loss: losses.last().copied().unwrap_or(Decimal::ZERO),
+    }
+}
+
+/// The `p` quantile of ascending `sorted`, interpolated linearly between the values
+/// at ranks `floor(p * (n - 1))` and the one above. Zero when `sorted` is empty.
+fn quantile(sorted: &[Decimal], p: Decimal) -> Decimal {
+    let Some(last) = sorted.len().checked_sub(1) else {
+        return Decimal::ZERO;
+    };
+    let rank = p * Decimal::from(last);
+    let below = rank.floor();
+    let index = usize::try_from(below).map_or(last, |i| i.min(last));
+    let lower = sorted[index];
+    match sorted.get(index + 1) {
+        Some(upper) => lower + (rank - below) * (upper - lower),
+        None => lower,
+    }
+}
+
+/// How one holder's margin headroom moves with a market's mark, from `sensitivity`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AccountSensitivity {
+    pub account_id: AccountId,
+    /// The account's signed position in the market.
+    #[serde(with = "decimal_str")]
+    pub quantity: Decimal,
+    /// d(equity − MM)/d(mark): the quantity, less the change in the position's
+    /// maintenance margin per unit of mark. Positive for a long, which a falling mark
+    /// hurts; negative for a short.
+    #[serde(with = "decimal_str")]
+    pub sensitivity: Decimal,
+    /// `equity - maintenance_margin` at the current marks.
+    #[serde(with = "decimal_str")]
+    pub headroom: Decimal,
+    /// How far the mark can move against the account, in price, before the headroom
+    /// is gone at this sensitivity. Negative for an account already under maintenance
+    /// margin; `None` when the sensitivity is zero.
+    #[serde(with = "decimal_str::option")]
+    pub adverse_move: Option<Decimal>,
+    /// The mark at which the headroom reaches zero at this sensitivity.
+    #[serde(with = "decimal_str::option")]
+    pub break_even_price: Option<Decimal>,
+}
+
+/// Every account holding a position in `market_id`, with how a move of its mark
+/// changes the account's headroom over maintenance margin, closest to liquidation
+/// first: ascending `adverse_move`, then account_id, with a zero sensitivity last. A
+/// pure read of `state`; empty for an unknown market.
+///
+/// The sensitivity is the exact derivative at the current mark, holding every other
+/// mark fixed. A position's maintenance margin grows by its fraction of `|quantity|`
+/// per unit of mark (with the mark's sign, since notional is an absolute value), and
+/// not at all while `min_maintenance_margin` holds it at the floor. Hedge-pair relief
+/// is taken as fixed. Equity and maintenance margin are linear in the mark until a
+/// floor or a hedge pair's smaller leg changes, or the mark crosses zero, so up to
+/// that point `break_even_price` is exactly where the account becomes liquidatable.
+pub fn sensitivity(state: &State, market_id: &MarketId) -> Vec<AccountSensitivity> {
+    let Some(market) = state.markets.get(market_id) else {
+        return Vec::new();
+    };
+    let mark = market.mark_price;
+    let mut rows: Vec<AccountSensitivity> = state
+        .accounts
+        .values()
+        .filter_map(|account| {
+            let quantity = account.positions.get(market_id)?.quantity;
+            let floored = market.min_maintenance_margin.is_some_and(|floor| {
+                floor
+                    > margin::position_notional(quantity, mark) * market.maintenance_margin_fraction
+            });
+            let mm_slope = if floored {
+                Decimal::ZERO
+            } else if mark.is_sign_negative() {
+                -quantity.abs() * market.maintenance_margin_fraction
+            } else {
+                quantity.abs() * market.maintenance_margin_fraction
+            };
+            let sensitivity = quantity - mm_slope;
+            let headroom = margin::equity(account, state)
+                - margin::maintenance_margin_required(account, state);
+            let adverse_move = headroom.checked_div(sensitivity.abs());
+            let break_even_price = headroom
+                .checked_div(sensitivity)
+                .map(|distance| mark - distance);
+            Some(AccountSensitivity {
+                account_id: account.account_id.clone(),
+                quantity,
+                sensitivity,
+                headroom,
+                adverse_move,
+                break_even_price,
+            })
+        })
+        .collect();
+    rows.sort_by(|a, b| match (a.adverse_move, b.adverse_move) {
+        (Some(x), Some(y)) => x.cmp(&y).then_with(|| a.account_id.cmp(&b.account_id)),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => a.account_id.cmp(&b.account_id),
+    });
+    rows
 }
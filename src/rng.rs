@@ -0,0 +1,50 @@
+//! A small seeded generator for reproducible draws.
+//!
+//! The backtest's fill jitter, the fuzz tests and the examples that make up their
+//! inputs all draw from `Lcg`, so a run with the same seed sees the same stream on
+//! every platform.
+
+use rust_decimal::Decimal;
+
+/// 64-bit linear congruential generator, yielding the top 31 bits of its state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lcg(u64);
+
+impl Lcg {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        self.0 >> 33
+    }
+
+    /// Below `n`, which must not be zero.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// An index into a slice of `len` items, which must not be zero.
+    pub fn index(&mut self, len: usize) -> usize {
+        self.below(len as u64) as usize
+    }
+
+    /// True `percent` times in 100.
+    pub fn chance(&mut self, percent: u64) -> bool {
+        self.below(100) < percent
+    }
+
+    pub fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.index(items.len())]
+    }
+
+    /// Uniform in [-1, 1], in steps of one millionth.
+    pub fn unit(&mut self) -> Decimal {
+        let draw = self.below(2_000_001) as i64 - 1_000_000;
+        Decimal::new(draw, 6)
+    }
+}
@@ -0,0 +1,1502 @@
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::engine::{Engine, EngineConfig};
+use crate::error::MarketError;
+use crate::events::{EventType, Liquidity};
+use crate::margin;
+use crate::state;
+use crate::types::{
+    AccountId, FundingMode, GroupId, ImportedPosition, InstrumentKind, Market, MarketId, PoolId,
+    DEFAULT_POOL,
+};
+
+/// A human-writable scenario: one-line steps and the markets they run against.
+///
+/// ```toml
+/// name = "Liquidation after adverse price move"
+/// steps = [
+///     "deposit alice 100000",
+///     "mark BTC-PERP 50000",
+///     "trade alice BTC-PERP +10 @ 50000",
+///     "expect alice equity 100000",
+/// ]
+///
+/// [[markets]]
+/// id = "BTC-PERP"
+/// initial_margin_fraction = "0.05"
+/// maintenance_margin_fraction = "0.03"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct Scenario {
+    #[serde(default)]
+    pub name: String,
+    pub steps: Vec<String>,
+    #[serde(default)]
+    pub markets: Vec<ScenarioMarket>,
+    /// Engine config for the run, as a `[config]` table; omitted fields default.
+    #[serde(default)]
+    pub config: EngineConfig,
+}
+
+/// Market parameters. Decimal fields accept strings (`"0.05"`) or TOML numbers.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScenarioMarket {
+    pub id: MarketId,
+    pub initial_margin_fraction: DecimalLit,
+    pub maintenance_margin_fraction: DecimalLit,
+    #[serde(default)]
+    pub liquidation_discount: Option<DecimalLit>,
+    #[serde(default)]
+    pub slippage_bps_per_notional: Option<DecimalLit>,
+    #[serde(default)]
+    pub concentration_threshold_notional: Option<DecimalLit>,
+    #[serde(default)]
+    pub concentration_add_on_fraction: Option<DecimalLit>,
+    #[serde(default)]
+    pub max_open_interest_notional: Option<DecimalLit>,
+    #[serde(default)]
+    pub skew_limit_notional: Option<DecimalLit>,
+    #[serde(default)]
+    pub max_leverage: Option<DecimalLit>,
+    #[serde(default)]
+    pub min_initial_margin: Option<DecimalLit>,
+    #[serde(default)]
+    pub min_maintenance_margin: Option<DecimalLit>,
+    #[serde(default)]
+    pub quantity_step: Option<DecimalLit>,
+    #[serde(default)]
+    pub min_liquidation_notional: Option<DecimalLit>,
+    #[serde(default)]
+    pub allow_negative_prices: bool,
+    /// `"Discrete"` (the default) or `"Continuous"`.
+    #[serde(default)]
+    pub funding_mode: FundingMode,
+    /// Makes the market a dated future expiring at this log time (Unix ms).
+    #[serde(default)]
+    pub expiry_timestamp: Option<u64>,
+}
+
+impl ScenarioMarket {
+    pub fn to_market(&self) -> Market {
+        let mut market = Market::new(
+            self.id.clone(),
+            self.initial_margin_fraction.0,
+            self.maintenance_margin_fraction.0,
+        );
+        if let Some(d) = &self.liquidation_discount {
+            market.liquidation_discount = d.0;
+        }
+        if let Some(d) = &self.slippage_bps_per_notional {
+            market.slippage_bps_per_notional = d.0;
+        }
+        if let Some(d) = &self.concentration_threshold_notional {
+            market.concentration_threshold_notional = d.0;
+        }
+        if let Some(d) = &self.concentration_add_on_fraction {
+            market.concentration_add_on_fraction = d.0;
+        }
+        market.max_open_interest_notional = self.max_open_interest_notional.as_ref().map(|d| d.0);
+        market.skew_limit_notional = self.skew_limit_notional.as_ref().map(|d| d.0);
+        market.max_leverage = self.max_leverage.as_ref().map(|d| d.0);
+        market.min_initial_margin = self.min_initial_margin.as_ref().map(|d| d.0);
+        market.min_maintenance_margin = self.min_maintenance_margin.as_ref().map(|d| d.0);
+        market.quantity_step = self.quantity_step.as_ref().map(|d| d.0);
+        market.min_liquidation_notional = self.min_liquidation_notional.as_ref().map(|d| d.0);
+        market.allow_negative_prices = self.allow_negative_prices;
+        market.funding_mode = self.funding_mode;
+        if let Some(expiry_timestamp) = self.expiry_timestamp {
+            market.instrument = InstrumentKind::Future { expiry_timestamp };
+        }
+        market
+    }
+}
+
+/// A decimal written as a TOML string, integer, or float. Floats go through their
+/// shortest round-trip text, so `0.05` means exactly 0.05.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecimalLit(pub Decimal);
+
+impl<'de> Deserialize<'de> for DecimalLit {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Str(String),
+            Int(i64),
+            Float(f64),
+        }
+
+        let text = match Raw::deserialize(deserializer)? {
+            Raw::Str(s) => s,
+            Raw::Int(i) => i.to_string(),
+            Raw::Float(f) => f.to_string(),
+        };
+        Decimal::from_str(&text)
+            .map(DecimalLit)
+            .map_err(|e| serde::de::Error::custom(format!("invalid decimal {text:?}: {e}")))
+    }
+}
+
+/// One compiled step.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    Action(Box<EventType>),
+    Expect(Expectation),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expectation {
+    Field {
+        account_id: AccountId,
+        field: AccountField,
+        value: Decimal,
+    },
+    Position {
+        account_id: AccountId,
+        market_id: MarketId,
+        quantity: Decimal,
+    },
+    /// Entry or break-even price of an open position.
+    PositionPrice {
+        account_id: AccountId,
+        market_id: MarketId,
+        break_even: bool,
+        price: Decimal,
+    },
+    /// `margin::effective_leverage` of a position: selected, or the market's own.
+    PositionLeverage {
+        account_id: AccountId,
+        market_id: MarketId,
+        leverage: Decimal,
+    },
+    /// What a backstop has absorbed in a market, or the notional it may still take.
+    Backstop {
+        account_id: AccountId,
+        market_id: MarketId,
+        remaining: bool,
+        amount: Decimal,
+    },
+    Flat {
+        account_id: AccountId,
+    },
+    /// The account does not exist: never opened, or closed by a merge.
+    Closed {
+        account_id: AccountId,
+    },
+    Liquidatable {
+        account_id: AccountId,
+        expected: bool,
+    },
+    Liquidated {
+        account_id: AccountId,
+    },
+    /// Number of liquidation steps (fills plus takeovers) the previous action took
+    /// against the account.
+    LiquidationSteps {
+        account_id: AccountId,
+        count: usize,
+    },
+    /// The quantities of those steps, in order.
+    LiquidationFills {
+        account_id: AccountId,
+        quantities: Vec<Decimal>,
+    },
+    /// The previous action generated exactly `count` events, each with `caused_by`
+    /// pointing at it.
+    Caused {
+        count: usize,
+    },
+    /// The previous action named an unregistered market and was ignored.
+    Ignored,
+    InsuranceFund {
+        pool_id: PoolId,
+        amount: Decimal,
+    },
+    InterestRevenue {
+        pool_id: PoolId,
+        amount: Decimal,
+    },
+    /// The previous action's `YieldResidual` for the pool, zero without one.
+    YieldResidual {
+        pool_id: PoolId,
+        amount: Decimal,
+    },
+    /// `state::pool_solvency` has a zero residual.
+    PoolBalanced {
+        pool_id: PoolId,
+    },
+    /// Whether the market is registered.
+    Market {
+        market_id: MarketId,
+        registered: bool,
+    },
+    MarkPrice {
+        market_id: MarketId,
+        price: Decimal,
+    },
+    /// `State::open_interest` at the market's absolute mark.
+    OpenInterest {
+        market_id: MarketId,
+        notional: Decimal,
+    },
+    /// Whether the market's net notional is over its skew limit.
+    Skew {
+        market_id: MarketId,
+        breached: bool,
+    },
+    /// `margin::group_notional`.
+    GroupNotional {
+        group_id: GroupId,
+        amount: Decimal,
+    },
+    /// The previous action's `RiskAlert` and `RiskAlertCleared` events, in order, as
+    /// (account, level moved to).
+    Alerts {
+        moves: Vec<(AccountId, usize)>,
+    },
+    /// Queued until a closed market's session opens.
+    Deferred {
+        account_id: AccountId,
+    },
+    /// Whether the account has an open margin call.
+    MarginCall {
+        account_id: AccountId,
+        open: bool,
+    },
+    /// The markets of the previous action's force-close fills for the account, in order.
+    ForceCloseFills {
+        account_id: AccountId,
+        market_ids: Vec<MarketId>,
+    },
+    /// The previous action's `YieldPaid` for the account, zero without one.
+    YieldPaid {
+        account_id: AccountId,
+        amount: Decimal,
+    },
+    /// Frozen by a `ForceClose` with exactly this reason.
+    Frozen {
+        account_id: AccountId,
+        reason: String,
+    },
+    /// The previous action was rejected, optionally with a reason containing this text.
+    Rejected {
+        reason_contains: Option<String>,
+    },
+    Accepted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountField {
+    Collateral,
+    Principal,
+    TradingBalance,
+    Equity,
+    UnrealizedPnl,
+    InitialMargin,
+    MaintenanceMargin,
+    ConcentrationAddOn,
+    BankruptcyDeficit,
+    MaxWithdrawable,
+    AlertLevel,
+    Turnover,
+    MakerNotional,
+    TakerNotional,
+    Trades,
+    Liquidations,
+    FeeRate,
+    PendingFunding,
+}
+
+impl AccountField {
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "collateral" => AccountField::Collateral,
+            "principal" => AccountField::Principal,
+            "trading_balance" => AccountField::TradingBalance,
+            "equity" => AccountField::Equity,
+            "unrealized_pnl" => AccountField::UnrealizedPnl,
+            "initial_margin" => AccountField::InitialMargin,
+            "maintenance_margin" => AccountField::MaintenanceMargin,
+            "concentration_add_on" => AccountField::ConcentrationAddOn,
+            "bankruptcy_deficit" => AccountField::BankruptcyDeficit,
+            "max_withdrawable" => AccountField::MaxWithdrawable,
+            "alert_level" => AccountField::AlertLevel,
+            "turnover" => AccountField::Turnover,
+            "maker_notional" => AccountField::MakerNotional,
+            "taker_notional" => AccountField::TakerNotional,
+            "trades" => AccountField::Trades,
+            "liquidations" => AccountField::Liquidations,
+            "fee_rate" => AccountField::FeeRate,
+            "pending_funding" => AccountField::PendingFunding,
+            _ => return None,
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            AccountField::Collateral => "collateral",
+            AccountField::Principal => "principal",
+            AccountField::TradingBalance => "trading_balance",
+            AccountField::Equity => "equity",
+            AccountField::UnrealizedPnl => "unrealized_pnl",
+            AccountField::InitialMargin => "initial_margin",
+            AccountField::MaintenanceMargin => "maintenance_margin",
+            AccountField::ConcentrationAddOn => "concentration_add_on",
+            AccountField::BankruptcyDeficit => "bankruptcy_deficit",
+            AccountField::MaxWithdrawable => "max_withdrawable",
+            AccountField::AlertLevel => "alert_level",
+            AccountField::Turnover => "turnover",
+            AccountField::MakerNotional => "maker_notional",
+            AccountField::TakerNotional => "taker_notional",
+            AccountField::Trades => "trades",
+            AccountField::Liquidations => "liquidations",
+            AccountField::FeeRate => "fee_rate",
+            AccountField::PendingFunding => "pending_funding",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    /// A `[[markets]]` entry failed `Market::validate`, or repeats a market id.
+    Market(MarketError),
+    /// A step could not be parsed. `step` is 1-based.
+    Syntax {
+        step: usize,
+        text: String,
+        message: String,
+    },
+    /// An `expect` step did not hold. `step` is 1-based.
+    ExpectationFailed {
+        step: usize,
+        text: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Io(e) => write!(f, "I/O error: {e}"),
+            ScenarioError::Toml(e) => write!(f, "invalid scenario file: {e}"),
+            ScenarioError::Market(e) => write!(f, "{e}"),
+            ScenarioError::Syntax {
+                step,
+                text,
+                message,
+            } => write!(f, "step {step} `{text}`: {message}"),
+            ScenarioError::ExpectationFailed {
+                step,
+                text,
+                message,
+            } => write!(f, "step {step} `{text}` failed: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl From<std::io::Error> for ScenarioError {
+    fn from(e: std::io::Error) -> Self {
+        ScenarioError::Io(e)
+    }
+}
+
+/// Outcome of a passing scenario.
+pub struct ScenarioRun {
+    /// The engine after the last step, with its full event log and snapshots.
+    pub engine: Engine,
+    pub expectations_checked: usize,
+}
+
+/// Read and parse a scenario file.
+pub fn load(path: impl AsRef<Path>) -> Result<Scenario, ScenarioError> {
+    parse(&std::fs::read_to_string(path)?)
+}
+
+/// Parse scenario TOML.
+pub fn parse(text: &str) -> Result<Scenario, ScenarioError> {
+    toml::from_str(text).map_err(ScenarioError::Toml)
+}
+
+/// Compile every step, reporting the first one that does not parse.
+pub fn compile(scenario: &Scenario) -> Result<Vec<Step>, ScenarioError> {
+    scenario
+        .steps
+        .iter()
+        .enumerate()
+        .map(|(i, text)| {
+            parse_step(text).map_err(|message| ScenarioError::Syntax {
+                step: i + 1,
+                text: text.clone(),
+                message,
+            })
+        })
+        .collect()
+}
+
+/// Run a scenario through a fresh engine, stopping at the first failed expectation.
+pub fn run(scenario: &Scenario) -> Result<ScenarioRun, ScenarioError> {
+    let steps = compile(scenario)?;
+
+    let mut engine = Engine::with_config(scenario.config.clone());
+    for market in &scenario.markets {
+        engine
+            .add_market(market.to_market())
+            .map_err(ScenarioError::Market)?;
+    }
+
+    // Events produced by the most recent action, for `rejected` / `liquidated`.
+    let mut last_action = 0..0;
+    let mut expectations_checked = 0;
+
+    for (i, step) in steps.into_iter().enumerate() {
+        match step {
+            Step::Action(event_type) => {
+                let start = engine.event_log.len();
+                engine.process(*event_type);
+                last_action = start..engine.event_log.len();
+            }
+            Step::Expect(expectation) => {
+                check(
+                    &engine,
+                    &engine.event_log[last_action.clone()],
+                    &expectation,
+                )
+                .map_err(|message| ScenarioError::ExpectationFailed {
+                    step: i + 1,
+                    text: scenario.steps[i].clone(),
+                    message,
+                })?;
+                expectations_checked += 1;
+            }
+        }
+    }
+
+    Ok(ScenarioRun {
+        engine,
+        expectations_checked,
+    })
+}
+
+/// Parse one step line.
+///
+/// Actions:
+/// - `deposit <account> <amount>`, `withdraw <account> <amount>`
+/// - `mark <market> <price>`
+/// - `marks <market> <price> [<market> <price> ...]` (one atomic batch)
+/// - `trade <account> <market> <signed qty> @ <price> [maker|taker]`
+/// - `funding <market> <new cumulative index>`
+/// - `funding-rate <market> <rate> <interval id>`
+/// - `accrue-funding <market> <accrued index>`
+/// - `reinstate <account>`
+/// - `force-close <account> <reason>` (the rest of the line is the reason)
+/// - `session-open <market>`, `session-close <market>`
+/// - `assign-pool <account> <pool>`, `insurance-deposit <pool> <amount>`
+/// - `hedge-pair <market a> <market b> <offset fraction>`
+/// - `group <group> <max notional> [<fee override>]`, `join-group <account> <group>`,
+///   `leave-group <account>`
+/// - `leverage <account> <market> <leverage>` (a market with `max_leverage`)
+/// - `limits <account> <max leverage> <max total notional>` (either `none` to clear)
+/// - `backstop <account> <market> <max notional>`
+/// - `merge <from account> <to account>`
+/// - `transfer <from account> <to account> <market> <signed qty> @ <price>`
+/// - `expire <market> <settlement price>` (a market with `expiry_timestamp`)
+/// - `interest-tick <interval id>` (under a `[config.interest]` table)
+/// - `distribute-yield <interval id> <rate>`
+/// - `add-market <market> <initial fraction> <maintenance fraction>` (other parameters
+///   default), `remove-market <market>`
+///
+/// Expectations, checked against live engine state with exact decimal equality:
+/// - `expect <account> <field> <value>`, field one of `collateral`, `principal`,
+///   `trading_balance`, `equity`, `unrealized_pnl`, `initial_margin`, `maintenance_margin`,
+///   `concentration_add_on` (the part of `initial_margin` above thresholds), `bankruptcy_deficit`,
+///   `max_withdrawable` (under the run's `withdrawal_buffer`), `alert_level`,
+///   `turnover`, `maker_notional`, `taker_notional`, `trades`, `liquidations` (over
+///   the `[config.trade_stats]` window),
+///   `fee_rate` (group override or turnover tier), `pending_funding` (accrued and
+///   not yet settled, over every market)
+/// - `expect <account> position <market> <qty>`, `expect <account> flat`,
+///   `expect <account> closed` (no such account, as after a merge)
+/// - `expect <account> entry_price <market> <price>`,
+///   `expect <account> break_even_price <market> <price>` (fees excluded, funding as paid)
+/// - `expect <account> leverage <market> <leverage>` (the leverage an open position's
+///   IM is charged at)
+/// - `expect <account> backstop_absorbed <market> <qty>`,
+///   `expect <account> backstop_remaining <market> <notional>` (at the current mark)
+/// - `expect <account> liquidatable`, `expect <account> healthy`
+/// - `expect <account> liquidated` (by the previous action)
+/// - `expect <account> liquidation_steps <n>`,
+///   `expect <account> liquidation_fills <qty> [<qty> ...]` (the previous action's
+///   fills and takeovers against the account, counted or by quantity in order)
+/// - `expect <account> deferred` (liquidation waiting for a session to open)
+/// - `expect <account> margin_call open`, `expect <account> margin_call none`
+///   (under a `[config.margin_call]` table)
+/// - `expect <account> force_close_fills <market> [<market> ...]` (the previous
+///   action's force-close fills for the account, by market in order)
+/// - `expect <account> frozen <reason>` (by a force close with that reason)
+/// - `expect <account> yield_paid <amount>` (the previous action's yield payment to
+///   the account)
+/// - `expect rejected [reason substring]`, `expect accepted` (the previous action)
+/// - `expect ignored` (the previous action named an unknown market)
+/// - `expect caused <n>` (the previous action generated `n` events, all linked to it)
+/// - `expect pool <pool> insurance_fund <amount>`,
+///   `expect pool <pool> interest_revenue <amount>`, `expect pool <pool> balanced`,
+///   `expect pool <pool> yield_residual <amount>` (the previous action's rounding
+///   residual for the pool)
+/// - `expect group <group> notional <amount>` (the members' combined notional)
+/// - `expect market <market> registered`, `expect market <market> absent`,
+///   `expect market <market> mark <price>`,
+///   `expect market <market> open_interest <notional>` (gross, at the absolute mark),
+///   `expect market <market> skew breached`, `expect market <market> skew clear`
+/// - `expect alerts [<account>:<level> ...]` (the previous action's risk alerts and
+///   clears, in order, by the level each moved to; none when empty)
+pub fn parse_step(text: &str) -> Result<Step, String> {
+    let tokens: Vec<&str> = text.split_whitespace().collect();
+
+    let step = match tokens.as_slice() {
+        ["deposit", account, amount] => Step::Action(Box::new(EventType::Deposit {
+            account_id: account_id(account)?,
+            amount: decimal(amount)?,
+        })),
+        ["withdraw", account, amount] => Step::Action(Box::new(EventType::Withdraw {
+            account_id: account_id(account)?,
+            amount: decimal(amount)?,
+        })),
+        ["mark", market, price] => Step::Action(Box::new(EventType::MarkPriceUpdate {
+            market_id: market_id(market)?,
+            price: decimal(price)?,
+        })),
+        ["marks", pairs @ ..] if !pairs.is_empty() && pairs.len().is_multiple_of(2) => {
+            let updates = pairs
+                .chunks(2)
+                .map(|pair| Ok((market_id(pair[0])?, decimal(pair[1])?)))
+                .collect::<Result<_, String>>()?;
+            Step::Action(Box::new(EventType::MarkPriceBatch { updates }))
+        }
+        ["trade", account, market, quantity, "@", price, side @ ..] if side.len() <= 1 => {
+            let liquidity = match side {
+                [] => None,
+                ["maker"] => Some(Liquidity::Maker),
+                ["taker"] => Some(Liquidity::Taker),
+                _ => return Err(format!("invalid liquidity {:?}", side[0])),
+            };
+            Step::Action(Box::new(EventType::TradeFill {
+                account_id: account_id(account)?,
+                market_id: market_id(market)?,
+                quantity: decimal(quantity)?,
+                price: decimal(price)?,
+                liquidity,
+            }))
+        }
+        ["funding", market, index] => Step::Action(Box::new(EventType::FundingUpdate {
+            market_id: market_id(market)?,
+            new_cumulative_index: decimal(index)?,
+        })),
+        ["accrue-funding", market, index] => Step::Action(Box::new(EventType::FundingAccrual {
+            market_id: market_id(market)?,
+            accrued_index: decimal(index)?,
+        })),
+        ["funding-rate", market, rate, interval] => {
+            Step::Action(Box::new(EventType::FundingRate {
+                market_id: market_id(market)?,
+                rate: decimal(rate)?,
+                interval_id: interval
+                    .parse()
+                    .map_err(|_| format!("invalid interval id {interval:?}"))?,
+            }))
+        }
+        ["reinstate", account] => Step::Action(Box::new(EventType::AccountReinstated {
+            account_id: account_id(account)?,
+        })),
+        ["force-close", account, reason @ ..] if !reason.is_empty() => {
+            Step::Action(Box::new(EventType::ForceClose {
+                account_id: account_id(account)?,
+                reason: reason.join(" "),
+            }))
+        }
+        ["session-open", market] => Step::Action(Box::new(EventType::SessionOpen {
+            market_id: market_id(market)?,
+        })),
+        ["session-close", market] => Step::Action(Box::new(EventType::SessionClose {
+            market_id: market_id(market)?,
+        })),
+        ["assign-pool", account, pool] => Step::Action(Box::new(EventType::AssignPool {
+            account_id: account_id(account)?,
+            pool_id: pool.to_string(),
+        })),
+        // Positions as `market quantity @ entry_price`, last settled at funding index 0.
+        ["import", account, collateral, positions @ ..] if positions.len().is_multiple_of(4) => {
+            let positions = positions
+                .chunks(4)
+                .map(|chunk| match chunk {
+                    [market, quantity, "@", price] => {
+                        let quantity = decimal(quantity)?;
+                        Ok(ImportedPosition {
+                            market_id: market_id(market)?,
+                            quantity,
+                            cost_basis: quantity * decimal(price)?,
+                            last_funding: Decimal::ZERO,
+                        })
+                    }
+                    _ => Err(format!("invalid imported position {:?}", chunk.join(" "))),
+                })
+                .collect::<Result<_, String>>()?;
+            Step::Action(Box::new(EventType::StateImport {
+                account_id: account_id(account)?,
+                pool_id: DEFAULT_POOL.to_string(),
+                collateral: decimal(collateral)?,
+                positions,
+            }))
+        }
+        ["hedge-pair", market_a, market_b, fraction] => {
+            Step::Action(Box::new(EventType::HedgePairAdded {
+                market_a: market_id(market_a)?,
+                market_b: market_id(market_b)?,
+                offset_fraction: decimal(fraction)?,
+            }))
+        }
+        ["group", group, cap, fee @ ..] if fee.len() <= 1 => {
+            Step::Action(Box::new(EventType::GroupCreated {
+                group_id: group.to_string(),
+                max_group_notional: Some(decimal(cap)?),
+                fee_override: fee.first().map(|rate| decimal(rate)).transpose()?,
+            }))
+        }
+        ["join-group", account, group] => Step::Action(Box::new(EventType::GroupMembershipSet {
+            account_id: account_id(account)?,
+            group_id: Some(group.to_string()),
+        })),
+        ["leave-group", account] => Step::Action(Box::new(EventType::GroupMembershipSet {
+            account_id: account_id(account)?,
+            group_id: None,
+        })),
+        ["leverage", account, market, leverage] => {
+            Step::Action(Box::new(EventType::SetPositionLeverage {
+                account_id: account_id(account)?,
+                market_id: market_id(market)?,
+                leverage: decimal(leverage)?,
+            }))
+        }
+        ["limits", account, max_leverage, max_notional] => {
+            Step::Action(Box::new(EventType::SetAccountLimits {
+                account_id: account_id(account)?,
+                max_leverage: optional_decimal(max_leverage)?,
+                max_total_notional: optional_decimal(max_notional)?,
+            }))
+        }
+        ["merge", from, to] => Step::Action(Box::new(EventType::AccountsMerged {
+            from: account_id(from)?,
+            to: account_id(to)?,
+        })),
+        ["transfer", from, to, market, quantity, "@", price] => {
+            Step::Action(Box::new(EventType::PositionTransfer {
+                from: account_id(from)?,
+                to: account_id(to)?,
+                market_id: market_id(market)?,
+                quantity: decimal(quantity)?,
+                transfer_price: decimal(price)?,
+            }))
+        }
+        ["backstop", account, market, max_notional] => {
+            Step::Action(Box::new(EventType::BackstopRegistered {
+                account_id: account_id(account)?,
+                market_id: market_id(market)?,
+                max_notional: decimal(max_notional)?,
+            }))
+        }
+        ["insurance-deposit", pool, amount] => {
+            Step::Action(Box::new(EventType::InsuranceFundDeposit {
+                pool_id: pool.to_string(),
+                amount: decimal(amount)?,
+            }))
+        }
+        ["expire", market, price] => Step::Action(Box::new(EventType::Expiry {
+            market_id: market_id(market)?,
+            settlement_price: decimal(price)?,
+        })),
+        ["add-market", market, initial, maintenance] => {
+            Step::Action(Box::new(EventType::MarketAdded {
+                market: Box::new(Market::new(
+                    market_id(market)?,
+                    decimal(initial)?,
+                    decimal(maintenance)?,
+                )),
+            }))
+        }
+        ["remove-market", market] => Step::Action(Box::new(EventType::MarketRemoved {
+            market_id: market_id(market)?,
+        })),
+
+        ["interest-tick", interval] => Step::Action(Box::new(EventType::InterestTick {
+            interval_id: interval
+                .parse()
+                .map_err(|_| format!("invalid interval id {interval:?}"))?,
+        })),
+        ["distribute-yield", interval, rate] => {
+            Step::Action(Box::new(EventType::YieldDistribution {
+                rate: decimal(rate)?,
+                interval_id: interval
+                    .parse()
+                    .map_err(|_| format!("invalid interval id {interval:?}"))?,
+            }))
+        }
+
+        ["expect", "accepted"] => Step::Expect(Expectation::Accepted),
+        ["expect", "ignored"] => Step::Expect(Expectation::Ignored),
+        ["expect", "caused", count] => Step::Expect(Expectation::Caused {
+            count: count
+                .parse()
+                .map_err(|_| format!("invalid event count: {count}"))?,
+        }),
+        ["expect", "rejected", reason @ ..] => Step::Expect(Expectation::Rejected {
+            reason_contains: (!reason.is_empty()).then(|| reason.join(" ")),
+        }),
+        ["expect", "pool", pool, "insurance_fund", amount] => {
+            Step::Expect(Expectation::InsuranceFund {
+                pool_id: pool.to_string(),
+                amount: decimal(amount)?,
+            })
+        }
+        ["expect", "pool", pool, "interest_revenue", amount] => {
+            Step::Expect(Expectation::InterestRevenue {
+                pool_id: pool.to_string(),
+                amount: decimal(amount)?,
+            })
+        }
+        ["expect", "pool", pool, "yield_residual", amount] => {
+            Step::Expect(Expectation::YieldResidual {
+                pool_id: pool.to_string(),
+                amount: decimal(amount)?,
+            })
+        }
+        ["expect", "group", group, "notional", amount] => {
+            Step::Expect(Expectation::GroupNotional {
+                group_id: group.to_string(),
+                amount: decimal(amount)?,
+            })
+        }
+        ["expect", "alerts", moves @ ..] => {
+            let moves = moves
+                .iter()
+                .map(|item| match item.split_once(':') {
+                    Some((account, level)) => Ok((
+                        account_id(account)?,
+                        level
+                            .parse()
+                            .map_err(|_| format!("invalid alert level in {item:?}"))?,
+                    )),
+                    None => Err(format!("expected <account>:<level>, got {item:?}")),
+                })
+                .collect::<Result<_, String>>()?;
+            Step::Expect(Expectation::Alerts { moves })
+        }
+        ["expect", "market", market, status @ ("registered" | "absent")] => {
+            Step::Expect(Expectation::Market {
+                market_id: market_id(market)?,
+                registered: *status == "registered",
+            })
+        }
+        ["expect", "market", market, "mark", price] => Step::Expect(Expectation::MarkPrice {
+            market_id: market_id(market)?,
+            price: decimal(price)?,
+        }),
+        ["expect", "market", market, "open_interest", notional] => {
+            Step::Expect(Expectation::OpenInterest {
+                market_id: market_id(market)?,
+                notional: decimal(notional)?,
+            })
+        }
+        ["expect", "market", market, "skew", status @ ("breached" | "clear")] => {
+            Step::Expect(Expectation::Skew {
+                market_id: market_id(market)?,
+                breached: *status == "breached",
+            })
+        }
+        ["expect", "pool", pool, "balanced"] => Step::Expect(Expectation::PoolBalanced {
+            pool_id: pool.to_string(),
+        }),
+        ["expect", account, "position", market, quantity] => Step::Expect(Expectation::Position {
+            account_id: account_id(account)?,
+            market_id: market_id(market)?,
+            quantity: decimal(quantity)?,
+        }),
+        ["expect", account, kind @ ("entry_price" | "break_even_price"), market, price] => {
+            Step::Expect(Expectation::PositionPrice {
+                account_id: account_id(account)?,
+                market_id: market_id(market)?,
+                break_even: *kind == "break_even_price",
+                price: decimal(price)?,
+            })
+        }
+        ["expect", account, "leverage", market, leverage] => {
+            Step::Expect(Expectation::PositionLeverage {
+                account_id: account_id(account)?,
+                market_id: market_id(market)?,
+                leverage: decimal(leverage)?,
+            })
+        }
+        ["expect", account, kind @ ("backstop_absorbed" | "backstop_remaining"), market, amount] => {
+            Step::Expect(Expectation::Backstop {
+                account_id: account_id(account)?,
+                market_id: market_id(market)?,
+                remaining: *kind == "backstop_remaining",
+                amount: decimal(amount)?,
+            })
+        }
+        ["expect", account, "flat"] => Step::Expect(Expectation::Flat {
+            account_id: account_id(account)?,
+        }),
+        ["expect", account, "closed"] => Step::Expect(Expectation::Closed {
+            account_id: account_id(account)?,
+        }),
+        ["expect", account, "liquidatable"] => Step::Expect(Expectation::Liquidatable {
+            account_id: account_id(account)?,
+            expected: true,
+        }),
+        ["expect", account, "healthy"] => Step::Expect(Expectation::Liquidatable {
+            account_id: account_id(account)?,
+            expected: false,
+        }),
+        ["expect", account, "liquidated"] => Step::Expect(Expectation::Liquidated {
+            account_id: account_id(account)?,
+        }),
+        ["expect", account, "liquidation_steps", count] => {
+            Step::Expect(Expectation::LiquidationSteps {
+                account_id: account_id(account)?,
+                count: count
+                    .parse()
+                    .map_err(|_| format!("invalid step count: {count}"))?,
+            })
+        }
+        ["expect", account, "liquidation_fills", quantities @ ..] => {
+            Step::Expect(Expectation::LiquidationFills {
+                account_id: account_id(account)?,
+                quantities: quantities
+                    .iter()
+                    .map(|q| decimal(q))
+                    .collect::<Result<_, _>>()?,
+            })
+        }
+        ["expect", account, "deferred"] => Step::Expect(Expectation::Deferred {
+            account_id: account_id(account)?,
+        }),
+        ["expect", account, "margin_call", state @ ("open" | "none")] => {
+            Step::Expect(Expectation::MarginCall {
+                account_id: account_id(account)?,
+                open: *state == "open",
+            })
+        }
+        ["expect", account, "force_close_fills", markets @ ..] => {
+            Step::Expect(Expectation::ForceCloseFills {
+                account_id: account_id(account)?,
+                market_ids: markets
+                    .iter()
+                    .map(|m| market_id(m))
+                    .collect::<Result<_, _>>()?,
+            })
+        }
+        ["expect", account, "yield_paid", amount] => Step::Expect(Expectation::YieldPaid {
+            account_id: account_id(account)?,
+            amount: decimal(amount)?,
+        }),
+        ["expect", account, "frozen", reason @ ..] if !reason.is_empty() => {
+            Step::Expect(Expectation::Frozen {
+                account_id: account_id(account)?,
+                reason: reason.join(" "),
+            })
+        }
+        ["expect", account, field, value] => {
+            let field =
+                AccountField::parse(field).ok_or_else(|| format!("unknown field {field:?}"))?;
+            Step::Expect(Expectation::Field {
+                account_id: account_id(account)?,
+                field,
+                value: decimal(value)?,
+            })
+        }
+
+        [] => return Err("empty step".into()),
+        [verb, ..] => return Err(format!("unrecognized step (starts with {verb:?})")),
+    };
+    Ok(step)
+}
+
+fn decimal(s: &str) -> Result<Decimal, String> {
+    Decimal::from_str(s.trim_start_matches('+')).map_err(|e| format!("invalid decimal {s:?}: {e}"))
+}
+
+/// A decimal, or `none` for an absent one.
+fn optional_decimal(s: &str) -> Result<Option<Decimal>, String> {
+    match s {
+        "none" => Ok(None),
+        s => decimal(s).map(Some),
+    }
+}
+
+fn account_id(s: &str) -> Result<AccountId, String> {
+    AccountId::try_from(s).map_err(|e| e.to_string())
+}
+
+fn market_id(s: &str) -> Result<MarketId, String> {
+    MarketId::try_from(s).map_err(|e| e.to_string())
+}
+
+/// Evaluate one expectation. `last_action` holds the events logged by the previous
+/// action step.
+fn check(
+    engine: &Engine,
+    last_action: &[Arc<crate::events::Event>],
+    expectation: &Expectation,
+) -> Result<(), String> {
+    let state = &engine.state;
+    let account = |id: &str| {
+        state
+            .accounts
+            .get(id)
+            .ok_or_else(|| format!("account {id} does not exist"))
+    };
+    let rejection = last_action
+        .iter()
+        .find_map(|e| rejection_reason(&e.event_type));
+
+    match expectation {
+        Expectation::Field {
+            account_id,
+            field,
+            value,
+        } => {
+            let acc = account(account_id)?;
+            let stats = engine
+                .account_stats(account_id)
+                .cloned()
+                .unwrap_or_default();
+            let actual = match field {
+                AccountField::Collateral => acc.collateral(),
+                AccountField::Principal => acc.principal,
+                AccountField::TradingBalance => acc.trading_balance,
+                AccountField::Equity => margin::equity(acc, state),
+                AccountField::UnrealizedPnl => margin::total_unrealized_pnl(acc, state),
+                AccountField::InitialMargin => margin::initial_margin_required(acc, state),
+                AccountField::MaintenanceMargin => margin::maintenance_margin_required(acc, state),
+                AccountField::ConcentrationAddOn => margin::concentration_add_on(acc, state),
+                AccountField::BankruptcyDeficit => acc.bankruptcy_deficit,
+                AccountField::MaxWithdrawable => {
+                    margin::max_withdrawable(acc, state, engine.config().withdrawal_buffer)
+                }
+                AccountField::AlertLevel => Decimal::from(acc.alert_level),
+                AccountField::Turnover => stats.turnover,
+                AccountField::MakerNotional => stats.maker_notional,
+                AccountField::TakerNotional => stats.taker_notional,
+                AccountField::Trades => Decimal::from(stats.trades),
+                AccountField::Liquidations => Decimal::from(stats.liquidations),
+                AccountField::FeeRate => engine
+                    .fee_rate(account_id)
+                    .ok_or_else(|| format!("{account_id} reaches no fee tier"))?,
+                AccountField::PendingFunding => acc.pending_funding.values().sum(),
+            };
+            if actual != *value {
+                return Err(format!(
+                    "expected {account_id} {} = {value}, got {}",
+                    field.name(),
+                    actual.normalize()
+                ));
+            }
+        }
+
+        Expectation::Position {
+            account_id,
+            market_id,
+            quantity,
+        } => {
+            let actual = account(account_id)?
+                .positions
+                .get(market_id)
+                .map(|p| p.quantity)
+                .unwrap_or(Decimal::ZERO);
+            if actual != *quantity {
+                return Err(format!(
+                    "expected {account_id} position in {market_id} = {quantity}, got {}",
+                    actual.normalize()
+                ));
+            }
+        }
+
+        Expectation::PositionPrice {
+            account_id,
+            market_id,
+            break_even,
+            price,
+        } => {
+            let name = if *break_even {
+                "break_even_price"
+            } else {
+                "entry_price"
+            };
+            let pos = account(account_id)?
+                .positions
+                .get(market_id)
+                .ok_or_else(|| {
+                    format!("expected {account_id} {name} in {market_id}, but it is flat")
+                })?;
+            let actual = if *break_even {
+                pos.break_even_price(Decimal::ZERO, pos.funding_paid)
+            } else {
+                pos.entry_price()
+            };
+            if actual != Some(*price) {
+                return Err(format!(
+                    "expected {account_id} {name} in {market_id} = {price}, got {}",
+                    actual.map_or("none".to_string(), |p| p.normalize().to_string())
+                ));
+            }
+        }
+
+        Expectation::PositionLeverage {
+            account_id,
+            market_id,
+            leverage,
+        } => {
+            let acc = account(account_id)?;
+            if !acc.positions.contains_key(market_id) {
+                return Err(format!(
+                    "expected {account_id} leverage in {market_id}, but it is flat"
+                ));
+            }
+            let market = state
+                .markets
+                .get(market_id)
+                .ok_or_else(|| format!("market {market_id} does not exist"))?;
+            let actual = margin::effective_leverage(market, acc.leverage.get(market_id).copied());
+            if actual != Some(*leverage) {
+                return Err(format!(
+                    "expected {account_id} leverage in {market_id} = {leverage}, got {}",
+                    actual.map_or("none".to_string(), |l| l.normalize().to_string())
+                ));
+            }
+        }
+
+        Expectation::Backstop {
+            account_id,
+            market_id,
+            remaining,
+            amount,
+        } => {
+            let backstop = state
+                .backstops
+                .iter()
+                .find(|b| b.account_id == *account_id && b.market_id == *market_id)
+                .ok_or_else(|| format!("{account_id} is not a backstop in {market_id}"))?;
+            let (name, actual) = if *remaining {
+                (
+                    "remaining",
+                    backstop.remaining(state.markets[market_id].mark_price),
+                )
+            } else {
+                ("absorbed", backstop.absorbed)
+            };
+            if actual != *amount {
+                return Err(format!(
+                    "expected {account_id} backstop {name} in {market_id} = {amount}, got {}",
+                    actual.normalize()
+                ));
+            }
+        }
+
+        Expectation::Closed { account_id } => {
+            if state.accounts.contains_key(account_id) {
+                return Err(format!("expected {account_id} closed, but it exists"));
+            }
+        }
+
+        Expectation::Flat { account_id } => {
+            let acc = account(account_id)?;
+            if !acc.positions.is_empty() {
+                let open: Vec<String> = acc
+                    .positions
+                    .values()
+                    .map(|p| format!("{} {}", p.market_id, p.quantity))
+                    .collect();
+                return Err(format!(
+                    "expected {account_id} flat, has positions: {}",
+                    open.join(", ")
+                ));
+            }
+        }
+
+        Expectation::Liquidatable {
+            account_id,
+            expected,
+        } => {
+            let acc = account(account_id)?;
+            let actual = margin::is_liquidatable(acc, state);
+            if actual != *expected {
+                return Err(format!(
+                    "expected {account_id} {}, but equity {} vs maintenance margin {}",
+                    if *expected { "liquidatable" } else { "healthy" },
+                    margin::equity(acc, state).normalize(),
+                    margin::maintenance_margin_required(acc, state).normalize()
+                ));
+            }
+        }
+
+        Expectation::Liquidated { account_id } => {
+            if liquidation_steps(last_action, account_id) == 0 {
+                return Err(format!("previous action did not liquidate {account_id}"));
+            }
+        }
+
+        Expectation::LiquidationSteps { account_id, count } => {
+            let actual = liquidation_steps(last_action, account_id);
+            if actual != *count {
+                return Err(format!(
+                    "expected {count} liquidation steps against {account_id}, previous action took {actual}"
+                ));
+            }
+        }
+
+        Expectation::LiquidationFills {
+            account_id,
+            quantities,
+        } => {
+            let actual: Vec<Decimal> = liquidation_quantities(last_action, account_id).collect();
+            if actual != *quantities {
+                return Err(format!(
+                    "expected liquidation fills {quantities:?} against {account_id}, previous action took {actual:?}"
+                ));
+            }
+        }
+
+        Expectation::Caused { count } => {
+            // The action's own event is the last one without a trigger; the config
+            // marker may precede it.
+            let Some(trigger) = last_action.iter().rposition(|e| e.caused_by.is_none()) else {
+                return Err("previous action logged no external event".into());
+            };
+            let sequence = last_action[trigger].sequence;
+            let generated = &last_action[trigger + 1..];
+            if let Some(e) = generated.iter().find(|e| e.caused_by != Some(sequence)) {
+                return Err(format!(
+                    "seq {} is caused by {:?}, not the action at seq {sequence}",
+                    e.sequence, e.caused_by
+                ));
+            }
+            if generated.len() != *count {
+                return Err(format!(
+                    "expected the action at seq {sequence} to cause {count} events, it caused {}",
+                    generated.len()
+                ));
+            }
+        }
+
+        Expectation::Deferred { account_id } => {
+            account(account_id)?;
+            if !state.deferred_liquidations.contains(account_id) {
+                return Err(format!(
+                    "expected {account_id} to have a deferred liquidation"
+                ));
+            }
+        }
+
+        Expectation::MarginCall { account_id, open } => {
+            let call = account(account_id)?.margin_call;
+            if call.is_some() != *open {
+                return Err(match call {
+                    Some(call) => format!(
+                        "expected no margin call for {account_id}, has one since seq {}",
+                        call.breached_at
+                    ),
+                    None => format!("expected {account_id} to have an open margin call"),
+                });
+            }
+        }
+
+        Expectation::ForceCloseFills {
+            account_id,
+            market_ids,
+        } => {
+            let actual: Vec<MarketId> = last_action
+                .iter()
+                .filter_map(|e| match &e.event_type {
+                    EventType::ForceCloseFill {
+                        account_id: id,
+                        market_id,
+                        ..
+                    } if id == account_id => Some(market_id.clone()),
+                    _ => None,
+                })
+                .collect();
+            if actual != *market_ids {
+                return Err(format!(
+                    "expected force-close fills in {market_ids:?} for {account_id}, previous action logged {actual:?}"
+                ));
+            }
+        }
+
+        Expectation::YieldPaid { account_id, amount } => {
+            let actual: Decimal = last_action
+                .iter()
+                .filter_map(|e| match &e.event_type {
+                    EventType::YieldPaid {
+                        account_id: id,
+                        amount,
+                        ..
+                    } if id == account_id => Some(*amount),
+                    _ => None,
+                })
+                .sum();
+            if actual != *amount {
+                return Err(format!(
+                    "expected {account_id} to be paid {amount} of yield by the previous action, got {actual}"
+                ));
+            }
+        }
+
+        Expectation::Frozen { account_id, reason } => {
+            let frozen = &account(account_id)?.frozen;
+            if frozen.as_deref() != Some(reason.as_str()) {
+                return Err(format!(
+                    "expected {account_id} frozen for {reason:?}, got {frozen:?}"
+                ));
+            }
+        }
+
+        Expectation::Rejected { reason_contains } => match (rejection, reason_contains) {
+            (None, _) => return Err("previous action was accepted".into()),
+            (Some(reason), Some(needle)) if !reason.contains(needle.as_str()) => {
+                return Err(format!(
+                    "rejection reason {reason:?} does not contain {needle:?}"
+                ))
+            }
+            _ => {}
+        },
+
+        Expectation::Accepted => {
+            if let Some(reason) = rejection {
+                return Err(format!("previous action was rejected: {reason}"));
+            }
+        }
+
+        Expectation::InsuranceFund { pool_id, amount } => {
+            let actual = state.insurance_fund(pool_id);
+            if actual != *amount {
+                return Err(format!(
+                    "expected pool {pool_id} insurance_fund = {amount}, got {actual}"
+                ));
+            }
+        }
+
+        Expectation::InterestRevenue { pool_id, amount } => {
+            let actual = state.interest_revenue(pool_id);
+            if actual != *amount {
+                return Err(format!(
+                    "expected pool {pool_id} interest_revenue = {amount}, got {actual}"
+                ));
+            }
+        }
+
+        Expectation::YieldResidual { pool_id, amount } => {
+            let actual: Decimal = last_action
+                .iter()
+                .filter_map(|e| match &e.event_type {
+                    EventType::YieldResidual {
+                        pool_id: id,
+                        amount,
+                        ..
+                    } if id == pool_id => Some(*amount),
+                    _ => None,
+                })
+                .sum();
+            if actual != *amount {
+                return Err(format!(
+                    "expected pool {pool_id} yield_residual = {amount} from the previous action, got {actual}"
+                ));
+            }
+        }
+
+        Expectation::GroupNotional { group_id, amount } => {
+            if !state.groups.contains_key(group_id) {
+                return Err(format!("group {group_id} does not exist"));
+            }
+            let actual = margin::group_notional(group_id, state);
+            if actual != *amount {
+                return Err(format!(
+                    "expected group {group_id} notional = {amount}, got {}",
+                    actual.normalize()
+                ));
+            }
+        }
+
+        Expectation::Market {
+            market_id,
+            registered,
+        } => {
+            if state.markets.contains_key(market_id) != *registered {
+                let status = if *registered { "registered" } else { "absent" };
+                return Err(format!("expected market {market_id} to be {status}"));
+            }
+        }
+
+        Expectation::MarkPrice { market_id, price } => {
+            let actual = state
+                .markets
+                .get(market_id)
+                .ok_or_else(|| format!("market {market_id} is not registered"))?
+                .mark_price;
+            if actual != *price {
+                return Err(format!(
+                    "expected {market_id} mark = {price}, got {}",
+                    actual.normalize()
+                ));
+            }
+        }
+
+        Expectation::OpenInterest {
+            market_id,
+            notional,
+        } => {
+            let mark = state
+                .markets
+                .get(market_id)
+                .ok_or_else(|| format!("market {market_id} is not registered"))?
+                .mark_price;
+            let actual = state.open_interest(market_id) * mark.abs();
+            if actual != *notional {
+                return Err(format!(
+                    "expected {market_id} open_interest = {notional}, got {}",
+                    actual.normalize()
+                ));
+            }
+        }
+
+        Expectation::Skew {
+            market_id,
+            breached,
+        } => {
+            let actual = state
+                .markets
+                .get(market_id)
+                .ok_or_else(|| format!("market {market_id} is not registered"))?
+                .skew_breached;
+            if actual != *breached {
+                let status = |breached| if breached { "breached" } else { "clear" };
+                return Err(format!(
+                    "expected {market_id} skew {}, got {}",
+                    status(*breached),
+                    status(actual)
+                ));
+            }
+        }
+
+        Expectation::PoolBalanced { pool_id } => {
+            let report = state::pool_solvency(state, engine.metrics(), pool_id);
+            if !report.is_balanced() {
+                return Err(format!(
+                    "pool {pool_id} books do not balance: residual {}",
+                    report.residual
+                ));
+            }
+        }
+
+        Expectation::Alerts { moves } => {
+            let actual: Vec<(AccountId, usize)> = last_action
+                .iter()
+                .filter_map(|e| match &e.event_type {
+                    EventType::RiskAlert {
+                        account_id, level, ..
+                    }
+                    | EventType::RiskAlertCleared {
+                        account_id, level, ..
+                    } => Some((account_id.clone(), *level)),
+                    _ => None,
+                })
+                .collect();
+            if actual != *moves {
+                return Err(format!(
+                    "expected alerts {}, previous action logged {}",
+                    alert_list(moves),
+                    alert_list(&actual)
+                ));
+            }
+        }
+
+        Expectation::Ignored => {
+            let ignored = last_action
+                .iter()
+                .any(|e| matches!(e.event_type, EventType::UnknownMarketIgnored { .. }));
+            if !ignored {
+                return Err("previous action was not ignored as an unknown market".into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Liquidation fills and takeovers against `account_id` among `events`.
+fn liquidation_steps(events: &[Arc<crate::events::Event>], account_id: &str) -> usize {
+    liquidation_quantities(events, account_id).count()
+}
+
+/// The quantity of each liquidation fill and takeover against `account_id`, in order.
+fn liquidation_quantities<'a>(
+    events: &'a [Arc<crate::events::Event>],
+    account_id: &'a str,
+) -> impl Iterator<Item = Decimal> + 'a {
+    events.iter().filter_map(move |e| match &e.event_type {
+        EventType::LiquidationFill {
+            account_id: id,
+            quantity,
+            ..
+        } if id == account_id => Some(*quantity),
+        EventType::LiquidationTakeover {
+            liquidated_account,
+            quantity,
+            ..
+        } if liquidated_account == account_id => Some(*quantity),
+        _ => None,
+    })
+}
+
+/// Alert moves as written in `expect alerts`.
+fn alert_list(moves: &[(AccountId, usize)]) -> String {
+    if moves.is_empty() {
+        return "none".to_string();
+    }
+    let items: Vec<String> = moves
+        .iter()
+        .map(|(account, level)| format!("{account}:{level}"))
+        .collect();
+    items.join(" ")
+}
+
+fn rejection_reason(event_type: &EventType) -> Option<&str> {
+    match event_type {
+        EventType::TradeRejected { reason, .. }
+        | EventType::WithdrawalRejected { reason, .. }
+        | EventType::MarkPriceRejected { reason, .. }
+        | EventType::MarkPriceBatchRejected { reason, .. }
+        | EventType::LiquidationTakeoverRejected { reason, .. }
+        | EventType::FundingRateRejected { reason, .. }
+        | EventType::FundingUpdateRejected { reason, .. }
+        | EventType::AccountMetadataRejected { reason, .. }
+        | EventType::AccountReinstatementRejected { reason, .. }
+        | EventType::AssignPoolRejected { reason, .. }
+        | EventType::StateImportRejected { reason, .. }
+        | EventType::HedgePairRejected { reason, .. }
+        | EventType::ExpiryRejected { reason, .. }
+        | EventType::InterestTickRejected { reason, .. }
+        | EventType::YieldDistributionRejected { reason, .. }
+        | EventType::GroupCreatedRejected { reason, .. }
+        | EventType::GroupMembershipRejected { reason, .. }
+        | EventType::PositionLeverageRejected { reason, .. }
+        | EventType::EventRejected { reason, .. } => Some(reason),
+        _ => None,
+    }
+}
@@ -0,0 +1,87 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::error::{EngineError, SinkError};
+use crate::snapshot::Snapshot;
+
+/// Where an engine's retained snapshots go instead of `Engine::snapshots`
+/// (`Engine::set_snapshot_sink`), or a replay's instead of
+/// `ReplayResult::snapshots` (`ReplayOptions::snapshot_sink`).
+///
+/// The sink is handed each snapshot `SnapshotPolicy::captures` selects, in sequence
+/// order; the policy's `KeepLast` retention is the sink's own business. A refusal
+/// never touches the engine: the event and its records are applied and logged as if
+/// the sink had accepted, and the refusal is reported next to the outcome.
+pub trait SnapshotSink {
+    fn accept(&mut self, snapshot: Snapshot) -> Result<(), SinkError>;
+}
+
+/// Keeps every snapshot in memory, like the engine's own vector. Clones share the
+/// same snapshots, so one clone can be handed to the engine and the other read.
+#[derive(Debug, Clone, Default)]
+pub struct InMemorySink(Arc<Mutex<Vec<Snapshot>>>);
+
+impl InMemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A copy of the snapshots accepted so far.
+    pub fn snapshots(&self) -> Vec<Snapshot> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// The snapshots accepted so far, leaving the sink empty.
+    pub fn take(&self) -> Vec<Snapshot> {
+        std::mem::take(&mut self.0.lock().unwrap())
+    }
+}
+
+impl SnapshotSink for InMemorySink {
+    fn accept(&mut self, snapshot: Snapshot) -> Result<(), SinkError> {
+        self.0.lock().unwrap().push(snapshot);
+        Ok(())
+    }
+}
+
+/// Appends each snapshot to a file as one JSON line and flushes it, so a snapshot
+/// the sink accepted is in the file. `snapshot::read_snapshots` reads it back.
+#[derive(Debug)]
+pub struct JsonlFileSink {
+    path: PathBuf,
+    writer: BufWriter<File>,
+}
+
+impl JsonlFileSink {
+    /// Create (or truncate) the file at `path`.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, EngineError> {
+        let path = path.as_ref().to_path_buf();
+        let writer = BufWriter::new(File::create(&path)?);
+        Ok(Self { path, writer })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl SnapshotSink for JsonlFileSink {
+    fn accept(&mut self, snapshot: Snapshot) -> Result<(), SinkError> {
+        let line = serde_json::to_string(&snapshot)?;
+        writeln!(self.writer, "{line}")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Drops every snapshot: an engine that keeps none and never fails to.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullSink;
+
+impl SnapshotSink for NullSink {
+    fn accept(&mut self, _snapshot: Snapshot) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
@@ -1,86 +1,1063 @@
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
+use crate::decimal_str;
+use crate::error::{EngineError, ResumeError};
+use crate::events::EventType;
 use crate::margin;
-use crate::state::State;
-use crate::types::{AccountId, MarketId};
+use crate::state::{AccountStats, IdempotencyWindow, RejectionHistory, State, TradeStats};
+use crate::types::{
+    Account, AccountGroup, AccountId, AccountLimits, Backstop, GroupId, HedgePair, MarginCallOpen,
+    Market, MarketId, OrderId, PoolId, Position, RestingOrder,
+};
+
+/// Which events get a snapshot captured after them.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum SnapshotPolicy {
+    #[default]
+    EveryEvent,
+    /// Capture after every Nth applied event.
+    EveryN(u64),
+    Never,
+    /// Capture after every event, but retain only the most recent N snapshots.
+    KeepLast(usize),
+    /// Capture only at liquidation and rejection boundaries: after `LiquidationFill`,
+    /// `LiquidationTakeover` and every `*Rejected` event.
+    Boundaries,
+}
+
+impl SnapshotPolicy {
+    /// Whether to capture after the `events_applied`-th event (1-based), judged on the
+    /// count alone. `Boundaries` depends on the event, so it never captures here; use
+    /// `captures`.
+    pub fn should_capture(&self, events_applied: u64) -> bool {
+        match self {
+            SnapshotPolicy::EveryEvent | SnapshotPolicy::KeepLast(_) => true,
+            SnapshotPolicy::EveryN(n) => *n > 0 && events_applied.is_multiple_of(*n),
+            SnapshotPolicy::Never | SnapshotPolicy::Boundaries => false,
+        }
+    }
+
+    /// Whether to capture after the `events_applied`-th event, which is `event_type`.
+    pub fn captures(&self, events_applied: u64, event_type: &EventType) -> bool {
+        match self {
+            SnapshotPolicy::Boundaries => {
+                event_type.is_rejection()
+                    || matches!(
+                        event_type,
+                        EventType::LiquidationFill { .. } | EventType::LiquidationTakeover { .. }
+                    )
+            }
+            _ => self.should_capture(events_applied),
+        }
+    }
+
+    /// Drop the oldest snapshots beyond what the policy retains.
+    pub fn retain(&self, snapshots: &mut Vec<Snapshot>) {
+        if let SnapshotPolicy::KeepLast(n) = self {
+            let excess = snapshots.len().saturating_sub(*n);
+            snapshots.drain(..excess);
+        }
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Snapshot {
     pub after_sequence: u64,
     pub accounts: BTreeMap<AccountId, AccountSnapshot>,
+    /// Every registered market. Empty in snapshots written before markets were
+    /// captured.
+    #[serde(default)]
+    pub markets: BTreeMap<MarketId, MarketSnapshot>,
+
+    // Engine-level state, so that `restore` can rebuild the full `State`.
+    #[serde(default)]
+    pub clock: Option<u64>,
+    #[serde(default, with = "decimal_str::map")]
+    pub insurance_funds: BTreeMap<PoolId, Decimal>,
+    #[serde(default)]
+    pub hedge_pairs: Vec<HedgePair>,
+    /// Every account group, with its members' combined notional.
+    #[serde(default)]
+    pub groups: BTreeMap<GroupId, GroupSnapshot>,
+    /// Backstop commitments in priority order, with what each has absorbed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub backstops: Vec<Backstop>,
+    #[serde(default, with = "decimal_str::map")]
+    pub interest_revenue: BTreeMap<PoolId, Decimal>,
+    #[serde(default)]
+    pub settled_interest_intervals: BTreeSet<u64>,
+    #[serde(default)]
+    pub distributed_yield_intervals: BTreeSet<u64>,
+    /// The idempotency keys in the window. Copied into every snapshot, so engines
+    /// with a large window and heavy keyed traffic want a sparse `SnapshotPolicy`.
+    #[serde(default)]
+    pub idempotency: IdempotencyWindow,
+    /// Recent rejections per account, under `EngineConfig::rejection_throttle`.
+    #[serde(default)]
+    pub rejection_history: BTreeMap<AccountId, RejectionHistory>,
+    /// Each account's fills in the window, under `EngineConfig::trade_stats`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub trade_stats: BTreeMap<AccountId, TradeStats>,
+}
+
+/// A market's pricing and margin parameters after an event.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MarketSnapshot {
+    #[serde(with = "decimal_str")]
+    pub mark_price: Decimal,
+    #[serde(with = "decimal_str")]
+    pub cumulative_funding_index: Decimal,
+    #[serde(with = "decimal_str")]
+    pub initial_margin_fraction: Decimal,
+    #[serde(with = "decimal_str")]
+    pub maintenance_margin_fraction: Decimal,
+    /// The mark is older than the market's staleness threshold.
+    pub stale: bool,
+    /// Outside its trading session (`SessionClose`).
+    pub session_closed: bool,
+    #[serde(default)]
+    pub last_mark_sequence: Option<u64>,
+    #[serde(default)]
+    pub last_mark_timestamp: Option<u64>,
+    #[serde(default)]
+    pub settled_funding_intervals: BTreeSet<u64>,
+    /// `Market::accrued_funding_index`.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "decimal_str::option"
+    )]
+    pub accrued_funding_index: Option<Decimal>,
+    #[serde(default)]
+    pub expired: bool,
+}
+
+/// An account group's settings and members after an event.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GroupSnapshot {
+    #[serde(flatten)]
+    pub group: AccountGroup,
+    /// `margin::group_notional`: what the group's cap is checked against.
+    #[serde(with = "decimal_str")]
+    pub notional: Decimal,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct AccountSnapshot {
+    #[serde(default)]
+    pub pool_id: PoolId,
+    #[serde(with = "decimal_str")]
     pub collateral: Decimal,
+    /// `Account::principal`. Collateral above it is trading balance, so a snapshot
+    /// written before the split restores its collateral as trading balance.
+    #[serde(default, with = "decimal_str")]
+    pub principal: Decimal,
+    #[serde(default, with = "decimal_str")]
+    pub trading_balance: Decimal,
+    #[serde(with = "decimal_str")]
     pub bankruptcy_deficit: Decimal,
 
+    #[serde(with = "decimal_str")]
     pub equity: Decimal,
+    #[serde(with = "decimal_str")]
     pub unrealized_pnl: Decimal,
+    #[serde(with = "decimal_str")]
     pub initial_margin_required: Decimal,
+    /// Concentration add-on already included in `initial_margin_required`.
+    #[serde(with = "decimal_str")]
+    pub concentration_add_on: Decimal,
+    #[serde(with = "decimal_str")]
     pub maintenance_margin_required: Decimal,
+    /// Hedge-pair relief already deducted from `initial_margin_required`.
+    #[serde(default, with = "decimal_str")]
+    pub initial_margin_hedge_offset: Decimal,
+    /// Hedge-pair relief already deducted from `maintenance_margin_required`.
+    #[serde(default, with = "decimal_str")]
+    pub maintenance_margin_hedge_offset: Decimal,
+    /// What the markets' `min_initial_margin` floors add, already included in
+    /// `initial_margin_required`.
+    #[serde(default, with = "decimal_str")]
+    pub initial_margin_floor: Decimal,
+    /// What the markets' `min_maintenance_margin` floors add, already included in
+    /// `maintenance_margin_required`.
+    #[serde(default, with = "decimal_str")]
+    pub maintenance_margin_floor: Decimal,
     pub liquidatable: bool,
+    /// Being liquidated by the cascade this snapshot belongs to.
+    #[serde(default)]
+    pub in_liquidation: bool,
+    /// Suspended after bankruptcy and not yet reinstated.
+    #[serde(default)]
+    pub suspended: bool,
+    /// Liquidation deferred until a closed market's session opens.
+    #[serde(default)]
+    pub liquidation_deferred: bool,
+    pub limits: AccountLimits,
+    /// `Account::leverage`: the leverage selected per market.
+    #[serde(default, with = "decimal_str::map")]
+    pub leverage: BTreeMap<MarketId, Decimal>,
+    #[serde(default)]
+    pub group_id: Option<GroupId>,
+    /// `Account::alert_level`: the risk alerts the account is at.
+    #[serde(default)]
+    pub alert_level: usize,
+    /// `Account::margin_call`: when its open margin call started, if it has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub margin_call: Option<MarginCallOpen>,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    /// Lifetime net funding paid per market (negative = received).
+    #[serde(default, with = "decimal_str::map")]
+    pub funding_paid: BTreeMap<MarketId, Decimal>,
+    /// Funding index each market was last settled or accrued at for the account.
+    #[serde(default, with = "decimal_str::map")]
+    pub last_funding: BTreeMap<MarketId, Decimal>,
+    /// `Account::pending_funding`: funding accrued and not yet settled, per market.
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        with = "decimal_str::map"
+    )]
+    pub pending_funding: BTreeMap<MarketId, Decimal>,
+    /// Markets the account is suspended from (`Account::suspended_markets`).
+    #[serde(default)]
+    pub suspended_markets: BTreeSet<MarketId>,
+    /// `Account::frozen`: the reason of the force close that froze the account.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frozen: Option<String>,
+    /// `Account::orders`: the resting orders.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub orders: BTreeMap<OrderId, RestingOrder>,
+    /// Margin `orders` reserve, on top of `initial_margin_required`.
+    #[serde(
+        default,
+        with = "decimal_str",
+        skip_serializing_if = "Decimal::is_zero"
+    )]
+    pub reserved_margin: Decimal,
+    /// Markets the current cascade has closed positions in. Empty unless
+    /// `in_liquidation`.
+    #[serde(default)]
+    pub liquidated_markets: BTreeSet<MarketId>,
+    /// Totals over the `EngineConfig::trade_stats` window; omitted while empty.
+    #[serde(default, skip_serializing_if = "AccountStats::is_empty")]
+    pub stats: AccountStats,
     pub positions: BTreeMap<MarketId, PositionSnapshot>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct PositionSnapshot {
+    #[serde(with = "decimal_str")]
     pub quantity: Decimal,
+    #[serde(with = "decimal_str")]
     pub cost_basis: Decimal,
+    #[serde(with = "decimal_str")]
     pub mark_price: Decimal,
+    #[serde(with = "decimal_str")]
     pub unrealized_pnl: Decimal,
+    #[serde(with = "decimal_str")]
     pub notional: Decimal,
+    /// The market's mark is stale; IM for this position carries the stale multiplier.
+    #[serde(default)]
+    pub mark_stale: bool,
+    /// Net funding paid since the position opened (negative = received).
+    #[serde(default, with = "decimal_str")]
+    pub funding_paid: Decimal,
+    /// `Position::entry_price`.
+    #[serde(default, with = "decimal_str::option")]
+    pub entry_price: Option<Decimal>,
+    /// `Position::break_even_price` with the position's `funding_paid`. Trade fees
+    /// are charged to the balance, not the position, so they are left out.
+    #[serde(default, with = "decimal_str::option")]
+    pub break_even_price: Option<Decimal>,
+    /// `margin::effective_leverage`: the leverage IM is charged at, selected or the
+    /// market's own. `None` in a market that charges no IM.
+    #[serde(default, with = "decimal_str::option")]
+    pub leverage: Option<Decimal>,
 }
 
-pub fn capture(state: &State, after_sequence: u64) -> Snapshot {
-    let mut accounts = BTreeMap::new();
-
-    for (account_id, account) in &state.accounts {
-        let eq = margin::equity(account, state);
-        let upnl = margin::total_unrealized_pnl(account, state);
-        let im = margin::initial_margin_required(account, state);
-        let mm = margin::maintenance_margin_required(account, state);
-
-        let mut positions = BTreeMap::new();
-        for (market_id, pos) in &account.positions {
-            let mark = state
-                .markets
-                .get(market_id)
-                .map(|m| m.mark_price)
-                .unwrap_or(Decimal::ZERO);
-
-            positions.insert(
-                market_id.clone(),
-                PositionSnapshot {
-                    quantity: pos.quantity,
-                    cost_basis: pos.cost_basis,
-                    mark_price: mark,
-                    unrealized_pnl: margin::position_unrealized_pnl(pos.quantity, pos.cost_basis, mark),
-                    notional: margin::position_notional(pos.quantity, mark),
-                },
-            );
-        }
-
-        accounts.insert(
-            account_id.clone(),
-            AccountSnapshot {
-                collateral: account.collateral,
-                bankruptcy_deficit: account.bankruptcy_deficit,
-
-                equity: eq,
-                unrealized_pnl: upnl,
-                initial_margin_required: im,
-                maintenance_margin_required: mm,
-                liquidatable: margin::is_liquidatable(account, state),
-                positions,
+/// Compare two snapshot streams aligned on `after_sequence` rather than position, and
+/// return the first sequence at which they differ. A sequence snapshotted in only one
+/// stream counts as a difference. `None` means the streams are identical.
+pub fn first_divergence(a: &[Snapshot], b: &[Snapshot]) -> Option<u64> {
+    let a: BTreeMap<u64, &Snapshot> = a.iter().map(|s| (s.after_sequence, s)).collect();
+    let b: BTreeMap<u64, &Snapshot> = b.iter().map(|s| (s.after_sequence, s)).collect();
+
+    a.keys()
+        .chain(b.keys())
+        .copied()
+        .collect::<std::collections::BTreeSet<u64>>()
+        .into_iter()
+        .find(|seq| a.get(seq) != b.get(seq))
+}
+
+/// A snapshot stream stored as its first snapshot and, after that, what each one
+/// changed from the one before (`compress`). Consecutive snapshots usually differ in
+/// one account, so this is a fraction of the size, and `decompress` gives back
+/// exactly the stream it was made from.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CompressedSnapshots {
+    /// `None` for an empty stream.
+    pub first: Option<Snapshot>,
+    pub deltas: Vec<SnapshotDelta>,
+}
+
+/// What changed from one snapshot to the next. Accounts and markets that changed are
+/// stored whole; engine-level fields appear only when they changed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SnapshotDelta {
+    pub after_sequence: u64,
+    /// Accounts that are new or differ, in full.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub accounts: BTreeMap<AccountId, AccountSnapshot>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub removed_accounts: BTreeSet<AccountId>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub markets: BTreeMap<MarketId, MarketSnapshot>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub removed_markets: BTreeSet<MarketId>,
+
+    /// `Some(clock)` when the clock changed, even to `None`.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "changed"
+    )]
+    pub clock: Option<Option<u64>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "decimal_str::option_map"
+    )]
+    pub insurance_funds: Option<BTreeMap<PoolId, Decimal>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hedge_pairs: Option<Vec<HedgePair>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub groups: Option<BTreeMap<GroupId, GroupSnapshot>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backstops: Option<Vec<Backstop>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "decimal_str::option_map"
+    )]
+    pub interest_revenue: Option<BTreeMap<PoolId, Decimal>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub settled_interest_intervals: Option<BTreeSet<u64>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub distributed_yield_intervals: Option<BTreeSet<u64>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency: Option<IdempotencyWindow>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rejection_history: Option<BTreeMap<AccountId, RejectionHistory>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trade_stats: Option<BTreeMap<AccountId, TradeStats>>,
+}
+
+/// A present field, including an explicit `null`, is a change.
+fn changed<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// The entries of `after` that are new or differ from `before`, and the keys it lost.
+fn map_delta<K: Ord + Clone, V: PartialEq + Clone>(
+    before: &BTreeMap<K, V>,
+    after: &BTreeMap<K, V>,
+) -> (BTreeMap<K, V>, BTreeSet<K>) {
+    let changed = after
+        .iter()
+        .filter(|(key, value)| before.get(*key) != Some(*value))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    let removed = before
+        .keys()
+        .filter(|key| !after.contains_key(*key))
+        .cloned()
+        .collect();
+    (changed, removed)
+}
+
+fn apply_map_delta<K: Ord + Clone, V: Clone>(
+    map: &mut BTreeMap<K, V>,
+    changed: &BTreeMap<K, V>,
+    removed: &BTreeSet<K>,
+) {
+    map.retain(|key, _| !removed.contains(key));
+    map.extend(
+        changed
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone())),
+    );
+}
+
+impl SnapshotDelta {
+    /// What turns `before` into `after`.
+    pub fn between(before: &Snapshot, after: &Snapshot) -> Self {
+        fn field<T: PartialEq + Clone>(before: &T, after: &T) -> Option<T> {
+            (before != after).then(|| after.clone())
+        }
+        let (accounts, removed_accounts) = map_delta(&before.accounts, &after.accounts);
+        let (markets, removed_markets) = map_delta(&before.markets, &after.markets);
+        Self {
+            after_sequence: after.after_sequence,
+            accounts,
+            removed_accounts,
+            markets,
+            removed_markets,
+            clock: field(&before.clock, &after.clock),
+            insurance_funds: field(&before.insurance_funds, &after.insurance_funds),
+            hedge_pairs: field(&before.hedge_pairs, &after.hedge_pairs),
+            groups: field(&before.groups, &after.groups),
+            backstops: field(&before.backstops, &after.backstops),
+            interest_revenue: field(&before.interest_revenue, &after.interest_revenue),
+            settled_interest_intervals: field(
+                &before.settled_interest_intervals,
+                &after.settled_interest_intervals,
+            ),
+            distributed_yield_intervals: field(
+                &before.distributed_yield_intervals,
+                &after.distributed_yield_intervals,
+            ),
+            idempotency: field(&before.idempotency, &after.idempotency),
+            rejection_history: field(&before.rejection_history, &after.rejection_history),
+            trade_stats: field(&before.trade_stats, &after.trade_stats),
+        }
+    }
+
+    /// The snapshot this delta was taken to from `before`.
+    pub fn apply(&self, before: &Snapshot) -> Snapshot {
+        fn field<T: Clone>(target: &mut T, change: &Option<T>) {
+            if let Some(value) = change {
+                *target = value.clone();
+            }
+        }
+        let mut snapshot = before.clone();
+        snapshot.after_sequence = self.after_sequence;
+        apply_map_delta(
+            &mut snapshot.accounts,
+            &self.accounts,
+            &self.removed_accounts,
+        );
+        apply_map_delta(&mut snapshot.markets, &self.markets, &self.removed_markets);
+        field(&mut snapshot.clock, &self.clock);
+        field(&mut snapshot.insurance_funds, &self.insurance_funds);
+        field(&mut snapshot.hedge_pairs, &self.hedge_pairs);
+        field(&mut snapshot.groups, &self.groups);
+        field(&mut snapshot.backstops, &self.backstops);
+        field(&mut snapshot.interest_revenue, &self.interest_revenue);
+        field(
+            &mut snapshot.settled_interest_intervals,
+            &self.settled_interest_intervals,
+        );
+        field(
+            &mut snapshot.distributed_yield_intervals,
+            &self.distributed_yield_intervals,
+        );
+        field(&mut snapshot.idempotency, &self.idempotency);
+        field(&mut snapshot.rejection_history, &self.rejection_history);
+        field(&mut snapshot.trade_stats, &self.trade_stats);
+        snapshot
+    }
+}
+
+/// Store `snapshots` as the first one and a `SnapshotDelta` for each after it, in
+/// the order given. Lossless for any stream, in any order.
+pub fn compress(snapshots: &[Snapshot]) -> CompressedSnapshots {
+    CompressedSnapshots {
+        first: snapshots.first().cloned(),
+        deltas: snapshots
+            .windows(2)
+            .map(|pair| SnapshotDelta::between(&pair[0], &pair[1]))
+            .collect(),
+    }
+}
+
+/// The stream `compress` was given.
+pub fn decompress(compressed: &CompressedSnapshots) -> Vec<Snapshot> {
+    let Some(first) = &compressed.first else {
+        return Vec::new();
+    };
+    let mut snapshots = Vec::with_capacity(compressed.deltas.len() + 1);
+    snapshots.push(first.clone());
+    for delta in &compressed.deltas {
+        let next = delta.apply(snapshots.last().expect("starts with the first snapshot"));
+        snapshots.push(next);
+    }
+    snapshots
+}
+
+/// Read a snapshot file in any of its forms: a JSON array of snapshots,
+/// `CompressedSnapshots`, or one snapshot per line as `sink::JsonlFileSink` writes
+/// them (blank lines skipped).
+pub fn read_snapshots(path: impl AsRef<std::path::Path>) -> Result<Vec<Snapshot>, EngineError> {
+    let content = std::fs::read_to_string(path)?;
+    let parse_error = |source: serde_json::Error| EngineError::Parse {
+        line: source.line(),
+        source,
+    };
+    let first_line = content.lines().find(|line| !line.trim().is_empty());
+    if content.trim_start().starts_with('[') {
+        serde_json::from_str(&content).map_err(parse_error)
+    } else if first_line.is_some_and(|line| serde_json::from_str::<Snapshot>(line).is_ok()) {
+        content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str(line).map_err(|source| EngineError::Parse {
+                    line: i + 1,
+                    source,
+                })
+            })
+            .collect()
+    } else {
+        let compressed = serde_json::from_str(&content).map_err(parse_error)?;
+        Ok(decompress(&compressed))
+    }
+}
+
+/// One per-account quantity that `series` can extract from a snapshot. Serialized
+/// as its column name.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(into = "String", try_from = "String")]
+pub enum Field {
+    Collateral,
+    Principal,
+    TradingBalance,
+    Equity,
+    UnrealizedPnl,
+    Im,
+    Mm,
+    /// `equity / maintenance_margin_required`; `None` without maintenance margin.
+    MarginRatio,
+    BankruptcyDeficit,
+    /// Signed quantity in a market, zero when flat.
+    PositionQty(MarketId),
+    /// Notional in a market, zero when flat.
+    PositionNotional(MarketId),
+    /// A market's mark price; `None` if the snapshot has no such market.
+    Mark(MarketId),
+    /// A market's cumulative funding index; `None` if the snapshot has no such market.
+    FundingIndex(MarketId),
+}
+
+impl Field {
+    fn value(&self, snapshot: &Snapshot, account: &AccountSnapshot) -> Option<Decimal> {
+        let position = |market_id: &MarketId| account.positions.get(market_id);
+        let market = |market_id: &MarketId| snapshot.markets.get(market_id);
+        match self {
+            Field::Collateral => Some(account.collateral),
+            Field::Principal => Some(account.principal),
+            Field::TradingBalance => Some(account.trading_balance),
+            Field::Equity => Some(account.equity),
+            Field::UnrealizedPnl => Some(account.unrealized_pnl),
+            Field::Im => Some(account.initial_margin_required),
+            Field::Mm => Some(account.maintenance_margin_required),
+            Field::MarginRatio => account
+                .equity
+                .checked_div(account.maintenance_margin_required),
+            Field::BankruptcyDeficit => Some(account.bankruptcy_deficit),
+            Field::PositionQty(market_id) => {
+                Some(position(market_id).map_or(Decimal::ZERO, |p| p.quantity))
+            }
+            Field::PositionNotional(market_id) => {
+                Some(position(market_id).map_or(Decimal::ZERO, |p| p.notional))
+            }
+            Field::Mark(market_id) => market(market_id).map(|m| m.mark_price),
+            Field::FundingIndex(market_id) => market(market_id).map(|m| m.cumulative_funding_index),
+        }
+    }
+}
+
+/// Column names as used on the command line and in CSV headers: `equity`,
+/// `margin_ratio`, `position_qty:BTC-PERP`, ...
+impl std::fmt::Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Field::Collateral => write!(f, "collateral"),
+            Field::Principal => write!(f, "principal"),
+            Field::TradingBalance => write!(f, "trading_balance"),
+            Field::Equity => write!(f, "equity"),
+            Field::UnrealizedPnl => write!(f, "unrealized_pnl"),
+            Field::Im => write!(f, "im"),
+            Field::Mm => write!(f, "mm"),
+            Field::MarginRatio => write!(f, "margin_ratio"),
+            Field::BankruptcyDeficit => write!(f, "bankruptcy_deficit"),
+            Field::PositionQty(market_id) => write!(f, "position_qty:{market_id}"),
+            Field::PositionNotional(market_id) => write!(f, "position_notional:{market_id}"),
+            Field::Mark(market_id) => write!(f, "mark:{market_id}"),
+            Field::FundingIndex(market_id) => write!(f, "funding_index:{market_id}"),
+        }
+    }
+}
+
+impl std::str::FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let market = |market_id: &str| MarketId::try_from(market_id).map_err(|e| e.to_string());
+        Ok(match s.split_once(':') {
+            Some(("position_qty", market_id)) => Field::PositionQty(market(market_id)?),
+            Some(("position_notional", market_id)) => Field::PositionNotional(market(market_id)?),
+            Some(("mark", market_id)) => Field::Mark(market(market_id)?),
+            Some(("funding_index", market_id)) => Field::FundingIndex(market(market_id)?),
+            _ => match s {
+                "collateral" => Field::Collateral,
+                "principal" => Field::Principal,
+                "trading_balance" => Field::TradingBalance,
+                "equity" => Field::Equity,
+                "unrealized_pnl" => Field::UnrealizedPnl,
+                "im" => Field::Im,
+                "mm" => Field::Mm,
+                "margin_ratio" => Field::MarginRatio,
+                "bankruptcy_deficit" => Field::BankruptcyDeficit,
+                _ => return Err(format!("unknown series field `{s}`")),
+            },
+        })
+    }
+}
+
+impl From<Field> for String {
+    fn from(field: Field) -> String {
+        field.to_string()
+    }
+}
+
+impl TryFrom<String> for Field {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, String> {
+        s.parse()
+    }
+}
+
+/// Values of `TimeSeries::fields`, in order, after one sequence.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SeriesPoint {
+    pub sequence: u64,
+    #[serde(with = "decimal_str::option_vec")]
+    pub values: Vec<Option<Decimal>>,
+}
+
+/// An account's fields over a snapshot stream; see `series`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TimeSeries {
+    pub account_id: AccountId,
+    pub fields: Vec<Field>,
+    pub points: Vec<SeriesPoint>,
+}
+
+impl TimeSeries {
+    /// `(sequence, value)` pairs for one field, skipping sequences where it is undefined.
+    pub fn pairs(&self, field: &Field) -> Vec<(u64, Decimal)> {
+        let Some(column) = self.fields.iter().position(|f| f == field) else {
+            return Vec::new();
+        };
+        self.points
+            .iter()
+            .filter_map(|p| p.values[column].map(|v| (p.sequence, v)))
+            .collect()
+    }
+
+    /// Value of `field` after `sequence`, if the series has a point there.
+    pub fn value_at(&self, field: &Field, sequence: u64) -> Option<Decimal> {
+        let column = self.fields.iter().position(|f| f == field)?;
+        let point = self.points.iter().find(|p| p.sequence == sequence)?;
+        point.values[column]
+    }
+
+    /// CSV with a `sequence` column followed by one column per field. Undefined
+    /// values are empty cells; decimals are normalized.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("sequence");
+        for field in &self.fields {
+            csv.push(',');
+            csv.push_str(&field.to_string());
+        }
+        csv.push('\n');
+        for point in &self.points {
+            csv.push_str(&point.sequence.to_string());
+            for value in &point.values {
+                csv.push(',');
+                if let Some(v) = value {
+                    csv.push_str(&decimal_str::normalize(*v).to_string());
+                }
+            }
+            csv.push('\n');
+        }
+        csv
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("time series serializes")
+    }
+}
+
+/// Extract `fields` of one account from a snapshot stream, one point per snapshot in
+/// sequence order. A snapshot the account is absent from repeats the account's
+/// previous point (forward fill); snapshots before the account first appears are
+/// skipped.
+pub fn series(snapshots: &[Snapshot], account_id: &AccountId, fields: &[Field]) -> TimeSeries {
+    let mut ordered: Vec<&Snapshot> = snapshots.iter().collect();
+    ordered.sort_by_key(|s| s.after_sequence);
+
+    let mut points = Vec::new();
+    let mut last: Option<Vec<Option<Decimal>>> = None;
+    for snapshot in ordered {
+        if let Some(account) = snapshot.accounts.get(account_id) {
+            last = Some(fields.iter().map(|f| f.value(snapshot, account)).collect());
+        }
+        if let Some(values) = &last {
+            points.push(SeriesPoint {
+                sequence: snapshot.after_sequence,
+                values: values.clone(),
+            });
+        }
+    }
+
+    TimeSeries {
+        account_id: account_id.clone(),
+        fields: fields.to_vec(),
+        points,
+    }
+}
+
+/// The margin figures of one account that `RiskDelta` reports.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct RiskFigures {
+    #[serde(with = "decimal_str")]
+    pub equity: Decimal,
+    #[serde(with = "decimal_str")]
+    pub initial_margin_required: Decimal,
+    #[serde(with = "decimal_str")]
+    pub maintenance_margin_required: Decimal,
+    /// `equity / maintenance_margin_required`; `None` without maintenance margin.
+    #[serde(with = "decimal_str::option")]
+    pub margin_ratio: Option<Decimal>,
+}
+
+impl RiskFigures {
+    /// The figures an account snapshot already holds; nothing is recomputed.
+    pub fn of(account: &AccountSnapshot) -> Self {
+        Self {
+            equity: account.equity,
+            initial_margin_required: account.initial_margin_required,
+            maintenance_margin_required: account.maintenance_margin_required,
+            margin_ratio: account
+                .equity
+                .checked_div(account.maintenance_margin_required),
+        }
+    }
+}
+
+/// How one event moved one account's margin figures. An account the event created
+/// moves from all zeros, and one it closed (the source of a merge) to all zeros.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RiskDelta {
+    pub sequence: u64,
+    pub account_id: AccountId,
+    pub before: RiskFigures,
+    pub after: RiskFigures,
+}
+
+/// The accounts whose figures differ between two snapshots, in account order, as
+/// deltas under `after.after_sequence`. Accounts whose figures did not move are left
+/// out.
+pub fn risk_deltas(before: &Snapshot, after: &Snapshot) -> Vec<RiskDelta> {
+    let account_ids: BTreeSet<&AccountId> = before
+        .accounts
+        .keys()
+        .chain(after.accounts.keys())
+        .collect();
+    account_ids
+        .into_iter()
+        .filter_map(|account_id| {
+            let old = before
+                .accounts
+                .get(account_id)
+                .map(RiskFigures::of)
+                .unwrap_or_default();
+            let new = after
+                .accounts
+                .get(account_id)
+                .map(RiskFigures::of)
+                .unwrap_or_default();
+            (old != new).then(|| RiskDelta {
+                sequence: after.after_sequence,
+                account_id: account_id.clone(),
+                before: old,
+                after: new,
+            })
+        })
+        .collect()
+}
+
+/// One account's snapshot against the current state, as `capture` records it.
+pub fn capture_account(account: &Account, state: &State) -> AccountSnapshot {
+    let account_id = &account.account_id;
+    let eq = margin::equity(account, state);
+    let upnl = margin::total_unrealized_pnl(account, state);
+    let im = margin::initial_margin_required(account, state);
+    let mm = margin::maintenance_margin_required(account, state);
+    let hedge_offset = margin::hedge_offset(&account.positions, &account.leverage, state);
+    let floors = margin::margin_floors(account, state);
+
+    let mut positions = BTreeMap::new();
+    for (market_id, pos) in &account.positions {
+        let market = state.markets.get(market_id);
+        let (mark, mark_stale) = market
+            .map(|m| (m.mark_price, m.stale))
+            .unwrap_or((Decimal::ZERO, false));
+        let selected = account.leverage.get(market_id).copied();
+
+        positions.insert(
+            market_id.clone(),
+            PositionSnapshot {
+                quantity: pos.quantity,
+                cost_basis: pos.cost_basis,
+                mark_price: mark,
+                unrealized_pnl: margin::position_unrealized_pnl(pos.quantity, pos.cost_basis, mark),
+                notional: margin::position_notional(pos.quantity, mark),
+                mark_stale,
+                funding_paid: pos.funding_paid,
+                entry_price: pos.entry_price(),
+                break_even_price: pos.break_even_price(Decimal::ZERO, pos.funding_paid),
+                leverage: market.map_or(selected, |m| margin::effective_leverage(m, selected)),
             },
         );
     }
 
+    AccountSnapshot {
+        pool_id: account.pool_id.clone(),
+        collateral: account.collateral(),
+        principal: account.principal,
+        trading_balance: account.trading_balance,
+        bankruptcy_deficit: account.bankruptcy_deficit,
+
+        equity: eq,
+        unrealized_pnl: upnl,
+        initial_margin_required: im,
+        concentration_add_on: margin::concentration_add_on(account, state),
+        maintenance_margin_required: mm,
+        initial_margin_hedge_offset: hedge_offset.initial,
+        maintenance_margin_hedge_offset: hedge_offset.maintenance,
+        initial_margin_floor: floors.initial,
+        maintenance_margin_floor: floors.maintenance,
+        liquidatable: margin::is_liquidatable(account, state),
+        in_liquidation: state.in_liquidation.contains(account_id),
+        suspended: account.suspended,
+        liquidation_deferred: state.deferred_liquidations.contains(account_id),
+        limits: account.limits.clone(),
+        leverage: account.leverage.clone(),
+        group_id: account.group_id.clone(),
+        alert_level: account.alert_level,
+        margin_call: account.margin_call,
+        metadata: account.metadata.clone(),
+        funding_paid: account.funding_paid.clone(),
+        last_funding: account.last_funding.clone(),
+        pending_funding: account.pending_funding.clone(),
+        suspended_markets: account.suspended_markets.clone(),
+        frozen: account.frozen.clone(),
+        orders: account.orders.clone(),
+        reserved_margin: margin::reserved_margin(account, state),
+        liquidated_markets: state
+            .liquidated_markets
+            .get(account_id)
+            .cloned()
+            .unwrap_or_default(),
+        stats: state
+            .trade_stats
+            .get(account_id)
+            .map(|s| s.totals.clone())
+            .unwrap_or_default(),
+        positions,
+    }
+}
+
+/// Every market's snapshot, as `capture` records them.
+pub fn capture_markets(state: &State) -> BTreeMap<MarketId, MarketSnapshot> {
+    state
+        .markets
+        .iter()
+        .map(|(market_id, market)| {
+            let snapshot = MarketSnapshot {
+                mark_price: market.mark_price,
+                cumulative_funding_index: market.cumulative_funding_index,
+                initial_margin_fraction: market.initial_margin_fraction,
+                maintenance_margin_fraction: market.maintenance_margin_fraction,
+                stale: market.stale,
+                session_closed: market.session_closed,
+                last_mark_sequence: market.last_mark_sequence,
+                last_mark_timestamp: market.last_mark_timestamp,
+                settled_funding_intervals: market.settled_funding_intervals.clone(),
+                accrued_funding_index: market.accrued_funding_index,
+                expired: market.expired,
+            };
+            (market_id.clone(), snapshot)
+        })
+        .collect()
+}
+
+pub fn capture(state: &State, after_sequence: u64) -> Snapshot {
+    let accounts = state
+        .accounts
+        .iter()
+        .map(|(account_id, account)| (account_id.clone(), capture_account(account, state)))
+        .collect();
+
+    let markets = capture_markets(state);
+
+    let groups = state
+        .groups
+        .iter()
+        .map(|(group_id, group)| {
+            let snapshot = GroupSnapshot {
+                group: group.clone(),
+                notional: margin::group_notional(group_id, state),
+            };
+            (group_id.clone(), snapshot)
+        })
+        .collect();
+
     Snapshot {
         after_sequence,
         accounts,
+        markets,
+        clock: state.clock,
+        insurance_funds: state.insurance_funds.clone(),
+        hedge_pairs: state.hedge_pairs.clone(),
+        groups,
+        backstops: state.backstops.clone(),
+        interest_revenue: state.interest_revenue.clone(),
+        settled_interest_intervals: state.settled_interest_intervals.clone(),
+        distributed_yield_intervals: state.distributed_yield_intervals.clone(),
+        idempotency: state.idempotency.clone(),
+        rejection_history: state.rejection_history.clone(),
+        trade_stats: state.trade_stats.clone(),
+    }
+}
+
+/// The state `snapshot` was captured from. `markets` are the markets as registered:
+/// their fixed parameters come from here, their marks, funding and sessions from the
+/// snapshot. As a proof that nothing was lost or altered, the restored state must
+/// capture back to exactly `snapshot`; otherwise this fails with
+/// `ResumeError::Inconsistent`.
+pub fn restore(snapshot: &Snapshot, markets: Vec<Market>) -> Result<State, ResumeError> {
+    let mut state = State::new();
+    for market in markets {
+        state.markets.insert(market.market_id.clone(), market);
+    }
+    for (market_id, saved) in &snapshot.markets {
+        let Some(market) = state.markets.get_mut(market_id) else {
+            return Err(ResumeError::UnknownMarket {
+                after_sequence: snapshot.after_sequence,
+                market_id: market_id.clone(),
+            });
+        };
+        market.mark_price = saved.mark_price;
+        market.cumulative_funding_index = saved.cumulative_funding_index;
+        market.initial_margin_fraction = saved.initial_margin_fraction;
+        market.maintenance_margin_fraction = saved.maintenance_margin_fraction;
+        market.stale = saved.stale;
+        market.session_closed = saved.session_closed;
+        market.last_mark_sequence = saved.last_mark_sequence;
+        market.last_mark_timestamp = saved.last_mark_timestamp;
+        market.settled_funding_intervals = saved.settled_funding_intervals.clone();
+        market.accrued_funding_index = saved.accrued_funding_index;
+        market.expired = saved.expired;
+    }
+
+    for (account_id, saved) in &snapshot.accounts {
+        let positions = saved
+            .positions
+            .iter()
+            .map(|(market_id, position)| {
+                let position = Position {
+                    market_id: market_id.clone(),
+                    quantity: position.quantity,
+                    cost_basis: position.cost_basis,
+                    funding_paid: position.funding_paid,
+                };
+                (market_id.clone(), position)
+            })
+            .collect();
+        let account = Account {
+            account_id: account_id.clone(),
+            pool_id: saved.pool_id.clone(),
+            principal: saved.principal,
+            trading_balance: saved.collateral - saved.principal,
+            positions,
+            last_funding: saved.last_funding.clone(),
+            funding_paid: saved.funding_paid.clone(),
+            pending_funding: saved.pending_funding.clone(),
+            bankruptcy_deficit: saved.bankruptcy_deficit,
+            suspended: saved.suspended,
+            suspended_markets: saved.suspended_markets.clone(),
+            frozen: saved.frozen.clone(),
+            limits: saved.limits.clone(),
+            leverage: saved.leverage.clone(),
+            group_id: saved.group_id.clone(),
+            alert_level: saved.alert_level,
+            margin_call: saved.margin_call,
+            metadata: saved.metadata.clone(),
+            orders: saved.orders.clone(),
+        };
+        state.accounts.insert(account_id.clone(), account);
+        if saved.in_liquidation {
+            state.in_liquidation.insert(account_id.clone());
+        }
+        if !saved.liquidated_markets.is_empty() {
+            state
+                .liquidated_markets
+                .insert(account_id.clone(), saved.liquidated_markets.clone());
+        }
+        if saved.liquidation_deferred {
+            state.deferred_liquidations.insert(account_id.clone());
+        }
+    }
+    state.clock = snapshot.clock;
+    state.insurance_funds = snapshot.insurance_funds.clone();
+    state.hedge_pairs = snapshot.hedge_pairs.clone();
+    state.groups = snapshot
+        .groups
+        .iter()
+        .map(|(group_id, saved)| (group_id.clone(), saved.group.clone()))
+        .collect();
+    state.backstops = snapshot.backstops.clone();
+    state.interest_revenue = snapshot.interest_revenue.clone();
+    state.settled_interest_intervals = snapshot.settled_interest_intervals.clone();
+    state.distributed_yield_intervals = snapshot.distributed_yield_intervals.clone();
+    state.idempotency = snapshot.idempotency.clone();
+    state.rejection_history = snapshot.rejection_history.clone();
+    state.trade_stats = snapshot.trade_stats.clone();
+
+    let recaptured = capture(&state, snapshot.after_sequence);
+    if recaptured != *snapshot {
+        let part = if let Some(account_id) = snapshot
+            .accounts
+            .keys()
+            .find(|id| recaptured.accounts.get(*id) != snapshot.accounts.get(*id))
+        {
+            format!("account {account_id}")
+        } else if let Some(market_id) = recaptured
+            .markets
+            .keys()
+            .find(|id| recaptured.markets.get(*id) != snapshot.markets.get(*id))
+        {
+            format!("market {market_id}")
+        } else {
+            "engine-level state".to_string()
+        };
+        return Err(ResumeError::Inconsistent {
+            after_sequence: snapshot.after_sequence,
+            part,
+        });
     }
+    Ok(state)
 }
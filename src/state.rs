@@ -1,27 +1,432 @@
-use std::collections::BTreeMap;
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 
-use crate::types::{Account, AccountId, Market, MarketId};
+use crate::config::{RejectionThrottle, StatsWindow};
+use crate::decimal_str;
+use crate::error::StateLoadError;
+use crate::events::{EventType, Liquidity};
+use crate::types::{
+    Account, AccountGroup, AccountId, Backstop, GroupId, HedgePair, Market, MarketId, PoolId,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct State {
     pub accounts: BTreeMap<AccountId, Account>,
     pub markets: BTreeMap<MarketId, Market>,
+
+    #[serde(default)]
+    pub idempotency: IdempotencyWindow,
+
+    /// Log clock: the latest event timestamp seen (Unix ms). Never moves backwards.
+    #[serde(default)]
+    pub clock: Option<u64>,
+
+    /// Accounts liquidated by the current cascade. Set by each `LiquidationFill` or
+    /// accepted `LiquidationTakeover`, cleared by the next event of any other kind.
+    #[serde(default)]
+    pub in_liquidation: BTreeSet<AccountId>,
+    /// Markets each account in `in_liquidation` has had positions closed in during the
+    /// current cascade. Cleared with `in_liquidation`.
+    #[serde(default)]
+    pub liquidated_markets: BTreeMap<AccountId, BTreeSet<MarketId>>,
+
+    /// Liquidatable accounts whose remaining positions are in closed markets, queued
+    /// by `LiquidationDeferred` under `ClosedSessionLiquidation::DeferUntilOpen`. A
+    /// `SessionOpen` releases the accounts holding that market, which are then scanned.
+    #[serde(default)]
+    pub deferred_liquidations: BTreeSet<AccountId>,
+
+    /// Insurance fund balance per collateral pool. Funded by `InsuranceFundDeposit`,
+    /// drawn by `InsuranceFundPayout` to cover deficits of the same pool only.
+    #[serde(default, with = "decimal_str::map")]
+    pub insurance_funds: BTreeMap<PoolId, Decimal>,
+
+    /// Market pairs whose opposite positions get margin relief, in the order
+    /// `HedgePairAdded` added them. No market is in more than one pair.
+    #[serde(default)]
+    pub hedge_pairs: Vec<HedgePair>,
+
+    /// Account groups by id, as `GroupCreated` created them, with their members.
+    #[serde(default)]
+    pub groups: BTreeMap<GroupId, AccountGroup>,
+
+    /// Backstop commitments in priority order: the order `BackstopRegistered` first
+    /// registered each account in each market.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub backstops: Vec<Backstop>,
+
+    /// Venue interest revenue per collateral pool: what `InterestTick`s charged on
+    /// negative balances less what they credited on positive ones, and less the
+    /// `YieldDistribution` payments. Negative when the venue has paid out more than it
+    /// charged.
+    #[serde(default, with = "decimal_str::map")]
+    pub interest_revenue: BTreeMap<PoolId, Decimal>,
+    /// Interval IDs already accrued by `InterestTick`. Duplicates are rejected.
+    #[serde(default)]
+    pub settled_interest_intervals: BTreeSet<u64>,
+    /// Interval IDs already paid by `YieldDistribution`. Duplicates are rejected.
+    #[serde(default)]
+    pub distributed_yield_intervals: BTreeSet<u64>,
+
+    /// Each account's recent rejections, kept only under
+    /// `EngineConfig::rejection_throttle`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rejection_history: BTreeMap<AccountId, RejectionHistory>,
+
+    /// Each account's fills in the window, kept only under `EngineConfig::trade_stats`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub trade_stats: BTreeMap<AccountId, TradeStats>,
+}
+
+/// The most recent idempotency keys seen, with the sequence of the event that
+/// carried each. Rebuilt on replay from the keys in the log; evicts oldest-first.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct IdempotencyWindow {
+    keys: BTreeMap<String, u64>,
+    order: VecDeque<String>,
+}
+
+impl IdempotencyWindow {
+    /// Sequence of the event that first carried `key`, if it is still in the window.
+    pub fn get(&self, key: &str) -> Option<u64> {
+        self.keys.get(key).copied()
+    }
+
+    /// Remember `key` for the event at `sequence`, evicting the oldest keys beyond
+    /// `capacity`. A key already in the window keeps its original sequence.
+    pub fn remember(&mut self, key: &str, sequence: u64, capacity: usize) {
+        if self.keys.contains_key(key) {
+            return;
+        }
+        self.keys.insert(key.to_string(), sequence);
+        self.order.push_back(key.to_string());
+        while self.order.len() > capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.keys.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+}
+
+/// What decides whether an account's next rejection is suppressed. Rebuilt on replay
+/// from the submissions and rejection records in the log; suppressed submissions are
+/// not logged, so they count for nothing.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RejectionHistory {
+    /// Logged submissions naming the account.
+    pub submissions: u64,
+    /// The value of `submissions` at each of its latest rejections, oldest first: at
+    /// most `RejectionThrottle::max_rejections` of them.
+    pub rejected_at: VecDeque<u64>,
+    /// The record of its latest rejection.
+    pub last: Option<EventType>,
+}
+
+impl RejectionHistory {
+    /// Whether the account's `submission`th submission is throttled: at least
+    /// `max_rejections` of the `window` submissions up to it were rejected.
+    pub fn throttles(&self, submission: u64, throttle: &RejectionThrottle) -> bool {
+        throttle.max_rejections > 0
+            && self.rejected_at.len() >= throttle.max_rejections
+            && self
+                .rejected_at
+                .front()
+                .is_some_and(|first| submission.saturating_sub(*first) < throttle.window)
+    }
+
+    /// Count the rejection of the latest submission, recorded as `record`.
+    pub(crate) fn reject(&mut self, record: &EventType, throttle: &RejectionThrottle) {
+        self.rejected_at.push_back(self.submissions);
+        while self.rejected_at.len() > throttle.max_rejections {
+            self.rejected_at.pop_front();
+        }
+        self.last = Some(record.clone());
+    }
+}
+
+/// An account's fills in its `StatsWindow`, oldest first, with their totals. Rebuilt
+/// on replay from the fills in the log.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TradeStats {
+    pub fills: VecDeque<StatsFill>,
+    pub totals: AccountStats,
+}
+
+/// One fill counted in an account's statistics.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StatsFill {
+    /// The log clock when the fill was applied (0 before the first timestamp).
+    pub clock: u64,
+    /// |quantity × price|.
+    #[serde(with = "decimal_str")]
+    pub notional: Decimal,
+    /// A liquidation step against the account rather than a trade of its own.
+    pub liquidation: bool,
+    /// The side of the match a trade was on, as its `TradeFill` gave it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub liquidity: Option<Liquidity>,
+}
+
+/// An account's totals over its `StatsWindow`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AccountStats {
+    /// Notional of its `TradeFill`s: the turnover fee tiers are set by.
+    #[serde(with = "decimal_str")]
+    pub turnover: Decimal,
+    pub trades: u64,
+    /// The part of `turnover` whose fills were flagged `Maker`, and `Taker`. Fills
+    /// without a flag count in neither.
+    #[serde(default, with = "decimal_str")]
+    pub maker_notional: Decimal,
+    #[serde(default, with = "decimal_str")]
+    pub taker_notional: Decimal,
+    /// Notional closed or taken over by liquidation steps.
+    #[serde(with = "decimal_str")]
+    pub liquidated_notional: Decimal,
+    pub liquidations: u64,
+}
+
+impl AccountStats {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    fn add(&mut self, fill: &StatsFill) {
+        if fill.liquidation {
+            self.liquidated_notional += fill.notional;
+            self.liquidations += 1;
+        } else {
+            self.turnover += fill.notional;
+            self.trades += 1;
+            match fill.liquidity {
+                Some(Liquidity::Maker) => self.maker_notional += fill.notional,
+                Some(Liquidity::Taker) => self.taker_notional += fill.notional,
+                None => {}
+            }
+        }
+    }
+
+    fn remove(&mut self, fill: &StatsFill) {
+        if fill.liquidation {
+            self.liquidated_notional -= fill.notional;
+            self.liquidations -= 1;
+        } else {
+            self.turnover -= fill.notional;
+            self.trades -= 1;
+            match fill.liquidity {
+                Some(Liquidity::Maker) => self.maker_notional -= fill.notional,
+                Some(Liquidity::Taker) => self.taker_notional -= fill.notional,
+                None => {}
+            }
+        }
+    }
+}
+
+impl TradeStats {
+    /// Count `fill`, then drop what has left `window`.
+    pub(crate) fn record(&mut self, fill: StatsFill, window: StatsWindow) {
+        self.totals.add(&fill);
+        self.fills.push_back(fill);
+        match window {
+            StatsWindow::Fills(n) => {
+                while self.fills.len() > n {
+                    self.evict();
+                }
+            }
+            StatsWindow::Millis(_) => {
+                self.expire(self.fills.back().map_or(0, |f| f.clock), window);
+            }
+        }
+    }
+
+    /// Drop the fills a `Millis` window has moved past at `clock`. Whether it dropped any.
+    pub(crate) fn expire(&mut self, clock: u64, window: StatsWindow) -> bool {
+        let StatsWindow::Millis(millis) = window else {
+            return false;
+        };
+        let mut expired = false;
+        while self
+            .fills
+            .front()
+            .is_some_and(|f| clock.saturating_sub(f.clock) >= millis)
+        {
+            self.evict();
+            expired = true;
+        }
+        expired
+    }
+
+    /// Take in another account's fills, as a merge does, in clock order with this
+    /// account's first on a tie, then trim to `window` as recording them would.
+    pub(crate) fn absorb(&mut self, other: TradeStats, window: StatsWindow) {
+        let mut fills: Vec<StatsFill> = std::mem::take(&mut self.fills)
+            .into_iter()
+            .chain(other.fills)
+            .collect();
+        fills.sort_by_key(|fill| fill.clock);
+        *self = TradeStats::default();
+        for fill in fills {
+            self.record(fill, window);
+        }
+    }
+
+    fn evict(&mut self) {
+        if let Some(fill) = self.fills.pop_front() {
+            self.totals.remove(&fill);
+        }
+    }
 }
 
 use serde::{Deserialize, Serialize};
 
+/// Version of the `State::to_json` format. Bump it when a change to `State` would
+/// make an older reader misread a newer file. Version 1 kept each account's balance
+/// as a single `collateral`.
+pub const STATE_SCHEMA_VERSION: u32 = 2;
+
+/// The serialized form of a `State`: its fields, plus `schema_version`.
+#[derive(Serialize)]
+struct StateFileRef<'a> {
+    schema_version: u32,
+    #[serde(flatten)]
+    state: &'a State,
+}
+
+#[derive(Deserialize)]
+struct StateFile {
+    schema_version: u32,
+    #[serde(flatten)]
+    state: State,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl State {
     pub fn new() -> Self {
         Self {
             accounts: BTreeMap::new(),
             markets: BTreeMap::new(),
+            idempotency: IdempotencyWindow::default(),
+            clock: None,
+            in_liquidation: BTreeSet::new(),
+            liquidated_markets: BTreeMap::new(),
+            deferred_liquidations: BTreeSet::new(),
+            insurance_funds: BTreeMap::new(),
+            hedge_pairs: Vec::new(),
+            groups: BTreeMap::new(),
+            backstops: Vec::new(),
+            interest_revenue: BTreeMap::new(),
+            settled_interest_intervals: BTreeSet::new(),
+            distributed_yield_intervals: BTreeSet::new(),
+            rejection_history: BTreeMap::new(),
+            trade_stats: BTreeMap::new(),
         }
     }
 
-    pub fn get_or_create_account(&mut self, account_id: &str) -> &mut Account {
+    /// The state as JSON, tagged with `STATE_SCHEMA_VERSION`. Every decimal is a
+    /// normalized string, as in events, so `from_json` reads back an equal state.
+    pub fn to_json(&self) -> String {
+        let file = StateFileRef {
+            schema_version: STATE_SCHEMA_VERSION,
+            state: self,
+        };
+        serde_json::to_string(&file).expect("a state always serializes")
+    }
+
+    /// Read a state written by `to_json`. Fails on another schema version, on a
+    /// market that `Market::validate` refuses, on positions in markets the state
+    /// does not register, and on accounts whose group membership disagrees with the
+    /// groups' member lists (all of them are listed).
+    pub fn from_json(json: &str) -> Result<State, StateLoadError> {
+        let file: StateFile = serde_json::from_str(json)?;
+        if file.schema_version != STATE_SCHEMA_VERSION {
+            return Err(StateLoadError::SchemaVersion {
+                found: file.schema_version,
+                supported: STATE_SCHEMA_VERSION,
+            });
+        }
+        let state = file.state;
+        for market in state.markets.values() {
+            market.validate()?;
+        }
+        let unknown: Vec<(AccountId, MarketId)> = state
+            .accounts
+            .iter()
+            .flat_map(|(account_id, account)| {
+                account
+                    .positions
+                    .keys()
+                    .filter(|market_id| !state.markets.contains_key(*market_id))
+                    .map(move |market_id| (account_id.clone(), market_id.clone()))
+            })
+            .collect();
+        if !unknown.is_empty() {
+            return Err(StateLoadError::UnknownMarkets(unknown));
+        }
+        let listed = |account: &Account, group_id: &str| {
+            state
+                .groups
+                .get(group_id)
+                .is_some_and(|g| g.members.contains(&account.account_id))
+        };
+        let mut mismatched: BTreeSet<AccountId> = state
+            .accounts
+            .values()
+            .filter(|a| a.group_id.as_ref().is_some_and(|g| !listed(a, g)))
+            .map(|a| a.account_id.clone())
+            .collect();
+        for (group_id, group) in &state.groups {
+            mismatched.extend(
+                group
+                    .members
+                    .iter()
+                    .filter(|id| {
+                        let account = state.accounts.get(*id);
+                        account.is_none_or(|a| a.group_id.as_ref() != Some(group_id))
+                    })
+                    .cloned(),
+            );
+        }
+        if !mismatched.is_empty() {
+            return Err(StateLoadError::GroupMembership(
+                mismatched.into_iter().collect(),
+            ));
+        }
+        Ok(state)
+    }
+
+    pub fn get_or_create_account(&mut self, account_id: &AccountId) -> &mut Account {
         self.accounts
-            .entry(account_id.to_string())
-            .or_insert_with(|| Account::new(account_id.to_string()))
+            .entry(account_id.clone())
+            .or_insert_with(|| Account::new(account_id.clone()))
+    }
+
+    /// Insurance fund balance of `pool_id`, zero if it was never funded.
+    pub fn insurance_fund(&self, pool_id: &str) -> Decimal {
+        self.insurance_funds
+            .get(pool_id)
+            .copied()
+            .unwrap_or(Decimal::ZERO)
+    }
+
+    /// Interest revenue of `pool_id`, zero if no tick ever touched it.
+    pub fn interest_revenue(&self, pool_id: &str) -> Decimal {
+        self.interest_revenue
+            .get(pool_id)
+            .copied()
+            .unwrap_or(Decimal::ZERO)
     }
 
     pub fn accounts_with_position_in(&self, market_id: &str) -> Vec<AccountId> {
@@ -31,4 +436,363 @@ impl State {
             .map(|(id, _)| id.clone())
             .collect()
     }
+
+    /// Deregister a market no account holds (see `risk::check_market_removal`), with
+    /// what refers to it: hedge pairs and backstops it is in, and accounts' leverage
+    /// selections and funding checkpoints in it. Lifetime funding totals stay, as
+    /// after a close.
+    pub fn remove_market(&mut self, market_id: &str) {
+        self.markets.remove(market_id);
+        self.hedge_pairs
+            .retain(|pair| pair.market_a != market_id && pair.market_b != market_id);
+        self.backstops
+            .retain(|backstop| backstop.market_id != market_id);
+        for account in self.accounts.values_mut() {
+            account.leverage.remove(market_id);
+            account.last_funding.remove(market_id);
+        }
+    }
+
+    /// Close an account, as a merge does its source, with what refers to it: its group
+    /// membership, backstop registrations, queued liquidation and trade statistics.
+    pub fn remove_account(&mut self, account_id: &str) {
+        if let Some(account) = self.accounts.remove(account_id) {
+            if let Some(group) = account.group_id.and_then(|g| self.groups.get_mut(&g)) {
+                group.members.remove(account_id);
+            }
+        }
+        self.backstops
+            .retain(|backstop| backstop.account_id != account_id);
+        self.deferred_liquidations.remove(account_id);
+        self.trade_stats.remove(account_id);
+    }
+
+    /// Cap what each backstop has absorbed at the position it now holds, releasing the
+    /// capacity its reductions freed. Run after every applied event.
+    pub(crate) fn release_backstops(&mut self) {
+        for backstop in &mut self.backstops {
+            let held = self
+                .accounts
+                .get(&backstop.account_id)
+                .and_then(|account| account.positions.get(&backstop.market_id))
+                .map_or(Decimal::ZERO, |position| position.quantity.abs());
+            backstop.absorbed = backstop.absorbed.min(held);
+        }
+    }
+
+    /// Advance the log clock to `timestamp` (if later) and refresh every market's
+    /// `stale` flag against it.
+    pub fn advance_clock(&mut self, timestamp: u64) {
+        let now = self.clock.map_or(timestamp, |c| c.max(timestamp));
+        self.clock = Some(now);
+        for market in self.markets.values_mut() {
+            market.stale = market.is_stale_at(now);
+        }
+    }
+
+    /// Net open interest of a market in contracts: longs minus shorts over all accounts.
+    pub fn net_open_interest(&self, market_id: &str) -> Decimal {
+        self.accounts
+            .values()
+            .filter_map(|acc| acc.positions.get(market_id))
+            .map(|pos| pos.quantity)
+            .sum()
+    }
+
+    /// Net open interest notional at the absolute mark, what a skew limit is checked
+    /// against: positive when longs are the heavy side. Saturates at `Decimal`'s range.
+    pub fn net_notional(&self, market_id: &str) -> Decimal {
+        let net = self.net_open_interest(market_id);
+        let mark = self
+            .markets
+            .get(market_id)
+            .map_or(Decimal::ZERO, |m| m.mark_price.abs());
+        net.checked_mul(mark).unwrap_or(if net.is_sign_negative() {
+            Decimal::MIN
+        } else {
+            Decimal::MAX
+        })
+    }
+
+    /// Gross open interest of a market in contracts: sum of |quantity| over all accounts.
+    pub fn open_interest(&self, market_id: &str) -> Decimal {
+        self.accounts
+            .values()
+            .filter_map(|acc| acc.positions.get(market_id))
+            .map(|pos| pos.quantity.abs())
+            .sum()
+    }
+
+    /// Gross open interest notional of every market together: `|quantity| × |mark|`
+    /// over all positions, what `EngineConfig::max_total_open_interest_notional` caps.
+    pub fn total_open_interest_notional(&self) -> Decimal {
+        self.accounts
+            .values()
+            .flat_map(|acc| acc.positions.iter())
+            .filter_map(|(market_id, pos)| {
+                let market = self.markets.get(market_id)?;
+                Some(pos.quantity.abs() * market.mark_price.abs())
+            })
+            .sum()
+    }
+}
+
+/// Cash that entered or left the balances of the book (or one pool of it). See
+/// `EngineMetrics`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CashFlows {
+    /// Collateral, cost basis and insurance funds already in the state the engine
+    /// started from.
+    #[serde(with = "decimal_str")]
+    pub opening_collateral: Decimal,
+    #[serde(with = "decimal_str")]
+    pub opening_cost_basis: Decimal,
+    #[serde(default, with = "decimal_str")]
+    pub opening_insurance: Decimal,
+    #[serde(default, with = "decimal_str")]
+    pub opening_interest_revenue: Decimal,
+    #[serde(with = "decimal_str")]
+    pub deposits: Decimal,
+    #[serde(with = "decimal_str")]
+    pub withdrawals: Decimal,
+    /// Contributions to insurance funds (`InsuranceFundDeposit`).
+    #[serde(default, with = "decimal_str")]
+    pub insurance_deposits: Decimal,
+    /// Collateral and open cost basis brought in by accepted `StateImport`s. They
+    /// count like opening balances.
+    #[serde(default, with = "decimal_str")]
+    pub imported_collateral: Decimal,
+    #[serde(default, with = "decimal_str")]
+    pub imported_cost_basis: Decimal,
+    /// Cash exchanged with counterparties outside the book: `-quantity * price` summed
+    /// over accepted `TradeFill`s and `LiquidationFill`s. A takeover moves cash between
+    /// two accounts in the book and nets to zero, so it is not counted.
+    #[serde(with = "decimal_str")]
+    pub fill_cash_flow: Decimal,
+    /// Funding settled into collateral, net (positive = received by accounts).
+    #[serde(with = "decimal_str")]
+    pub funding: Decimal,
+    /// Trade fees charged to accounts (`FeeCharged`), net of rebates: the venue's fee
+    /// revenue.
+    #[serde(default, with = "decimal_str")]
+    pub fees: Decimal,
+}
+
+impl CashFlows {
+    fn opening<'a>(
+        accounts: impl Iterator<Item = &'a Account> + Clone,
+        insurance: Decimal,
+        interest_revenue: Decimal,
+    ) -> Self {
+        Self {
+            opening_collateral: accounts.clone().map(|a| a.collateral()).sum(),
+            opening_cost_basis: cost_basis(accounts),
+            opening_insurance: insurance,
+            opening_interest_revenue: interest_revenue,
+            ..Self::default()
+        }
+    }
+}
+
+/// Running totals of the cash that entered or left the accounts in the book, kept by
+/// the engine alongside its state, for the whole book and per collateral pool.
+/// Updated from accepted events only, so replay rebuilds them exactly.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct EngineMetrics {
+    #[serde(flatten)]
+    pub total: CashFlows,
+    /// The same totals per pool. They sum to `total`.
+    #[serde(default)]
+    pub pools: BTreeMap<PoolId, CashFlows>,
+    /// `MarkPriceUpdate`s and `FundingUpdate`s ignored because their market is not
+    /// registered (one `UnknownMarketIgnored` each).
+    #[serde(default)]
+    pub unknown_markets_ignored: u64,
+    /// Rejected submissions left out of the log under
+    /// `EngineConfig::rejection_throttle`, as counted by its `RejectionSuppressed`
+    /// summaries.
+    #[serde(default)]
+    pub rejections_suppressed: u64,
+}
+
+impl EngineMetrics {
+    /// Metrics for an engine seeded from `state`: its balances count as opening funds.
+    pub fn opening(state: &State) -> Self {
+        let pools = pool_ids(state)
+            .into_iter()
+            .map(|pool_id| {
+                let flows = CashFlows::opening(
+                    pool_accounts(state, &pool_id),
+                    state.insurance_fund(&pool_id),
+                    state.interest_revenue(&pool_id),
+                );
+                (pool_id, flows)
+            })
+            .collect();
+        Self {
+            total: CashFlows::opening(
+                state.accounts.values(),
+                state.insurance_funds.values().sum(),
+                state.interest_revenue.values().sum(),
+            ),
+            pools,
+            ..Self::default()
+        }
+    }
+
+    /// Apply `update` to the totals and to `pool_id`'s flows.
+    pub(crate) fn record(&mut self, pool_id: &str, update: impl Fn(&mut CashFlows)) {
+        update(&mut self.total);
+        update(self.pools.entry(pool_id.to_string()).or_default());
+    }
+}
+
+/// Both sides of the books and what is left over. See `solvency`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SolvencyReport {
+    /// Sum of collateral over all accounts, negative balances of bankrupt accounts
+    /// included.
+    #[serde(with = "decimal_str")]
+    pub total_collateral: Decimal,
+    /// Insurance fund balances.
+    #[serde(default, with = "decimal_str")]
+    pub insurance_funds: Decimal,
+    /// Venue interest revenue. Interest moves collateral between accounts and this
+    /// bucket, so like the insurance funds it is part of what the book holds.
+    #[serde(default, with = "decimal_str")]
+    pub interest_revenue: Decimal,
+    /// Venue fee revenue: trade fees less rebates, as charged to collateral.
+    #[serde(default, with = "decimal_str")]
+    pub fee_revenue: Decimal,
+
+    /// Opening and imported collateral, opening insurance and interest revenue, plus
+    /// deposits (to accounts and to insurance funds) less withdrawals.
+    #[serde(with = "decimal_str")]
+    pub net_transfers: Decimal,
+    /// PnL the book realized against outside counterparties: the fill cash flow plus
+    /// the change in open cost basis.
+    #[serde(with = "decimal_str")]
+    pub realized_pnl: Decimal,
+    #[serde(with = "decimal_str")]
+    pub funding: Decimal,
+
+    /// Outstanding bankruptcy deficits. Informational: they are already part of
+    /// `total_collateral` as negative balances.
+    #[serde(with = "decimal_str")]
+    pub bankruptcy_deficits: Decimal,
+
+    /// `total_collateral + insurance_funds + interest_revenue + fee_revenue -
+    /// (net_transfers + realized_pnl + funding)`.
+    /// Zero when no value was created or destroyed.
+    #[serde(with = "decimal_str")]
+    pub residual: Decimal,
+}
+
+impl SolvencyReport {
+    pub fn is_balanced(&self) -> bool {
+        self.residual.is_zero()
+    }
+}
+
+/// Check that the collateral held for accounts, plus the insurance funds and the
+/// venue's interest and fee revenue, is exactly what came in from transfers, trading
+/// against the outside and funding.
+///
+/// Realized PnL is derived from cash flows (`metrics.total.fill_cash_flow`) and the
+/// cost basis still open, never from collateral, so a bug that realizes PnL twice,
+/// drops a cost basis or settles funding into the wrong balance shows up in `residual`.
+pub fn solvency(state: &State, metrics: &EngineMetrics) -> SolvencyReport {
+    books(
+        state.accounts.values(),
+        state.insurance_funds.values().sum(),
+        state.interest_revenue.values().sum(),
+        &metrics.total,
+    )
+}
+
+/// `solvency` restricted to one collateral pool: its accounts, its insurance fund and
+/// its cash flows. A nonzero residual means value crossed into or out of the pool.
+pub fn pool_solvency(state: &State, metrics: &EngineMetrics, pool_id: &str) -> SolvencyReport {
+    books(
+        pool_accounts(state, pool_id),
+        state.insurance_fund(pool_id),
+        state.interest_revenue(pool_id),
+        &metrics.pools.get(pool_id).cloned().unwrap_or_default(),
+    )
+}
+
+/// `pool_solvency` for every pool that has an account, an insurance fund or cash flows.
+pub fn solvency_by_pool(
+    state: &State,
+    metrics: &EngineMetrics,
+) -> BTreeMap<PoolId, SolvencyReport> {
+    let mut pools = pool_ids(state);
+    pools.extend(metrics.pools.keys().cloned());
+    pools
+        .into_iter()
+        .map(|pool_id| {
+            let report = pool_solvency(state, metrics, &pool_id);
+            (pool_id, report)
+        })
+        .collect()
+}
+
+fn books<'a>(
+    accounts: impl Iterator<Item = &'a Account> + Clone,
+    insurance_funds: Decimal,
+    interest_revenue: Decimal,
+    flows: &CashFlows,
+) -> SolvencyReport {
+    let total_collateral: Decimal = accounts.clone().map(|a| a.collateral()).sum();
+    let net_transfers = flows.opening_collateral
+        + flows.opening_insurance
+        + flows.opening_interest_revenue
+        + flows.deposits
+        - flows.withdrawals
+        + flows.insurance_deposits
+        + flows.imported_collateral;
+    let realized_pnl = flows.fill_cash_flow + cost_basis(accounts.clone())
+        - flows.opening_cost_basis
+        - flows.imported_cost_basis;
+    let residual = total_collateral + insurance_funds + interest_revenue + flows.fees
+        - (net_transfers + realized_pnl + flows.funding);
+
+    SolvencyReport {
+        total_collateral,
+        insurance_funds,
+        interest_revenue,
+        fee_revenue: flows.fees,
+        net_transfers,
+        realized_pnl,
+        funding: flows.funding,
+        bankruptcy_deficits: accounts.map(|a| a.bankruptcy_deficit).sum(),
+        residual,
+    }
+}
+
+fn pool_accounts<'a>(
+    state: &'a State,
+    pool_id: &'a str,
+) -> impl Iterator<Item = &'a Account> + Clone {
+    state
+        .accounts
+        .values()
+        .filter(move |a| a.pool_id == pool_id)
+}
+
+fn pool_ids(state: &State) -> BTreeSet<PoolId> {
+    state
+        .accounts
+        .values()
+        .map(|a| a.pool_id.clone())
+        .chain(state.insurance_funds.keys().cloned())
+        .chain(state.interest_revenue.keys().cloned())
+        .collect()
+}
+
+fn cost_basis<'a>(accounts: impl Iterator<Item = &'a Account>) -> Decimal {
+    accounts
+        .flat_map(|a| a.positions.values())
+        .map(|p| p.cost_basis)
+        .sum()
 }
@@ -0,0 +1,66 @@
+//! `tracing` instrumentation, compiled in by the `trace` feature.
+//!
+//! `span!` enters a `DEBUG` span until the returned guard drops, and `event!` emits
+//! a `DEBUG` event, both under the `cross_margin_engine` target with `tracing`'s
+//! field syntax. Without the feature both expand to nothing: their fields are never
+//! evaluated, let alone formatted.
+
+#[cfg(feature = "trace")]
+macro_rules! span {
+    ($name:literal $(, $($fields:tt)*)?) => {
+        ::tracing::debug_span!(target: "cross_margin_engine", $name $(, $($fields)*)?).entered()
+    };
+}
+
+#[cfg(not(feature = "trace"))]
+macro_rules! span {
+    ($($tokens:tt)*) => {
+        $crate::trace::Disabled
+    };
+}
+
+#[cfg(feature = "trace")]
+macro_rules! event {
+    ($($fields:tt)+) => {
+        ::tracing::debug!(target: "cross_margin_engine", $($fields)+)
+    };
+}
+
+#[cfg(not(feature = "trace"))]
+macro_rules! event {
+    ($($tokens:tt)*) => {};
+}
+
+pub(crate) use {event, span};
+
+/// What `span!` returns without the `trace` feature.
+#[cfg(not(feature = "trace"))]
+pub(crate) struct Disabled;
+
+/// Displays each of an account's positions in a known market as `market=notional`,
+/// the candidates a liquidation step is selected from. Formatted only by a
+/// subscriber that records the field.
+#[cfg(feature = "trace")]
+pub(crate) struct Notionals<'a> {
+    pub state: &'a crate::state::State,
+    pub account_id: &'a crate::types::AccountId,
+}
+
+#[cfg(feature = "trace")]
+impl std::fmt::Display for Notionals<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Some(account) = self.state.accounts.get(self.account_id) else {
+            return Ok(());
+        };
+        let mut separator = "";
+        for (market_id, position) in &account.positions {
+            if let Some(market) = self.state.markets.get(market_id) {
+                let notional =
+                    crate::margin::position_notional(position.quantity, market.mark_price);
+                write!(f, "{separator}{market_id}={notional}")?;
+                separator = " ";
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,49 +1,629 @@
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
 
-pub type AccountId = String;
-pub type MarketId = String;
+use crate::config::WithdrawalOrder;
+use crate::decimal_str;
+use crate::error::{IdError, MarketConfigError};
+
+/// Longest account or market ID accepted, in bytes.
+pub const MAX_ID_LEN: usize = 64;
+
+/// Check `id` as an ID of `kind`: non-empty, at most `MAX_ID_LEN` bytes, and only
+/// ASCII letters, digits, `-`, `_`, `.` and `:`.
+fn validate_id(kind: &'static str, id: &str) -> Result<(), IdError> {
+    if id.is_empty() {
+        return Err(IdError::Empty { kind });
+    }
+    if id.len() > MAX_ID_LEN {
+        return Err(IdError::TooLong {
+            kind,
+            len: id.len(),
+        });
+    }
+    match id
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':')))
+    {
+        Some(found) => Err(IdError::InvalidChar {
+            kind,
+            id: id.to_string(),
+            found,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// A validated string ID. It serializes as the bare string, so logs read the same
+/// as before, and an invalid one fails to deserialize. It borrows as `str`, so maps
+/// keyed by it are looked up with a `&str`.
+macro_rules! string_id {
+    ($(#[$doc:meta])* $name:ident, $kind:literal) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+        #[serde(try_from = "String", into = "String")]
+        pub struct $name(String);
+
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl TryFrom<String> for $name {
+            type Error = IdError;
+
+            fn try_from(id: String) -> Result<Self, IdError> {
+                validate_id($kind, &id)?;
+                Ok(Self(id))
+            }
+        }
+
+        impl TryFrom<&str> for $name {
+            type Error = IdError;
+
+            fn try_from(id: &str) -> Result<Self, IdError> {
+                Self::try_from(id.to_string())
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = IdError;
+
+            fn from_str(id: &str) -> Result<Self, IdError> {
+                Self::try_from(id)
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(id: $name) -> String {
+                id.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl Deref for $name {
+            type Target = str;
+
+            fn deref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl Borrow<str> for $name {
+            fn borrow(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                self.0 == other
+            }
+        }
+
+        impl PartialEq<&str> for $name {
+            fn eq(&self, other: &&str) -> bool {
+                self.0 == *other
+            }
+        }
+
+        impl PartialEq<$name> for str {
+            fn eq(&self, other: &$name) -> bool {
+                self == other.0
+            }
+        }
+
+        impl PartialEq<$name> for &str {
+            fn eq(&self, other: &$name) -> bool {
+                *self == other.0
+            }
+        }
+    };
+}
+
+string_id!(
+    /// A trading account.
+    AccountId,
+    "account"
+);
+string_id!(
+    /// A market (instrument).
+    MarketId,
+    "market"
+);
+
+/// A segregated collateral pool (legal entity). Losses and insurance never cross pools.
+pub type PoolId = String;
+/// A named set of accounts sharing a notional cap, such as a market-maker tier.
+pub type GroupId = String;
+/// An order's id, unique among its account's resting orders.
+pub type OrderId = String;
+
+/// Pool of every account not explicitly assigned one with `AssignPool`.
+pub const DEFAULT_POOL: &str = "default";
+
+pub(crate) fn default_pool() -> PoolId {
+    DEFAULT_POOL.to_string()
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Position {
     pub market_id: MarketId,
+    #[serde(with = "decimal_str")]
     pub quantity: Decimal,
+    #[serde(with = "decimal_str")]
     pub cost_basis: Decimal,
+    /// Net funding paid on this position since it was opened (negative = received).
+    /// Resets when the position closes or flips.
+    #[serde(default, with = "decimal_str")]
+    pub funding_paid: Decimal,
+}
+
+impl Position {
+    /// Average entry price, `cost_basis / quantity`. Positive for shorts too, whose
+    /// quantity and cost basis are both negative. `None` when flat.
+    pub fn entry_price(&self) -> Option<Decimal> {
+        self.cost_basis.checked_div(self.quantity)
+    }
+
+    /// Mark at which closing the whole position nets zero after `fees_paid` and
+    /// `funding_paid` (both positive = paid): `(cost_basis + fees + funding) / quantity`.
+    /// Costs push a long's break-even above its entry price and a short's below it.
+    /// `None` when flat.
+    pub fn break_even_price(&self, fees_paid: Decimal, funding_paid: Decimal) -> Option<Decimal> {
+        (self.cost_basis + fees_paid + funding_paid).checked_div(self.quantity)
+    }
+}
+
+/// One open position carried into the engine by `StateImport`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ImportedPosition {
+    pub market_id: MarketId,
+    #[serde(with = "decimal_str")]
+    pub quantity: Decimal,
+    #[serde(with = "decimal_str")]
+    pub cost_basis: Decimal,
+    /// Cumulative funding index the position was last settled at. The next funding
+    /// event settles from here, so funding the old system had not yet charged carries over.
+    #[serde(with = "decimal_str")]
+    pub last_funding: Decimal,
+}
+
+/// Compliance limits set per account via `SetAccountLimits`. `None` means unlimited.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AccountLimits {
+    #[serde(default, with = "decimal_str::option")]
+    pub max_leverage: Option<Decimal>,
+    #[serde(default, with = "decimal_str::option")]
+    pub max_total_notional: Option<Decimal>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Account {
     pub account_id: AccountId,
-    pub collateral: Decimal,
+    /// Set when the account is created (by `AssignPool`, else `DEFAULT_POOL`) and
+    /// never changed afterwards.
+    #[serde(default = "default_pool")]
+    pub pool_id: PoolId,
+    /// Customer principal: net deposits and withdrawals, and the balance an account
+    /// was imported with.
+    #[serde(with = "decimal_str")]
+    pub principal: Decimal,
+    /// Everything else credited or debited: realized PnL (liquidation closes and
+    /// takeover discounts included), funding, interest, yield, expiry settlement,
+    /// insurance payouts and socialized losses.
+    #[serde(with = "decimal_str")]
+    pub trading_balance: Decimal,
     pub positions: BTreeMap<MarketId, Position>,
+    #[serde(with = "decimal_str::map")]
     pub last_funding: BTreeMap<MarketId, Decimal>,
+    /// Net funding paid per market over the account's lifetime (negative = received).
+    /// Unlike `Position::funding_paid`, it survives position closes.
+    #[serde(default, with = "decimal_str::map")]
+    pub funding_paid: BTreeMap<MarketId, Decimal>,
+    /// Funding accrued by `FundingAccrual` and not yet settled, per market (positive =
+    /// to be received). The market's next settlement pays it into the trading balance,
+    /// whether or not the position is still open. Counts toward equity in a
+    /// `FundingMode::Continuous` market only.
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        with = "decimal_str::map"
+    )]
+    pub pending_funding: BTreeMap<MarketId, Decimal>,
 
     /// If all positions are closed and collateral is negative, this records the
     /// bankruptcy deficit as a non-negative number (auditable + replay-stable).
     /// Otherwise this is zero.
+    #[serde(with = "decimal_str")]
     pub bankruptcy_deficit: Decimal,
+    /// Set whenever a bankruptcy deficit is recorded; cleared only by an accepted
+    /// `AccountReinstated`, which requires the deficit to be repaid first.
+    #[serde(default)]
+    pub suspended: bool,
+    /// Markets the account was liquidated in by the cascade that bankrupted it (see
+    /// `BankruptcySuspension::BankruptedMarkets`). Cleared on reinstatement.
+    #[serde(default)]
+    pub suspended_markets: BTreeSet<MarketId>,
+    /// The reason of the `ForceClose` that froze the account: set, it takes no trades,
+    /// keeper takeovers or withdrawals. Cleared by `AccountReinstated`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frozen: Option<String>,
+
+    #[serde(default)]
+    pub limits: AccountLimits,
+    /// Leverage selected per market with `SetPositionLeverage`, kept while the
+    /// account is flat there. A market without an entry margins at its own fraction.
+    #[serde(default, with = "decimal_str::map")]
+    pub leverage: BTreeMap<MarketId, Decimal>,
+    /// The account's group, set by `GroupMembershipSet`. `None` outside any group.
+    #[serde(default)]
+    pub group_id: Option<GroupId>,
+    /// Margin-usage alert level: how many `RiskAlertLadder` thresholds the account is
+    /// at, as set by the latest `RiskAlert` or `RiskAlertCleared`.
+    #[serde(default)]
+    pub alert_level: usize,
+    /// The open margin call, set by `MarginCall` and cleared by `MarginCallCleared`.
+    /// `None` when the account has none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub margin_call: Option<MarginCallOpen>,
+
+    /// Operator-facing labels (desk name, contact, ...) set via `AccountMetadata`.
+    /// Never read by margin math.
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    /// Resting orders by id, each reserving margin (see `margin::reserved_margin`).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub orders: BTreeMap<OrderId, RestingOrder>,
 }
 
 impl Account {
     pub fn new(account_id: AccountId) -> Self {
+        Self::in_pool(account_id, default_pool())
+    }
+
+    pub fn in_pool(account_id: AccountId, pool_id: PoolId) -> Self {
         Self {
             account_id,
-            collateral: Decimal::ZERO,
+            pool_id,
+            principal: Decimal::ZERO,
+            trading_balance: Decimal::ZERO,
             positions: BTreeMap::new(),
             last_funding: BTreeMap::new(),
+            funding_paid: BTreeMap::new(),
+            pending_funding: BTreeMap::new(),
             bankruptcy_deficit: Decimal::ZERO,
+            suspended: false,
+            suspended_markets: BTreeSet::new(),
+            frozen: None,
+            limits: AccountLimits::default(),
+            leverage: BTreeMap::new(),
+            group_id: None,
+            alert_level: 0,
+            margin_call: None,
+            metadata: BTreeMap::new(),
+            orders: BTreeMap::new(),
+        }
+    }
+
+    /// `principal + trading_balance`, the balance every margin figure is computed on.
+    pub fn collateral(&self) -> Decimal {
+        self.principal + self.trading_balance
+    }
+
+    /// Debit a withdrawal of `amount`: from the positive part of the balance `order`
+    /// names first, the rest from the other. Returns the amounts taken from
+    /// `principal` and `trading_balance`.
+    pub fn withdraw(&mut self, amount: Decimal, order: WithdrawalOrder) -> (Decimal, Decimal) {
+        let first = match order {
+            WithdrawalOrder::TradingBalanceFirst => self.trading_balance,
+            WithdrawalOrder::PrincipalFirst => self.principal,
+        };
+        let from_first = amount.min(first.max(Decimal::ZERO));
+        let (from_principal, from_trading) = match order {
+            WithdrawalOrder::TradingBalanceFirst => (amount - from_first, from_first),
+            WithdrawalOrder::PrincipalFirst => (from_first, amount - from_first),
+        };
+        self.principal -= from_principal;
+        self.trading_balance -= from_trading;
+        (from_principal, from_trading)
+    }
+
+    /// Settle the funding pending in `market_id` alone, as a settlement of the market
+    /// would: it moves to the trading balance and counts as funding paid on the market
+    /// and on the position. Returns the amount (positive = received).
+    pub(crate) fn settle_pending_funding(&mut self, market_id: &MarketId) -> Decimal {
+        let Some(amount) = self.pending_funding.remove(market_id) else {
+            return Decimal::ZERO;
+        };
+        self.trading_balance += amount;
+        if let Some(position) = self.positions.get_mut(market_id) {
+            position.funding_paid -= amount;
         }
+        *self.funding_paid.entry(market_id.clone()).or_default() -= amount;
+        amount
+    }
+}
+
+/// Maximum number of metadata keys per account.
+pub const MAX_METADATA_KEYS: usize = 16;
+/// Maximum length in bytes of a metadata key or value.
+pub const MAX_METADATA_BYTES: usize = 256;
+
+/// Validate an `AccountMetadata` update against an account's current metadata.
+/// An empty `value` removes the key; a non-empty one inserts or overwrites it.
+pub fn check_metadata_update(
+    metadata: &BTreeMap<String, String>,
+    key: &str,
+    value: &str,
+) -> Result<(), String> {
+    if key.is_empty() {
+        return Err("Metadata key must not be empty".into());
+    }
+    if key.len() > MAX_METADATA_BYTES {
+        return Err(format!(
+            "Metadata key is {} bytes, limit is {MAX_METADATA_BYTES}",
+            key.len()
+        ));
+    }
+    if value.len() > MAX_METADATA_BYTES {
+        return Err(format!(
+            "Metadata value for {key} is {} bytes, limit is {MAX_METADATA_BYTES}",
+            value.len()
+        ));
+    }
+    if !value.is_empty() && !metadata.contains_key(key) && metadata.len() >= MAX_METADATA_KEYS {
+        return Err(format!(
+            "Account already has {MAX_METADATA_KEYS} metadata keys; cannot add {key}"
+        ));
+    }
+    Ok(())
+}
+
+/// An account group created by `GroupCreated`. Accounts join and leave it with
+/// `GroupMembershipSet`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AccountGroup {
+    /// Cap on the members' combined gross notional at mark (see
+    /// `margin::group_notional`). `None` means uncapped.
+    #[serde(default, with = "decimal_str::option")]
+    pub max_group_notional: Option<Decimal>,
+    /// Trade fee rate, as a fraction of notional, the members' fills are charged in
+    /// place of the turnover tiers; negative for a rebate.
+    #[serde(default, with = "decimal_str::option")]
+    pub fee_override: Option<Decimal>,
+    /// Accounts whose `group_id` names this group, so the cap check sums them
+    /// without scanning every account.
+    #[serde(default)]
+    pub members: BTreeSet<AccountId>,
+}
+
+/// Two markets whose opposite positions offset each other's margin, such as a
+/// perpetual and a dated future on the same underlying. Added by `HedgePairAdded`;
+/// see `margin::hedge_offset`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HedgePair {
+    pub market_a: MarketId,
+    pub market_b: MarketId,
+    /// Share of the margin on the overlapping notional that is waived, in `(0, 1]`.
+    #[serde(with = "decimal_str")]
+    pub offset_fraction: Decimal,
+}
+
+/// An order resting for an account, placed with `OrderPlaced`. The engine does not
+/// match it against anything; it only reserves the margin a fill of the whole order
+/// would need (`margin::order_reservation`) until `OrderCancelled` or
+/// `OrdersAutoCancelled` takes it off.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RestingOrder {
+    pub market_id: MarketId,
+    /// Signed like a fill: positive buys, negative sells.
+    #[serde(with = "decimal_str")]
+    pub quantity: Decimal,
+}
+
+/// When an account's margin call started: the sequence of the event that took it to
+/// or under maintenance margin, and the log clock then (0 before the first
+/// timestamp). `GracePeriod` counts from these.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MarginCallOpen {
+    pub breached_at: u64,
+    pub clock: u64,
+}
+
+/// A liquidity provider's commitment to take over liquidated positions in one market,
+/// registered by `BackstopRegistered`. Liquidations offer each close to a market's
+/// backstops in registration order before any keeper or the engine close.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Backstop {
+    pub account_id: AccountId,
+    pub market_id: MarketId,
+    /// Cap on the notional at mark of what the backstop has absorbed and still holds.
+    #[serde(with = "decimal_str")]
+    pub max_notional: Decimal,
+    /// Quantity, unsigned, taken over in the market and not yet released. Never more
+    /// than the backstop's position, so reducing the position releases capacity.
+    #[serde(with = "decimal_str")]
+    pub absorbed: Decimal,
+}
+
+impl Backstop {
+    /// Notional at `mark` the backstop may still absorb; zero once it is full.
+    pub fn remaining(&self, mark: Decimal) -> Decimal {
+        (self.max_notional - self.absorbed * mark.abs()).max(Decimal::ZERO)
     }
 }
 
+/// How a `FundingRate` event is converted into a cumulative funding index increment.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum FundingRateFormula {
+    /// Increment = rate * mark_price (rate is quoted as a fraction of notional per interval).
+    #[default]
+    RateTimesMark,
+    /// Increment = rate (rate is already quoted in quote currency per contract).
+    RateIsIndexDelta,
+}
+
+/// When funding reaches a market's holders' equity.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum FundingMode {
+    /// Funding moves equity when a `FundingUpdate` or `FundingRate` settles it.
+    /// Funding accrued in between is tracked but does not count.
+    #[default]
+    Discrete,
+    /// Funding moves equity as `FundingAccrual` accrues it. Settlement only moves the
+    /// accrued amount into the trading balance, so equity does not jump if it settles
+    /// at the last accrued index.
+    Continuous,
+}
+
+/// What a market trades. Both kinds share positions, margin and liquidation.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum InstrumentKind {
+    /// Never expires; settles funding.
+    #[default]
+    Perpetual,
+    /// Pays no funding, and settles every position at `Expiry`, which is refused
+    /// before `expiry_timestamp` (Unix ms) on the log clock.
+    Future { expiry_timestamp: u64 },
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Market {
     pub market_id: MarketId,
+    #[serde(with = "decimal_str")]
     pub mark_price: Decimal,
+    #[serde(with = "decimal_str")]
     pub initial_margin_fraction: Decimal,
+    #[serde(with = "decimal_str")]
     pub maintenance_margin_fraction: Decimal,
+    #[serde(with = "decimal_str")]
     pub cumulative_funding_index: Decimal,
+
+    #[serde(default)]
+    pub funding_rate_formula: FundingRateFormula,
+    #[serde(default)]
+    pub funding_mode: FundingMode,
+    /// The index the last `FundingAccrual` accrued to, until the next settlement.
+    /// `None` when nothing has accrued since.
+    #[serde(default, with = "decimal_str::option")]
+    pub accrued_funding_index: Option<Decimal>,
+
+    /// Notional above which a position pays the concentration add-on on top of IM.
+    #[serde(default, with = "decimal_str")]
+    pub concentration_threshold_notional: Decimal,
+    /// Extra IM fraction charged on the notional above the threshold. Zero disables it.
+    #[serde(default, with = "decimal_str")]
+    pub concentration_add_on_fraction: Decimal,
+
+    /// Cap on the market's gross open interest notional (sum of |qty| * mark across
+    /// all accounts). Non-reducing fills that would exceed it are rejected.
+    #[serde(default, with = "decimal_str::option")]
+    pub max_open_interest_notional: Option<Decimal>,
+
+    /// Venue-wide skew limit: the most net open interest notional (longs minus shorts,
+    /// at the absolute mark) the book may carry either way before `SkewLimitBreached`.
+    /// `None` (the default) tracks no skew.
+    #[serde(default, with = "decimal_str::option")]
+    pub skew_limit_notional: Option<Decimal>,
+    /// Set by `SkewLimitBreached`, cleared by `SkewLimitCleared`.
+    #[serde(default)]
+    pub skew_breached: bool,
+
+    /// Highest leverage an account may select for a position with
+    /// `SetPositionLeverage`. `None` (the default) offers no selection: every
+    /// position is margined at `initial_margin_fraction`.
+    #[serde(default, with = "decimal_str::option")]
+    pub max_leverage: Option<Decimal>,
+
+    /// Least initial margin a single position is charged, however small its notional.
+    /// `None` (the default) charges notional times the fraction alone.
+    #[serde(default, with = "decimal_str::option")]
+    pub min_initial_margin: Option<Decimal>,
+    /// Least maintenance margin a single position is charged. Never above
+    /// `min_initial_margin`.
+    #[serde(default, with = "decimal_str::option")]
+    pub min_maintenance_margin: Option<Decimal>,
+
+    /// Lot size. Every fill must be a whole number of steps, except one closing a
+    /// position already smaller than a step. `None` (the default) takes any quantity.
+    #[serde(default, with = "decimal_str::option")]
+    pub quantity_step: Option<Decimal>,
+    /// Least notional at mark a liquidation close may have. A position worth less is
+    /// closed whole in one fill, and a smaller partial close is rounded up to it (see
+    /// `liquidation_close`). `None` (the default) takes closes of any size.
+    #[serde(default, with = "decimal_str::option")]
+    pub min_liquidation_notional: Option<Decimal>,
+
+    /// Discount (fraction of mark) at which a keeper takes over a liquidated position.
+    /// The keeper's gain is credited as realized PnL at takeover.
+    #[serde(default, with = "decimal_str")]
+    pub liquidation_discount: Decimal,
+
+    /// Liquidation slippage: an engine close of notional N fills `N * this` basis
+    /// points worse than mark. Zero (the default) closes at mark. Keeper takeovers
+    /// use `liquidation_discount` instead.
+    #[serde(default, with = "decimal_str")]
+    pub slippage_bps_per_notional: Decimal,
+
+    /// Permit zero and negative mark/fill prices (e.g. commodity perps). When false,
+    /// prices must be strictly positive and anything else is rejected.
+    #[serde(default)]
+    pub allow_negative_prices: bool,
+
+    /// Sequence of the last applied mark (`MarkPriceUpdate` or `MarkPriceBatch`). `None` means the market has
+    /// never been priced, which is distinct from a legitimate mark of zero.
+    #[serde(default)]
+    pub last_mark_sequence: Option<u64>,
+    /// Log clock (Unix ms) when the last mark was applied. `None` until a mark
+    /// arrives after the first timestamped event.
+    #[serde(default)]
+    pub last_mark_timestamp: Option<u64>,
+
+    /// A mark older than this (by the log clock) makes the market stale. `None`
+    /// disables staleness tracking.
+    #[serde(default)]
+    pub staleness_threshold_ms: Option<u64>,
+    /// Multiplier on a stale market's initial margin (concentration add-on included),
+    /// at least 1.
+    #[serde(default = "Market::default_stale_im_multiplier", with = "decimal_str")]
+    pub stale_im_multiplier: Decimal,
+    /// Derived from the log clock and `last_mark_timestamp`; maintained by the engine.
+    #[serde(default)]
+    pub stale: bool,
+
+    /// Outside its trading session: set by `SessionClose`, cleared by `SessionOpen`.
+    /// Non-reducing fills are rejected; marks still apply.
+    #[serde(default)]
+    pub session_closed: bool,
+
+    /// Interval IDs already settled via `FundingRate`. Duplicates are rejected so a
+    /// retried rate feed cannot charge the same interval twice.
+    #[serde(default)]
+    pub settled_funding_intervals: BTreeSet<u64>,
+
+    #[serde(default)]
+    pub instrument: InstrumentKind,
+    /// Set by `Expiry`, after which the market holds no positions and takes no
+    /// trades, marks or imports.
+    #[serde(default)]
+    pub expired: bool,
 }
 
 impl Market {
@@ -58,6 +638,228 @@ impl Market {
             initial_margin_fraction,
             maintenance_margin_fraction,
             cumulative_funding_index: Decimal::ZERO,
+            funding_rate_formula: FundingRateFormula::default(),
+            funding_mode: FundingMode::default(),
+            accrued_funding_index: None,
+            concentration_threshold_notional: Decimal::ZERO,
+            concentration_add_on_fraction: Decimal::ZERO,
+            max_open_interest_notional: None,
+            skew_limit_notional: None,
+            skew_breached: false,
+            max_leverage: None,
+            min_initial_margin: None,
+            min_maintenance_margin: None,
+            quantity_step: None,
+            min_liquidation_notional: None,
+            liquidation_discount: Decimal::ZERO,
+            slippage_bps_per_notional: Decimal::ZERO,
+            allow_negative_prices: false,
+            last_mark_sequence: None,
+            last_mark_timestamp: None,
+            staleness_threshold_ms: None,
+            stale_im_multiplier: Self::default_stale_im_multiplier(),
+            stale: false,
+            session_closed: false,
+            settled_funding_intervals: BTreeSet::new(),
+            instrument: InstrumentKind::Perpetual,
+            expired: false,
+        }
+    }
+
+    /// A dated future, otherwise as `new`.
+    pub fn future(
+        market_id: MarketId,
+        initial_margin_fraction: Decimal,
+        maintenance_margin_fraction: Decimal,
+        expiry_timestamp: u64,
+    ) -> Self {
+        Self {
+            instrument: InstrumentKind::Future { expiry_timestamp },
+            ..Self::new(
+                market_id,
+                initial_margin_fraction,
+                maintenance_margin_fraction,
+            )
+        }
+    }
+
+    pub fn is_future(&self) -> bool {
+        matches!(self.instrument, InstrumentKind::Future { .. })
+    }
+
+    /// The index funding has reached: the last accrued one, else the last settled one.
+    /// The next accrual or settlement charges a holder without an index of its own
+    /// from here.
+    pub fn funding_index_reached(&self) -> Decimal {
+        self.accrued_funding_index
+            .unwrap_or(self.cumulative_funding_index)
+    }
+
+    /// Check the parameters for combinations that make margin meaningless: fractions
+    /// outside `0 < maintenance <= initial < 1`, a negative concentration setting,
+    /// open-interest cap, skew limit, margin floor, minimum liquidation notional,
+    /// discount or slippage, a `min_maintenance_margin` above `min_initial_margin`, a
+    /// `max_leverage` below 1 or above `1 / maintenance`, a `quantity_step` that is not
+    /// positive, or a `stale_im_multiplier` below 1. Mark, funding and session state
+    /// are not checked.
+    pub fn validate(&self) -> Result<(), MarketConfigError> {
+        let (im, mm) = (
+            self.initial_margin_fraction,
+            self.maintenance_margin_fraction,
+        );
+        if !(Decimal::ZERO < mm && mm <= im && im < Decimal::ONE) {
+            return Err(MarketConfigError::MarginFractions {
+                market_id: self.market_id.clone(),
+                initial: im,
+                maintenance: mm,
+            });
+        }
+        let non_negative = [
+            (
+                "concentration_threshold_notional",
+                Some(self.concentration_threshold_notional),
+            ),
+            (
+                "concentration_add_on_fraction",
+                Some(self.concentration_add_on_fraction),
+            ),
+            (
+                "max_open_interest_notional",
+                self.max_open_interest_notional,
+            ),
+            ("skew_limit_notional", self.skew_limit_notional),
+            ("min_initial_margin", self.min_initial_margin),
+            ("min_maintenance_margin", self.min_maintenance_margin),
+            ("min_liquidation_notional", self.min_liquidation_notional),
+            ("liquidation_discount", Some(self.liquidation_discount)),
+            (
+                "slippage_bps_per_notional",
+                Some(self.slippage_bps_per_notional),
+            ),
+        ];
+        for (field, value) in non_negative {
+            if let Some(value) = value.filter(|v| v.is_sign_negative() && !v.is_zero()) {
+                return Err(MarketConfigError::Negative {
+                    market_id: self.market_id.clone(),
+                    field,
+                    value,
+                });
+            }
+        }
+        if let Some(maintenance) = self.min_maintenance_margin {
+            if maintenance > self.min_initial_margin.unwrap_or(Decimal::ZERO) {
+                return Err(MarketConfigError::MarginFloors {
+                    market_id: self.market_id.clone(),
+                    initial: self.min_initial_margin,
+                    maintenance,
+                });
+            }
+        }
+        if let Some(max_leverage) = self.max_leverage {
+            if max_leverage < Decimal::ONE || max_leverage * mm > Decimal::ONE {
+                return Err(MarketConfigError::MaxLeverage {
+                    market_id: self.market_id.clone(),
+                    max_leverage,
+                    maintenance: mm,
+                });
+            }
+        }
+        if let Some(step) = self.quantity_step.filter(|step| *step <= Decimal::ZERO) {
+            return Err(MarketConfigError::QuantityStep {
+                market_id: self.market_id.clone(),
+                step,
+            });
+        }
+        if self.stale_im_multiplier < Decimal::ONE {
+            return Err(MarketConfigError::StaleImMultiplier {
+                market_id: self.market_id.clone(),
+                multiplier: self.stale_im_multiplier,
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether a fill of `quantity` against a position of `current` keeps to
+    /// `quantity_step`: a whole number of steps, or the close of a position already
+    /// below one step or that `min_liquidation_notional` closes whole.
+    pub fn on_step(&self, current: Decimal, quantity: Decimal) -> bool {
+        match self.quantity_step.filter(|step| *step > Decimal::ZERO) {
+            Some(step) => {
+                let closes_dust = (current + quantity).is_zero()
+                    && (current.abs() < step || self.liquidation_close(current) == quantity);
+                (quantity % step).is_zero() || closes_dust
+            }
+            None => true,
+        }
+    }
+
+    /// The close the liquidation engine plans for a position of `quantity`: its
+    /// `step_close`, unless that is worth less than `min_liquidation_notional` at the
+    /// absolute mark. Then it is rounded up to the threshold, in whole steps, or to the
+    /// whole position when that is no more. A position worth less than the threshold
+    /// is therefore closed whole, in one fill.
+    pub fn liquidation_close(&self, quantity: Decimal) -> Decimal {
+        let close = self.step_close(quantity);
+        match self.min_close_quantity(close) {
+            Some(least) if least < quantity.abs() => {
+                if quantity.is_sign_negative() {
+                    least
+                } else {
+                    -least
+                }
+            }
+            Some(_) => -quantity,
+            None => close,
+        }
+    }
+
+    /// The unsigned quantity a close of `close` must be rounded up to under
+    /// `min_liquidation_notional`: the threshold at the absolute mark, in whole
+    /// `quantity_step`s where the market has one. `None` without a threshold, or when
+    /// the close is worth at least that already. At a zero mark, or where the quantity
+    /// overflows, it is `Decimal::MAX`, which only a whole close satisfies.
+    pub fn min_close_quantity(&self, close: Decimal) -> Option<Decimal> {
+        let threshold = self.min_liquidation_notional?;
+        let mark = self.mark_price.abs();
+        if close
+            .abs()
+            .checked_mul(mark)
+            .is_none_or(|notional| notional >= threshold)
+        {
+            return None;
+        }
+        let least = threshold.checked_div(mark);
+        Some(
+            match self.quantity_step.filter(|step| *step > Decimal::ZERO) {
+                Some(step) => least
+                    .and_then(|least| least.checked_div(step))
+                    .and_then(|steps| steps.ceil().checked_mul(step)),
+                None => least,
+            }
+            .unwrap_or(Decimal::MAX),
+        )
+    }
+
+    /// The fill that closes a position of `quantity` as far as whole steps go: all of
+    /// it without a step, or the part a whole number of steps covers, rounded toward
+    /// zero so it never flips the position. A position below one step is closed whole,
+    /// as dust. Whatever rounding leaves over is the next close.
+    pub fn step_close(&self, quantity: Decimal) -> Decimal {
+        match self.quantity_step.filter(|step| *step > Decimal::ZERO) {
+            Some(step) if quantity.abs() >= step => -(quantity - quantity % step).normalize(),
+            _ => -quantity,
+        }
+    }
+
+    fn default_stale_im_multiplier() -> Decimal {
+        Decimal::ONE
+    }
+
+    /// Whether the mark is older than the staleness threshold at log time `now`.
+    pub fn is_stale_at(&self, now: u64) -> bool {
+        match (self.staleness_threshold_ms, self.last_mark_timestamp) {
+            (Some(threshold), Some(marked_at)) => now.saturating_sub(marked_at) > threshold,
+            _ => false,
         }
     }
 }
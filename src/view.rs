@@ -0,0 +1,302 @@
+use arc_swap::ArcSwap;
+use im::OrdMap;
+use rust_decimal::Decimal;
+
+use crate::snapshot::{self, AccountSnapshot, MarketSnapshot, Snapshot};
+use crate::state::State;
+use crate::types::{AccountId, MarketId, OrderId, PoolId, RestingOrder};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+/// An immutable copy of the figures readers ask for, as of one recorded event: every
+/// account as `snapshot::capture_account` sees it, the markets and the insurance
+/// funds. The accounts are a persistent map, so consecutive views share every account
+/// that did not change and most of the tree that holds them, and publishing one costs
+/// the changed accounts, not the book.
+#[derive(Debug, Clone, Default)]
+pub struct StateView {
+    after_sequence: u64,
+    accounts: OrdMap<AccountId, Arc<AccountSnapshot>>,
+    markets: Arc<BTreeMap<MarketId, MarketSnapshot>>,
+    insurance_funds: Arc<BTreeMap<PoolId, Decimal>>,
+    /// Accounts in liquidation, deferred, or with markets liquidated in the current
+    /// cascade. The next event can clear these flags without naming the account.
+    flagged: Arc<BTreeSet<AccountId>>,
+}
+
+/// The accounts an event may have changed, as the engine hands them to
+/// `StateView::updated`.
+#[derive(Debug)]
+pub(crate) enum Touched {
+    /// Every account, for an event that can move any of them (an interest tick, a
+    /// new hedge pair) and for the first view.
+    All,
+    /// The accounts the event names and any others it changed without naming them,
+    /// and the markets whose holders it may have changed: a funding event moves them
+    /// even where it leaves the market's snapshot as it was. The holders of a market
+    /// whose snapshot changed and the accounts whose liquidation flags did are added
+    /// by the view.
+    Some {
+        accounts: BTreeSet<AccountId>,
+        markets: Vec<MarketId>,
+    },
+}
+
+impl StateView {
+    /// A view of `snapshot`, sharing nothing.
+    pub fn of(snapshot: &Snapshot) -> StateView {
+        StateView {
+            after_sequence: snapshot.after_sequence,
+            accounts: snapshot
+                .accounts
+                .iter()
+                .map(|(account_id, account)| (account_id.clone(), Arc::new(account.clone())))
+                .collect(),
+            markets: Arc::new(snapshot.markets.clone()),
+            insurance_funds: Arc::new(snapshot.insurance_funds.clone()),
+            flagged: Arc::new(
+                snapshot
+                    .accounts
+                    .iter()
+                    .filter(|(_, account)| {
+                        account.in_liquidation
+                            || account.liquidation_deferred
+                            || !account.liquidated_markets.is_empty()
+                    })
+                    .map(|(account_id, _)| account_id.clone())
+                    .collect(),
+            ),
+        }
+    }
+
+    /// The sequence of the event this view was taken after (0 before any).
+    pub fn after_sequence(&self) -> u64 {
+        self.after_sequence
+    }
+
+    pub fn account(&self, account_id: &str) -> Option<&AccountSnapshot> {
+        self.accounts.get(account_id).map(|account| &**account)
+    }
+
+    /// Every account, in account ID order.
+    pub fn accounts(&self) -> impl Iterator<Item = (&AccountId, &AccountSnapshot)> {
+        self.accounts
+            .iter()
+            .map(|(account_id, account)| (account_id, &**account))
+    }
+
+    /// The number of accounts.
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    pub fn markets(&self) -> &BTreeMap<MarketId, MarketSnapshot> {
+        &self.markets
+    }
+
+    pub fn insurance_funds(&self) -> &BTreeMap<PoolId, Decimal> {
+        &self.insurance_funds
+    }
+
+    /// Whether `self` and `other` hold the same account, rather than equal copies of it.
+    pub fn shares_account(&self, other: &StateView, account_id: &str) -> bool {
+        match (
+            self.accounts.get(account_id),
+            other.accounts.get(account_id),
+        ) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    /// The view of `state` after `after_sequence`, and the accounts whose snapshot it
+    /// changed, added or removed. Only the `touched` accounts, the holders of every
+    /// market whose snapshot changed and the accounts whose liquidation flags changed
+    /// are captured again; every other account is shared with `self`, as is an
+    /// account captured again unchanged.
+    pub(crate) fn updated(
+        &self,
+        state: &State,
+        after_sequence: u64,
+        touched: Touched,
+    ) -> (StateView, BTreeSet<AccountId>) {
+        let markets = snapshot::capture_markets(state);
+        let flagged: BTreeSet<AccountId> = state
+            .in_liquidation
+            .iter()
+            .chain(&state.deferred_liquidations)
+            .chain(state.liquidated_markets.keys())
+            .cloned()
+            .collect();
+        let mut accounts = self.accounts.clone();
+        let mut changed = BTreeSet::new();
+        let recapture = match touched {
+            Touched::All => {
+                for account_id in self.accounts.keys() {
+                    if !state.accounts.contains_key(account_id) {
+                        accounts.remove(account_id);
+                        changed.insert(account_id.clone());
+                    }
+                }
+                state.accounts.keys().cloned().collect()
+            }
+            Touched::Some {
+                accounts: mut account_ids,
+                markets: funded,
+            } => {
+                // Markets added, changed or removed.
+                let added_or_changed = markets
+                    .iter()
+                    .filter(|(market_id, market)| self.markets.get(*market_id) != Some(*market));
+                let removed = self
+                    .markets
+                    .iter()
+                    .filter(|(market_id, _)| !markets.contains_key(*market_id));
+                let moved: Vec<&MarketId> = added_or_changed
+                    .chain(removed)
+                    .map(|(market_id, _)| market_id)
+                    .chain(&funded)
+                    .collect();
+                if !moved.is_empty() {
+                    // Holders as of the last view too: an event can drop an account's
+                    // funding in a market it no longer holds.
+                    let before = self.accounts.iter().filter(|(_, account)| {
+                        moved.iter().any(|market_id| {
+                            reads_market(
+                                market_id,
+                                &account.positions,
+                                &account.pending_funding,
+                                &account.last_funding,
+                                &account.orders,
+                            )
+                        })
+                    });
+                    let after = state.accounts.iter().filter(|(_, account)| {
+                        moved.iter().any(|market_id| {
+                            reads_market(
+                                market_id,
+                                &account.positions,
+                                &account.pending_funding,
+                                &account.last_funding,
+                                &account.orders,
+                            )
+                        })
+                    });
+                    account_ids.extend(before.map(|(account_id, _)| account_id.clone()));
+                    account_ids.extend(after.map(|(account_id, _)| account_id.clone()));
+                }
+                account_ids.extend(self.flagged.iter().cloned());
+                account_ids.extend(flagged.iter().cloned());
+                account_ids
+            }
+        };
+        for account_id in recapture {
+            let Some(account) = state.accounts.get(&account_id) else {
+                if accounts.remove(&account_id).is_some() {
+                    changed.insert(account_id);
+                }
+                continue;
+            };
+            let captured = snapshot::capture_account(account, state);
+            if accounts
+                .get(&account_id)
+                .is_none_or(|old| **old != captured)
+            {
+                accounts.insert(account_id.clone(), Arc::new(captured));
+                changed.insert(account_id);
+            }
+        }
+
+        let markets = if *self.markets == markets {
+            self.markets.clone()
+        } else {
+            Arc::new(markets)
+        };
+        let insurance_funds = if *self.insurance_funds == state.insurance_funds {
+            self.insurance_funds.clone()
+        } else {
+            Arc::new(state.insurance_funds.clone())
+        };
+        let view = StateView {
+            after_sequence,
+            accounts,
+            markets,
+            insurance_funds,
+            flagged: Arc::new(flagged),
+        };
+        (view, changed)
+    }
+
+    /// Every difference between the view and `state`: an account whose figures differ
+    /// from `snapshot::capture_account`, one missing from either side, or a market or
+    /// fund that differs. Empty when the view is `state` exactly.
+    pub fn differences(&self, state: &State) -> Vec<String> {
+        let mut differences = Vec::new();
+        let mut accounts = self.accounts();
+        let mut expected = state.accounts.iter();
+        loop {
+            match (accounts.next(), expected.next()) {
+                (None, None) => break,
+                (Some((account_id, account)), Some((expected_id, expected))) => {
+                    if account_id != expected_id {
+                        differences.push(format!(
+                            "account {account_id} where {expected_id} is expected"
+                        ));
+                    } else if *account != snapshot::capture_account(expected, state) {
+                        differences.push(format!("account {account_id} differs"));
+                    }
+                }
+                (Some((account_id, _)), None) => {
+                    differences.push(format!("extra account {account_id}"))
+                }
+                (None, Some((account_id, _))) => {
+                    differences.push(format!("missing account {account_id}"))
+                }
+            }
+        }
+        if *self.markets != snapshot::capture_markets(state) {
+            differences.push("markets differ".to_string());
+        }
+        if *self.insurance_funds != state.insurance_funds {
+            differences.push("insurance funds differ".to_string());
+        }
+        differences
+    }
+}
+
+/// Whether the snapshot of an account with these positions, funding and resting
+/// orders reads `market_id`'s.
+fn reads_market<P>(
+    market_id: &MarketId,
+    positions: &BTreeMap<MarketId, P>,
+    pending_funding: &BTreeMap<MarketId, Decimal>,
+    last_funding: &BTreeMap<MarketId, Decimal>,
+    orders: &BTreeMap<OrderId, RestingOrder>,
+) -> bool {
+    positions.contains_key(market_id)
+        || pending_funding.contains_key(market_id)
+        || last_funding.contains_key(market_id)
+        || orders.values().any(|order| order.market_id == *market_id)
+}
+
+/// A handle on an engine's latest `StateView`, from `Engine::views`. Clones share the
+/// handle and can move to other threads. The engine builds each view before it swaps
+/// it in with one atomic store, so a reader never waits on the engine's work, or on
+/// a lock, and keeps its view for as long as it likes.
+#[derive(Debug, Clone, Default)]
+pub struct StateViews(Arc<ArcSwap<StateView>>);
+
+impl StateViews {
+    /// The latest view.
+    pub fn load(&self) -> Arc<StateView> {
+        self.0.load_full()
+    }
+
+    pub(crate) fn publish(&self, view: Arc<StateView>) {
+        self.0.store(view);
+    }
+}
@@ -0,0 +1,111 @@
+// Account metadata caps: up to `MAX_METADATA_KEYS` keys an account, keys and values of
+// up to `MAX_METADATA_BYTES` bytes. An update exactly at a limit is accepted and one
+// past it rejected, with an `AccountMetadataRejected` record and the metadata left as
+// it was. Overwriting or removing a key that is already set does not count against
+// the key limit. Replay reproduces the metadata and every decision.
+
+mod common;
+
+use common::id;
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::types::{MAX_METADATA_BYTES, MAX_METADATA_KEYS};
+use std::collections::BTreeMap;
+
+fn set(engine: &mut Engine, key: &str, value: &str) -> ProcessOutcome {
+    engine.process(EventType::AccountMetadata {
+        account_id: id("alice"),
+        key: key.into(),
+        value: value.into(),
+    })
+}
+
+/// The reason an `AccountMetadata` update was refused, after checking it was logged
+/// as an `AccountMetadataRejected`.
+fn refused(engine: &mut Engine, key: &str, value: &str) -> String {
+    let ProcessOutcome::Rejected {
+        reason: RejectReason::AccountMetadata(reason),
+        ..
+    } = set(engine, key, value)
+    else {
+        panic!("{key} = {value} was accepted");
+    };
+    assert!(matches!(
+        &engine.event_log.last().unwrap().event_type,
+        EventType::AccountMetadataRejected { reason: logged, .. } if *logged == reason
+    ));
+    reason
+}
+
+fn metadata(engine: &Engine) -> &BTreeMap<String, String> {
+    &engine.state.accounts["alice"].metadata
+}
+
+/// alice with every key she is allowed, `k0` to `k15`.
+fn full() -> Engine {
+    let mut engine = Engine::new();
+    for i in 0..MAX_METADATA_KEYS {
+        assert!(set(&mut engine, &format!("k{i}"), "desk").is_accepted());
+    }
+    assert_eq!(metadata(&engine).len(), MAX_METADATA_KEYS);
+    engine
+}
+
+#[test]
+fn seventeenth_key_is_rejected() {
+    let mut engine = full();
+    let before = metadata(&engine).clone();
+    assert_eq!(
+        refused(&mut engine, "k16", "desk"),
+        "Account already has 16 metadata keys; cannot add k16"
+    );
+    assert_eq!(metadata(&engine), &before);
+
+    // Removing a key makes room for another.
+    assert!(set(&mut engine, "k0", "").is_accepted());
+    assert!(set(&mut engine, "k16", "desk").is_accepted());
+    assert_eq!(metadata(&engine).len(), MAX_METADATA_KEYS);
+}
+
+#[test]
+fn key_and_value_sizes_are_capped() {
+    let mut engine = Engine::new();
+    let at_limit = "x".repeat(MAX_METADATA_BYTES);
+    let over = "x".repeat(MAX_METADATA_BYTES + 1);
+
+    assert!(set(&mut engine, "desk", &at_limit).is_accepted());
+    assert!(set(&mut engine, &at_limit, "rates").is_accepted());
+    assert_eq!(metadata(&engine)["desk"].len(), MAX_METADATA_BYTES);
+
+    assert_eq!(
+        refused(&mut engine, "desk", &over),
+        "Metadata value for desk is 257 bytes, limit is 256"
+    );
+    assert_eq!(metadata(&engine)["desk"], at_limit);
+    assert_eq!(
+        refused(&mut engine, &over, "rates"),
+        "Metadata key is 257 bytes, limit is 256"
+    );
+    assert!(!metadata(&engine).contains_key(&over));
+
+    // Bytes, not characters: 128 two-byte characters fill the limit exactly.
+    assert!(set(&mut engine, "desk", &"é".repeat(128)).is_accepted());
+    refused(&mut engine, "desk", &"é".repeat(129));
+}
+
+#[test]
+fn overwriting_does_not_count_against_the_key_limit() {
+    let mut engine = full();
+    assert!(set(&mut engine, "k3", "credit").is_accepted());
+    assert_eq!(metadata(&engine)["k3"], "credit");
+    assert_eq!(metadata(&engine).len(), MAX_METADATA_KEYS);
+    refused(&mut engine, "k16", "desk");
+
+    let replayed =
+        Engine::replay_verified(&engine.event_log, vec![], EngineConfig::default()).unwrap();
+    assert_eq!(replayed.state, engine.state);
+    assert_eq!(replayed.snapshots, engine.snapshots);
+    assert_eq!(
+        engine.snapshots.last().unwrap().accounts[&id("alice")].metadata,
+        *metadata(&engine)
+    );
+}
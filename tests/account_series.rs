@@ -0,0 +1,92 @@
+// `snapshot::series` over the demo's snapshots: alice's equity curve runs down with
+// the BTC mark from 100,000 to the 10,000 left at her liquidation fill, snapshots
+// that leave her out repeat her last point, and the CSV and JSON exports carry the
+// same values.
+
+mod common;
+
+use common::{btc, id};
+use cross_margin_engine::demo;
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::snapshot::{self, Field, TimeSeries};
+use rust_decimal_macros::dec;
+
+#[test]
+fn alice_equity_at_liquidation() {
+    let engine = demo::engine();
+    let liquidation = engine
+        .event_log
+        .iter()
+        .find(|e| {
+            matches!(&e.event_type, EventType::LiquidationFill { account_id, .. } if *account_id == id("alice"))
+        })
+        .expect("the demo liquidates alice")
+        .sequence;
+    let fields = [Field::Equity, Field::PositionQty(btc()), Field::Mark(btc())];
+    let series = snapshot::series(&engine.snapshots, &id("alice"), &fields);
+
+    assert_eq!(
+        series.value_at(&Field::Equity, liquidation),
+        Some(dec!(10000))
+    );
+    assert_eq!(
+        series.value_at(&Field::PositionQty(btc()), liquidation),
+        Some(dec!(0))
+    );
+    assert_eq!(
+        series.value_at(&Field::Mark(btc()), liquidation),
+        Some(dec!(41000))
+    );
+    let equity: Vec<_> = series
+        .pairs(&Field::Equity)
+        .into_iter()
+        .filter(|(sequence, _)| *sequence <= liquidation)
+        .map(|(_, value)| value)
+        .collect();
+    assert_eq!(
+        equity,
+        [
+            dec!(100000),
+            dec!(100000),
+            dec!(100000),
+            dec!(20000),
+            dec!(10000),
+            dec!(10000)
+        ]
+    );
+}
+
+#[test]
+fn absent_sequences_repeat_the_last_point() {
+    let engine = demo::engine();
+    let full = snapshot::series(&engine.snapshots, &id("alice"), &[Field::Equity]);
+    // One point per snapshot from alice's deposit on.
+    let first = engine
+        .snapshots
+        .iter()
+        .position(|s| s.accounts.contains_key(&id("alice")))
+        .unwrap();
+    assert_eq!(full.points.len(), engine.snapshots.len() - first);
+
+    // Nothing changes alice after her liquidation, so snapshots that leave her out
+    // from there on give the same series.
+    let liquidated = full
+        .points
+        .iter()
+        .position(|p| p.values == [Some(dec!(10000))])
+        .unwrap();
+    let mut delta = engine.snapshots.clone();
+    for snapshot in &mut delta[first + liquidated + 2..] {
+        snapshot.accounts.remove(&id("alice"));
+    }
+    assert_eq!(
+        snapshot::series(&delta, &id("alice"), &[Field::Equity]),
+        full
+    );
+
+    let csv = full.to_csv();
+    assert_eq!(csv.lines().next(), Some("sequence,equity"));
+    assert_eq!(csv.lines().count(), full.points.len() + 1);
+    let parsed: TimeSeries = serde_json::from_str(&full.to_json()).unwrap();
+    assert_eq!(parsed, full);
+}
@@ -0,0 +1,159 @@
+// PnL attribution over fixed windows of the demo log, worked out by hand. alice's
+// 10 BTC long loses 80,000 to the drop to 42,000 and 10,000 more to 41,000, where it
+// is liquidated at mark; bob's 20 ETH long pays 30 of funding on the index going to
+// 1.50. Every fill of the demo is at mark, so trading and liquidation are zero, and
+// the default engine charges no fees; under a 5 bp fee the same fills cost alice 250
+// and bob 30. The replayed log and snapshots give the same reports as the live ones.
+
+mod common;
+
+use common::engine_with;
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::{demo, report};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+/// What a window's report must say, component by component.
+struct Expected {
+    price_moves: Decimal,
+    funding: Decimal,
+    trading: Decimal,
+    liquidation: Decimal,
+    fees: Decimal,
+    transfers: Decimal,
+    equity_change: Decimal,
+}
+
+fn attribution(engine: &Engine, account: &str, from: u64, to: u64) -> report::AttributionReport {
+    report::attribution(
+        &engine.event_log,
+        &engine.snapshots,
+        &account.parse().unwrap(),
+        from,
+        to,
+    )
+}
+
+fn assert_window(engine: &Engine, account: &str, (from, to): (u64, u64), expected: Expected) {
+    let report = attribution(engine, account, from, to);
+    let window = format!("{account} ({from}, {to}]: {report:?}");
+    assert_eq!(
+        (report.from_sequence, report.to_sequence),
+        (from, to),
+        "{window}"
+    );
+    assert_eq!(report.price_moves, expected.price_moves, "{window}");
+    assert_eq!(report.funding, expected.funding, "{window}");
+    assert_eq!(report.trading, expected.trading, "{window}");
+    assert_eq!(report.liquidation, expected.liquidation, "{window}");
+    assert_eq!(report.fees, expected.fees, "{window}");
+    assert_eq!(report.transfers, expected.transfers, "{window}");
+    assert_eq!(report.interest, Decimal::ZERO, "{window}");
+    assert_eq!(report.collateral_yield, Decimal::ZERO, "{window}");
+    assert_eq!(report.equity_change, expected.equity_change, "{window}");
+    assert_eq!(report.residual, Decimal::ZERO, "{window}");
+    assert!(report.reconciled, "{window}");
+}
+
+/// Only price moves and transfers; the rest zero.
+fn moves(price_moves: Decimal, transfers: Decimal) -> Expected {
+    Expected {
+        price_moves,
+        funding: dec!(0),
+        trading: dec!(0),
+        liquidation: dec!(0),
+        fees: dec!(0),
+        transfers,
+        equity_change: price_moves + transfers,
+    }
+}
+
+// Demo sequences: 2 alice deposits 100,000; 4 alice longs 10 BTC at 50,000; 5 and 6
+// mark BTC at 42,000 and 41,000; 7 liquidates her; 8 bob deposits 10,000; 10 bob longs
+// 20 ETH at 3,000; 11-12 his rejected second fill; 19-20 ETH funding and his payment.
+
+#[test]
+fn alice_over_scenario_one() {
+    let engine = demo::engine();
+    // The long at mark changes nothing; 10 × −8,000 on the first drop.
+    assert_window(&engine, "alice", (3, 4), moves(dec!(0), dec!(0)));
+    assert_window(&engine, "alice", (4, 5), moves(dec!(-80000), dec!(0)));
+    // 10 × −1,000 more, then the close at mark.
+    assert_window(&engine, "alice", (5, 7), moves(dec!(-10000), dec!(0)));
+    assert_window(&engine, "alice", (0, 7), moves(dec!(-90000), dec!(100000)));
+    // Flat from the liquidation on: nothing moves her again.
+    assert_window(&engine, "alice", (7, 21), moves(dec!(0), dec!(0)));
+}
+
+#[test]
+fn bob_over_scenarios_two_and_four() {
+    let engine = demo::engine();
+    assert_window(&engine, "bob", (7, 10), moves(dec!(0), dec!(10000)));
+    // ETH holds at 3,000; the index goes from 0 to 1.50 on 20 ETH.
+    let funded = Expected {
+        funding: dec!(-30),
+        equity_change: dec!(-30),
+        ..moves(dec!(0), dec!(0))
+    };
+    assert_window(&engine, "bob", (18, 20), funded);
+    let whole = Expected {
+        funding: dec!(-30),
+        equity_change: dec!(9970),
+        ..moves(dec!(0), dec!(10000))
+    };
+    assert_window(&engine, "bob", (0, 21), whole);
+}
+
+#[test]
+fn fees_are_attributed_under_a_fee_tier() {
+    let config = EngineConfig {
+        trade_stats: Some(TradeStatistics {
+            window: StatsWindow::Fills(10),
+            fee_tiers: vec![FeeTier {
+                min_turnover: dec!(0),
+                rate: dec!(0.0005),
+            }],
+        }),
+        ..EngineConfig::default()
+    };
+    let mut engine = engine_with(config, demo::markets());
+    for event_type in demo::events() {
+        engine.process(event_type);
+    }
+
+    // 5 bp of alice's 500,000 fill; her liquidation close is not a trade and is free.
+    let charged = Expected {
+        fees: dec!(-250),
+        equity_change: dec!(9750),
+        ..moves(dec!(-90000), dec!(100000))
+    };
+    let last = engine.event_log.last().unwrap().sequence;
+    assert_window(&engine, "alice", (0, last), charged);
+    // 5 bp of bob's 60,000 fill, and the funding as before.
+    let charged = Expected {
+        funding: dec!(-30),
+        fees: dec!(-30),
+        equity_change: dec!(9940),
+        ..moves(dec!(0), dec!(10000))
+    };
+    assert_window(&engine, "bob", (0, last), charged);
+}
+
+#[test]
+fn replayed_artifacts_give_the_live_reports() {
+    let engine = demo::engine();
+    let (_, snapshots) = Engine::replay(&engine.event_log, demo::markets());
+    let last = engine.event_log.last().unwrap().sequence;
+    for account in ["alice", "bob"] {
+        for (from, to) in [(0, 7), (4, 5), (7, last), (18, 20), (0, last)] {
+            let replayed = report::attribution(
+                &engine.event_log,
+                &snapshots,
+                &account.parse().unwrap(),
+                from,
+                to,
+            );
+            assert_eq!(replayed, attribution(&engine, account, from, to));
+        }
+    }
+}
@@ -0,0 +1,74 @@
+// A funding update inside a `process_batch` settles each position as the earlier
+// events of the batch left it. alice's fills and two updates share one batch: the
+// first update charges the position her first fill grew, the second the one her
+// second fill flipped, and replay of the batch settles exactly the same amounts.
+
+mod common;
+
+use common::{btc, btc_market, deposit, engine_with, mark, process, trade};
+use cross_margin_engine::prelude::*;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+fn funding(index: Decimal) -> EventType {
+    EventType::FundingUpdate {
+        market_id: btc(),
+        new_cumulative_index: index,
+    }
+}
+
+/// The `FundingPayment` amounts logged for `account`, in log order.
+fn payments(engine: &Engine, account: &str) -> Vec<Decimal> {
+    engine
+        .event_log
+        .iter()
+        .filter_map(|e| match &e.event_type {
+            EventType::FundingPayment {
+                account_id, amount, ..
+            } if account_id.as_str() == account => Some(*amount),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn funding_settles_the_position_earlier_fills_of_the_batch_left() {
+    let mut engine = engine_with(EngineConfig::default(), vec![btc_market()]);
+    for event_type in [
+        mark("BTC-PERP", dec!(50000)),
+        deposit("alice", dec!(100000)),
+        deposit("bob", dec!(100000)),
+        trade("alice", "BTC-PERP", dec!(1), dec!(50000)),
+        trade("bob", "BTC-PERP", dec!(-1), dec!(50000)),
+    ] {
+        process(&mut engine, event_type);
+    }
+
+    let outcomes = engine.process_batch(
+        [
+            // Long 3 when the index moves to 10, short 1 when it moves on to 25.
+            trade("alice", "BTC-PERP", dec!(2), dec!(50000)),
+            funding(dec!(10)),
+            trade("alice", "BTC-PERP", dec!(-4), dec!(50000)),
+            funding(dec!(25)),
+        ]
+        .map(|event_type| (event_type, Submission::default())),
+    );
+    assert!(
+        outcomes.iter().all(ProcessOutcome::is_accepted),
+        "{outcomes:?}"
+    );
+    assert_eq!(payments(&engine, "alice"), [dec!(-30), dec!(15)]);
+    assert_eq!(payments(&engine, "bob"), [dec!(10), dec!(15)]);
+    let alice = &engine.state.accounts["alice"];
+    assert_eq!(alice.positions["BTC-PERP"].quantity, dec!(-1));
+    assert_eq!(alice.last_funding["BTC-PERP"], dec!(25));
+
+    let replayed = Engine::replay_verified(
+        &engine.event_log,
+        vec![btc_market()],
+        EngineConfig::default(),
+    )
+    .unwrap();
+    assert_eq!(replayed.state, engine.state);
+}
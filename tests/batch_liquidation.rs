@@ -0,0 +1,212 @@
+// Inside a `process_batch`, the liquidation scan waits for the end of the batch. A
+// mark early in the batch that leaves alice under maintenance margin is followed by
+// her own fill: it must not add risk to an account the end scan is about to close,
+// and replay of the batch, between its markers, must refuse it the same way.
+
+mod common;
+
+use common::{btc, btc_market, deposit, engine_with, id, mark, process, trade};
+use cross_margin_engine::events;
+use cross_margin_engine::margin;
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::regenerate::diff_logs;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+/// alice long 3 BTC at 50,000 on 10,000; bob with room to trade.
+fn engine() -> Engine {
+    let mut engine = engine_with(EngineConfig::default(), vec![btc_market()]);
+    for event_type in [
+        mark("BTC-PERP", dec!(50000)),
+        deposit("alice", dec!(10000)),
+        deposit("bob", dec!(100000)),
+        trade("alice", "BTC-PERP", dec!(3), dec!(50000)),
+    ] {
+        process(&mut engine, event_type);
+    }
+    engine
+}
+
+fn batch(engine: &mut Engine, events: Vec<EventType>) -> Vec<ProcessOutcome> {
+    engine.process_batch(
+        events
+            .into_iter()
+            .map(|event_type| (event_type, Submission::default())),
+    )
+}
+
+fn in_liquidation(outcome: &ProcessOutcome) -> bool {
+    matches!(
+        outcome,
+        ProcessOutcome::Rejected {
+            reason: RejectReason::AccountInLiquidation(_),
+            ..
+        }
+    )
+}
+
+fn quantity(engine: &Engine, account: &str) -> Decimal {
+    engine.state.accounts[account]
+        .positions
+        .get("BTC-PERP")
+        .map_or(Decimal::ZERO, |p| p.quantity)
+}
+
+#[test]
+fn fill_after_the_mark_that_breached_is_rejected() {
+    let mut engine = engine();
+    let started = engine.next_sequence();
+    let outcomes = batch(
+        &mut engine,
+        vec![
+            // Equity 1,000 against MM 4,230.
+            mark("BTC-PERP", dec!(47000)),
+            trade("alice", "BTC-PERP", dec!(1), dec!(47000)),
+            EventType::Withdraw {
+                account_id: id("alice"),
+                amount: dec!(1),
+            },
+            trade("alice", "BTC-PERP", dec!(-1), dec!(47000)),
+            trade("bob", "BTC-PERP", dec!(1), dec!(47000)),
+        ],
+    );
+    assert_eq!(outcomes.len(), 5);
+    assert!(outcomes[0].is_accepted());
+    assert!(in_liquidation(&outcomes[1]), "{:?}", outcomes[1]);
+    assert!(in_liquidation(&outcomes[2]), "{:?}", outcomes[2]);
+    // Reducing still passes, and other accounts trade as usual.
+    assert!(outcomes[3].is_accepted());
+    assert!(outcomes[4].is_accepted());
+
+    // The markers bracket the batch, and the end scan closes what alice has left,
+    // caused by the `BatchEnded`.
+    let log: Vec<&Event> = engine
+        .event_log
+        .iter()
+        .map(AsRef::as_ref)
+        .filter(|e| e.sequence >= started)
+        .collect();
+    assert_eq!(
+        log[0].event_type,
+        EventType::BatchStarted { submissions: 5 }
+    );
+    let end = log
+        .iter()
+        .find(|e| e.event_type == EventType::BatchEnded { submissions: 5 })
+        .unwrap();
+    let liquidations: Vec<&EventType> = log
+        .iter()
+        .filter(|e| matches!(e.event_type, EventType::LiquidationFill { .. }))
+        .map(|e| {
+            assert_eq!(e.caused_by, Some(end.sequence));
+            &e.event_type
+        })
+        .collect();
+    assert_eq!(
+        liquidations,
+        [&EventType::LiquidationFill {
+            account_id: id("alice"),
+            market_id: btc(),
+            quantity: dec!(-2),
+            price: dec!(47000),
+        }]
+    );
+    assert_eq!(quantity(&engine, "alice"), Decimal::ZERO);
+    assert_eq!(engine.state.accounts["alice"].collateral(), dec!(1000));
+    assert_eq!(quantity(&engine, "bob"), dec!(1));
+    assert!(engine.solvency().is_balanced());
+}
+
+#[test]
+fn an_account_brought_back_before_its_fill_trades() {
+    let mut engine = engine();
+    let outcomes = batch(
+        &mut engine,
+        vec![
+            mark("BTC-PERP", dec!(47000)),
+            deposit("alice", dec!(20000)),
+            trade("alice", "BTC-PERP", dec!(1), dec!(47000)),
+        ],
+    );
+    assert!(outcomes.iter().all(ProcessOutcome::is_accepted));
+    assert_eq!(quantity(&engine, "alice"), dec!(4));
+    assert!(!margin::is_liquidatable(
+        &engine.state.accounts["alice"],
+        &engine.state
+    ));
+}
+
+#[test]
+fn replay_and_regeneration_reject_the_same_fills() {
+    let mut engine = engine();
+    batch(
+        &mut engine,
+        vec![
+            mark("BTC-PERP", dec!(47000)),
+            trade("alice", "BTC-PERP", dec!(1), dec!(47000)),
+            trade("alice", "BTC-PERP", dec!(-1), dec!(47000)),
+            trade("bob", "BTC-PERP", dec!(1), dec!(47000)),
+        ],
+    );
+    assert!(engine.process(mark("BTC-PERP", dec!(48000))).is_accepted());
+
+    let replayed = Engine::replay_verified(
+        &engine.event_log,
+        vec![btc_market()],
+        EngineConfig::default(),
+    )
+    .unwrap();
+    assert_eq!(replayed.state, engine.state);
+    assert_eq!(replayed.snapshots, engine.snapshots);
+    let rejected: Vec<u64> = engine
+        .event_log
+        .windows(2)
+        .filter(|pair| pair[1].event_type.is_rejection())
+        .map(|pair| pair[0].sequence)
+        .collect();
+    assert_eq!(rejected.len(), 1);
+    assert_eq!(
+        replayed
+            .rejections
+            .iter()
+            .map(|(sequence, _)| *sequence)
+            .collect::<Vec<_>>(),
+        rejected
+    );
+
+    let regenerated = Engine::regenerate(
+        &engine.event_log,
+        vec![btc_market()],
+        EngineConfig::default(),
+    )
+    .unwrap();
+    assert_eq!(diff_logs(&engine.event_log, &regenerated), None);
+
+    // Each account's shard replays its side of the batch on its own.
+    let shards =
+        events::split_by_account(&engine.event_log, |account| usize::from(account != "alice"))
+            .unwrap();
+    for shard in &shards {
+        Engine::replay_verified(shard, vec![btc_market()], EngineConfig::default()).unwrap();
+    }
+}
+
+#[test]
+fn unpaired_batch_markers_fail_verification() {
+    let mut engine = engine();
+    batch(&mut engine, vec![mark("BTC-PERP", dec!(49000))]);
+    let mut log: Vec<Event> = engine
+        .event_log
+        .iter()
+        .map(|e| e.as_ref().clone())
+        .collect();
+    let end = log
+        .iter_mut()
+        .find(|e| matches!(e.event_type, EventType::BatchEnded { .. }))
+        .unwrap();
+    end.event_type = EventType::BatchStarted { submissions: 1 };
+    assert!(matches!(
+        Engine::replay_verified(&log, vec![btc_market()], EngineConfig::default()),
+        Err(EngineError::InvalidDerivedEvent { .. })
+    ));
+}
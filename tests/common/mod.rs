@@ -0,0 +1,74 @@
+// Helpers shared by the integration tests. Each test crate uses its own subset.
+#![allow(dead_code)]
+
+use cross_margin_engine::prelude::*;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+pub fn id(text: &str) -> AccountId {
+    text.parse().unwrap()
+}
+
+pub fn market(text: &str) -> MarketId {
+    text.parse().unwrap()
+}
+
+pub fn btc() -> MarketId {
+    market("BTC-PERP")
+}
+
+/// BTC-PERP at 5% IM and 3% MM.
+pub fn btc_market() -> Market {
+    Market::new(btc(), dec!(0.05), dec!(0.03))
+}
+
+/// ETH-PERP at 10% IM and 5% MM.
+pub fn eth_market() -> Market {
+    Market::new(market("ETH-PERP"), dec!(0.10), dec!(0.05))
+}
+
+pub fn mark(market_id: &str, price: Decimal) -> EventType {
+    EventType::MarkPriceUpdate {
+        market_id: market(market_id),
+        price,
+    }
+}
+
+pub fn trade(account: &str, market_id: &str, quantity: Decimal, price: Decimal) -> EventType {
+    EventType::TradeFill {
+        account_id: id(account),
+        market_id: market(market_id),
+        quantity,
+        price,
+        liquidity: None,
+    }
+}
+
+pub fn deposit(account: &str, amount: Decimal) -> EventType {
+    EventType::Deposit {
+        account_id: id(account),
+        amount,
+    }
+}
+
+/// BTC-PERP and ETH-PERP, as `btc_market` and `eth_market` have them.
+pub fn markets() -> Vec<Market> {
+    vec![btc_market(), eth_market()]
+}
+
+/// An engine under `config` with `markets` added and nothing processed.
+pub fn engine_with(config: EngineConfig, markets: Vec<Market>) -> Engine {
+    let mut engine = Engine::with_config(config);
+    for market in markets {
+        engine.add_market(market).unwrap();
+    }
+    engine
+}
+
+/// Process `event_type`, which must be accepted.
+pub fn process(engine: &mut Engine, event_type: EventType) {
+    assert!(
+        engine.process(event_type.clone()).is_accepted(),
+        "{event_type:?}"
+    );
+}
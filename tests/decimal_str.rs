@@ -0,0 +1,138 @@
+// The canonical decimal encoding. Every event, snapshot and final state of every
+// scenario reads back equal to what was written and writes the same bytes again;
+// a decimal serializes the same whatever its scale, in each `decimal_str` form and
+// inside an event, a snapshot and a state.
+
+mod common;
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use common::{btc_market, deposit, engine_with, mark, process, trade};
+use cross_margin_engine::decimal_str;
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::scenario;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+fn scenario_paths() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios");
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// `value` reads back equal and writes the same bytes again.
+fn assert_round_trips<T>(value: &T, what: &str)
+where
+    T: Serialize + DeserializeOwned + PartialEq + std::fmt::Debug,
+{
+    let json = serde_json::to_string(value).unwrap();
+    let back: T = serde_json::from_str(&json).unwrap_or_else(|e| panic!("{what}: {e}"));
+    assert_eq!(&back, value, "{what}");
+    assert_eq!(serde_json::to_string(&back).unwrap(), json, "{what}");
+}
+
+#[test]
+fn every_scenario_round_trips() {
+    for path in scenario_paths() {
+        let run = scenario::load(&path)
+            .and_then(|s| scenario::run(&s))
+            .unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+        let name = path.file_name().unwrap().to_string_lossy();
+        let engine = &run.engine;
+        for event in engine.event_log.iter() {
+            assert_round_trips(&**event, &format!("{name} seq {}", event.sequence));
+        }
+        for snapshot in &engine.snapshots {
+            let what = format!("{name} snapshot {}", snapshot.after_sequence);
+            assert_round_trips(snapshot, &what);
+        }
+        assert_round_trips(&engine.state, &format!("{name} state"));
+        let json = engine.state.to_json();
+        let back = State::from_json(&json).unwrap();
+        assert_eq!(back, engine.state, "{name}");
+        assert_eq!(back.to_json(), json, "{name}");
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Forms {
+    #[serde(with = "decimal_str")]
+    plain: Decimal,
+    #[serde(with = "decimal_str::option")]
+    option: Option<Decimal>,
+    #[serde(with = "decimal_str::vec")]
+    vec: Vec<Decimal>,
+    #[serde(with = "decimal_str::option_vec")]
+    option_vec: Vec<Option<Decimal>>,
+    #[serde(with = "decimal_str::map")]
+    map: BTreeMap<String, Decimal>,
+    #[serde(with = "decimal_str::option_map")]
+    option_map: Option<BTreeMap<String, Decimal>>,
+}
+
+fn forms(value: Decimal) -> Forms {
+    Forms {
+        plain: value,
+        option: Some(value),
+        vec: vec![value, -value],
+        option_vec: vec![Some(value), None],
+        map: BTreeMap::from([("BTC-PERP".to_string(), value)]),
+        option_map: Some(BTreeMap::from([("BTC-PERP".to_string(), value)])),
+    }
+}
+
+#[test]
+fn scale_does_not_change_the_bytes() {
+    let (whole, scaled) = (dec!(41000), dec!(41000.00));
+    assert_ne!(whole.scale(), scaled.scale());
+
+    let json = serde_json::to_string(&forms(whole)).unwrap();
+    assert_eq!(serde_json::to_string(&forms(scaled)).unwrap(), json);
+    assert!(json.contains(r#""plain":"41000""#), "{json}");
+    assert_round_trips(&forms(scaled), "forms");
+    // Zero at any scale, negative zero included, is "0".
+    for zero in [dec!(0), dec!(0.000), -dec!(0.00)] {
+        let json = serde_json::to_string(&forms(zero)).unwrap();
+        assert!(json.contains(r#""plain":"0""#), "{json}");
+        assert!(!json.contains("-0"), "{json}");
+    }
+}
+
+/// An engine that marked BTC at `price` and opened alice 1 BTC at it.
+fn engine_at(price: Decimal) -> Engine {
+    let mut engine = engine_with(EngineConfig::default(), vec![btc_market()]);
+    for event_type in [
+        mark("BTC-PERP", price),
+        deposit("alice", dec!(100000)),
+        trade("alice", "BTC-PERP", dec!(1.000), price),
+    ] {
+        process(&mut engine, event_type);
+    }
+    engine
+}
+
+#[test]
+fn events_snapshots_and_states_ignore_scale() {
+    let whole = engine_at(dec!(41000));
+    let scaled = engine_at(dec!(41000.00));
+    for (a, b) in whole.event_log.iter().zip(scaled.event_log.iter()) {
+        assert_eq!(
+            serde_json::to_string(a).unwrap(),
+            serde_json::to_string(b).unwrap()
+        );
+    }
+    let price = serde_json::to_string(&whole.event_log[1]).unwrap();
+    assert!(price.contains(r#""price":"41000""#), "{price}");
+    assert_eq!(
+        serde_json::to_string(&whole.snapshots).unwrap(),
+        serde_json::to_string(&scaled.snapshots).unwrap()
+    );
+    assert_eq!(whole.state.to_json(), scaled.state.to_json());
+}
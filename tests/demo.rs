@@ -0,0 +1,83 @@
+// The checks behind the demo's walkthrough, as assertions. Replay of the demo log
+// reaches the live state along the same snapshots, resuming at any of them lands on
+// the same end, and resubmitting what was submitted writes the same log again. The
+// books balance live and replayed, and the funding report of the one ETH funding
+// event matches what the longs paid.
+
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::{demo, regenerate, report, snapshot, state};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+#[test]
+fn replay_follows_the_live_path() {
+    let engine = demo::engine();
+    let (state, snapshots) = Engine::replay(&engine.event_log, demo::markets());
+    assert_eq!(state, engine.state);
+    assert!(snapshots.iter().all(|s| s.markets.len() == 2));
+    assert_eq!(
+        snapshot::first_divergence(&engine.snapshots, &snapshots),
+        None
+    );
+    assert_eq!(snapshots, engine.snapshots);
+}
+
+#[test]
+fn resuming_at_every_snapshot_reaches_the_same_end() {
+    let engine = demo::engine();
+    let (end, snapshots) = Engine::replay(&engine.event_log, demo::markets());
+    assert_eq!(snapshots.len(), 19);
+    for (i, snapshot) in snapshots.iter().enumerate() {
+        let (state, tail) =
+            Engine::resume_from_snapshot(snapshot, demo::markets(), &engine.event_log)
+                .unwrap_or_else(|e| panic!("resume at snapshot {i}: {e}"));
+        assert_eq!(state, end, "resume at snapshot {i}");
+        assert_eq!(tail, snapshots[i + 1..], "resume at snapshot {i}");
+    }
+}
+
+#[test]
+fn regeneration_writes_the_same_log() {
+    let engine = demo::engine();
+    let regenerated =
+        Engine::regenerate(&engine.event_log, demo::markets(), engine.config().clone()).unwrap();
+    assert_eq!(regenerate::diff_logs(&engine.event_log, &regenerated), None);
+}
+
+#[test]
+fn books_balance_live_and_replayed() {
+    let engine = demo::engine();
+    let live = engine.solvency();
+    assert!(live.is_balanced(), "{live:?}");
+    assert_eq!(live.residual, Decimal::ZERO);
+
+    let replayed =
+        Engine::replay_with(ReplayOptions::default(), &engine.event_log, demo::markets());
+    let books = state::solvency(&replayed.state, &replayed.metrics);
+    assert!(books.is_balanced(), "{books:?}");
+    assert_eq!(books, live);
+}
+
+#[test]
+fn funding_report_matches_what_the_longs_paid() {
+    let engine = demo::engine();
+    let paid: Decimal = engine
+        .state
+        .accounts
+        .values()
+        .filter_map(|a| a.funding_paid.get("ETH-PERP"))
+        .sum();
+    // bob's 20 ETH and charlie's 15 on an index from 0 to 1.50.
+    assert_eq!(paid, dec!(52.5));
+
+    // Longs and no shorts: the period does not net to zero.
+    let funding = report::funding_history(&engine.event_log, demo::markets());
+    let [period] = funding.as_slice() else {
+        panic!("one funding period, got {funding:?}");
+    };
+    assert_eq!(period.new_index - period.old_index, dec!(1.50));
+    assert_eq!(period.paid_by_longs, paid);
+    assert_eq!(period.received_by_shorts, Decimal::ZERO);
+    assert_eq!(period.residual, -paid);
+    assert!(!period.balanced);
+}
@@ -0,0 +1,215 @@
+// Seven submissions journaled through a DurableEngine, the last three as one group
+// commit. Cutting the journal at every byte offset of its last record, and of its
+// header, recovers exactly the engine that processed the submissions whose lines
+// survived: state and log, duplicate keys and liquidations included. A recovered
+// journal takes the lost submission again; interior damage and a different config
+// are refused.
+
+mod common;
+
+use common::{deposit, mark, markets, trade};
+use cross_margin_engine::prelude::*;
+use rust_decimal_macros::dec;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+fn config() -> EngineConfig {
+    EngineConfig {
+        rejection_throttle: Some(RejectionThrottle {
+            window: 4,
+            max_rejections: 2,
+            summary_every: 10,
+        }),
+        ..EngineConfig::default()
+    }
+}
+
+fn submissions() -> Vec<(EventType, Submission)> {
+    let keyed = |key: &str| Submission {
+        idempotency_key: Some(key.into()),
+        ..Submission::default()
+    };
+    let at = |timestamp| Submission {
+        timestamp: Some(timestamp),
+        ..Submission::default()
+    };
+    vec![
+        (mark("BTC-PERP", dec!(50000)), at(1_700_000_000_000)),
+        (deposit("alice", dec!(10000)), keyed("dep-1")),
+        (deposit("alice", dec!(10000)), keyed("dep-1")),
+        (
+            trade("alice", "BTC-PERP", dec!(1), dec!(50000)),
+            Submission::default(),
+        ),
+        (
+            trade("alice", "BTC-PERP", dec!(10), dec!(50000)),
+            Submission::default(),
+        ),
+        (mark("BTC-PERP", dec!(40000)), at(1_700_000_060_000)),
+        (deposit("bob", dec!(10000)), Submission::default()),
+    ]
+}
+
+/// The journal of the submissions and what a plain engine makes of them.
+struct Journal {
+    path: PathBuf,
+    bytes: Vec<u8>,
+    /// The state and log after each prefix of the submissions, the empty one first.
+    prefixes: Vec<(State, Vec<Arc<Event>>)>,
+    outcomes: Vec<ProcessOutcome>,
+}
+
+impl Journal {
+    /// Journal the submissions at a path of the temporary directory named after
+    /// `name`: four synced one by one, then three in one group commit.
+    fn write(name: &str) -> Journal {
+        let mut reference = Engine::builder().config(config()).build();
+        for market in markets() {
+            reference.add_market(market).unwrap();
+        }
+        let mut prefixes = vec![(reference.state.clone(), reference.event_log.clone())];
+        let mut outcomes = Vec::new();
+        for (event_type, submission) in submissions() {
+            outcomes.push(reference.process_with(event_type, submission));
+            prefixes.push((reference.state.clone(), reference.event_log.clone()));
+        }
+
+        let path = std::env::temp_dir().join(format!("cross-margin-engine-{name}.jsonl"));
+        let mut durable = DurableEngine::create(&path, markets(), config()).unwrap();
+        let mut all = submissions();
+        let batch = all.split_off(4);
+        let mut live = Vec::new();
+        for (event_type, submission) in all {
+            live.push(durable.process_with(event_type, submission).unwrap());
+        }
+        live.extend(durable.process_batch(batch).unwrap());
+        assert_eq!(live, outcomes);
+        let metrics = durable.sync_metrics();
+        assert_eq!((metrics.syncs, metrics.submissions), (5, 7));
+        assert!(metrics.max_latency >= metrics.mean_latency());
+        assert_eq!(durable.engine().event_log, prefixes[7].1);
+        drop(durable);
+
+        let bytes = std::fs::read(&path).unwrap();
+        Journal {
+            path,
+            bytes,
+            prefixes,
+            outcomes,
+        }
+    }
+
+    /// The offset of the line that ends at `end`.
+    fn line_start(&self, end: usize) -> usize {
+        self.bytes[..end - 1]
+            .iter()
+            .rposition(|b| *b == b'\n')
+            .map_or(0, |i| i + 1)
+    }
+
+    /// A path next to the journal for a damaged copy of it.
+    fn copy_path(&self) -> PathBuf {
+        self.path.with_extension("cut.jsonl")
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+        let _ = std::fs::remove_file(self.copy_path());
+    }
+}
+
+#[test]
+fn the_reference_run_liquidates() {
+    let journal = Journal::write("journal-reference");
+    let liquidated = journal.prefixes[7]
+        .1
+        .iter()
+        .any(|e| matches!(e.event_type, EventType::LiquidationFill { .. }));
+    assert!(liquidated, "the 40,000 mark liquidates alice");
+}
+
+#[test]
+fn a_cut_inside_the_header_starts_over() {
+    let journal = Journal::write("journal-header");
+    let cut = journal.copy_path();
+    let header_end = journal.bytes.iter().position(|b| *b == b'\n').unwrap() + 1;
+    for offset in 0..header_end {
+        std::fs::write(&cut, &journal.bytes[..offset]).unwrap();
+        let recovered = DurableEngine::open(&cut, markets(), config()).unwrap();
+        assert_eq!(recovered.recovery().submissions, 0, "offset {offset}");
+        assert!(recovered.engine().event_log.is_empty());
+    }
+}
+
+#[test]
+fn a_cut_inside_the_last_record_recovers_the_submissions_before_it() {
+    let journal = Journal::write("journal-last");
+    let cut = journal.copy_path();
+    let last = journal.line_start(journal.bytes.len());
+    // The last submission is lost unless only its newline is, and the engine equals
+    // the one that processed the rest.
+    for offset in last..=journal.bytes.len() {
+        std::fs::write(&cut, &journal.bytes[..offset]).unwrap();
+        let mut recovered = DurableEngine::open(&cut, markets(), config()).unwrap();
+        let kept = if offset >= journal.bytes.len() - 1 {
+            7
+        } else {
+            6
+        };
+        let recovery = recovered.recovery();
+        assert_eq!(recovery.submissions, kept, "offset {offset}");
+        let torn = if kept == 6 { offset - last } else { 0 };
+        assert_eq!(recovery.torn_bytes, torn, "offset {offset}");
+        let (state, log) = &journal.prefixes[kept as usize];
+        assert_eq!(recovered.engine().state, *state, "offset {offset}");
+        assert_eq!(recovered.engine().event_log, *log, "offset {offset}");
+
+        // The lost submission can be sent again, and the journal then recovers to the
+        // full run.
+        if kept == 6 {
+            let (event_type, submission) = submissions().pop().unwrap();
+            assert_eq!(
+                recovered.process_with(event_type, submission).unwrap(),
+                journal.outcomes[6]
+            );
+        }
+        drop(recovered);
+        let reopened = DurableEngine::open(&cut, markets(), config()).unwrap();
+        assert_eq!(
+            reopened.engine().event_log,
+            journal.prefixes[7].1,
+            "offset {offset}"
+        );
+        assert_eq!(
+            std::fs::read(&cut).unwrap(),
+            journal.bytes,
+            "offset {offset}"
+        );
+    }
+}
+
+#[test]
+fn a_garbled_interior_line_is_refused() {
+    // It loses a submission that was acknowledged.
+    let journal = Journal::write("journal-garbled");
+    let cut = journal.copy_path();
+    let last = journal.line_start(journal.bytes.len());
+    let mut garbled = journal.bytes.clone();
+    garbled[journal.line_start(last) + 2] = b'#';
+    std::fs::write(&cut, &garbled).unwrap();
+    let result = DurableEngine::open(&cut, markets(), config());
+    assert!(matches!(result, Err(EngineError::CorruptLog { .. })));
+}
+
+#[test]
+fn another_config_is_refused() {
+    // Re-processing under it would rebuild another engine.
+    let journal = Journal::write("journal-config");
+    let result = DurableEngine::open(&journal.path, markets(), EngineConfig::default());
+    let Err(EngineError::ConfigMismatch { fields, .. }) = result else {
+        panic!("expected a config mismatch, got {:?}", result.err())
+    };
+    assert_eq!(fields, ["rejection_throttle"]);
+}
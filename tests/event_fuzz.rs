@@ -0,0 +1,757 @@
+// Throw arbitrary event sequences at the engine: every event type, engine-generated
+// ones included, with unknown accounts and markets, duplicate keys, retries of the
+// event before, clocks that jump around, and decimals from zero and 1e-28 up to
+// `Decimal::MAX`, of either sign. No event may panic `process`, and the log must
+// replay to the same state with no invariant violations. Unless a rejection throttle
+// left submissions out of it, it must also regenerate from its external events byte
+// for byte. The books must balance after every event, to within the rounding of
+// values carrying all 28 of `Decimal`'s digits, relative to the largest of them. The
+// raw events, taken as a log as they are, must replay without panicking too, and
+// published state views must match the state. The sequences it has found failing run
+// first, reduced to a few events each.
+//
+// `cargo test --test event_fuzz`; `EVENT_FUZZ_EVENTS` (events per seed) and
+// `EVENT_FUZZ_SEEDS` override the quick defaults. A long run:
+// `EVENT_FUZZ_EVENTS=1000000 EVENT_FUZZ_SEEDS=8 cargo test --release --test event_fuzz`.
+
+mod common;
+
+use common::{btc_market, engine_with, eth_market};
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::regenerate::diff_logs;
+use cross_margin_engine::rng::Lcg;
+use cross_margin_engine::types::FundingMode;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::BTreeMap;
+
+/// The draws the fuzzer needs beyond `Lcg`'s own.
+trait Draws {
+    fn id<T: std::str::FromStr>(&mut self, items: &[&str]) -> T
+    where
+        T::Err: std::fmt::Debug;
+
+    /// Mostly plausible values around `base`, sometimes anything at all.
+    fn decimal(&mut self, base: i64) -> Decimal;
+
+    fn option_decimal(&mut self, base: i64) -> Option<Decimal>;
+}
+
+impl Draws for Lcg {
+    fn id<T: std::str::FromStr>(&mut self, items: &[&str]) -> T
+    where
+        T::Err: std::fmt::Debug,
+    {
+        self.pick(items).parse().unwrap()
+    }
+
+    fn decimal(&mut self, base: i64) -> Decimal {
+        match self.below(20) {
+            0 => Decimal::ZERO,
+            1 => Decimal::MAX,
+            2 => Decimal::MIN,
+            3 => Decimal::new(1, 28),
+            4 => Decimal::new(-(self.next_u64() as i64), self.below(29) as u32),
+            5 => Decimal::from_i128_with_scale(
+                self.next_u64() as i128 * self.next_u64() as i128,
+                self.below(12) as u32,
+            ),
+            6 => Decimal::new(self.next_u64() as i64, self.below(29) as u32),
+            _ => {
+                let step = Decimal::new(self.below(2001) as i64 - 1000, 3);
+                (Decimal::from(base) * (Decimal::ONE + step / dec!(10)))
+                    .round_dp(self.below(9) as u32)
+            }
+        }
+    }
+
+    fn option_decimal(&mut self, base: i64) -> Option<Decimal> {
+        self.chance(50).then(|| self.decimal(base))
+    }
+}
+
+const ACCOUNTS: [&str; 6] = ["alice", "bob", "carol", "dave", "keeper", "ghost"];
+const MARKETS: [&str; 4] = ["BTC-PERP", "ETH-PERP", "ETH-0627", "NOPE-PERP"];
+const POOLS: [&str; 3] = ["default", "pool-a", "pool-b"];
+const GROUPS: [&str; 3] = ["mm-1", "mm-2", ""];
+const EXPIRY: u64 = 1_700_000_500_000;
+
+fn markets() -> Vec<Market> {
+    let mut btc = btc_market();
+    btc.liquidation_discount = dec!(0.01);
+    btc.slippage_bps_per_notional = dec!(0.00001);
+    btc.staleness_threshold_ms = Some(60_000);
+    btc.max_open_interest_notional = Some(dec!(50000000));
+    btc.max_leverage = Some(dec!(25));
+    btc.funding_mode = FundingMode::Continuous;
+    let mut eth = eth_market();
+    eth.allow_negative_prices = true;
+    eth.concentration_threshold_notional = dec!(100000);
+    eth.concentration_add_on_fraction = dec!(0.02);
+    eth.skew_limit_notional = Some(dec!(20000));
+    let mut future = Market::future("ETH-0627".parse().unwrap(), dec!(0.10), dec!(0.05), EXPIRY);
+    future.quantity_step = Some(dec!(0.01));
+    future.min_liquidation_notional = Some(dec!(50));
+    vec![btc, eth, future]
+}
+
+fn config(rng: &mut Lcg) -> EngineConfig {
+    EngineConfig {
+        liquidation_path: if rng.chance(50) {
+            LiquidationPath::Keepers(vec!["keeper".parse().unwrap(), "dave".parse().unwrap()])
+        } else {
+            LiquidationPath::EngineClose
+        },
+        scan_order: [
+            ScanOrder::AccountId,
+            ScanOrder::WorstMarginRatioFirst,
+            ScanOrder::LargestNotionalFirst,
+        ][rng.below(3) as usize],
+        liquidation_strategy: if rng.chance(50) {
+            LiquidationStrategy::BestMarginImprovementFirst
+        } else {
+            LiquidationStrategy::LargestNotionalFirst
+        },
+        trade_margin_policy: if rng.chance(50) {
+            TradeMarginPolicy::IncrementalIm
+        } else {
+            TradeMarginPolicy::FullPortfolio
+        },
+        bankruptcy_suspension: [
+            BankruptcySuspension::Off,
+            BankruptcySuspension::AllMarkets,
+            BankruptcySuspension::BankruptedMarkets,
+        ][rng.below(3) as usize],
+        residual_deficit: [
+            ResidualDeficit::Hold,
+            ResidualDeficit::Socialize,
+            ResidualDeficit::SocializeEquity,
+        ][rng.below(3) as usize],
+        max_cascade_rescans: [0, 1, 16][rng.below(3) as usize],
+        skew_response: [SkewResponse::Report, SkewResponse::ReduceOnly][rng.below(2) as usize],
+        closed_session_liquidation: if rng.chance(50) {
+            ClosedSessionLiquidation::DeferUntilOpen
+        } else {
+            ClosedSessionLiquidation::LiquidateAnyway
+        },
+        unknown_markets: [UnknownMarketPolicy::Ignore, UnknownMarketPolicy::Reject]
+            [rng.below(2) as usize],
+        import_margin_check: [ImportMarginCheck::Reject, ImportMarginCheck::Warn]
+            [rng.below(2) as usize],
+        withdrawal_buffer: [dec!(1), dec!(1.25)][rng.below(2) as usize],
+        risk_deltas: [RiskDeltaPolicy::Off, RiskDeltaPolicy::ObserversAndQueue]
+            [rng.below(2) as usize],
+        interest: rng.chance(50).then(|| InterestAccrual {
+            rate_per_interval: dec!(0.001),
+            credit_rate_per_interval: dec!(0.0001),
+        }),
+        yield_basis: [YieldBasis::PositiveBalances, YieldBasis::AllBalances][rng.below(2) as usize],
+        rejection_throttle: rng.chance(50).then_some(RejectionThrottle {
+            window: 8,
+            max_rejections: 2,
+            summary_every: 5,
+        }),
+        risk_alerts: rng.chance(50).then(|| RiskAlertLadder {
+            thresholds: vec![dec!(0.5), dec!(0.8), dec!(0.95)],
+            hysteresis: [dec!(0), dec!(0.05)][rng.below(2) as usize],
+        }),
+        trade_stats: rng.chance(50).then(|| TradeStatistics {
+            window: [StatsWindow::Fills(3), StatsWindow::Millis(5_000)][rng.below(2) as usize],
+            fee_tiers: vec![FeeTier {
+                min_turnover: dec!(100000),
+                rate: dec!(0.0003),
+            }],
+        }),
+        margin_call: rng.chance(50).then(|| MarginCallPolicy {
+            grace: [GracePeriod::Events(6), GracePeriod::Millis(5_000)][rng.below(2) as usize],
+            hard_fraction: dec!(0.5),
+        }),
+        max_total_open_interest_notional: rng.chance(50).then_some(dec!(80000000)),
+        idempotency_window: 16,
+        ..EngineConfig::default()
+    }
+}
+
+fn event(rng: &mut Lcg) -> EventType {
+    let price = |rng: &mut Lcg, market_id: &str| match market_id {
+        "BTC-PERP" => rng.decimal(50_000),
+        _ => rng.decimal(3_000),
+    };
+    match rng.below(39) {
+        0..=3 => EventType::Deposit {
+            account_id: rng.id(&ACCOUNTS),
+            amount: rng.decimal(20_000),
+        },
+        4 => EventType::Withdraw {
+            account_id: rng.id(&ACCOUNTS),
+            amount: rng.decimal(5_000),
+        },
+        5..=9 => {
+            let market_id: MarketId = rng.id(&MARKETS);
+            let side = if rng.chance(50) { 1 } else { -1 };
+            EventType::TradeFill {
+                account_id: rng.id(&ACCOUNTS),
+                quantity: rng.decimal(side),
+                price: price(rng, &market_id),
+                market_id,
+                liquidity: [None, Some(Liquidity::Maker), Some(Liquidity::Taker)]
+                    [rng.below(3) as usize],
+            }
+        }
+        10..=13 => {
+            let market_id: MarketId = rng.id(&MARKETS);
+            EventType::MarkPriceUpdate {
+                price: price(rng, &market_id),
+                market_id,
+            }
+        }
+        14 => {
+            let mut updates = BTreeMap::new();
+            for _ in 0..rng.below(4) {
+                let market_id: MarketId = rng.id(&MARKETS);
+                updates.insert(market_id.clone(), price(rng, &market_id));
+            }
+            EventType::MarkPriceBatch { updates }
+        }
+        15 => EventType::FundingUpdate {
+            market_id: rng.id(&MARKETS),
+            new_cumulative_index: rng.decimal(10),
+        },
+        16 => EventType::FundingRate {
+            market_id: rng.id(&MARKETS),
+            rate: dec!(0.0003),
+            interval_id: rng.below(50),
+        },
+        17 => EventType::SetAccountLimits {
+            account_id: rng.id(&ACCOUNTS),
+            max_leverage: rng.option_decimal(10),
+            max_total_notional: rng.option_decimal(500_000),
+        },
+        18 => EventType::AccountMetadata {
+            account_id: rng.id(&ACCOUNTS),
+            key: rng.id(&["desk", "tier", ""]),
+            value: "x".repeat(rng.below(300) as usize),
+        },
+        19 => EventType::AssignPool {
+            account_id: rng.id(&ACCOUNTS),
+            pool_id: rng.id(&POOLS),
+        },
+        20 => EventType::InsuranceFundDeposit {
+            pool_id: rng.id(&POOLS),
+            amount: rng.decimal(10_000),
+        },
+        21 => EventType::StateImport {
+            account_id: rng.id(&ACCOUNTS),
+            pool_id: rng.id(&POOLS),
+            collateral: rng.decimal(20_000),
+            positions: (0..rng.below(3))
+                .map(|_| {
+                    let market_id: MarketId = rng.id(&MARKETS);
+                    ImportedPosition {
+                        quantity: rng.decimal(1),
+                        cost_basis: price(rng, &market_id),
+                        last_funding: rng.decimal(1),
+                        market_id,
+                    }
+                })
+                .collect(),
+        },
+        22 => {
+            let market_id: MarketId = rng.id(&MARKETS);
+            if rng.chance(50) {
+                EventType::SessionOpen { market_id }
+            } else {
+                EventType::SessionClose { market_id }
+            }
+        }
+        23 => EventType::HedgePairAdded {
+            market_a: rng.id(&MARKETS),
+            market_b: rng.id(&MARKETS),
+            offset_fraction: rng.decimal(0),
+        },
+        24 => {
+            let market_id: MarketId = rng.id(&MARKETS);
+            EventType::Expiry {
+                settlement_price: price(rng, &market_id),
+                market_id,
+            }
+        }
+        25 => EventType::InterestTick {
+            interval_id: rng.below(50),
+        },
+        26 => {
+            let account_id = rng.id(&ACCOUNTS);
+            if rng.chance(10) {
+                EventType::ForceClose {
+                    account_id,
+                    reason: "compliance".into(),
+                }
+            } else {
+                EventType::AccountReinstated { account_id }
+            }
+        }
+        27 => {
+            let market_id: MarketId = rng.id(&MARKETS);
+            EventType::LiquidationTakeover {
+                liquidated_account: rng.id(&ACCOUNTS),
+                keeper_account: rng.id(&ACCOUNTS),
+                quantity: rng.decimal(1),
+                price: price(rng, &market_id),
+                market_id,
+            }
+        }
+        28 => {
+            let group_id = rng.id(&GROUPS);
+            if rng.chance(30) {
+                EventType::GroupCreated {
+                    group_id,
+                    max_group_notional: rng.option_decimal(300_000),
+                    fee_override: rng.option_decimal(0),
+                }
+            } else {
+                EventType::GroupMembershipSet {
+                    account_id: rng.id(&ACCOUNTS),
+                    group_id: rng.chance(80).then_some(group_id),
+                }
+            }
+        }
+        29 => EventType::SetPositionLeverage {
+            account_id: rng.id(&ACCOUNTS),
+            market_id: rng.id(&MARKETS),
+            leverage: rng.decimal(30),
+        },
+        30 => {
+            let market_id: MarketId = rng.id(&MARKETS);
+            if rng.chance(50) {
+                EventType::MarketRemoved { market_id }
+            } else {
+                let fraction = |rng: &mut Lcg| Decimal::new(rng.below(20) as i64, 2);
+                let mut market = Market::new(market_id.clone(), fraction(rng), fraction(rng));
+                market.mark_price = price(rng, &market_id);
+                EventType::MarketAdded {
+                    market: Box::new(market),
+                }
+            }
+        }
+        31 => EventType::YieldDistribution {
+            rate: if rng.chance(80) {
+                Decimal::new(rng.below(1_100) as i64 - 100, 5)
+            } else {
+                rng.decimal(0)
+            },
+            interval_id: rng.below(50),
+        },
+        32 => EventType::ConfigUpdated {
+            config: config(rng),
+        },
+        33 => EventType::BackstopRegistered {
+            account_id: rng.id(&ACCOUNTS),
+            market_id: rng.id(&MARKETS),
+            max_notional: rng.decimal(100_000),
+        },
+        34 => EventType::FundingAccrual {
+            market_id: rng.id(&MARKETS),
+            accrued_index: rng.decimal(10),
+        },
+        35 => EventType::AccountsMerged {
+            from: rng.id(&ACCOUNTS),
+            to: rng.id(&ACCOUNTS),
+        },
+        36 => {
+            let market_id: MarketId = rng.id(&MARKETS);
+            let side = if rng.chance(50) { 1 } else { -1 };
+            EventType::PositionTransfer {
+                from: rng.id(&ACCOUNTS),
+                to: rng.id(&ACCOUNTS),
+                quantity: rng.decimal(side),
+                transfer_price: price(rng, &market_id),
+                market_id,
+            }
+        }
+        // Records only the engine writes; submitting them is a caller bug.
+        _ => engine_generated(rng),
+    }
+}
+
+fn engine_generated(rng: &mut Lcg) -> EventType {
+    let account_id = rng.id(&ACCOUNTS);
+    let market_id: MarketId = rng.id(&MARKETS);
+    match rng.below(19) {
+        0 => EventType::LiquidationFill {
+            account_id,
+            market_id,
+            quantity: rng.decimal(1),
+            price: rng.decimal(3_000),
+        },
+        1 => EventType::FundingPayment {
+            account_id,
+            market_id,
+            amount: rng.decimal(10),
+        },
+        2 => EventType::InsuranceFundPayout {
+            pool_id: rng.id(&POOLS),
+            account_id,
+            amount: rng.decimal(100),
+        },
+        3 => EventType::LiquidationDeferred {
+            account_id,
+            market_ids: vec![market_id],
+        },
+        4 => EventType::ExpirySettlement {
+            account_id,
+            market_id,
+            quantity: rng.decimal(1),
+            price: rng.decimal(3_000),
+            realized_pnl: rng.decimal(100),
+        },
+        5 => EventType::InterestCharged {
+            account_id,
+            amount: rng.decimal(10),
+        },
+        6 => EventType::UnknownMarketIgnored {
+            market_id,
+            original_sequence: rng.below(100),
+        },
+        7 => EventType::DuplicateIgnored {
+            key: "k".into(),
+            original_sequence: rng.below(100),
+        },
+        8 => EventType::ConfigMarker {
+            config_hash: String::new(),
+            config: EngineConfig::default(),
+        },
+        9 => EventType::RejectionSuppressed {
+            account_id,
+            count: rng.below(10),
+            first_sequence: rng.below(100),
+            last_sequence: rng.below(100),
+        },
+        10 => EventType::RiskAlert {
+            account_id,
+            level: rng.below(4) as usize,
+            margin_usage: rng.chance(80).then(|| rng.decimal(1)),
+        },
+        11 => EventType::LossSocialized {
+            pool_id: rng.id(&POOLS),
+            account_id,
+            amount: rng.decimal(100),
+            charges: BTreeMap::from([(rng.id(&ACCOUNTS), rng.decimal(100))]),
+        },
+        12 => EventType::SkewLimitBreached {
+            market_id,
+            net_notional: rng.decimal(100_000),
+        },
+        13 => EventType::ForceCloseFill {
+            account_id,
+            market_id,
+            quantity: rng.decimal(1),
+            price: rng.decimal(3_000),
+            reason: "compliance".into(),
+        },
+        14 => EventType::YieldPaid {
+            account_id,
+            interval_id: rng.below(50),
+            amount: rng.decimal(10),
+        },
+        15 => EventType::YieldResidual {
+            pool_id: rng.id(&POOLS),
+            interval_id: rng.below(50),
+            amount: rng.decimal(0),
+        },
+        16 => EventType::MarginCall {
+            account_id,
+            equity: rng.decimal(1_000),
+            maintenance_margin: rng.decimal(1_000),
+        },
+        17 => EventType::FeeCharged {
+            account_id,
+            market_id,
+            rate: dec!(0.0003),
+            amount: rng.decimal(10),
+        },
+        _ => EventType::TradeRejected {
+            account_id,
+            market_id,
+            quantity: rng.decimal(1),
+            price: rng.decimal(3_000),
+            reason: "made up".into(),
+        },
+    }
+}
+
+/// The largest of the totals a solvency residual is computed from. Funding can carry
+/// them past 1e20, where 28 digits leave only a few decimal places.
+fn magnitude(solvency: &SolvencyReport) -> Decimal {
+    [
+        solvency.total_collateral,
+        solvency.insurance_funds,
+        solvency.interest_revenue,
+        solvency.fee_revenue,
+        solvency.net_transfers,
+        solvency.realized_pnl,
+        solvency.funding,
+        solvency.bankruptcy_deficits,
+    ]
+    .iter()
+    .map(|total| total.abs())
+    .max()
+    .unwrap_or_default()
+}
+
+/// `name` from the environment, or `default` when it is unset.
+fn env_count(name: &str, default: u64) -> u64 {
+    std::env::var(name).map_or(default, |value| {
+        value
+            .parse()
+            .unwrap_or_else(|_| panic!("{name} is not a number: {value:?}"))
+    })
+}
+
+#[test]
+fn seeded_event_sequences() {
+    let per_seed = env_count("EVENT_FUZZ_EVENTS", 4_000);
+    let seeds = env_count("EVENT_FUZZ_SEEDS", 4);
+
+    let (mut accepted, mut rejected, mut suppressed) = (0u64, 0u64, 0u64);
+    for seed in 0..seeds {
+        let mut rng = Lcg::new(0x5eed_0000 + seed);
+        let config = config(&mut rng);
+        let mut engine = engine_with(config.clone(), markets());
+        // Odd seeds publish state views, which debug builds check after every event.
+        let views = (seed % 2 == 1).then(|| engine.views());
+
+        let mut clock = 1_700_000_000_000;
+        let mut raw = Vec::new();
+        for i in 0..per_seed {
+            // A client retrying what was just rejected, for the rejection throttle.
+            let mut event = match raw.last() {
+                Some(last) if rng.chance(20) => EventType::clone(last),
+                _ => event(&mut rng),
+            };
+            // Most updates keep what a running engine cannot change, so they apply.
+            if let EventType::ConfigUpdated { config: next } = &mut event {
+                if rng.chance(80) {
+                    next.risk_deltas = engine.config().risk_deltas;
+                    next.rejection_throttle = engine.config().rejection_throttle.clone();
+                    next.trade_stats = engine.config().trade_stats.clone();
+                }
+            }
+            raw.push(event.clone());
+            clock = match rng.below(10) {
+                0 => clock - rng.below(120_000).min(clock),
+                1..=3 => clock + rng.below(120_000),
+                _ => clock + 1_000,
+            };
+            let submission = Submission {
+                idempotency_key: rng.chance(10).then(|| format!("key-{}", rng.below(40))),
+                timestamp: rng.chance(90).then_some(clock),
+            };
+            match engine.process_with(event, submission) {
+                ProcessOutcome::Accepted { .. } => accepted += 1,
+                ProcessOutcome::Rejected { .. } => rejected += 1,
+                ProcessOutcome::Suppressed { .. } => suppressed += 1,
+                ProcessOutcome::Duplicate { .. } => {}
+                ProcessOutcome::SinkFailed { .. } => unreachable!("no snapshot sink"),
+                ProcessOutcome::LogStoreFailed { .. } => unreachable!("no log store"),
+            }
+            if i % 256 == 0 {
+                engine.drain_risk_deltas();
+            }
+            let solvency = engine.solvency();
+            let tolerance =
+                dec!(0.000001).max(magnitude(&solvency) * dec!(0.000000000000000000000001));
+            assert!(
+                solvency.residual.abs() < tolerance,
+                "seed {seed}, event {i}: {solvency:?}"
+            );
+        }
+
+        engine.summarize_suppressed_rejections();
+        if let Some(views) = &views {
+            let differences = views.load().differences(&engine.state);
+            assert!(differences.is_empty(), "seed {seed}: {differences:?}");
+        }
+        let replayed = Engine::replay_verified(&engine.event_log, markets(), config.clone())
+            .unwrap_or_else(|e| panic!("seed {seed}: {e}"));
+        assert_eq!(replayed.state, engine.state, "seed {seed}");
+        // Resubmitting the external events writes the log again, unless a throttle
+        // kept submissions out of it.
+        if config.rejection_throttle.is_none() {
+            let regenerated =
+                Engine::regenerate(&engine.event_log, markets(), config.clone()).unwrap();
+            if let Some(divergence) = diff_logs(&engine.event_log, &regenerated) {
+                panic!("seed {seed}: {divergence}");
+            }
+        }
+        // Accounts and groups must agree on membership, or the state would not load.
+        State::from_json(&engine.state.to_json()).unwrap_or_else(|e| panic!("seed {seed}: {e}"));
+
+        // The raw events, numbered as a log, under both the run's config and the
+        // default: anything may be refused, nothing may panic.
+        let log: Vec<Event> = raw
+            .into_iter()
+            .enumerate()
+            .map(|(i, event_type)| Event::new(i as u64 + 1, event_type))
+            .collect();
+        for config in [config, EngineConfig::default()] {
+            let options = ReplayOptions {
+                config,
+                ..ReplayOptions::default()
+            };
+            Engine::replay_with(options, &log, markets());
+        }
+    }
+    println!(
+        "{seeds} seeds x {per_seed} events: {accepted} accepted, {rejected} rejected, \
+         {suppressed} suppressed, no panics"
+    );
+}
+
+/// Sequences the fuzzer found panicking or diverging from their replay, each reduced
+/// to a few events.
+#[test]
+fn regressions() {
+    let interest = InterestAccrual {
+        rate_per_interval: dec!(0.001),
+        credit_rate_per_interval: Decimal::ZERO,
+    };
+    let mut engine = Engine::builder().interest(interest).build();
+    for market in markets() {
+        engine.add_market(market).unwrap();
+    }
+    expect_rejected(
+        &mut engine,
+        EventType::MarkPriceUpdate {
+            market_id: "BTC-PERP".parse().unwrap(),
+            price: Decimal::MAX,
+        },
+        "MarkPrice",
+    );
+    // Overflowed multiplying by the mark in the import's margin check.
+    expect_rejected(
+        &mut engine,
+        EventType::StateImport {
+            account_id: "alice".parse().unwrap(),
+            pool_id: "default".into(),
+            collateral: dec!(1000),
+            positions: vec![ImportedPosition {
+                market_id: "ETH-PERP".parse().unwrap(),
+                quantity: Decimal::MAX,
+                cost_basis: dec!(3000),
+                last_funding: Decimal::ZERO,
+            }],
+        },
+        "StateImport",
+    );
+    // Overflowed dividing the cost basis by the quantity for the entry price.
+    expect_rejected(
+        &mut engine,
+        EventType::StateImport {
+            account_id: "alice".parse().unwrap(),
+            pool_id: "default".into(),
+            collateral: dec!(1000),
+            positions: vec![ImportedPosition {
+                market_id: "ETH-PERP".parse().unwrap(),
+                quantity: Decimal::new(1, 28),
+                cost_basis: dec!(3000),
+                last_funding: Decimal::ZERO,
+            }],
+        },
+        "StateImport",
+    );
+    // Had no rejection variant, so building its rejection was unreachable.
+    expect_rejected(
+        &mut engine,
+        EventType::Deposit {
+            account_id: "alice".parse().unwrap(),
+            amount: Decimal::MAX,
+        },
+        "InvalidEvent",
+    );
+    // Submitted engine records: a liquidation fill was applied as if the engine had
+    // written it, and a config marker broke replay of the log.
+    engine.process(EventType::Deposit {
+        account_id: "bob".parse().unwrap(),
+        amount: dec!(5000),
+    });
+    expect_rejected(
+        &mut engine,
+        EventType::LiquidationFill {
+            account_id: "bob".parse().unwrap(),
+            market_id: "ETH-PERP".parse().unwrap(),
+            quantity: dec!(1),
+            price: dec!(3000),
+        },
+        "InvalidEvent",
+    );
+    expect_rejected(
+        &mut engine,
+        EventType::ConfigMarker {
+            config_hash: String::new(),
+            config: EngineConfig::default(),
+        },
+        "InvalidEvent",
+    );
+    assert!(engine.state.accounts["bob"].positions.is_empty());
+
+    // A flat account charged into a negative balance was put into bankruptcy by the
+    // liquidation scan after the tick, with nothing in the log to say so.
+    engine.process(EventType::MarkPriceUpdate {
+        market_id: "ETH-PERP".parse().unwrap(),
+        price: dec!(3000),
+    });
+    for (quantity, price) in [(dec!(10), dec!(3000)), (dec!(-10), dec!(2400))] {
+        let outcome = engine.process(EventType::TradeFill {
+            account_id: "bob".parse().unwrap(),
+            market_id: "ETH-PERP".parse().unwrap(),
+            quantity,
+            price,
+            liquidity: None,
+        });
+        assert!(outcome.is_accepted(), "{outcome:?}");
+    }
+    assert!(engine
+        .process(EventType::InterestTick { interval_id: 1 })
+        .is_accepted());
+    let bob = &engine.state.accounts["bob"];
+    assert_eq!(bob.collateral(), dec!(-1001));
+    assert!(!bob.suspended && bob.bankruptcy_deficit.is_zero());
+
+    let replayed =
+        Engine::replay_verified(&engine.event_log, markets(), engine.config().clone()).unwrap();
+    assert_eq!(replayed.state, engine.state);
+}
+
+fn expect_rejected(engine: &mut Engine, event: EventType, kind: &str) {
+    let outcome = engine.process(event);
+    let ProcessOutcome::Rejected { reason, .. } = &outcome else {
+        panic!("{outcome:?}")
+    };
+    assert_eq!(reason.kind(), kind, "{reason}");
+}
+
+/// A log store whose writes fail panicked the engine mid-event; the failure is now an
+/// outcome, and the engine refuses everything after it.
+#[cfg(target_os = "linux")]
+#[test]
+fn log_store_failure() {
+    let mut engine = engine_with(EngineConfig::default(), markets());
+    let options = LogStoreOptions {
+        memory_capacity: 1,
+        flush: FlushPolicy::EveryN(1_000),
+    };
+    engine
+        .set_log_store(LogStore::create("/dev/full", options).unwrap())
+        .unwrap();
+    let mut rng = Lcg::new(0x5eed_f011);
+    let mut failed = None;
+    for _ in 0..64 {
+        match engine.process(event(&mut rng)) {
+            ProcessOutcome::LogStoreFailed { sequence, .. } => {
+                assert_eq!(*failed.get_or_insert(sequence), sequence);
+            }
+            outcome => assert!(failed.is_none(), "{outcome:?} after the failure"),
+        }
+    }
+    assert!(failed.is_some());
+}
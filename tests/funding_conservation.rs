@@ -0,0 +1,187 @@
+// Funding settlement conserves collateral. On a book whose longs and shorts balance,
+// every `FundingUpdate` and `FundingRate` must leave total collateral exactly where it
+// was, with fractional positions, indices and marks that do not divide evenly. On an
+// unbalanced book the total moves by the raw total rounded to collateral precision,
+// and by nothing else. Either way each account moves by its `FundingPayment`, at
+// collateral precision.
+//
+// `cargo test --test funding_conservation`; `FUNDING_SEEDS` overrides the number of
+// books.
+
+mod common;
+
+use common::{btc, deposit, engine_with, mark, process, trade};
+use cross_margin_engine::margin::{self, COLLATERAL_DECIMALS};
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::rng::Lcg;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::BTreeMap;
+
+trait Draws {
+    /// A non-zero value of up to `digits` digits either side of zero, at a scale of
+    /// `min_scale` and up to `scales - 1` more.
+    fn signed(&mut self, digits: u32, min_scale: u32, scales: u64) -> Decimal;
+}
+
+impl Draws for Lcg {
+    fn signed(&mut self, digits: u32, min_scale: u32, scales: u64) -> Decimal {
+        let bound = 10u64.pow(digits);
+        let value = self.below(2 * bound) as i64 - bound as i64;
+        let scale = min_scale + self.below(scales) as u32;
+        Decimal::new(if value == 0 { 1 } else { value }, scale)
+    }
+}
+
+const ACCOUNTS: [&str; 7] = ["alice", "bob", "carol", "dave", "erin", "frank", "gina"];
+
+fn seeds() -> u64 {
+    std::env::var("FUNDING_SEEDS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(200)
+}
+
+/// Accounts holding fractional positions at a fractional mark, funded far beyond
+/// anything the intervals below can charge, so nothing is ever liquidated. A balanced
+/// book gives the last account the opposite of the others' total.
+fn book(rng: &mut Lcg, balanced: bool) -> Engine {
+    let markets = vec![Market::new(btc(), dec!(0.01), dec!(0.005))];
+    let mut engine = engine_with(EngineConfig::default(), markets);
+    let price = Decimal::new(
+        5_000_000 + rng.below(1_000_000) as i64,
+        2 + rng.below(3) as u32,
+    );
+    process(&mut engine, mark("BTC-PERP", price));
+    let accounts = &ACCOUNTS[..2 + rng.below(ACCOUNTS.len() as u64 - 1) as usize];
+    let mut total = Decimal::ZERO;
+    for (i, account) in accounts.iter().enumerate() {
+        process(&mut engine, deposit(account, dec!(1000000000)));
+        let quantity = if balanced && i == accounts.len() - 1 {
+            -total
+        } else {
+            rng.signed(4, 1, 7)
+        };
+        total += quantity;
+        if !quantity.is_zero() {
+            process(&mut engine, trade(account, "BTC-PERP", quantity, price));
+        }
+    }
+    engine
+}
+
+fn total_collateral(engine: &Engine) -> Decimal {
+    engine.state.accounts.values().map(|a| a.collateral()).sum()
+}
+
+/// Apply one funding event, a `FundingRate` or a `FundingUpdate`, and check it against
+/// the raw deltas. Returns the total collateral change.
+fn settle(engine: &mut Engine, rng: &mut Lcg, interval_id: u64) -> Decimal {
+    let before: BTreeMap<AccountId, Decimal> = engine
+        .state
+        .accounts
+        .iter()
+        .map(|(id, a)| (id.clone(), a.collateral()))
+        .collect();
+    let positions: Vec<(AccountId, Decimal)> = engine
+        .state
+        .accounts
+        .iter()
+        .filter_map(|(id, a)| Some((id.clone(), a.positions.get("BTC-PERP")?.quantity)))
+        .collect();
+    let last_index = engine.state.markets["BTC-PERP"].cumulative_funding_index;
+    let logged = engine.event_log.len();
+    let total = total_collateral(engine);
+
+    let event_type = if rng.below(2) == 0 {
+        EventType::FundingRate {
+            market_id: btc(),
+            rate: rng.signed(5, 6, 5),
+            interval_id,
+        }
+    } else {
+        EventType::FundingUpdate {
+            market_id: btc(),
+            new_cumulative_index: last_index + rng.signed(4, 2, 11),
+        }
+    };
+    process(engine, event_type);
+
+    let new_index = engine.state.markets["BTC-PERP"].cumulative_funding_index;
+    let raw: Decimal = positions
+        .iter()
+        .map(|(_, quantity)| margin::funding_delta(*quantity, last_index, new_index))
+        .sum();
+    let change = total_collateral(engine) - total;
+    assert_eq!(change, raw.round_dp(COLLATERAL_DECIMALS));
+
+    let payments: BTreeMap<AccountId, Decimal> = engine.event_log[logged..]
+        .iter()
+        .filter_map(|e| match &e.event_type {
+            EventType::FundingPayment {
+                account_id, amount, ..
+            } => Some((account_id.clone(), *amount)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(payments.values().sum::<Decimal>(), change);
+    for (account_id, collateral) in before {
+        let moved = engine.state.accounts[&account_id].collateral() - collateral;
+        let paid = payments.get(&account_id).copied().unwrap_or_default();
+        assert_eq!(moved, paid, "{account_id}");
+        assert_eq!(paid, paid.round_dp(COLLATERAL_DECIMALS), "{account_id}");
+    }
+    change
+}
+
+#[test]
+fn balanced_book_conserves_collateral() {
+    for seed in 0..seeds() {
+        let mut rng = Lcg::new(seed);
+        let mut engine = book(&mut rng, true);
+        for interval_id in 1..=20 {
+            // Between intervals the mark moves, and two holders trade with each other,
+            // which keeps the book balanced.
+            if rng.below(3) == 0 {
+                let price = engine.state.markets["BTC-PERP"].mark_price + rng.signed(4, 2, 1);
+                process(&mut engine, mark("BTC-PERP", price));
+            }
+            if rng.below(3) == 0 {
+                let accounts: Vec<String> = engine
+                    .state
+                    .accounts
+                    .keys()
+                    .map(|a| a.to_string())
+                    .collect();
+                let buyer = &accounts[rng.below(accounts.len() as u64) as usize];
+                let seller = &accounts[rng.below(accounts.len() as u64) as usize];
+                if buyer != seller {
+                    let quantity = rng.signed(4, 1, 5).abs();
+                    let price = engine.state.markets["BTC-PERP"].mark_price;
+                    process(&mut engine, trade(buyer, "BTC-PERP", quantity, price));
+                    process(&mut engine, trade(seller, "BTC-PERP", -quantity, price));
+                }
+            }
+            let change = settle(&mut engine, &mut rng, interval_id);
+            assert!(change.is_zero(), "seed {seed} interval {interval_id}");
+        }
+        assert!(engine.solvency().is_balanced(), "seed {seed}");
+    }
+}
+
+#[test]
+fn unbalanced_book_moves_by_the_rounded_total() {
+    let mut moved = 0;
+    for seed in 0..seeds() {
+        let mut rng = Lcg::new(seed);
+        let mut engine = book(&mut rng, false);
+        for interval_id in 1..=5 {
+            if !settle(&mut engine, &mut rng, interval_id).is_zero() {
+                moved += 1;
+            }
+        }
+        assert!(engine.solvency().is_balanced(), "seed {seed}");
+    }
+    // The check above is only worth something if funding did move collateral.
+    assert!(moved > 0);
+}
@@ -0,0 +1,123 @@
+// Funding paid, per position and per account, across a position's life: open, funding,
+// partial close, funding, full close, funding while flat, reopen, funding. The
+// position's total carries through the partial close and starts again from zero when
+// the position is reopened; the account's per-market total carries across all of it.
+// Snapshots report both after every event, and replay reproduces them exactly.
+
+mod common;
+
+use common::{btc, btc_market, deposit, engine_with, mark, process, trade};
+use cross_margin_engine::prelude::*;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+fn funding(index: Decimal) -> EventType {
+    EventType::FundingUpdate {
+        market_id: btc(),
+        new_cumulative_index: index,
+    }
+}
+
+/// alice long 10 BTC at 50,000 against bob's short, at a funding index of zero.
+fn engine() -> Engine {
+    let mut engine = engine_with(EngineConfig::default(), vec![btc_market()]);
+    for event_type in [
+        mark("BTC-PERP", dec!(50000)),
+        deposit("alice", dec!(100000)),
+        deposit("bob", dec!(100000)),
+        trade("alice", "BTC-PERP", dec!(10), dec!(50000)),
+        trade("bob", "BTC-PERP", dec!(-10), dec!(50000)),
+    ] {
+        process(&mut engine, event_type);
+    }
+    engine
+}
+
+/// `account`'s funding paid on its BTC position, if it has one, and on BTC-PERP over
+/// its lifetime, checked against the latest snapshot.
+fn paid(engine: &Engine, account: &str) -> (Option<Decimal>, Decimal) {
+    let state = &engine.state.accounts[account];
+    let position = state.positions.get("BTC-PERP").map(|p| p.funding_paid);
+    let lifetime = state
+        .funding_paid
+        .get("BTC-PERP")
+        .copied()
+        .unwrap_or_default();
+
+    let snapshot = &engine.snapshots.last().unwrap().accounts[account];
+    assert_eq!(
+        snapshot.positions.get(&btc()).map(|p| p.funding_paid),
+        position
+    );
+    assert_eq!(
+        snapshot
+            .funding_paid
+            .get(&btc())
+            .copied()
+            .unwrap_or_default(),
+        lifetime
+    );
+    (position, lifetime)
+}
+
+#[test]
+fn funding_paid_over_a_position_lifecycle() {
+    let mut engine = engine();
+    assert_eq!(paid(&engine, "alice"), (Some(dec!(0)), dec!(0)));
+
+    // 10 long pays 10 a unit.
+    process(&mut engine, funding(dec!(10)));
+    assert_eq!(paid(&engine, "alice"), (Some(dec!(100)), dec!(100)));
+    assert_eq!(paid(&engine, "bob"), (Some(dec!(-100)), dec!(-100)));
+
+    // A partial close keeps what the position has paid so far.
+    process(
+        &mut engine,
+        trade("alice", "BTC-PERP", dec!(-4), dec!(50000)),
+    );
+    process(&mut engine, trade("bob", "BTC-PERP", dec!(4), dec!(50000)));
+    assert_eq!(paid(&engine, "alice"), (Some(dec!(100)), dec!(100)));
+
+    // The 6 left pay 5 a unit.
+    process(&mut engine, funding(dec!(15)));
+    assert_eq!(paid(&engine, "alice"), (Some(dec!(130)), dec!(130)));
+    assert_eq!(paid(&engine, "bob"), (Some(dec!(-130)), dec!(-130)));
+
+    // Closing drops the position; the account keeps its history.
+    process(
+        &mut engine,
+        trade("alice", "BTC-PERP", dec!(-6), dec!(50000)),
+    );
+    process(&mut engine, trade("bob", "BTC-PERP", dec!(6), dec!(50000)));
+    assert_eq!(paid(&engine, "alice"), (None, dec!(130)));
+
+    // Flat, nothing is paid.
+    process(&mut engine, funding(dec!(20)));
+    assert_eq!(paid(&engine, "alice"), (None, dec!(130)));
+
+    // A reopened position starts from zero, and pays only from the index it opened at.
+    process(
+        &mut engine,
+        trade("alice", "BTC-PERP", dec!(2), dec!(50000)),
+    );
+    process(&mut engine, trade("bob", "BTC-PERP", dec!(-2), dec!(50000)));
+    assert_eq!(paid(&engine, "alice"), (Some(dec!(0)), dec!(130)));
+    process(&mut engine, funding(dec!(25)));
+    assert_eq!(paid(&engine, "alice"), (Some(dec!(10)), dec!(140)));
+    assert_eq!(paid(&engine, "bob"), (Some(dec!(-10)), dec!(-140)));
+
+    // Collateral moved by exactly what was paid.
+    assert_eq!(
+        engine.state.accounts["alice"].collateral(),
+        dec!(100000) - dec!(140)
+    );
+
+    let replayed = Engine::replay_verified(
+        &engine.event_log,
+        vec![btc_market()],
+        EngineConfig::default(),
+    )
+    .unwrap();
+    assert_eq!(replayed.state, engine.state);
+    assert_eq!(replayed.snapshots, engine.snapshots);
+}
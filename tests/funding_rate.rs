@@ -0,0 +1,174 @@
+// A `FundingRate` feed and the `FundingUpdate` feed its integrator would have had to
+// compute, run side by side: the same marks, trades and funding, one quoted as rates
+// per interval and one as the cumulative index, end in the same state.
+
+mod common;
+
+use common::{btc_market, deposit, engine_with, eth_market, market, process};
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::types::FundingRateFormula;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+/// BTC-PERP quotes rates as a fraction of notional, ETH-PERP per contract.
+fn markets() -> Vec<Market> {
+    let mut eth = eth_market();
+    eth.funding_rate_formula = FundingRateFormula::RateIsIndexDelta;
+    vec![btc_market(), eth]
+}
+
+enum Step {
+    Event(Box<EventType>),
+    /// One funding interval for a market, at a rate.
+    Funding(&'static str, Decimal, u64),
+}
+
+fn mark(market_id: &str, price: Decimal) -> Step {
+    Step::Event(Box::new(common::mark(market_id, price)))
+}
+
+fn trade(account: &str, market_id: &str, quantity: Decimal, price: Decimal) -> Step {
+    Step::Event(Box::new(common::trade(account, market_id, quantity, price)))
+}
+
+/// Marks that move between intervals, trades that open, flip and close between them,
+/// and rates of both signs.
+fn steps() -> Vec<Step> {
+    let mut steps = vec![mark("BTC-PERP", dec!(50000)), mark("ETH-PERP", dec!(3000))];
+    for account in ["alice", "bob", "carol"] {
+        steps.push(Step::Event(Box::new(deposit(account, dec!(100000)))));
+    }
+    steps.extend([
+        trade("alice", "BTC-PERP", dec!(2), dec!(50000)),
+        trade("bob", "BTC-PERP", dec!(-2), dec!(50000)),
+        trade("alice", "ETH-PERP", dec!(-10), dec!(3000)),
+        trade("carol", "ETH-PERP", dec!(10), dec!(3000)),
+        Step::Funding("BTC-PERP", dec!(0.0001), 1),
+        Step::Funding("ETH-PERP", dec!(0.3), 1),
+        mark("BTC-PERP", dec!(52000)),
+        trade("alice", "BTC-PERP", dec!(-3), dec!(52000)),
+        trade("carol", "BTC-PERP", dec!(3), dec!(52000)),
+        Step::Funding("BTC-PERP", dec!(-0.00025), 2),
+        mark("ETH-PERP", dec!(2900)),
+        Step::Funding("ETH-PERP", dec!(-0.45), 2),
+        trade("bob", "BTC-PERP", dec!(2), dec!(52000)),
+        mark("BTC-PERP", dec!(49000)),
+        Step::Funding("BTC-PERP", dec!(0.0003), 3),
+        Step::Funding("ETH-PERP", dec!(0.1), 3),
+    ]);
+    steps
+}
+
+/// The steps with funding sent as `FundingRate`.
+fn by_rate() -> Engine {
+    let mut engine = engine_with(EngineConfig::default(), markets());
+    for step in steps() {
+        let event_type = match step {
+            Step::Event(event_type) => *event_type,
+            Step::Funding(market_id, rate, interval_id) => EventType::FundingRate {
+                market_id: market(market_id),
+                rate,
+                interval_id,
+            },
+        };
+        process(&mut engine, event_type);
+    }
+    engine
+}
+
+/// The steps with funding sent as `FundingUpdate`, the index kept here the way an
+/// integrator would: each rate converted at the market's formula and current mark.
+fn by_index() -> Engine {
+    let mut engine = engine_with(EngineConfig::default(), markets());
+    let mut indices = [Decimal::ZERO; 2];
+    for step in steps() {
+        let event_type = match step {
+            Step::Event(event_type) => *event_type,
+            Step::Funding(market_id, rate, _) => {
+                let (index, increment) = match market_id {
+                    "BTC-PERP" => (
+                        &mut indices[0],
+                        rate * engine.state.markets[market_id].mark_price,
+                    ),
+                    _ => (&mut indices[1], rate),
+                };
+                *index += increment;
+                EventType::FundingUpdate {
+                    market_id: market(market_id),
+                    new_cumulative_index: *index,
+                }
+            }
+        };
+        process(&mut engine, event_type);
+    }
+    engine
+}
+
+#[test]
+fn rate_and_index_streams_end_in_identical_state() {
+    let (rate, index) = (by_rate(), by_index());
+    assert_eq!(rate.state.accounts, index.state.accounts);
+    for market_id in ["BTC-PERP", "ETH-PERP"] {
+        let (by_rate, by_index) = (
+            &rate.state.markets[market_id],
+            &index.state.markets[market_id],
+        );
+        assert_eq!(
+            by_rate.cumulative_funding_index,
+            by_index.cumulative_funding_index
+        );
+        assert_eq!(by_rate.settled_funding_intervals.len(), 3);
+        assert!(by_index.settled_funding_intervals.is_empty());
+    }
+    assert_eq!(rate.metrics(), index.metrics());
+    assert!(rate.solvency().is_balanced());
+}
+
+#[test]
+fn payments_match_interval_by_interval() {
+    let payments = |engine: &Engine| -> Vec<EventType> {
+        engine
+            .event_log
+            .iter()
+            .filter(|e| matches!(e.event_type, EventType::FundingPayment { .. }))
+            .map(|e| e.event_type.clone())
+            .collect()
+    };
+    let (rate, index) = (by_rate(), by_index());
+    assert!(!payments(&rate).is_empty());
+    assert_eq!(payments(&rate), payments(&index));
+}
+
+#[test]
+fn duplicate_interval_changes_nothing() {
+    let mut engine = by_rate();
+    let state = engine.state.clone();
+    let outcome = engine.process(EventType::FundingRate {
+        market_id: market("BTC-PERP"),
+        rate: dec!(0.0003),
+        interval_id: 3,
+    });
+    assert!(!outcome.is_accepted());
+    assert_eq!(engine.state.accounts, state.accounts);
+    assert_eq!(engine.state.markets, state.markets);
+    // The same rate under a new interval is settled, and matches a further index step.
+    let mut index = by_index();
+    process(
+        &mut engine,
+        EventType::FundingRate {
+            market_id: market("BTC-PERP"),
+            rate: dec!(0.0003),
+            interval_id: 4,
+        },
+    );
+    let next =
+        index.state.markets["BTC-PERP"].cumulative_funding_index + dec!(0.0003) * dec!(49000);
+    process(
+        &mut index,
+        EventType::FundingUpdate {
+            market_id: market("BTC-PERP"),
+            new_cumulative_index: next,
+        },
+    );
+    assert_eq!(engine.state.accounts, index.state.accounts);
+}
@@ -0,0 +1,121 @@
+// The funding sign convention, through `margin::funding_delta` and through the engine:
+// a rising index charges longs and pays shorts, a falling one the reverse, an index
+// that does not move changes nothing, and an account holding both sides nets them.
+
+mod common;
+
+use common::{btc_market, deposit, engine_with, eth_market, mark, market, process, trade};
+use cross_margin_engine::margin;
+use cross_margin_engine::prelude::*;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+/// alice holds `btc` BTC and `eth` ETH against bob's opposite positions, each market
+/// at a funding index of zero.
+fn engine(btc: Decimal, eth: Decimal) -> Engine {
+    let mut engine = engine_with(EngineConfig::default(), vec![btc_market(), eth_market()]);
+    let mut events = vec![mark("BTC-PERP", dec!(50000)), mark("ETH-PERP", dec!(3000))];
+    for account in ["alice", "bob"] {
+        events.push(deposit(account, dec!(100000)));
+    }
+    for (market_id, quantity, price) in [
+        ("BTC-PERP", btc, dec!(50000)),
+        ("ETH-PERP", eth, dec!(3000)),
+    ] {
+        if quantity.is_zero() {
+            continue;
+        }
+        for (account, quantity) in [("alice", quantity), ("bob", -quantity)] {
+            events.push(trade(account, market_id, quantity, price));
+        }
+    }
+    for event_type in events {
+        process(&mut engine, event_type);
+    }
+    engine
+}
+
+/// alice's and bob's collateral changes when `market_id`'s index moves to `new_index`.
+fn settle(engine: &mut Engine, market_id: &str, new_index: Decimal) -> (Decimal, Decimal) {
+    let before = |engine: &Engine, account: &str| engine.state.accounts[account].collateral();
+    let (alice, bob) = (before(engine, "alice"), before(engine, "bob"));
+    assert!(engine
+        .process(EventType::FundingUpdate {
+            market_id: market(market_id),
+            new_cumulative_index: new_index,
+        })
+        .is_accepted());
+    (before(engine, "alice") - alice, before(engine, "bob") - bob)
+}
+
+#[test]
+fn long_pays_on_rising_index() {
+    assert_eq!(margin::funding_delta(dec!(2), dec!(0), dec!(10)), dec!(-20));
+    let mut engine = engine(dec!(2), Decimal::ZERO);
+    assert_eq!(settle(&mut engine, "BTC-PERP", dec!(10)).0, dec!(-20));
+}
+
+#[test]
+fn short_receives_on_rising_index() {
+    assert_eq!(margin::funding_delta(dec!(-2), dec!(0), dec!(10)), dec!(20));
+    let mut engine = engine(dec!(-2), Decimal::ZERO);
+    assert_eq!(
+        settle(&mut engine, "BTC-PERP", dec!(10)),
+        (dec!(20), dec!(-20))
+    );
+}
+
+#[test]
+fn long_receives_on_falling_index() {
+    assert_eq!(margin::funding_delta(dec!(2), dec!(10), dec!(4)), dec!(12));
+    let mut engine = engine(dec!(2), Decimal::ZERO);
+    settle(&mut engine, "BTC-PERP", dec!(10));
+    assert_eq!(
+        settle(&mut engine, "BTC-PERP", dec!(4)),
+        (dec!(12), dec!(-12))
+    );
+}
+
+#[test]
+fn short_pays_on_falling_index() {
+    assert_eq!(
+        margin::funding_delta(dec!(-2), dec!(0), dec!(-5)),
+        dec!(-10)
+    );
+    let mut engine = engine(dec!(-2), Decimal::ZERO);
+    assert_eq!(
+        settle(&mut engine, "BTC-PERP", dec!(-5)),
+        (dec!(-10), dec!(10))
+    );
+}
+
+#[test]
+fn unchanged_index_is_a_no_op() {
+    assert!(margin::funding_delta(dec!(2), dec!(7), dec!(7)).is_zero());
+    assert!(margin::funding_delta(Decimal::ZERO, dec!(0), dec!(7)).is_zero());
+    let mut engine = engine(dec!(2), dec!(-3));
+    settle(&mut engine, "BTC-PERP", dec!(7));
+    let state = engine.state.clone();
+    assert_eq!(
+        settle(&mut engine, "BTC-PERP", dec!(7)),
+        (Decimal::ZERO, Decimal::ZERO)
+    );
+    assert_eq!(engine.state.accounts, state.accounts);
+}
+
+#[test]
+fn mixed_portfolio_nets_across_markets() {
+    // Long 2 BTC and short 3 ETH, with both indices rising: the BTC long pays 20
+    // and the ETH short receives 15.
+    let mut engine = engine(dec!(2), dec!(-3));
+    let btc = settle(&mut engine, "BTC-PERP", dec!(10));
+    let eth = settle(&mut engine, "ETH-PERP", dec!(5));
+    assert_eq!(btc, (dec!(-20), dec!(20)));
+    assert_eq!(eth, (dec!(15), dec!(-15)));
+    assert_eq!(
+        btc.0 + eth.0,
+        margin::funding_delta(dec!(2), dec!(0), dec!(10))
+            + margin::funding_delta(dec!(-3), dec!(0), dec!(5))
+    );
+    assert_eq!(btc.0 + eth.0 + btc.1 + eth.1, Decimal::ZERO);
+}
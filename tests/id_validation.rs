@@ -0,0 +1,134 @@
+// Account and market ids are validated wherever they enter: built in code, read
+// from a JSONL log, sent as a JSON command, or written in a scenario step. Ids of
+// up to 64 ASCII letters, digits, '-', '_', '.' and ':' are accepted and serialize
+// as bare strings; an empty, overlong or otherwise spelled id is refused with a
+// typed `IdError`, which a log read reports against the line that carried it.
+
+mod common;
+
+use common::{btc_market, deposit, engine_with, id, process};
+use cross_margin_engine::command::{CommandErrorKind, Response};
+use cross_margin_engine::jsonl;
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::scenario;
+use cross_margin_engine::types::MAX_ID_LEN;
+use rust_decimal_macros::dec;
+
+/// An engine with deposits for alice and bob.
+fn engine() -> Engine {
+    let mut engine = engine_with(EngineConfig::default(), vec![btc_market()]);
+    process(&mut engine, deposit("alice", dec!(1000)));
+    process(&mut engine, deposit("bob", dec!(1000)));
+    engine
+}
+
+#[test]
+fn construction_accepts_valid_ids_and_types_each_refusal() {
+    for text in [
+        "alice",
+        "acct-007",
+        "desk_3.sub:a",
+        "BTC-PERP",
+        &"x".repeat(MAX_ID_LEN),
+    ] {
+        let account_id = AccountId::try_from(text).unwrap();
+        assert_eq!(account_id, text);
+        assert_eq!(account_id.to_string(), text);
+    }
+    assert_eq!(
+        AccountId::try_from(""),
+        Err(IdError::Empty { kind: "account" })
+    );
+    let long = "x".repeat(MAX_ID_LEN + 1);
+    assert_eq!(
+        MarketId::try_from(long),
+        Err(IdError::TooLong {
+            kind: "market",
+            len: MAX_ID_LEN + 1
+        })
+    );
+    for (text, found) in [
+        ("al ice", ' '),
+        ("bob\n", '\n'),
+        ("café", 'é'),
+        ("a/b", '/'),
+        ("\"q\"", '"'),
+    ] {
+        let err = text.parse::<AccountId>().unwrap_err();
+        assert_eq!(
+            err,
+            IdError::InvalidChar {
+                kind: "account",
+                id: text.to_string(),
+                found
+            }
+        );
+    }
+}
+
+#[test]
+fn an_id_serializes_as_the_bare_string() {
+    let alice = id("alice");
+    assert_eq!(serde_json::to_string(&alice).unwrap(), "\"alice\"");
+    assert_eq!(
+        serde_json::from_str::<AccountId>("\"alice\"").unwrap(),
+        alice
+    );
+    let err = serde_json::from_str::<MarketId>("\"\"").unwrap_err();
+    assert_eq!(err.to_string(), "market id is empty");
+}
+
+#[test]
+fn a_bad_id_in_a_log_fails_the_line_it_is_on() {
+    let engine = engine();
+    let lines: Vec<String> = engine
+        .event_log
+        .iter()
+        .map(|e| serde_json::to_string(e).unwrap())
+        .collect();
+    assert_eq!(
+        jsonl::parse_jsonl(&lines.join("\n")).unwrap(),
+        engine.event_log
+    );
+    let tampered = lines.join("\n").replace("\"bob\"", "\"bob smith\"");
+    let Err(EngineError::Parse { line, source }) = jsonl::parse_jsonl(&tampered) else {
+        panic!("a log with a bad account id must not parse");
+    };
+    assert_eq!(
+        line,
+        lines.iter().position(|l| l.contains("\"bob\"")).unwrap() + 1
+    );
+    assert!(
+        source
+            .to_string()
+            .contains("account id \"bob smith\" contains ' '"),
+        "{source}"
+    );
+}
+
+#[test]
+fn a_json_command_with_a_bad_id_is_a_parse_error_and_logs_nothing() {
+    let mut engine = engine();
+    let before = engine.event_log.len();
+    for command in [
+        r#"{"command":"Process","event":{"type":"Deposit","account_id":"","amount":"5"}}"#,
+        r#"{"command":"GetAccount","account_id":"ali ce"}"#,
+        r#"{"command":"GetMarket","market_id":"BTC/PERP"}"#,
+    ] {
+        let response: Response = serde_json::from_str(&engine.handle(command)).unwrap();
+        let Response::Error { kind, message } = response else {
+            panic!("{command} must be refused");
+        };
+        assert_eq!(kind, CommandErrorKind::Parse);
+        assert!(message.contains(" id "), "{message}");
+    }
+    assert_eq!(engine.event_log.len(), before);
+}
+
+#[test]
+fn a_scenario_step_with_a_bad_id_is_refused() {
+    let err = scenario::parse_step("deposit ali/ce 100").unwrap_err();
+    assert!(err.contains("account id \"ali/ce\" contains '/'"), "{err}");
+    let err = scenario::parse_step("marks BTC_PERP 1 ETH%PERP 2").unwrap_err();
+    assert!(err.contains("market id \"ETH%PERP\" contains '%'"), "{err}");
+}
@@ -0,0 +1,142 @@
+// Idempotency keys. A submission whose key is still in the window is logged only as a
+// `DuplicateIgnored` naming the sequence that first carried the key, and changes
+// nothing, even when that first event was rejected. Once the key has been evicted,
+// oldest first, the same submission applies again. Replay and regeneration rebuild the
+// window from the log and make every one of those decisions the same way.
+
+mod common;
+
+use common::{deposit, engine_with, id};
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::regenerate::diff_logs;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+fn config() -> EngineConfig {
+    EngineConfig {
+        idempotency_window: 2,
+        ..EngineConfig::default()
+    }
+}
+
+fn submit(engine: &mut Engine, event_type: EventType, key: &str) -> ProcessOutcome {
+    engine.process_with(
+        event_type,
+        Submission {
+            idempotency_key: Some(key.into()),
+            ..Submission::default()
+        },
+    )
+}
+
+fn withdraw(account: &str, amount: Decimal) -> EventType {
+    EventType::Withdraw {
+        account_id: id(account),
+        amount,
+    }
+}
+
+fn accepted(outcome: ProcessOutcome) -> u64 {
+    match outcome {
+        ProcessOutcome::Accepted { sequence } => sequence,
+        other => panic!("{other:?}"),
+    }
+}
+
+/// The sequence a duplicate was logged at, after checking it names `original`.
+fn duplicate_of(engine: &Engine, outcome: ProcessOutcome, original: u64) -> u64 {
+    let ProcessOutcome::Duplicate {
+        sequence,
+        original_sequence,
+    } = outcome
+    else {
+        panic!("{outcome:?}");
+    };
+    assert_eq!(original_sequence, original);
+    let logged = engine.event_log.last().unwrap();
+    assert_eq!(logged.sequence, sequence);
+    assert!(matches!(
+        &logged.event_type,
+        EventType::DuplicateIgnored { original_sequence, .. } if *original_sequence == original
+    ));
+    sequence
+}
+
+fn collateral(engine: &Engine) -> Decimal {
+    engine.state.accounts["alice"].collateral()
+}
+
+#[test]
+fn duplicate_names_the_original_sequence() {
+    let mut engine = engine_with(config(), vec![]);
+    let original = accepted(submit(&mut engine, deposit("alice", dec!(100)), "dep-1"));
+    let state = engine.state.clone();
+
+    let outcome = submit(&mut engine, deposit("alice", dec!(100)), "dep-1");
+    duplicate_of(&engine, outcome, original);
+    assert_eq!(collateral(&engine), dec!(100));
+    // The key decides, not the event it comes with.
+    let outcome = submit(&mut engine, deposit("alice", dec!(5)), "dep-1");
+    duplicate_of(&engine, outcome, original);
+    assert_eq!(engine.state, state);
+}
+
+#[test]
+fn key_is_accepted_again_after_eviction() {
+    let mut engine = engine_with(config(), vec![]);
+    accepted(submit(&mut engine, deposit("alice", dec!(100)), "a"));
+    let b = accepted(submit(&mut engine, deposit("alice", dec!(10)), "b"));
+    let c = accepted(submit(&mut engine, deposit("alice", dec!(1)), "c"));
+    // A window of two keeps the two latest keys.
+    assert_eq!(engine.state.idempotency.get("a"), None);
+    assert_eq!(engine.state.idempotency.get("b"), Some(b));
+
+    let outcome = submit(&mut engine, deposit("alice", dec!(1)), "c");
+    duplicate_of(&engine, outcome, c);
+    let again = accepted(submit(&mut engine, deposit("alice", dec!(100)), "a"));
+    assert_eq!(collateral(&engine), dec!(211));
+    assert_eq!(engine.state.idempotency.get("a"), Some(again));
+    assert_eq!(engine.state.idempotency.get("b"), None);
+}
+
+#[test]
+fn replay_makes_the_same_duplicate_decisions() {
+    let mut engine = engine_with(config(), vec![]);
+    accepted(submit(&mut engine, deposit("alice", dec!(100)), "dep-1"));
+    // A rejected submission uses its key: the retry is still a retry.
+    let rejected = match submit(&mut engine, withdraw("alice", dec!(500)), "wd-1") {
+        ProcessOutcome::Rejected { sequence, .. } => sequence,
+        other => panic!("{other:?}"),
+    };
+    let outcome = submit(&mut engine, withdraw("alice", dec!(50)), "wd-1");
+    duplicate_of(&engine, outcome, rejected);
+    assert_eq!(collateral(&engine), dec!(100));
+    // dep-1 is evicted by the next key and applies again.
+    accepted(submit(&mut engine, deposit("bob", dec!(1)), "dep-2"));
+    accepted(submit(&mut engine, deposit("alice", dec!(100)), "dep-1"));
+    assert_eq!(collateral(&engine), dec!(200));
+
+    let replayed = Engine::replay_verified(&engine.event_log, vec![], config()).unwrap();
+    assert_eq!(replayed.state, engine.state);
+    assert_eq!(replayed.state.idempotency, engine.state.idempotency);
+    let regenerated = Engine::regenerate(&engine.event_log, vec![], config()).unwrap();
+    assert_eq!(diff_logs(&engine.event_log, &regenerated), None);
+
+    // A duplicate of a key that was not in use at that point does not replay.
+    let mut log: Vec<Event> = engine
+        .event_log
+        .iter()
+        .map(|e| e.as_ref().clone())
+        .collect();
+    let duplicate = log
+        .iter_mut()
+        .find(|e| matches!(e.event_type, EventType::DuplicateIgnored { .. }))
+        .unwrap();
+    if let EventType::DuplicateIgnored {
+        original_sequence, ..
+    } = &mut duplicate.event_type
+    {
+        *original_sequence += 1;
+    }
+    assert!(Engine::replay_verified(&log, vec![], config()).is_err());
+}
@@ -0,0 +1,100 @@
+// The planner and the engine agree on the demo's Scenario 1: alice, long 10 BTC from
+// 50,000, is liquidated at a mark of 41,000. Planned from the state just before that
+// mark, with the mark moved, the closes are the fills the demo log records for it, and
+// the projections are the state the engine left.
+
+mod common;
+
+use common::engine_with;
+use cross_margin_engine::demo;
+use cross_margin_engine::liquidation;
+use cross_margin_engine::margin;
+use cross_margin_engine::prelude::*;
+use rust_decimal_macros::dec;
+
+#[test]
+fn demo_scenario_1_fills_equal_the_plan() {
+    let log = demo::engine().event_log;
+    let alice: AccountId = "alice".parse().unwrap();
+    let btc: MarketId = "BTC-PERP".parse().unwrap();
+    let crash = log
+        .iter()
+        .find(|e| {
+            e.event_type
+                == EventType::MarkPriceUpdate {
+                    market_id: btc.clone(),
+                    price: dec!(41000),
+                }
+        })
+        .unwrap();
+
+    // The engine as it stood before the mark, and the state the mark alone leaves.
+    let mut engine = engine_with(EngineConfig::default(), demo::markets());
+    for event in log
+        .iter()
+        .take_while(|e| e.sequence < crash.sequence)
+        .filter(|e| e.caused_by.is_none() && !e.event_type.is_engine_generated())
+    {
+        engine.process(event.event_type.clone());
+    }
+    let mut state = engine.state.clone();
+    state.markets.get_mut(&btc).unwrap().mark_price = dec!(41000);
+
+    let plans = liquidation::plan_all(&state);
+    assert_eq!(plans.len(), 1);
+    let plan = &plans[0];
+    assert_eq!(plan.account_id, alice);
+    assert_eq!(liquidation::plan(&state, &alice).as_ref(), Some(plan));
+    assert!(!plan.exhausted && plan.deferred.is_empty(), "{plan:?}");
+
+    // One whole close at mark, leaving the 10,000 the account had over its loss.
+    let recorded: Vec<_> = log
+        .iter()
+        .filter(|e| e.caused_by == Some(crash.sequence))
+        .map(|e| e.event_type.clone())
+        .collect();
+    let planned: Vec<_> = plan
+        .steps
+        .iter()
+        .map(|step| EventType::LiquidationFill {
+            account_id: alice.clone(),
+            market_id: step.market_id.clone(),
+            quantity: step.close_quantity,
+            price: step.price,
+        })
+        .collect();
+    assert_eq!(recorded, planned);
+    assert_eq!(
+        planned,
+        [EventType::LiquidationFill {
+            account_id: alice.clone(),
+            market_id: btc.clone(),
+            quantity: dec!(-10),
+            price: dec!(41000),
+        }]
+    );
+
+    // The engine executes the same plan live, and ends where the plan projected.
+    let sequence = engine.next_sequence();
+    assert!(engine.process(crash.event_type.clone()).is_accepted());
+    let executed: Vec<_> = engine
+        .event_log
+        .iter()
+        .filter(|e| e.caused_by == Some(sequence))
+        .map(|e| e.event_type.clone())
+        .collect();
+    assert_eq!(executed, planned);
+    let step = plan.steps.last().unwrap();
+    let account = &engine.state.accounts[&alice];
+    assert_eq!(step.projected_collateral, account.collateral());
+    assert_eq!(step.projected_collateral, dec!(10000));
+    assert_eq!(
+        step.projected_equity,
+        margin::equity(account, &engine.state)
+    );
+    assert_eq!(
+        step.projected_maintenance_margin,
+        margin::maintenance_margin_required(account, &engine.state)
+    );
+    assert!(liquidation::plan_all(&engine.state).is_empty());
+}
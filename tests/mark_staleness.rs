@@ -0,0 +1,161 @@
+// A market goes stale on the log clock alone: timestamps move past its staleness
+// threshold while no mark arrives. New risk there is then refused, IM carries the
+// stale multiplier and snapshots flag the position; a fresh mark undoes all three, and
+// replay agrees at every step.
+
+mod common;
+
+use common::{btc_market, deposit, engine_with, eth_market, id, mark, market, trade};
+use cross_margin_engine::margin;
+use cross_margin_engine::prelude::*;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+/// BTC-PERP goes stale a minute after its last mark and then charges double IM.
+/// ETH-PERP has no threshold.
+fn markets() -> Vec<Market> {
+    let mut btc = btc_market();
+    btc.staleness_threshold_ms = Some(60_000);
+    btc.stale_im_multiplier = dec!(2);
+    vec![btc, eth_market()]
+}
+
+/// alice long 1 BTC and bob long 10 ETH, both marked at 0 ms.
+fn engine() -> Engine {
+    let mut engine = engine_with(EngineConfig::default(), markets());
+    for (at, event_type) in [
+        (0, mark("BTC-PERP", dec!(50000))),
+        (0, mark("ETH-PERP", dec!(3000))),
+        (0, deposit("alice", dec!(10000))),
+        (0, deposit("bob", dec!(10000))),
+        (1_000, trade("alice", "BTC-PERP", dec!(1), dec!(50000))),
+        (1_000, trade("bob", "ETH-PERP", dec!(10), dec!(3000))),
+    ] {
+        assert!(engine.process_at(at, event_type).is_accepted());
+    }
+    engine
+}
+
+fn stale(engine: &Engine) -> bool {
+    engine.state.markets["BTC-PERP"].stale
+}
+
+fn initial_margin(engine: &Engine, account: &str) -> Decimal {
+    margin::initial_margin_required(&engine.state.accounts[account], &engine.state)
+}
+
+fn maintenance_margin(engine: &Engine, account: &str) -> Decimal {
+    margin::maintenance_margin_required(&engine.state.accounts[account], &engine.state)
+}
+
+/// The reason `outcome` was rejected for, if it was.
+fn rejection(outcome: ProcessOutcome) -> Option<String> {
+    match outcome {
+        ProcessOutcome::Rejected { reason, .. } => Some(reason.to_string()),
+        _ => None,
+    }
+}
+
+/// Whether the latest snapshot flags alice's BTC position as stale.
+fn flagged(engine: &Engine) -> bool {
+    engine.snapshots.last().unwrap().accounts[&id("alice")].positions[&market("BTC-PERP")]
+        .mark_stale
+}
+
+#[test]
+fn advancing_the_clock_makes_the_mark_stale() {
+    let mut engine = engine();
+    assert_eq!(initial_margin(&engine, "alice"), dec!(2500));
+
+    // Exactly the threshold after the mark is still fresh; an event with no price in
+    // it, a millisecond later, makes it stale.
+    assert!(engine
+        .process_at(60_000, deposit("carol", dec!(1)))
+        .is_accepted());
+    assert!(!stale(&engine) && !flagged(&engine));
+    assert_eq!(initial_margin(&engine, "alice"), dec!(2500));
+    assert!(engine
+        .process_at(60_001, deposit("carol", dec!(1)))
+        .is_accepted());
+    assert!(stale(&engine) && flagged(&engine));
+
+    // IM doubles; MM and the fresh ETH market do not move.
+    assert_eq!(initial_margin(&engine, "alice"), dec!(5000));
+    assert_eq!(maintenance_margin(&engine, "alice"), dec!(1500));
+    assert_eq!(initial_margin(&engine, "bob"), dec!(3000));
+    assert!(!margin::is_liquidatable(
+        &engine.state.accounts["alice"],
+        &engine.state
+    ));
+}
+
+#[test]
+fn stale_market_refuses_new_risk_only() {
+    let mut engine = engine();
+    assert!(engine
+        .process_at(120_000, deposit("carol", dec!(1)))
+        .is_accepted());
+    assert!(stale(&engine));
+
+    let refused = engine.process_at(120_000, trade("alice", "BTC-PERP", dec!(0.1), dec!(50000)));
+    assert_eq!(
+        rejection(refused).unwrap(),
+        "Market BTC-PERP mark is stale: last mark at 0 ms, clock 120000 ms, threshold 60000 ms"
+    );
+    let refused = engine.process_at(
+        120_000,
+        trade("carol", "BTC-PERP", dec!(-0.0001), dec!(50000)),
+    );
+    assert!(rejection(refused).is_some());
+
+    // Reducing passes, at the stale IM; the fresh market trades as before.
+    assert!(engine
+        .process_at(120_000, trade("alice", "BTC-PERP", dec!(-0.5), dec!(50000)))
+        .is_accepted());
+    assert_eq!(initial_margin(&engine, "alice"), dec!(2500));
+    assert!(engine
+        .process_at(120_000, trade("bob", "ETH-PERP", dec!(1), dec!(3000)))
+        .is_accepted());
+
+    // A fresh mark clears the flag and the multiplier at once.
+    assert!(engine
+        .process_at(130_000, mark("BTC-PERP", dec!(50000)))
+        .is_accepted());
+    assert!(!stale(&engine) && !flagged(&engine));
+    assert_eq!(initial_margin(&engine, "alice"), dec!(1250));
+    assert!(engine
+        .process_at(130_000, trade("alice", "BTC-PERP", dec!(0.1), dec!(50000)))
+        .is_accepted());
+}
+
+#[test]
+fn replay_and_untimed_logs_agree() {
+    let mut engine = engine();
+    for (at, event_type) in [
+        (90_000, deposit("carol", dec!(1))),
+        (90_000, trade("alice", "BTC-PERP", dec!(0.1), dec!(50000))),
+        (95_000, mark("BTC-PERP", dec!(49000))),
+        (200_000, deposit("carol", dec!(1))),
+    ] {
+        engine.process_at(at, event_type);
+    }
+    // The trade at 90,000 ms came with the mark 90 seconds old, and was refused.
+    assert!(stale(&engine));
+    assert_eq!(
+        engine.state.accounts["alice"].positions["BTC-PERP"].quantity,
+        dec!(1)
+    );
+    let replayed =
+        Engine::replay_verified(&engine.event_log, markets(), EngineConfig::default()).unwrap();
+    assert_eq!(replayed.state, engine.state);
+    assert_eq!(replayed.snapshots, engine.snapshots);
+
+    // Without timestamps there is no clock and nothing goes stale, so the same trade
+    // goes through, and 1.1 BTC at 49,000 is charged the plain 5%.
+    let mut untimed = engine_with(EngineConfig::default(), markets());
+    for event in engine.event_log.iter().filter(|e| e.caused_by.is_none()) {
+        untimed.process(event.event_type.clone());
+    }
+    assert!(!stale(&untimed));
+    assert_eq!(initial_margin(&untimed, "alice"), dec!(2695));
+}
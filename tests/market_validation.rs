@@ -0,0 +1,281 @@
+// `Market::validate`, case by case, from a BTC-PERP at 5% IM and 3% MM: each bad
+// setting is refused with the error that names it, and each boundary it may sit on is
+// accepted. `add_market` before the first event and `MarketAdded` after it refuse the
+// same markets and register nothing.
+
+mod common;
+
+use common::{btc, btc_market, deposit, engine_with, process};
+use cross_margin_engine::prelude::*;
+use rust_decimal_macros::dec;
+
+type Edit = fn(&mut Market);
+
+/// Each bad setting with the error it must give.
+fn invalid() -> Vec<(&'static str, Edit, MarketConfigError)> {
+    let fractions = |initial, maintenance| MarketConfigError::MarginFractions {
+        market_id: btc(),
+        initial,
+        maintenance,
+    };
+    let negative = |field, value| MarketConfigError::Negative {
+        market_id: btc(),
+        field,
+        value,
+    };
+    vec![
+        (
+            "mm above im",
+            |m| m.maintenance_margin_fraction = dec!(0.06),
+            fractions(dec!(0.05), dec!(0.06)),
+        ),
+        (
+            "mm zero",
+            |m| m.maintenance_margin_fraction = dec!(0),
+            fractions(dec!(0.05), dec!(0)),
+        ),
+        (
+            "mm negative",
+            |m| m.maintenance_margin_fraction = dec!(-0.01),
+            fractions(dec!(0.05), dec!(-0.01)),
+        ),
+        (
+            "im one",
+            |m| m.initial_margin_fraction = dec!(1),
+            fractions(dec!(1), dec!(0.03)),
+        ),
+        (
+            "im above one",
+            |m| m.initial_margin_fraction = dec!(1.5),
+            fractions(dec!(1.5), dec!(0.03)),
+        ),
+        (
+            "negative concentration threshold",
+            |m| m.concentration_threshold_notional = dec!(-1),
+            negative("concentration_threshold_notional", dec!(-1)),
+        ),
+        (
+            "negative concentration add-on",
+            |m| m.concentration_add_on_fraction = dec!(-0.01),
+            negative("concentration_add_on_fraction", dec!(-0.01)),
+        ),
+        (
+            "negative open-interest cap",
+            |m| m.max_open_interest_notional = Some(dec!(-1)),
+            negative("max_open_interest_notional", dec!(-1)),
+        ),
+        (
+            "negative skew limit",
+            |m| m.skew_limit_notional = Some(dec!(-1)),
+            negative("skew_limit_notional", dec!(-1)),
+        ),
+        (
+            "negative initial floor",
+            |m| m.min_initial_margin = Some(dec!(-1)),
+            negative("min_initial_margin", dec!(-1)),
+        ),
+        (
+            "negative maintenance floor",
+            |m| {
+                m.min_initial_margin = Some(dec!(10));
+                m.min_maintenance_margin = Some(dec!(-1));
+            },
+            negative("min_maintenance_margin", dec!(-1)),
+        ),
+        (
+            "negative minimum liquidation notional",
+            |m| m.min_liquidation_notional = Some(dec!(-1)),
+            negative("min_liquidation_notional", dec!(-1)),
+        ),
+        (
+            "negative liquidation discount",
+            |m| m.liquidation_discount = dec!(-0.01),
+            negative("liquidation_discount", dec!(-0.01)),
+        ),
+        (
+            "negative slippage",
+            |m| m.slippage_bps_per_notional = dec!(-0.5),
+            negative("slippage_bps_per_notional", dec!(-0.5)),
+        ),
+        (
+            "maintenance floor above initial floor",
+            |m| {
+                m.min_initial_margin = Some(dec!(10));
+                m.min_maintenance_margin = Some(dec!(11));
+            },
+            MarketConfigError::MarginFloors {
+                market_id: btc(),
+                initial: Some(dec!(10)),
+                maintenance: dec!(11),
+            },
+        ),
+        (
+            "maintenance floor without initial floor",
+            |m| m.min_maintenance_margin = Some(dec!(5)),
+            MarketConfigError::MarginFloors {
+                market_id: btc(),
+                initial: None,
+                maintenance: dec!(5),
+            },
+        ),
+        (
+            "max leverage below one",
+            |m| m.max_leverage = Some(dec!(0.5)),
+            MarketConfigError::MaxLeverage {
+                market_id: btc(),
+                max_leverage: dec!(0.5),
+                maintenance: dec!(0.03),
+            },
+        ),
+        (
+            "max leverage past 1 / mm",
+            |m| m.max_leverage = Some(dec!(34)),
+            MarketConfigError::MaxLeverage {
+                market_id: btc(),
+                max_leverage: dec!(34),
+                maintenance: dec!(0.03),
+            },
+        ),
+        (
+            "zero quantity step",
+            |m| m.quantity_step = Some(dec!(0)),
+            MarketConfigError::QuantityStep {
+                market_id: btc(),
+                step: dec!(0),
+            },
+        ),
+        (
+            "negative quantity step",
+            |m| m.quantity_step = Some(dec!(-0.1)),
+            MarketConfigError::QuantityStep {
+                market_id: btc(),
+                step: dec!(-0.1),
+            },
+        ),
+        (
+            "stale multiplier below one",
+            |m| m.stale_im_multiplier = dec!(0.5),
+            MarketConfigError::StaleImMultiplier {
+                market_id: btc(),
+                multiplier: dec!(0.5),
+            },
+        ),
+        (
+            "negative stale multiplier",
+            |m| m.stale_im_multiplier = dec!(-2),
+            MarketConfigError::StaleImMultiplier {
+                market_id: btc(),
+                multiplier: dec!(-2),
+            },
+        ),
+    ]
+}
+
+/// Settings on or inside each boundary.
+fn valid() -> Vec<(&'static str, Edit)> {
+    vec![
+        ("mm equal to im", |m| {
+            m.maintenance_margin_fraction = dec!(0.05)
+        }),
+        ("im just under one", |m| {
+            m.initial_margin_fraction = dec!(0.99)
+        }),
+        ("zero caps and discounts", |m| {
+            m.concentration_threshold_notional = dec!(0);
+            m.concentration_add_on_fraction = dec!(0);
+            m.max_open_interest_notional = Some(dec!(0));
+            m.skew_limit_notional = Some(dec!(0));
+            m.min_liquidation_notional = Some(dec!(0));
+            m.liquidation_discount = dec!(0);
+            m.slippage_bps_per_notional = dec!(0);
+        }),
+        ("floors equal", |m| {
+            m.min_initial_margin = Some(dec!(10));
+            m.min_maintenance_margin = Some(dec!(10));
+        }),
+        ("initial floor alone", |m| {
+            m.min_initial_margin = Some(dec!(10))
+        }),
+        ("max leverage one", |m| m.max_leverage = Some(dec!(1))),
+        ("max leverage at 1 / mm", |m| {
+            m.maintenance_margin_fraction = dec!(0.04);
+            m.max_leverage = Some(dec!(25));
+        }),
+        ("positive quantity step", |m| {
+            m.quantity_step = Some(dec!(0.001))
+        }),
+        ("stale multiplier one", |m| m.stale_im_multiplier = dec!(1)),
+        ("stale multiplier above one", |m| {
+            m.stale_im_multiplier = dec!(2)
+        }),
+    ]
+}
+
+fn edited(edit: Edit) -> Market {
+    let mut market = btc_market();
+    edit(&mut market);
+    market
+}
+
+#[test]
+fn each_bad_setting_is_refused_by_name() {
+    assert_eq!(btc_market().validate(), Ok(()));
+    for (case, edit, expected) in invalid() {
+        assert_eq!(edited(edit).validate(), Err(expected), "{case}");
+    }
+}
+
+#[test]
+fn each_boundary_is_accepted() {
+    for (case, edit) in valid() {
+        assert_eq!(edited(edit).validate(), Ok(()), "{case}");
+    }
+}
+
+#[test]
+fn add_market_registers_nothing_invalid() {
+    for (case, edit, expected) in invalid() {
+        let mut engine = Engine::new();
+        assert_eq!(
+            engine.add_market(edited(edit)),
+            Err(MarketError::Invalid(expected)),
+            "{case}"
+        );
+        assert!(engine.state.markets.is_empty(), "{case}");
+    }
+}
+
+#[test]
+fn market_added_is_rejected_for_an_invalid_market() {
+    for (case, edit, expected) in invalid() {
+        // After the first event, registration goes through the log.
+        let mut engine = engine_with(EngineConfig::default(), vec![]);
+        process(&mut engine, deposit("alice", dec!(1)));
+        let outcome = engine.process(EventType::MarketAdded {
+            market: Box::new(edited(edit)),
+        });
+        let ProcessOutcome::Rejected { reason, .. } = outcome else {
+            panic!("{case}: {outcome:?}");
+        };
+        assert!(
+            reason.to_string().contains(&expected.to_string()),
+            "{case}: {reason}"
+        );
+        assert!(engine.state.markets.is_empty(), "{case}");
+    }
+
+    let mut engine = engine_with(EngineConfig::default(), vec![]);
+    process(&mut engine, deposit("alice", dec!(1)));
+    for (case, edit) in valid() {
+        let market = edited(edit);
+        process(
+            &mut engine,
+            EventType::MarketAdded {
+                market: Box::new(market.clone()),
+            },
+        );
+        assert_eq!(engine.state.markets.get(&btc()), Some(&market), "{case}");
+        process(&mut engine, EventType::MarketRemoved { market_id: btc() });
+    }
+    assert_eq!(engine.state.markets.len(), 0);
+}
@@ -0,0 +1,227 @@
+// Resting orders reserve margin. A mark move that leaves alice's positions covered
+// but not her reservations cancels, under `ReservationBreach::AutoCancel`, the fewest
+// orders that bring her back, largest reservation first and then by id, before the
+// scan liquidates anyone; her positions are left as they were. Under `Hold` the orders
+// stay and she can only cancel. Replay and regeneration agree either way.
+
+mod common;
+
+use common::{btc, btc_market, deposit, engine_with, id, mark, process, trade};
+use cross_margin_engine::margin;
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::regenerate::diff_logs;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+fn order(account: &str, order_id: &str, quantity: Decimal) -> EventType {
+    EventType::OrderPlaced {
+        account_id: id(account),
+        order_id: order_id.into(),
+        market_id: btc(),
+        quantity,
+    }
+}
+
+fn config(policy: ReservationBreach) -> EngineConfig {
+    EngineConfig {
+        reservation_breach: policy,
+        ..EngineConfig::default()
+    }
+}
+
+/// alice long 1 BTC at 50,000 on 10,000, resting orders reserving 2,500, 1,500,
+/// 1,500 and 500 at that mark; bob long 1 BTC on 6,000.
+fn engine(policy: ReservationBreach) -> Engine {
+    let mut engine = engine_with(config(policy), vec![btc_market()]);
+    for event_type in [
+        mark("BTC-PERP", dec!(50000)),
+        deposit("alice", dec!(10000)),
+        deposit("bob", dec!(6000)),
+        trade("alice", "BTC-PERP", dec!(1), dec!(50000)),
+        trade("bob", "BTC-PERP", dec!(1), dec!(50000)),
+        order("alice", "o-a", dec!(1)),
+        order("alice", "o-c", dec!(0.6)),
+        order("alice", "o-b", dec!(-0.6)),
+        order("alice", "o-d", dec!(0.2)),
+    ] {
+        process(&mut engine, event_type);
+    }
+    engine
+}
+
+fn orders(engine: &Engine) -> Vec<&str> {
+    engine.state.accounts["alice"]
+        .orders
+        .keys()
+        .map(String::as_str)
+        .collect()
+}
+
+fn reserved(engine: &Engine) -> Decimal {
+    margin::reserved_margin(&engine.state.accounts["alice"], &engine.state)
+}
+
+#[test]
+fn orders_reserve_margin_at_placement() {
+    let mut engine = engine(ReservationBreach::AutoCancel);
+    assert_eq!(reserved(&engine), dec!(6000));
+    // IM 2,500 plus 6,000 reserved leaves 1,500 of the 10,000.
+    let refused = engine.process(order("alice", "o-e", dec!(0.7)));
+    assert!(!refused.is_accepted());
+    let refused = engine.process(order("alice", "o-a", dec!(0.1)));
+    assert!(!refused.is_accepted());
+    process(&mut engine, order("alice", "o-e", dec!(0.6)));
+    let withdrawable =
+        margin::max_withdrawable(&engine.state.accounts["alice"], &engine.state, Decimal::ONE);
+    assert_eq!(withdrawable, Decimal::ZERO);
+    assert!(!engine
+        .process(EventType::Withdraw {
+            account_id: id("alice"),
+            amount: dec!(1),
+        })
+        .is_accepted());
+
+    // Cancelling releases the reservation.
+    process(
+        &mut engine,
+        EventType::OrderCancelled {
+            account_id: id("alice"),
+            order_id: "o-e".into(),
+        },
+    );
+    assert_eq!(reserved(&engine), dec!(6000));
+    assert!(!engine
+        .process(EventType::OrderCancelled {
+            account_id: id("alice"),
+            order_id: "o-e".into(),
+        })
+        .is_accepted());
+}
+
+#[test]
+fn mark_move_cancels_the_minimal_set_before_liquidating() {
+    let mut engine = engine(ReservationBreach::AutoCancel);
+    let positions = engine.state.accounts["alice"].positions.clone();
+    let sequence = engine.next_sequence();
+    // Equity 5,000 and IM 2,250 leave room for 2,750 of the 5,400 now reserved:
+    // o-a's 2,250 is not enough on its own, o-a and o-b's 1,350 are. o-b and o-c
+    // reserve the same, and o-b goes first by id.
+    process(&mut engine, mark("BTC-PERP", dec!(45000)));
+
+    let derived: Vec<&EventType> = engine
+        .event_log
+        .iter()
+        .filter(|e| e.caused_by == Some(sequence))
+        .map(|e| &e.event_type)
+        .collect();
+    assert_eq!(
+        derived[0],
+        &EventType::OrdersAutoCancelled {
+            account_id: id("alice"),
+            order_ids: vec!["o-a".into(), "o-b".into()],
+            reason: "Equity 5000 below IM 2250 plus reserved 5400".into(),
+        }
+    );
+    // bob, under MM, is liquidated after it.
+    assert!(derived[1..].iter().any(
+        |e| matches!(e, EventType::LiquidationFill { account_id, .. } if account_id == "bob")
+    ));
+    assert!(derived[1..]
+        .iter()
+        .all(|e| !matches!(e, EventType::OrdersAutoCancelled { .. })));
+
+    assert_eq!(orders(&engine), ["o-c", "o-d"]);
+    let alice = &engine.state.accounts["alice"];
+    assert_eq!(alice.positions, positions);
+    assert_eq!(alice.collateral(), dec!(10000));
+    assert!(
+        margin::equity(alice, &engine.state)
+            >= margin::initial_margin_required(alice, &engine.state) + reserved(&engine)
+    );
+
+    // Back above IM plus reservations, the next event cancels nothing more.
+    let sequence = engine.next_sequence();
+    process(&mut engine, mark("BTC-PERP", dec!(45100)));
+    assert!(engine
+        .event_log
+        .iter()
+        .all(|e| e.caused_by != Some(sequence)
+            || !matches!(e.event_type, EventType::OrdersAutoCancelled { .. })));
+}
+
+#[test]
+fn positions_under_im_cancel_nothing() {
+    let mut engine = engine(ReservationBreach::AutoCancel);
+    // Equity 2,000 against IM 2,100: the reservations are not what breaches.
+    process(&mut engine, mark("BTC-PERP", dec!(42000)));
+    assert_eq!(orders(&engine), ["o-a", "o-b", "o-c", "o-d"]);
+    assert!(!engine
+        .event_log
+        .iter()
+        .any(|e| matches!(e.event_type, EventType::OrdersAutoCancelled { .. })));
+}
+
+#[test]
+fn hold_keeps_the_orders_and_blocks_new_ones() {
+    let mut engine = engine(ReservationBreach::Hold);
+    process(&mut engine, mark("BTC-PERP", dec!(45000)));
+    assert_eq!(orders(&engine), ["o-a", "o-b", "o-c", "o-d"]);
+    assert!(!engine
+        .process(order("alice", "o-e", dec!(0.01)))
+        .is_accepted());
+    assert!(!engine
+        .process(EventType::Withdraw {
+            account_id: id("alice"),
+            amount: dec!(1),
+        })
+        .is_accepted());
+    process(
+        &mut engine,
+        EventType::OrderCancelled {
+            account_id: id("alice"),
+            order_id: "o-a".into(),
+        },
+    );
+}
+
+#[test]
+fn replay_applies_the_recorded_cancellations() {
+    for policy in [ReservationBreach::AutoCancel, ReservationBreach::Hold] {
+        let mut engine = engine(policy);
+        process(&mut engine, mark("BTC-PERP", dec!(45000)));
+        process(&mut engine, mark("BTC-PERP", dec!(47000)));
+        let replayed =
+            Engine::replay_verified(&engine.event_log, vec![btc_market()], config(policy)).unwrap();
+        assert_eq!(replayed.state, engine.state);
+        assert_eq!(replayed.snapshots, engine.snapshots);
+        let regenerated =
+            Engine::regenerate(&engine.event_log, vec![btc_market()], config(policy)).unwrap();
+        assert_eq!(diff_logs(&engine.event_log, &regenerated), None);
+    }
+
+    // A recorded cancellation other than the one the reservations call for is refused.
+    let mut engine = engine(ReservationBreach::AutoCancel);
+    process(&mut engine, mark("BTC-PERP", dec!(45000)));
+    let mut log: Vec<Event> = engine
+        .event_log
+        .iter()
+        .map(|e| e.as_ref().clone())
+        .collect();
+    let EventType::OrdersAutoCancelled { order_ids, .. } = &mut log
+        .iter_mut()
+        .find(|e| matches!(e.event_type, EventType::OrdersAutoCancelled { .. }))
+        .unwrap()
+        .event_type
+    else {
+        unreachable!()
+    };
+    order_ids[1] = "o-c".into();
+    assert!(matches!(
+        Engine::replay_verified(
+            &log,
+            vec![btc_market()],
+            config(ReservationBreach::AutoCancel)
+        ),
+        Err(EngineError::InvalidDerivedEvent { .. })
+    ));
+}
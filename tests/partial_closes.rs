@@ -0,0 +1,148 @@
+// A long and a short cut down by 1000 sequential partial closes of 0.3% each,
+// through the engine, checked against exact rational accounting of the final cost
+// basis and total realized PnL. With an average entry that terminates (one open at
+// 50000.25) both match to within one unit of collateral precision. With an entry that
+// does not (1 at 50000 and 2 at 50001, entry 50000.666...), realized PnL is rounded
+// down on every close, so it may fall short of exact by at most one unit a close, and
+// collateral less cost basis still moves by exactly the cash exchanged. Replay
+// reproduces the final state.
+
+mod common;
+
+use common::{btc, deposit, engine_with, mark, process, trade};
+use cross_margin_engine::prelude::*;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+const CLOSES: u32 = 1000;
+const DEPOSIT: Decimal = dec!(100000000);
+/// Quantities are whole lots of 0.000001, prices whole ticks of 0.01.
+const LOT: Decimal = dec!(0.000001);
+const TICK: Decimal = dec!(0.01);
+/// One unit of collateral precision (`COLLATERAL_DECIMALS` = 8).
+const UNIT: Decimal = dec!(0.00000001);
+
+/// BTC-PERP at 1% IM and 0.5% MM, so the 1000 BTC open fits the deposit.
+fn markets() -> Vec<Market> {
+    vec![Market::new(btc(), dec!(0.01), dec!(0.005))]
+}
+
+fn whole(value: Decimal, step: Decimal) -> i128 {
+    let steps = value / step;
+    assert!(
+        steps.fract().is_zero(),
+        "{value} is not a multiple of {step}"
+    );
+    steps.trunc().mantissa()
+}
+
+fn lots(quantity: Decimal) -> i128 {
+    whole(quantity, LOT)
+}
+
+fn ticks(price: Decimal) -> i128 {
+    whole(price, TICK)
+}
+
+/// Open with `opens` (long, or short with `side` -1), then make the closes. Returns
+/// the engine and every close as (lots, ticks).
+fn run(opens: &[(Decimal, Decimal)], side: Decimal) -> (Engine, Vec<(i128, i128)>) {
+    let mut engine = engine_with(EngineConfig::default(), markets());
+    process(&mut engine, mark("BTC-PERP", dec!(50000)));
+    process(&mut engine, deposit("alice", DEPOSIT));
+    for (quantity, price) in opens {
+        process(
+            &mut engine,
+            trade("alice", "BTC-PERP", quantity * side, *price),
+        );
+    }
+
+    let mut closes = Vec::new();
+    let mut remaining: Decimal = opens.iter().map(|(quantity, _)| quantity).sum();
+    for k in 0..CLOSES {
+        let quantity = (remaining * dec!(0.003)).round_dp(6).max(LOT);
+        // A deterministic walk around 50000, on the tick.
+        let price =
+            dec!(50000) + Decimal::from(k % 97) * dec!(13.37) - Decimal::from(k % 13) * dec!(41.03);
+        process(
+            &mut engine,
+            trade("alice", "BTC-PERP", -quantity * side, price),
+        );
+        closes.push((lots(quantity), ticks(price)));
+        remaining -= quantity;
+    }
+    (engine, closes)
+}
+
+/// `numerator / denominator` units, as a decimal of collateral precision (rounded).
+fn units(numerator: i128, denominator: i128) -> Decimal {
+    Decimal::from_i128_with_scale(numerator, 8) / Decimal::from_i128_with_scale(denominator, 0)
+}
+
+fn check(opens: &[(Decimal, Decimal)], side: Decimal, terminating: bool) {
+    let (engine, closes) = run(opens, side);
+    let account = &engine.state.accounts["alice"];
+    let cost_basis = account.positions["BTC-PERP"].cost_basis;
+    let realized = account.collateral() - DEPOSIT;
+
+    // Exact reference in integers: lots × ticks is a unit of 1e-8. The cost basis of
+    // what remains is the opening cost scaled by remaining / opened; the realized PnL
+    // is what the closes received less the cost they took off.
+    let sign = if side.is_sign_negative() { -1 } else { 1 };
+    let opened: i128 = opens.iter().map(|(quantity, _)| lots(*quantity)).sum();
+    let opening_cost: i128 = opens.iter().map(|(q, p)| sign * lots(*q) * ticks(*p)).sum();
+    let remaining = opened - closes.iter().map(|(quantity, _)| quantity).sum::<i128>();
+    let received: i128 = closes.iter().map(|(q, p)| sign * q * p).sum();
+    // cost_exact = opening_cost * remaining / opened, as a fraction over `opened`.
+    let cost_exact = opening_cost * remaining;
+    let realized_exact = received * opened - (opening_cost * opened - cost_exact);
+
+    let cost_error = cost_basis - units(cost_exact, opened);
+    let realized_error = realized - units(realized_exact, opened);
+
+    // No value is created or lost: collateral less cost basis moved by exactly the
+    // cash the closes exchanged.
+    assert_eq!(
+        (realized - cost_basis) / UNIT,
+        Decimal::from_i128_with_scale(received - opening_cost, 0)
+    );
+    if terminating {
+        assert!(
+            cost_error.abs() <= UNIT && realized_error.abs() <= UNIT,
+            "cost basis off by {cost_error}, realized off by {realized_error}"
+        );
+    } else {
+        // Rounded down: never ahead of exact, behind by under a unit per close.
+        assert!(
+            realized_error <= UNIT && realized_error > -UNIT * Decimal::from(CLOSES),
+            "realized off by {realized_error}"
+        );
+    }
+
+    let replayed =
+        Engine::replay_verified(&engine.event_log, markets(), EngineConfig::default()).unwrap();
+    assert_eq!(replayed.state.accounts, engine.state.accounts);
+}
+
+const TERMINATING: [(Decimal, Decimal); 1] = [(dec!(1000), dec!(50000.25))];
+const THIRDS: [(Decimal, Decimal); 2] = [(dec!(1), dec!(50000)), (dec!(2), dec!(50001))];
+
+#[test]
+fn long_with_a_terminating_entry_matches_exact_accounting() {
+    check(&TERMINATING, Decimal::ONE, true);
+}
+
+#[test]
+fn short_with_a_terminating_entry_matches_exact_accounting() {
+    check(&TERMINATING, -Decimal::ONE, true);
+}
+
+#[test]
+fn long_with_a_repeating_entry_trails_exact_by_under_a_unit_a_close() {
+    check(&THIRDS, Decimal::ONE, false);
+}
+
+#[test]
+fn short_with_a_repeating_entry_trails_exact_by_under_a_unit_a_close() {
+    check(&THIRDS, -Decimal::ONE, false);
+}
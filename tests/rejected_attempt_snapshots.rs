@@ -0,0 +1,135 @@
+// A burst of rejected fills under both `RejectedAttemptSnapshots` settings. `Skip`
+// keeps one snapshot per rejection, under the rejection event's sequence; `Keep` adds
+// an identical one under the attempt's. The logs, final states and books are the
+// same either way, each replays to its own snapshots, and the two streams agree at
+// every sequence they share.
+
+mod common;
+
+use common::{btc_market, deposit, engine_with, mark, process, trade};
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::snapshot;
+use rust_decimal_macros::dec;
+
+const BURST: usize = 25;
+
+fn config(rejected_attempt_snapshots: RejectedAttemptSnapshots) -> EngineConfig {
+    EngineConfig {
+        rejected_attempt_snapshots,
+        ..EngineConfig::default()
+    }
+}
+
+/// alice on 10,000 at a BTC mark of 50,000, then `BURST` fills of 10 BTC she has no
+/// margin for, and one she has.
+fn run(rejected_attempt_snapshots: RejectedAttemptSnapshots) -> Engine {
+    let mut engine = engine_with(config(rejected_attempt_snapshots), vec![btc_market()]);
+    process(&mut engine, mark("BTC-PERP", dec!(50000)));
+    process(&mut engine, deposit("alice", dec!(10000)));
+    for _ in 0..BURST {
+        let outcome = engine.process(trade("alice", "BTC-PERP", dec!(10), dec!(50000)));
+        assert!(!outcome.is_accepted(), "{outcome:?}");
+    }
+    process(
+        &mut engine,
+        trade("alice", "BTC-PERP", dec!(1), dec!(50000)),
+    );
+    engine
+}
+
+fn rejected_attempts(engine: &Engine) -> Vec<u64> {
+    engine
+        .event_log
+        .windows(2)
+        .filter(|pair| pair[1].caused_by == Some(pair[0].sequence))
+        .filter(|pair| matches!(pair[1].event_type, EventType::TradeRejected { .. }))
+        .map(|pair| pair[0].sequence)
+        .collect()
+}
+
+fn assert_replays_to_its_snapshots(engine: &Engine) {
+    let options = ReplayOptions {
+        config: engine.config().clone(),
+        ..ReplayOptions::default()
+    };
+    let result = Engine::replay_with(options, &engine.event_log, vec![btc_market()]);
+    assert_eq!(result.status, ReplayStatus::Completed);
+    assert_eq!(result.rejections.len(), BURST);
+    assert_eq!(result.state, engine.state);
+    assert_eq!(result.metrics, *engine.metrics());
+    assert_eq!(result.snapshots, engine.snapshots);
+}
+
+#[test]
+fn skip_takes_one_snapshot_per_rejection() {
+    let engine = run(RejectedAttemptSnapshots::Skip);
+    let attempts = rejected_attempts(&engine);
+    assert_eq!(attempts.len(), BURST);
+    // Every event but the attempts: two per rejection become one.
+    assert_eq!(engine.snapshots.len(), engine.event_log.len() - BURST);
+    for attempt in &attempts {
+        let retained = engine.snapshots.iter().map(|s| s.after_sequence);
+        assert!(!retained.clone().any(|seq| seq == *attempt));
+        assert!(retained.clone().any(|seq| seq == attempt + 1));
+    }
+    assert_replays_to_its_snapshots(&engine);
+}
+
+#[test]
+fn keep_snapshots_the_attempt_as_well() {
+    let engine = run(RejectedAttemptSnapshots::Keep);
+    assert_eq!(engine.snapshots.len(), engine.event_log.len());
+    for attempt in rejected_attempts(&engine) {
+        let at = |seq: u64| {
+            engine
+                .snapshots
+                .iter()
+                .find(|s| s.after_sequence == seq)
+                .cloned()
+                .unwrap()
+        };
+        let rejection = at(attempt + 1);
+        assert_eq!(
+            at(attempt),
+            Snapshot {
+                after_sequence: attempt,
+                ..rejection
+            }
+        );
+    }
+    assert_replays_to_its_snapshots(&engine);
+}
+
+#[test]
+fn both_settings_end_in_the_same_state() {
+    let skip = run(RejectedAttemptSnapshots::Skip);
+    let keep = run(RejectedAttemptSnapshots::Keep);
+    assert_eq!(skip.snapshots.len() + BURST, keep.snapshots.len());
+
+    // The same events; only the config in the marker differs.
+    let events = |engine: &Engine| -> Vec<EventType> {
+        engine
+            .event_log
+            .iter()
+            .skip(1)
+            .map(|e| e.event_type.clone())
+            .collect()
+    };
+    assert_eq!(events(&skip), events(&keep));
+    assert_eq!(skip.state, keep.state);
+    assert_eq!(skip.metrics(), keep.metrics());
+    assert!(skip.solvency().is_balanced());
+    // Aligned on sequence, the streams differ only by the attempts `Keep` adds.
+    let attempts = rejected_attempts(&keep);
+    assert_eq!(
+        snapshot::first_divergence(&skip.snapshots, &keep.snapshots),
+        attempts.first().copied()
+    );
+    let shared: Vec<Snapshot> = keep
+        .snapshots
+        .iter()
+        .filter(|s| !attempts.contains(&s.after_sequence))
+        .cloned()
+        .collect();
+    assert_eq!(snapshot::first_divergence(&skip.snapshots, &shared), None);
+}
@@ -0,0 +1,121 @@
+// Count heap allocations around a 10k-event log. `Engine::event_log` shares its
+// events as `Arc<Event>`, so copying the log, or replaying from copies of it, copies
+// no event, and the log serializes exactly as a log of plain events does.
+//
+// `cargo test --test replay_allocations`
+
+mod common;
+
+use common::{deposit, engine_with, mark, markets, trade};
+use cross_margin_engine::jsonl::{self, WriteOptions};
+use cross_margin_engine::prelude::*;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+struct Counting;
+
+thread_local! {
+    /// This thread's allocations, so the test harness's own do not count.
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const EVENTS: usize = 10_000;
+
+/// Allocations made by `f` on this thread, with its result.
+fn counted<T>(f: impl FnOnce() -> T) -> (u64, T) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    (ALLOCATIONS.with(Cell::get) - before, result)
+}
+
+#[test]
+fn shared_log() {
+    let mut engine = engine_with(EngineConfig::default(), markets());
+    for account in ["alice", "bob", "carol"] {
+        engine.process(deposit(account, dec!(1000000)));
+    }
+    let mut i: u64 = 0;
+    while engine.event_log.len() < EVENTS {
+        i += 1;
+        let (market_id, base) = if i.is_multiple_of(2) {
+            ("BTC-PERP", dec!(50000))
+        } else {
+            ("ETH-PERP", dec!(3000))
+        };
+        let event = if i.is_multiple_of(3) {
+            trade(
+                ["alice", "bob", "carol"][(i % 9 / 3) as usize],
+                market_id,
+                if i % 4 < 2 { dec!(0.1) } else { dec!(-0.1) },
+                engine.state.markets[market_id].mark_price.max(base),
+            )
+        } else {
+            let step = Decimal::from(i % 21) - dec!(10);
+            mark(market_id, base * (Decimal::ONE + step / dec!(1000)))
+        };
+        engine.process(event);
+    }
+
+    let log = &engine.event_log;
+
+    // A copy of the log is one vector of shared events; a copy of its events is at
+    // least one allocation each, for the strings they carry.
+    let (shared, copy) = counted(|| log.clone());
+    let (deep, owned) = counted(|| log.iter().map(|e| Event::clone(e)).collect::<Vec<Event>>());
+    assert_eq!(shared, 1);
+    assert!(
+        deep > EVENTS as u64,
+        "copying the events allocated {deep} times"
+    );
+    assert_eq!(copy, owned);
+
+    // No snapshots, so the counts are dominated by how the events reach the replay.
+    let options = || ReplayOptions {
+        snapshot_policy: SnapshotPolicy::Never,
+        ..ReplayOptions::default()
+    };
+    let (cloned, from_clones) =
+        counted(|| Engine::replay_with(options(), owned.iter().cloned(), markets()));
+    let (sharing, from_shared) =
+        counted(|| Engine::replay_with(options(), log.iter().cloned(), markets()));
+    let (borrowed, from_refs) = counted(|| Engine::replay_with(options(), log, markets()));
+    assert_eq!(from_refs.state, engine.state);
+    assert_eq!(from_shared.state, engine.state);
+    assert_eq!(from_clones.state, engine.state);
+    assert_eq!(sharing, borrowed);
+    assert!(
+        borrowed < cloned,
+        "borrowed replay allocated {borrowed} times, cloned {cloned}"
+    );
+
+    // Sharing is invisible on the wire.
+    assert_eq!(
+        serde_json::to_string(log).unwrap(),
+        serde_json::to_string(&owned).unwrap()
+    );
+    let path = std::env::temp_dir().join("cross-margin-engine-replay-allocations.jsonl");
+    jsonl::write_jsonl(&path, log, WriteOptions::default()).unwrap();
+    assert_eq!(jsonl::read_jsonl(&path).unwrap(), owned);
+    println!(
+        "{} events: copying the log allocated {shared} times, its events {deep}; \
+         replay allocated {cloned} times from copies, {sharing} from shared events, \
+         {borrowed} from borrowed ones",
+        log.len()
+    );
+}
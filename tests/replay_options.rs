@@ -0,0 +1,103 @@
+// `ReplayOptions` early exits. A replay stopped at any sequence of the demo log, or
+// cancelled from its own progress callback just after it, ends with the state,
+// snapshots and books of a full replay of the log up to that sequence. Attempts the
+// log records as rejected are reported in the result and in progress, not printed.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use cross_margin_engine::demo;
+use cross_margin_engine::engine::ReplayProgress;
+use cross_margin_engine::prelude::*;
+
+/// A full replay of the demo log up to and including `sequence`.
+fn prefix(log: &[Arc<Event>], sequence: u64) -> ReplayResult {
+    let events = log.iter().take_while(|event| event.sequence <= sequence);
+    let result = Engine::replay_with(ReplayOptions::default(), events, demo::markets());
+    assert_eq!(result.status, ReplayStatus::Completed);
+    result
+}
+
+fn assert_same_prefix(result: &ReplayResult, expected: &ReplayResult) {
+    assert_eq!(result.state, expected.state);
+    assert_eq!(result.snapshots, expected.snapshots);
+    assert_eq!(result.metrics, expected.metrics);
+    assert_eq!(result.events_applied, expected.events_applied);
+    assert_eq!(result.last_sequence, expected.last_sequence);
+    assert_eq!(result.rejections, expected.rejections);
+}
+
+#[test]
+fn stop_at_sequence_equals_the_prefix_replay() {
+    let log = demo::engine().event_log.clone();
+    let last = log.last().unwrap().sequence;
+    for event in &log {
+        let sequence = event.sequence;
+        let options = ReplayOptions {
+            stop_at_sequence: Some(sequence),
+            ..ReplayOptions::default()
+        };
+        let result = Engine::replay_with(options, &log, demo::markets());
+        let status = if sequence == last {
+            ReplayStatus::Completed
+        } else {
+            ReplayStatus::StoppedAt(sequence)
+        };
+        assert_eq!(result.status, status, "seq {sequence}");
+        assert_same_prefix(&result, &prefix(&log, sequence));
+    }
+}
+
+#[test]
+fn cancelling_from_the_progress_callback_stops_at_an_event_boundary() {
+    let log = demo::engine().event_log.clone();
+    // Mid-cascade: after BTC's drop to 41,000, before alice's liquidation fill.
+    for cancel_after in [6, 12, 18] {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&cancel);
+        let options = ReplayOptions {
+            progress: Some(Box::new(move |progress: ReplayProgress| {
+                if progress.current_sequence == cancel_after {
+                    flag.store(true, Ordering::Relaxed);
+                }
+            })),
+            progress_interval: 1,
+            cancel: Some(cancel),
+            ..ReplayOptions::default()
+        };
+        let result = Engine::replay_with(options, &log, demo::markets());
+        assert_eq!(result.status, ReplayStatus::Cancelled);
+        assert_same_prefix(&result, &prefix(&log, cancel_after));
+    }
+}
+
+#[test]
+fn rejections_are_reported_through_the_result_and_progress() {
+    let log = demo::engine().event_log.clone();
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&reports);
+    let options = ReplayOptions {
+        progress: Some(Box::new(move |progress: ReplayProgress| {
+            seen.lock().unwrap().push(progress);
+        })),
+        progress_interval: 1,
+        ..ReplayOptions::default()
+    };
+    let result = Engine::replay_with(options, &log, demo::markets());
+    assert_eq!(result.status, ReplayStatus::Completed);
+
+    // bob's second ETH fill and charlie's 30 ETH, each refused as it was live.
+    let rejected: Vec<u64> = result.rejections.iter().map(|(seq, _)| *seq).collect();
+    assert_eq!(rejected, [11, 16]);
+    assert!(result.rejections[0].1.contains("Insufficient margin"));
+
+    let reports = reports.lock().unwrap();
+    assert_eq!(reports.len() as u64, result.events_applied);
+    for progress in reports.iter() {
+        let so_far = rejected
+            .iter()
+            .filter(|seq| **seq <= progress.current_sequence)
+            .count();
+        assert_eq!(progress.rejections, so_far as u64, "{progress:?}");
+    }
+}
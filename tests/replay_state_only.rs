@@ -0,0 +1,109 @@
+// `Engine::replay_state_only` lands on the state `replay_with` does, under the config
+// the log was written with: the default one, one set when the engine was built, and
+// one installed mid-log by a `ConfigUpdated`. Under any other config it reports the
+// mismatch at the log's `ConfigMarker` instead of handing back a partial state.
+
+mod common;
+
+use common::engine_with;
+use cross_margin_engine::demo;
+use cross_margin_engine::prelude::*;
+use rust_decimal_macros::dec;
+use std::sync::Arc;
+
+fn config() -> EngineConfig {
+    EngineConfig {
+        withdrawal_buffer: dec!(1.5),
+        snapshot_policy: SnapshotPolicy::Never,
+        scan_order: ScanOrder::WorstMarginRatioFirst,
+        ..EngineConfig::default()
+    }
+}
+
+/// The demo's submissions to an engine built with `config`.
+fn demo_log(config: EngineConfig) -> Vec<Event> {
+    let mut engine = engine_with(config, demo::markets());
+    for event_type in demo::events() {
+        engine.process(event_type);
+    }
+    engine
+        .event_log
+        .into_iter()
+        .map(Arc::unwrap_or_clone)
+        .collect()
+}
+
+/// The state `replay_with` reaches under `config`, which must get through the log.
+fn full_replay(log: &[Event], config: EngineConfig) -> State {
+    let options = ReplayOptions {
+        config,
+        ..ReplayOptions::default()
+    };
+    let result = Engine::replay_with(options, log, demo::markets());
+    assert_eq!(result.status, ReplayStatus::Completed);
+    assert_eq!(result.events_applied, log.len() as u64);
+    result.state
+}
+
+#[test]
+fn equals_full_replay_under_the_default_config() {
+    let log = demo_log(EngineConfig::default());
+    let state =
+        Engine::replay_state_only(log.clone(), demo::markets(), EngineConfig::default()).unwrap();
+    assert_eq!(state, full_replay(&log, EngineConfig::default()));
+    assert_eq!(state, demo::engine().state);
+}
+
+#[test]
+fn equals_full_replay_under_the_log_config() {
+    let log = demo_log(config());
+    assert!(matches!(
+        &log[0].event_type,
+        EventType::ConfigMarker { config: marker, .. } if *marker == config()
+    ));
+    let state = Engine::replay_state_only(log.clone(), demo::markets(), config()).unwrap();
+    assert_eq!(state, full_replay(&log, config()));
+    assert!(state.accounts["alice"].positions.is_empty());
+}
+
+#[test]
+fn reports_a_config_mismatch() {
+    let log = demo_log(config());
+    let Err(EngineError::ConfigMismatch { sequence, fields }) =
+        Engine::replay_state_only(log.clone(), demo::markets(), EngineConfig::default())
+    else {
+        panic!("replayed a log written under another config");
+    };
+    assert_eq!(sequence, log[0].sequence);
+    assert!(
+        fields.contains(&"withdrawal_buffer".to_string()),
+        "{fields:?}"
+    );
+    assert!(fields.contains(&"scan_order".to_string()), "{fields:?}");
+}
+
+#[test]
+fn follows_a_config_updated_in_the_log() {
+    let mut engine = engine_with(EngineConfig::default(), demo::markets());
+    let events = demo::events();
+    let (before, after) = events.split_at(events.len() / 2);
+    for event_type in before {
+        engine.process(event_type.clone());
+    }
+    assert!(engine
+        .process(EventType::ConfigUpdated { config: config() })
+        .is_accepted());
+    for event_type in after {
+        engine.process(event_type.clone());
+    }
+    let log: Vec<Event> = engine
+        .event_log
+        .iter()
+        .map(|e| e.as_ref().clone())
+        .collect();
+
+    let state =
+        Engine::replay_state_only(log.clone(), demo::markets(), EngineConfig::default()).unwrap();
+    assert_eq!(state, full_replay(&log, EngineConfig::default()));
+    assert_eq!(state, engine.state);
+}
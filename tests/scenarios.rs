@@ -0,0 +1,51 @@
+// Every scenario in scenarios/*.toml runs and meets all of its expectations, as
+// `cargo run -- run-scenario <file>` would report PASS for it. A scenario whose
+// expectation does not hold must fail, so a passing run means something.
+
+use cross_margin_engine::scenario::{self, ScenarioError};
+use std::path::{Path, PathBuf};
+
+fn scenario_paths() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios");
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn every_scenario_passes() {
+    let paths = scenario_paths();
+    assert!(!paths.is_empty());
+    let mut failures = Vec::new();
+    for path in &paths {
+        let outcome = scenario::load(path).and_then(|s| scenario::run(&s));
+        match outcome {
+            Ok(run) if run.expectations_checked > 0 => {}
+            Ok(_) => failures.push(format!("{}: checks no expectation", path.display())),
+            Err(e) => failures.push(format!("{}: {e}", path.display())),
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} of {} scenarios failed:\n{}",
+        failures.len(),
+        paths.len(),
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn a_wrong_expectation_fails() {
+    let text = std::fs::read_to_string(&scenario_paths()[0]).unwrap();
+    let mut scenario = scenario::parse(&text).unwrap();
+    let last = scenario.steps.len();
+    scenario.steps.push("expect alice collateral 1".to_string());
+    assert!(matches!(
+        scenario::run(&scenario),
+        Err(ScenarioError::ExpectationFailed { step, .. }) if step == last + 1
+    ));
+}
@@ -0,0 +1,83 @@
+// `events::split_by_account` on events naming two accounts. A transfer or merge whose
+// accounts are assigned to one shard splits and replays there; assigned to two, the
+// split fails with `SplitError::CrossShard`, naming the event's sequence and shards.
+
+mod common;
+
+use common::{btc_market, deposit, engine_with, id, mark, process, trade};
+use cross_margin_engine::events;
+use cross_margin_engine::prelude::*;
+use rust_decimal_macros::dec;
+
+/// alice, bob and carol funded, alice long 1 BTC at 50,000, then `last` between
+/// alice and bob.
+fn engine_ending_in(last: EventType) -> Engine {
+    let mut engine = engine_with(EngineConfig::default(), vec![btc_market()]);
+    process(&mut engine, mark("BTC-PERP", dec!(50000)));
+    process(&mut engine, deposit("alice", dec!(10000)));
+    process(&mut engine, deposit("bob", dec!(10000)));
+    process(&mut engine, deposit("carol", dec!(10000)));
+    process(
+        &mut engine,
+        trade("alice", "BTC-PERP", dec!(1), dec!(50000)),
+    );
+    process(&mut engine, last);
+    engine
+}
+
+fn between_alice_and_bob() -> Vec<EventType> {
+    vec![
+        EventType::PositionTransfer {
+            from: id("alice"),
+            to: id("bob"),
+            market_id: "BTC-PERP".parse().unwrap(),
+            quantity: dec!(0.5),
+            transfer_price: dec!(50000),
+        },
+        EventType::AccountsMerged {
+            from: id("alice"),
+            to: id("bob"),
+        },
+    ]
+}
+
+#[test]
+fn two_accounts_in_one_shard_split() {
+    for last in between_alice_and_bob() {
+        let engine = engine_ending_in(last);
+        // alice and bob together, carol in a shard of its own.
+        let shards =
+            events::split_by_account(&engine.event_log, |account| usize::from(account == "carol"))
+                .unwrap();
+        assert_eq!(shards.len(), 2);
+        let replayed =
+            Engine::replay_verified(&shards[0], vec![btc_market()], EngineConfig::default())
+                .unwrap();
+        assert_eq!(
+            replayed.state.accounts.get(&id("bob")),
+            engine.state.accounts.get(&id("bob"))
+        );
+    }
+}
+
+#[test]
+fn two_accounts_in_two_shards_fail_at_the_event() {
+    for last in between_alice_and_bob() {
+        let engine = engine_ending_in(last);
+        let sequence = engine.event_log.last().unwrap().sequence;
+        let err =
+            events::split_by_account(&engine.event_log, |account| usize::from(account != "alice"))
+                .unwrap_err();
+        assert_eq!(
+            err,
+            SplitError::CrossShard {
+                sequence,
+                shards: vec![0, 1],
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            format!("seq {sequence} names accounts in shards [0, 1]")
+        );
+    }
+}
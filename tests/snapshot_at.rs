@@ -0,0 +1,130 @@
+// `Engine::snapshot_at` for every sequence of a log gives the snapshot of the log
+// replayed from genesis up to it, whatever the engine retained. An engine keeping only boundary snapshots (or the last few)
+// reconstructs the rest by replaying from the nearest retained one, under the config
+// in effect there, and agrees with an engine that retained every snapshot: mid-cascade,
+// after a `ConfigUpdated`, and before its first retained snapshot.
+
+mod common;
+
+use common::{deposit, engine_with, id, mark, markets, process, trade};
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::snapshot;
+use rust_decimal_macros::dec;
+
+fn config(snapshot_policy: SnapshotPolicy) -> EngineConfig {
+    EngineConfig {
+        snapshot_policy,
+        ..EngineConfig::default()
+    }
+}
+
+/// alice long BTC and ETH on 11,000 is liquidated market by market when BTC drops to
+/// 46,500, after a 20% withdrawal buffer came in. carol's withdrawal after the cascade
+/// is refused only under the buffer.
+fn run(snapshot_policy: SnapshotPolicy) -> Engine {
+    let mut engine = engine_with(config(snapshot_policy), markets());
+    for event_type in [
+        mark("BTC-PERP", dec!(50000)),
+        mark("ETH-PERP", dec!(3000)),
+        deposit("alice", dec!(11000)),
+        deposit("carol", dec!(10000)),
+        trade("alice", "BTC-PERP", dec!(3), dec!(50000)),
+        trade("alice", "ETH-PERP", dec!(10), dec!(3000)),
+        trade("carol", "BTC-PERP", dec!(1), dec!(50000)),
+        EventType::ConfigUpdated {
+            config: EngineConfig {
+                withdrawal_buffer: dec!(1.2),
+                ..config(snapshot_policy)
+            },
+        },
+        mark("BTC-PERP", dec!(46500)),
+    ] {
+        process(&mut engine, event_type);
+    }
+    let outcome = engine.process(EventType::Withdraw {
+        account_id: id("carol"),
+        amount: dec!(4000),
+    });
+    assert!(!outcome.is_accepted(), "{outcome:?}");
+    engine
+}
+
+fn liquidation_fills(engine: &Engine) -> Vec<u64> {
+    engine
+        .event_log
+        .iter()
+        .filter(|event| matches!(event.event_type, EventType::LiquidationFill { .. }))
+        .map(|event| event.sequence)
+        .collect()
+}
+
+/// The snapshot after `sequence` of `engine`'s log replayed from genesis.
+fn prefix_replay(engine: &Engine, sequence: u64, snapshot_policy: SnapshotPolicy) -> Snapshot {
+    let options = ReplayOptions {
+        stop_at_sequence: Some(sequence),
+        config: config(snapshot_policy),
+        ..ReplayOptions::default()
+    };
+    let result = Engine::replay_with(options, engine.event_log.iter(), markets());
+    assert!(result.invariant_violations.is_empty());
+    snapshot::capture(&result.state, sequence)
+}
+
+fn assert_matches_full_retention(snapshot_policy: SnapshotPolicy) {
+    let full = run(SnapshotPolicy::EveryEvent);
+    let sparse = run(snapshot_policy);
+    // The same log but for the snapshot policy in its configs.
+    assert_eq!(sparse.event_log.len(), full.event_log.len());
+    assert!(sparse.snapshots.len() < full.snapshots.len());
+
+    for event in full.event_log.iter() {
+        let sequence = event.sequence;
+        let expected = prefix_replay(&sparse, sequence, snapshot_policy);
+        assert_eq!(
+            sparse.snapshot_at(sequence).unwrap(),
+            expected,
+            "seq {sequence}"
+        );
+        assert_eq!(
+            sparse.reconstruct_snapshot(sequence).unwrap(),
+            expected,
+            "seq {sequence}"
+        );
+        assert_eq!(
+            full.snapshot_at(sequence).unwrap(),
+            expected,
+            "seq {sequence}"
+        );
+    }
+}
+
+#[test]
+fn boundary_snapshots_seed_reconstruction_mid_cascade() {
+    let engine = run(SnapshotPolicy::Boundaries);
+    let fills = liquidation_fills(&engine);
+    assert_eq!(fills.len(), 2, "{:?}", engine.event_log);
+    // Each fill is retained, the mark that set off the cascade is not.
+    let retained: Vec<u64> = engine.snapshots.iter().map(|s| s.after_sequence).collect();
+    assert!(
+        fills.iter().all(|seq| retained.contains(seq)),
+        "{retained:?}"
+    );
+    assert!(!retained.contains(&(fills[0] - 1)), "{retained:?}");
+
+    assert_matches_full_retention(SnapshotPolicy::Boundaries);
+}
+
+#[test]
+fn keep_last_reconstructs_before_and_after_its_window() {
+    assert_matches_full_retention(SnapshotPolicy::KeepLast(2));
+}
+
+#[test]
+fn a_sequence_outside_the_log_is_refused() {
+    let engine = run(SnapshotPolicy::Boundaries);
+    let past = engine.next_sequence();
+    assert!(matches!(
+        engine.snapshot_at(past),
+        Err(EngineError::SequenceNotInLog { sequence }) if sequence == past
+    ));
+}
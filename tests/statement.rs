@@ -0,0 +1,132 @@
+// alice's and bob's collateral statements over the demo log, line by line. alice
+// deposits 100,000 and loses 90,000 when the 10 BTC long is liquidated at 41,000;
+// bob deposits 10,000 and pays 30 of funding on 20 ETH, and bob's rejected second
+// fill moves nothing. Every `balance_after` is the collateral of the replayed
+// snapshot at that line, and the last is the replayed collateral at the end. A log
+// read back from JSONL gives the same statements.
+
+use cross_margin_engine::prelude::*;
+use cross_margin_engine::report::{self, LedgerKind, LedgerLine};
+use cross_margin_engine::{demo, jsonl};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+/// A line of `amount` at `sequence`, with the balances it leaves.
+fn line(
+    sequence: u64,
+    kind: LedgerKind,
+    market_id: Option<&str>,
+    amount: Decimal,
+    (principal_after, trading_balance_after): (Decimal, Decimal),
+) -> LedgerLine {
+    let principal_amount = match kind {
+        LedgerKind::Deposit => amount,
+        _ => Decimal::ZERO,
+    };
+    LedgerLine {
+        sequence,
+        kind,
+        market_id: market_id.map(|m| m.parse().unwrap()),
+        amount,
+        balance_after: principal_after + trading_balance_after,
+        principal_amount,
+        principal_after,
+        trading_balance_after,
+    }
+}
+
+/// Each line's `balance_after` against the snapshot after its event, and the last
+/// against the replayed account.
+fn assert_balances_replay(engine: &Engine, account: &str, lines: &[LedgerLine]) {
+    let (state, snapshots) = Engine::replay(&engine.event_log, demo::markets());
+    for line in lines {
+        let snapshot = snapshots
+            .iter()
+            .find(|s| s.after_sequence == line.sequence)
+            .unwrap_or_else(|| panic!("no snapshot at seq {}", line.sequence));
+        let held = &snapshot.accounts[account];
+        assert_eq!(line.balance_after, held.collateral, "{line:?}");
+        assert_eq!(line.principal_after, held.principal, "{line:?}");
+        assert_eq!(line.trading_balance_after, held.trading_balance, "{line:?}");
+    }
+    let last = lines.last().unwrap();
+    assert_eq!(last.balance_after, state.accounts[account].collateral());
+    assert_eq!(
+        last.balance_after,
+        engine.state.accounts[account].collateral()
+    );
+}
+
+#[test]
+fn alice_deposits_and_is_liquidated() {
+    let engine = demo::engine();
+    let lines = report::statement(&engine.event_log, "alice", demo::markets());
+    assert_eq!(
+        lines,
+        [
+            line(
+                2,
+                LedgerKind::Deposit,
+                None,
+                dec!(100000),
+                (dec!(100000), dec!(0))
+            ),
+            // The fill at 4 and the marks at 5 and 6 move no collateral.
+            line(
+                7,
+                LedgerKind::Liquidation,
+                Some("BTC-PERP"),
+                dec!(-90000),
+                (dec!(100000), dec!(-90000))
+            ),
+        ]
+    );
+    assert_balances_replay(&engine, "alice", &lines);
+    assert_eq!(lines.last().unwrap().balance_after, dec!(10000));
+}
+
+#[test]
+fn bob_deposits_and_pays_funding() {
+    let engine = demo::engine();
+    let lines = report::statement(&engine.event_log, "bob", demo::markets());
+    assert_eq!(
+        lines,
+        [
+            line(
+                8,
+                LedgerKind::Deposit,
+                None,
+                dec!(10000),
+                (dec!(10000), dec!(0))
+            ),
+            // Paid at the funding update, not at its payment record.
+            line(
+                19,
+                LedgerKind::Funding,
+                Some("ETH-PERP"),
+                dec!(-30),
+                (dec!(10000), dec!(-30))
+            ),
+        ]
+    );
+    assert_balances_replay(&engine, "bob", &lines);
+    assert_eq!(lines.last().unwrap().balance_after, dec!(9970));
+}
+
+#[test]
+fn statements_read_the_same_from_a_written_log() {
+    let engine = demo::engine();
+    let written: Vec<String> = engine
+        .event_log
+        .iter()
+        .map(|event| serde_json::to_string(event).unwrap())
+        .collect();
+    let log = jsonl::parse_jsonl(&written.join("\n")).unwrap();
+    for account in ["alice", "bob"] {
+        assert_eq!(
+            report::statement(&log, account, demo::markets()),
+            report::statement(&engine.event_log, account, demo::markets()),
+            "{account}"
+        );
+    }
+}
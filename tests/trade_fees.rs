@@ -0,0 +1,227 @@
+// Trade fees: each accepted `TradeFill` is charged the rate `Engine::fee_rate` gave
+// before it, out of the account's trading balance, and the charge is logged as a
+// `FeeCharged` and booked as venue fee revenue. Crossing a turnover tier changes the
+// rate of the very next fill, and a group's `fee_override` replaces the tiers for its
+// members. Strict replay derives the same records, state and books.
+
+mod common;
+
+use common::{btc, btc_market, deposit, engine_with, id, mark, process, trade};
+use cross_margin_engine::prelude::*;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+/// 5 bps below 100,000 of turnover over the last ten fills, 3 bps from there.
+fn tiered() -> EngineConfig {
+    EngineConfig {
+        trade_stats: Some(TradeStatistics {
+            window: StatsWindow::Fills(10),
+            fee_tiers: vec![
+                FeeTier {
+                    min_turnover: dec!(0),
+                    rate: dec!(0.0005),
+                },
+                FeeTier {
+                    min_turnover: dec!(100000),
+                    rate: dec!(0.0003),
+                },
+            ],
+        }),
+        ..EngineConfig::default()
+    }
+}
+
+/// alice funded with 100,000 at a BTC mark of 50,000.
+fn engine(config: EngineConfig) -> Engine {
+    let mut engine = engine_with(config, vec![btc_market()]);
+    process(&mut engine, mark("BTC-PERP", dec!(50000)));
+    process(&mut engine, deposit("alice", dec!(100000)));
+    engine
+}
+
+/// alice's BTC fill at 50,000 on the `liquidity` side.
+fn flagged(quantity: Decimal, liquidity: Liquidity) -> EventType {
+    EventType::TradeFill {
+        account_id: id("alice"),
+        market_id: btc(),
+        quantity,
+        price: dec!(50000),
+        liquidity: Some(liquidity),
+    }
+}
+
+/// `(rate, amount)` of every `FeeCharged` in the log, in order.
+fn fees(engine: &Engine) -> Vec<(Decimal, Decimal)> {
+    engine
+        .event_log
+        .iter()
+        .filter_map(|event| match &event.event_type {
+            EventType::FeeCharged { rate, amount, .. } => Some((*rate, *amount)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The log replays strictly to the live state and books, which balance.
+fn assert_replays(engine: &Engine) {
+    let replayed = Engine::replay_verified(
+        &engine.event_log,
+        vec![btc_market()],
+        engine.config().clone(),
+    )
+    .unwrap();
+    assert_eq!(replayed.state, engine.state);
+    assert_eq!(replayed.metrics, *engine.metrics());
+    assert!(engine.solvency().is_balanced(), "{:?}", engine.solvency());
+}
+
+#[test]
+fn the_first_fill_past_a_tier_is_charged_the_new_rate() {
+    let mut engine = engine(tiered());
+
+    // Two fills of 50,000 at 5 bps take turnover to the 100,000 tier.
+    process(
+        &mut engine,
+        trade("alice", "BTC-PERP", dec!(1), dec!(50000)),
+    );
+    process(
+        &mut engine,
+        trade("alice", "BTC-PERP", dec!(1), dec!(50000)),
+    );
+    assert_eq!(
+        engine.account_stats("alice").unwrap().turnover,
+        dec!(100000)
+    );
+    assert_eq!(engine.fee_rate("alice"), Some(dec!(0.0003)));
+
+    // The next fill is charged 3 bps on its 50,000.
+    process(
+        &mut engine,
+        trade("alice", "BTC-PERP", dec!(-1), dec!(50000)),
+    );
+    assert_eq!(
+        fees(&engine),
+        [
+            (dec!(0.0005), dec!(25)),
+            (dec!(0.0005), dec!(25)),
+            (dec!(0.0003), dec!(15)),
+        ]
+    );
+
+    // All at mark, so the fees are the only change to the balance.
+    let alice = &engine.state.accounts["alice"];
+    assert_eq!(alice.trading_balance, dec!(-65));
+    assert_eq!(alice.principal, dec!(100000));
+    assert_eq!(engine.metrics().total.fees, dec!(65));
+    assert_eq!(engine.solvency().fee_revenue, dec!(65));
+    assert_replays(&engine);
+}
+
+#[test]
+fn a_fee_is_rounded_up_and_a_rejected_fill_is_free() {
+    let mut engine = engine(tiered());
+
+    // 0.0005 × 0.3 × 50,000.0000001 = 7.500000000015, charged as 7.50000001.
+    process(
+        &mut engine,
+        trade("alice", "BTC-PERP", dec!(0.3), dec!(50000.0000001)),
+    );
+    assert_eq!(fees(&engine), [(dec!(0.0005), dec!(7.50000001))]);
+
+    let outcome = engine.process(trade("alice", "BTC-PERP", dec!(1000), dec!(50000)));
+    assert!(!outcome.is_accepted());
+    assert_eq!(fees(&engine).len(), 1);
+    assert_replays(&engine);
+}
+
+#[test]
+fn fills_are_free_without_a_rate() {
+    let mut engine = engine(EngineConfig::default());
+    process(
+        &mut engine,
+        trade("alice", "BTC-PERP", dec!(1), dec!(50000)),
+    );
+    assert_eq!(engine.fee_rate("alice"), None);
+    assert!(fees(&engine).is_empty());
+    assert_eq!(engine.state.accounts["alice"].collateral(), dec!(100000));
+    assert_replays(&engine);
+}
+
+#[test]
+fn maker_and_taker_notional_are_counted_apart() {
+    let mut engine = engine(tiered());
+    process(&mut engine, flagged(dec!(1), Liquidity::Maker));
+    process(&mut engine, flagged(dec!(-0.4), Liquidity::Taker));
+    // Unflagged: turnover only.
+    process(
+        &mut engine,
+        trade("alice", "BTC-PERP", dec!(0.1), dec!(50000)),
+    );
+
+    let stats = engine.account_stats("alice").unwrap();
+    assert_eq!(stats.turnover, dec!(75000));
+    assert_eq!(stats.maker_notional, dec!(50000));
+    assert_eq!(stats.taker_notional, dec!(20000));
+    assert_eq!(stats.trades, 3);
+    assert_replays(&engine);
+}
+
+fn group(fee_override: Decimal) -> EventType {
+    EventType::GroupCreated {
+        group_id: "mm".into(),
+        max_group_notional: None,
+        fee_override: Some(fee_override),
+    }
+}
+
+fn join(account: &str, group_id: Option<&str>) -> EventType {
+    EventType::GroupMembershipSet {
+        account_id: id(account),
+        group_id: group_id.map(String::from),
+    }
+}
+
+#[test]
+fn a_group_member_is_charged_the_override_instead_of_its_tier() {
+    let mut engine = engine(tiered());
+    process(&mut engine, deposit("bob", dec!(100000)));
+    process(&mut engine, group(dec!(0.0001)));
+    process(&mut engine, join("alice", Some("mm")));
+
+    // Same fill, same turnover: the member pays 1 bp, bob the 5 bp tier.
+    process(
+        &mut engine,
+        trade("alice", "BTC-PERP", dec!(1), dec!(50000)),
+    );
+    process(&mut engine, trade("bob", "BTC-PERP", dec!(1), dec!(50000)));
+    assert_eq!(
+        fees(&engine),
+        [(dec!(0.0001), dec!(5)), (dec!(0.0005), dec!(25))]
+    );
+
+    // Out of the group, alice is back on the tiers.
+    process(&mut engine, join("alice", None));
+    process(
+        &mut engine,
+        trade("alice", "BTC-PERP", dec!(-1), dec!(50000)),
+    );
+    assert_eq!(fees(&engine)[2], (dec!(0.0005), dec!(25)));
+    assert_eq!(engine.state.accounts["alice"].collateral(), dec!(99970));
+    assert_replays(&engine);
+}
+
+#[test]
+fn a_rebate_override_is_charged_without_trade_statistics() {
+    let mut engine = engine(EngineConfig::default());
+    process(&mut engine, group(dec!(-0.0001)));
+    process(&mut engine, join("alice", Some("mm")));
+    process(
+        &mut engine,
+        trade("alice", "BTC-PERP", dec!(1), dec!(50000)),
+    );
+
+    assert_eq!(fees(&engine), [(dec!(-0.0001), dec!(-5))]);
+    assert_eq!(engine.state.accounts["alice"].collateral(), dec!(100005));
+    assert_eq!(engine.solvency().fee_revenue, dec!(-5));
+    assert_replays(&engine);
+}